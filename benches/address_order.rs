@@ -0,0 +1,61 @@
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+// Run with `--features slab`. The benchmarks report how many distinct pages the working set
+// touches, in LIFO order and in address order.
+
+/// The number of objects in the working set.
+const OBJECTS: usize = 10000;
+
+/// Churn a working set of small objects, and count the distinct pages it ends up on.
+fn working_set() -> usize {
+    let mut ptrs = Vec::with_capacity(2 * OBJECTS);
+    for i in 0..2 * OBJECTS {
+        ptrs.push(ralloc::alloc(i % 8 * 16 + 16, 8));
+    }
+
+    // Free every other object, and reallocate the working set into the holes.
+    let mut live = Vec::with_capacity(OBJECTS);
+    for (i, ptr) in ptrs.into_iter().enumerate() {
+        if i % 2 == 0 {
+            unsafe { ralloc::free(ptr, i % 8 * 16 + 16); }
+        } else {
+            live.push((ptr, i % 8 * 16 + 16));
+        }
+    }
+    for i in 0..OBJECTS / 2 {
+        let (ptr, size) = live.swap_remove(i * 7 % live.len());
+        unsafe { ralloc::free(ptr, size); }
+        live.push((ralloc::alloc(size, 8), size));
+    }
+
+    let mut pages: Vec<usize> = live.iter().map(|&(ptr, _)| ptr as usize / 4096).collect();
+    pages.sort();
+    pages.dedup();
+
+    for (ptr, size) in live {
+        unsafe { ralloc::free(ptr, size); }
+    }
+
+    pages.len()
+}
+
+#[bench]
+fn bench_lifo(b: &mut test::Bencher) {
+    ralloc::set_address_ordered(false);
+    println!("LIFO: {} distinct pages", working_set());
+
+    b.iter(working_set);
+}
+
+#[bench]
+fn bench_address_ordered(b: &mut test::Bencher) {
+    ralloc::set_address_ordered(true);
+    println!("address order: {} distinct pages", working_set());
+
+    b.iter(working_set);
+
+    ralloc::set_address_ordered(false);
+}
@@ -0,0 +1,33 @@
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+#[bench]
+fn bench_alloc_many(b: &mut test::Bencher) {
+    b.iter(|| {
+        let mut ptrs = [0 as *mut u8; 256];
+
+        let n = ralloc::alloc_many(32, 8, &mut ptrs);
+        for ptr in &mut ptrs[n..] {
+            *ptr = ralloc::alloc(32, 8);
+        }
+
+        unsafe { ralloc::dealloc_many(&mut ptrs, 32); }
+    });
+}
+
+#[bench]
+fn bench_alloc_single(b: &mut test::Bencher) {
+    b.iter(|| {
+        let mut ptrs = [0 as *mut u8; 256];
+
+        for ptr in ptrs.iter_mut() {
+            *ptr = ralloc::alloc(32, 8);
+        }
+
+        for &ptr in ptrs.iter() {
+            unsafe { ralloc::free(ptr, 32); }
+        }
+    });
+}
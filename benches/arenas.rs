@@ -0,0 +1,51 @@
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+use std::thread;
+
+// Run with and without `--features arenas` to compare the arenas to the single global pool, and
+// with `--features cpu_shards` to compare per-CPU shards to the round-robin arenas.
+
+/// Churn 8 KiB buffers on some number of threads.
+fn churn(threads: usize) {
+    let mut handles = Vec::with_capacity(threads);
+
+    for _ in 0..threads {
+        handles.push(thread::spawn(|| {
+            let mut ptrs = [0 as *mut u8; 64];
+
+            for _ in 0..16 {
+                for ptr in ptrs.iter_mut() {
+                    *ptr = ralloc::alloc(8192, 8);
+                    unsafe { **ptr = 0xAA; }
+                }
+
+                for &ptr in ptrs.iter() {
+                    unsafe { ralloc::free(ptr, 8192); }
+                }
+            }
+        }));
+    }
+
+    for i in handles {
+        i.join().unwrap();
+    }
+}
+
+#[bench]
+fn bench_churn_1_thread(b: &mut test::Bencher) {
+    b.iter(|| churn(1));
+}
+
+#[bench]
+fn bench_churn_16_threads(b: &mut test::Bencher) {
+    b.iter(|| churn(16));
+}
+
+// Many more threads than arenas, as in a thread pool server.
+#[bench]
+fn bench_churn_64_threads(b: &mut test::Bencher) {
+    b.iter(|| churn(64));
+}
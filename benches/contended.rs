@@ -0,0 +1,85 @@
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+// The threads must share the global pool, so this needs a build without TLS (which would give
+// every thread a pool of its own). Without the `allocator` feature, the standard library keeps
+// the system allocator, so only the allocations below go through ralloc:
+//
+//     cargo bench --bench contended --no-default-features
+//
+// The baseline serializes the same work through the lock the pool had before (a flag, which the
+// waiters spin on, yielding between attempts), with the lock of the pool compiled out:
+//
+//     cargo bench --bench contended --no-default-features --features unsafe_no_mutex_lock
+//
+// Compare the two.
+
+#[cfg(not(feature = "tls"))]
+mod contended {
+    use ralloc;
+    use test;
+
+    #[cfg(feature = "unsafe_no_mutex_lock")]
+    use std::sync::atomic::{self, AtomicBool};
+    use std::thread;
+
+    /// The number of threads.
+    const THREADS: usize = 16;
+    /// The number of allocations of every thread.
+    const ALLOCATIONS: usize = 0xFF;
+
+    /// The lock of the baseline.
+    #[cfg(feature = "unsafe_no_mutex_lock")]
+    static SPIN: AtomicBool = AtomicBool::new(false);
+
+    /// Allocate and free a small buffer.
+    fn churn() {
+        let ptr = ralloc::alloc(16, 8);
+        unsafe {
+            *ptr = 0xAA;
+            ralloc::free(ptr, 16);
+        }
+    }
+
+    /// Run `f` on every thread, `ALLOCATIONS` times.
+    fn contend(b: &mut test::Bencher, f: fn()) {
+        b.iter(|| {
+            let mut handles = Vec::with_capacity(THREADS);
+
+            for _ in 0..THREADS {
+                handles.push(thread::spawn(move || {
+                    for _ in 0..ALLOCATIONS {
+                        f();
+                    }
+                }));
+            }
+
+            for i in handles {
+                i.join().unwrap();
+            }
+        });
+    }
+
+    #[cfg(not(feature = "unsafe_no_mutex_lock"))]
+    #[bench]
+    fn bench_contended(b: &mut test::Bencher) {
+        contend(b, churn);
+    }
+
+    #[cfg(feature = "unsafe_no_mutex_lock")]
+    #[bench]
+    fn bench_contended_spinlock(b: &mut test::Bencher) {
+        contend(b, || {
+            // The pool isn't locked in this build, so this is the only lock taken.
+            while SPIN.compare_and_swap(false, true, atomic::Ordering::SeqCst) {
+                thread::yield_now();
+            }
+
+            churn();
+
+            SPIN.store(false, atomic::Ordering::SeqCst);
+        });
+    }
+}
@@ -0,0 +1,82 @@
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+// The reported time is the one taken by the threads, which never grow the heap, while another
+// thread keeps growing it. Their allocations refill from the global allocator, so they stall,
+// whenever the growth holds its lock.
+//
+// Compare the two benchmarks. The baseline grows the heap with the global allocator locked, as
+// the allocator did before (see `ralloc::set_staged_growth`).
+
+/// The number of threads, which don't grow the heap.
+const THREADS: usize = 4;
+/// The number of allocations per thread.
+const SAMPLES: usize = 4096;
+/// The size of the buffers of the threads.
+///
+/// This is beyond what the local allocators hold on to, so every thread refills from the global
+/// allocator.
+const SIZE: usize = 32 * 1024;
+
+/// Allocate and free buffers.
+fn churn() {
+    for _ in 0..SAMPLES {
+        let ptr = ralloc::alloc(SIZE, 8);
+
+        unsafe {
+            *ptr = 0xAA;
+            ralloc::free(ptr, SIZE);
+        }
+    }
+}
+
+/// Run the threads beside a thread growing the heap periodically.
+fn contend() {
+    let done = Arc::new(AtomicBool::new(false));
+    let grower = {
+        let done = done.clone();
+        thread::spawn(move || {
+            let mut bufs = Vec::new();
+            while !done.load(Ordering::SeqCst) {
+                // The buffers are kept, so every one of them grows the heap.
+                bufs.push(ralloc::alloc(1024 * 1024, 8));
+                thread::yield_now();
+            }
+
+            for ptr in bufs {
+                unsafe { ralloc::free(ptr, 1024 * 1024); }
+            }
+        })
+    };
+
+    let threads: Vec<_> = (0..THREADS).map(|_| thread::spawn(churn)).collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    done.store(true, Ordering::SeqCst);
+    grower.join().unwrap();
+}
+
+/// Run the benchmark with or without the staged growth.
+fn growth(b: &mut test::Bencher, staged: bool) {
+    ralloc::set_staged_growth(staged);
+    b.iter(contend);
+    ralloc::set_staged_growth(true);
+}
+
+#[bench]
+fn bench_contended_growth(b: &mut test::Bencher) {
+    growth(b, true);
+}
+
+#[bench]
+fn bench_contended_growth_locked(b: &mut test::Bencher) {
+    growth(b, false);
+}
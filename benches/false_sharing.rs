@@ -0,0 +1,70 @@
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+// Run with `--features stats`, and compare the two benchmarks. Without false sharing, polling the
+// statistics from another thread barely slows the allocating threads down.
+
+/// Churn buffers of different size classes on 4 threads, optionally polling the statistics from
+/// another thread meanwhile.
+fn churn(poll: bool) {
+    let done = Arc::new(AtomicBool::new(false));
+
+    let poller = if poll {
+        let done = done.clone();
+        Some(thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                #[cfg(feature = "stats")]
+                {
+                    use ralloc::stats::{self, SizeClass};
+
+                    for i in 0..16 {
+                        test::black_box(stats::class(SizeClass::from_index(i)));
+                    }
+                }
+            }
+        }))
+    } else {
+        None
+    };
+
+    let mut handles = Vec::with_capacity(4);
+    for n in 0..4 {
+        handles.push(thread::spawn(move || {
+            // Every thread sticks to its own class.
+            let size = 16 * (n + 1);
+
+            for _ in 0..0x400 {
+                let ptr = ralloc::alloc(size, 8);
+                unsafe {
+                    *ptr = 0xAA;
+                    ralloc::free(ptr, size);
+                }
+            }
+        }));
+    }
+
+    for i in handles {
+        i.join().unwrap();
+    }
+
+    done.store(true, Ordering::Relaxed);
+    if let Some(poller) = poller {
+        poller.join().unwrap();
+    }
+}
+
+#[bench]
+fn bench_churn(b: &mut test::Bencher) {
+    b.iter(|| churn(false));
+}
+
+#[bench]
+fn bench_churn_polled(b: &mut test::Bencher) {
+    b.iter(|| churn(true));
+}
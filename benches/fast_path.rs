@@ -0,0 +1,23 @@
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+// Without the features hooking into allocations, the small requests take the fast path, while
+// the overaligned ones always take the slow path.
+
+#[bench]
+fn bench_small(b: &mut test::Bencher) {
+    b.iter(|| unsafe {
+        let ptr = ralloc::alloc(test::black_box(32), 8);
+        ralloc::free(ptr, 32);
+    });
+}
+
+#[bench]
+fn bench_overaligned(b: &mut test::Bencher) {
+    b.iter(|| unsafe {
+        let ptr = ralloc::alloc(test::black_box(32), 64);
+        ralloc::free(ptr, 32);
+    });
+}
@@ -0,0 +1,24 @@
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+#[cfg(feature = "debugger")]
+#[bench]
+fn bench_find_allocation(b: &mut test::Bencher) {
+    let mut ptrs = [0 as *mut u8; 1000];
+    for ptr in ptrs.iter_mut() {
+        *ptr = ralloc::alloc(64, 8);
+    }
+
+    // Scan the interior of every allocation, like a conservative GC would.
+    b.iter(|| {
+        for &ptr in ptrs.iter() {
+            test::black_box(ralloc::debug::find_allocation(unsafe { ptr.offset(32) }));
+        }
+    });
+
+    for &ptr in ptrs.iter() {
+        unsafe { ralloc::free(ptr, 64); }
+    }
+}
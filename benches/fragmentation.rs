@@ -0,0 +1,29 @@
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+#[bench]
+fn bench_fragmentation(b: &mut test::Bencher) {
+    b.iter(|| {
+        let mut ptrs = [0 as *mut u8; 64];
+
+        // Allocate buffers of varying sizes, and free every other one to leave holes.
+        for (n, ptr) in ptrs.iter_mut().enumerate() {
+            *ptr = ralloc::alloc(16 + n % 7 * 24, 8);
+        }
+        for (n, &ptr) in ptrs.iter().enumerate().filter(|&(n, _)| n % 2 == 0) {
+            unsafe { ralloc::free(ptr, 16 + n % 7 * 24); }
+        }
+
+        // Fill the holes again.
+        for (n, ptr) in ptrs.iter_mut().enumerate().filter(|&(n, _)| n % 2 == 0) {
+            *ptr = ralloc::alloc(16 + n % 5 * 16, 8);
+        }
+
+        for (n, &ptr) in ptrs.iter().enumerate() {
+            let size = if n % 2 == 0 { 16 + n % 5 * 16 } else { 16 + n % 7 * 24 };
+            unsafe { ralloc::free(ptr, size); }
+        }
+    });
+}
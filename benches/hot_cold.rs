@@ -0,0 +1,74 @@
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+use std::ptr;
+
+// The reported throughput is the number of bytes released by a purge after the phases, so higher
+// is better. A spike of large buffers is freed, and then left alone (cold) while a churning
+// working set runs. Taking the hot blocks for the churn keeps it from scattering over the freed
+// spike, which stays in large blocks for the purge to give back.
+//
+// Compare the two benchmarks. The baseline runs the same phases with the plain first fit the
+// allocator used before (see `ralloc::set_hot_first`).
+
+/// The number of buffers of the spike.
+const SPIKE: usize = 64;
+/// The size of the buffers of the spike.
+const SPIKE_SIZE: usize = 64 * 1024;
+/// The number of buffers of the working set.
+const WORKING_SET: usize = 256;
+/// The number of rounds of churn, each freeing the whole working set.
+///
+/// This is enough frees for the spike to turn cold.
+const ROUNDS: usize = 64;
+
+/// Get the size of the `n`th buffer of the working set.
+fn size(n: usize) -> usize {
+    64 + n % 13 * 80
+}
+
+/// Run the phases, and report the bytes released by the purge.
+fn phased(b: &mut test::Bencher, hot_first: bool) {
+    ralloc::set_hot_first(hot_first);
+    let mut released = 0;
+
+    b.iter(|| {
+        // The spike.
+        let mut spike = [0 as *mut u8; SPIKE];
+        for buf in spike.iter_mut() {
+            *buf = ralloc::alloc(SPIKE_SIZE, 8);
+            unsafe { ptr::write_bytes(*buf, 0xAA, SPIKE_SIZE); }
+        }
+        for &buf in spike.iter() {
+            unsafe { ralloc::free(buf, SPIKE_SIZE); }
+        }
+
+        // The churn.
+        let mut ptrs = [0 as *mut u8; WORKING_SET];
+        for _ in 0..ROUNDS {
+            for (n, ptr) in ptrs.iter_mut().enumerate() {
+                *ptr = ralloc::alloc(size(n), 8);
+            }
+            for (n, &ptr) in ptrs.iter().enumerate() {
+                unsafe { ralloc::free(ptr, size(n)); }
+            }
+        }
+
+        released = ralloc::purge().total();
+    });
+
+    ralloc::set_hot_first(true);
+    b.bytes = released as u64;
+}
+
+#[bench]
+fn bench_phased_purge(b: &mut test::Bencher) {
+    phased(b, true);
+}
+
+#[bench]
+fn bench_phased_purge_first_fit(b: &mut test::Bencher) {
+    phased(b, false);
+}
@@ -0,0 +1,16 @@
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+// Run with `--features log_internal` (and stderr sent to `/dev/null`) to measure the cost of the
+// log lines, which mostly format blocks, addresses and sizes.
+
+#[bench]
+fn bench_logged_alloc(b: &mut test::Bencher) {
+    b.iter(|| unsafe {
+        let ptr = ralloc::alloc(test::black_box(200), 8);
+        let ptr = ralloc::realloc(ptr, 200, 400, 8);
+        ralloc::free(ptr, 400);
+    });
+}
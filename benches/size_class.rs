@@ -0,0 +1,25 @@
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+// The size classes are only exposed through the statistics, so run with `--features stats`.
+
+#[cfg(feature = "stats")]
+#[bench]
+fn bench_of(b: &mut test::Bencher) {
+    use ralloc::stats::SizeClass;
+
+    // Mostly small sizes, with some large ones, spread over every class.
+    let sizes: Vec<usize> = (0..1024).map(|i| if i % 16 == 0 { i * 37 } else { i * 13 % 600 })
+        .collect();
+
+    b.iter(|| {
+        let mut sum = 0;
+        for &size in test::black_box(&sizes) {
+            sum += SizeClass::of(size).index();
+        }
+
+        sum
+    });
+}
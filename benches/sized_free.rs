@@ -0,0 +1,22 @@
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+// With metadata, the sized free skips the lookup of the size (in release builds).
+
+#[bench]
+fn bench_free(b: &mut test::Bencher) {
+    b.iter(|| unsafe {
+        let ptr = ralloc::alloc(test::black_box(1000), 8);
+        ralloc::free(ptr, 1000);
+    });
+}
+
+#[bench]
+fn bench_dealloc_sized(b: &mut test::Bencher) {
+    b.iter(|| unsafe {
+        let ptr = ralloc::alloc(test::black_box(1000), 8);
+        ralloc::dealloc_sized(ptr, 1000, 8);
+    });
+}
@@ -0,0 +1,80 @@
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+// Run with and without `--features slab` to compare the slabs to the pool.
+
+#[bench]
+fn bench_small(b: &mut test::Bencher) {
+    b.iter(|| {
+        let mut ptrs = [0 as *mut u8; 256];
+
+        for ptr in ptrs.iter_mut() {
+            *ptr = ralloc::alloc(24, 8);
+        }
+
+        for &ptr in ptrs.iter() {
+            unsafe { ralloc::free(ptr, 24); }
+        }
+    });
+}
+
+#[bench]
+fn bench_tiny(b: &mut test::Bencher) {
+    b.iter(|| {
+        let mut ptrs = [0 as *mut u8; 1024];
+
+        for ptr in ptrs.iter_mut() {
+            *ptr = ralloc::alloc(16, 8);
+        }
+
+        for &ptr in ptrs.iter() {
+            unsafe { ralloc::free(ptr, 16); }
+        }
+    });
+}
+
+#[bench]
+fn bench_tiny_single(b: &mut test::Bencher) {
+    b.iter(|| unsafe { ralloc::free(test::black_box(ralloc::alloc(16, 8)), 16) });
+}
+
+#[bench]
+fn bench_mixed_sizes(b: &mut test::Bencher) {
+    b.iter(|| {
+        let mut ptrs = [(0 as *mut u8, 0); 256];
+
+        for (i, x) in ptrs.iter_mut().enumerate() {
+            let size = i * 37 % 512 + 1;
+            *x = (ralloc::alloc(size, 8), size);
+        }
+
+        // Free the even sizes first and then the odd ones, to interleave the frees.
+        for start in 0..2 {
+            for &(ptr, size) in ptrs.iter().filter(|x| x.1 % 2 == start) {
+                unsafe { ralloc::free(ptr, size); }
+            }
+        }
+    });
+}
+
+#[cfg(feature = "stats")]
+#[bench]
+fn bench_overhead(b: &mut test::Bencher) {
+    // Report the memory overhead of the slabs (the unused cells and rounding).
+    let mut ptrs = [0 as *mut u8; 1000];
+    for ptr in ptrs.iter_mut() {
+        *ptr = ralloc::alloc(40, 8);
+    }
+
+    let stats = ralloc::stats::snapshot();
+    println!("{} bytes requested, {} slabs, {} bytes in cells", 40 * ptrs.len(), stats.slab_count,
+             stats.slab_bytes);
+
+    b.iter(|| unsafe { ralloc::free(test::black_box(ralloc::alloc(40, 8)), 40) });
+
+    for &ptr in ptrs.iter() {
+        unsafe { ralloc::free(ptr, 40); }
+    }
+}
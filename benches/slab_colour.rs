@@ -0,0 +1,86 @@
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+// Run with `--features slab`, and compare the two benchmarks. The walk touches the same field of
+// objects in different slabs back to back, which piles onto a few cache sets when every slab
+// places its cells at the same offsets.
+
+/// The number of objects walked.
+const OBJECTS: usize = 10000;
+/// The size of the objects.
+///
+/// This is the largest class, which leaves the most slack (and hence colours) in a slab.
+const SIZE: usize = 512;
+/// The size of a slab.
+const SLAB: usize = 16384;
+/// The number of cells in a slab of `SIZE`.
+const CELLS: usize = 31;
+/// The offset of the first cell in an uncoloured slab (past the header).
+const FIRST: usize = 176;
+
+/// Order the objects slab by slab for every cell index, such that consecutive objects are in
+/// different slabs.
+fn transpose(ptrs: &mut Vec<*mut u8>) {
+    let slabs = (ptrs.len() + CELLS - 1) / CELLS;
+    let mut res = Vec::with_capacity(ptrs.len());
+
+    for cell in 0..CELLS {
+        for slab in 0..slabs {
+            if let Some(&ptr) = ptrs.get(slab * CELLS + cell) {
+                res.push(ptr);
+            }
+        }
+    }
+
+    *ptrs = res;
+}
+
+/// Walk the second word of every object.
+fn walk(b: &mut test::Bencher, ptrs: &[*mut u8]) {
+    b.iter(|| {
+        let mut sum = 0usize;
+        for &ptr in ptrs {
+            sum = sum.wrapping_add(unsafe { *(ptr.offset(8) as *const usize) });
+        }
+
+        test::black_box(sum)
+    });
+}
+
+#[bench]
+fn bench_walk_coloured(b: &mut test::Bencher) {
+    let mut ptrs: Vec<*mut u8> = (0..OBJECTS).map(|_| {
+        let ptr = ralloc::alloc(SIZE, 16);
+        unsafe { std::ptr::write_bytes(ptr, 0, SIZE); }
+
+        ptr
+    }).collect();
+    // Group the objects by slab.
+    ptrs.sort();
+    transpose(&mut ptrs);
+
+    walk(b, &ptrs);
+
+    for &ptr in &ptrs {
+        unsafe { ralloc::free(ptr, SIZE); }
+    }
+}
+
+#[bench]
+fn bench_walk_uncoloured(b: &mut test::Bencher) {
+    // The same objects, laid out like slabs without colours.
+    let slabs = (OBJECTS + CELLS - 1) / CELLS;
+    let buf = ralloc::alloc(slabs * SLAB, SLAB);
+    unsafe { std::ptr::write_bytes(buf, 0, slabs * SLAB); }
+
+    let mut ptrs: Vec<*mut u8> = (0..OBJECTS).map(|i| unsafe {
+        buf.offset((i / CELLS * SLAB + FIRST + i % CELLS * SIZE) as isize)
+    }).collect();
+    transpose(&mut ptrs);
+
+    walk(b, &ptrs);
+
+    unsafe { ralloc::free(buf, slabs * SLAB); }
+}
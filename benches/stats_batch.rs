@@ -0,0 +1,73 @@
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+use std::sync::atomic::{self, AtomicUsize};
+use std::thread;
+
+// Run with `--features stats`, and compare the two benchmarks. The threads allocate from the same
+// size class, so without the buffered counts, every allocation and free contends on its counters.
+// The baseline adds the shared counts the allocator made before the buffering (those of the class,
+// of the size and waste histograms, and of the alignment histogram) to the same work.
+
+/// The number of threads.
+const THREADS: usize = 8;
+/// The number of allocations per thread.
+const ALLOCS: usize = 4096;
+
+/// The shared counters of the baseline.
+///
+/// These are the allocations, the frees and the bytes of the class, and the buckets of the size,
+/// the waste and the alignment.
+static SHARED: [AtomicUsize; 6] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+/// Allocate and free a buffer from the shared class.
+fn churn() {
+    let ptr = ralloc::alloc(test::black_box(32), 8);
+    unsafe { ralloc::free(ptr, 32); }
+}
+
+/// Count an allocation and a free in the shared counters.
+fn count_shared() {
+    // The allocation.
+    SHARED[0].fetch_add(1, atomic::Ordering::Relaxed);
+    SHARED[2].fetch_add(32, atomic::Ordering::Relaxed);
+    SHARED[3].fetch_add(1, atomic::Ordering::Relaxed);
+    SHARED[4].fetch_add(1, atomic::Ordering::Relaxed);
+    SHARED[5].fetch_add(1, atomic::Ordering::Relaxed);
+    // The free.
+    SHARED[1].fetch_add(1, atomic::Ordering::Relaxed);
+    SHARED[2].fetch_sub(32, atomic::Ordering::Relaxed);
+}
+
+/// Run `f` on every thread, `ALLOCS` times.
+fn contend(b: &mut test::Bencher, f: fn()) {
+    b.iter(|| {
+        let handles: Vec<_> = (0..THREADS).map(|_| thread::spawn(move || {
+            for _ in 0..ALLOCS {
+                f();
+            }
+        })).collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    });
+}
+
+#[bench]
+fn bench_shared_class(b: &mut test::Bencher) {
+    contend(b, churn);
+}
+
+#[bench]
+fn bench_shared_class_unbuffered(b: &mut test::Bencher) {
+    contend(b, || {
+        churn();
+        count_shared();
+    });
+}
@@ -0,0 +1,37 @@
+//! Build the C test of the FFI.
+//!
+//! With the `ffi_test` feature, `tests/ffi.c` is compiled (against `include/ralloc.h`) into a
+//! static library, which `tests/ffi.rs` links and runs. The C compiler is taken from `CC`.
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn main() {
+    if env::var_os("CARGO_FEATURE_FFI_TEST").is_none() {
+        return;
+    }
+
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_owned());
+    let obj = out.join("ffi.o");
+
+    let status = Command::new(&cc)
+        .args(&["-std=c11", "-Wall", "-Werror", "-fPIC", "-Iinclude", "-c", "tests/ffi.c", "-o"])
+        .arg(&obj)
+        .status()
+        .expect("Unable to run the C compiler.");
+    assert!(status.success(), "Unable to compile the C test.");
+
+    let status = Command::new("ar")
+        .arg("crs")
+        .arg(out.join("libralloc_ffi_test.a"))
+        .arg(&obj)
+        .status()
+        .expect("Unable to run ar.");
+    assert!(status.success(), "Unable to archive the C test.");
+
+    println!("cargo:rustc-link-search=native={}", out.display());
+    println!("cargo:rerun-if-changed=tests/ffi.c");
+    println!("cargo:rerun-if-changed=include/ralloc.h");
+}
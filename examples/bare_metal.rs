@@ -0,0 +1,43 @@
+//! Using ralloc on a bare-metal (Cortex-M style) target.
+//!
+//! This only serves as a compile check. Build it with:
+//!
+//! ```
+//! cargo build --example bare_metal --target thumbv7m-none-eabi --no-default-features \
+//!     --features critical_section
+//! ```
+
+#![feature(lang_items, start)]
+#![no_std]
+#![no_main]
+
+extern crate ralloc;
+
+/// The size of the heap.
+const HEAP_SIZE: usize = 32 * 1024;
+
+/// The heap.
+static mut HEAP: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
+
+/// The entry point (called from the reset handler).
+#[no_mangle]
+pub extern fn main() -> ! {
+    unsafe {
+        // Seed the allocator before anything allocates.
+        ralloc::init_from_buffer(&mut HEAP).ok().expect("Allocated before initializing.");
+
+        let ptr = ralloc::alloc(64, 8);
+        *ptr = 42;
+        ralloc::free(ptr, 64);
+    }
+
+    loop {}
+}
+
+#[lang = "eh_personality"]
+extern fn eh_personality() {}
+
+#[lang = "panic_fmt"]
+extern fn panic_fmt() -> ! {
+    loop {}
+}
@@ -0,0 +1,27 @@
+//! Print the report of the built-in benchmarks.
+//!
+//! Run it with:
+//!
+//! ```
+//! cargo run --release --example bench --features "bench stats" -- [seed] [iterations]
+//! ```
+
+extern crate ralloc;
+
+#[cfg(feature = "bench")]
+fn main() {
+    use std::env;
+
+    let mut args = env::args().skip(1).map(|x| x.parse().expect("Invalid number."));
+    let seed = args.next().unwrap_or(1);
+    let iterations = args.next().unwrap_or(1000000);
+
+    let mut report = String::new();
+    ralloc::bench::run_all(&mut report, seed, iterations).unwrap();
+    print!("{}", report);
+}
+
+#[cfg(not(feature = "bench"))]
+fn main() {
+    println!("Build with the `bench` feature.");
+}
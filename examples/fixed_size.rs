@@ -0,0 +1,190 @@
+//! A fixed-size allocator built on the raw pool.
+//!
+//! Run it (or its tests) with:
+//!
+//! ```
+//! cargo run --example fixed_size --features raw
+//! cargo test --example fixed_size --features raw
+//! ```
+
+extern crate ralloc;
+
+#[cfg(feature = "raw")]
+mod fixed {
+    use ralloc::raw::{self, Align, Block, Pointer, Pool};
+
+    /// The size of the regions requested for the slots.
+    pub const REGION_SIZE: usize = 64 * 1024;
+
+    /// The regions requested by an allocator.
+    ///
+    /// They are given back on drop, which comes after the drop of the pool, as the field is
+    /// declared after it.
+    struct Regions(Vec<(*mut u8, usize)>);
+
+    impl Drop for Regions {
+        fn drop(&mut self) {
+            for &(start, size) in &self.0 {
+                unsafe {
+                    raw::return_region(Block::from_raw_parts(Pointer::new(start), size));
+                }
+            }
+        }
+    }
+
+    /// An allocator of slots of a fixed size.
+    pub struct Fixed {
+        /// The pool serving the slots.
+        pool: Pool,
+        /// The regions inserted into the pool.
+        regions: Regions,
+        /// The size of a slot.
+        size: usize,
+        /// The alignment of a slot.
+        align: Align,
+        /// The number of live slots.
+        live: usize,
+    }
+
+    impl Fixed {
+        /// Create an allocator of slots of `size` bytes aligned to `align`.
+        pub fn new(size: usize, align: usize) -> Fixed {
+            assert!(size != 0 && size <= REGION_SIZE, "Invalid slot size: {}.", size);
+
+            Fixed {
+                pool: Pool::new().expect("Unable to create the pool."),
+                regions: Regions(Vec::new()),
+                size: size,
+                align: Align::new(align).expect("The alignment is not a power of two."),
+                live: 0,
+            }
+        }
+
+        /// Allocate a slot.
+        pub fn alloc(&mut self) -> *mut u8 {
+            let block = match self.pool.alloc(self.size, self.align) {
+                Some(block) => block,
+                None => {
+                    self.grow();
+                    self.pool.alloc(self.size, self.align).expect("The new region has no slot.")
+                },
+            };
+
+            self.live += 1;
+            *Pointer::from(block)
+        }
+
+        /// Free a slot.
+        ///
+        /// The slot must be allocated by this allocator, and not be used afterwards.
+        pub unsafe fn free(&mut self, ptr: *mut u8) {
+            self.live -= 1;
+            self.pool.free(Block::from_raw_parts(Pointer::new(ptr), self.size));
+        }
+
+        /// Get the number of live slots.
+        pub fn live(&self) -> usize {
+            self.live
+        }
+
+        /// Get the number of free bytes.
+        pub fn free_bytes(&self) -> usize {
+            self.pool.total_bytes()
+        }
+
+        /// Get the number of bytes in the regions.
+        pub fn region_bytes(&self) -> usize {
+            self.regions.0.iter().map(|&(_, size)| size).sum()
+        }
+
+        /// Request a new region, and insert it into the pool.
+        fn grow(&mut self) {
+            let region = raw::request_region(REGION_SIZE).expect("Unable to request a region.");
+            self.regions.0.push((*Pointer::from(region.empty_left()), region.size()));
+
+            unsafe {
+                // The region is fresh, so it overlaps nothing in the pool.
+                self.pool.insert(region).expect("Unable to grow the metadata of the pool.");
+            }
+        }
+    }
+}
+
+#[cfg(feature = "raw")]
+fn main() {
+    use fixed::Fixed;
+
+    let mut slots = Fixed::new(48, 16);
+
+    let ptrs: Vec<_> = (0..4096).map(|n| {
+        let ptr = slots.alloc();
+        unsafe { *ptr = n as u8; }
+        ptr
+    }).collect();
+    for (n, &ptr) in ptrs.iter().enumerate() {
+        assert_eq!(unsafe { *ptr }, n as u8);
+    }
+
+    println!("{} slots live in {} bytes of regions.", slots.live(), slots.region_bytes());
+
+    for ptr in ptrs {
+        unsafe { slots.free(ptr); }
+    }
+    assert_eq!(slots.free_bytes(), slots.region_bytes());
+}
+
+#[cfg(not(feature = "raw"))]
+fn main() {
+    println!("Build with the `raw` feature.");
+}
+
+#[cfg(all(test, feature = "raw"))]
+mod test {
+    use fixed::{Fixed, REGION_SIZE};
+
+    #[test]
+    fn aligned_and_disjoint() {
+        let mut slots = Fixed::new(24, 32);
+
+        let mut ptrs: Vec<_> = (0..256).map(|_| slots.alloc() as usize).collect();
+        assert!(ptrs.iter().all(|&ptr| ptr % 32 == 0));
+
+        ptrs.sort();
+        assert!(ptrs.windows(2).all(|x| x[1] - x[0] >= 24));
+
+        // The slots take 32 bytes each with their aligners, so they fit in one region.
+        assert_eq!(slots.region_bytes(), REGION_SIZE);
+
+        for ptr in ptrs {
+            unsafe { slots.free(ptr as *mut u8); }
+        }
+        assert_eq!(slots.live(), 0);
+    }
+
+    #[test]
+    fn reuse() {
+        let mut slots = Fixed::new(64, 8);
+
+        let a = slots.alloc();
+        unsafe { slots.free(a); }
+        assert_eq!(slots.alloc(), a);
+
+        unsafe { slots.free(a); }
+    }
+
+    #[test]
+    fn grow_and_merge() {
+        let mut slots = Fixed::new(256, 8);
+
+        // Spill over into a second region.
+        let ptrs: Vec<_> = (0..REGION_SIZE / 256 + 1).map(|_| slots.alloc()).collect();
+        assert!(slots.region_bytes() >= 2 * REGION_SIZE);
+
+        for ptr in ptrs {
+            unsafe { slots.free(ptr); }
+        }
+
+        // The slots merge back into whole regions.
+        assert_eq!(slots.free_bytes(), slots.region_bytes());
+    }
+}
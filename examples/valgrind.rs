@@ -0,0 +1,31 @@
+//! Running ralloc under Valgrind.
+//!
+//! Build and run it with:
+//!
+//! ```
+//! cargo build --example valgrind --features debugger
+//! valgrind --leak-check=full target/debug/examples/valgrind
+//! ```
+//!
+//! Valgrind should report the 64 byte buffer as definitely lost, and the overrun of the 32 byte
+//! buffer as an invalid write 0 bytes after a block of size 32.
+
+extern crate ralloc;
+
+use std::mem;
+
+fn main() {
+    // A leak.
+    mem::forget(vec![0u8; 64]);
+
+    // An overrun into the redzone.
+    let ptr = ralloc::alloc(32, 1);
+    unsafe {
+        *ptr.offset(32) = 1;
+        ralloc::free(ptr, 32);
+    }
+
+    // Ordinary allocations are heap blocks, so nothing is reported for these.
+    let v: Vec<u32> = (0..1000).collect();
+    assert_eq!(v.iter().sum::<u32>(), 499500);
+}
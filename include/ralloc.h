@@ -0,0 +1,112 @@
+/*
+ * The C interface of ralloc.
+ *
+ * This is available with the `ffi` feature. Every function returns an error
+ * code (`RALLOC_OK` on success), and hands its results through out-pointers.
+ * On failure, the out-pointers are left untouched.
+ *
+ * Heaps are opaque handles. Null, foreign and destroyed handles are rejected
+ * with `RALLOC_EHANDLE`.
+ */
+
+#ifndef RALLOC_H
+#define RALLOC_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The call succeeded. */
+#define RALLOC_OK 0
+/* An argument is invalid (e.g. the alignment is not a power of two). */
+#define RALLOC_EINVAL (-1)
+/* The heap handle is invalid (null, foreign or destroyed). */
+#define RALLOC_EHANDLE (-2)
+/* The memory couldn't be provided, or the request exceeds the maximal allocation size. */
+#define RALLOC_ENOMEM (-3)
+/* An internal limit was reached (e.g. too many heaps are live). */
+#define RALLOC_ELIMIT (-4)
+/* The operation is unsupported in this build or on this platform. */
+#define RALLOC_EUNSUPPORTED (-5)
+
+/* An independent heap. */
+typedef struct ralloc_heap ralloc_heap;
+
+/* The statistics of a heap. */
+typedef struct ralloc_heap_stats {
+    /* The number of bytes taken by the heap. */
+    size_t owned_bytes;
+    /* The number of free bytes in the heap. */
+    size_t free_bytes;
+    /* The number of bytes in the live buffers of the heap. */
+    size_t live_bytes;
+} ralloc_heap_stats;
+
+/* The statistics of the allocator. */
+typedef struct ralloc_stats {
+    /* The number of bytes in live allocations. */
+    size_t live_bytes;
+    /* The number of bytes used from the bootstrap arena. */
+    size_t bootstrap_bytes;
+    /* The number of live secure allocations. */
+    size_t secure_count;
+    /* The number of bytes in live secure allocations. */
+    size_t secure_bytes;
+    /* The number of slabs. */
+    size_t slab_count;
+    /* The number of bytes in allocated slab cells. */
+    size_t slab_bytes;
+} ralloc_stats;
+
+/* The memory reclaimed by `ralloc_purge`, in bytes. */
+typedef struct ralloc_purge_report {
+    /* The bytes in empty slabs returned to the pool. */
+    size_t slabs;
+    /* The bytes moved from the local allocator of the calling thread to the global allocator. */
+    size_t local;
+    /* The bytes released to the OS by moving the program break back. */
+    size_t trimmed;
+    /* The bytes of free pages given back to the OS, while staying in the pool. */
+    size_t advised;
+    /* The bytes kept after shrunk buffers, which were given back to the pool. */
+    size_t slack;
+} ralloc_purge_report;
+
+/* The Rust side asserts the same sizes. */
+_Static_assert(sizeof(ralloc_heap_stats) == 3 * sizeof(size_t), "ralloc_heap_stats layout");
+_Static_assert(sizeof(ralloc_stats) == 6 * sizeof(size_t), "ralloc_stats layout");
+_Static_assert(sizeof(ralloc_purge_report) == 5 * sizeof(size_t), "ralloc_purge_report layout");
+
+/* Allocate `size` bytes aligned to `align`. */
+int ralloc_alloc(size_t size, size_t align, void **out);
+/* Reallocate a buffer. On failure, the buffer is left intact. */
+int ralloc_realloc(void *ptr, size_t old_size, size_t size, size_t align, void **out);
+/* Free a buffer, or a buffer of a heap. Null pointers are ignored. */
+int ralloc_free(void *ptr, size_t size);
+
+/* Create a heap. */
+int ralloc_heap_new(ralloc_heap **out);
+/* Destroy a heap, freeing all of its buffers. */
+int ralloc_heap_destroy(ralloc_heap *heap);
+/* Allocate `size` bytes aligned to `align` from a heap. */
+int ralloc_heap_alloc(ralloc_heap *heap, size_t size, size_t align, void **out);
+/* Reallocate a buffer of a heap. On failure, the buffer is left intact. */
+int ralloc_heap_realloc(ralloc_heap *heap, void *ptr, size_t old_size, size_t size, size_t align,
+                        void **out);
+/* Free a buffer to a heap. Null pointers are ignored. */
+int ralloc_heap_free(ralloc_heap *heap, void *ptr, size_t size);
+/* Get the statistics of a heap. */
+int ralloc_heap_stats_get(ralloc_heap *heap, ralloc_heap_stats *out);
+
+/* Get the statistics of the allocator (needs the `stats` feature). */
+int ralloc_stats_get(ralloc_stats *out);
+/* Give as much memory back to the OS as possible. `out` may be null. */
+int ralloc_purge(ralloc_purge_report *out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
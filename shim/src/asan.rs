@@ -0,0 +1,53 @@
+//! AddressSanitizer manual poisoning.
+//!
+//! The ASan interface is linked weakly, so these are NOOPs, when the program isn't built with
+//! ASan. Miri cannot resolve weak symbols, so they are NOOPs under Miri too.
+
+use core::mem;
+
+extern {
+    #[linkage = "extern_weak"]
+    static __asan_poison_memory_region: *const u8;
+    #[linkage = "extern_weak"]
+    static __asan_unpoison_memory_region: *const u8;
+    #[linkage = "extern_weak"]
+    static __asan_address_is_poisoned: *const u8;
+}
+
+/// The type of the (un)poisoning functions.
+type Poison = unsafe extern fn(ptr: *const u8, size: usize);
+/// The type of the poison query function.
+type IsPoisoned = unsafe extern fn(ptr: *const u8) -> i32;
+
+/// Check if the program is built with ASan.
+pub fn enabled() -> bool {
+    !cfg!(feature = "miri") && unsafe { !__asan_poison_memory_region.is_null() }
+}
+
+/// Poison a segment, making any access to it an ASan error.
+pub fn poison(ptr: *const u8, size: usize) {
+    unsafe {
+        if enabled() {
+            mem::transmute::<*const u8, Poison>(__asan_poison_memory_region)(ptr, size);
+        }
+    }
+}
+
+/// Unpoison a segment, making it accessible again.
+pub fn unpoison(ptr: *const u8, size: usize) {
+    unsafe {
+        if !cfg!(feature = "miri") && !__asan_unpoison_memory_region.is_null() {
+            mem::transmute::<*const u8, Poison>(__asan_unpoison_memory_region)(ptr, size);
+        }
+    }
+}
+
+/// Check if a byte is poisoned.
+///
+/// Without ASan, this is always false.
+pub fn is_poisoned(ptr: *const u8) -> bool {
+    unsafe {
+        !cfg!(feature = "miri") && !__asan_address_is_poisoned.is_null()
+            && mem::transmute::<*const u8, IsPoisoned>(__asan_address_is_poisoned)(ptr) != 0
+    }
+}
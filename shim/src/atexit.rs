@@ -0,0 +1,42 @@
+//! Process exit handlers.
+//!
+//! This module supplies the ability to register handlers called upon process exit.
+
+extern {
+    #[linkage = "extern_weak"]
+    static __dso_handle: *mut u8;
+    #[linkage = "extern_weak"]
+    static __cxa_atexit: *const u8;
+}
+
+/// Register a handler called upon process exit, with `arg` as the argument.
+///
+/// The handlers are called in reverse order of registration, when the process exits normally
+/// (returning from `main` or calling `exit`).
+///
+/// If the platform provides no way of registering the handler (e.g. under Miri), `Err(())` is
+/// returned.
+pub fn register(arg: *mut u8, handler: unsafe extern fn(*mut u8)) -> Result<(), ()> {
+    use core::mem;
+
+    if cfg!(feature = "miri") {
+        return Err(());
+    }
+
+    /// An exit handler registration function.
+    type Register = unsafe extern fn(handler: unsafe extern fn(*mut u8), arg: *mut u8,
+                                     dso_handle: *mut u8) -> i32;
+
+    unsafe {
+        if __cxa_atexit.is_null() {
+            return Err(());
+        }
+
+        if mem::transmute::<*const u8, Register>(__cxa_atexit)
+            (handler, arg, &__dso_handle as *const _ as *mut _) == 0 {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+}
@@ -26,8 +26,251 @@ pub const LOCAL_MEMTRIM_LIMIT: usize = 16384;
 /// than this value.
 pub const LOCAL_MEMTRIM_STOP: usize = 1024;
 
-/// The minimum log level.
+/// The size of the bootstrap arena.
+///
+/// The bootstrap arena is a static buffer serving the allocators' initial metadata, such that
+/// initialization never needs to call the allocator itself.
+pub const BOOTSTRAP_SIZE: usize = 8192;
+
+/// The minimal size of the chunks taken by an arena from the global allocator.
+///
+/// With the `arenas` feature, the arenas refill in chunks of (at least) this size.
+pub const ARENA_CHUNK_SIZE: usize = 256 * 1024;
+
+/// The largest alignment carved from the pool, in pages.
+///
+/// Carving larger alignments from the pool wastes up to the alignment in padding, so they are
+/// mapped directly instead, and the slop of the mapping is unmapped.
+pub const POOL_ALIGN_PAGES: usize = 16;
+
+/// The number of refills, for which a thread's CPU hint is trusted.
+///
+/// With the `cpu_shards` feature, threads refill from the arena of the CPU they run on. The CPU
+/// is looked up again after this many refills, to follow migrations.
+pub const CPU_HINT_TTL: usize = 32;
+
+/// The number of allocation sites tracked.
+///
+/// With the `sites` feature, allocation sites beyond this are counted together in one bucket.
+pub const SITE_CAPACITY: usize = 128;
+
+/// The minimal size of the chunks taken by a heap.
+///
+/// See `ralloc::Heap`.
+pub const HEAP_CHUNK_SIZE: usize = 64 * 1024;
+/// The maximal number of chunks taken by a heap.
+///
+/// The chunks are the regions of the heap, which tell the pointers it owns. They are kept in a
+/// fixed table, so a heap never allocates to grow it.
+pub const HEAP_REGIONS: usize = 64;
+
+/// The minimal size of the allocations interleaved over the NUMA nodes.
+///
+/// With the `numa` feature and interleaving on, allocations of at least this size are mapped with
+/// their pages spread over the nodes. See `ralloc::set_numa_interleave`.
+pub const NUMA_INTERLEAVE_MIN: usize = 1024 * 1024;
+
+/// The default share of a buffer (in percent), which a shrinking reallocation keeps as slack.
+///
+/// With the `realloc_slack` feature, tails below this share of the block are kept after the
+/// buffer rather than freed. See `ralloc::set_realloc_slack`.
+pub const REALLOC_SLACK_PERCENT: usize = 25;
+/// The maximal number of buffers with slack kept after them.
+///
+/// The slack is kept in a fixed table, so keeping it never allocates. When the table is full,
+/// the tails are freed as usual.
+pub const REALLOC_SLACK_CAPACITY: usize = 64;
+
+/// The maximal number of regions taken by a raw pool for its metadata.
+///
+/// With the `raw` feature, a `ralloc::raw::Pool` requests regions of its own to hold its metadata,
+/// which are kept in a fixed table. Every region is at least twice as large as the last, so few are
+/// needed.
+pub const RAW_REGIONS: usize = 16;
+
+/// The minimal size of a free block, before `purge` gives its pages back to the OS.
+pub const PURGE_ADVISE_MIN: usize = 256 * 1024;
+
+/// The minimal number of free bytes in the global pool, before the automatic trimming kicks in.
+pub const AUTO_TRIM_MIN_FREE: usize = 1024 * 1024;
+
+/// The automatic trimming only kicks in when more than `1 / AUTO_TRIM_FRACTION` of the memory held
+/// by the allocator is free.
+pub const AUTO_TRIM_FRACTION: usize = 4;
+
+/// The maximal number of blocks advised to the OS by a single automatic trim.
+pub const AUTO_TRIM_ADVISE_MAX: usize = 4;
+
+/// The number of allocations and frees counted by a thread, before its counts are added to the
+/// shared statistics.
+///
+/// This bounds how far behind the statistics lag (see `ralloc::stats`).
+pub const STATS_BATCH: usize = 64;
+
+/// The number of frees by a bookkeeper, after which the generation of free blocks advances.
+pub const GENERATION_FREES: usize = 1024;
+
+/// The least extra space BRK'd beyond a request, when the extra adapts to the growth rate.
+///
+/// See `ralloc::set_adaptive_brk`.
+pub const BRK_EXTRA_MIN: usize = 1024;
+/// The most extra space BRK'd beyond a request, when the extra adapts to the growth rate.
+pub const BRK_EXTRA_MAX: usize = 4 * 1024 * 1024;
+/// The weight of the history in the moving average of the growth rate.
+///
+/// Every generation, the average keeps `1 - 1 / BRK_RATE_WEIGHT` of itself, and takes in
+/// `1 / BRK_RATE_WEIGHT` of the bytes requested by the growths in the generation.
+pub const BRK_RATE_WEIGHT: usize = 4;
+
+/// The maximal age (in generations) of a hot free block.
+///
+/// Allocations prefer hot blocks, such that the cold ones pile up to be trimmed or advised.
+pub const HOT_GENERATIONS: usize = 2;
+
+/// The number of fitting blocks searched for a hot block, before the first fitting one is used.
+pub const HOT_CANDIDATES: usize = 4;
+
+/// The minimal age (in generations) of a cold free block.
+///
+/// The cold blocks are advised to the OS first, and the automatic trimming only advises those.
+pub const COLD_GENERATIONS: usize = 8;
+
+/// The maximal number of registered regions.
+///
+/// The region registry is static, so it never allocates. Regions beyond this are still used, but
+/// never given back to the OS.
+pub const REGION_CAPACITY: usize = 512;
+
+/// The size of the redzone after every buffer.
+///
+/// With the `debugger` feature, this many bytes are reserved after every buffer, and declared
+/// inaccessible to the debugger, catching overruns.
+pub const VALGRIND_REDZONE: usize = 16;
+
+/// The number of allocations in a generation of the table of live allocations.
+///
+/// With the `debugger` feature, every allocation is stamped with its generation, giving a coarse
+/// age without reading a clock.
+pub const LIVE_GENERATION: usize = 1024;
+
+/// The number of records in the trace ring.
+///
+/// With the `trace` feature, the oldest records are dropped, when the ring is full.
+pub const TRACE_CAPACITY: usize = 16384;
+/// The number of live buffers, whose trace ids are tracked.
+///
+/// Buffers allocated while the id table is full are not traced.
+pub const TRACE_IDS: usize = 4096;
+
+/// The size of the buffer backing the allocator under Miri.
+///
+/// With the `miri` feature, no syscalls are made, so the allocator is seeded with a buffer of this
+/// size from the host allocator, and fails to allocate beyond it.
+pub const MIRI_BUFFER: usize = 4 << 20;
+
+/// The size of the emergency pool for async-signal-safe allocation.
+///
+/// The pool is a static buffer, disjoint from the heap. It must be at most 1 MiB on 32-bit
+/// platforms.
+pub const SIG_POOL_SIZE: usize = 64 * 1024;
+
+/// The size of a slab.
+///
+/// Slabs are aligned to their size, which must be a power of two and a multiple of the page size.
+pub const SLAB_SIZE: usize = 16384;
+/// The number of empty slabs kept per size class.
+///
+/// Empty slabs beyond this are returned to the pool. Keeping some avoids repeatedly creating and
+/// destroying slabs when the number of allocations hovers around a slab boundary.
+pub const SLAB_EMPTY_KEEP: usize = 1;
+/// The maximal number of slabs passed over when linking a slab into a partial list in address
+/// order.
+///
+/// This bounds the insertion, so the lists are only approximately ordered. See
+/// `conf::set_address_ordered`.
+pub const SLAB_ORDER_SCAN: usize = 8;
+/// The number of slab allocations per slab generation.
+///
+/// The generations are the clock by which the empty slabs of idle size classes decay. See
+/// `conf::set_slab_decay`.
+pub const SLAB_GENERATION: usize = 4096;
+/// The default number of generations a size class can be idle, before its empty slabs are
+/// returned to the pool.
+pub const SLAB_DECAY: usize = 16;
+/// The number of free cells of every size class cached by a thread.
+///
+/// The cells are taken from and given back to the slabs in batches of half this, behind one lock.
+pub const SLAB_CACHE: usize = 32;
+
+/// The minimal size of the fragments left in the pool when splitting a free block.
+///
+/// Blocks, which would leave a smaller aligner or rest, are passed over by allocations. The
+/// default is the minimal alignment of the buffers, below which a fragment is hardly usable.
+pub const MIN_SPLIT_REMAINDER: usize = 16;
+
+/// The alignment padding, above which an allocation is logged.
+///
+/// Allocations aligned above `MIN_ALIGN`, which split off an aligner of more than this many bytes,
+/// are logged at debug level, pointing out alignments satisfied at a high cost.
+pub const ALIGN_WASTE_LOG: usize = 256;
+
+/// The margin below a watermark, in a power of two fraction of the watermark.
+///
+/// Once the heap usage reached a watermark, it must drop `1 / 2^WATERMARK_HYSTERESIS_LOG` of the
+/// watermark below it, before the watermark is reported crossed downwards, such that a usage
+/// hovering around a watermark doesn't flap.
+pub const WATERMARK_HYSTERESIS_LOG: usize = 4;
+
+/// The maximal size of the requests taking the fast path.
+///
+/// Requests of up to this many bytes, with no more than the minimal alignment, go straight to the
+/// local allocator, when no feature hooks into allocations.
+pub const FAST_PATH_MAX: usize = 512;
+
+/// The number of frees a thread can defer.
+///
+/// With the `bounded_free` feature, a free only queues the block on a per-thread list of this many
+/// entries, which is merged into the pool later.
+pub const PENDING_FREES: usize = 64;
+/// The number of deferred frees merged into the pool at a time.
+///
+/// A free finding the list of deferred frees full merges this many of them.
+pub const PENDING_FREE_SLICE: usize = 4;
+
+/// The size of the heap extension reserved by the initialization.
+///
+/// With the `early_init` feature, the allocator extends the heap by this many bytes (plus the
+/// usual extra) when it initializes, such that the first allocations don't each hit the OS.
+pub const EARLY_RESERVE: usize = 256 * 1024;
+
+/// The number of candidate blocks for randomized placement.
+///
+/// With the `aslr` feature, allocations are placed in a random block among the first
+/// `ASLR_CANDIDATES` fitting blocks.
+pub const ASLR_CANDIDATES: usize = 8;
+/// The maximal number of pages burned as a gap before fresh BRK segments.
+///
+/// With the `aslr` feature, a random number of pages (up to this) is left unused before every
+/// fresh segment.
+pub const ASLR_MAX_GAP_PAGES: usize = 16;
+
+/// The number of spinning rounds before a mutex parks the thread.
+///
+/// Every round spins twice as long as the previous one, until `MUTEX_MAX_BACKOFF` is reached.
+pub const MUTEX_SPIN_LIMIT: usize = 16;
+/// The maximal number of `pause`s in a single spinning round.
+pub const MUTEX_MAX_BACKOFF: usize = 64;
+
+/// The default minimum log level.
+///
+/// This can be raised at runtime through `RALLOC_CONF=log:<level>`. Levels below the ones compiled
+/// in (see the `log_debug` and `log_internal` features) are never logged.
 pub const MIN_LOG_LEVEL: u8 = 0;
+/// The size of the buffer a log line is formatted into.
+///
+/// Log lines are formatted on the stack, such that logging never allocates. Longer lines are cut
+/// off with an ellipsis.
+pub const LOG_BUFFER_SIZE: usize = 512;
 
 /// The default OOM handler.
 #[cold]
@@ -42,11 +285,21 @@ pub fn default_oom_handler() -> ! {
 
 /// Write to the log.
 ///
-/// This points to stderr, but could be changed arbitrarily.
+/// This points to stderr, but could be changed arbitrarily. The string is written with a single
+/// `write` syscall, so lines written at once aren't interleaved with the lines of other threads.
+#[cfg(not(feature = "miri"))]
 pub fn log(s: &str) -> usize {
     unsafe { syscall!(WRITE, 2, s.as_ptr(), s.len()) }
 }
 
+/// Write to the log.
+///
+/// Under Miri, no syscalls are made, so the log is discarded.
+#[cfg(feature = "miri")]
+pub fn log(s: &str) -> usize {
+    s.len()
+}
+
 /// Canonicalize a fresh allocation.
 ///
 /// The return value specifies how much _more_ space is requested to the fresh allocator.
@@ -63,7 +316,7 @@ pub fn extra_fresh(size: usize) -> usize {
     /// The maximal amount of _extra_ bytes.
     const MAX_EXTRA: usize = 1024;
 
-    cmp::max(MIN_EXTRA, cmp::min(MULTIPLIER * size, MAX_EXTRA))
+    cmp::max(MIN_EXTRA, cmp::min(MULTIPLIER.saturating_mul(size), MAX_EXTRA))
 }
 
 /// Canonicalize a BRK request.
@@ -90,5 +343,5 @@ pub fn extra_brk(size: usize) -> usize {
     /// The maximal amount of _extra_ bytes.
     const MAX_EXTRA: usize = 65536;
 
-    cmp::max(MIN_EXTRA, cmp::min(MULTIPLIER * size, MAX_EXTRA))
+    cmp::max(MIN_EXTRA, cmp::min(MULTIPLIER.saturating_mul(size), MAX_EXTRA))
 }
@@ -0,0 +1,29 @@
+//! Constructors run before `main`.
+//!
+//! Constructors are function pointers placed in a dedicated section of the binary, which the
+//! dynamic loader (or the startup code of static binaries) calls before `main`: `.init_array` on
+//! ELF platforms, and `__mod_init_func` on Mac OS.
+
+/// Register a constructor, which is called before `main`.
+///
+/// This defines the public static `$name`, holding the function pointer. It must be public (and
+/// reachable), such that the linker doesn't strip it. On platforms without constructors, the
+/// static is defined all the same, but never called.
+///
+/// ```ignore
+/// extern fn init() { ... }
+///
+/// constructor!(__MY_INIT, init);
+/// ```
+#[macro_export]
+macro_rules! constructor {
+    ($name:ident, $f:path) => {
+        #[doc(hidden)]
+        #[cfg_attr(any(target_os = "linux", target_os = "android", target_os = "freebsd",
+                       target_os = "dragonfly", target_os = "netbsd", target_os = "openbsd",
+                       target_os = "redox"),
+                   link_section = ".init_array")]
+        #[cfg_attr(target_os = "macos", link_section = "__DATA,__mod_init_func")]
+        pub static $name: extern fn() = $f;
+    };
+}
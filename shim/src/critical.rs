@@ -0,0 +1,50 @@
+//! Critical sections.
+//!
+//! On bare-metal targets, a lock held by the interrupted code can deadlock an interrupt handler
+//! which allocates. To prevent this, locks can disable interrupts while they are held (see the
+//! `critical_section` feature of `ralloc`).
+//!
+//! On targets, where interrupts aren't under our control, these are NOOPs.
+
+/// Enter a critical section.
+///
+/// This disables interrupts, and returns the state to be restored by `exit`.
+#[cfg(target_arch = "arm")]
+#[inline]
+pub fn enter() -> usize {
+    let primask: usize;
+
+    unsafe {
+        // Save the interrupt mask and disable the interrupts.
+        asm!("mrs $0, PRIMASK
+              cpsid i" : "=r"(primask) ::: "volatile");
+    }
+
+    primask
+}
+
+/// Exit a critical section.
+///
+/// This restores the interrupt state returned by `enter`.
+#[cfg(target_arch = "arm")]
+#[inline]
+pub fn exit(state: usize) {
+    // Only enable the interrupts, if they were enabled when we entered.
+    if state & 1 == 0 {
+        unsafe {
+            asm!("cpsie i" :::: "volatile");
+        }
+    }
+}
+
+/// Enter a critical section (NOOP on this platform).
+#[cfg(not(target_arch = "arm"))]
+#[inline]
+pub fn enter() -> usize {
+    0
+}
+
+/// Exit a critical section (NOOP on this platform).
+#[cfg(not(target_arch = "arm"))]
+#[inline]
+pub fn exit(_state: usize) {}
@@ -1,17 +1,18 @@
 //! Bindings to debuggers.
+//!
+//! The default implementation informs Valgrind through client requests.
 
-extern {
-    /// Valgrind symbol to declare memory undefined.
-    fn valgrind_make_mem_undefined(ptr: *const u8, size: usize);
-    /// Valgrind symbol to declare memory freed.
-    fn valgrind_freelike_block(ptr: *const u8, size: usize);
-}
+use valgrind;
 
 /// Mark this segment undefined to the debugger.
+///
+/// This is used when a block is taken (recycled) from the pool.
 pub fn mark_undefined(ptr: *const u8, size: usize) {
-    unsafe { valgrind_make_mem_undefined(ptr, size) }
+    valgrind::make_mem_undefined(ptr, size);
 }
 /// Mark this segment free to the debugger.
+///
+/// This is used when a block enters the pool, making any access to it an error.
 pub fn mark_free(ptr: *const u8, size: usize) {
-    unsafe { valgrind_freelike_block(ptr, size) }
+    valgrind::make_mem_noaccess(ptr, size);
 }
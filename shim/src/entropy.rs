@@ -0,0 +1,151 @@
+//! Entropy for seeding the random number generator.
+//!
+//! The sources are tried in order of quality:
+//!
+//! 1. The `getrandom` syscall.
+//! 2. `/dev/urandom`, read through raw syscalls (for kernels before 3.17, or sandboxes filtering
+//!    `getrandom`).
+//! 3. The `RDRAND` instruction, on x86 targets compiled with the `rdrnd` target feature.
+//! 4. A weak fallback, mixing the clock with addresses randomized by ASLR. This is predictable
+//!    to an attacker knowing the memory layout and roughly the start time of the process, so it
+//!    only serves to make the placement differ between runs.
+
+use syscalls;
+
+/// The source of a seed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// The `getrandom` syscall.
+    Getrandom,
+    /// The `/dev/urandom` device.
+    Urandom,
+    /// The `RDRAND` instruction.
+    Rdrand,
+    /// The clock and the ASLR addresses.
+    Weak,
+}
+
+impl Source {
+    /// Get the name of the source.
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::Getrandom => "getrandom",
+            Source::Urandom => "/dev/urandom",
+            Source::Rdrand => "rdrand",
+            Source::Weak => "weak (clock and addresses)",
+        }
+    }
+}
+
+/// A seed.
+pub type Seed = [u64; 2];
+
+/// Get a seed from the best source available.
+///
+/// If `os` is false, the sources needing syscalls are passed over (e.g. on bare metal).
+pub fn seed(os: bool) -> (Seed, Source) {
+    if os {
+        if let Some(seed) = from_getrandom() {
+            return (seed, Source::Getrandom);
+        }
+        if let Some(seed) = from_urandom() {
+            return (seed, Source::Urandom);
+        }
+    }
+    if let Some(seed) = from_rdrand() {
+        return (seed, Source::Rdrand);
+    }
+
+    (weak(os), Source::Weak)
+}
+
+/// Convert 16 bytes to a seed.
+fn to_seed(buf: &[u8; 16]) -> Seed {
+    let mut seed = [0; 2];
+    for (n, &byte) in buf.iter().enumerate() {
+        seed[n / 8] |= (byte as u64) << (n % 8 * 8);
+    }
+
+    seed
+}
+
+/// Get a seed from the `getrandom` syscall.
+fn from_getrandom() -> Option<Seed> {
+    let mut buf = [0; 16];
+
+    match syscalls::getrandom(&mut buf) {
+        Ok(16) => Some(to_seed(&buf)),
+        _ => None,
+    }
+}
+
+/// Get a seed from `/dev/urandom`.
+fn from_urandom() -> Option<Seed> {
+    let fd = match syscalls::open_read(b"/dev/urandom\0") {
+        Ok(fd) => fd,
+        Err(_) => return None,
+    };
+
+    let mut buf = [0; 16];
+    let res = syscalls::read(fd, &mut buf);
+    syscalls::close(fd);
+
+    match res {
+        Ok(16) => Some(to_seed(&buf)),
+        _ => None,
+    }
+}
+
+/// Get a seed from the `RDRAND` instruction.
+#[cfg(all(target_arch = "x86_64", target_feature = "rdrnd", not(feature = "miri")))]
+fn from_rdrand() -> Option<Seed> {
+    /// Draw a random number, retrying a few times, as recommended by Intel.
+    fn draw() -> Option<u64> {
+        for _ in 0..10 {
+            let x: u64;
+            let ok: u8;
+            unsafe {
+                // The carry flag tells whether a random number was available.
+                asm!("rdrand $0
+                      setc $1" : "=r"(x), "=r"(ok) ::: "volatile");
+            }
+
+            if ok != 0 {
+                return Some(x);
+            }
+        }
+
+        None
+    }
+
+    match (draw(), draw()) {
+        (Some(a), Some(b)) => Some([a, b]),
+        _ => None,
+    }
+}
+
+/// Get a seed from the `RDRAND` instruction.
+///
+/// This is unsupported on this target.
+#[cfg(not(all(target_arch = "x86_64", target_feature = "rdrnd", not(feature = "miri"))))]
+fn from_rdrand() -> Option<Seed> {
+    None
+}
+
+/// Get a weak seed from the clock and the addresses randomized by ASLR.
+///
+/// The clock is only read if `os` is true.
+fn weak(os: bool) -> Seed {
+    /// An address in the data segment.
+    static DATA: u8 = 0;
+
+    let stack = 0u8;
+    let clock = if os { syscalls::clock_monotonic() } else { 0 };
+
+    // Mix the sources, such that each affects all the bits.
+    let stack = (&stack as *const u8 as u64).rotate_left(17);
+    let code = (weak as fn(bool) -> Seed as usize as u64).rotate_left(41);
+    let data = &DATA as *const u8 as u64;
+
+    [stack ^ clock ^ 0x9E3779B97F4A7C15, code ^ data ^ clock.rotate_left(32) ^ 0xBF58476D1CE4E5B9]
+}
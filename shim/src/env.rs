@@ -0,0 +1,53 @@
+//! Environment access.
+//!
+//! The environment is read directly from `environ`, such that no allocation happens.
+
+use core::slice;
+
+extern {
+    #[linkage = "extern_weak"]
+    static environ: *const *const *const u8;
+}
+
+/// Get the value of an environment variable.
+///
+/// `None` is returned if the variable isn't set, or if the environment is unavailable (as under
+/// Miri).
+pub fn var(name: &[u8]) -> Option<&'static [u8]> {
+    if cfg!(feature = "miri") {
+        return None;
+    }
+
+    unsafe {
+        // Make sure the symbol exists.
+        if environ.is_null() || (*environ).is_null() {
+            return None;
+        }
+
+        let mut env = *environ;
+        while !(*env).is_null() {
+            let entry = *env;
+
+            // Compare the name. The NUL terminator never matches, since `name` contains no NULs.
+            let mut i = 0;
+            while i < name.len() && *entry.offset(i as isize) == name[i] {
+                i += 1;
+            }
+
+            if i == name.len() && *entry.offset(i as isize) == b'=' {
+                // Find the length of the value.
+                let val = entry.offset(i as isize + 1);
+                let mut len = 0;
+                while *val.offset(len as isize) != 0 {
+                    len += 1;
+                }
+
+                return Some(slice::from_raw_parts(val, len));
+            }
+
+            env = env.offset(1);
+        }
+
+        None
+    }
+}
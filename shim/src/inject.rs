@@ -0,0 +1,147 @@
+//! Failure injection.
+//!
+//! This module is only available with the `test_util` feature.
+//!
+//! For testing the error paths, the next growth of the heap through BRK or through a memory
+//! mapping can be made to fail with some error number, without asking the OS. An injected failure
+//! is used up by the first growth of the injecting thread hitting it. Other threads are
+//! unaffected, so tests can inject failures while running in parallel.
+//!
+//! Every growth passes through here, so the growths are counted as well (see `grows`). The count
+//! is kept for the whole process.
+//!
+//! The NUMA policies can be tested on any machine by mocking `mbind` (see `mock_mbind`): the
+//! calls of the mocking thread are recorded rather than made.
+
+use core::cell::Cell;
+use core::sync::atomic::{self, AtomicUsize};
+
+/// The error number injected into the next growth of the program break, or zero.
+#[thread_local]
+static BRK: Cell<usize> = Cell::new(0);
+/// The error number injected into the next memory mapping, or zero.
+#[thread_local]
+static MMAP: Cell<usize> = Cell::new(0);
+/// The number of growths attempted.
+static GROWS: AtomicUsize = AtomicUsize::new(0);
+/// Is `mbind` mocked?
+#[thread_local]
+static MOCK_MBIND: Cell<bool> = Cell::new(false);
+/// The number of mocked `mbind` calls.
+#[thread_local]
+static MBINDS: Cell<usize> = Cell::new(0);
+/// The arguments of the last mocked `mbind` call.
+#[thread_local]
+static LAST_MBIND: Cell<Mbind> = Cell::new(Mbind {
+    addr: 0,
+    size: 0,
+    mode: 0,
+    mask: 0,
+});
+
+/// The arguments of a mocked `mbind` call.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Mbind {
+    /// The start of the range.
+    pub addr: usize,
+    /// The size of the range.
+    pub size: usize,
+    /// The policy, e.g. `syscalls::MPOL_BIND`.
+    pub mode: usize,
+    /// The nodes, one bit each.
+    pub mask: usize,
+}
+
+/// Make the next growth of the program break by this thread fail with `errno`.
+///
+/// Zero clears an injected failure.
+pub fn fail_brk(errno: usize) {
+    BRK.set(errno);
+}
+
+/// Make the next memory mapping by this thread fail with `errno`.
+///
+/// Zero clears an injected failure.
+pub fn fail_mmap(errno: usize) {
+    MMAP.set(errno);
+}
+
+/// Get the number of growths of the heap attempted, through BRK or memory mappings.
+///
+/// This counts the syscalls growing the heap (along with the injected failures), e.g. to check
+/// that a workload runs without any.
+pub fn grows() -> usize {
+    GROWS.load(atomic::Ordering::SeqCst)
+}
+
+/// Mock `mbind` for this thread, or stop mocking it.
+///
+/// While mocked, `mbind` succeeds without asking the OS, and its arguments are recorded (see
+/// `last_mbind`).
+pub fn mock_mbind(mock: bool) {
+    MOCK_MBIND.set(mock);
+}
+
+/// Get the number of mocked `mbind` calls of this thread.
+pub fn mbinds() -> usize {
+    MBINDS.get()
+}
+
+/// Get the arguments of the last mocked `mbind` call of this thread, if any.
+pub fn last_mbind() -> Option<Mbind> {
+    if mbinds() == 0 {
+        None
+    } else {
+        Some(LAST_MBIND.get())
+    }
+}
+
+/// Record an `mbind` call, if it is mocked for this thread.
+///
+/// This returns whether it is mocked, in which case the syscall must not be made.
+#[inline]
+pub fn record_mbind(addr: usize, size: usize, mode: usize, mask: usize) -> bool {
+    if !MOCK_MBIND.get() {
+        return false;
+    }
+
+    LAST_MBIND.set(Mbind {
+        addr: addr,
+        size: size,
+        mode: mode,
+        mask: mask,
+    });
+    MBINDS.set(MBINDS.get() + 1);
+
+    true
+}
+
+/// Take the failure injected into the next growth of the program break, if any.
+///
+/// This is called on every growth of the program break, which is counted.
+#[inline]
+pub fn take_brk() -> Option<usize> {
+    GROWS.fetch_add(1, atomic::Ordering::Relaxed);
+    take(&BRK)
+}
+
+/// Take the failure injected into the next memory mapping, if any.
+///
+/// This is called on every memory mapping, which is counted.
+#[inline]
+pub fn take_mmap() -> Option<usize> {
+    GROWS.fetch_add(1, atomic::Ordering::Relaxed);
+    take(&MMAP)
+}
+
+/// Take an injected error number.
+#[inline]
+fn take(errno: &Cell<usize>) -> Option<usize> {
+    match errno.get() {
+        0 => None,
+        x => {
+            errno.set(0);
+            Some(x)
+        },
+    }
+}
@@ -0,0 +1,103 @@
+//! The allocator being interposed.
+//!
+//! When `ralloc` is preloaded into a running process (or loaded late), some buffers were allocated
+//! by the allocator it replaces. This resolves the functions of the next allocator in the symbol
+//! lookup order (`dlsym(RTLD_NEXT, ...)`), such that those buffers can be handed back to it.
+//!
+//! The symbols are looked up by static, NUL-terminated names, and cached, so no allocation happens
+//! on our side.
+
+use core::mem;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+extern {
+    #[linkage = "extern_weak"]
+    static dlsym: *const u8;
+}
+
+/// The pseudo-handle of the next object in the symbol lookup order.
+const RTLD_NEXT: *mut u8 = !0 as *mut u8;
+
+/// A symbol of the next allocator, resolved on first use.
+struct Symbol {
+    /// The NUL-terminated name of the symbol.
+    name: &'static [u8],
+    /// The address of the symbol, or null if it hasn't been resolved.
+    addr: AtomicPtr<u8>,
+}
+
+impl Symbol {
+    /// Create a symbol, which is yet to be resolved.
+    const fn new(name: &'static [u8]) -> Symbol {
+        Symbol {
+            name: name,
+            addr: AtomicPtr::new(0 as *mut u8),
+        }
+    }
+
+    /// Resolve the symbol.
+    ///
+    /// If the symbol (or the dynamic linker) is unavailable, `None` is returned.
+    fn get(&self) -> Option<*mut u8> {
+        if cfg!(feature = "miri") {
+            return None;
+        }
+
+        let addr = self.addr.load(Ordering::Acquire);
+        if !addr.is_null() {
+            return Some(addr);
+        }
+
+        /// The symbol lookup function.
+        type Lookup = unsafe extern fn(handle: *mut u8, name: *const u8) -> *mut u8;
+
+        unsafe {
+            if dlsym.is_null() {
+                return None;
+            }
+
+            let addr = mem::transmute::<*const u8, Lookup>(dlsym)(RTLD_NEXT, self.name.as_ptr());
+            if addr.is_null() {
+                return None;
+            }
+
+            // Racing lookups resolve the same address, so the last store wins harmlessly.
+            self.addr.store(addr, Ordering::Release);
+
+            Some(addr)
+        }
+    }
+}
+
+/// The `free` of the next allocator.
+static FREE: Symbol = Symbol::new(b"free\0");
+/// The `malloc_usable_size` of the next allocator.
+static USABLE_SIZE: Symbol = Symbol::new(b"malloc_usable_size\0");
+
+/// Free a buffer of the next allocator.
+///
+/// If the next allocator cannot be resolved, `Err(())` is returned.
+///
+/// # Safety
+///
+/// `ptr` must be a live buffer of the next allocator.
+pub unsafe fn free(ptr: *mut u8) -> Result<(), ()> {
+    /// The signature of `free`.
+    type Free = unsafe extern fn(ptr: *mut u8);
+
+    FREE.get().map(|addr| mem::transmute::<*mut u8, Free>(addr)(ptr)).ok_or(())
+}
+
+/// Get the usable size of a buffer of the next allocator.
+///
+/// If the next allocator cannot be resolved, `Err(())` is returned.
+///
+/// # Safety
+///
+/// `ptr` must be a live buffer of the next allocator.
+pub unsafe fn usable_size(ptr: *mut u8) -> Result<usize, ()> {
+    /// The signature of `malloc_usable_size`.
+    type UsableSize = unsafe extern fn(ptr: *mut u8) -> usize;
+
+    USABLE_SIZE.get().map(|addr| mem::transmute::<*mut u8, UsableSize>(addr)(ptr)).ok_or(())
+}
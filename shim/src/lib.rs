@@ -7,14 +7,26 @@
 //! You CANNOT use libc library calls, due to no guarantees being made about allocations of the
 //! functions in the POSIX specification. Therefore, we use the system calls directly.
 
-#![feature(linkage, core_intrinsics)]
+#![feature(linkage, core_intrinsics, asm, const_fn, thread_local)]
 #![no_std]
 #![warn(missing_docs)]
 
 #[macro_use]
 extern crate sc;
 
+pub mod asan;
+pub mod atexit;
 pub mod config;
+#[macro_use]
+pub mod constructor;
+pub mod critical;
 pub mod thread_destructor;
 pub mod debug;
+pub mod entropy;
+pub mod env;
+#[cfg(feature = "test_util")]
+pub mod inject;
+pub mod interpose;
+pub mod mte;
 pub mod syscalls;
+pub mod valgrind;
@@ -0,0 +1,55 @@
+//! Memory tagging (the AArch64 Memory Tagging Extension).
+//!
+//! With MTE, every 16-byte granule of memory carries a 4-bit tag, and pointers carry one in their
+//! top byte. Accesses through a pointer whose tag doesn't match the memory fault.
+//!
+//! On targets without MTE (i.e. not compiled with the `mte` target feature), no tags are ever
+//! generated or stored, so every operation is a NOOP and pointers are left untagged.
+
+/// The size of a tag granule.
+pub const GRANULE: usize = 16;
+/// The position of the tag in a pointer.
+pub const TAG_SHIFT: usize = 56;
+
+/// Get a pointer to `ptr` with a random nonzero tag.
+///
+/// Tag zero is excluded, as it is the tag of the free memory.
+#[cfg(all(target_arch = "aarch64", target_feature = "mte"))]
+#[inline]
+pub unsafe fn random_tag(ptr: *mut u8) -> *mut u8 {
+    let res;
+    // The exclusion mask has the bit of tag zero set.
+    asm!("irg $0, $1, $2" : "=r"(res) : "r"(ptr), "r"(1usize) :: "volatile");
+
+    res
+}
+
+/// Get a pointer to `ptr` with a random nonzero tag.
+///
+/// Without MTE, `ptr` is returned as is.
+#[cfg(not(all(target_arch = "aarch64", target_feature = "mte")))]
+#[inline]
+pub unsafe fn random_tag(ptr: *mut u8) -> *mut u8 {
+    ptr
+}
+
+/// Tag the `size` bytes at `ptr` with the tag of `ptr`.
+///
+/// `ptr` and `size` must be multiples of the granule.
+#[cfg(all(target_arch = "aarch64", target_feature = "mte"))]
+#[inline]
+pub unsafe fn store_tags(ptr: *mut u8, size: usize) {
+    let mut granule = ptr;
+    let end = (ptr as usize).wrapping_add(size);
+    while (granule as usize) < end {
+        asm!("stg $0, [$0]" :: "r"(granule) : "memory" : "volatile");
+        granule = granule.offset(GRANULE as isize);
+    }
+}
+
+/// Tag the `size` bytes at `ptr` with the tag of `ptr`.
+///
+/// Without MTE, this is a NOOP.
+#[cfg(not(all(target_arch = "aarch64", target_feature = "mte")))]
+#[inline]
+pub unsafe fn store_tags(_ptr: *mut u8, _size: usize) {}
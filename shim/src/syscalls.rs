@@ -1,5 +1,10 @@
 //! System calls.
 
+use core::fmt;
+
+#[cfg(feature = "test_util")]
+use inject;
+
 /// Change the data segment. See `man brk`.
 ///
 /// On success, the new program break is returned. On failure, the old program break is returned.
@@ -7,11 +12,501 @@
 /// # Note
 ///
 /// This is the `brk` **syscall**, not the library function.
+#[cfg(not(feature = "miri"))]
 pub unsafe fn brk(ptr: *const u8) -> *const u8 {
     syscall!(BRK, ptr) as *const u8
 }
 
+/// Change the data segment.
+///
+/// Under Miri, there is no program break, so this always fails, returning a null break.
+#[cfg(feature = "miri")]
+pub unsafe fn brk(_ptr: *const u8) -> *const u8 {
+    0 as *const u8
+}
+
+/// The error number for "interrupted system call".
+pub const EINTR: usize = 4;
+/// The error number for "input/output error".
+pub const EIO: usize = 5;
+/// The error number for "out of memory".
+pub const ENOMEM: usize = 12;
+/// The error number for "too many open files" (`mmap` gives this when the limit of mappings is
+/// reached).
+pub const EMFILE: usize = 24;
+/// The error number for "function not implemented".
+pub const ENOSYS: usize = 38;
+
+/// A failure to grow the memory of the process.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GrowError {
+    /// The program break couldn't be moved.
+    ///
+    /// This carries the error number. The syscall doesn't tell why it failed, so this is
+    /// `ENOMEM`, unless the failure was injected (or there is no program break at all, giving
+    /// `ENOSYS`).
+    Brk(usize),
+    /// The memory couldn't be mapped.
+    ///
+    /// This carries the error number, e.g. `ENOMEM`, or `EMFILE` when the limit of mappings is
+    /// reached.
+    Mmap(usize),
+}
+
+impl GrowError {
+    /// Get the error number.
+    pub fn errno(self) -> usize {
+        match self {
+            GrowError::Brk(errno) | GrowError::Mmap(errno) => errno,
+        }
+    }
+}
+
+impl fmt::Display for GrowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let call = match *self {
+            GrowError::Brk(_) => "brk",
+            GrowError::Mmap(_) => "mmap",
+        };
+
+        match self.errno() {
+            ENOMEM => write!(f, "{} failed with ENOMEM", call),
+            EMFILE => write!(f, "{} failed with EMFILE", call),
+            ENOSYS => write!(f, "{} failed with ENOSYS", call),
+            errno => write!(f, "{} failed with error {}", call, errno),
+        }
+    }
+}
+
+/// Convert a raw syscall return value to a result.
+///
+/// Linux returns errors as negative error numbers in the range `-4095..0`.
+#[inline]
+fn result(res: usize) -> Result<usize, usize> {
+    if res > -4096isize as usize {
+        Err(res.wrapping_neg())
+    } else {
+        Ok(res)
+    }
+}
+
+/// Get the page size.
+pub fn page_size() -> usize {
+    4096
+}
+
+/// Map `size` bytes of fresh, zeroed, readable and writable memory. See `man mmap`.
+///
+/// On failure (or if a failure is injected, see `inject`), the error is returned.
+#[cfg(all(target_os = "linux", not(feature = "miri")))]
+pub fn mmap(size: usize) -> Result<*mut u8, GrowError> {
+    /// Pages may be read.
+    const PROT_READ: usize = 1;
+    /// Pages may be written.
+    const PROT_WRITE: usize = 2;
+    /// The mapping is private.
+    const MAP_PRIVATE: usize = 2;
+    /// The mapping is not backed by any file.
+    const MAP_ANONYMOUS: usize = 0x20;
+
+    #[cfg(feature = "test_util")]
+    {
+        if let Some(errno) = inject::take_mmap() {
+            return Err(GrowError::Mmap(errno));
+        }
+    }
+
+    result(unsafe {
+        syscall!(MMAP, 0, size, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, !0, 0)
+    }).map(|x| x as *mut u8).map_err(GrowError::Mmap)
+}
+
+/// Map `size` bytes of fresh memory.
+///
+/// This is unsupported on this platform.
+#[cfg(any(not(target_os = "linux"), feature = "miri"))]
+pub fn mmap(_size: usize) -> Result<*mut u8, GrowError> {
+    #[cfg(feature = "test_util")]
+    {
+        if let Some(errno) = inject::take_mmap() {
+            return Err(GrowError::Mmap(errno));
+        }
+    }
+
+    Err(GrowError::Mmap(ENOSYS))
+}
+
+/// Map `size` bytes of fresh memory bound to the NUMA node `node`.
+///
+/// The pages of the mapping are only ever placed on the node. On failure, the error is returned,
+/// and nothing is mapped.
+///
+/// # Panics
+///
+/// This panics if `node` doesn't fit the node mask of `mbind`.
+pub fn map_on_node(size: usize, node: usize) -> Result<*mut u8, GrowError> {
+    assert!(node < ::core::mem::size_of::<usize>() * 8, "The node {} is out of range.", node);
+
+    let ptr = mmap(size)?;
+
+    // The mapping is fresh, so no page has been placed yet.
+    match unsafe { mbind(ptr, size, MPOL_BIND, 1 << node) } {
+        Ok(()) => Ok(ptr),
+        Err(errno) => {
+            unsafe { let _ = munmap(ptr, size); }
+
+            Err(GrowError::Mmap(errno))
+        },
+    }
+}
+
+/// The default memory policy of the process (see `mbind`).
+pub const MPOL_DEFAULT: usize = 0;
+/// Place the pages on the nodes of the mask only (see `mbind`).
+pub const MPOL_BIND: usize = 2;
+/// Spread the pages round-robin over the nodes of the mask (see `mbind`).
+pub const MPOL_INTERLEAVE: usize = 3;
+
+/// Set the NUMA memory policy of a range of pages. See `man mbind`.
+///
+/// `mask` holds a bit for every node. If `mbind` is mocked (see `inject::mock_mbind`), the call
+/// is only recorded. On failure, the error number is returned.
+#[cfg(all(target_os = "linux", not(feature = "miri")))]
+pub unsafe fn mbind(ptr: *mut u8, size: usize, mode: usize, mask: usize) -> Result<(), usize> {
+    #[cfg(feature = "test_util")]
+    {
+        if inject::record_mbind(ptr as usize, size, mode, mask) {
+            return Ok(());
+        }
+    }
+
+    // The kernel reads one bit less of the mask than it is told.
+    let max_node = ::core::mem::size_of::<usize>() * 8 + 1;
+    result(syscall!(MBIND, ptr, size, mode, &mask as *const usize, max_node, 0)).map(|_| ())
+}
+
+/// Set the NUMA memory policy of a range of pages.
+///
+/// This is unsupported on this platform, unless mocked (see `inject::mock_mbind`).
+#[cfg(any(not(target_os = "linux"), feature = "miri"))]
+#[allow(unused_variables)]
+pub unsafe fn mbind(ptr: *mut u8, size: usize, mode: usize, mask: usize) -> Result<(), usize> {
+    #[cfg(feature = "test_util")]
+    {
+        if inject::record_mbind(ptr as usize, size, mode, mask) {
+            return Ok(());
+        }
+    }
+
+    Err(ENOSYS)
+}
+
+/// Unmap memory. See `man munmap`.
+///
+/// On failure, the error number is returned.
+#[cfg(all(target_os = "linux", not(feature = "miri")))]
+pub unsafe fn munmap(ptr: *mut u8, size: usize) -> Result<(), usize> {
+    result(syscall!(MUNMAP, ptr, size)).map(|_| ())
+}
+
+/// Unmap memory.
+///
+/// This is unsupported on this platform.
+#[cfg(any(not(target_os = "linux"), feature = "miri"))]
+pub unsafe fn munmap(_ptr: *mut u8, _size: usize) -> Result<(), usize> {
+    Err(ENOSYS)
+}
+
+/// Lock memory into RAM, preventing it from being swapped. See `man mlock`.
+///
+/// On failure (e.g. `ENOMEM` when exceeding `RLIMIT_MEMLOCK`), the error number is returned.
+#[cfg(all(target_os = "linux", not(feature = "miri")))]
+pub unsafe fn mlock(ptr: *const u8, size: usize) -> Result<(), usize> {
+    result(syscall!(MLOCK, ptr, size)).map(|_| ())
+}
+
+/// Lock memory into RAM.
+///
+/// This is unsupported on this platform.
+#[cfg(any(not(target_os = "linux"), feature = "miri"))]
+pub unsafe fn mlock(_ptr: *const u8, _size: usize) -> Result<(), usize> {
+    Err(ENOSYS)
+}
+
+/// Unlock memory previously locked by `mlock`. See `man munlock`.
+#[cfg(all(target_os = "linux", not(feature = "miri")))]
+pub unsafe fn munlock(ptr: *const u8, size: usize) -> Result<(), usize> {
+    result(syscall!(MUNLOCK, ptr, size)).map(|_| ())
+}
+
+/// Unlock memory previously locked by `mlock`.
+///
+/// This is unsupported on this platform.
+#[cfg(any(not(target_os = "linux"), feature = "miri"))]
+pub unsafe fn munlock(_ptr: *const u8, _size: usize) -> Result<(), usize> {
+    Err(ENOSYS)
+}
+
+/// Exclude memory from core dumps (`MADV_DONTDUMP`), where available.
+///
+/// This is best-effort, so failure is ignored.
+#[allow(unused_variables)]
+pub unsafe fn madvise_dontdump(ptr: *mut u8, size: usize) {
+    #[cfg(all(target_os = "linux", not(feature = "miri")))]
+    {
+        /// Exclude from core dumps.
+        const MADV_DONTDUMP: usize = 16;
+
+        let _ = syscall!(MADVISE, ptr, size, MADV_DONTDUMP);
+    }
+}
+
+/// Tell the OS that some memory is unused (`MADV_DONTNEED`). See `man madvise`.
+///
+/// The memory stays mapped, but the OS is free to reclaim the pages, which are zeroed on the next
+/// access.
+#[cfg(all(target_os = "linux", not(feature = "miri")))]
+pub unsafe fn madvise_dontneed(ptr: *mut u8, size: usize) -> Result<(), usize> {
+    /// Drop the pages.
+    const MADV_DONTNEED: usize = 4;
+
+    result(syscall!(MADVISE, ptr, size, MADV_DONTNEED)).map(|_| ())
+}
+
+/// Tell the OS that some memory is unused (not supported on this platform).
+#[cfg(any(not(target_os = "linux"), feature = "miri"))]
+pub unsafe fn madvise_dontneed(_ptr: *mut u8, _size: usize) -> Result<(), usize> {
+    Err(ENOSYS)
+}
+
+/// Fill `buf` with random bytes from the kernel, without blocking. See `man getrandom`.
+///
+/// On success, the number of bytes written is returned, which can be less than `buf.len()`. On
+/// failure (e.g. `ENOSYS` on kernels before 3.17), the error number is returned.
+#[cfg(all(target_os = "linux", not(feature = "miri")))]
+pub fn getrandom(buf: &mut [u8]) -> Result<usize, usize> {
+    /// Fail rather than block if the pool isn't initialized yet.
+    const GRND_NONBLOCK: usize = 1;
+
+    result(unsafe { syscall!(GETRANDOM, buf.as_mut_ptr(), buf.len(), GRND_NONBLOCK) })
+}
+
+/// Fill `buf` with random bytes from the kernel.
+///
+/// This is unsupported on this platform.
+#[cfg(any(not(target_os = "linux"), feature = "miri"))]
+pub fn getrandom(_buf: &mut [u8]) -> Result<usize, usize> {
+    Err(ENOSYS)
+}
+
+/// Open a file for reading. See `man openat`.
+///
+/// `path` must be null-terminated. On success, the file descriptor is returned. On failure, the
+/// error number is returned.
+#[cfg(all(target_os = "linux", not(feature = "miri")))]
+pub fn open_read(path: &[u8]) -> Result<usize, usize> {
+    /// Resolve relative paths against the working directory.
+    const AT_FDCWD: isize = -100;
+    /// Open for reading only.
+    const O_RDONLY: usize = 0;
+    /// Close the file on `exec`.
+    const O_CLOEXEC: usize = 0o2000000;
+
+    assert!(path.last() == Some(&0), "The path is not null-terminated.");
+
+    result(unsafe { syscall!(OPENAT, AT_FDCWD, path.as_ptr(), O_RDONLY | O_CLOEXEC) })
+}
+
+/// Open a file for reading.
+///
+/// This is unsupported on this platform.
+#[cfg(any(not(target_os = "linux"), feature = "miri"))]
+pub fn open_read(_path: &[u8]) -> Result<usize, usize> {
+    Err(ENOSYS)
+}
+
+/// Read from a file into `buf`. See `man read`.
+///
+/// On success, the number of bytes read is returned. On failure, the error number is returned.
+#[cfg(all(target_os = "linux", not(feature = "miri")))]
+pub fn read(fd: usize, buf: &mut [u8]) -> Result<usize, usize> {
+    result(unsafe { syscall!(READ, fd, buf.as_mut_ptr(), buf.len()) })
+}
+
+/// Read from a file.
+///
+/// This is unsupported on this platform.
+#[cfg(any(not(target_os = "linux"), feature = "miri"))]
+pub fn read(_fd: usize, _buf: &mut [u8]) -> Result<usize, usize> {
+    Err(ENOSYS)
+}
+
+/// Write all of `buf` to a file. See `man write`.
+///
+/// Interrupted writes are retried, and short writes are continued. On failure, the error number is
+/// returned (`EIO` if nothing could be written, without any error).
+#[cfg(all(target_os = "linux", not(feature = "miri")))]
+pub fn write_all(fd: usize, mut buf: &[u8]) -> Result<(), usize> {
+    while !buf.is_empty() {
+        match result(unsafe { syscall!(WRITE, fd, buf.as_ptr(), buf.len()) }) {
+            Ok(0) => return Err(EIO),
+            Ok(n) => buf = &buf[n..],
+            Err(EINTR) => {},
+            Err(errno) => return Err(errno),
+        }
+    }
+
+    Ok(())
+}
+
+/// Write to a file.
+///
+/// This is unsupported on this platform.
+#[cfg(any(not(target_os = "linux"), feature = "miri"))]
+pub fn write_all(_fd: usize, _buf: &[u8]) -> Result<(), usize> {
+    Err(ENOSYS)
+}
+
+/// Close a file. See `man close`.
+#[cfg(all(target_os = "linux", not(feature = "miri")))]
+pub fn close(fd: usize) {
+    unsafe { syscall!(CLOSE, fd); }
+}
+
+/// Close a file (NOOP on this platform).
+#[cfg(any(not(target_os = "linux"), feature = "miri"))]
+pub fn close(_fd: usize) {}
+
+/// Get the CPU, which the calling thread is running on. See `man getcpu`.
+///
+/// The answer may be stale as soon as it is returned, so it is only a hint. On failure, the error
+/// number is returned.
+#[cfg(all(target_os = "linux", not(feature = "miri")))]
+pub fn getcpu() -> Result<usize, usize> {
+    let mut cpu: u32 = 0;
+
+    result(unsafe { syscall!(GETCPU, &mut cpu as *mut u32, 0, 0) }).map(|_| cpu as usize)
+}
+
+/// Get the CPU, which the calling thread is running on.
+///
+/// This is unsupported on this platform.
+#[cfg(any(not(target_os = "linux"), feature = "miri"))]
+pub fn getcpu() -> Result<usize, usize> {
+    Err(ENOSYS)
+}
+
+/// Get the number of CPUs, which the process may run on. See `man sched_getaffinity`.
+///
+/// On failure, the error number is returned.
+#[cfg(all(target_os = "linux", not(feature = "miri")))]
+pub fn cpu_count() -> Result<usize, usize> {
+    // Room for 1024 CPUs (on 64-bit targets), like glibc's `cpu_set_t`.
+    let mut mask = [0usize; 16];
+    let word = ::core::mem::size_of::<usize>();
+    let len = mask.len() * word;
+
+    // The kernel returns the number of bytes of the mask it wrote.
+    let written = result(unsafe { syscall!(SCHED_GETAFFINITY, 0, len, mask.as_mut_ptr()) })?;
+    let words = ::core::cmp::min(written / word, mask.len());
+
+    Ok(mask[..words].iter().map(|x| x.count_ones() as usize).sum())
+}
+
+/// Get the number of CPUs, which the process may run on.
+///
+/// This is unsupported on this platform.
+#[cfg(any(not(target_os = "linux"), feature = "miri"))]
+pub fn cpu_count() -> Result<usize, usize> {
+    Err(ENOSYS)
+}
+
+/// Read the monotonic clock, in nanoseconds. See `man clock_gettime`.
+///
+/// The origin is arbitrary, so only differences are meaningful.
+#[cfg(all(target_os = "linux", not(feature = "miri")))]
+pub fn clock_monotonic() -> u64 {
+    /// The clock, which never jumps.
+    const CLOCK_MONOTONIC: usize = 1;
+
+    /// A time as seconds and nanoseconds.
+    #[repr(C)]
+    struct Timespec {
+        /// The seconds.
+        sec: isize,
+        /// The nanoseconds.
+        nsec: isize,
+    }
+
+    let mut time = Timespec { sec: 0, nsec: 0 };
+    unsafe { syscall!(CLOCK_GETTIME, CLOCK_MONOTONIC, &mut time as *mut Timespec); }
+
+    time.sec as u64 * 1_000_000_000 + time.nsec as u64
+}
+
+/// Read the monotonic clock (not supported on this platform).
+///
+/// The clock stands still at zero.
+#[cfg(any(not(target_os = "linux"), feature = "miri"))]
+pub fn clock_monotonic() -> u64 {
+    0
+}
+
 /// Voluntarily give a time slice to the scheduler.
+#[cfg(not(feature = "miri"))]
 pub fn sched_yield() -> usize {
     unsafe { syscall!(SCHED_YIELD) }
 }
+
+/// Voluntarily give a time slice to the scheduler (NOOP under Miri).
+#[cfg(feature = "miri")]
+pub fn sched_yield() -> usize {
+    0
+}
+
+/// Wait on a futex.
+///
+/// This blocks the thread as long as `*addr == val`, or until woken up by `futex_wake`. Spurious
+/// wakeups are possible, so the condition has to be checked by the caller.
+///
+/// On platforms without futexes, this simply yields the time slice.
+#[cfg(all(target_os = "linux", not(feature = "miri")))]
+pub fn futex_wait(addr: *const u32, val: u32) {
+    /// Wait, if the value matches.
+    const FUTEX_WAIT_PRIVATE: usize = 0 | 128;
+
+    unsafe { syscall!(FUTEX, addr, FUTEX_WAIT_PRIVATE, val, 0); }
+}
+
+/// Wait on a futex.
+///
+/// This blocks the thread as long as `*addr == val`, or until woken up by `futex_wake`. Spurious
+/// wakeups are possible, so the condition has to be checked by the caller.
+///
+/// On platforms without futexes, this simply yields the time slice.
+#[cfg(any(not(target_os = "linux"), feature = "miri"))]
+pub fn futex_wait(_addr: *const u32, _val: u32) {
+    sched_yield();
+}
+
+/// Wake up at most `n` threads waiting on a futex.
+#[cfg(all(target_os = "linux", not(feature = "miri")))]
+pub fn futex_wake(addr: *const u32, n: u32) {
+    /// Wake up the waiters.
+    const FUTEX_WAKE_PRIVATE: usize = 1 | 128;
+
+    unsafe { syscall!(FUTEX, addr, FUTEX_WAKE_PRIVATE, n); }
+}
+
+/// Wake up at most `n` threads waiting on a futex.
+///
+/// On platforms without futexes, the waiters are yielding, so this is a no-op.
+#[cfg(any(not(target_os = "linux"), feature = "miri"))]
+pub fn futex_wake(_addr: *const u32, _n: u32) {}
+
+/// Hint the CPU that we are in a spin loop.
+#[inline(always)]
+pub fn cpu_relax() {
+    #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), not(feature = "miri")))]
+    unsafe { asm!("pause" :::: "volatile"); }
+}
@@ -0,0 +1,197 @@
+//! Valgrind client requests.
+//!
+//! Client requests are special instruction sequences, which are NOOPs when run natively, but are
+//! recognized by Valgrind. This allows informing Valgrind about the allocator's internal state
+//! without linking against anything.
+//!
+//! On unsupported architectures, every request is a NOOP.
+
+/// The base of the core requests.
+const CORE_BASE: usize = 0x1000;
+/// The base of the Memcheck requests (`'M' << 24 | 'C' << 16`).
+const MEMCHECK_BASE: usize = 0x4D43_0000;
+
+/// Check if running on Valgrind.
+const RUNNING_ON_VALGRIND: usize = CORE_BASE + 0x001;
+/// Declare a heap block.
+const MALLOCLIKE_BLOCK: usize = CORE_BASE + 0x301;
+/// Declare a heap block freed.
+const FREELIKE_BLOCK: usize = CORE_BASE + 0x302;
+/// Declare a heap block resized inplace.
+const RESIZEINPLACE_BLOCK: usize = CORE_BASE + 0x30b;
+/// Change the error reporting disablement of the current thread.
+const CHANGE_ERR_DISABLEMENT: usize = CORE_BASE + 0x801;
+/// Declare memory inaccessible.
+const MAKE_MEM_NOACCESS: usize = MEMCHECK_BASE;
+/// Declare memory accessible, but undefined.
+const MAKE_MEM_UNDEFINED: usize = MEMCHECK_BASE + 1;
+/// Declare memory accessible and defined.
+const MAKE_MEM_DEFINED: usize = MEMCHECK_BASE + 2;
+
+/// Make a client request.
+///
+/// `default` is returned when not running on Valgrind.
+#[cfg(all(target_arch = "x86_64", not(feature = "miri")))]
+#[inline]
+fn request(default: usize, req: usize, a1: usize, a2: usize, a3: usize, a4: usize, a5: usize)
+           -> usize {
+    let args = [req, a1, a2, a3, a4, a5];
+    let res;
+
+    unsafe {
+        asm!("rolq $$3, %rdi
+              rolq $$13, %rdi
+              rolq $$61, %rdi
+              rolq $$51, %rdi
+              xchgq %rbx, %rbx"
+             : "={rdx}"(res)
+             : "{rax}"(args.as_ptr()), "0"(default)
+             : "cc", "memory"
+             : "volatile");
+    }
+
+    res
+}
+
+/// Make a client request.
+///
+/// `default` is returned when not running on Valgrind.
+#[cfg(all(target_arch = "x86", not(feature = "miri")))]
+#[inline]
+fn request(default: usize, req: usize, a1: usize, a2: usize, a3: usize, a4: usize, a5: usize)
+           -> usize {
+    let args = [req, a1, a2, a3, a4, a5];
+    let res;
+
+    unsafe {
+        asm!("roll $$3, %edi
+              roll $$13, %edi
+              roll $$29, %edi
+              roll $$19, %edi
+              xchgl %ebx, %ebx"
+             : "={edx}"(res)
+             : "{eax}"(args.as_ptr()), "0"(default)
+             : "cc", "memory"
+             : "volatile");
+    }
+
+    res
+}
+
+/// Make a client request.
+///
+/// `default` is returned when not running on Valgrind.
+#[cfg(all(target_arch = "aarch64", not(feature = "miri")))]
+#[inline]
+fn request(default: usize, req: usize, a1: usize, a2: usize, a3: usize, a4: usize, a5: usize)
+           -> usize {
+    let args = [req, a1, a2, a3, a4, a5];
+    let res;
+
+    unsafe {
+        asm!("ror x12, x12, #3
+              ror x12, x12, #13
+              ror x12, x12, #51
+              ror x12, x12, #61
+              orr x10, x10, x10"
+             : "={x3}"(res)
+             : "{x4}"(args.as_ptr()), "0"(default)
+             : "cc", "memory"
+             : "volatile");
+    }
+
+    res
+}
+
+/// Make a client request.
+///
+/// `default` is returned when not running on Valgrind.
+#[cfg(all(target_arch = "arm", not(feature = "miri")))]
+#[inline]
+fn request(default: usize, req: usize, a1: usize, a2: usize, a3: usize, a4: usize, a5: usize)
+           -> usize {
+    let args = [req, a1, a2, a3, a4, a5];
+    let res;
+
+    unsafe {
+        asm!("mov r12, r12, ror #3
+              mov r12, r12, ror #13
+              mov r12, r12, ror #29
+              mov r12, r12, ror #19
+              orr r10, r10, r10"
+             : "={r3}"(res)
+             : "{r4}"(args.as_ptr()), "0"(default)
+             : "cc", "memory"
+             : "volatile");
+    }
+
+    res
+}
+
+/// Make a client request (NOOP on this platform, and under Miri).
+#[cfg(any(not(any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64",
+                  target_arch = "arm")),
+          feature = "miri"))]
+#[inline]
+fn request(default: usize, _req: usize, _a1: usize, _a2: usize, _a3: usize, _a4: usize,
+           _a5: usize) -> usize {
+    default
+}
+
+/// Check if the program runs on Valgrind.
+#[inline]
+pub fn running_on_valgrind() -> bool {
+    request(0, RUNNING_ON_VALGRIND, 0, 0, 0, 0, 0) != 0
+}
+
+/// Declare a heap block of `size` bytes at `ptr`, surrounded by redzones of `redzone` bytes.
+///
+/// If `zeroed` is true, the block is defined, otherwise it is undefined.
+#[inline]
+pub fn malloclike_block(ptr: *const u8, size: usize, redzone: usize, zeroed: bool) {
+    request(0, MALLOCLIKE_BLOCK, ptr as usize, size, redzone, zeroed as usize, 0);
+}
+
+/// Declare the heap block at `ptr` freed.
+#[inline]
+pub fn freelike_block(ptr: *const u8, redzone: usize) {
+    request(0, FREELIKE_BLOCK, ptr as usize, redzone, 0, 0, 0);
+}
+
+/// Declare the heap block at `ptr` resized inplace from `old_size` to `size` bytes.
+#[inline]
+pub fn resizeinplace_block(ptr: *const u8, old_size: usize, size: usize, redzone: usize) {
+    request(0, RESIZEINPLACE_BLOCK, ptr as usize, old_size, size, redzone, 0);
+}
+
+/// Disable the error reporting of the current thread.
+///
+/// This nests, and is undone by `enable_error_reporting`.
+#[inline]
+pub fn disable_error_reporting() {
+    request(0, CHANGE_ERR_DISABLEMENT, 1, 0, 0, 0, 0);
+}
+
+/// Enable the error reporting of the current thread again.
+#[inline]
+pub fn enable_error_reporting() {
+    request(0, CHANGE_ERR_DISABLEMENT, !0, 0, 0, 0, 0);
+}
+
+/// Declare memory inaccessible.
+#[inline]
+pub fn make_mem_noaccess(ptr: *const u8, size: usize) {
+    request(0, MAKE_MEM_NOACCESS, ptr as usize, size, 0, 0, 0);
+}
+
+/// Declare memory accessible, but undefined.
+#[inline]
+pub fn make_mem_undefined(ptr: *const u8, size: usize) {
+    request(0, MAKE_MEM_UNDEFINED, ptr as usize, size, 0, 0, 0);
+}
+
+/// Declare memory accessible and defined.
+#[inline]
+pub fn make_mem_defined(ptr: *const u8, size: usize) {
+    request(0, MAKE_MEM_DEFINED, ptr as usize, size, 0, 0, 0);
+}
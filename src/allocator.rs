@@ -4,29 +4,307 @@
 
 use prelude::*;
 
-use core::{mem, ops};
+use core::{cmp, mem, ops, ptr};
+use atomic;
 
-use {brk, sync};
-use bookkeeper::{self, Bookkeeper, Allocator};
+use {brk, sync, bootstrap, conf, fail, layout, mapped, secure, sig, sort, watermark};
+#[cfg(feature = "slab")]
+use slab;
+#[cfg(feature = "realloc_slack")]
+use slack;
+#[cfg(feature = "stats")]
+use stats;
+#[cfg(feature = "shadow_accounting")]
+use shadow;
+#[cfg(feature = "debugger")]
+use live;
+#[cfg(feature = "tagging")]
+use tag;
+#[cfg(feature = "sites")]
+use site::{self, Site};
+#[cfg(feature = "arenas")]
+use arena;
+#[cfg(all(feature = "debugger", feature = "tls"))]
+use scratch;
+#[cfg(feature = "trace")]
+use trace;
+#[cfg(feature = "log")]
+use log;
+use fail::AllocErr;
+use ptr::{map_addr, MAX_BLOCK};
+use block::FitResult;
+use meta::{self, Metadata};
+use bookkeeper::{self, Bookkeeper, Allocator, HeapError, RepairReport};
+use region::{self, OwnedRegion, Origin};
+#[cfg(feature = "mte")]
+use mte;
 
-use shim::config;
+#[cfg(feature = "tls")]
+use core::cell::Cell;
+
+use shim::{config, syscalls};
+#[cfg(feature = "debugger")]
+use shim::valgrind;
+
+/// The size of the redzone after every buffer.
+#[cfg(feature = "debugger")]
+const REDZONE: usize = config::VALGRIND_REDZONE;
+/// The size of the redzone after every buffer.
+#[cfg(not(feature = "debugger"))]
+const REDZONE: usize = 0;
 
 #[cfg(feature = "tls")]
 use tls;
 
+/// The minimal alignment of every buffer.
+///
+/// Like `malloc`, every buffer is aligned to at least this (the alignment of C's `max_align_t` on
+/// the common targets), whatever alignment was requested, such that C code can store any type in
+/// it. Larger alignments are honored as requested.
+pub const MIN_ALIGN: usize = 16;
+
+/// Does `alloc` have a fast path?
+///
+/// The fast path skips the checks and the bookkeeping of the features hooking into allocations, so
+/// it only exists without them.
+const FAST_PATH: bool = cfg!(not(any(feature = "debugger", feature = "header", feature = "log",
+                                     feature = "sanitize", feature = "sidetable", feature = "slab",
+                                     feature = "stats", feature = "tagging", feature = "trace",
+                                     feature = "mte")));
+
 /// Alias for the wrapper type of the thread-local variable holding the local allocator.
 #[cfg(feature = "tls")]
 type ThreadLocalAllocator = MoveCell<Option<LazyInit<fn() -> LocalAllocator, LocalAllocator>>>;
 
+/// Alias for the type of the mutex holding the global allocator.
+type GlobalMutex = sync::Mutex<LazyInit<fn() -> GlobalAllocator, GlobalAllocator>>;
+
 /// The global default allocator.
+///
+/// Every thread hits its lock, so it is kept off the lines of other values.
 // TODO: Remove these filthy function pointers.
-static GLOBAL_ALLOCATOR: sync::Mutex<LazyInit<fn() -> GlobalAllocator, GlobalAllocator>> =
-    sync::Mutex::new(LazyInit::new(GlobalAllocator::init));
+static GLOBAL_ALLOCATOR: sync::CachePadded<GlobalMutex> =
+    sync::CachePadded::new(sync::Mutex::ranked("global allocator", sync::rank::POOL,
+                                               LazyInit::new(GlobalAllocator::init)));
+/// The initialization of the global allocator.
+///
+/// Whether it is initialized by the constructor (with the `early_init` feature), lazily by the
+/// first allocation, or by `init_from_buffer`, it happens exactly once.
+static GLOBAL_INIT: sync::Once = sync::Once::new();
+/// The number of bytes reserved by the initialization, ahead of the first allocation.
+#[cfg(feature = "early_init")]
+static RESERVED: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+/// The number of bytes obtained by `preallocate`.
+static PREALLOCATED: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
 #[cfg(feature = "tls")]
 tls! {
     /// The thread-local allocator.
     static THREAD_ALLOCATOR: ThreadLocalAllocator = MoveCell::new(Some(LazyInit::new(LocalAllocator::init)));
 }
+#[cfg(feature = "tls")]
+tls! {
+    /// Is the current thread initializing an allocator?
+    ///
+    /// This is used to detect reentrancy (i.e. the allocator being called from its own
+    /// initialization).
+    static INITIALIZING: Cell<bool> = Cell::new(false);
+}
+
+/// Run some initialization routine of an allocator.
+///
+/// The initialization routine must not call the allocator itself. With the `tls` feature, this is
+/// checked, aborting if the allocator is reentered during the routine.
+#[inline]
+fn initialize<T, F: FnOnce() -> T>(init: F) -> T {
+    // Initialization routines can be nested (the local allocator initializing the global one), so
+    // we restore the old state afterwards.
+    #[cfg(feature = "tls")]
+    let old = INITIALIZING.with(|x| {
+        let old = x.get();
+        x.set(true);
+        old
+    });
+
+    let res = init();
+
+    #[cfg(feature = "tls")]
+    INITIALIZING.with(|x| x.set(old));
+
+    res
+}
+
+/// Initialize the global allocator, unless it is initialized already.
+///
+/// With the `early_init` feature, this also reserves the initial extension of the heap, such that
+/// the first allocations are served without extending the program break.
+#[inline]
+fn init_global() {
+    GLOBAL_INIT.call_once(|| {
+        let mut global_alloc = GLOBAL_ALLOCATOR.lock();
+
+        // Run the initializer of the global allocator.
+        global_alloc.get();
+
+        #[cfg(feature = "early_init")]
+        reserve(global_alloc.get());
+    });
+}
+
+/// Reserve the initial extension of the heap, and add it to the pool of the global allocator.
+#[cfg(feature = "early_init")]
+fn reserve(global_alloc: &mut GlobalAllocator) {
+    /// Logging...
+    log!(NOTE, "Reserving the initial extension of the heap.");
+
+    initialize(|| {
+        let (aligner, block, excessive) =
+            brk::lock().canonical_brk(config::EARLY_RESERVE, Align::of::<Block>());
+        RESERVED.store(aligner.size() + block.size() + excessive.size(),
+                       atomic::Ordering::Relaxed);
+
+        // The blocks are above every block in the pool, as BRK extends the data segment.
+        global_alloc.push(aligner);
+        global_alloc.push(block);
+        global_alloc.push(excessive);
+    });
+}
+
+/// Initialize the allocator before `main`.
+///
+/// This is registered as a constructor with the `early_init` feature, so the initialization
+/// happens before any thread is spawned. If the platform doesn't run it, the allocator is
+/// initialized lazily all the same.
+#[cfg(feature = "early_init")]
+pub extern fn early_init() {
+    /// Logging...
+    log!(NOTE, "Initializing the allocator from the constructor.");
+
+    init_global();
+}
+
+/// The number of bytes reserved by the initialization, ahead of the first allocation.
+///
+/// This is zero until the allocator is initialized.
+#[cfg(feature = "early_init")]
+pub fn reserved() -> usize {
+    RESERVED.load(atomic::Ordering::Relaxed)
+}
+
+/// Obtain `bytes` bytes of memory from the OS up front, and seed the global pool with them.
+///
+/// The memory is taken by moving the program break, or by a mapping, if the break cannot be
+/// moved. It is registered as preallocated, so it is never given back to the OS, and its pages
+/// are never advised, such that allocations served from it (up to about `bytes` bytes in total)
+/// make no syscalls. The size is rounded up to whole pages.
+///
+/// This is meant to be called at startup, e.g. by programs running under tools, which are
+/// confused by memory being mapped in the middle of the run.
+///
+/// # Errors
+///
+/// If the size is beyond the largest block, `AllocErr::TooLarge` is returned. If the OS cannot
+/// provide the memory (or there is no OS, in bare-metal mode), `AllocErr::Os` is returned, and if
+/// the region registry is full, `AllocErr::LimitReached` is. The pool is left untouched then.
+pub fn preallocate(bytes: usize) -> Result<(), AllocErr> {
+    log!(CALL, "Preallocating {} bytes.", bytes);
+
+    let size = Align::page().round_up(bytes).ok_or(AllocErr::TooLarge {
+        requested: bytes,
+        limit: MAX_BLOCK,
+    })?;
+    if size == 0 {
+        return Ok(());
+    }
+
+    // The initial extension of the heap is reserved first, as it must be above the pool.
+    check_reentrancy();
+    init_global();
+
+    let mut global_alloc = GLOBAL_ALLOCATOR.lock();
+    let global_alloc = global_alloc.get();
+
+    let block = match brk::lock().preallocate(size) {
+        Ok(block) => block,
+        Err(AllocErr::Os(_)) if !bare_metal() => map_preallocated(size)?,
+        Err(err) => return Err(err),
+    };
+    PREALLOCATED.fetch_add(block.size(), atomic::Ordering::Relaxed);
+
+    // Mapped memory can be anywhere, so the block is freed into place, rather than pushed.
+    global_alloc.free(block);
+    watermark::flush();
+
+    Ok(())
+}
+
+/// Map a preallocated segment of `size` bytes.
+///
+/// The mapping is registered as preallocated, so it is never unmapped.
+fn map_preallocated(size: usize) -> Result<Block, AllocErr> {
+    // Logging.
+    log!(NOTE, "Mapping a preallocated segment of size {}.", size);
+
+    let ptr = syscalls::mmap(size).map_err(AllocErr::Os)?;
+    let block = unsafe {
+        // The mapping was just acquired, and is `size` bytes long.
+        Block::from_raw_parts(Pointer::new(ptr), size)
+    };
+
+    region::register(OwnedRegion::new(block, Origin::Preallocated)).or_else(|region| {
+        // Logging.
+        log!(WARNING, "Unable to register the preallocated mapping {:?}.", region.block);
+
+        unsafe {
+            // The mapping was never handed out.
+            let _ = syscalls::munmap(ptr, size);
+        }
+
+        Err(AllocErr::LimitReached)
+    })
+}
+
+/// The number of bytes obtained by `preallocate`.
+pub fn preallocated() -> usize {
+    PREALLOCATED.load(atomic::Ordering::Relaxed)
+}
+
+/// The number of preallocated bytes, which are free in the pool of the global allocator.
+///
+/// The blocks held by the local allocators are counted as used.
+pub fn preallocated_free() -> usize {
+    let mut global_alloc = GLOBAL_ALLOCATOR.lock();
+    let global_alloc = global_alloc.get();
+
+    let mut free = 0;
+    region::for_each(|region| if region.origin == Origin::Preallocated {
+        for block in global_alloc.iter() {
+            let start = Pointer::from(block.empty_left()).addr();
+            let end = start + block.size();
+
+            free += cmp::min(end, region.end).saturating_sub(cmp::max(start, region.start));
+        }
+    });
+
+    free
+}
+
+/// The number of free bytes in the pool of the global allocator.
+pub fn global_free_bytes() -> usize {
+    GLOBAL_ALLOCATOR.lock().get().total_bytes()
+}
+
+/// Make sure that the allocator isn't reentered from its own initialization or logging.
+#[inline]
+fn check_reentrancy() {
+    #[cfg(feature = "tls")]
+    INITIALIZING.with(|x| {
+        assert!(!x.get(), "The allocator was reentered during its initialization. Is a log sink, \
+                a hook, or the shim allocating?")
+    });
+
+    #[cfg(feature = "log")]
+    log::internal::check_not_emitting();
+}
 
 /// Temporarily get the allocator.
 ///
@@ -36,12 +314,30 @@ tls! {
 /// 2. If the allocator is not yet initialized, fallback to the global allocator.
 /// 3. Unlock/move temporarily out of reference.
 ///
+/// An `else` expression can be given for the fallback, which is then evaluated without locking
+/// the global allocator (e.g. to grow the heap outside its lock).
+///
 /// This is a macro due to the lack of generic closure, which makes it impossible to have one
 /// closure for both cases (global and local).
 // TODO: Instead of falling back to the global allocator, the thread dtor should be set such that
 // it run after the TLS keys that might be declared.
 macro_rules! get_allocator {
-    (|$v:ident| $b:expr) => {{
+    (|$v:ident| $b:expr) => {
+        get_allocator!(|$v| $b, else {
+            // Lock the global allocator.
+            let mut guard = GLOBAL_ALLOCATOR.lock();
+
+            // Call the block in question.
+            let $v = guard.get();
+            $b
+        })
+    };
+    (|$v:ident| $b:expr, else $g:expr) => {{
+        // Make sure that we aren't called from the initialization of the allocator.
+        check_reentrancy();
+        // Initialize the global allocator, if the constructor didn't.
+        init_global();
+
         // Get the thread allocator, if TLS is enabled
         #[cfg(feature = "tls")]
         {
@@ -62,12 +358,7 @@ macro_rules! get_allocator {
                     // the global allocator.
                     log!(WARNING, "Accessing the allocator after deinitialization of the local allocator.");
 
-                    // Lock the global allocator.
-                    let mut guard = GLOBAL_ALLOCATOR.lock();
-
-                    // Call the block in question.
-                    let $v = guard.get();
-                    $b
+                    $g
                 }
             })
         }
@@ -75,12 +366,7 @@ macro_rules! get_allocator {
         // TLS is disabled, just use the global allocator.
         #[cfg(not(feature = "tls"))]
         {
-            // Lock the global allocator.
-            let mut guard = GLOBAL_ALLOCATOR.lock();
-
-            // Call the block in question.
-            let $v = guard.get();
-            $b
+            $g
         }
     }}
 }
@@ -118,28 +404,217 @@ struct GlobalAllocator {
 
 impl GlobalAllocator {
     /// Initialize the global allocator.
+    #[cfg(not(feature = "miri"))]
     fn init() -> GlobalAllocator {
         /// Logging...
         log!(NOTE, "Initializing the global allocator.");
 
-        // The initial acquired segment.
-        let (aligner, initial_segment, excessive) =
-            brk::lock().canonical_brk(4 * bookkeeper::EXTRA_ELEMENTS * mem::size_of::<Block>(), mem::align_of::<Block>());
+        // Load the runtime configuration.
+        conf::load();
+
+        initialize(|| {
+            // The size of the initial segment.
+            let size = 4 * bookkeeper::EXTRA_ELEMENTS * mem::size_of::<Block>();
+
+            // The initial segment is served by the bootstrap arena, such that initialization
+            // never calls the allocator.
+            if let Some(initial_segment) = bootstrap::alloc(size, Align::of::<Block>()) {
+                return GlobalAllocator {
+                    inner: Bookkeeper::new(unsafe {
+                        Vec::from_raw_parts(initial_segment, 0)
+                    }),
+                };
+            }
+
+            // The bootstrap arena is exhausted, so we fall back to BRK'ing the initial segment.
+            let (aligner, initial_segment, excessive) =
+                brk::lock().canonical_brk(size, Align::of::<Block>());
+
+            // Initialize the new allocator.
+            let mut res = GlobalAllocator {
+                inner: Bookkeeper::new(unsafe {
+                    Vec::from_raw_parts(initial_segment, 0)
+                }),
+            };
+
+            // Free the secondary space.
+            res.push(aligner);
+            res.push(excessive);
+
+            res
+        })
+    }
+
+    /// Initialize the global allocator under Miri.
+    ///
+    /// No syscalls can be made, so the allocator is put in bare-metal mode, and seeded with a
+    /// buffer of the host allocator (see `config::MIRI_BUFFER`), which is never given back.
+    #[cfg(feature = "miri")]
+    fn init() -> GlobalAllocator {
+        /// Logging...
+        log!(NOTE, "Initializing the global allocator from the host allocator.");
+
+        let buf: ::std::boxed::Box<[u8]> = ::std::iter::repeat(0).take(config::MIRI_BUFFER)
+            .collect::<::std::vec::Vec<u8>>().into_boxed_slice();
+
+        conf::FLAGS.bare_metal.store(true, atomic::Ordering::SeqCst);
+        GlobalAllocator::from_buffer(unsafe {
+            // The buffer is leaked, so it lives as long as the program.
+            &mut *::std::boxed::Box::into_raw(buf)
+        })
+    }
+
+    /// Initialize the global allocator from a static buffer.
+    ///
+    /// The metadata is carved from the start of the buffer, and the rest seeds the pool. The
+    /// buffer is registered as a static region.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the buffer is too small to hold the metadata.
+    fn from_buffer(buf: &'static mut [u8]) -> GlobalAllocator {
+        /// Logging...
+        log!(NOTE, "Initializing the global allocator from a buffer.");
+
+        // Load the runtime configuration.
+        conf::load();
 
-        // Initialize the new allocator.
-        let mut res = GlobalAllocator {
-            inner: Bookkeeper::new(unsafe {
+        initialize(|| {
+            // The size of the initial segment.
+            let size = 4 * bookkeeper::EXTRA_ELEMENTS * mem::size_of::<Block>();
+
+            let mut block = unsafe {
                 // LAST AUDIT: 2016-08-21 (Ticki).
 
-                Vec::from_raw_parts(initial_segment, 0)
-            }),
-        };
+                // The buffer is static and borrowed mutably, so we own it for the rest of the
+                // program.
+                Block::from_raw_parts(Pointer::new(buf.as_mut_ptr()), buf.len())
+            };
 
-        // Free the secondary space.
-        res.push(aligner);
-        res.push(excessive);
+            assert!(block.fits(size, Align::of::<Block>()),
+                    "The buffer is too small to hold the allocator metadata.");
+            let (aligner, rest) = block.align(Align::of::<Block>()).unwrap();
+            let (initial_segment, rest) = rest.split(size);
 
-        res
+            // Register the initial segment. The rest of the buffer is registered as it seeds the
+            // pool, and merged with this region.
+            let initial_segment = region::register(OwnedRegion::new(initial_segment,
+                                                                    Origin::Static))
+                .unwrap_or_else(region::unregistered);
+
+            let mut res = GlobalAllocator {
+                inner: Bookkeeper::new(unsafe {
+                    Vec::from_raw_parts(initial_segment, 0)
+                }),
+            };
+
+            // Seed the pool with the rest.
+            res.extend_from_region(OwnedRegion::new(aligner, Origin::Static));
+            res.extend_from_region(OwnedRegion::new(rest, Origin::Static));
+
+            res
+        })
+    }
+
+    /// Release the free memory at the end of the data segment to the OS.
+    ///
+    /// Only memory registered as originating from the program break is released. The number of
+    /// bytes released is returned.
+    fn trim(&mut self) -> usize {
+        self.trim_bounded(!0)
+    }
+
+    /// Release the free memory at the end of the data segment, moving the program break at most
+    /// `max` times.
+    ///
+    /// The number of bytes released is returned.
+    fn trim_bounded(&mut self, max: usize) -> usize {
+        let mut trimmed = 0;
+        let mut releases = 0;
+
+        while releases < max {
+            let block = match self.pop() {
+                Some(block) => block,
+                None => break,
+            };
+
+            // Empty blocks are simply dropped from the pool.
+            if block.is_empty() { continue; }
+
+            // Only the memory from the program break can be released.
+            let (below, block) = match split_brk(block) {
+                Ok(x) => x,
+                Err(block) => {
+                    // The top of the block isn't from the program break, so it must stay.
+                    self.push(block);
+                    break;
+                },
+            };
+            let split = below.is_some();
+
+            let size = block.size();
+            let res = brk::lock().release(block);
+            releases += 1;
+
+            if let Some(below) = below {
+                self.push(below);
+            }
+
+            match res {
+                Ok(()) => trimmed += size,
+                Err(block) => {
+                    // The block is not next to the program break, so neither are the rest.
+                    self.push(block);
+                    break;
+                },
+            }
+
+            // The memory below the region is now the top of the pool.
+            if split { break; }
+        }
+
+        trimmed
+    }
+
+    /// Serve an allocation of the locked pool, which the heap failed to grow for.
+    ///
+    /// This is `relieve` with the pool locked. The deferred frees cannot be flushed meanwhile,
+    /// since their locks rank below the pool, so only the slack kept after shrunk buffers is given
+    /// back, before the OOM handler is called.
+    #[cold]
+    #[allow(unused_variables)]
+    fn relieve(&mut self, size: usize, align: Align, err: AllocErr) -> Block {
+        #[cfg(feature = "realloc_slack")]
+        {
+            if release_slack_to(self) != 0 {
+                if let Some(res) = self.alloc_pooled(size, align) {
+                    return res;
+                }
+            }
+        }
+
+        fail::oom(err)
+    }
+}
+
+/// Split a block from the top of the pool at the start of the region from the program break, which
+/// the top of the block lies in.
+///
+/// The block might have been merged with memory below the region (e.g. preallocated memory), which
+/// must stay. The memory below the region (if any) and the rest of the block are returned. If the
+/// top of the block isn't from the program break, the block is returned as the error.
+fn split_brk(block: Block) -> Result<(Option<Block>, Block), Block> {
+    let start = Pointer::from(block.empty_left()).addr();
+    let region = match region::lookup(start + block.size() - 1) {
+        Some(region) if region.origin == Origin::Brk => region,
+        _ => return Err(block),
+    };
+
+    if region.start > start {
+        let (below, block) = block.split(region.start - start);
+        Ok((Some(below), block))
+    } else {
+        Ok((None, block))
     }
 }
 
@@ -147,9 +622,12 @@ derive_deref!(GlobalAllocator, Bookkeeper);
 
 impl Allocator for GlobalAllocator {
     #[inline]
-    fn alloc_fresh(&mut self, size: usize, align: usize) -> Block {
+    fn alloc_fresh(&mut self, size: usize, align: Align) -> Block {
         // Obtain what you need.
-        let (alignment_block, res, excessive) = brk::lock().canonical_brk(size, align);
+        let (alignment_block, res, excessive) = match try_grow_global(size, align) {
+            Ok(res) => res,
+            Err(err) => return self.relieve(size, align, err),
+        };
 
         // Add it to the list. This will not change the order, since the pointer is higher than all
         // the previous blocks (BRK extends the data segment). Although, it is worth noting that
@@ -172,11 +650,22 @@ impl Allocator for GlobalAllocator {
                 /// Logging...
                 log!(NOTE, "Memtrimming the global allocator.");
 
-                // Release the block to the OS.
-                if let Err(block) = brk::lock().release(block) {
-                    // It failed, put the block back.
-                    // TODO: This can be done faster.
-                    self.push(block);
+                // Release the part of the block from the program break to the OS.
+                match split_brk(block) {
+                    Ok((below, block)) => {
+                        let res = brk::lock().release(block);
+
+                        // The memory below the region stays.
+                        if let Some(below) = below {
+                            self.push(below);
+                        }
+                        if let Err(block) = res {
+                            // It failed, put the block back.
+                            // TODO: This can be done faster.
+                            self.push(block);
+                        }
+                    },
+                    Err(block) => self.push(block),
                 }
 
                 // Note that this block is the only block next to the program break, due to the
@@ -194,6 +683,22 @@ impl Allocator for GlobalAllocator {
     }
 }
 
+/// Grow the heap for the global allocator, failing rather than calling the OOM handler.
+///
+/// This only takes the BRK lock, so the global allocator may or may not be locked meanwhile. See
+/// `BrkLock::try_canonical_brk`.
+fn try_grow_global(size: usize, align: Align) -> Result<(Block, Block, Block), AllocErr> {
+    let res = brk::lock().try_canonical_brk(size, align)?;
+    #[cfg(feature = "stats")]
+    stats::record_align_path(if res.0.is_empty() {
+        stats::AlignPath::Natural
+    } else {
+        stats::AlignPath::Explicit
+    });
+
+    Ok(res)
+}
+
 /// A local allocator.
 ///
 /// This acquires memory from the upstream (global) allocator, which is protected by a `Mutex`.
@@ -201,6 +706,193 @@ impl Allocator for GlobalAllocator {
 pub struct LocalAllocator {
     // The inner bookkeeper.
     inner: Bookkeeper,
+    /// The frees deferred by the thread.
+    ///
+    /// The list is registered (see `PENDING_LISTS`), and freed along with the local allocator.
+    #[cfg(feature = "bounded_free")]
+    pending: Pointer<PendingNode>,
+}
+
+/// The frees deferred by a thread.
+///
+/// With the `bounded_free` feature, freeing a block to the pool only queues it here, which takes
+/// constant time. The queued blocks are merged into the pool in bounded slices: by the frees
+/// finding the list full, and by `maintenance`. Refills of the pool hand them upstream instead,
+/// and so does `relieve` for the lists of every thread.
+#[cfg(feature = "bounded_free")]
+struct Pending {
+    /// The queued blocks, as start and size.
+    blocks: [(*mut u8, usize); config::PENDING_FREES],
+    /// The number of queued blocks.
+    len: usize,
+    /// The number of queued bytes.
+    bytes: usize,
+}
+
+// The queued blocks are owned by the list.
+#[cfg(feature = "bounded_free")]
+unsafe impl Send for Pending {}
+
+/// A registered list of deferred frees.
+#[cfg(feature = "bounded_free")]
+struct PendingNode {
+    /// The deferred frees.
+    ///
+    /// The owning thread queues and merges them, and other threads flush them, when the heap
+    /// cannot grow. The lock is only held to queue or take a single block.
+    list: sync::Mutex<Pending>,
+    /// The next registered list, or null.
+    ///
+    /// This is guarded by the lock of the registry.
+    next: *mut PendingNode,
+}
+
+/// The registered lists of deferred frees.
+#[cfg(feature = "bounded_free")]
+struct PendingLists {
+    /// The first list, or null.
+    first: *mut PendingNode,
+}
+
+// The lists are only reached behind the lock of the registry.
+#[cfg(feature = "bounded_free")]
+unsafe impl Send for PendingLists {}
+
+/// The lists of deferred frees of the live threads.
+///
+/// Through this, the deferred frees of every thread are flushed before an allocation runs out of
+/// memory (see `relieve`), rather than only those of the allocating thread.
+#[cfg(feature = "bounded_free")]
+static PENDING_LISTS: sync::Mutex<PendingLists> = sync::Mutex::ranked("pending lists",
+    sync::rank::PENDING_LISTS, PendingLists { first: ptr::null_mut() });
+
+#[cfg(feature = "bounded_free")]
+impl Pending {
+    /// Create an empty list.
+    fn new() -> Pending {
+        Pending {
+            blocks: [(ptr::null_mut(), 0); config::PENDING_FREES],
+            len: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Queue a block.
+    ///
+    /// The list must not be full.
+    fn push(&mut self, block: Block) {
+        debug_assert!(self.len < config::PENDING_FREES, "The list of deferred frees is full.");
+
+        let size = block.size();
+        self.blocks[self.len] = (*Pointer::from(block), size);
+        self.len += 1;
+        self.bytes += size;
+    }
+
+    /// Take the most recently queued block.
+    fn pop(&mut self) -> Option<Block> {
+        if self.len == 0 {
+            return None;
+        }
+
+        self.len -= 1;
+        let (ptr, size) = self.blocks[self.len];
+        self.bytes -= size;
+
+        unsafe {
+            // The block was queued by a free, so it is owned by the list.
+            Some(Block::from_raw_parts(Pointer::new(ptr), size))
+        }
+    }
+}
+
+/// Register an empty list of deferred frees.
+#[cfg(feature = "bounded_free")]
+fn register_pending() -> Pointer<PendingNode> {
+    let node: Pointer<PendingNode> = Pointer::from(global_alloc(mem::size_of::<PendingNode>(),
+                                                                Align::of::<PendingNode>())).cast();
+
+    let mut lists = PENDING_LISTS.lock();
+    unsafe {
+        // The block was just allocated for the node, which is linked in first.
+        ptr::write(*node, PendingNode {
+            list: sync::Mutex::ranked("pending", sync::rank::PENDING, Pending::new()),
+            next: lists.first,
+        });
+    }
+    lists.first = *node;
+
+    node
+}
+
+/// Unlink a list of deferred frees, and free its node.
+///
+/// The list must be empty.
+#[cfg(feature = "bounded_free")]
+fn deregister_pending(node: Pointer<PendingNode>) {
+    {
+        let mut lists = PENDING_LISTS.lock();
+        let mut link: *mut *mut PendingNode = &mut lists.first;
+
+        unsafe {
+            // The links are guarded by the lock of the registry, and the node is registered, so
+            // the walk ends at its link.
+            debug_assert!((**node).list.lock().len == 0, "Unregistering deferred frees.");
+            while *link != *node {
+                link = &mut (**link).next;
+            }
+            *link = (**node).next;
+        }
+    }
+
+    unsafe {
+        // The node is unlinked, so nothing refers to it anymore.
+        GLOBAL_ALLOCATOR.lock().get().free(Block::from_raw_parts(node.cast(),
+                                                                 mem::size_of::<PendingNode>()));
+    }
+}
+
+/// Hand the deferred frees of every thread to the global allocator.
+///
+/// With the `arenas` feature, the blocks go back to the arenas owning them instead (see
+/// `arena::free`). The number of bytes handed over is returned.
+#[cfg(feature = "bounded_free")]
+fn flush_pending() -> usize {
+    let mut flushed = 0;
+    while let Some(block) = pop_pending() {
+        flushed += block.size();
+
+        #[cfg(feature = "arenas")]
+        arena::free(block);
+        #[cfg(not(feature = "arenas"))]
+        GLOBAL_ALLOCATOR.lock().get().free_used(block);
+    }
+
+    flushed
+}
+
+/// Take a deferred free of any thread.
+///
+/// The registry is only locked meanwhile, so the block can be freed to an arena, whose lock ranks
+/// below it.
+#[cfg(feature = "bounded_free")]
+fn pop_pending() -> Option<Block> {
+    let lists = PENDING_LISTS.lock();
+
+    let mut node = lists.first;
+    while !node.is_null() {
+        unsafe {
+            // The registered nodes are only freed after being unlinked under the lock of the
+            // registry, which is held.
+            if let Some(block) = (*node).list.lock().pop() {
+                return Some(block);
+            }
+
+            node = (*node).next;
+        }
+    }
+
+    None
 }
 
 #[cfg(feature = "tls")]
@@ -221,50 +913,169 @@ impl LocalAllocator {
             // `None` as a permanent marker indicating that the allocator is deinitialized. After such
             // a state is in place, all allocation calls will be redirected to the global allocator,
             // which is of course still usable at this moment.
-            let alloc = alloc.replace(None).expect("Thread-local allocator is already freed.");
+            let alloc = alloc.replace(None).expect("Thread-local allocator is already freed.")
+                .into_inner();
 
-            // Lock the global allocator.
-            let mut global_alloc = GLOBAL_ALLOCATOR.lock();
-            let global_alloc = global_alloc.get();
+            // The deferred frees are merged first, such that they are released along with the
+            // rest of the pool.
+            #[cfg(feature = "bounded_free")]
+            let alloc = {
+                let mut alloc = alloc;
+                alloc.merge_pending(!0);
+                deregister_pending(alloc.pending.clone());
+
+                alloc
+            };
+
+            // With arenas, the blocks go back to the arenas owning them.
+            #[cfg(feature = "arenas")]
+            alloc.inner.for_each(arena::free);
 
-            // TODO: we know this is sorted, so we could abuse that fact to faster insertion in the
-            // global allocator.
+            #[cfg(not(feature = "arenas"))]
+            {
+                // Lock the global allocator.
+                let mut global_alloc = GLOBAL_ALLOCATOR.lock();
+                let global_alloc = global_alloc.get();
 
-            alloc.into_inner().inner.for_each(move |block| global_alloc.free(block));
+                // TODO: we know this is sorted, so we could abuse that fact to faster insertion in
+                // the global allocator.
+
+                alloc.inner.for_each(move |block| global_alloc.free(block));
+            }
         }
 
         /// Logging...
         log!(NOTE, "Initializing the local allocator.");
 
-        // The initial acquired segment.
-        let initial_segment = GLOBAL_ALLOCATOR
-            .lock()
-            .get()
-            .alloc(4 * bookkeeper::EXTRA_ELEMENTS * mem::size_of::<Block>(), mem::align_of::<Block>());
+        // Load the runtime configuration.
+        conf::load();
 
-        unsafe {
-            // LAST AUDIT: 2016-08-21 (Ticki).
+        initialize(|| {
+            // The size of the initial segment.
+            let size = 4 * bookkeeper::EXTRA_ELEMENTS * mem::size_of::<Block>();
+
+            // The initial acquired segment. We prefer the bootstrap arena, and fall back to the
+            // global allocator when it is exhausted.
+            let initial_segment = bootstrap::alloc(size, Align::of::<Block>())
+                .unwrap_or_else(|| global_alloc(size, Align::of::<Block>()));
 
-            // Register the thread destructor on the current thread.
-            THREAD_ALLOCATOR.register_thread_destructor(dtor);
+            unsafe {
+                // LAST AUDIT: 2016-08-21 (Ticki).
+
+                // Register the thread destructor on the current thread.
+                THREAD_ALLOCATOR.register_thread_destructor(dtor);
 
-            LocalAllocator {
-                inner: Bookkeeper::new(Vec::from_raw_parts(initial_segment, 0)),
+                LocalAllocator {
+                    inner: Bookkeeper::new(Vec::from_raw_parts(initial_segment, 0)),
+                    #[cfg(feature = "bounded_free")]
+                    pending: register_pending(),
+                }
             }
-        }
+        })
     }
 }
 
 #[cfg(feature = "tls")]
 derive_deref!(LocalAllocator, Bookkeeper);
 
+#[cfg(feature = "bounded_free")]
+impl LocalAllocator {
+    /// Get the list of deferred frees.
+    fn pending(&self) -> &sync::Mutex<Pending> {
+        unsafe {
+            // The node is registered for as long as the local allocator lives.
+            &(**self.pending).list
+        }
+    }
+
+    /// Defer the free of a block.
+    ///
+    /// If the list of deferred frees is full, a slice of it is merged into the pool first. The
+    /// number of pool operations done (the queuing included) is returned.
+    fn defer_free(&mut self, block: Block) -> usize {
+        {
+            let mut pending = self.pending().lock();
+            if pending.len < config::PENDING_FREES {
+                pending.push(block);
+
+                return 1;
+            }
+        }
+
+        let merged = self.merge_pending(config::PENDING_FREE_SLICE);
+        self.pending().lock().push(block);
+
+        merged + 1
+    }
+
+    /// Merge up to `max` deferred frees into the pool.
+    ///
+    /// The number of blocks merged is returned.
+    fn merge_pending(&mut self, max: usize) -> usize {
+        let mut merged = 0;
+        while merged < max {
+            // The list is only locked for taking the block, as other threads may flush it.
+            let block = self.pending().lock().pop();
+            match block {
+                Some(block) => self.free_used(block),
+                None => break,
+            }
+
+            merged += 1;
+        }
+
+        merged
+    }
+
+    /// Hand every deferred free to the upstream allocator.
+    fn release_pending(&mut self) {
+        let (len, bytes) = {
+            let pending = self.pending().lock();
+            (pending.len, pending.bytes)
+        };
+        if len == 0 {
+            return;
+        }
+
+        log!(DEBUG, "Releasing {} deferred frees of {} bytes upstream.", len, bytes);
+
+        loop {
+            let block = self.pending().lock().pop();
+            let block = match block {
+                Some(block) => block,
+                None => break,
+            };
+
+            // With arenas, the blocks go back to the arenas owning them.
+            #[cfg(feature = "arenas")]
+            arena::free(block);
+            #[cfg(not(feature = "arenas"))]
+            GLOBAL_ALLOCATOR.lock().get().free(block);
+        }
+    }
+}
+
 #[cfg(feature = "tls")]
 impl Allocator for LocalAllocator {
     #[inline]
-    fn alloc_fresh(&mut self, size: usize, align: usize) -> Block {
-        // Get the block from the global allocator. Please note that we cannot canonicalize `size`,
-        // due to freeing excessive blocks would change the order.
-        GLOBAL_ALLOCATOR.lock().get().alloc(size, align)
+    fn alloc_fresh(&mut self, size: usize, align: Align) -> Block {
+        // Before more memory is acquired (and possibly found missing), the deferred frees are
+        // handed upstream, where they are merged and can serve the request. They cannot be merged
+        // into this pool, which is in the middle of an operation.
+        #[cfg(feature = "bounded_free")]
+        self.release_pending();
+        // Refills flush the buffered statistics.
+        #[cfg(feature = "stats")]
+        stats::flush();
+
+        // Get the block from the arenas or the global allocator. Please note that we cannot
+        // canonicalize `size`, due to freeing excessive blocks would change the order.
+        #[cfg(feature = "arenas")]
+        let res = arena::alloc(size, align);
+        #[cfg(not(feature = "arenas"))]
+        let res = global_alloc(size, align);
+
+        res
     }
 
     #[inline]
@@ -276,58 +1087,1789 @@ impl Allocator for LocalAllocator {
             // Log stuff.
             log!(NOTE, "Memtrimming the local allocator.");
 
-            // Lock the global allocator.
-            let mut global_alloc = GLOBAL_ALLOCATOR.lock();
-            let global_alloc = global_alloc.get();
+            // Spills flush the buffered statistics.
+            #[cfg(feature = "stats")]
+            stats::flush();
 
-            while let Some(block) = self.pop() {
-                // Pop'n'free.
-                global_alloc.free(block);
+            // With arenas, the blocks go back to the arenas owning them.
+            #[cfg(feature = "arenas")]
+            {
+                while let Some(block) = self.pop() {
+                    arena::free(block);
 
-                // Memtrim 'till we won't memtrim anymore.
-                if self.total_bytes() < config::LOCAL_MEMTRIM_STOP { break; }
+                    if self.total_bytes() < config::LOCAL_MEMTRIM_STOP { break; }
+                }
+            }
+
+            #[cfg(not(feature = "arenas"))]
+            {
+                // Lock the global allocator.
+                let mut global_alloc = GLOBAL_ALLOCATOR.lock();
+                let global_alloc = global_alloc.get();
+
+                while let Some(block) = self.pop() {
+                    // Pop'n'free.
+                    global_alloc.free(block);
+
+                    // Memtrim 'till we won't memtrim anymore.
+                    if self.total_bytes() < config::LOCAL_MEMTRIM_STOP { break; }
+                }
+            }
+        }
+    }
+}
+
+/// The global allocator was already initialized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AlreadyInitialized;
+
+/// Initialize the allocator from a static buffer, for targets without an OS.
+///
+/// This puts the allocator in bare-metal mode: The pool is seeded with the buffer, and no
+/// syscalls are ever made. The program break is never touched, so when the buffer is exhausted,
+/// allocation fails and the OOM handler is called.
+///
+/// This must be called before the first allocation, otherwise `AlreadyInitialized` is returned
+/// and nothing changes. With the `early_init` feature, the allocator is initialized before
+/// `main`, so this always fails.
+///
+/// # Panics
+///
+/// This panics if the buffer is too small to hold the allocator metadata (a few hundred bytes).
+pub fn init_from_buffer(buf: &'static mut [u8]) -> Result<(), AlreadyInitialized> {
+    log!(CALL, "Initializing from a buffer of size {}.", buf.len());
+
+    let mut res = Err(AlreadyInitialized);
+    GLOBAL_INIT.call_once(|| {
+        let mut global_alloc = GLOBAL_ALLOCATOR.lock();
+        if global_alloc.is_initialized() {
+            return;
+        }
+
+        // Enter bare-metal mode before initializing, such that no syscalls are made.
+        conf::FLAGS.bare_metal.store(true, atomic::Ordering::SeqCst);
+        global_alloc.set(GlobalAllocator::from_buffer(buf));
+
+        res = Ok(());
+    });
+
+    res
+}
+
+/// Is the allocator in bare-metal mode?
+///
+/// In this mode, the global allocator is seeded from a static buffer, and no syscalls are made.
+/// See `init_from_buffer`.
+#[inline]
+pub fn bare_metal() -> bool {
+    conf::FLAGS.bare_metal.load(atomic::Ordering::Relaxed)
+}
+
+/// The memory reclaimed by `purge`, in bytes.
+///
+/// This is laid out as a C struct, since the `ffi` feature hands it to C (as
+/// `ralloc_purge_report`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct PurgeReport {
+    /// The bytes in empty slabs returned to the pool.
+    pub slabs: usize,
+    /// The bytes moved from the local allocator of the calling thread to the global allocator (or
+    /// with the `arenas` feature, to the arenas owning them).
+    pub local: usize,
+    /// The bytes released to the OS by moving the program break back.
+    pub trimmed: usize,
+    /// The bytes of free pages given back to the OS, while staying in the pool.
+    pub advised: usize,
+    /// The bytes kept after shrunk buffers, which were given back to the pool.
+    ///
+    /// This is always zero without the `realloc_slack` feature.
+    pub slack: usize,
+}
+
+impl PurgeReport {
+    /// The total number of bytes given back to the OS.
+    ///
+    /// The bytes moved between the allocator layers are not counted, since they are still held
+    /// by the process.
+    pub fn total(&self) -> usize {
+        self.trimmed + self.advised
+    }
+}
+
+/// Give as much memory back to the OS as possible.
+///
+/// This is meant to be called after a spike in memory use. The stages are run in order, each
+/// feeding the next:
+///
+/// 1. Empty slabs are returned to the pool.
+/// 2. The free memory of the calling thread's local allocator is moved to the global allocator,
+///    where it is merged with the neighboring blocks. The local allocators of other threads are
+///    left untouched.
+/// 3. The free memory at the end of the data segment is released by moving the program break.
+/// 4. The interior pages of the large cold free blocks are given back to the OS.
+///
+/// With the `realloc_slack` feature, the slack kept after shrunk buffers is given back to the pool
+/// before the first stage.
+///
+/// It is safe to call this while other threads allocate.
+pub fn purge() -> PurgeReport {
+    log!(CALL, "Purging.");
+
+    let mut report = PurgeReport::default();
+
+    #[cfg(feature = "realloc_slack")]
+    {
+        report.slack = release_slack(!0);
+    }
+
+    #[cfg(feature = "slab")]
+    {
+        report.slabs = slab::release_empty();
+    }
+
+    #[cfg(feature = "tls")]
+    {
+        report.local = flush_local();
+    }
+
+    // Nothing can be given back in bare-metal mode.
+    if !bare_metal() {
+        let mut global_alloc = GLOBAL_ALLOCATOR.lock();
+        let global_alloc = global_alloc.get();
+
+        report.trimmed = global_alloc.trim();
+        report.advised = global_alloc.advise_free();
+    }
+    watermark::flush();
+
+    log!(NOTE, "Purged: {:?}.", report);
+
+    report
+}
+
+/// Validate the pools, repairing the minor inconsistencies in place.
+///
+/// The pool of the calling thread's local allocator and the global pool are validated. Blocks
+/// left adjacent are merged, stray empty blocks are removed, and wrong byte counts are corrected
+/// (see `Bookkeeper::repair`), each repair logging a warning. This lets a program limp along
+/// after minor damage, where the consistency checks would abort.
+///
+/// Fatal inconsistencies, such as overlapping blocks, are never papered over: The first one found
+/// is returned, and the rest of the pool is left as is. With the `slab` feature, the slabs are
+/// validated first, and any inconsistency of theirs is fatal.
+pub fn validate_and_repair() -> Result<RepairReport, HeapError> {
+    log!(CALL, "Validating and repairing the pools.");
+
+    let mut report = RepairReport::default();
+
+    // The slabs are validated, but never repaired, since their cells are owned by the program.
+    #[cfg(feature = "slab")]
+    slab::validate()?;
+
+    #[cfg(feature = "tls")]
+    report.add(get_allocator!(|alloc| alloc.repair())?);
+
+    report.add(GLOBAL_ALLOCATOR.lock().get().repair()?);
+
+    if report.total() > 0 {
+        log!(WARNING, "Repaired the pools: {:?}.", report);
+    }
+
+    Ok(report)
+}
+
+/// Call `f` on every free block of the calling thread's local allocator and the global allocator.
+///
+/// `f` gets the start and size of the blocks. The number of free bytes claimed by the pools (see
+/// `Bookkeeper::total_bytes`) is returned. The pools are locked meanwhile, so `f` must not
+/// allocate.
+#[cfg(feature = "debugger")]
+pub fn for_each_free<F: FnMut(*mut u8, usize)>(mut f: F) -> usize {
+    let mut total = 0;
+
+    #[cfg(feature = "tls")]
+    {
+        total += get_allocator!(|alloc| {
+            for block in alloc.iter() {
+                f(**block.start(), block.size());
+            }
+
+            alloc.total_bytes()
+        });
+    }
+
+    // The deferred frees are free too.
+    #[cfg(feature = "bounded_free")]
+    {
+        total += THREAD_ALLOCATOR.with(|thread_alloc| {
+            if let Some(mut thread_alloc_original) = thread_alloc.replace(None) {
+                let res = {
+                    let pending = thread_alloc_original.get().pending().lock();
+                    for &(ptr, size) in &pending.blocks[..pending.len] {
+                        f(ptr, size);
+                    }
+
+                    pending.bytes
+                };
+
+                // Put back the original allocator.
+                thread_alloc.replace(Some(thread_alloc_original));
+
+                res
+            } else {
+                0
+            }
+        });
+    }
+
+    let mut global_alloc = GLOBAL_ALLOCATOR.lock();
+    let global_alloc = global_alloc.get();
+    for block in global_alloc.iter() {
+        f(**block.start(), block.size());
+    }
+
+    total + global_alloc.total_bytes()
+}
+
+/// Move the free memory of the calling thread's local allocator to the global allocator.
+///
+/// With the `arenas` feature, the blocks go back to the arenas owning them instead (see
+/// `arena::free`). The number of bytes moved is returned.
+#[cfg(feature = "tls")]
+fn flush_local() -> usize {
+    check_reentrancy();
+
+    THREAD_ALLOCATOR.with(|thread_alloc| {
+        if let Some(mut thread_alloc_original) = thread_alloc.replace(None) {
+            let res = {
+                let local = thread_alloc_original.get();
+                // The deferred frees are flushed along with the pool.
+                #[cfg(feature = "bounded_free")]
+                local.merge_pending(!0);
+
+                let mut moved = 0;
+
+                #[cfg(feature = "arenas")]
+                {
+                    while let Some(block) = local.pop() {
+                        moved += block.size();
+                        arena::free(block);
+                    }
+                }
+
+                #[cfg(not(feature = "arenas"))]
+                {
+                    let mut global_alloc = GLOBAL_ALLOCATOR.lock();
+                    let global_alloc = global_alloc.get();
+
+                    while let Some(block) = local.pop() {
+                        moved += block.size();
+                        global_alloc.free(block);
+                    }
+                }
+
+                moved
+            };
+
+            // Put back the original allocator.
+            thread_alloc.replace(Some(thread_alloc_original));
+
+            res
+        } else {
+            0
+        }
+    })
+}
+
+/// Give up to `max` tails kept after shrunk buffers back to the pool.
+///
+/// The tails go to the pool of the calling thread. The number of bytes given back is returned.
+#[cfg(feature = "realloc_slack")]
+fn release_slack(max: usize) -> usize {
+    let mut released = 0;
+    for _ in 0..max {
+        match slack::pop() {
+            Some(block) => {
+                released += block.size();
+                get_allocator!(|alloc| alloc.free_used(block));
+            },
+            None => break,
+        }
+    }
+
+    released
+}
+
+/// Give every tail kept after shrunk buffers to a pool under memory pressure.
+///
+/// The number of bytes given back is returned.
+#[cfg(feature = "realloc_slack")]
+#[cold]
+fn release_slack_to<A: Allocator>(alloc: &mut A) -> usize {
+    let mut released = 0;
+    while let Some(block) = slack::pop() {
+        released += block.size();
+        alloc.free_used(block);
+    }
+
+    if released != 0 {
+        // Logging.
+        log!(NOTE, "Released {} bytes of realloc slack under memory pressure.", released);
+    }
+
+    released
+}
+
+/// Give the tails kept after a batch of buffers back to the pool.
+///
+/// A tail directly follows the block of its buffer, so the two merge, once the buffer is freed.
+#[cfg(feature = "realloc_slack")]
+fn release_tails<I: Iterator<Item = *mut u8>>(ptrs: I) {
+    if !slack::held() {
+        return;
+    }
+
+    for ptr in ptrs {
+        if let Some(tail) = slack::detach(ptr) {
+            pool_free(tail);
+        }
+    }
+}
+
+/// The number of frees counted towards the automatic trimming.
+static FREES: sync::CachePadded<atomic::AtomicUsize> =
+    sync::CachePadded::new(atomic::AtomicUsize::new(0));
+/// The address, from which the next automatic trim continues advising the OS.
+static ADVISE_CURSOR: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+/// Is an automatic trim due?
+#[cfg(feature = "bounded_free")]
+static TRIM_DUE: atomic::AtomicBool = atomic::AtomicBool::new(false);
+/// The most pool operations done by a single free.
+#[cfg(feature = "bounded_free")]
+static FREE_OPS_PEAK: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+
+/// The bound on the pool operations done by a single free, with the `bounded_free` feature.
+///
+/// A free queues its block, after merging a slice of the deferred frees if the list is full.
+#[cfg(feature = "bounded_free")]
+pub const FREE_OPS_BOUND: usize = config::PENDING_FREE_SLICE + 1;
+
+/// Count a free, and trim automatically, if it is time to.
+///
+/// See `conf::set_auto_trim`. When the automatic trimming is off, nothing is counted. The
+/// watermarks crossed (see `watermark`) are reported afterwards.
+///
+/// With the `bounded_free` feature, the trim is only marked due, and done by `maintenance`.
+#[inline]
+fn heartbeat() {
+    let interval = conf::auto_trim();
+
+    if interval != 0 && FREES.fetch_add(1, atomic::Ordering::Relaxed) % interval == interval - 1 {
+        #[cfg(feature = "bounded_free")]
+        TRIM_DUE.store(true, atomic::Ordering::Relaxed);
+        #[cfg(not(feature = "bounded_free"))]
+        auto_trim();
+    }
+
+    watermark::flush();
+}
+
+/// Trim incrementally, if enough memory is free.
+///
+/// The free memory of the calling thread is moved to the global allocator first. At most one block
+/// is released by moving the program break, and at most `config::AUTO_TRIM_ADVISE_MAX` blocks are
+/// advised, continuing where the previous trim left off.
+#[cold]
+fn auto_trim() {
+    // Nothing can be given back in bare-metal mode.
+    if bare_metal() { return; }
+
+    #[cfg(feature = "tls")]
+    flush_local();
+
+    let mut global_alloc = GLOBAL_ALLOCATOR.lock();
+    let global_alloc = global_alloc.get();
+
+    let free = global_alloc.total_bytes();
+    #[cfg(feature = "stats")]
+    let live = stats::live_bytes();
+    #[cfg(not(feature = "stats"))]
+    let live = 0;
+
+    // Only trim when a lot of memory is free, both absolutely and relatively.
+    if free < config::AUTO_TRIM_MIN_FREE
+       || free < free.saturating_add(live) / config::AUTO_TRIM_FRACTION {
+        return;
+    }
+
+    log!(NOTE, "Trimming automatically with {} bytes free.", free);
+
+    let trimmed = global_alloc.trim_bounded(1);
+    let (advised, cursor) = global_alloc.advise_from(ADVISE_CURSOR.load(atomic::Ordering::Relaxed),
+                                                     config::AUTO_TRIM_ADVISE_MAX);
+    ADVISE_CURSOR.store(cursor, atomic::Ordering::Relaxed);
+
+    log!(NOTE, "Trimmed {} bytes and advised {} bytes automatically.", trimmed, advised);
+}
+
+/// Defer the free of a block to the pool.
+///
+/// Without a local allocator, the block is freed to the global allocator right away.
+#[cfg(feature = "bounded_free")]
+#[inline]
+fn defer_free(block: Block) {
+    check_reentrancy();
+    init_global();
+
+    let ops = THREAD_ALLOCATOR.with(|thread_alloc| {
+        if let Some(mut thread_alloc_original) = thread_alloc.replace(None) {
+            let res = thread_alloc_original.get().defer_free(block);
+
+            // Put back the original allocator.
+            thread_alloc.replace(Some(thread_alloc_original));
+
+            res
+        } else {
+            GLOBAL_ALLOCATOR.lock().get().free_used(block);
+
+            1
+        }
+    });
+
+    // Keep track of the peak.
+    let mut peak = FREE_OPS_PEAK.load(atomic::Ordering::Relaxed);
+    while ops > peak {
+        let old = FREE_OPS_PEAK.compare_and_swap(peak, ops, atomic::Ordering::Relaxed);
+        if old == peak { break; }
+        peak = old;
+    }
+}
+
+/// Merge up to `max` deferred frees of the calling thread into the pool.
+///
+/// The number of frees still deferred is returned.
+#[cfg(feature = "bounded_free")]
+fn merge_pending(max: usize) -> usize {
+    check_reentrancy();
+
+    THREAD_ALLOCATOR.with(|thread_alloc| {
+        if let Some(mut thread_alloc_original) = thread_alloc.replace(None) {
+            let res = {
+                let local = thread_alloc_original.get();
+                local.merge_pending(max);
+
+                let left = local.pending().lock().len;
+                left
+            };
+
+            // Put back the original allocator.
+            thread_alloc.replace(Some(thread_alloc_original));
+
+            res
+        } else {
+            0
+        }
+    })
+}
+
+/// Do up to `budget_ops` operations of deferred work.
+///
+/// With the `bounded_free` feature, frees are only queued on a per-thread list, and merged into the
+/// pool later. This merges up to `budget_ops` deferred frees of the calling thread. Once they are
+/// all merged, a due automatic trim (see `set_auto_trim`) is done too.
+///
+/// The number of frees still deferred is returned, so calling this until it returns zero drains
+/// the list.
+///
+/// With the `realloc_slack` feature, up to `budget_ops` tails kept after shrunk buffers are given
+/// back to the pool as well, once the deferred frees are merged.
+#[cfg(feature = "bounded_free")]
+pub fn maintenance(budget_ops: usize) -> usize {
+    log!(CALL, "Doing up to {} operations of maintenance.", budget_ops);
+
+    let left = merge_pending(budget_ops);
+
+    #[cfg(feature = "realloc_slack")]
+    {
+        if left == 0 {
+            release_slack(budget_ops);
+        }
+    }
+
+    if left == 0 && TRIM_DUE.swap(false, atomic::Ordering::Relaxed) {
+        auto_trim();
+    }
+    watermark::flush();
+
+    left
+}
+
+/// Get the most pool operations done by a single free so far.
+///
+/// This never exceeds `FREE_OPS_BOUND`.
+#[cfg(feature = "bounded_free")]
+pub fn free_ops_peak() -> usize {
+    FREE_OPS_PEAK.load(atomic::Ordering::Relaxed)
+}
+
+/// Allocate a block of memory.
+///
+/// # Errors
+///
+/// The OOM handler handles out-of-memory conditions. If `align` is zero or not a power of two, or
+/// `size` exceeds the maximal allocation size, a null pointer is returned.
+#[inline]
+pub fn alloc(size: usize, align: usize) -> *mut u8 {
+    #[cfg(feature = "shadow_accounting")]
+    let call = shadow::enter();
+
+    // Small requests with the minimal alignment need no padding and no checks but the maximal
+    // allocation size (which can be below `FAST_PATH_MAX`), so they go straight to the pool.
+    let ptr = if FAST_PATH && size <= config::FAST_PATH_MAX && align <= MIN_ALIGN
+                 && align.is_power_of_two() && size <= conf::max_allocation() {
+        // The size is rounded like on free, which gives back `pool_size(size)` bytes.
+        let ptr = *Pointer::from(pool_alloc(pool_size(size), Align::BUFFER));
+        watermark::flush();
+
+        ptr
+    } else {
+        alloc_slow(size, align)
+    };
+
+    #[cfg(feature = "shadow_accounting")]
+    account(&call, shadow::Op::Alloc, accounted(ptr), size);
+
+    ptr
+}
+
+/// The slow path of `alloc`.
+///
+/// This handles every request without the fast path, and every request not eligible for it.
+#[cold]
+#[inline(never)]
+fn alloc_slow(size: usize, align: usize) -> *mut u8 {
+    log!(CALL, "Allocating buffer of size {} (align {}).", size, align);
+
+    if check_size(size).is_err() {
+        return ptr::null_mut();
+    }
+
+    match check_align(align) {
+        Some(buffer_align) => {
+            #[cfg(feature = "stats")]
+            stats::record_align(align);
+
+            alloc_buffer(size, buffer_align, 0)
+        },
+        None => ptr::null_mut(),
+    }
+}
+
+/// Allocate a block of memory, failing on sizes above the limit.
+///
+/// This is like `alloc`, but tells why the allocation was rejected, rather than returning a null
+/// pointer.
+///
+/// # Errors
+///
+/// If `size` exceeds the maximal allocation size (see `set_max_allocation`),
+/// `AllocErr::TooLarge` is returned. If no backend can provide the alignment (see `max_align`),
+/// `AllocErr::UnsupportedAlignment` is. The OOM handler handles out-of-memory conditions.
+///
+/// # Panics
+///
+/// This panics if `align` is zero or not a power of two.
+pub fn try_alloc(size: usize, align: usize) -> Result<*mut u8, AllocErr> {
+    log!(CALL, "Trying to allocate buffer of size {} (align {}).", size, align);
+
+    #[cfg(feature = "shadow_accounting")]
+    let call = shadow::enter();
+
+    let buffer_align = check_align(align).expect("Invalid alignment.");
+    check_size(size)?;
+
+    #[cfg(feature = "stats")]
+    stats::record_align(align);
+
+    if mapped::maps(size, buffer_align) {
+        return alloc_mapped(size, buffer_align);
+    }
+
+    let ptr = alloc_buffer(size, buffer_align, 0);
+    #[cfg(feature = "shadow_accounting")]
+    account(&call, shadow::Op::Alloc, accounted(ptr), size);
+
+    Ok(ptr)
+}
+
+/// Allocate a buffer attributed to some tag.
+///
+/// This is like `alloc`, but the memory is accounted under `tag` in the per-tag statistics (see
+/// `stats::by_tag`). Untagged allocations are accounted under tag 0.
+///
+/// The tag follows the buffer through reallocations, and is dropped when it is freed.
+///
+/// # Errors
+///
+/// The OOM handler handles out-of-memory conditions. If `align` is zero or not a power of two, or
+/// `size` exceeds the maximal allocation size, a null pointer is returned.
+#[cfg(feature = "tagging")]
+#[inline]
+pub fn alloc_tagged(size: usize, align: usize, tag: u8) -> *mut u8 {
+    log!(CALL, "Allocating buffer of size {} (align {}) with tag {}.", size, align, tag);
+
+    #[cfg(feature = "shadow_accounting")]
+    let call = shadow::enter();
+
+    if check_size(size).is_err() {
+        return ptr::null_mut();
+    }
+
+    match check_align(align) {
+        Some(buffer_align) => {
+            #[cfg(feature = "stats")]
+            stats::record_align(align);
+
+            let ptr = alloc_buffer(size, buffer_align, tag);
+            #[cfg(feature = "shadow_accounting")]
+            account(&call, shadow::Op::Alloc, accounted(ptr), size);
+
+            ptr
+        },
+        None => ptr::null_mut(),
+    }
+}
+
+/// Allocate a buffer attributed to an allocation site.
+///
+/// This is like `alloc`, but the buffer is accounted to `site` in the per-site statistics (see
+/// `stats::top_sites`). The site follows the buffer through reallocations. Usually, this is
+/// called through the `alloc_here!` macro, which makes a site of the source location.
+///
+/// # Errors
+///
+/// The OOM handler handles out-of-memory conditions. If `align` is zero or not a power of two, or
+/// `size` exceeds the maximal allocation size, a null pointer is returned.
+#[cfg(feature = "sites")]
+pub fn alloc_at_site(size: usize, align: usize, site: &'static Site) -> *mut u8 {
+    log!(CALL, "Allocating buffer of size {} (align {}) at {}.", size, align, site);
+
+    let ptr = alloc(size, align);
+    if !ptr.is_null() {
+        site::insert(ptr, size, site);
+    }
+
+    ptr
+}
+
+/// Allocate a zeroed array of `n` elements of size `size`.
+///
+/// The array is laid out as given by `layout::checked_array_layout(size, n, align)`, and its size
+/// (which is `n * size`, when `size` is divisible by `align`) must be passed when freeing it.
+///
+/// If the size of the array overflows or exceeds the maximal allocation size, a null pointer is
+/// returned, like C's `calloc`.
+///
+/// # Errors
+///
+/// The OOM handler handles out-of-memory conditions. Invalid alignments give a null pointer.
+pub fn calloc(n: usize, size: usize, align: usize) -> *mut u8 {
+    log!(CALL, "Allocating {} zeroed elements of size {} (align {}).", n, size, align);
+
+    #[cfg(feature = "shadow_accounting")]
+    let call = shadow::enter();
+
+    // The array is laid out with the requested alignment, while the buffer gets the raised one.
+    let buffer_align = match check_align(align) {
+        Some(align) => align,
+        None => return ptr::null_mut(),
+    };
+    let layout = match layout::checked_array_layout(size, n, align) {
+        Ok(layout) => layout,
+        Err(_) => {
+            log!(WARNING, "An array of {} elements of size {} (align {}) overflows.", n, size,
+                 align);
+
+            return ptr::null_mut();
+        },
+    };
+    if check_size(layout.size()).is_err() {
+        return ptr::null_mut();
+    }
+
+    #[cfg(feature = "stats")]
+    stats::record_align(align);
+    let ptr = alloc_buffer(layout.size(), buffer_align, 0);
+    unsafe {
+        // The buffer was just allocated with this size.
+        ptr::write_bytes(ptr, 0, layout.size());
+    }
+    #[cfg(feature = "shadow_accounting")]
+    account(&call, shadow::Op::Alloc, accounted(ptr), layout.size());
+
+    ptr
+}
+
+/// Check a size passed to the API against the maximal allocation size.
+///
+/// Sizes beyond the largest block (`ptr::MAX_BLOCK`) are rejected as well, whatever the limit.
+/// Rejections are logged and counted in the statistics. See `conf::set_max_allocation`.
+#[inline]
+pub fn check_size(size: usize) -> Result<(), AllocErr> {
+    let limit = cmp::min(conf::max_allocation(), MAX_BLOCK);
+    if size <= limit {
+        return Ok(());
+    }
+
+    log!(WARNING, "Rejecting an allocation of {} bytes (the limit is {}).", size, limit);
+    #[cfg(feature = "stats")]
+    stats::record_too_large();
+
+    Err(AllocErr::TooLarge {
+        requested: size,
+        limit: limit,
+    })
+}
+
+/// Validate an alignment passed to the API.
+///
+/// Valid alignments are raised to `MIN_ALIGN`, such that only larger alignments take the explicit
+/// alignment paths (e.g. bypassing the slabs). If `align` is zero or not a power of two, a warning
+/// is logged and `None` is returned, in which case the call fails.
+#[inline]
+fn check_align(align: usize) -> Option<Align> {
+    match Align::new(align) {
+        Some(align) => Some(cmp::max(align, Align::BUFFER)),
+        None => {
+            log!(WARNING, "Invalid alignment {} (not a power of two).", align);
+
+            None
+        },
+    }
+}
+
+/// Allocate a buffer with some tag.
+///
+/// The block is laid out as the padding (holding the header), the buffer, and the redzone.
+///
+/// Sizes overflowing with the padding and the redzone cannot be satisfied, so they are handled
+/// as out-of-memory conditions.
+///
+/// Alignments beyond the pool's limit are mapped directly (untagged). If no backend can provide
+/// the alignment, a null pointer is returned.
+#[inline]
+fn alloc_buffer(size: usize, align: Align, tag: u8) -> *mut u8 {
+    if mapped::maps(size, align) {
+        return alloc_mapped(size, align).unwrap_or_else(|err| {
+            log!(WARNING, "Unable to allocate buffer of size {}: {}.", size, err);
+
+            ptr::null_mut()
+        });
+    }
+
+    // Buffers cover whole tag granules.
+    #[cfg(feature = "mte")]
+    let size = mte::round(size);
+
+    let padding = padding(align);
+    let total = size.checked_add(padding + REDZONE).unwrap_or_else(|| {
+        fail::oom(AllocErr::TooLarge {
+            requested: size,
+            limit: !0 - padding - REDZONE,
+        })
+    });
+    let block = alloc_block(total, align);
+    let total = block.size();
+    // Check the buffer after the header against the alignment too, not only the block. The
+    // buffer is freed through the padding, so there must be no aligner before it.
+    debug_assert_eq!(block.fits_layout(size + REDZONE, align, padding), FitResult::Fits(padding),
+                     "The block {:?} cannot hold the buffer.", block);
+    let ptr = map_addr(*Pointer::from(block), |x| x + padding);
+
+    stamp(ptr, size, padding, tag, meta::now());
+    guard(ptr, size, total - padding);
+    record_alloc(ptr, size, tag);
+    // The padding and the redzone are not part of the granted size.
+    #[cfg(feature = "stats")]
+    stats::record_grant(size, total - padding - REDZONE);
+    #[cfg(feature = "trace")]
+    trace::record_alloc(ptr, size, align.get());
+    watermark::flush();
+
+    // The bookkeeping is done on the untagged pointer, which is then tagged.
+    #[cfg(feature = "mte")]
+    let ptr = unsafe {
+        // The buffer is fresh, granule aligned, and its size is rounded.
+        mte::tag(ptr, size)
+    };
+
+    ptr
+}
+
+/// Is a buffer accounted in the statistics?
+///
+/// Null pointers, mappings, and foreign buffers are not. Freed buffers must be told apart before
+/// they are gone.
+#[cfg(feature = "shadow_accounting")]
+fn accounted(ptr: *mut u8) -> bool {
+    #[cfg(feature = "interpose")]
+    {
+        if !ptr.is_null() && is_foreign(ptr) {
+            return false;
+        }
+    }
+
+    !ptr.is_null() && region::mapping(ptr).is_none()
+}
+
+/// Account a buffer allocated or freed by a public call in the shadow (see `shadow`).
+///
+/// Nothing is accounted for buffers, which the statistics don't account (see `accounted`).
+#[cfg(feature = "shadow_accounting")]
+fn account(call: &shadow::Call, op: fn(usize) -> shadow::Op, accounted: bool, size: usize) {
+    // The statistics count whole granules.
+    #[cfg(feature = "mte")]
+    let size = mte::round(size);
+
+    if accounted {
+        call.op(op(size));
+    }
+}
+
+/// Record an allocation in the statistics, the tags, and the table of live allocations.
+///
+/// The buffer must be followed by a redzone of `REDZONE` bytes.
+#[inline]
+#[allow(unused_variables)]
+fn record_alloc(ptr: *mut u8, size: usize, tag: u8) {
+    #[cfg(feature = "stats")]
+    stats::record_alloc(size);
+    // The double count, which the shadow must catch.
+    #[cfg(all(test, feature = "shadow_accounting"))]
+    {
+        if shadow::DOUBLE_COUNT.compare_and_swap(size, 0, atomic::Ordering::Relaxed) == size {
+            stats::record_alloc(size);
+        }
+    }
+    #[cfg(feature = "tagging")]
+    tag::insert(ptr, size, tag);
+    #[cfg(feature = "debugger")]
+    live::insert(ptr, size, REDZONE);
+}
+
+/// Record a (possibly partial) free in the statistics, the tags, and the table of live
+/// allocations.
+///
+/// The range of memory to release (which differs from the freed range, when redzones are
+/// involved) and the tag of the freed buffer are returned.
+#[inline]
+#[allow(unused_variables)]
+fn record_free(ptr: *mut u8, size: usize) -> (*mut u8, usize, u8) {
+    #[cfg(feature = "stats")]
+    stats::record_free(size);
+    #[cfg(feature = "sites")]
+    site::remove(ptr, size);
+    #[cfg(feature = "tagging")]
+    let tag = tag::remove(ptr, size);
+    #[cfg(not(feature = "tagging"))]
+    let tag = 0;
+
+    #[cfg(feature = "debugger")]
+    let (ptr, size) = live::remove(ptr, size);
+
+    (ptr, size, tag)
+}
+
+/// Run a closure without reporting errors to the debugger.
+///
+/// Reallocation copies the buffer after it has been declared freed, which the debugger would
+/// otherwise report as invalid reads.
+#[inline]
+fn quiet<T, F: FnOnce() -> T>(f: F) -> T {
+    #[cfg(feature = "debugger")]
+    valgrind::disable_error_reporting();
+    let res = f();
+    #[cfg(feature = "debugger")]
+    valgrind::enable_error_reporting();
+
+    res
+}
+
+/// Declare the kept part of a reallocated buffer defined to the debugger.
+///
+/// Declaring the new buffer makes it undefined, so the definedness of the kept bytes is lost. We
+/// approximate it by declaring them defined.
+#[inline]
+#[allow(unused_variables)]
+fn keep_defined(ptr: *mut u8, size: usize) {
+    #[cfg(feature = "debugger")]
+    valgrind::make_mem_defined(ptr, size);
+}
+
+/// Get the padding before buffers aligned to `align`.
+#[inline]
+fn padding(align: Align) -> usize {
+    meta::Active::padding(align)
+}
+
+/// Get the size of the pool block holding `size` bytes.
+///
+/// This is the size given by the metadata, rounded up to `MIN_ALIGN`, such that the aligners and
+/// the rests cut from pool blocks are never too small to be kept in the pool (see
+/// `config::MIN_SPLIT_REMAINDER`). It is applied both when allocating and when freeing.
+#[inline]
+fn pool_size(size: usize) -> usize {
+    let size = meta::Active::pool_size(size);
+
+    // Sizes this large cannot be allocated anyway, so they are passed on as they are.
+    match size.checked_add(MIN_ALIGN - 1) {
+        Some(x) => x & !(MIN_ALIGN - 1),
+        None => size,
+    }
+}
+
+/// Record the metadata of a buffer, born in the generation `birth`.
+#[inline]
+fn stamp(ptr: *mut u8, size: usize, padding: usize, tag: u8, birth: u16) {
+    unsafe {
+        // The buffer was just allocated with this padding.
+        meta::Active::stamp(ptr, size, padding, tag, birth);
+    }
+}
+
+/// Get the generation the buffer at `ptr` was born in.
+///
+/// Reallocated buffers keep their birth. If it isn't recorded, the buffer is taken to be born
+/// now.
+#[inline]
+unsafe fn birth(ptr: *mut u8) -> u16 {
+    meta::Active::birth(ptr).unwrap_or_else(meta::now)
+}
+
+/// Check and forget the metadata of a buffer, and get the padding before it.
+///
+/// # Failure
+///
+/// If the metadata is corrupted, the heap corruption handler is called.
+#[inline]
+unsafe fn unstamp(ptr: *mut u8, size: usize) -> usize {
+    meta::Active::unstamp(ptr, size)
+}
+
+/// Poison the memory following the user-visible part of a buffer to the sanitizer.
+///
+/// `total` is the size of the underlying block, such that the redzone and the slack of slab cells
+/// stay poisoned.
+#[inline]
+#[allow(unused_variables)]
+fn guard(ptr: *mut u8, size: usize, total: usize) {
+    #[cfg(feature = "sanitize")]
+    ::shim::asan::poison(map_addr(ptr, |x| x + size), total - size);
+}
+
+/// Unpoison a buffer to the sanitizer.
+///
+/// This is done before the allocator touches the buffer (e.g. zeroing it on free).
+#[inline]
+#[allow(unused_variables)]
+fn unguard(ptr: *mut u8, size: usize) {
+    #[cfg(feature = "sanitize")]
+    ::shim::asan::unpoison(ptr, size);
+}
+
+/// Get the size of the redzone after the buffer at `ptr`.
+#[inline]
+#[allow(unused_variables)]
+fn redzone(ptr: *mut u8) -> usize {
+    #[cfg(feature = "debugger")]
+    let redzone = live::redzone(ptr);
+    #[cfg(not(feature = "debugger"))]
+    let redzone = 0;
+
+    redzone
+}
+
+/// Allocate a block from the slabs or the pool.
+#[inline]
+fn alloc_block(size: usize, align: Align) -> Block {
+    // Small buffers are served by the slabs.
+    #[cfg(feature = "slab")]
+    {
+        if let Some(block) = slab::alloc(size, align) {
+            return block;
+        }
+    }
+
+    pool_alloc(pool_size(size), align)
+}
+
+/// Allocate a block directly from the global allocator, bypassing the local allocator.
+///
+/// If no block of the pool fits, the heap is grown with the pool unlocked, such that the other
+/// threads only wait for the system call, if they have to grow the heap too. The pool is checked
+/// anew after locking it again, as other threads may have freed a fitting block meanwhile. That
+/// block is then taken (keeping the heap compact), and the fresh segment is freed into the pool
+/// as a whole, where it is trimmed like any other free memory at the top of the heap.
+///
+/// The fresh segment is only known to the thread, which grew the heap, until that thread inserts
+/// it (both ways), so no memory is lost in between. Other threads can insert their own segments
+/// first, so it is inserted in address order rather than pushed.
+///
+/// With `conf::set_staged_growth(false)`, the heap is grown with the pool locked instead.
+pub fn global_alloc(size: usize, align: Align) -> Block {
+    if !conf::staged_growth() {
+        return GLOBAL_ALLOCATOR.lock().get().alloc(size, align);
+    }
+
+    if let Some(res) = GLOBAL_ALLOCATOR.lock().get().alloc_pooled(size, align) {
+        return res;
+    }
+
+    // Logging.
+    log!(DEBUG, "Growing the heap for {} bytes outside the lock of the global allocator.", size);
+
+    let (aligner, fresh, excessive) = match try_grow_global(size, align) {
+        Ok(res) => res,
+        Err(err) => return relieve(size, align, err),
+    };
+
+    let mut global_alloc = GLOBAL_ALLOCATOR.lock();
+    let global_alloc = global_alloc.get();
+    let res = match global_alloc.alloc_pooled(size, align) {
+        Some(res) => {
+            // Logging.
+            log!(DEBUG, "A fitting block was freed while growing the heap.");
+            #[cfg(feature = "stats")]
+            stats::record_raced_growth();
+
+            global_alloc.free(fresh);
+            res
+        },
+        None => fresh,
+    };
+
+    global_alloc.free(aligner);
+    global_alloc.free(excessive);
+
+    res
+}
+
+/// Reallocate a block directly in the global allocator, bypassing the local allocator.
+///
+/// If the block can neither be resized nor moved within the pool, it is moved to a fresh block,
+/// for which the heap is grown outside the lock (see `global_alloc`).
+pub fn global_realloc(block: Block, size: usize, align: Align) -> Block {
+    if !conf::staged_growth() {
+        return GLOBAL_ALLOCATOR.lock().get().realloc(block, size, align);
+    }
+
+    // The lock must be released before growing.
+    let res = GLOBAL_ALLOCATOR.lock().get().realloc_pooled(block, size, align);
+    res.unwrap_or_else(|block| {
+        let fresh = global_alloc(size, align);
+        GLOBAL_ALLOCATOR.lock().get().move_to(block, fresh)
+    })
+}
+
+/// Reallocate a block to a size between `needed` and `preferred` directly in the global
+/// allocator, bypassing the local allocator.
+///
+/// See `global_realloc`.
+pub fn global_realloc_with_hint(block: Block, needed: usize, preferred: usize, align: Align)
+                                -> Block {
+    if !conf::staged_growth() {
+        return GLOBAL_ALLOCATOR.lock().get().realloc_with_hint(block, needed, preferred, align);
+    }
+
+    // The lock must be released before growing.
+    let res = GLOBAL_ALLOCATOR.lock().get().realloc_with_hint_pooled(block, needed, preferred,
+                                                                      align);
+    res.unwrap_or_else(|block| {
+        let fresh = global_alloc(preferred, align);
+        GLOBAL_ALLOCATOR.lock().get().move_to(block, fresh)
+    })
+}
+
+/// Serve an allocation, which the heap failed to grow for.
+///
+/// With the `bounded_free` feature, the deferred frees of every thread are handed to the global
+/// allocator, and the allocation is retried. With the `realloc_slack` feature, the slack kept
+/// after shrunk buffers is given back too. If that fails too (or nothing was given back), the OOM
+/// handler is called with the error.
+#[cold]
+#[allow(unused_variables)]
+fn relieve(size: usize, align: Align, err: AllocErr) -> Block {
+    #[cfg(feature = "bounded_free")]
+    {
+        let flushed = flush_pending();
+        if flushed != 0 {
+            // Logging.
+            log!(NOTE, "Flushed {} bytes of deferred frees under memory pressure.", flushed);
+
+            if let Some(res) = GLOBAL_ALLOCATOR.lock().get().alloc_pooled(size, align) {
+                return res;
+            }
+        }
+    }
+
+    #[cfg(feature = "realloc_slack")]
+    {
+        // The local allocator (if any) is in the middle of an operation, so the slack goes to the
+        // global allocator.
+        let mut global_alloc = GLOBAL_ALLOCATOR.lock();
+        let global_alloc = global_alloc.get();
+        if release_slack_to(global_alloc) != 0 {
+            if let Some(res) = global_alloc.alloc_pooled(size, align) {
+                return res;
+            }
+        }
+    }
+
+    fail::oom(err)
+}
+
+/// Free a block directly to the global allocator, bypassing the local allocator.
+#[cfg(feature = "arenas")]
+pub fn global_free(block: Block) {
+    GLOBAL_ALLOCATOR.lock().get().free(block)
+}
+
+/// Allocate a block directly from the pool, bypassing the slabs.
+///
+/// If the pool is the one of the global allocator (without the `tls` feature, or after the local
+/// allocator was deinitialized), the heap is grown outside its lock (see `global_alloc`).
+pub fn pool_alloc(size: usize, align: Align) -> Block {
+    get_allocator!(|alloc| alloc.alloc(size, align), else global_alloc(size, align))
+}
+
+/// Free a block directly to the pool, bypassing the slabs.
+pub fn pool_free(block: Block) {
+    get_allocator!(|alloc| alloc.free(block))
+}
+
+/// Allocate a batch of equally sized buffers.
+///
+/// This allocates up to `out.len()` buffers of size `size` aligned to `align`, writing their
+/// pointers to `out`. The allocator is only locked once, and the buffers are carved as runs out of
+/// one or a few free blocks, making this much faster than allocating them one by one.
+///
+/// The number of buffers allocated is returned. This can be less than `out.len()`, in which case
+/// the caller should fall back to `alloc` for the remainder. If the size of the batch overflows,
+/// `size` exceeds the maximal allocation size, or the alignment is invalid, nothing is allocated.
+///
+/// Every buffer can be freed individually with `free` or together with `dealloc_many`.
+///
+/// # Errors
+///
+/// The OOM handler handles out-of-memory conditions.
+pub fn alloc_many(size: usize, align: usize, out: &mut [*mut u8]) -> usize {
+    log!(CALL, "Allocating {} buffers of size {} (align {}).", out.len(), size, align);
+
+    #[cfg(feature = "shadow_accounting")]
+    let call = shadow::enter();
+
+    // Zero-sized batches are left to the single allocation path, and so are buffers with
+    // metadata or tags.
+    if size == 0 || out.is_empty()
+        || cfg!(any(feature = "header", feature = "sidetable", feature = "mte")) {
+        return 0;
+    }
+    if check_size(size).is_err() {
+        return 0;
+    }
+    #[cfg(feature = "stats")]
+    let requested = align;
+    let align = match check_align(align) {
+        Some(align) => align,
+        None => return 0,
+    };
+
+    // Every buffer is followed by its redzone, and takes a pool block, as if it was allocated
+    // alone, such that it can be freed alone.
+    let padded = match size.checked_add(REDZONE) {
+        Some(x) => pool_size(x),
+        None => return 0,
+    };
+    // The objects of a run are laid out as an array, such that every object is aligned.
+    let batch = match layout::checked_array_layout(padded, out.len(), align.get()) {
+        Ok(batch) => batch,
+        Err(_) => {
+            log!(WARNING, "A batch of {} buffers of size {} overflows.", out.len(), size);
+
+            return 0;
+        },
+    };
+    let stride = batch.size() / out.len();
+
+    let produced = get_allocator!(|alloc| {
+        let mut produced = 0;
+
+        // Carve runs from the pool until the batch is satisfied or no block fits anymore.
+        while produced < out.len() {
+            if let Some(run) = alloc.alloc_run(stride, out.len() - produced, align) {
+                produced += split_run(alloc, run, padded, stride, &mut out[produced..]);
+            } else {
+                break;
+            }
+        }
+
+        // If nothing could be carved from the pool, we allocate a single fresh run for the whole
+        // batch.
+        if produced == 0 {
+            let run = alloc.alloc(batch.size(), align);
+            produced = split_run(alloc, run, padded, stride, out);
+        }
+
+        produced
+    });
+
+    for &ptr in &out[..produced] {
+        guard(ptr, size, padded);
+        record_alloc(ptr, size, 0);
+        #[cfg(feature = "stats")]
+        {
+            stats::record_grant(size, size);
+            stats::record_align(requested);
+        }
+        #[cfg(feature = "trace")]
+        trace::record_alloc(ptr, size, align.get());
+        #[cfg(feature = "shadow_accounting")]
+        account(&call, shadow::Op::Alloc, accounted(ptr), size);
+    }
+
+    produced
+}
+
+/// Split a run of objects into buffers of size `size`, writing the pointers to `out`.
+///
+/// The padding between the objects (when `stride` is larger than `size`) is freed. The number of
+/// objects is returned.
+fn split_run<A: Allocator>(alloc: &mut A, mut run: Block, size: usize, stride: usize,
+                           out: &mut [*mut u8]) -> usize {
+    let mut n = 0;
+
+    while !run.is_empty() {
+        // Make some assertions.
+        debug_assert!(n < out.len(), "The run is larger than the batch.");
+
+        let (obj, rest) = run.split(stride);
+        let (obj, padding) = obj.split(size);
+
+        // Free the padding.
+        alloc.free(padding);
+
+        out[n] = *Pointer::from(obj);
+        run = rest;
+        n += 1;
+    }
+
+    n
+}
+
+/// Free a batch of equally sized buffers.
+///
+/// The pointers are sorted (`ptrs` is reordered in place), and adjacent buffers are merged into
+/// runs before being freed, making this faster than freeing them one by one.
+///
+/// # Safety
+///
+/// The same rules as for `free` apply to every buffer in the batch.
+pub unsafe fn dealloc_many(ptrs: &mut [*mut u8], size: usize) {
+    log!(CALL, "Freeing {} buffers of size {}.", ptrs.len(), size);
+
+    #[cfg(feature = "shadow_accounting")]
+    let call = shadow::enter();
+    #[cfg(feature = "shadow_accounting")]
+    for &ptr in ptrs.iter() {
+        account(&call, shadow::Op::Free, accounted(ptr), size);
+    }
+
+    // Buffers with metadata or tags are freed one by one.
+    if cfg!(any(feature = "header", feature = "sidetable", feature = "mte")) {
+        for &ptr in ptrs.iter() {
+            free(ptr, size);
+        }
+
+        return;
+    }
+
+    // The slack kept after the buffers is released along with them.
+    #[cfg(feature = "realloc_slack")]
+    release_tails(ptrs.iter().cloned());
+
+    for &ptr in ptrs.iter() {
+        #[cfg(feature = "trace")]
+        trace::record_free(ptr, size);
+        record_free(ptr, size);
+    }
+
+    // The buffers are released along with their redzones.
+    let size = size + REDZONE;
+    for &ptr in ptrs.iter() {
+        unguard(ptr, size);
+    }
+
+    // Slab cells cannot be merged into runs, so they are freed one by one and nulled out.
+    #[cfg(feature = "slab")]
+    {
+        for ptr in ptrs.iter_mut() {
+            if slab::free(*ptr, size).is_ok() {
+                *ptr = ptr::null_mut();
+            }
+        }
+    }
+
+    // Every buffer took a pool block of this size (see `alloc_many`).
+    let size = pool_size(size);
+
+    // Sort the pointers to find the runs.
+    sort::sort_by(ptrs, |a, b| a < b);
+
+    get_allocator!(|alloc| {
+        let mut iter = ptrs.iter().peekable();
+
+        while let Some(&start) = iter.next() {
+            // Skip the freed slab cells.
+            if start.is_null() { continue; }
+
+            // Extend the run as long as the next buffer is adjacent.
+            let mut len = size;
+            while iter.peek().map_or(false, |&&x| start as usize + len == x as usize) {
+                iter.next();
+                len += size;
+            }
+
+            alloc.free_used(Block::from_raw_parts(Pointer::new(start), len));
+        }
+    })
+}
+
+/// Allocate `total` bytes as a few discontiguous chunks.
+///
+/// When the pool is fragmented, no free block may be able to hold `total` bytes, even though
+/// enough bytes are free. This takes the largest free blocks first, writing a pointer and a length
+/// for every chunk to `out`, such that the lengths sum to `total`. Every chunk holds at least
+/// `min_chunk` bytes (except when `total` is smaller) and is aligned to `MIN_ALIGN`.
+///
+/// As few chunks as possible are used, and the last one is allocated fresh if the pool runs out.
+/// The number of chunks is returned. They can be freed individually with `free` or together with
+/// `dealloc_scatter`.
+///
+/// # Errors
+///
+/// If `total` exceeds the maximal allocation size, `AllocErr::TooLarge` is returned, and if `out`
+/// is empty (while `total` is not zero), `AllocErr::LimitReached` is. The OOM handler handles
+/// out-of-memory conditions.
+pub fn alloc_scatter(total: usize, min_chunk: usize, out: &mut [(*mut u8, usize)])
+                     -> Result<usize, AllocErr> {
+    log!(CALL, "Allocating {} bytes in chunks of at least {}.", total, min_chunk);
+
+    #[cfg(feature = "shadow_accounting")]
+    let call = shadow::enter();
+
+    check_size(total)?;
+    if total == 0 {
+        return Ok(0);
+    }
+    if out.is_empty() {
+        return Err(AllocErr::LimitReached);
+    }
+
+    // Buffers with metadata or tags are allocated as one chunk.
+    if cfg!(any(feature = "header", feature = "sidetable", feature = "mte")) {
+        out[0] = (try_alloc(total, MIN_ALIGN)?, total);
+        #[cfg(feature = "shadow_accounting")]
+        account(&call, shadow::Op::Alloc, accounted(out[0].0), total);
+
+        return Ok(1);
+    }
+
+    let min_chunk = cmp::min(cmp::max(min_chunk, 1), total);
+    // Every chunk is followed by its redzone.
+    let padded = total.checked_add(REDZONE).ok_or(AllocErr::TooLarge {
+        requested: total,
+        limit: !0 - REDZONE,
+    })?;
+
+    let count = get_allocator!(|alloc| {
+        let mut count = 0;
+
+        // The chunks are rounded to the minimal alignment, like the pool sizes they are freed
+        // with by `dealloc_scatter`.
+        let rest = alloc.alloc_scatter(total, min_chunk, REDZONE, out.len(), Align::BUFFER,
+                                       |chunk, len| {
+            out[count] = (*Pointer::from(chunk.empty_left()), len);
+            count += 1;
+        });
+
+        // The pool ran out, so the rest is allocated fresh.
+        if rest > 0 {
+            let chunk = alloc.alloc(pool_size(padded - (total - rest)), Align::BUFFER);
+            out[count] = (*Pointer::from(chunk.empty_left()), rest);
+            count += 1;
+        }
+
+        count
+    });
+
+    for &(ptr, len) in &out[..count] {
+        guard(ptr, len, len + REDZONE);
+        record_alloc(ptr, len, 0);
+        #[cfg(feature = "stats")]
+        stats::record_grant(len, len);
+        #[cfg(feature = "trace")]
+        trace::record_alloc(ptr, len, MIN_ALIGN);
+        #[cfg(feature = "shadow_accounting")]
+        account(&call, shadow::Op::Alloc, accounted(ptr), len);
+    }
+
+    Ok(count)
+}
+
+/// Free the chunks of a scattered allocation.
+///
+/// This takes the chunks written by `alloc_scatter` and frees them, locking the allocator once.
+///
+/// # Safety
+///
+/// Every chunk must be a live allocation of the given length. After the call, the chunks are
+/// invalid.
+pub unsafe fn dealloc_scatter(chunks: &[(*mut u8, usize)]) {
+    log!(CALL, "Freeing {} scattered chunks.", chunks.len());
+
+    #[cfg(feature = "shadow_accounting")]
+    let call = shadow::enter();
+    #[cfg(feature = "shadow_accounting")]
+    for &(ptr, len) in chunks {
+        account(&call, shadow::Op::Free, accounted(ptr), len);
+    }
+
+    // Buffers with metadata or tags are freed one by one.
+    if cfg!(any(feature = "header", feature = "sidetable", feature = "mte")) {
+        for &(ptr, len) in chunks {
+            free(ptr, len);
+        }
+
+        return;
+    }
+
+    // The slack kept after the chunks is released along with them.
+    #[cfg(feature = "realloc_slack")]
+    release_tails(chunks.iter().map(|&(ptr, _)| ptr));
+
+    for &(ptr, len) in chunks {
+        #[cfg(feature = "trace")]
+        trace::record_free(ptr, len);
+        record_free(ptr, len);
+        // The chunks are released along with their redzones.
+        unguard(ptr, len + REDZONE);
+    }
+
+    get_allocator!(|alloc| {
+        for &(ptr, len) in chunks {
+            alloc.free_used(Block::from_raw_parts(Pointer::new(ptr), pool_size(len + REDZONE)));
+        }
+    })
+}
+
+/// Free a buffer.
+///
+/// Note that this do not have to be a buffer allocated through ralloc. The only requirement is
+/// that it is not used after the free.
+///
+/// Buffers from `secure_alloc` are recognized by their region, and unmapped.
+///
+/// With the `interpose` feature, buffers outside every region of `ralloc` were allocated by the
+/// allocator it replaced, so they are handed back to it (see `free_foreign`).
+///
+/// With automatic trimming (see `conf::set_auto_trim`), every so often a free gives some memory
+/// back to the OS.
+///
+/// # Important!
+///
+/// You should only allocate buffers allocated through `ralloc`. Anything else is considered
+/// invalid.
+///
+/// # Errors
+///
+/// The OOM handler handles out-of-memory conditions.
+///
+/// # Safety
+///
+/// Rust assume that the allocation symbols returns correct values. For this reason, freeing
+/// invalid pointers might introduce memory unsafety.
+///
+/// Secondly, freeing an used buffer can introduce use-after-free.
+#[inline]
+pub unsafe fn free(ptr: *mut u8, size: usize) {
+    log!(CALL, "Freeing buffer of size {}.", size);
+
+    #[cfg(feature = "shadow_accounting")]
+    let call = shadow::enter();
+    #[cfg(feature = "shadow_accounting")]
+    account(&call, shadow::Op::Free, accounted(ptr), size);
+
+    free_buffer(ptr, size, None);
+}
+
+/// Free a buffer of known size and alignment.
+///
+/// `size` and `align` must be the ones the buffer was allocated (or last reallocated) with. Since
+/// the alignment gives the padding, the metadata of the buffer needn't be read at all, saving a
+/// (likely cold) memory access per free.
+///
+/// In debug builds, the metadata is checked against the size and the alignment anyway.
+///
+/// # Failure
+///
+/// If `align` is invalid, or (in debug builds) the size or the alignment doesn't match the
+/// metadata, the heap corruption handler is called.
+///
+/// # Safety
+///
+/// The same rules as for `free` apply.
+#[inline]
+pub unsafe fn dealloc_sized(ptr: *mut u8, size: usize, align: usize) {
+    log!(CALL, "Freeing buffer of size {} with alignment {}.", size, align);
+
+    #[cfg(feature = "shadow_accounting")]
+    let call = shadow::enter();
+    #[cfg(feature = "shadow_accounting")]
+    account(&call, shadow::Op::Free, accounted(ptr), size);
+
+    let align = check_align(align).unwrap_or_else(|| fail::corruption(ptr));
+    free_buffer(ptr, size, Some(align));
+}
+
+/// Map a buffer aligned beyond the pool's limit.
+///
+/// Mapped buffers carry no metadata, and are not accounted in the statistics.
+///
+/// # Errors
+///
+/// If no backend can provide the alignment, `AllocErr::UnsupportedAlignment` is returned. The
+/// OOM handler handles every other failure.
+#[cold]
+fn alloc_mapped(size: usize, align: Align) -> Result<*mut u8, AllocErr> {
+    match mapped::alloc(size, align) {
+        Ok(ptr) => {
+            #[cfg(feature = "trace")]
+            trace::record_alloc(ptr, size, align.get());
+            watermark::flush();
+
+            Ok(ptr)
+        },
+        Err(err @ AllocErr::UnsupportedAlignment(_)) => Err(err),
+        Err(err) => fail::oom(err),
+    }
+}
+
+/// Free a buffer, which might be from a mapping or (with `interpose`) a foreign allocator.
+///
+/// If the alignment is given, the metadata is forgotten rather than checked, except in debug
+/// builds.
+#[inline]
+unsafe fn free_buffer(ptr: *mut u8, size: usize, align: Option<Align>) {
+    // The memory of tagged buffers goes back to tag zero, such that stale pointers fault, and the
+    // tag is stripped before the pointer goes any further.
+    #[cfg(feature = "mte")]
+    let (ptr, size) = {
+        let size = mte::round(size);
+        if mte::tag_of(ptr) != 0 {
+            mte::untag(ptr, size);
+        }
+
+        (mte::strip(ptr), size)
+    };
+
+    // Make some assertions.
+    debug_assert!(!sig::contains(ptr), "Freeing a buffer from the emergency pool. Use \
+                  `sig::dealloc` instead.");
+    #[cfg(all(feature = "debugger", feature = "tls"))]
+    assert!(!scratch::owns(ptr), "Freeing {:?}, which is a scratch allocation. Scratch \
+            allocations are freed along with their guard.", ptr);
+
+    // Buffers of the interposed allocator are handed back to it.
+    #[cfg(feature = "interpose")]
+    {
+        if is_foreign(ptr) {
+            free_foreign(ptr);
+            return;
+        }
+    }
+
+    // The free is traced before the address can be reused.
+    #[cfg(feature = "trace")]
+    trace::record_free(ptr, size);
+
+    // Mappings are unmapped rather than freed to the pool.
+    if let Some(region) = region::mapping(ptr) {
+        match region.origin {
+            Origin::Mmap { locked: true, .. } => secure::secure_free(ptr, region.size()),
+            _ => mapped::free(ptr, region.size()),
+        }
+        watermark::flush();
+
+        return;
+    }
+
+    // The age is read before the metadata is forgotten.
+    #[cfg(feature = "stats")]
+    {
+        if let Some(birth) = meta::Active::birth(ptr) {
+            stats::record_lifetime(size, meta::age(birth));
+        }
+    }
+
+    let padding = match align {
+        Some(align) => {
+            if cfg!(debug_assertions) && !meta::Active::verify(ptr, size, align) {
+                log!(ERROR, "Sized free of a buffer of size {} with alignment {} doesn't match \
+                             its metadata.", size, align);
+                fail::corruption(ptr);
             }
+
+            meta::Active::forget(ptr, size, align)
+        },
+        None => unstamp(ptr, size),
+    };
+    let (ptr, size, _) = record_free(ptr, size);
+    // The padding is released along with the buffer.
+    let (ptr, size) = (map_addr(ptr, |x| x - padding), padding + size);
+    unguard(ptr, size);
+
+    #[cfg(feature = "slab")]
+    {
+        if slab::free(ptr, size).is_ok() {
+            heartbeat();
+            return;
         }
     }
+
+    let size = pool_size(size);
+    // The slack kept after the buffer is released along with it.
+    #[cfg(feature = "realloc_slack")]
+    let size = size + slack::take(ptr);
+    let block = Block::from_raw_parts(Pointer::new(ptr), size);
+    #[cfg(feature = "bounded_free")]
+    defer_free(block);
+    #[cfg(not(feature = "bounded_free"))]
+    get_allocator!(|alloc| alloc.free_used(block));
+
+    heartbeat();
 }
 
-/// Allocate a block of memory.
+/// Free a buffer without knowing its size.
 ///
-/// # Errors
+/// The size is read from the metadata of the buffer, making this usable for C's `free`.
 ///
-/// The OOM handler handles out-of-memory conditions.
+/// # Failure
+///
+/// If the metadata is corrupted, the heap corruption handler is called.
+///
+/// # Safety
+///
+/// The same rules as for `free` apply.
+#[cfg(any(feature = "header", feature = "sidetable"))]
 #[inline]
-pub fn alloc(size: usize, align: usize) -> *mut u8 {
-    log!(CALL, "Allocating buffer of size {} (align {}).", size, align);
+pub unsafe fn free_unsized(ptr: *mut u8) {
+    // Buffers of the interposed allocator carry none of our metadata.
+    #[cfg(feature = "interpose")]
+    {
+        if is_foreign(ptr) {
+            free_foreign(ptr);
+            return;
+        }
+    }
 
-    get_allocator!(|alloc| *Pointer::from(alloc.alloc(size, align)))
+    // Mappings carry no metadata.
+    if let Some(region) = region::mapping(ptr) {
+        return free(ptr, region.size());
+    }
+
+    free(ptr, meta::Active::size(untagged(ptr)));
 }
 
-/// Free a buffer.
-///
-/// Note that this do not have to be a buffer allocated through ralloc. The only requirement is
-/// that it is not used after the free.
+/// Get the usable size of a buffer from its metadata.
 ///
-/// # Important!
+/// With headers, this is the requested size. With the side table, the buffers in slabs have the
+/// size of their class.
 ///
-/// You should only allocate buffers allocated through `ralloc`. Anything else is considered
-/// invalid.
+/// With the `realloc_slack` feature, the slack kept after a shrunk buffer is not included. The
+/// caller may write up to the usable size, but the slack can be given back to the pool (and handed
+/// to another buffer) at any time, e.g. by `purge` or a failing allocation in another thread.
 ///
-/// # Errors
+/// # Failure
 ///
-/// The OOM handler handles out-of-memory conditions.
+/// If the metadata is corrupted, the heap corruption handler is called.
 ///
 /// # Safety
 ///
-/// Rust assume that the allocation symbols returns correct values. For this reason, freeing
-/// invalid pointers might introduce memory unsafety.
+/// `ptr` must be a live buffer allocated through `ralloc` (or, with the `interpose` feature, the
+/// allocator it replaced).
+#[cfg(any(feature = "header", feature = "sidetable"))]
+#[inline]
+pub unsafe fn usable_size(ptr: *mut u8) -> usize {
+    // The interposed allocator knows the size of its buffers.
+    #[cfg(feature = "interpose")]
+    {
+        if is_foreign(ptr) {
+            return ::shim::interpose::usable_size(ptr).unwrap_or_else(|()| {
+                log!(WARNING, "Unable to get the size of foreign buffer {:?}.", ptr);
+
+                0
+            });
+        }
+    }
+
+    // Mappings carry no metadata.
+    if let Some(size) = mapped::size(ptr) {
+        return size;
+    }
+
+    meta::Active::size(untagged(ptr))
+}
+
+/// Strip the memory tag of a pointer (see `mte`).
+#[cfg(feature = "mte")]
+#[inline]
+fn untagged(ptr: *mut u8) -> *mut u8 {
+    mte::strip(ptr)
+}
+
+/// Strip the memory tag of a pointer.
 ///
-/// Secondly, freeing an used buffer can introduce use-after-free.
+/// Without the `mte` feature, pointers are never tagged.
+#[cfg(not(feature = "mte"))]
 #[inline]
-pub unsafe fn free(ptr: *mut u8, size: usize) {
-    log!(CALL, "Freeing buffer of size {}.", size);
+fn untagged(ptr: *mut u8) -> *mut u8 {
+    ptr
+}
+
+/// Is `ptr` a buffer of the interposed allocator?
+///
+/// Every piece of memory of `ralloc` is registered as a region (failing to register one is fatal
+/// with this feature, see `region::unregistered`), so a buffer outside all of them was allocated
+/// by the allocator `ralloc` replaced (e.g. before it was preloaded).
+#[cfg(feature = "interpose")]
+#[inline]
+fn is_foreign(ptr: *mut u8) -> bool {
+    region::lookup(untagged(ptr) as usize).is_none()
+}
+
+/// Free a buffer of the interposed allocator.
+///
+/// The buffer is handed to the `free` of the next allocator in the symbol lookup order. If there
+/// is none, the buffer is leaked, as it cannot be told apart from a wild pointer.
+#[cfg(feature = "interpose")]
+#[cold]
+unsafe fn free_foreign(ptr: *mut u8) {
+    log!(NOTE, "Freeing foreign buffer {:?} through the interposed allocator.", ptr);
 
-    get_allocator!(|alloc| alloc.free(Block::from_raw_parts(Pointer::new(ptr), size)))
+    if ::shim::interpose::free(ptr).is_err() {
+        log!(WARNING, "Unable to resolve the interposed allocator. Leaking {:?}.", ptr);
+    }
 }
 
 /// Reallocate memory.
@@ -338,11 +2880,14 @@ pub unsafe fn free(ptr: *mut u8, size: usize) {
 /// # Important!
 ///
 /// You should only reallocate buffers allocated through `ralloc`. Anything else is considered
-/// invalid.
+/// invalid. With the `interpose` feature, buffers of the allocator `ralloc` replaced are accepted
+/// as well, and moved into a new buffer of `ralloc`.
 ///
 /// # Errors
 ///
-/// The OOM handler handles out-of-memory conditions.
+/// The OOM handler handles out-of-memory conditions. If `align` is zero or not a power of two, or
+/// the buffer would grow beyond the maximal allocation size, a null pointer is returned, and the
+/// buffer is left intact.
 ///
 /// # Safety
 ///
@@ -352,20 +2897,345 @@ pub unsafe fn free(ptr: *mut u8, size: usize) {
 pub unsafe fn realloc(ptr: *mut u8, old_size: usize, size: usize, align: usize) -> *mut u8 {
     log!(CALL, "Reallocating buffer of size {} to new size {}.", old_size, size);
 
-    get_allocator!(|alloc| {
-        *Pointer::from(alloc.realloc(
-            Block::from_raw_parts(Pointer::new(ptr), old_size),
-            size,
-            align
-        ))
-    })
+    #[cfg(feature = "shadow_accounting")]
+    let call = shadow::enter();
+    // The buffer is only accounted as freed, if the reallocation succeeds, but it is told apart
+    // before it is gone.
+    #[cfg(feature = "shadow_accounting")]
+    let old = accounted(ptr);
+
+    let res = realloc_buffer(ptr, old_size, size, align);
+
+    #[cfg(feature = "shadow_accounting")]
+    {
+        if !res.is_null() {
+            account(&call, shadow::Op::Free, old, old_size);
+            account(&call, shadow::Op::Alloc, accounted(res), size);
+        }
+    }
+
+    res
+}
+
+/// Reallocate a buffer.
+///
+/// This is `realloc` without the shadow accounting.
+#[inline]
+unsafe fn realloc_buffer(ptr: *mut u8, old_size: usize, size: usize, align: usize) -> *mut u8 {
+    // The buffer is left intact, if the alignment is invalid or the buffer would grow beyond the
+    // maximal allocation size.
+    let align = match check_align(align) {
+        Some(align) => align,
+        None => return ptr::null_mut(),
+    };
+    if size > old_size && check_size(size).is_err() {
+        return ptr::null_mut();
+    }
+
+    // Tagged buffers are moved through their tagged pointers, such that the copy matches the tags
+    // of both buffers. The allocation tag is not carried over.
+    #[cfg(feature = "mte")]
+    {
+        if mte::tag_of(ptr) != 0 {
+            let res = alloc_buffer(size, align, 0);
+            if !res.is_null() {
+                ptr::copy_nonoverlapping(ptr, res, cmp::min(old_size, size));
+                free_buffer(ptr, old_size, None);
+            }
+
+            return res;
+        }
+    }
+    // Untagged buffers (i.e. without MTE hardware) still cover whole granules.
+    #[cfg(feature = "mte")]
+    let (old_size, size) = (mte::round(old_size), mte::round(size));
+
+    // Buffers of the interposed allocator are moved into our own.
+    #[cfg(feature = "interpose")]
+    {
+        if is_foreign(ptr) {
+            let res = alloc_buffer(size, align, 0);
+            ptr::copy_nonoverlapping(ptr, res, cmp::min(old_size, size));
+            free_foreign(ptr);
+
+            return res;
+        }
+    }
+
+    // Mappings have no neighbors to grow into, so they are moved.
+    if mapped::size(ptr).is_some() {
+        let res = alloc_buffer(size, align, 0);
+        if !res.is_null() {
+            ptr::copy_nonoverlapping(ptr, res, cmp::min(old_size, size));
+            free_buffer(ptr, old_size, None);
+        }
+
+        return res;
+    }
+
+    #[cfg(feature = "trace")]
+    let id = trace::begin_realloc(ptr);
+    let birth = birth(ptr);
+    let padding = unstamp(ptr, old_size);
+    let redzone = redzone(ptr);
+    #[cfg(feature = "sites")]
+    let site = site::remove(ptr, old_size);
+    let (_, _, tag) = record_free(ptr, old_size);
+    unguard(ptr, old_size + redzone);
+
+    let start = map_addr(ptr, |x| x - padding);
+    let res = quiet(|| {
+        realloc_block(start, padding + old_size + redzone, padding + size + REDZONE, align)
+    });
+    let res = map_addr(res, |x| x + padding);
+
+    unguard(res, size);
+    guard(res, size, size + REDZONE);
+    stamp(res, size, padding, tag, birth);
+    record_alloc(res, size, tag);
+    #[cfg(feature = "sites")]
+    site::restore(res, size, site);
+    keep_defined(res, cmp::min(old_size, size));
+    #[cfg(feature = "trace")]
+    trace::end_realloc(id, res, size, align.get());
+    watermark::flush();
+
+    res
+}
+
+/// Reallocate a buffer in the slabs or the pool.
+#[inline]
+unsafe fn realloc_block(ptr: *mut u8, old_size: usize, size: usize, align: Align) -> *mut u8 {
+    // Slab cells are moved out of (or kept in) their cell.
+    #[cfg(feature = "slab")]
+    {
+        if let Some(cell) = slab::cell_size(ptr, old_size) {
+            if size <= cell && align.get() <= slab::MAX_ALIGN {
+                return ptr;
+            }
+
+            let res = *Pointer::from(alloc_block(size, align));
+            ptr::copy_nonoverlapping(ptr, res, cmp::min(old_size, size));
+            slab::free(ptr, old_size).expect("The slab cell was freed during reallocation.");
+
+            return res;
+        }
+    }
+
+    let (old_size, size) = (pool_size(old_size), pool_size(size));
+    // The slack kept after the block is part of it again, and a resize within the block can be
+    // kept without going through the pool.
+    #[cfg(feature = "realloc_slack")]
+    let old_size = old_size + slack::take(ptr);
+    #[cfg(feature = "realloc_slack")]
+    {
+        if slack::keep(ptr, old_size, size, align) {
+            return ptr;
+        }
+    }
+
+    let block = Block::from_raw_parts(Pointer::new(ptr), old_size);
+    *Pointer::from(get_allocator!(
+        |alloc| alloc.realloc(block, size, align),
+        else global_realloc(block, size, align)
+    ))
+}
+
+/// Reallocate memory, granting anywhere between `needed` and `preferred` bytes.
+///
+/// Reallocate the buffer starting at `ptr` with size `old_size`. If the buffer can be extended
+/// inplace to at least `needed` bytes, it is extended as far as possible without exceeding
+/// `preferred` (e.g. taking the whole free neighbor). Otherwise, it is moved to a buffer of
+/// `preferred` bytes.
+///
+/// The new pointer and the granted size are returned. The granted size is the size of the buffer
+/// from now on, and must be passed when freeing or reallocating it.
+///
+/// This is meant for growable structures (e.g. vectors), which can use the slack as capacity.
+///
+/// # Important!
+///
+/// You should only reallocate buffers allocated through `ralloc`. Anything else is considered
+/// invalid. With the `interpose` feature, buffers of the allocator `ralloc` replaced are accepted
+/// as well, and moved into a new buffer of `ralloc`.
+///
+/// # Errors
+///
+/// The OOM handler handles out-of-memory conditions. If `align` is zero or not a power of two, or
+/// `needed` exceeds the maximal allocation size, a null pointer (with a granted size of zero) is
+/// returned, and the buffer is left intact. The granted size never exceeds the limit, unless the
+/// buffer already did.
+///
+/// # Safety
+///
+/// Due to being able to potentially memcpy an arbitrary buffer, as well as shrinking a buffer,
+/// this is marked unsafe.
+#[inline]
+pub unsafe fn realloc_with_hint(ptr: *mut u8, old_size: usize, needed: usize, preferred: usize,
+                                align: usize) -> (*mut u8, usize) {
+    log!(CALL, "Reallocating buffer of size {} to new size {} (preferably {}).", old_size, needed,
+         preferred);
+
+    #[cfg(feature = "shadow_accounting")]
+    let call = shadow::enter();
+    // See `realloc`.
+    #[cfg(feature = "shadow_accounting")]
+    let old = accounted(ptr);
+
+    let (res, granted) = realloc_with_hint_buffer(ptr, old_size, needed, preferred, align);
+
+    #[cfg(feature = "shadow_accounting")]
+    {
+        if !res.is_null() {
+            account(&call, shadow::Op::Free, old, old_size);
+            account(&call, shadow::Op::Alloc, accounted(res), granted);
+        }
+    }
+
+    (res, granted)
+}
+
+/// Reallocate a buffer, granting anywhere between `needed` and `preferred` bytes.
+///
+/// This is `realloc_with_hint` without the shadow accounting.
+#[inline]
+unsafe fn realloc_with_hint_buffer(ptr: *mut u8, old_size: usize, needed: usize,
+                                   preferred: usize, align: usize) -> (*mut u8, usize) {
+    // Make some assertions.
+    debug_assert!(needed <= preferred, "The needed size is larger than the preferred size.");
+
+    // The buffer is left intact, if the alignment is invalid or the buffer would grow beyond the
+    // maximal allocation size.
+    let align = match check_align(align) {
+        Some(align) => align,
+        None => return (ptr::null_mut(), 0),
+    };
+    if needed > old_size && check_size(needed).is_err() {
+        return (ptr::null_mut(), 0);
+    }
+    // The slack is bounded by the limit as well.
+    let preferred = cmp::max(needed, cmp::min(preferred, conf::max_allocation()));
+
+    // Tagged buffers are moved (see `realloc`), without slack beyond their granules.
+    #[cfg(feature = "mte")]
+    {
+        if mte::tag_of(ptr) != 0 {
+            let res = realloc(ptr, old_size, preferred, align.get());
+            return (res, if res.is_null() { 0 } else { mte::round(preferred) });
+        }
+    }
+    #[cfg(feature = "mte")]
+    let (old_size, needed, preferred) = (mte::round(old_size), mte::round(needed),
+                                         mte::round(preferred));
+
+    // Buffers of the interposed allocator are moved into our own.
+    #[cfg(feature = "interpose")]
+    {
+        if is_foreign(ptr) {
+            let res = alloc_buffer(preferred, align, 0);
+            ptr::copy_nonoverlapping(ptr, res, cmp::min(old_size, preferred));
+            free_foreign(ptr);
+
+            return (res, preferred);
+        }
+    }
+
+    // Mappings grant the rest of their pages, and are moved otherwise.
+    if let Some(mapping) = mapped::size(ptr) {
+        if needed <= mapping && align.is_aligned(ptr as usize) {
+            return (ptr, cmp::min(mapping, preferred));
+        }
+
+        let res = alloc_buffer(preferred, align, 0);
+        if res.is_null() {
+            return (res, 0);
+        }
+        ptr::copy_nonoverlapping(ptr, res, cmp::min(old_size, preferred));
+        free_buffer(ptr, old_size, None);
+
+        return (res, preferred);
+    }
+
+    #[cfg(feature = "trace")]
+    let id = trace::begin_realloc(ptr);
+    let birth = birth(ptr);
+    let padding = unstamp(ptr, old_size);
+    let redzone = redzone(ptr);
+    #[cfg(feature = "sites")]
+    let site = site::remove(ptr, old_size);
+    let (_, _, tag) = record_free(ptr, old_size);
+    unguard(ptr, old_size + redzone);
+
+    let start = map_addr(ptr, |x| x - padding);
+    let (res, granted) = quiet(|| {
+        realloc_with_hint_block(start, padding + old_size + redzone, padding + needed + REDZONE,
+                                padding + preferred + REDZONE, align)
+    });
+    let res = map_addr(res, |x| x + padding);
+    // The padding and the redzone are not part of the granted size.
+    let granted = granted - padding - REDZONE;
+    // The granted size covers whole granules (the needed size does, so it is kept).
+    #[cfg(feature = "mte")]
+    let granted = granted & !(mte::GRANULE - 1);
+
+    unguard(res, granted);
+    guard(res, granted, granted + REDZONE);
+    stamp(res, granted, padding, tag, birth);
+    record_alloc(res, granted, tag);
+    #[cfg(feature = "sites")]
+    site::restore(res, granted, site);
+    keep_defined(res, cmp::min(old_size, granted));
+    #[cfg(feature = "trace")]
+    trace::end_realloc(id, res, granted, align.get());
+    watermark::flush();
+
+    (res, granted)
+}
+
+/// Reallocate a buffer with a size hint in the slabs or the pool.
+#[inline]
+unsafe fn realloc_with_hint_block(ptr: *mut u8, old_size: usize, needed: usize, preferred: usize,
+                                  align: Align) -> (*mut u8, usize) {
+    // Slab cells grant the rest of their cell.
+    #[cfg(feature = "slab")]
+    {
+        if let Some(cell) = slab::cell_size(ptr, old_size) {
+            if needed <= cell && align.get() <= slab::MAX_ALIGN {
+                return (ptr, cmp::min(cell, preferred));
+            }
+
+            let res = *Pointer::from(alloc_block(preferred, align));
+            ptr::copy_nonoverlapping(ptr, res, cmp::min(old_size, preferred));
+            slab::free(ptr, old_size).expect("The slab cell was freed during reallocation.");
+
+            return (res, preferred);
+        }
+    }
+
+    let old_size = pool_size(old_size);
+    // The slack kept after the block is part of it again.
+    #[cfg(feature = "realloc_slack")]
+    let old_size = old_size + slack::take(ptr);
+
+    let block = Block::from_raw_parts(Pointer::new(ptr), old_size);
+    let (pool_needed, pool_preferred) = (pool_size(needed), pool_size(preferred));
+    let block = get_allocator!(
+        |alloc| alloc.realloc_with_hint(block, pool_needed, pool_preferred, align),
+        else global_realloc_with_hint(block, pool_needed, pool_preferred, align)
+    );
+    // Blocks rounded up by the metadata never grant more than the preferred size, since freeing
+    // rounds the size up the same way.
+    let size = cmp::min(block.size(), preferred);
+
+    (*Pointer::from(block), size)
 }
 
 /// Try to reallocate the buffer _inplace_.
 ///
 /// In case of success, return the new buffer's size. On failure, return the old size.
 ///
-/// This can be used to shrink (truncate) a buffer as well.
+/// This can be used to shrink (truncate) a buffer as well. Growing the buffer beyond the maximal
+/// allocation size fails.
 ///
 /// # Safety
 ///
@@ -374,14 +3244,334 @@ pub unsafe fn realloc(ptr: *mut u8, old_size: usize, size: usize, align: usize)
 pub unsafe fn realloc_inplace(ptr: *mut u8, old_size: usize, size: usize) -> Result<(), ()> {
     log!(CALL, "Inplace reallocating buffer of size {} to new size {}.", old_size, size);
 
+    #[cfg(feature = "shadow_accounting")]
+    let call = shadow::enter();
+
+    let res = realloc_inplace_buffer(ptr, old_size, size);
+
+    #[cfg(feature = "shadow_accounting")]
+    {
+        if res.is_ok() {
+            // The buffer stays where it is, so it is accounted (or not) on both sides alike.
+            let accounted = accounted(ptr);
+            account(&call, shadow::Op::Free, accounted, old_size);
+            account(&call, shadow::Op::Alloc, accounted, size);
+        }
+    }
+
+    res
+}
+
+/// Try to reallocate a buffer inplace.
+///
+/// This is `realloc_inplace` without the shadow accounting.
+#[inline]
+unsafe fn realloc_inplace_buffer(ptr: *mut u8, old_size: usize, size: usize) -> Result<(), ()> {
+    if size > old_size && check_size(size).is_err() {
+        return Err(());
+    }
+    // Buffers of the interposed allocator are never resized inplace.
+    #[cfg(feature = "interpose")]
+    {
+        if is_foreign(ptr) {
+            return Err(());
+        }
+    }
+    // Mappings can be resized within their pages.
+    if let Some(mapping) = mapped::size(ptr) {
+        return if size <= mapping { Ok(()) } else { Err(()) };
+    }
+
+    // The bookkeeping is done on the untagged pointer. A tail given back goes to tag zero first,
+    // since it can be reused right away.
+    #[cfg(feature = "mte")]
+    let (tagged, ptr, old_size, size) = (ptr, mte::strip(ptr), mte::round(old_size),
+                                         mte::round(size));
+    #[cfg(feature = "mte")]
+    {
+        if size < old_size {
+            mte::untag(map_addr(ptr, |x| x + size), old_size - size);
+        }
+    }
+
+    let birth = birth(ptr);
+    let padding = unstamp(ptr, old_size);
+    let redzone = redzone(ptr);
+    unguard(ptr, old_size + redzone);
+
+    let start = map_addr(ptr, |x| x - padding);
+    let res = realloc_inplace_block(start, padding + old_size + redzone,
+                                    padding + size + REDZONE);
+
+    if res.is_ok() {
+        #[cfg(feature = "sites")]
+        let site = site::remove(ptr, old_size);
+        let (_, _, tag) = record_free(ptr, old_size);
+        guard(ptr, size, size + REDZONE);
+        stamp(ptr, size, padding, tag, birth);
+        record_alloc(ptr, size, tag);
+        #[cfg(feature = "sites")]
+        site::restore(ptr, size, site);
+        keep_defined(ptr, cmp::min(old_size, size));
+
+        // The tag of the buffer is extended over the memory it grew into.
+        #[cfg(feature = "mte")]
+        {
+            if size > old_size {
+                mte::extend(map_addr(tagged, |x| x + old_size), size - old_size);
+            }
+        }
+
+        // Inplace reallocations keep the address, so they can be traced afterwards.
+        #[cfg(feature = "trace")]
+        {
+            let id = trace::begin_realloc(ptr);
+            trace::end_realloc(id, ptr, size, 1);
+        }
+    } else {
+        guard(ptr, old_size, old_size + redzone);
+
+        // The tail stays with the buffer after all.
+        #[cfg(feature = "mte")]
+        {
+            if size < old_size {
+                mte::extend(map_addr(tagged, |x| x + size), old_size - size);
+            }
+        }
+    }
+
+    res
+}
+
+/// Try to reallocate a buffer inplace in the slabs or the pool.
+#[inline]
+unsafe fn realloc_inplace_block(ptr: *mut u8, old_size: usize, size: usize) -> Result<(), ()> {
+    // Slab cells can be resized within their cell.
+    #[cfg(feature = "slab")]
+    {
+        if let Some(cell) = slab::cell_size(ptr, old_size) {
+            return if size <= cell { Ok(()) } else { Err(()) };
+        }
+    }
+
+    let (old_size, size) = (pool_size(old_size), pool_size(size));
+    // The slack kept after the block is part of it again, and a resize within the block can be
+    // kept without going through the pool.
+    #[cfg(feature = "realloc_slack")]
+    let tail = slack::take(ptr);
+    #[cfg(not(feature = "realloc_slack"))]
+    let tail = 0;
+    #[cfg(feature = "realloc_slack")]
+    {
+        if slack::keep(ptr, old_size + tail, size, Align::MIN) {
+            return Ok(());
+        }
+    }
+
     get_allocator!(|alloc| {
         if alloc.realloc_inplace(
-            Block::from_raw_parts(Pointer::new(ptr), old_size),
+            Block::from_raw_parts(Pointer::new(ptr), old_size + tail),
             size
         ).is_ok() {
             Ok(())
         } else {
+            // The block is left as it was, so the slack is kept again, if there is room.
+            #[cfg(feature = "realloc_slack")]
+            {
+                if tail != 0 && !slack::insert(ptr, old_size, tail) {
+                    alloc.free_used(Block::from_raw_parts(Pointer::new(ptr), old_size + tail)
+                                        .split(old_size).1);
+                }
+            }
+
             Err(())
         }
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use prelude::*;
+
+    use core::mem;
+
+    use sync;
+
+    #[test]
+    fn test_check_size() {
+        assert!(check_size(MAX_BLOCK).is_ok() || conf::max_allocation() < MAX_BLOCK);
+        assert_eq!(check_size(MAX_BLOCK + 1), Err(AllocErr::TooLarge {
+            requested: MAX_BLOCK + 1,
+            limit: cmp::min(conf::max_allocation(), MAX_BLOCK),
+        }));
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "32")]
+    fn test_check_size_32() {
+        // 3 GiB fits a `usize`, but not an `isize`, so it is rejected before anything is taken.
+        assert!(check_size(3 << 30).is_err());
+        assert!(try_alloc(3 << 30, 8).is_err());
+        assert!(alloc(3 << 30, 8).is_null());
+    }
+
+    #[test]
+    #[cfg(feature = "mte")]
+    fn test_granule_rounding() {
+        for size in 1..70 {
+            unsafe {
+                // Every buffer is freed with the size it was given.
+                let ptr = alloc(size, 1);
+                assert_eq!(mte::strip(ptr) as usize % mte::GRANULE, 0);
+
+                // Growing inplace within the last granule always succeeds.
+                assert!(realloc_inplace(ptr, size, mte::round(size)).is_ok());
+
+                let ptr = realloc(ptr, mte::round(size), size + 1, 1);
+                assert!(!ptr.is_null());
+
+                let (ptr, granted) = realloc_with_hint(ptr, size + 1, size + 5, size + 40, 1);
+                assert!(!ptr.is_null());
+                assert_eq!(granted % mte::GRANULE, 0);
+                assert!(granted >= mte::round(size + 5));
+
+                free(ptr, granted);
+            }
+        }
+    }
+
+    #[test]
+    fn test_layout() {
+        // The global lock starts a line, and no other value shares its lines.
+        assert_eq!(&GLOBAL_ALLOCATOR as *const _ as usize % sync::CACHE_LINE, 0);
+        assert_eq!(mem::size_of_val(&GLOBAL_ALLOCATOR) % sync::CACHE_LINE, 0);
+    }
+
+    #[test]
+    fn test_from_buffer() {
+        extern crate std;
+
+        use self::std::boxed::Box;
+
+        let buf: &'static mut [u8; 4096] = unsafe {
+            // The box is leaked, so it lives for the rest of the program.
+            &mut *Box::into_raw(Box::new([0; 4096]))
+        };
+        let range = buf.as_ptr() as usize..buf.as_ptr() as usize + buf.len();
+
+        let mut alloc = GlobalAllocator::from_buffer(buf);
+
+        let a = alloc.alloc(100, Align::new(8).unwrap());
+        let b = alloc.alloc(1000, Align::new(64).unwrap());
+        assert!(range.start <= *Pointer::from(a.empty_left()) as usize);
+        assert!(*Pointer::from(b.empty_right()) as usize <= range.end);
+        assert!(b.aligned_to(Align::new(64).unwrap()));
+
+        alloc.free(a);
+        alloc.free(b);
+
+        // The freed memory is reused.
+        let c = alloc.alloc(2000, Align::MIN);
+        assert!(range.start <= *Pointer::from(c.empty_left()) as usize);
+        assert!(*Pointer::from(c.empty_right()) as usize <= range.end);
+        alloc.free(c);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_sig_alloc_under_lock() {
+        use core::ptr;
+        use core::sync::atomic::{self, AtomicUsize};
+
+        use sig;
+
+        /// The buffer allocated by the signal handler.
+        static BUFFER: AtomicUsize = AtomicUsize::new(0);
+
+        extern {
+            fn signal(signum: i32, handler: usize) -> usize;
+            fn raise(sig: i32) -> i32;
+        }
+
+        extern fn handler(_: i32) {
+            if let Some(ptr) = sig::alloc(64) {
+                unsafe {
+                    // The buffer is 64 bytes long.
+                    ptr::write_bytes(ptr, 0xAB, 64);
+                }
+                BUFFER.store(ptr as usize, atomic::Ordering::SeqCst);
+            }
+        }
+
+        unsafe {
+            // SIGUSR1.
+            signal(10, handler as usize);
+
+            // The signal is delivered to this thread, while it holds the pool lock. Allocating
+            // from the pool would deadlock.
+            let lock = GLOBAL_ALLOCATOR.lock();
+            raise(10);
+            drop(lock);
+
+            let ptr = BUFFER.load(atomic::Ordering::SeqCst) as *mut u8;
+            assert!(!ptr.is_null());
+            assert!(sig::contains(ptr));
+            for i in 0..64 {
+                assert_eq!(*ptr.offset(i), 0xAB);
+            }
+
+            sig::dealloc(ptr);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "bounded_free")]
+    fn test_relieve_flushes_every_thread() {
+        extern crate std;
+
+        use self::std::sync::mpsc;
+        use self::std::thread;
+
+        /// Is `addr` queued on any list of deferred frees?
+        fn queued(addr: usize) -> bool {
+            let lists = PENDING_LISTS.lock();
+            let mut node = lists.first;
+            let mut found = false;
+            while !node.is_null() {
+                unsafe {
+                    let pending = (*node).list.lock();
+                    found |= pending.blocks[..pending.len].iter().any(|&(ptr, _)| {
+                        ptr as usize == addr
+                    });
+                    node = (*node).next;
+                }
+            }
+
+            found
+        }
+
+        // Another thread defers a free (too large for the slabs), and stays alive, with its list
+        // registered.
+        let (queued_tx, queued_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let other = thread::spawn(move || {
+            let ptr = alloc(2000, 16);
+            unsafe { free(ptr, 2000); }
+            queued_tx.send(ptr as usize).unwrap();
+            done_rx.recv().unwrap();
+        });
+
+        let addr = queued_rx.recv().unwrap();
+        assert!(queued(addr));
+
+        // The heap failing to grow flushes the list of the other thread too.
+        let block = relieve(2000, Align::new(16).unwrap(), AllocErr::LimitReached);
+        assert!(!queued(addr));
+        GLOBAL_ALLOCATOR.lock().get().free(block);
+
+        done_tx.send(()).unwrap();
+        other.join().unwrap();
+    }
+}
@@ -0,0 +1,330 @@
+//! Arenas.
+//!
+//! With the `arenas` feature, the local allocators don't refill from the global allocator
+//! directly, but from one of `COUNT` independent arenas, each a pool behind its own lock. Threads
+//! are assigned an arena (round-robin) on their first refill, so threads only contend when they
+//! share an arena.
+//!
+//! Arenas take their memory from the global allocator in chunks of at least
+//! `config::ARENA_CHUNK_SIZE` bytes. Every chunk is assigned to the arena in the region registry
+//! (see `region::assign`), so blocks given back by local allocators are routed to the arena owning
+//! them. When the home arena is exhausted, the other arenas are searched (stealing their free
+//! blocks) before a new chunk is taken from the global allocator.
+//!
+//! With the `cpu_shards` feature, the arenas are shards of the CPUs instead: a thread refills from
+//! the shard of the CPU it is running on (as told by `getcpu`), so the threads of a large thread
+//! pool share the few shards of the cores they run on, rather than holding on to arenas of their
+//! own. The CPU is cached per thread, and looked up again every `config::CPU_HINT_TTL` refills.
+//! There is one shard per CPU (up to `COUNT`), and where the CPU cannot be told, shard 0 is used.
+
+use prelude::*;
+
+use core::cell::Cell;
+use atomic::{self, AtomicUsize};
+use core::{cmp, mem, ops};
+
+use shim::config;
+#[cfg(feature = "cpu_shards")]
+use shim::syscalls;
+
+use bookkeeper::{self, Bookkeeper, Allocator};
+use fail::{self, AllocErr};
+use {allocator, bootstrap, region, sync, tls};
+
+/// The number of arenas.
+pub const COUNT: usize = 8;
+
+/// The arenas.
+// The mutexes aren't `Copy`, so we cannot use the repeat syntax.
+static ARENAS: [sync::Mutex<LazyInit<fn() -> Arena, Arena>>; COUNT] = [
+    sync::Mutex::ranked("arena", sync::rank::ARENA, LazyInit::new(Arena::init)),
+    sync::Mutex::ranked("arena", sync::rank::ARENA, LazyInit::new(Arena::init)),
+    sync::Mutex::ranked("arena", sync::rank::ARENA, LazyInit::new(Arena::init)),
+    sync::Mutex::ranked("arena", sync::rank::ARENA, LazyInit::new(Arena::init)),
+    sync::Mutex::ranked("arena", sync::rank::ARENA, LazyInit::new(Arena::init)),
+    sync::Mutex::ranked("arena", sync::rank::ARENA, LazyInit::new(Arena::init)),
+    sync::Mutex::ranked("arena", sync::rank::ARENA, LazyInit::new(Arena::init)),
+    sync::Mutex::ranked("arena", sync::rank::ARENA, LazyInit::new(Arena::init)),
+];
+/// The next arena to assign.
+#[cfg(not(feature = "cpu_shards"))]
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+tls! {
+    /// The arena of the current thread, or `COUNT`, if it hasn't been assigned yet.
+    static HOME: Cell<usize> = Cell::new(COUNT);
+}
+#[cfg(feature = "cpu_shards")]
+tls! {
+    /// The number of refills left, before the CPU of the current thread is looked up again.
+    static TTL: Cell<usize> = Cell::new(0);
+}
+/// The number of shards, or zero if it hasn't been determined yet.
+#[cfg(feature = "cpu_shards")]
+static SHARDS: AtomicUsize = AtomicUsize::new(0);
+
+/// The statistics of an arena.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArenaStats {
+    /// The number of bytes taken from the global allocator.
+    pub owned: usize,
+    /// The number of free bytes in the arena.
+    pub free: usize,
+    /// The number of chunks taken from the global allocator.
+    pub refills: usize,
+    /// The number of blocks taken by threads of other arenas.
+    pub stolen: usize,
+}
+
+/// An arena.
+struct Arena {
+    /// The inner bookkeeper.
+    inner: Bookkeeper,
+    /// The statistics (except the free bytes, which the bookkeeper knows).
+    stats: ArenaStats,
+}
+
+impl Arena {
+    /// Initialize an arena.
+    fn init() -> Arena {
+        /// Logging...
+        log!(NOTE, "Initializing an arena.");
+
+        // The size of the initial segment.
+        let size = 4 * bookkeeper::EXTRA_ELEMENTS * mem::size_of::<Block>();
+
+        // The initial segment. We prefer the bootstrap arena, and fall back to the global
+        // allocator when it is exhausted.
+        let initial_segment = bootstrap::alloc(size, Align::of::<Block>())
+            .unwrap_or_else(|| allocator::global_alloc(size, Align::of::<Block>()));
+
+        Arena {
+            inner: Bookkeeper::new(unsafe {
+                Vec::from_raw_parts(initial_segment, 0)
+            }),
+            stats: ArenaStats::default(),
+        }
+    }
+
+    /// Take a block from the arena, without taking fresh memory.
+    fn take(&mut self, size: usize, align: Align) -> Option<Block> {
+        self.take_fitting(size, align).map(|block| {
+            let (res, excessive) = block.mark_uninitialized().split(size);
+            self.free(excessive);
+
+            res
+        })
+    }
+}
+
+impl ops::Deref for Arena {
+    type Target = Bookkeeper;
+
+    fn deref(&self) -> &Bookkeeper {
+        &self.inner
+    }
+}
+
+impl ops::DerefMut for Arena {
+    fn deref_mut(&mut self) -> &mut Bookkeeper {
+        &mut self.inner
+    }
+}
+
+impl Allocator for Arena {
+    /// Allocate fresh space from the global allocator.
+    ///
+    /// This is only reached by the bookkeeper's own metadata, since the arena is refilled through
+    /// `refill`. The space is not registered, so when it is freed, it goes back to the global
+    /// allocator.
+    #[inline]
+    fn alloc_fresh(&mut self, size: usize, align: Align) -> Block {
+        allocator::global_alloc(size, align)
+    }
+}
+
+/// Get the arena of the current thread.
+///
+/// Threads are assigned round-robin on their first call.
+#[cfg(not(feature = "cpu_shards"))]
+fn home() -> usize {
+    HOME.with(|home| {
+        if home.get() == COUNT {
+            home.set(NEXT.fetch_add(1, atomic::Ordering::Relaxed) % COUNT);
+        }
+
+        home.get()
+    })
+}
+
+/// Get the arena of the current thread.
+///
+/// This is the shard of the CPU, which the thread runs on. The CPU is looked up again every
+/// `config::CPU_HINT_TTL` calls. If it cannot be told, shard 0 is used.
+#[cfg(feature = "cpu_shards")]
+fn home() -> usize {
+    HOME.with(|home| TTL.with(|ttl| {
+        if home.get() == COUNT || ttl.get() == 0 {
+            home.set(syscalls::getcpu().map_or(0, |cpu| cpu % shards()));
+            ttl.set(config::CPU_HINT_TTL);
+        }
+        ttl.set(ttl.get() - 1);
+
+        home.get()
+    }))
+}
+
+/// Get the number of arenas in use.
+#[cfg(not(feature = "cpu_shards"))]
+#[inline]
+fn active() -> usize {
+    COUNT
+}
+
+/// Get the number of arenas in use.
+#[cfg(feature = "cpu_shards")]
+#[inline]
+fn active() -> usize {
+    shards()
+}
+
+/// Get the number of shards.
+///
+/// This is the number of CPUs, which the process may run on, but at most `COUNT`. If the CPUs
+/// cannot be counted, there is a single shard.
+#[cfg(feature = "cpu_shards")]
+pub fn shards() -> usize {
+    let n = SHARDS.load(atomic::Ordering::Relaxed);
+    if n != 0 {
+        return n;
+    }
+
+    let n = cmp::max(cmp::min(syscalls::cpu_count().unwrap_or(1), COUNT), 1);
+    SHARDS.store(n, atomic::Ordering::Relaxed);
+
+    n
+}
+
+/// Take a block from arena `n`, without taking fresh memory.
+fn take(n: usize, size: usize, align: Align) -> Option<Block> {
+    ARENAS[n].lock().get().take(size, align)
+}
+
+/// Take a new chunk from the global allocator for arena `n`, and allocate from it.
+fn refill(n: usize, size: usize, align: Align) -> Block {
+    let chunk_size = size.checked_add(align.get())
+        .map(|x| cmp::max(x, config::ARENA_CHUNK_SIZE))
+        .unwrap_or_else(|| fail::oom(AllocErr::TooLarge {
+            requested: size,
+            limit: !0 - align.get(),
+        }));
+    let chunk = allocator::global_alloc(chunk_size, Align::MIN);
+
+    // Logging.
+    log!(INTERNAL, "Refilling arena {} with {:?}.", n, chunk);
+
+    region::assign(&chunk, n);
+
+    let mut arena = ARENAS[n].lock();
+    let arena = arena.get();
+    arena.stats.owned += chunk_size;
+    arena.stats.refills += 1;
+    arena.free(chunk);
+
+    // The chunk fits the block, so this takes no fresh memory.
+    arena.alloc(size, align)
+}
+
+/// Allocate a block for a local allocator.
+///
+/// The block is taken from the home arena of the current thread. If the home arena is exhausted,
+/// the other arenas are tried, and if all of them are, the home arena takes a new chunk from the
+/// global allocator.
+pub fn alloc(size: usize, align: Align) -> Block {
+    let home = home();
+
+    if let Some(block) = take(home, size, align) {
+        return block;
+    }
+
+    // Steal from the other arenas, before growing the heap.
+    let count = active();
+    for i in 1..count {
+        let n = (home + i) % count;
+
+        let mut arena = ARENAS[n].lock();
+        let arena = arena.get();
+        if let Some(block) = arena.take(size, align) {
+            arena.stats.stolen += 1;
+            return block;
+        }
+    }
+
+    refill(home, size, align)
+}
+
+/// Free a block from a local allocator.
+///
+/// Every part of the block is routed to the arena owning it. Parts outside the regions of the
+/// arenas are freed to the global allocator.
+pub fn free(mut block: Block) {
+    while !block.is_empty() {
+        let start = *Pointer::from(block.empty_left()) as usize;
+        let (owner, end) = region::owner(start);
+
+        let (head, rest) = block.split(cmp::min(end - start, block.size()));
+        match owner {
+            Some(n) => ARENAS[n].lock().get().free(head),
+            None => allocator::global_free(head),
+        }
+
+        block = rest;
+    }
+}
+
+/// Get the statistics of every arena.
+pub fn stats() -> [ArenaStats; COUNT] {
+    let mut res = [ArenaStats::default(); COUNT];
+
+    for (n, stats) in res.iter_mut().enumerate() {
+        let mut arena = ARENAS[n].lock();
+        // Don't initialize arenas just to report on them.
+        if arena.is_initialized() {
+            let arena = arena.get();
+            *stats = arena.stats;
+            stats.free = arena.total_bytes();
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(not(feature = "cpu_shards"))]
+    fn test_round_robin() {
+        let first = home();
+        // The assignment sticks.
+        assert_eq!(home(), first);
+        assert!(first < COUNT);
+    }
+
+    #[test]
+    #[cfg(feature = "cpu_shards")]
+    fn test_shards() {
+        use shim::config;
+
+        let shards = shards();
+        assert!(shards >= 1 && shards <= COUNT);
+        // The count sticks.
+        assert_eq!(super::shards(), shards);
+
+        // The hint is refreshed, but always names a shard.
+        for _ in 0..3 * config::CPU_HINT_TTL {
+            assert!(home() < shards);
+        }
+    }
+}
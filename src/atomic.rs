@@ -0,0 +1,156 @@
+//! Atomics.
+//!
+//! The allocator uses the atomics of this module rather than those of `core`. Normally, they are
+//! the same, but with the `single_threaded` feature, they are replaced by stand-ins with the same
+//! interface, which only load and store, and thus work on targets without compare-and-swap.
+//!
+//! The stand-ins are `Sync` like the atomics they wrap, so no claim is made about threads. The
+//! allocator is still only correct if the program really is single-threaded: no other thread may
+//! ever call into the allocator, and neither may an interrupt or signal handler (unless the
+//! `critical_section` feature keeps it out of the locks, and it doesn't touch the statistics).
+
+#[cfg(not(feature = "single_threaded"))]
+pub use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicU32, AtomicUsize, Ordering};
+
+#[cfg(feature = "single_threaded")]
+pub use self::cell::*;
+
+/// The single-threaded stand-ins of the atomics.
+///
+/// The stand-ins keep the value in an atomic of `core`, but only ever load and store it, which
+/// needs no compare-and-swap. The read-modify-write operations are a load followed by a store, so
+/// they are only atomic, if nothing runs in between.
+#[cfg(feature = "single_threaded")]
+mod cell {
+    use core::sync::atomic;
+
+    pub use core::sync::atomic::Ordering;
+
+    /// The ordering of the loads and stores.
+    ///
+    /// Nothing else observes the values, so no ordering is needed.
+    const RELAXED: Ordering = Ordering::Relaxed;
+
+    /// Define a stand-in for an atomic integer type.
+    macro_rules! atomic_int {
+        ($name:ident, $ty:ty) => {
+            /// A single-threaded stand-in for the atomic type of the same name.
+            pub struct $name {
+                /// The inner value.
+                inner: atomic::$name,
+            }
+
+            impl $name {
+                /// Create a new value.
+                #[inline]
+                pub const fn new(x: $ty) -> $name {
+                    $name {
+                        inner: atomic::$name::new(x),
+                    }
+                }
+
+                /// Load the value.
+                #[inline]
+                pub fn load(&self, _: Ordering) -> $ty {
+                    self.inner.load(RELAXED)
+                }
+
+                /// Store a value.
+                #[inline]
+                pub fn store(&self, x: $ty, _: Ordering) {
+                    self.inner.store(x, RELAXED);
+                }
+
+                /// Store a value, returning the old one.
+                #[inline]
+                pub fn swap(&self, x: $ty, _: Ordering) -> $ty {
+                    let old = self.inner.load(RELAXED);
+                    self.inner.store(x, RELAXED);
+                    old
+                }
+
+                /// Store `new` if the value is `current`, returning the old value.
+                #[inline]
+                pub fn compare_and_swap(&self, current: $ty, new: $ty, _: Ordering) -> $ty {
+                    let old = self.inner.load(RELAXED);
+                    if old == current {
+                        self.inner.store(new, RELAXED);
+                    }
+                    old
+                }
+            }
+        };
+        ($name:ident, $ty:ty, arithmetic) => {
+            atomic_int!($name, $ty);
+
+            impl $name {
+                /// Add to the value (wrapping around), returning the old value.
+                #[inline]
+                pub fn fetch_add(&self, x: $ty, _: Ordering) -> $ty {
+                    let old = self.inner.load(RELAXED);
+                    self.inner.store(old.wrapping_add(x), RELAXED);
+                    old
+                }
+
+                /// Subtract from the value (wrapping around), returning the old value.
+                #[inline]
+                pub fn fetch_sub(&self, x: $ty, _: Ordering) -> $ty {
+                    let old = self.inner.load(RELAXED);
+                    self.inner.store(old.wrapping_sub(x), RELAXED);
+                    old
+                }
+            }
+        };
+    }
+
+    atomic_int!(AtomicBool, bool);
+    atomic_int!(AtomicU8, u8, arithmetic);
+    atomic_int!(AtomicU32, u32, arithmetic);
+    atomic_int!(AtomicUsize, usize, arithmetic);
+
+    /// A single-threaded stand-in for `AtomicPtr`.
+    pub struct AtomicPtr<T> {
+        /// The inner pointer.
+        inner: atomic::AtomicPtr<T>,
+    }
+
+    impl<T> AtomicPtr<T> {
+        /// Create a new pointer.
+        #[inline]
+        pub const fn new(ptr: *mut T) -> AtomicPtr<T> {
+            AtomicPtr {
+                inner: atomic::AtomicPtr::new(ptr),
+            }
+        }
+
+        /// Load the pointer.
+        #[inline]
+        pub fn load(&self, _: Ordering) -> *mut T {
+            self.inner.load(RELAXED)
+        }
+
+        /// Store a pointer.
+        #[inline]
+        pub fn store(&self, ptr: *mut T, _: Ordering) {
+            self.inner.store(ptr, RELAXED);
+        }
+
+        /// Store a pointer, returning the old one.
+        #[inline]
+        pub fn swap(&self, ptr: *mut T, _: Ordering) -> *mut T {
+            let old = self.inner.load(RELAXED);
+            self.inner.store(ptr, RELAXED);
+            old
+        }
+
+        /// Store `new` if the pointer is `current`, returning the old pointer.
+        #[inline]
+        pub fn compare_and_swap(&self, current: *mut T, new: *mut T, _: Ordering) -> *mut T {
+            let old = self.inner.load(RELAXED);
+            if old == current {
+                self.inner.store(new, RELAXED);
+            }
+            old
+        }
+    }
+}
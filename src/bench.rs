@@ -0,0 +1,338 @@
+//! A micro-benchmark harness.
+//!
+//! This module is only available with the `bench` feature. It runs named scenarios against a
+//! fresh pool, timed with the monotonic clock of the shim, such that numbers can be taken without
+//! the benchmark framework of nightly Rust (or without `std` at all). The scenarios are driven by a
+//! seeded generator, so runs with the same seed do the same work.
+//!
+//! The pool is single-threaded, so patterns like the producer and consumer are modelled by the
+//! order of the operations. The benchmarks in `benches/` cover the global allocator with threads.
+
+use prelude::*;
+
+use core::{fmt, mem, ops};
+
+use shim::{config, syscalls};
+
+use allocator;
+use bookkeeper::{Allocator, Bookkeeper};
+use random::Rng;
+#[cfg(feature = "stats")]
+use stats;
+
+/// The size of the data buffer of the pool.
+const DATA_SIZE: usize = 8 << 20;
+/// The size of the metadata buffer of the pool.
+const META_SIZE: usize = 64 << 10;
+/// The size of the spare region, which fresh allocations of the pool are carved from.
+const SPARE_SIZE: usize = 1 << 20;
+/// The maximal number of live buffers.
+const MAX_LIVE: usize = 4096;
+
+/// A benchmark scenario.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scenario {
+    /// Churn of small buffers.
+    ///
+    /// Buffers of 16 to 256 bytes are allocated and freed at random, keeping up to 256 of them
+    /// live.
+    Churn,
+    /// The pattern of a queue between a producer and a consumer.
+    ///
+    /// Buffers of 32 to 1024 bytes are freed in the order they were allocated, 64 buffers behind
+    /// the allocations.
+    ProducerConsumer,
+    /// Fragmentation.
+    ///
+    /// Buffers of mixed sizes are allocated, every other of them is freed, and the holes are
+    /// refilled with larger buffers, which mostly don't fit them.
+    Fragmentation,
+    /// Growth by reallocation.
+    ///
+    /// Buffers are grown in turns, doubling from 16 bytes to 64 KiB.
+    ReallocGrowth,
+}
+
+/// Every scenario.
+pub const SCENARIOS: [Scenario; 4] = [
+    Scenario::Churn,
+    Scenario::ProducerConsumer,
+    Scenario::Fragmentation,
+    Scenario::ReallocGrowth,
+];
+
+impl Scenario {
+    /// Get the name of the scenario.
+    pub fn name(self) -> &'static str {
+        match self {
+            Scenario::Churn => "churn",
+            Scenario::ProducerConsumer => "producer-consumer",
+            Scenario::Fragmentation => "fragmentation",
+            Scenario::ReallocGrowth => "realloc-growth",
+        }
+    }
+
+    /// Run the scenario for about `iterations` operations.
+    ///
+    /// The buffers still live at the end are left in `live`. The number of operations is
+    /// returned.
+    fn run(self, pool: &mut BenchPool, live: &mut Vec<Block>, rng: &mut Rng, iterations: usize)
+           -> usize {
+        let mut ops = 0;
+
+        match self {
+            Scenario::Churn => for _ in 0..iterations {
+                if live.is_empty() || live.len() < 256 && rng.below(2) == 0 {
+                    keep(live, pool.alloc(16 + rng.below(241), Align::BUFFER));
+                } else {
+                    let n = rng.below(live.len());
+                    pool.free(take(live, n));
+                }
+
+                ops += 1;
+            },
+            Scenario::ProducerConsumer => {
+                for _ in 0..64 {
+                    keep(live, pool.alloc(32 + rng.below(993), Align::BUFFER));
+                    ops += 1;
+                }
+
+                for n in 0..iterations / 2 {
+                    // The oldest buffer is consumed, and its slot refilled by the producer.
+                    let slot = n % 64;
+                    pool.free(live[slot].pop());
+                    live[slot] = pool.alloc(32 + rng.below(993), Align::BUFFER);
+
+                    ops += 2;
+                }
+            },
+            Scenario::Fragmentation => for _ in 0..iterations / 512 + 1 {
+                for _ in 0..256 {
+                    keep(live, pool.alloc(16 + rng.below(2033), Align::BUFFER));
+                }
+                // Free every other buffer, leaving holes.
+                for n in 0..128 {
+                    let block = live[2 * n].pop();
+                    pool.free(block);
+                }
+                for _ in 0..128 {
+                    keep(live, pool.alloc(1024 + rng.below(2049), Align::BUFFER));
+                }
+                while let Some(block) = live.pop() {
+                    if !block.is_empty() {
+                        pool.free(block);
+                    }
+                }
+
+                ops += 768;
+            },
+            Scenario::ReallocGrowth => for _ in 0..iterations / 224 + 1 {
+                for _ in 0..16 {
+                    keep(live, pool.alloc(16, Align::BUFFER));
+                }
+                // Grow the buffers in turns, such that they get in the way of each other.
+                for shift in 5..17 {
+                    for n in 0..16 {
+                        let block = live[n].pop();
+                        live[n] = pool.realloc(block, 1 << shift, Align::BUFFER);
+                    }
+                }
+                while let Some(block) = live.pop() {
+                    pool.free(block);
+                }
+
+                ops += 224;
+            },
+        }
+
+        ops
+    }
+}
+
+/// A pool over a fixed buffer.
+///
+/// The pool grows its metadata through fresh allocations, which are carved from a spare region.
+/// When the spare region runs out, it panics.
+struct BenchPool {
+    /// The inner bookkeeper.
+    inner: Bookkeeper,
+    /// The spare region.
+    spare: Block,
+}
+
+impl ops::Deref for BenchPool {
+    type Target = Bookkeeper;
+
+    fn deref(&self) -> &Bookkeeper {
+        &self.inner
+    }
+}
+
+impl ops::DerefMut for BenchPool {
+    fn deref_mut(&mut self) -> &mut Bookkeeper {
+        &mut self.inner
+    }
+}
+
+impl Allocator for BenchPool {
+    fn alloc_fresh(&mut self, size: usize, align: Align) -> Block {
+        assert!(self.spare.fits(size, align),
+                "The spare region of the benchmark pool is exhausted.");
+
+        // The aligner is lost, as it is never handed to the pool.
+        let (_, spare) = self.spare.pop().align(align).unwrap();
+        let (res, spare) = spare.split(size);
+        self.spare = spare;
+
+        res
+    }
+}
+
+/// Add a buffer to the live buffers.
+fn keep(live: &mut Vec<Block>, block: Block) {
+    live.push(block).expect("Too many live buffers.");
+}
+
+/// Take the `n`th buffer out of the live buffers.
+///
+/// The last buffer takes its place.
+fn take(live: &mut Vec<Block>, n: usize) -> Block {
+    let last = live.len() - 1;
+    live.swap(n, last);
+
+    live.pop().unwrap()
+}
+
+/// The result of running a scenario.
+#[derive(Clone, Copy, Debug)]
+pub struct Report {
+    /// The scenario.
+    pub scenario: Scenario,
+    /// The number of operations (allocations, frees, and reallocations).
+    pub ops: usize,
+    /// The wall time, in nanoseconds.
+    pub nanos: u64,
+    /// The number of free blocks in the pool at the end of the scenario.
+    pub free_blocks: usize,
+    /// The number of free bytes in the pool at the end of the scenario.
+    pub free_bytes: usize,
+}
+
+impl Report {
+    /// Get the number of operations per second.
+    ///
+    /// If the clock is unavailable (it stands still), zero is returned.
+    pub fn ops_per_sec(&self) -> u64 {
+        if self.nanos == 0 {
+            0
+        } else {
+            self.ops as u64 * 1_000_000_000 / self.nanos
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<18} {:>10} ops {:>12} ops/s {:>8} free blocks {:>10} free bytes",
+               self.scenario.name(), self.ops, self.ops_per_sec(), self.free_blocks,
+               self.free_bytes)
+    }
+}
+
+/// Run a scenario for about `iterations` operations against a fresh pool.
+///
+/// The pool is carved out of a single buffer from the global allocator, which is freed
+/// afterwards.
+pub fn run(scenario: Scenario, seed: usize, iterations: usize) -> Report {
+    let size = META_SIZE + MAX_LIVE * mem::size_of::<Block>() + SPARE_SIZE + DATA_SIZE;
+    let ptr = allocator::alloc(size, 4096);
+
+    let report = unsafe {
+        // The buffer was just allocated, and is split into disjoint parts, which are dropped
+        // before it is freed.
+        let (meta, rest) = Block::from_raw_parts(Pointer::new(ptr), size).split(META_SIZE);
+        let (live, rest) = rest.split(MAX_LIVE * mem::size_of::<Block>());
+        let (spare, data) = rest.split(SPARE_SIZE);
+
+        let mut pool = BenchPool {
+            inner: Bookkeeper::new(Vec::from_raw_parts(meta, 0)),
+            spare: spare,
+        };
+        // Split the blocks like the allocator would.
+        pool.set_min_split_remainder(config::MIN_SPLIT_REMAINDER);
+        pool.free(data);
+        let mut live = Vec::from_raw_parts(live, 0);
+
+        let mut rng = Rng::new(seed);
+        let start = syscalls::clock_monotonic();
+        let ops = scenario.run(&mut pool, &mut live, &mut rng, iterations);
+        let nanos = syscalls::clock_monotonic() - start;
+
+        let report = Report {
+            scenario: scenario,
+            ops: ops,
+            nanos: nanos,
+            free_blocks: pool.len(),
+            free_bytes: pool.total_bytes(),
+        };
+
+        while let Some(block) = live.pop() {
+            pool.free(block);
+        }
+        pool.check();
+
+        report
+    };
+
+    unsafe { allocator::free(ptr, size); }
+
+    report
+}
+
+/// Allocate a small buffer through the global allocator.
+///
+/// This takes the fast path of `alloc` (when it is compiled in), and is exported unmangled, such
+/// that the generated code can be inspected with a disassembler.
+#[no_mangle]
+pub extern fn ralloc_bench_alloc_small(size: usize) -> *mut u8 {
+    allocator::alloc(size, 8)
+}
+
+/// Run every scenario for about `iterations` operations, writing a report to `w`.
+///
+/// With the `stats` feature, the statistics of the allocator follow.
+pub fn run_all<W: fmt::Write>(w: &mut W, seed: usize, iterations: usize) -> fmt::Result {
+    writeln!(w, "ralloc benchmarks (seed {}, {} iterations):", seed, iterations)?;
+    for &scenario in SCENARIOS.iter() {
+        writeln!(w, "  {}", run(scenario, seed, iterations))?;
+    }
+
+    #[cfg(feature = "stats")]
+    stats::write_report(w)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bench_smoke() {
+        extern crate std;
+
+        use self::std::string::String;
+
+        let mut report = String::new();
+        run_all(&mut report, 7, 1000).unwrap();
+
+        for &scenario in SCENARIOS.iter() {
+            assert!(report.contains(scenario.name()));
+        }
+
+        // The same seed does the same work.
+        let a = run(Scenario::Fragmentation, 7, 1000);
+        let b = run(Scenario::Fragmentation, 7, 1000);
+        assert_eq!((a.ops, a.free_blocks, a.free_bytes), (b.ops, b.free_blocks, b.free_bytes));
+    }
+}
@@ -3,12 +3,56 @@
 //! Blocks are the main unit for the memory bookkeeping. A block is a simple construct with a
 //! `Pointer` pointer and a size. Occupied (non-free) blocks are represented by a zero-sized block.
 
-// TODO: Check the allow(cast_possible_wrap)s again.
-
 use prelude::*;
 
 use core::{ptr, cmp, mem, fmt};
 
+use ptr::MAX_BLOCK;
+
+use {conf, log};
+
+/// The number of generations of blocks (see `Block::generation`).
+///
+/// The generation lives in the high bits of the size, which sizes never reach, since blocks are
+/// bounded by `MAX_BLOCK`. On 32-bit targets, blocks can span half of the address space, leaving
+/// no bits to spare, so every block is of generation zero.
+#[cfg(target_pointer_width = "64")]
+pub const GENERATIONS: usize = 1 << 8;
+/// The number of generations of blocks (see `Block::generation`).
+///
+/// On 32-bit targets, there are no bits to spare in the size.
+#[cfg(not(target_pointer_width = "64"))]
+pub const GENERATIONS: usize = 1;
+/// The position of the generation in the size field.
+#[cfg(target_pointer_width = "64")]
+const GENERATION_SHIFT: usize = 56;
+/// The bits of the size field holding the size.
+#[cfg(target_pointer_width = "64")]
+const SIZE_MASK: usize = (1 << GENERATION_SHIFT) - 1;
+/// The bits of the size field holding the size.
+#[cfg(not(target_pointer_width = "64"))]
+const SIZE_MASK: usize = !0;
+
+/// The outcome of fitting a buffer and its header into a block (see `Block::fits_layout`).
+///
+/// Callers match every variant, such that a new way of failing cannot slip through unhandled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FitResult {
+    /// The buffer fits, starting at this offset from the start of the block.
+    Fits(usize),
+    /// The block ends before the first address aligned to the alignment.
+    Unaligned,
+    /// The block is too small after the aligner.
+    TooSmall {
+        /// The bytes needed after the aligner, header padding included.
+        needed: usize,
+        /// The bytes left after the aligner.
+        available: usize,
+    },
+    /// The padded header and the buffer together overflow `usize`.
+    Overflow,
+}
+
 /// A contiguous memory block.
 ///
 /// This provides a number of guarantees,
@@ -28,6 +72,8 @@ use core::{ptr, cmp, mem, fmt};
 #[must_use]
 pub struct Block {
     /// The size of this block, in bytes.
+    ///
+    /// On 64-bit targets, the high bits (above `MAX_BLOCK`) hold the generation of the block.
     size: usize,
     /// The pointer to the start of this block.
     ptr: Pointer<u8>,
@@ -35,8 +81,17 @@ pub struct Block {
 
 impl Block {
     /// Construct a block from its raw parts (pointer and size).
+    ///
+    /// # Panics
+    ///
+    /// In debug mode, this panics if `size` exceeds `MAX_BLOCK`, as the block couldn't be offset
+    /// through. This is on every path, so release builds rely on the sizes being checked at the
+    /// entry points (see `allocator::check_size`).
     #[inline]
     pub unsafe fn from_raw_parts(ptr: Pointer<u8>, size: usize) -> Block {
+        debug_assert!(size <= MAX_BLOCK, "The block of {} bytes is larger than the largest \
+                      block.", size);
+
         Block {
             size: size,
             ptr: ptr,
@@ -61,18 +116,30 @@ impl Block {
 
     /// Create an empty block representing the right edge of this block
     #[inline]
-    #[allow(cast_possible_wrap)]
     pub fn empty_right(&self) -> Block {
-        Block {
-            size: 0,
-            ptr: unsafe {
-                // LAST AUDIT: 2016-08-21 (Ticki).
+        Block::empty(self.end())
+    }
 
-                // By the invariants of this type (the end is addressable), this conversion isn't
-                // overflowing.
-                self.ptr.clone().offset(self.size as isize)
-            },
-        }
+    /// Get the pointer to the start of this block.
+    #[inline]
+    pub fn start(&self) -> &Pointer<u8> {
+        &self.ptr
+    }
+
+    /// Get the pointer to the end of this block.
+    #[inline]
+    pub fn end(&self) -> Pointer<u8> {
+        // By the invariants of this type, the end is addressable.
+        self.ptr.clone().checked_offset_bytes(self.size())
+            .expect("The block overflows the address space.")
+    }
+
+    /// Compare the address of this block with a bare pointer.
+    ///
+    /// This is the order of `Ord`, without an empty block to compare against.
+    #[inline]
+    pub fn cmp_ptr(&self, ptr: &Pointer<u8>) -> cmp::Ordering {
+        self.ptr.cmp(ptr)
     }
 
     /// Merge this block with a block to the right.
@@ -89,28 +156,215 @@ impl Block {
         } else if self.left_to(block) {
             // Since the end of `block` is bounded by the address space, adding them cannot
             // overflow.
-            self.size += block.pop().size;
+            let size = block.pop().size();
+            debug_assert!(self.size().checked_add(size).map_or(false, |x| x <= MAX_BLOCK),
+                          "Merging {:?} overflows.", self);
+            self.size += size;
             // We pop it to make sure it isn't aliased.
 
             Ok(())
         } else { Err(()) }
     }
 
+    /// Merge a run of blocks to the right into this block.
+    ///
+    /// The blocks of `iter` are merged (and emptied) one by one, as long as they are adjacent to
+    /// this block. The merging stops at the first non-adjacent block, which is taken from the
+    /// iterator, but left intact. Empty blocks are passed over, like in `merge_right`.
+    ///
+    /// The number of (non-empty) blocks absorbed is returned.
+    pub fn try_merge_chain<'a, I>(&mut self, iter: I) -> usize
+        where I: IntoIterator<Item = &'a mut Block> {
+        let mut absorbed = 0;
+
+        for block in iter {
+            if block.is_empty() {
+                continue;
+            }
+
+            if self.merge_right(block).is_err() {
+                break;
+            }
+            absorbed += 1;
+        }
+
+        absorbed
+    }
+
     /// Is this block empty/free?
     #[inline]
     pub fn is_empty(&self) -> bool {
-        self.size == 0
+        self.size() == 0
     }
 
     /// Get the size of the block.
     pub fn size(&self) -> usize {
-        self.size
+        self.size & SIZE_MASK
+    }
+
+    /// Get the generation of the block.
+    ///
+    /// This is a coarse timestamp of the last use of a free block, which the bookkeeper sets when
+    /// the block is freed. Blocks start out in generation zero, and keep their generation when
+    /// split or aligned, and the generation of the left block when merged. It is below
+    /// `GENERATIONS`.
+    #[cfg(target_pointer_width = "64")]
+    #[inline]
+    pub fn generation(&self) -> usize {
+        self.size >> GENERATION_SHIFT
+    }
+
+    /// Get the generation of the block.
+    ///
+    /// On 32-bit targets, this is always zero.
+    #[cfg(not(target_pointer_width = "64"))]
+    #[inline]
+    pub fn generation(&self) -> usize {
+        0
     }
 
+    /// Set the generation of the block.
+    ///
+    /// The generation is taken modulo `GENERATIONS`.
+    #[cfg(target_pointer_width = "64")]
+    #[inline]
+    pub fn set_generation(&mut self, generation: usize) {
+        self.size = self.size() | (generation % GENERATIONS) << GENERATION_SHIFT;
+    }
+
+    /// Set the generation of the block.
+    ///
+    /// On 32-bit targets, this does nothing.
+    #[cfg(not(target_pointer_width = "64"))]
+    #[inline]
+    pub fn set_generation(&mut self, _generation: usize) {}
+
     /// Is this block aligned to `align`?
     #[inline]
-    pub fn aligned_to(&self, align: usize) -> bool {
-        *self.ptr as usize % align == 0
+    pub fn aligned_to(&self, align: Align) -> bool {
+        align.is_aligned(self.ptr.addr())
+    }
+
+    /// Is this block aligned to the page size?
+    #[inline]
+    pub fn is_page_aligned(&self) -> bool {
+        self.aligned_to(Align::page())
+    }
+
+    /// Get the largest range of whole pages within this block.
+    ///
+    /// The range is given as the offsets (start and end) from the start of the block. If the block
+    /// holds no whole page, `None` is returned.
+    #[inline]
+    pub fn page_trimmed(&self) -> Option<(usize, usize)> {
+        self.trimmed_to(Align::page())
+    }
+
+    /// Get the pages spanned by this block.
+    ///
+    /// The address of the first page touched by the block and the number of pages touched are
+    /// returned.
+    #[inline]
+    pub fn page_span(&self) -> (usize, usize) {
+        self.span_of(Align::page())
+    }
+
+    /// Get the largest range of whole `align` sized chunks within this block.
+    ///
+    /// This is `page_trimmed` for any page size.
+    pub fn trimmed_to(&self, align: Align) -> Option<(usize, usize)> {
+        let start = align.padding(self.ptr.addr());
+        // A block ending at the top of the address space has its end one past the last address,
+        // so the sum saturates, which only rounds off the last chunk.
+        let end = (self.ptr.addr().saturating_add(self.size()) & !align.mask())
+            .wrapping_sub(self.ptr.addr());
+
+        // The end offset wraps, if the end is rounded below the start of the block.
+        if start < end && end <= self.size() {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    /// Get the `align` sized chunks spanned by this block.
+    ///
+    /// This is `page_span` for any page size.
+    pub fn span_of(&self, align: Align) -> (usize, usize) {
+        let first = self.ptr.addr() & !align.mask();
+
+        if self.is_empty() {
+            (first, 0)
+        } else {
+            // Count to the last byte, such that blocks at the top of the address space don't
+            // overflow.
+            let last = (self.ptr.addr() + (self.size() - 1)) & !align.mask();
+            (first, (last - first) / align.get() + 1)
+        }
+    }
+
+    /// Where would a buffer of `size` bytes aligned to `align`, preceded by a header of `header`
+    /// bytes, go in this block?
+    ///
+    /// The header starts at the first aligned address of the block, and the buffer at the first
+    /// aligned address after the header. On success, the offset of the buffer from the start of
+    /// the block is returned.
+    #[inline]
+    pub fn fits_layout(&self, size: usize, align: Align, header: usize) -> FitResult {
+        // Calculate the aligner (see `align`).
+        let aligner = self.ptr.align_offset(align);
+        if aligner >= self.size() {
+            return FitResult::Unaligned;
+        }
+
+        let padding = match align.round_up(header) {
+            Some(padding) => padding,
+            None => return FitResult::Overflow,
+        };
+        let needed = match padding.checked_add(size) {
+            Some(needed) => needed,
+            None => return FitResult::Overflow,
+        };
+
+        let available = self.size() - aligner;
+        if available < needed {
+            FitResult::TooSmall {
+                needed: needed,
+                available: available,
+            }
+        } else {
+            FitResult::Fits(aligner + padding)
+        }
+    }
+
+    /// Can this block hold `size` bytes aligned to `align`?
+    ///
+    /// This holds if and only if `align` would succeed and the aligned block would be at least
+    /// `size` bytes.
+    #[inline]
+    pub fn fits(&self, size: usize, align: Align) -> bool {
+        match self.fits_layout(size, align, 0) {
+            FitResult::Fits(_) => true,
+            FitResult::Unaligned | FitResult::TooSmall { .. } | FitResult::Overflow => false,
+        }
+    }
+
+    /// Can this block hold `size` bytes aligned to `align`, without leaving a fragment smaller
+    /// than `min` bytes?
+    ///
+    /// This is `fits`, where furthermore both the aligner and the rest after the `size` bytes are
+    /// either empty or at least `min` bytes.
+    #[inline]
+    pub fn fits_cleanly(&self, size: usize, align: Align, min: usize) -> bool {
+        match self.fits_layout(size, align, 0) {
+            // Without a header, the offset is the aligner.
+            FitResult::Fits(aligner) => (aligner == 0 || aligner >= min) && {
+                // The block fits, so this cannot underflow.
+                let rest = self.size() - aligner - size;
+                rest == 0 || rest >= min
+            },
+            FitResult::Unaligned | FitResult::TooSmall { .. } | FitResult::Overflow => false,
+        }
     }
 
     /// memcpy the block to another pointer.
@@ -123,30 +377,117 @@ impl Block {
         log!(INTERNAL, "Copying {:?} to {:?}", *self, *block);
 
         // Bound check.
-        assert!(self.size <= block.size, "Block too small.");
+        assert!(self.size() <= block.size(), "Block too small.");
 
         unsafe {
             // LAST AUDIT: 2016-08-21 (Ticki).
 
             // From the invariants of `Block`, this copy is well-defined.
-            ptr::copy_nonoverlapping(*self.ptr, *block.ptr, self.size);
+            ptr::copy_nonoverlapping(*self.ptr, *block.ptr, self.size());
         }
     }
 
-    /// Volatile zero this memory if the `security` feature is set.
+    /// memmove `count` bytes at offset `src` to offset `dest` within the block.
+    ///
+    /// The two ranges may overlap.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if either range is out of bound.
+    #[inline]
+    #[allow(cast_possible_wrap)]
+    pub fn copy_within(&mut self, src: usize, dest: usize, count: usize) {
+        log!(INTERNAL, "Moving {} bytes from {} to {} in {:?}", count, src, dest, *self);
+
+        // Bound check.
+        assert!(cmp::max(src, dest).checked_add(count).map_or(false, |end| end <= self.size()),
+                "Move out of bound.");
+
+        unsafe {
+            // Both ranges are within the block, so this copy is well-defined (and the offsets
+            // don't wrap, as blocks are addressable). `ptr::copy` handles the overlap.
+            ptr::copy(*self.ptr.clone().offset_bytes(src), *self.ptr.clone().offset_bytes(dest),
+                      count);
+        }
+    }
+
+    /// Volatile zero this memory if zero-on-free is enabled.
+    ///
+    /// Zero-on-free is enabled by default with the `security` feature, and can be toggled at
+    /// runtime (see `conf::set_zero_on_free`).
+    #[inline]
     pub fn sec_zero(&mut self) {
+        if conf::zero_on_free() {
+            self.wipe();
+        }
+    }
+
+    /// Volatile zero this memory unconditionally.
+    ///
+    /// This is used for metadata, which is always wiped regardless of the zero-on-free policy.
+    pub fn wipe(&mut self) {
         use core::intrinsics;
 
-        if cfg!(feature = "security") {
-            log!(INTERNAL, "Zeroing {:?}", *self);
+        log!(INTERNAL, "Zeroing {:?}", *self);
+
+        unsafe {
+            // LAST AUDIT: 2016-08-21 (Ticki).
+
+            // Since the memory of the block is inaccessible (read-wise), zeroing it is fully
+            // safe.
+            intrinsics::volatile_set_memory(*self.ptr, 0, self.size());
+        }
+    }
+
+    /// Fill this memory with `byte`.
+    ///
+    /// This is the plain counterpart of `wipe`, meant for patterns, which are checked later (see
+    /// `verify_fill`), rather than secrets, so the writes may be optimized.
+    pub fn fill(&mut self, byte: u8) {
+        unsafe {
+            // By the invariants of `Block`, the memory is owned by the block.
+            ptr::write_bytes(*self.ptr, byte, self.size());
+        }
+    }
+
+    /// Check that every byte of this memory is `byte`.
+    ///
+    /// If not, the offset of the first mismatching byte is returned. The bulk of the block is
+    /// compared a word at a time.
+    pub fn verify_fill(&self, byte: u8) -> Result<(), usize> {
+        /// The size of a word.
+        const WORD: usize = mem::size_of::<usize>();
+
+        let at = |i: usize| unsafe {
+            // The offsets are within the block.
+            *self.ptr.clone().offset_bytes(i) as *const u8
+        };
+        // The byte repeated over a word.
+        let pattern = !0 / 0xFF * byte as usize;
+
+        unsafe {
+            // By the invariants of `Block`, the memory is owned by the block, and the words read
+            // are aligned and within it.
+            let mismatch = |from: usize, to: usize| {
+                (from..to).find(|&i| *at(i) != byte).map_or(Ok(()), Err)
+            };
 
-            unsafe {
-                // LAST AUDIT: 2016-08-21 (Ticki).
+            // The bytes before the first aligned word.
+            let head = cmp::min(self.ptr.align_offset(Align::of::<usize>()), self.size());
+            mismatch(0, head)?;
 
-                // Since the memory of the block is inaccessible (read-wise), zeroing it is fully
-                // safe.
-                intrinsics::volatile_set_memory(*self.ptr, 0, self.size);
+            let mut i = head;
+            while i + WORD <= self.size() {
+                if *(at(i) as *const usize) != pattern {
+                    // Find the byte in the word.
+                    return mismatch(i, i + WORD);
+                }
+
+                i += WORD;
             }
+
+            // The bytes after the last aligned word.
+            mismatch(i, self.size())
         }
     }
 
@@ -155,14 +496,33 @@ impl Block {
     /// This marks it as free, and returns the old value.
     #[inline]
     pub fn pop(&mut self) -> Block {
-        unborrow!(mem::replace(self, Block::empty(self.ptr.clone())))
+        let ptr = self.ptr.clone();
+        mem::replace(self, Block::empty(ptr))
     }
 
     /// Is this block placed left to the given other block?
+    ///
+    /// A block is never left to itself (an empty block included).
+    ///
+    /// In debug builds, asking this of two non-empty blocks in the wrong order (`self` starting
+    /// at or after `to`) logs a warning, as it hints at a bug in the caller. With the
+    /// `strict_checks` feature, it panics instead.
     #[inline]
     pub fn left_to(&self, to: &Block) -> bool {
-        // This won't overflow due to the end being bounded by the address space.
-        self.size + *self.ptr as usize == *to.ptr as usize
+        if self.ptr == to.ptr && self.size() == to.size() {
+            return false;
+        }
+
+        #[cfg(debug_assertions)]
+        {
+            if !self.is_empty() && !to.is_empty() && self.ptr.addr() >= to.ptr.addr() {
+                out_of_order(self, to);
+            }
+        }
+
+        // A corrupt block could wrap around the address space, in which case it is never left to
+        // anything.
+        self.ptr.distance_to(&to.ptr) == Some(self.size())
     }
 
     /// Split the block at some position.
@@ -171,24 +531,23 @@ impl Block {
     ///
     /// Panics if `pos` is out of bound.
     #[inline]
-    #[allow(cast_possible_wrap)]
     pub fn split(self, pos: usize) -> (Block, Block) {
-        assert!(pos <= self.size, "Split {} out of bound (size is {})!", pos, self.size);
+        assert!(pos <= self.size(), "Split {} out of bound (size is {})!", pos, self.size());
+
+        // Both halves keep the generation.
+        let generation = self.size & !SIZE_MASK;
 
         (
             Block {
-                size: pos,
+                size: pos | generation,
                 ptr: self.ptr.clone(),
             },
             Block {
-                size: self.size - pos,
-                ptr: unsafe {
-                    // LAST AUDIT: 2016-08-21 (Ticki).
-
-                    // This won't overflow due to the assertion above, ensuring that it is bounded
-                    // by the address space. See the `split_at_mut` source from libcore.
-                    self.ptr.offset(pos as isize)
-                },
+                size: self.size() - pos | generation,
+                // This won't overflow due to the assertion above, ensuring that it is bounded by the
+                // address space.
+                ptr: self.ptr.checked_offset_bytes(pos)
+                    .expect("Splitting overflows the address space."),
             }
         )
     }
@@ -197,8 +556,7 @@ impl Block {
     ///
     /// Returns an `None` holding the intact block if `align` is out of bounds.
     #[inline]
-    #[allow(cast_possible_wrap)]
-    pub fn align(&mut self, align: usize) -> Option<(Block, Block)> {
+    pub fn align(&mut self, align: Align) -> Option<(Block, Block)> {
         // Logging.
         log!(INTERNAL, "Padding {:?} to align {}", self, align);
 
@@ -207,30 +565,27 @@ impl Block {
 
         // Calculate the aligner, which defines the smallest size required as precursor to align
         // the block to `align`.
-        let aligner = (align - *self.ptr as usize % align) % align;
-        //                                                 ^^^^^^^^
-        // To avoid wasting space on the case where the block is already aligned, we calculate it
-        // modulo `align`.
+        // No space is wasted, when the block is already aligned.
+        let aligner = self.ptr.align_offset(align);
 
         // Bound check.
-        if aligner < self.size {
+        if aligner < self.size() {
             // Invalidate the old block.
             let old = self.pop();
+            // Both parts keep the generation.
+            let generation = old.size & !SIZE_MASK;
 
             Some((
                 Block {
-                    size: aligner,
+                    size: aligner | generation,
                     ptr: old.ptr.clone(),
                 },
                 Block {
-                    size: old.size - aligner,
-                    ptr: unsafe {
-                        // LAST AUDIT: 2016-08-21 (Ticki).
-
-                        // The aligner is bounded by the size, which itself is bounded by the
-                        // address space. Therefore, this conversion cannot overflow.
-                        old.ptr.offset(aligner as isize)
-                    },
+                    size: old.size() - aligner | generation,
+                    // The aligner is bounded by the size, which itself is bounded by the address
+                    // space. Therefore, this cannot overflow.
+                    ptr: old.ptr.checked_offset_bytes(aligner)
+                        .expect("Aligning overflows the address space."),
                 }
             ))
         } else {
@@ -245,10 +600,14 @@ impl Block {
     ///
     /// The debugger might do things like memleak and use-after-free checks. This methods informs
     /// the debugger that this block is freed.
+    ///
+    /// With the `sanitize` feature, the block is poisoned as well.
     #[inline]
     pub fn mark_free(self) -> Block {
         #[cfg(feature = "debugger")]
-        ::shim::debug::mark_free(*self.ptr as *const u8, self.size);
+        ::shim::debug::mark_free(*self.ptr as *const u8, self.size());
+        #[cfg(feature = "sanitize")]
+        ::shim::asan::poison(*self.ptr as *const u8, self.size());
 
         self
     }
@@ -256,10 +615,14 @@ impl Block {
     /// Mark this block uninitialized to the debugger.
     ///
     /// To detect use-after-free, the allocator need to mark
+    ///
+    /// With the `sanitize` feature, the block is unpoisoned as well.
     #[inline]
     pub fn mark_uninitialized(self) -> Block {
         #[cfg(feature = "debugger")]
-        ::shim::debug::mark_unintialized(*self.ptr as *const u8, self.size);
+        ::shim::debug::mark_undefined(*self.ptr as *const u8, self.size());
+        #[cfg(feature = "sanitize")]
+        ::shim::asan::unpoison(*self.ptr as *const u8, self.size());
 
         self
     }
@@ -271,6 +634,23 @@ impl From<Block> for Pointer<u8> {
     }
 }
 
+/// Report two non-empty blocks compared by `left_to` in the wrong order.
+///
+/// This panics with the `strict_checks` feature, and warns otherwise.
+#[cfg(debug_assertions)]
+#[cold]
+#[allow(unused_variables)]
+fn out_of_order(left: &Block, right: &Block) {
+    #[cfg(test)]
+    test::OUT_OF_ORDER.fetch_add(1, ::core::sync::atomic::Ordering::Relaxed);
+
+    #[cfg(feature = "strict_checks")]
+    panic!("Checking whether {:?} is left to {:?}, which it starts after.", left, right);
+
+    #[cfg(not(feature = "strict_checks"))]
+    log!(WARNING, "Checking whether {:?} is left to {:?}, which it starts after.", left, right);
+}
+
 /// Compare the blocks address.
 impl PartialOrd for Block {
     #[inline]
@@ -298,7 +678,11 @@ impl cmp::Eq for Block {}
 
 impl fmt::Debug for Block {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "0x{:x}[{}]", *self.ptr as usize, self.size)
+        // Blocks are in most log lines, so this skips the formatting machinery.
+        log::fmt_hex_addr(self.ptr.addr(), f)?;
+        f.write_str("[")?;
+        log::fmt_size(self.size(), f)?;
+        f.write_str("]")
     }
 }
 
@@ -306,6 +690,38 @@ impl fmt::Debug for Block {
 mod test {
     use prelude::*;
 
+    use core::cmp::Ordering;
+    use core::sync::atomic::{self, AtomicUsize};
+
+    /// The number of `left_to` checks of blocks in the wrong order.
+    pub static OUT_OF_ORDER: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn test_largest_block() {
+        use ptr::MAX_BLOCK;
+
+        // Only the size is checked, so the synthetic block needn't be backed by memory.
+        let block = unsafe { Block::from_raw_parts(Pointer::new(0x1000 as *mut u8), MAX_BLOCK) };
+        assert_eq!(block.size(), MAX_BLOCK);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "larger than the largest block")]
+    fn test_oversize_block() {
+        use ptr::MAX_BLOCK;
+
+        let _ = unsafe { Block::from_raw_parts(Pointer::new(0x1000 as *mut u8), MAX_BLOCK + 1) };
+    }
+
+    #[test]
+    #[cfg(all(target_pointer_width = "32", debug_assertions))]
+    #[should_panic(expected = "larger than the largest block")]
+    fn test_oversize_block_32() {
+        // 3 GiB would be a negative offset.
+        let _ = unsafe { Block::from_raw_parts(Pointer::new(0x1000 as *mut u8), 3 << 30) };
+    }
+
     #[test]
     fn test_array() {
         let arr = b"Lorem ipsum dolor sit amet";
@@ -321,9 +737,141 @@ mod test {
 
         assert_eq!(lorem, lorem);
         assert!(!rest.is_empty());
-        assert!(lorem.align(2).unwrap().1.aligned_to(2));
-        assert!(rest.align(15).unwrap().1.aligned_to(15));
-        assert_eq!(*Pointer::from(lorem) as usize + 5, *Pointer::from(rest) as usize);
+        let two = Align::new(2).unwrap();
+        let sixteen = Align::new(16).unwrap();
+        assert!(lorem.align(two).unwrap().1.aligned_to(two));
+        assert!(rest.align(sixteen).unwrap().1.aligned_to(sixteen));
+        assert_eq!(Pointer::from(lorem).distance_to(&Pointer::from(rest)), Some(5));
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_generation() {
+        use block::GENERATIONS;
+
+        let arr = [0u8; 64];
+        let mut block = unsafe {
+            Block::from_raw_parts(Pointer::new(arr.as_ptr() as *mut u8), arr.len())
+        };
+        assert_eq!(block.generation(), 0);
+
+        // The generation is kept apart from the size.
+        block.set_generation(GENERATIONS + 7);
+        assert_eq!(block.generation(), 7);
+        assert_eq!(block.size(), 64);
+        block.set_generation(GENERATIONS - 1);
+        assert_eq!(block.generation(), GENERATIONS - 1);
+        assert_eq!(block.size(), 64);
+        assert_eq!(block.end().addr(), arr.as_ptr() as usize + 64);
+
+        // Splitting and aligning keep it...
+        block.set_generation(7);
+        let (mut left, mut right) = block.split(5);
+        assert_eq!((left.generation(), left.size()), (7, 5));
+        assert_eq!((right.generation(), right.size()), (7, 59));
+        let (mut aligner, mut rest) = right.align(Align::new(16).unwrap()).unwrap();
+        assert_eq!(aligner.generation(), 7);
+        assert_eq!(rest.generation(), 7);
+        assert_eq!(aligner.size() + rest.size(), 59);
+
+        // ...and merging keeps the one of the left block.
+        rest.set_generation(9);
+        left.merge_right(&mut aligner).unwrap();
+        left.merge_right(&mut rest).unwrap();
+        assert_eq!((left.generation(), left.size()), (7, 64));
+    }
+
+    #[test]
+    fn test_pop() {
+        let arr = b"Lorem ipsum";
+        let mut block = unsafe {
+            Block::from_raw_parts(Pointer::new(arr.as_ptr() as *mut u8), arr.len())
+        };
+        let left = block.empty_left();
+
+        // The old block is returned, and an empty block is left at the same address.
+        let old = block.pop();
+        assert_eq!(old.size(), arr.len());
+        assert_eq!(old, left);
+        assert!(block.is_empty());
+        assert_eq!(block, left);
+
+        // Popping an empty block gives an empty block.
+        let again = block.pop();
+        assert!(again.is_empty());
+        assert_eq!(again, left);
+    }
+
+    #[test]
+    fn test_merge_right() {
+        let arr = b"Lorem ipsum dolor sit amet";
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new(arr.as_ptr() as *mut u8), arr.len())
+        };
+        let (mut a, rest) = block.split(5);
+        let (mut b, mut c) = rest.split(5);
+
+        // Non-adjacent blocks are left intact.
+        assert!(a.merge_right(&mut c).is_err());
+        assert_eq!((a.size(), c.size()), (5, 16));
+
+        // Adjacent blocks are merged, and the right one is emptied.
+        assert!(a.merge_right(&mut b).is_ok());
+        assert_eq!(a.size(), 10);
+        assert!(b.is_empty());
+
+        // Empty blocks merge with anything.
+        let mut empty = c.empty_right();
+        assert!(a.merge_right(&mut empty).is_ok());
+        assert_eq!(a.size(), 10);
+
+        assert!(a.merge_right(&mut c).is_ok());
+        assert_eq!(a.size(), arr.len());
+        assert!(c.is_empty());
+    }
+
+    #[test]
+    fn test_try_merge_chain() {
+        let arr = [0u8; 64];
+        let block = |start: usize, end: usize| unsafe {
+            Block::from_raw_parts(Pointer::new(arr.as_ptr().offset(start as isize) as *mut u8),
+                                  end - start)
+        };
+
+        // Runs as `(start, end)`, with the expected number of absorbed blocks and the final size.
+        let cases: [(&[(usize, usize)], usize, usize); 5] = [
+            (&[(8, 16), (16, 24), (24, 32)], 3, 32),
+            (&[(8, 16), (20, 24), (24, 32)], 1, 16),
+            (&[(9, 16), (16, 24)], 0, 8),
+            (&[(8, 16), (16, 16), (16, 24), (32, 40)], 2, 24),
+            (&[], 0, 8),
+        ];
+
+        for &(run, absorbed, size) in &cases {
+            let mut blocks = [block(0, 0), block(0, 0), block(0, 0), block(0, 0)];
+            for (place, &(start, end)) in blocks.iter_mut().zip(run) {
+                *place = block(start, end);
+            }
+
+            let mut a = block(0, 8);
+            assert_eq!(a.try_merge_chain(blocks[..run.len()].iter_mut()), absorbed);
+            assert_eq!(a.size(), size);
+
+            // The absorbed blocks are emptied, and the rest is left intact.
+            let mut left = absorbed;
+            for (x, &(start, end)) in blocks.iter().zip(run) {
+                if start == end {
+                    continue;
+                }
+
+                if left > 0 {
+                    assert!(x.is_empty());
+                    left -= 1;
+                } else {
+                    assert_eq!(x.size(), end - start);
+                }
+            }
+        }
     }
 
     #[test]
@@ -341,6 +889,98 @@ mod test {
         lorem.split(2).0.merge_right(&mut tmp).unwrap();
     }
 
+    #[test]
+    fn test_copy_within() {
+        let mut arr = *b"0123456789";
+        let mut block = unsafe {
+            Block::from_raw_parts(Pointer::new(arr.as_mut_ptr()), arr.len())
+        };
+
+        // Overlapping moves in both directions.
+        block.copy_within(4, 2, 6);
+        assert_eq!(&arr, b"0145678989");
+
+        let mut block = unsafe {
+            Block::from_raw_parts(Pointer::new(arr.as_mut_ptr()), arr.len())
+        };
+        block.copy_within(0, 3, 7);
+        assert_eq!(&arr, b"0140145678");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_copy_within_oob() {
+        let mut arr = *b"lorem";
+        let mut block = unsafe {
+            Block::from_raw_parts(Pointer::new(arr.as_mut_ptr()), arr.len())
+        };
+
+        block.copy_within(0, 1, 5);
+    }
+
+    #[test]
+    fn test_page_helpers() {
+        // Simulate 4 KiB and 16 KiB pages.
+        for &page in &[4096, 16384] {
+            let align = Align::new(page).unwrap();
+            let base = 16 * page;
+
+            // The start offset, the size, the whole pages, and the number of pages spanned.
+            let table = [
+                (0, 0, None, 0),
+                (0, page - 1, None, 1),
+                (0, page, Some((0, page)), 1),
+                (0, page + 1, Some((0, page)), 2),
+                (1, page, None, 2),
+                (1, 2 * page, Some((page - 1, 2 * page - 1)), 3),
+                (page - 1, page + 2, Some((1, page + 1)), 3),
+                (3, 5 * page, Some((page - 3, 5 * page - 3)), 6),
+                (page, 3 * page, Some((0, 3 * page)), 3),
+                (page + 7, 9, None, 1),
+            ];
+
+            for &(start, size, trimmed, pages) in &table {
+                let block = unsafe {
+                    Block::from_raw_parts(Pointer::new((base + start) as *mut u8), size)
+                };
+
+                assert_eq!(block.trimmed_to(align), trimmed, "{} bytes at {}", size, start);
+                assert_eq!(block.span_of(align), ((base + start) & !align.mask(), pages));
+                assert_eq!(block.aligned_to(align), start % page == 0);
+
+                // The pages are within the block.
+                if let Some((a, b)) = trimmed {
+                    assert!(align.is_aligned(base + start + a));
+                    assert!(align.is_aligned(base + start + b));
+                    assert!(b <= size);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_page_span_top() {
+        // A block at the top of the address space.
+        let page = Align::page();
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new((0usize.wrapping_sub(page.get())) as *mut u8),
+                                  page.get() - 1)
+        };
+
+        assert_eq!(block.page_span().1, 1);
+        assert_eq!(block.page_trimmed(), None);
+        assert!(block.is_page_aligned());
+
+        // A block reaching the very end.
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new((0usize.wrapping_sub(3 * page.get())) as *mut u8),
+                                  3 * page.get())
+        };
+
+        assert_eq!(block.page_span().1, 3);
+        assert_eq!(block.page_trimmed(), Some((0, 2 * page.get())));
+    }
+
     #[test]
     #[should_panic]
     fn test_oob() {
@@ -367,6 +1007,124 @@ mod test {
         assert_eq!(arr, [0, 2, 0, 2, 255, 255]);
     }
 
+    #[test]
+    fn test_fits() {
+        let arr = b"Lorem ipsum dolor sit amet";
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new(arr.as_ptr() as *mut u8), arr.len())
+        };
+
+        assert!(block.fits(arr.len(), Align::MIN));
+        assert!(!block.fits(arr.len() + 1, Align::MIN));
+        assert!(block.fits(0, Align::MIN));
+        assert!(!block.empty_left().fits(0, Align::MIN));
+
+        for align in (0..6).map(|x| Align::new(1 << x).unwrap()) {
+            for size in 0..arr.len() + 1 {
+                let mut tmp = unsafe {
+                    Block::from_raw_parts(Pointer::new(arr.as_ptr() as *mut u8), arr.len())
+                };
+
+                // Compare it to the actual aligning.
+                assert_eq!(block.fits(size, align),
+                           tmp.align(align).map_or(false, |(_, x)| x.size() >= size));
+            }
+        }
+    }
+
+    #[test]
+    fn test_fits_layout() {
+        use block::FitResult;
+
+        let arr = [0u64; 8];
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new((arr.as_ptr() as *mut u8).offset(4)), 60)
+        };
+        let eight = Align::new(8).unwrap();
+
+        // The header is padded to the alignment, after the aligner.
+        assert_eq!(block.fits_layout(16, eight, 5), FitResult::Fits(12));
+        assert_eq!(block.fits_layout(48, eight, 0), FitResult::Fits(4));
+        assert_eq!(block.fits_layout(48, eight, 1), FitResult::Fits(12));
+        assert_eq!(block.fits_layout(49, eight, 1), FitResult::TooSmall {
+            needed: 57,
+            available: 56,
+        });
+        // The header cannot be padded.
+        assert_eq!(block.fits_layout(0, eight, !0), FitResult::Overflow);
+        assert_eq!(block.fits_layout(!0, eight, 1), FitResult::Overflow);
+
+        // The aligner takes the whole block.
+        let (block, _) = block.split(4);
+        assert_eq!(block.fits_layout(0, eight, 0), FitResult::Unaligned);
+    }
+
+    #[test]
+    fn test_fits_layout_brute_force() {
+        use block::FitResult;
+        use test_util::Rng;
+
+        let arr = [0u64; 64];
+        let base = arr.as_ptr() as usize;
+        let mut rng = Rng::new(0xF17);
+
+        for _ in 0..20000 {
+            let start = rng.below(256);
+            let len = rng.below(512 - start + 1);
+            let align = Align::new(1 << rng.below(7)).unwrap();
+            let size = rng.below(256);
+            let header = rng.below(96);
+
+            let block = unsafe {
+                Block::from_raw_parts(Pointer::new((base + start) as *mut u8), len)
+            };
+            let aligned = |x: usize| (base + start + x) % align.get() == 0;
+
+            // Search the header and the buffer byte by byte.
+            let expected = match (0..len).find(|&x| aligned(x)) {
+                None => FitResult::Unaligned,
+                Some(aligner) => {
+                    let offset = (aligner + header..).find(|&x| aligned(x)).unwrap();
+                    if offset + size <= len {
+                        FitResult::Fits(offset)
+                    } else {
+                        FitResult::TooSmall {
+                            needed: offset - aligner + size,
+                            available: len - aligner,
+                        }
+                    }
+                },
+            };
+
+            assert_eq!(block.fits_layout(size, align, header), expected,
+                       "{:?} with size {}, alignment {} and header {}.", block, size, align.get(),
+                       header);
+        }
+    }
+
+    #[test]
+    fn test_fits_cleanly() {
+        let arr = [0u64; 8];
+        let block = unsafe { Block::from_raw_parts(Pointer::new(arr.as_ptr() as *mut u8), 64) };
+        let eight = Align::new(8).unwrap();
+
+        // Exact fits and large rests are clean.
+        assert!(block.fits_cleanly(64, eight, 16));
+        assert!(block.fits_cleanly(48, eight, 16));
+        // Small rests are not.
+        assert!(!block.fits_cleanly(56, eight, 16));
+        assert!(block.fits_cleanly(56, eight, 8));
+        // Nor is anything, which doesn't fit.
+        assert!(!block.fits_cleanly(72, eight, 0));
+
+        // Small aligners are not clean either.
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new((arr.as_ptr() as *mut u8).offset(4)), 60)
+        };
+        assert!(!block.fits_cleanly(8, eight, 16));
+        assert!(block.fits_cleanly(8, eight, 4));
+    }
+
     #[test]
     fn test_empty_lr() {
         let arr = b"Lorem ipsum dolor sit amet";
@@ -379,4 +1137,363 @@ mod test {
         assert_eq!(*Pointer::from(block.empty_left()) as *const u8, arr.as_ptr());
         assert_eq!(block.empty_right(), block.split(arr.len()).1);
     }
+
+    #[test]
+    fn test_start_end() {
+        let arr = b"Lorem ipsum";
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new(arr.as_ptr() as *mut u8), arr.len())
+        };
+
+        assert_eq!(**block.start() as *const u8, arr.as_ptr());
+        assert_eq!(*block.end() as usize, arr.as_ptr() as usize + arr.len());
+        assert_eq!(block.cmp_ptr(block.start()), Ordering::Equal);
+        assert_eq!(block.cmp_ptr(&block.end()), Ordering::Less);
+        assert_eq!(block.empty_right().cmp_ptr(&block.end()), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_align_too_small() {
+        let arr = [0u64; 4];
+        let ptr = unsafe { (arr.as_ptr() as *mut u8).offset(1) };
+
+        // The aligner (7 bytes) exceeds the size, so the block must be left intact rather than
+        // wrapping around to a huge block.
+        let mut block = unsafe { Block::from_raw_parts(Pointer::new(ptr), 5) };
+        let eight = Align::new(8).unwrap();
+        assert!(block.align(eight).is_none());
+        assert_eq!(block.size(), 5);
+        assert!(!block.fits(0, eight));
+
+        // An aligner equal to the size leaves nothing.
+        let mut block = unsafe { Block::from_raw_parts(Pointer::new(ptr), 7) };
+        assert!(block.align(eight).is_none());
+        assert_eq!(block.size(), 7);
+
+        // One more byte is enough.
+        let mut block = unsafe { Block::from_raw_parts(Pointer::new(ptr), 8) };
+        let (pad, rest) = block.align(eight).unwrap();
+        assert_eq!((pad.size(), rest.size()), (7, 1));
+    }
+
+    #[test]
+    fn test_verify_fill() {
+        let mut arr = [0u64; 10];
+        let start = arr.as_mut_ptr() as *mut u8;
+
+        // Every size, both aligned and unaligned.
+        for offset in 0..2 {
+            for size in 0..65 {
+                let mut block = unsafe {
+                    Block::from_raw_parts(Pointer::new(start.offset(offset)), size)
+                };
+
+                block.fill(0xA5);
+                assert_eq!(block.verify_fill(0xA5), Ok(()));
+                if size == 0 {
+                    assert_eq!(block.verify_fill(0), Ok(()));
+                    continue;
+                }
+                assert_eq!(block.verify_fill(0xA4), Err(0));
+
+                // The first byte, the last byte, and the first word-aligned byte in the middle.
+                let middle = (size / 2..size).find(|&x| (offset as usize + x) % 8 == 0)
+                    .unwrap_or(size - 1);
+                for &pos in &[0, size - 1, middle] {
+                    unsafe { *start.offset(offset + pos as isize) = 0; }
+                    assert_eq!(block.verify_fill(0xA5), Err(pos));
+                    unsafe { *start.offset(offset + pos as isize) = 0xA5; }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_left_to() {
+        let arr = b"Lorem ipsum dolor sit amet";
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new(arr.as_ptr() as *mut u8), arr.len())
+        };
+        let (a, rest) = block.split(5);
+        let (b, c) = rest.split(5);
+        let empty = b.empty_left();
+
+        // None of these are out of order, so they would panic with strict checks.
+
+        // Adjacent blocks.
+        assert!(a.left_to(&b));
+        assert!(b.left_to(&c));
+        assert!(!a.left_to(&c));
+
+        // An empty block at the same address is left to the block, but not to itself.
+        assert!(empty.left_to(&b));
+        assert!(!empty.left_to(&empty));
+        assert!(!b.left_to(&empty));
+        assert!(!a.left_to(&a));
+    }
+
+    #[test]
+    #[cfg(all(debug_assertions, not(feature = "strict_checks")))]
+    fn test_left_to_reversed() {
+        let arr = b"Lorem ipsum dolor sit amet";
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new(arr.as_ptr() as *mut u8), arr.len())
+        };
+        let (a, b) = block.split(5);
+
+        // Other tests might warn meanwhile, so only the increase is checked.
+        let warnings = OUT_OF_ORDER.load(atomic::Ordering::SeqCst);
+        assert!(!b.left_to(&a));
+        assert!(OUT_OF_ORDER.load(atomic::Ordering::SeqCst) > warnings);
+    }
+
+    #[test]
+    #[should_panic(expected = "which it starts after")]
+    #[cfg(all(debug_assertions, feature = "strict_checks"))]
+    fn test_left_to_reversed_strict() {
+        let arr = b"Lorem ipsum dolor sit amet";
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new(arr.as_ptr() as *mut u8), arr.len())
+        };
+        let (a, b) = block.split(5);
+
+        b.left_to(&a);
+    }
+
+    #[test]
+    fn test_left_to_wrapping() {
+        // A block ending past the address space is never left to anything.
+        let block = unsafe { Block::from_raw_parts(Pointer::new(!0 as *mut u8), 2) };
+        let other = Block::empty(unsafe { Pointer::new(1 as *mut u8) });
+
+        assert!(!block.left_to(&other));
+    }
+}
+
+/// Property tests of the block algebra.
+///
+/// Random sequences of operations are applied to blocks tiling a buffer, while a shadow model of
+/// the `(address, size)` ranges tracks what the blocks should be. After every operation, the
+/// blocks must tile the buffer exactly (no overlap, no loss) and agree with the model.
+#[cfg(test)]
+mod block_ops {
+    extern crate std;
+
+    use prelude::*;
+
+    use self::std::vec::Vec;
+
+    use test_util::Rng;
+
+    /// The size of the tiled buffer.
+    const BUFFER: usize = 1024;
+
+    /// An operation on the blocks.
+    ///
+    /// The indices and positions are reduced modulo the current bounds when the operation is
+    /// applied, such that any sequence (in particular, any subsequence) is valid.
+    #[derive(Clone, Copy, Debug)]
+    enum Op {
+        /// Split a block at a position.
+        Split(usize, usize),
+        /// Merge a block into another block, which must fail unless it is its right neighbor.
+        MergeRight(usize, usize),
+        /// Merge a run of blocks into the block to their left.
+        MergeChain(usize, usize),
+        /// Align a block.
+        Align(usize, usize),
+    }
+
+    impl Op {
+        /// Generate a random operation.
+        fn random(rng: &mut Rng) -> Op {
+            let (a, b) = (rng.get(), rng.get());
+
+            match rng.below(4) {
+                0 => Op::Split(a, b),
+                1 => Op::MergeRight(a, b),
+                2 => Op::MergeChain(a, b),
+                _ => Op::Align(a, b),
+            }
+        }
+    }
+
+    /// Remove the empty blocks from the list, and the empty ranges from the model.
+    fn prune(blocks: &mut Vec<Block>, model: &mut Vec<(usize, usize)>) {
+        blocks.retain(|x| !x.is_empty());
+        model.retain(|&(_, size)| size != 0);
+    }
+
+    /// Apply an operation to the blocks and the model.
+    fn apply(op: Op, blocks: &mut Vec<Block>, model: &mut Vec<(usize, usize)>)
+        -> Result<(), &'static str> {
+        let len = blocks.len();
+
+        match op {
+            Op::Split(n, pos) => {
+                let n = n % len;
+                let (addr, size) = model[n];
+                let pos = pos % (size + 1);
+
+                let (left, right) = blocks.remove(n).split(pos);
+                blocks.insert(n, right);
+                blocks.insert(n, left);
+                model[n] = (addr + pos, size - pos);
+                model.insert(n, (addr, pos));
+            },
+            Op::MergeRight(n, m) => {
+                let (n, m) = (n % len, m % len);
+                if n == m {
+                    return Ok(());
+                }
+
+                // Merging into a block to the right is a bug, which panics with strict checks.
+                if cfg!(feature = "strict_checks") && m < n && model[m].1 != 0
+                   && model[n].1 != 0 {
+                    return Ok(());
+                }
+
+                let mut right = blocks[m].pop();
+                let res = blocks[n].merge_right(&mut right);
+                if m == n + 1 {
+                    if res.is_err() {
+                        return Err("Merging adjacent blocks failed.");
+                    }
+                    if !right.is_empty() {
+                        return Err("The merged block wasn't emptied.");
+                    }
+
+                    model[n].1 += model[m].1;
+                    model[m].1 = 0;
+                } else {
+                    if res.is_ok() {
+                        return Err("Merging non-adjacent blocks succeeded.");
+                    }
+
+                    // Put the block back.
+                    blocks[m] = right;
+                }
+            },
+            Op::MergeChain(n, count) => {
+                let n = n % len;
+                let count = count % (len - n);
+
+                let (left, right) = blocks.split_at_mut(n + 1);
+                let absorbed = left[n].try_merge_chain(right[..count].iter_mut());
+                // The blocks tile the buffer, so the whole run is adjacent.
+                if absorbed != count {
+                    return Err("Merging a run of adjacent blocks stopped early.");
+                }
+
+                for i in n + 1..n + 1 + count {
+                    model[n].1 += model[i].1;
+                    model[i].1 = 0;
+                }
+            },
+            Op::Align(n, align) => {
+                let n = n % len;
+                let align = 1 << (align % 10);
+                let (addr, size) = model[n];
+                let aligner = (align - addr % align) % align;
+
+                match blocks[n].align(Align::new(align).unwrap()) {
+                    Some((aligner_block, rest)) => {
+                        if aligner >= size {
+                            return Err("Aligning succeeded without room for the aligner.");
+                        }
+                        if !blocks[n].is_empty() || !rest.aligned_to(Align::new(align).unwrap()) {
+                            return Err("The aligned block is invalid.");
+                        }
+
+                        blocks[n] = rest;
+                        blocks.insert(n, aligner_block);
+                        model[n] = (addr + aligner, size - aligner);
+                        model.insert(n, (addr, aligner));
+                    },
+                    None => if aligner < size {
+                        return Err("Aligning failed with room for the aligner.");
+                    },
+                }
+            },
+        }
+
+        prune(blocks, model);
+        Ok(())
+    }
+
+    /// Check that the blocks tile the buffer starting at `start`, and agree with the model.
+    fn check(start: usize, blocks: &[Block], model: &[(usize, usize)]) -> Result<(), &'static str> {
+        if blocks.len() != model.len() {
+            return Err("The number of blocks disagrees with the model.");
+        }
+
+        let mut end = start;
+        for (block, &(addr, size)) in blocks.iter().zip(model) {
+            let block_addr = Pointer::from(block.empty_left()).addr();
+            if block_addr != addr || block.size() != size {
+                return Err("A block disagrees with the model.");
+            }
+            if addr != end {
+                return Err("The blocks overlap or leave a gap.");
+            }
+
+            end = addr + size;
+        }
+
+        if end != start + BUFFER {
+            return Err("The blocks don't cover the buffer.");
+        }
+
+        Ok(())
+    }
+
+    /// Run a sequence of operations on a fresh buffer.
+    ///
+    /// The index of the failing operation and the failure are returned on failure.
+    fn run(ops: &[Op]) -> Result<(), (usize, &'static str)> {
+        let mut buf = [0u8; BUFFER];
+        let start = buf.as_ptr() as usize;
+
+        let mut blocks = Vec::with_capacity(ops.len() + 1);
+        blocks.push(unsafe { Block::from_raw_parts(Pointer::new(buf.as_mut_ptr()), BUFFER) });
+        let mut model = Vec::with_capacity(ops.len() + 1);
+        model.push((start, BUFFER));
+
+        for (n, &op) in ops.iter().enumerate() {
+            apply(op, &mut blocks, &mut model)
+                .and_then(|()| check(start, &blocks, &model))
+                .map_err(|err| (n, err))?;
+        }
+
+        Ok(())
+    }
+
+    /// Minimize a failing sequence, by removing operations for as long as it still fails.
+    fn minimize(mut ops: Vec<Op>) -> Vec<Op> {
+        let mut n = 0;
+        while n < ops.len() {
+            let op = ops.remove(n);
+            if run(&ops).is_ok() {
+                // The operation is needed for the failure.
+                ops.insert(n, op);
+                n += 1;
+            }
+        }
+
+        ops
+    }
+
+    #[test]
+    fn test_block_ops() {
+        let mut rng = Rng::new(0xB10C);
+
+        for _ in 0..5000 {
+            let ops: Vec<Op> = (0..rng.below(64) + 1).map(|_| Op::random(&mut rng)).collect();
+
+            if let Err((n, err)) = run(&ops) {
+                let ops = minimize(ops[..n + 1].to_vec());
+                panic!("{} Minimized sequence: {:?}", err, ops);
+            }
+        }
+    }
 }
@@ -3,9 +3,18 @@
 use prelude::*;
 
 use core::ops::Range;
-use core::{ptr, mem, ops};
+use core::{ptr, mem, ops, cmp, iter, slice};
+use core::marker::PhantomData;
 
-use shim::config;
+use shim::{config, syscalls};
+
+use block::GENERATIONS;
+use region::{self, OwnedRegion, Origin};
+use {conf, fail, layout, sort};
+#[cfg(feature = "aslr")]
+use random;
+#[cfg(feature = "stats")]
+use stats::{self, AlignPath, ReallocStrategy};
 
 /// Elements required _more_ than the length as capacity.
 ///
@@ -20,14 +29,327 @@ use shim::config;
 /// See assumption 4.
 pub const EXTRA_ELEMENTS: usize = 4;
 
-#[cfg(feature = "alloc_id")]
-use core::sync::atomic::{self, AtomicUsize};
+use atomic::{self, AtomicUsize};
+
+/// The generation clock.
+///
+/// Freed blocks are stamped with the current generation (see `Block::generation`), which
+/// advances once every `config::GENERATION_FREES` frees of a bookkeeper.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Get the current generation.
+#[inline]
+pub fn generation() -> usize {
+    GENERATION.load(atomic::Ordering::Relaxed)
+}
+
+/// Advance the generation clock by `n` generations.
+///
+/// This ages every free block and every live buffer at once, which is useful for tests.
+pub fn advance_generation(n: usize) {
+    GENERATION.fetch_add(n, atomic::Ordering::Relaxed);
+}
+
+/// Get the age of a free block, in generations.
+///
+/// The age is coarse, and saturates: Every bookkeeper clamps the ages of its cold blocks to
+/// `config::COLD_GENERATIONS` before they could wrap around (see `Bookkeeper::saturate_ages`), so
+/// a block untouched for long never looks fresh again.
+#[inline]
+pub fn age(block: &Block) -> usize {
+    age_of(block.generation())
+}
+
+/// Get the age of a generation (as stored in a block).
+#[inline]
+fn age_of(generation: usize) -> usize {
+    GENERATION.load(atomic::Ordering::Relaxed).wrapping_sub(generation) % GENERATIONS
+}
+
+/// Get the older of two generations.
+///
+/// Merged blocks take the older generation of the two, such that freeing a block next to a cold
+/// run does not make the whole run look hot.
+#[inline]
+fn older(a: usize, b: usize) -> usize {
+    if age_of(a) >= age_of(b) { a } else { b }
+}
+
+/// Is a free block hot, i.e. recently freed?
+#[inline]
+fn is_hot(block: &Block) -> bool {
+    age(block) <= config::HOT_GENERATIONS
+}
+
+/// Is a free block cold, i.e. untouched for long?
+///
+/// Without generations (on 32-bit targets), every block is cold.
+#[inline]
+fn is_cold(block: &Block) -> bool {
+    GENERATIONS == 1 || age(block) >= config::COLD_GENERATIONS
+}
+
 /// The bookkeeper ID count.
 ///
 /// This is atomically incremented whenever a new `Bookkeeper` is created.
 #[cfg(feature = "alloc_id")]
 static BOOKKEEPER_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
+/// An error claiming a fixed range of memory.
+///
+/// See `Allocator::alloc_at`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocAtError {
+    /// No byte of the range is free.
+    ///
+    /// The range lies within a region of the allocator, but is in use.
+    NotFree,
+    /// Some, but not all, bytes of the range are free.
+    PartiallyFree,
+    /// The range is not within a single region owned by the allocator.
+    OutsideRegions,
+}
+
+/// An edge of the pool.
+///
+/// See `Allocator::take_boundary`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    /// The lowest block, next to the start of its region.
+    Low,
+    /// The highest block, next to the end of its region.
+    High,
+}
+
+/// A mutation of the pool, as recorded since a checkpoint.
+///
+/// See `Allocator::checkpoint`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mutation {
+    /// Bytes were taken out of the pool.
+    Taken {
+        /// The pointer to the bytes.
+        ptr: *mut u8,
+        /// The number of bytes.
+        size: usize,
+    },
+    /// Bytes were given to the pool.
+    Given {
+        /// The pointer to the bytes.
+        ptr: *mut u8,
+        /// The number of bytes.
+        size: usize,
+    },
+}
+
+impl Mutation {
+    /// The bytes of `block` being taken out of the pool.
+    fn taken(block: &Block) -> Mutation {
+        Mutation::Taken {
+            ptr: *Pointer::from(block.empty_left()),
+            size: block.size(),
+        }
+    }
+
+    /// The bytes of `block` being given to the pool.
+    fn given(block: &Block) -> Mutation {
+        Mutation::Given {
+            ptr: *Pointer::from(block.empty_left()),
+            size: block.size(),
+        }
+    }
+}
+
+/// A checkpoint of a pool.
+///
+/// This is consumed by either `Allocator::commit` or `Allocator::rollback`. It borrows the log of
+/// the mutations, such that the log cannot be touched while the pool writes to it.
+#[must_use]
+pub struct Checkpoint<'a> {
+    /// The log of the mutations, which the pool writes to.
+    ///
+    /// This also prevents construction outside this module.
+    _log: PhantomData<&'a mut [Mutation]>,
+}
+
+/// An error rolling a pool back to a checkpoint.
+///
+/// On error, nothing is undone, as if the checkpoint was committed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RollbackError {
+    /// The log of the checkpoint ran full, so some mutations weren't recorded.
+    Overflow,
+    /// The metadata of the pool cannot hold the blocks to restore without being reallocated.
+    Capacity,
+}
+
+/// An inconsistency of a pool.
+///
+/// See `Bookkeeper::validate`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeapError {
+    /// The block at the index overlaps the next one, or is placed after it.
+    Overlap(usize),
+    /// The block at the index is adjacent to the next one, but they aren't merged.
+    Unmerged(usize),
+    /// The empty block at the index doesn't stand in for its right neighbor (or is trailing).
+    StrayEmpty(usize),
+    /// The blocks sum up to another number of bytes than the pool claims.
+    TotalBytes {
+        /// The sum of the blocks.
+        counted: usize,
+        /// The number claimed.
+        claimed: usize,
+    },
+    /// The slab at the address is inconsistent, or, if the address is zero, the counts of the
+    /// slabs are (see `slab::validate`).
+    Slab(usize),
+}
+
+impl HeapError {
+    /// Can the inconsistency be repaired in place?
+    ///
+    /// Overlapping blocks mean that memory is owned twice, which can't be undone, so they are
+    /// fatal, and so are inconsistent slabs. The others only waste memory or skew the counts.
+    pub fn is_repairable(self) -> bool {
+        match self {
+            HeapError::Overlap(_) | HeapError::Slab(_) => false,
+            _ => true,
+        }
+    }
+}
+
+/// The repairs made to a pool.
+///
+/// See `Bookkeeper::repair`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// The pairs of adjacent blocks merged.
+    pub merged: usize,
+    /// The stray empty blocks removed.
+    pub removed: usize,
+    /// The times the byte count was corrected.
+    pub recounted: usize,
+}
+
+impl RepairReport {
+    /// The total number of repairs.
+    pub fn total(&self) -> usize {
+        self.merged + self.removed + self.recounted
+    }
+
+    /// Add the repairs of another report.
+    pub fn add(&mut self, other: RepairReport) {
+        self.merged += other.merged;
+        self.removed += other.removed;
+        self.recounted += other.recounted;
+    }
+}
+
+/// The log of the mutations of a pool since its checkpoint.
+struct Journal {
+    /// The mutations, in order.
+    entries: Vec<Mutation>,
+    /// Did the log run full?
+    overflowed: bool,
+}
+
+// The mutations point into the pool, and are only used through it.
+unsafe impl Send for Journal {}
+
+/// A scope guard setting a flag of a pool, when dropped.
+///
+/// Dropped at the end of a scope, it resets a flag on every way out. Disarmed (forgotten) at the
+/// end instead, it only sets the flag, if a panic unwinds through the scope, which is how a pool
+/// is poisoned (see `Bookkeeper::begin`).
+struct SetOnDrop {
+    /// The flag.
+    flag: *mut bool,
+    /// The value set.
+    value: bool,
+}
+
+impl SetOnDrop {
+    /// Create a guard setting `flag` to `value`.
+    fn new(flag: &mut bool, value: bool) -> SetOnDrop {
+        SetOnDrop {
+            flag: flag,
+            value: value,
+        }
+    }
+
+    /// Disarm the guard, such that it never sets the flag.
+    #[inline]
+    fn disarm(self) {
+        mem::forget(self);
+    }
+}
+
+impl Drop for SetOnDrop {
+    fn drop(&mut self) {
+        unsafe {
+            // The guard doesn't outlive the scope of the operation, nor thus the pool.
+            *self.flag = self.value;
+        }
+    }
+}
+
+/// An iterator over the free blocks of a pool.
+pub type Iter<'a> = iter::Filter<slice::Iter<'a, Block>, fn(&&Block) -> bool>;
+
+/// A callback relocating a movable allocation.
+///
+/// This is called by `Allocator::defragment` with the old and the new address of a buffer (the
+/// data is already copied) and its size, such that the owner can fix its pointers. If it returns
+/// `false`, the buffer stays where it was.
+pub type Relocator = fn(old: *mut u8, new: *mut u8, size: usize) -> bool;
+
+/// The movable allocations of a pool.
+///
+/// See `Allocator::set_relocator`.
+struct Movables {
+    /// The callback relocating the allocations.
+    relocator: Relocator,
+    /// The allocations as pointer, size and alignment, sorted by address.
+    entries: Vec<(*mut u8, usize, Align)>,
+}
+
+// The allocations are owned by the pool, and are only used through it.
+unsafe impl Send for Movables {}
+
+impl Movables {
+    /// Add an allocation.
+    ///
+    /// If the table is full, `Err(())` is returned.
+    fn insert(&mut self, ptr: *mut u8, size: usize, align: Align) -> Result<(), ()> {
+        self.entries.push((ptr, size, align))?;
+
+        // Move the entry into place.
+        sort::place_last(&mut self.entries, |x| x.0);
+
+        Ok(())
+    }
+
+    /// Remove the allocation at `ptr`, if any.
+    fn remove(&mut self, ptr: *mut u8) -> Option<(*mut u8, usize, Align)> {
+        let n = match self.entries.binary_search_by(|x| x.0.cmp(&ptr)) {
+            Ok(n) => n,
+            Err(_) => return None,
+        };
+
+        sort::shift_out(&mut self.entries, n);
+        self.entries.pop()
+    }
+
+    /// Get the highest allocation below `addr`, if any.
+    fn below(&self, addr: usize) -> Option<(*mut u8, usize, Align)> {
+        match self.entries.binary_search_by(|x| (x.0 as usize).cmp(&addr)) {
+            Ok(0) | Err(0) => None,
+            Ok(n) | Err(n) => Some(self.entries[n - 1]),
+        }
+    }
+}
+
 /// The memory bookkeeper.
 ///
 /// This stores data about the state of the allocator, and in particular, the free memory.
@@ -56,12 +378,44 @@ pub struct Bookkeeper {
     pool: Vec<Block>,
     /// The total number of bytes in the pool.
     total_bytes: usize,
+    /// The minimal size of the fragments left when splitting a free block.
+    ///
+    /// See `set_min_split_remainder`.
+    min_split_remainder: usize,
+    /// The maximal size of an allocation through `try_alloc`.
+    ///
+    /// See `set_max_allocation`.
+    max_allocation: usize,
     /// Is this bookkeeper currently reserving?
     ///
     /// This is used to avoid unbounded metacircular reallocation (reservation).
     ///
     // TODO: Find a replacement for this "hack".
     reserving: bool,
+    /// Did an operation panic midway, leaving the pool in an unknown state?
+    ///
+    /// See `Bookkeeper::begin`.
+    poisoned: bool,
+    /// The log of the mutations since the active checkpoint, if any.
+    ///
+    /// See `Allocator::checkpoint`.
+    journal: Option<Journal>,
+    /// The movable allocations, if a relocator is set.
+    ///
+    /// See `Allocator::set_relocator`.
+    movables: Option<Movables>,
+    /// The number of blocks stamped by this bookkeeper.
+    ///
+    /// See `Allocator::free_used`.
+    stamped: usize,
+    /// The generation, when the ages of the blocks were last saturated.
+    ///
+    /// See `saturate_ages`.
+    swept: usize,
+    /// Is this the bookkeeper of a raw pool?
+    ///
+    /// See `set_raw`.
+    raw: bool,
     /// The allocator ID.
     ///
     /// This is simply to be able to distinguish allocators in the locks.
@@ -82,7 +436,15 @@ impl Bookkeeper {
         let res = Bookkeeper {
             pool: vec,
             total_bytes: 0,
+            min_split_remainder: config::MIN_SPLIT_REMAINDER,
+            max_allocation: !0,
             reserving: false,
+            poisoned: false,
+            journal: None,
+            movables: None,
+            stamped: 0,
+            swept: generation(),
+            raw: false,
             // Increment the ID counter to get a brand new ID.
             id: BOOKKEEPER_ID_COUNTER.fetch_add(1, atomic::Ordering::SeqCst),
         };
@@ -90,7 +452,15 @@ impl Bookkeeper {
         let res = Bookkeeper {
             pool: vec,
             total_bytes: 0,
+            min_split_remainder: config::MIN_SPLIT_REMAINDER,
+            max_allocation: !0,
             reserving: false,
+            poisoned: false,
+            journal: None,
+            movables: None,
+            stamped: 0,
+            swept: generation(),
+            raw: false,
         };
 
         bk_log!(res, "Bookkeeper created.");
@@ -99,51 +469,52 @@ impl Bookkeeper {
         res
     }
 
-    /// Perform a binary search to find the appropriate place where the block can be insert or is
-    /// located.
+    /// Perform a binary search to find the appropriate place where a block starting at `ptr` can
+    /// be inserted or is located.
+    ///
+    /// This only needs the address, so the caller keeps the block (if any) it searches for.
     ///
     /// It is guaranteed that no block left to the returned value, satisfy the above condition.
     #[inline]
-    fn find(&mut self, block: &Block) -> usize {
-        // Logging.
-        bk_log!(self, "Searching (exact) for {:?}.", block);
-
-        let ind = match self.pool.binary_search(block) {
+    fn position_of(&self, ptr: &Pointer<u8>) -> usize {
+        let ind = match self.pool.binary_search_by(|x| x.cmp_ptr(ptr)) {
             Ok(x) | Err(x) => x,
         };
         let len = self.pool.len();
 
         // Move left.
-        ind - self.pool.iter_mut()
+        ind - self.pool.iter()
             .rev()
             .skip(len - ind)
             .take_while(|x| x.is_empty())
             .count()
     }
 
-    /// Perform a binary search to find the appropriate bound where the block can be insert or is
+    /// Perform a binary search to find the appropriate place where the block can be insert or is
     /// located.
     ///
     /// It is guaranteed that no block left to the returned value, satisfy the above condition.
     #[inline]
-    fn find_bound(&mut self, block: &Block) -> Range<usize> {
+    fn find(&self, block: &Block) -> usize {
         // Logging.
-        bk_log!(self, "Searching (bounds) for {:?}.", block);
+        bk_log!(self, "Searching (exact) for {:?}.", block);
 
-        let mut left_ind = match self.pool.binary_search(block) {
-            Ok(x) | Err(x) => x,
-        };
+        self.position_of(block.start())
+    }
 
-        let len = self.pool.len();
+    /// Perform a binary search to find the appropriate bound where the block can be insert or is
+    /// located.
+    ///
+    /// It is guaranteed that no block left to the returned value, satisfy the above condition.
+    #[inline]
+    fn find_bound(&self, block: &Block) -> Range<usize> {
+        // Logging.
+        bk_log!(self, "Searching (bounds) for {:?}.", block);
 
-        // Move left.
-        left_ind -= self.pool.iter_mut()
-            .rev()
-            .skip(len - left_ind)
-            .take_while(|x| x.is_empty())
-            .count();
+        let left_ind = self.position_of(block.start());
 
-        let mut right_ind = match self.pool.binary_search(&block.empty_right()) {
+        let end = block.end();
+        let mut right_ind = match self.pool.binary_search_by(|x| x.cmp_ptr(&end)) {
             Ok(x) | Err(x) => x,
         };
 
@@ -173,11 +544,56 @@ impl Bookkeeper {
         f(Block::from(self.pool));
     }
 
+    /// Stamp a block, which the program is done using, with the current generation.
+    ///
+    /// Every `config::GENERATION_FREES` blocks stamped, the generation clock advances.
+    #[inline]
+    fn stamp(&mut self, block: &mut Block) {
+        // Raw pools leave the clock alone.
+        if self.raw {
+            return;
+        }
+
+        self.saturate_ages();
+
+        self.stamped += 1;
+        if self.stamped % config::GENERATION_FREES == 0 {
+            GENERATION.fetch_add(1, atomic::Ordering::Relaxed);
+        }
+
+        block.set_generation(GENERATION.load(atomic::Ordering::Relaxed));
+    }
+
+    /// Clamp the ages of the cold blocks, before they wrap around.
+    ///
+    /// Generations are stored modulo `GENERATIONS`, so ages are only exact below that. Every half
+    /// wrap of the clock, the blocks at least `config::COLD_GENERATIONS` old are restamped to be
+    /// exactly that old, keeping every age in range. If the bookkeeper was left alone for almost a
+    /// whole wrap, every block (even one stamped after the previous sweep) is cold by now, so they
+    /// are all restamped.
+    fn saturate_ages(&mut self) {
+        let now = generation();
+        let gap = now.wrapping_sub(self.swept);
+        if GENERATIONS == 1 || self.raw || gap < GENERATIONS / 2 {
+            return;
+        }
+
+        self.swept = now;
+        let stale = gap >= GENERATIONS - config::COLD_GENERATIONS;
+        let cold = now.wrapping_sub(config::COLD_GENERATIONS);
+        for block in self.pool.iter_mut() {
+            if stale || age(block) >= config::COLD_GENERATIONS {
+                block.set_generation(cold);
+            }
+        }
+    }
+
     /// Pop the top block from the pool.
     pub fn pop(&mut self) -> Option<Block> {
         self.pool.pop().map(|res| {
             // Update the byte count.
             self.total_bytes -= res.size();
+            self.record(Mutation::taken(&res));
 
             // Check stuff, just in case.
             self.check();
@@ -186,6 +602,193 @@ impl Bookkeeper {
         })
     }
 
+    /// Give the interior pages of the large free blocks back to the OS.
+    ///
+    /// The blocks stay in the pool, but the OS is free to reclaim their pages. Only blocks wholly
+    /// within a region from the program break or a mapping are considered, since the pages of
+    /// static buffers cannot be reclaimed. The number of bytes given back is returned.
+    ///
+    /// Only the cold blocks (see `config::COLD_GENERATIONS`) are advised. The blocks freed
+    /// recently are likely to be reused, and advising them would only fault their pages back in.
+    pub fn advise_free(&mut self) -> usize {
+        self.advise_from(0, !0).0
+    }
+
+    /// Give the interior pages of at most `max` large cold free blocks starting at or above
+    /// `from` back to the OS.
+    ///
+    /// This is the incremental version of `advise_free`. The blocks freed recently are left
+    /// alone, as the program is likely to reuse them. The number of bytes given back and the
+    /// address to continue from (zero, if the end of the pool was reached) are returned.
+    pub fn advise_from(&mut self, from: usize, max: usize) -> (usize, usize) {
+        self.saturate_ages();
+
+        // Logging.
+        bk_log!(self, "Advising the OS of the free blocks from {:x}...", from);
+
+        let mut advised = 0;
+        let mut count = 0;
+
+        for block in self.pool.iter()
+            .filter(|x| x.size() >= config::PURGE_ADVISE_MIN && is_cold(x)) {
+            let addr = Pointer::from(block.empty_left()).addr();
+            if addr < from { continue; }
+
+            // Continue from this block the next time.
+            if count == max {
+                return (advised, addr);
+            }
+
+            // Preallocated memory stays resident, such that using it never faults.
+            match region::lookup(addr) {
+                Some(ref region) if region.origin != Origin::Static
+                    && region.origin != Origin::Preallocated && region.covers(block) => {},
+                _ => continue,
+            }
+
+            count += 1;
+
+            // Only whole pages can be given back.
+            if let Some((start, end)) = block.page_trimmed() {
+                if unsafe {
+                    // The pages are within a free block, so nothing refers to their content.
+                    let pages = Pointer::from(block.empty_left()).map_addr(|x| x + start);
+                    syscalls::madvise_dontneed(*pages, end - start)
+                }.is_ok() {
+                    advised += end - start;
+                }
+            }
+        }
+
+        (advised, 0)
+    }
+
+    /// Find the `k` free blocks closest to `ptr`.
+    ///
+    /// The distance of a block is the number of bytes between it and `ptr` (zero, if it contains
+    /// `ptr`). The blocks are written to `out` as their start and size, nearest first (ties going
+    /// to the lower address), and their number (at most `k` and `out.len()`) is returned.
+    ///
+    /// This is meant for defragmentation heuristics, which want to know whether the free space
+    /// around a buffer is worth compacting.
+    ///
+    /// # Complexity
+    ///
+    /// This is a single binary search followed by a walk in both directions, so it runs in
+    /// O(log n + k + e) time, where n is the length of the pool, and e is the number of empty
+    /// entries passed during the walk.
+    pub fn neighbors(&self, ptr: Pointer<u8>, k: usize, out: &mut [(Pointer<u8>, usize)]) -> usize {
+        let addr = ptr.addr();
+        let start = |block: &Block| block.start().addr();
+        let k = cmp::min(k, out.len());
+
+        // Split the pool into the blocks starting below `ptr` and the rest.
+        let mut right = match self.pool.binary_search_by(|x| {
+            if start(x) < addr { cmp::Ordering::Less } else { cmp::Ordering::Greater }
+        }) {
+            Ok(n) | Err(n) => n,
+        };
+        let mut left = right;
+
+        let mut n = 0;
+        while n < k {
+            // Skip the empty entries.
+            while left > 0 && self.pool[left - 1].is_empty() { left -= 1; }
+            while right < self.pool.len() && self.pool[right].is_empty() { right += 1; }
+
+            // The blocks below end at or before `ptr`, unless they contain it.
+            let below = if left > 0 {
+                let block = &self.pool[left - 1];
+                Some(addr.saturating_sub(start(block) + block.size()))
+            } else {
+                None
+            };
+            let above = self.pool.get(right).map(|block| start(block) - addr);
+
+            // Take the nearer one.
+            let block = match (below, above) {
+                (Some(x), Some(y)) if x <= y => {
+                    left -= 1;
+                    &self.pool[left]
+                },
+                (_, Some(_)) => {
+                    right += 1;
+                    &self.pool[right - 1]
+                },
+                (Some(_), None) => {
+                    left -= 1;
+                    &self.pool[left]
+                },
+                (None, None) => break,
+            };
+
+            out[n] = (Pointer::from(block.empty_left()), block.size());
+            n += 1;
+        }
+
+        n
+    }
+
+    /// Set the minimal size of the fragments left when splitting a free block.
+    ///
+    /// Allocations pass over the free blocks, which they would split into a fragment (an aligner
+    /// or a rest) smaller than `min` bytes, since such fragments are hardly usable, but still cost
+    /// an entry in the pool. Zero turns this off. The default is `config::MIN_SPLIT_REMAINDER`.
+    ///
+    /// This only concerns the free blocks split by `alloc`, not the blocks freed into the pool.
+    pub fn set_min_split_remainder(&mut self, min: usize) {
+        self.min_split_remainder = min;
+    }
+
+    /// Set the bookkeeper apart from the allocator, for a raw pool (see `raw::Pool`).
+    ///
+    /// The freed blocks are no longer stamped with the generation clock (so the clock only
+    /// advances with the allocator, and allocations take the first fitting block), and the
+    /// operations are not counted in the statistics. The minimal split remainder is turned off,
+    /// as the caller picks the sizes.
+    pub fn set_raw(&mut self) {
+        self.raw = true;
+        self.min_split_remainder = 0;
+    }
+
+    /// Count a reallocation in the statistics, unless this is a raw pool.
+    #[cfg(feature = "stats")]
+    #[inline]
+    fn record_realloc(&self, strategy: ReallocStrategy) {
+        if !self.raw {
+            stats::record_realloc(strategy);
+        }
+    }
+
+    /// Get the number of bytes of metadata, which inserting up to `entries` blocks might reserve.
+    ///
+    /// This is zero, if the pool has room for them already. It is at least the size of the
+    /// reservation (see `Allocator::reserve`), so pools, whose fresh space is fallible, can make
+    /// sure it is at hand before an operation.
+    pub fn reservation(&self, entries: usize) -> usize {
+        let min_cap = self.pool.len() + entries;
+        if self.pool.capacity() >= min_cap + EXTRA_ELEMENTS {
+            return 0;
+        }
+
+        layout::array::<Block>(min_cap + EXTRA_ELEMENTS + config::extra_fresh(min_cap))
+            .map(|x| x.size())
+            .unwrap_or(!0)
+    }
+
+    /// Set the maximal size of an allocation through `try_alloc`, in bytes.
+    ///
+    /// This is independent of the global limit (see `conf::set_max_allocation`), such that a pool
+    /// can be restricted further. There is no limit by default.
+    pub fn set_max_allocation(&mut self, limit: usize) {
+        self.max_allocation = limit;
+    }
+
+    /// Get the maximal size of an allocation through `try_alloc`, in bytes.
+    pub fn max_allocation(&self) -> usize {
+        self.max_allocation
+    }
+
     /// Get the length of the pool.
     pub fn len(&self) -> usize {
         self.pool.len()
@@ -196,6 +799,88 @@ impl Bookkeeper {
         self.total_bytes
     }
 
+    /// Iterate over the free blocks of the pool, in address order.
+    ///
+    /// The empty entries of the pool are skipped.
+    pub fn iter(&self) -> Iter {
+        /// Is the block non-empty?
+        fn non_empty(block: &&Block) -> bool {
+            !block.is_empty()
+        }
+
+        self.pool.iter().filter(non_empty as fn(&&Block) -> bool)
+    }
+
+    /// Did an operation on the pool panic midway?
+    ///
+    /// A poisoned pool aborts the process on its next mutation.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    /// Clear the checkpoint and the poisoning of the pool.
+    ///
+    /// This is for pools copied back in place from a copy (see `Heap::restore`). Their links are
+    /// consistent, but the checkpoint and the poisoning are those of the time of the copy.
+    pub fn reset_transient(&mut self) {
+        self.journal = None;
+        self.poisoned = false;
+    }
+
+    /// Forget every block of the pool, and start its statistics over.
+    ///
+    /// The blocks are dropped rather than freed, so this is for pools, whose memory is rebuilt by
+    /// the caller (see `Heap::reset`). The settings of the pool are kept, but the checkpoint and
+    /// the movable allocations are gone.
+    ///
+    /// The buffer of the pool stays in use, so its address range is returned, such that the
+    /// caller can leave it out.
+    pub fn clear(&mut self) -> Range<usize> {
+        // Logging.
+        bk_log!(self, "Clearing the pool.");
+
+        self.pool.truncate(0);
+        self.total_bytes = 0;
+        self.stamped = 0;
+        self.reset_transient();
+        if let Some(ref mut movables) = self.movables {
+            movables.entries.truncate(0);
+        }
+
+        let start = self.pool.as_ptr() as usize;
+        start..start + self.pool.capacity() * mem::size_of::<Block>()
+    }
+
+    /// Begin the modification of the links of the pool.
+    ///
+    /// The returned guard poisons the pool, unless it is disarmed once the links are consistent
+    /// again. Everything, which can fail, is done before, such that the guard only goes off for
+    /// bugs (failed assertions). If the pool is poisoned already, the process is aborted.
+    #[inline]
+    fn begin(&mut self) -> SetOnDrop {
+        if self.poisoned {
+            fail::poisoned();
+        }
+
+        SetOnDrop::new(&mut self.poisoned, true)
+    }
+
+    /// Record a mutation of the pool, if a checkpoint is active.
+    ///
+    /// Bytes moving between entries of the pool (e.g. merging) are not mutations, only bytes
+    /// entering or leaving the pool are. Every such move must be recorded exactly once.
+    #[inline]
+    fn record(&mut self, mutation: Mutation) {
+        if let Some(ref mut journal) = self.journal {
+            match mutation {
+                Mutation::Taken { size: 0, .. } | Mutation::Given { size: 0, .. } => {},
+                _ => if journal.entries.push(mutation).is_err() {
+                    journal.overflowed = true;
+                },
+            }
+        }
+    }
+
     /// Perform consistency checks.
     ///
     /// This will check for the following conditions:
@@ -204,7 +889,7 @@ impl Bookkeeper {
     /// 2. No blocks are adjacent.
     ///
     /// This is NOOP in release mode.
-    fn check(&self) {
+    pub fn check(&self) {
         if cfg!(debug_assertions) {
             // Logging.
             bk_log!(self, "Checking...");
@@ -243,140 +928,904 @@ impl Bookkeeper {
                     next = i;
                 }
 
-                // Check for trailing empty blocks.
-                assert!(!self.pool.last().unwrap().is_empty(), "Trailing empty blocks.");
+                // Check for trailing empty blocks.
+                assert!(!self.pool.last().unwrap().is_empty(), "Trailing empty blocks.");
+            }
+
+            // Make sure the sum is maintained properly.
+            assert!(total_bytes == self.total_bytes, "The sum is not equal to the 'total_bytes' \
+                    field: {} ≠ {}.", total_bytes, self.total_bytes);
+        }
+    }
+
+    /// Find the first inconsistency of the pool, if any.
+    ///
+    /// This checks the invariants of `check` (except for the capacity), but also in release
+    /// builds, and reports the violation rather than panicking.
+    pub fn validate(&self) -> Result<(), HeapError> {
+        let start = |block: &Block| block.start().addr();
+        let mut total_bytes = 0;
+
+        for (n, block) in self.pool.iter().enumerate() {
+            total_bytes += block.size();
+
+            let next = match self.pool.get(n + 1) {
+                Some(next) => next,
+                // Trailing empty blocks are stray.
+                None if block.is_empty() => return Err(HeapError::StrayEmpty(n)),
+                None => break,
+            };
+
+            if block.is_empty() {
+                if start(block) != start(next) {
+                    return Err(HeapError::StrayEmpty(n));
+                }
+            } else {
+                // An empty neighbor stands in for the next non-empty block. A block wrapping
+                // around the address space overlaps everything.
+                match start(block).checked_add(block.size()) {
+                    Some(end) if end < start(next) => {},
+                    Some(end) if end == start(next) => return Err(HeapError::Unmerged(n)),
+                    _ => return Err(HeapError::Overlap(n)),
+                }
+            }
+        }
+
+        if total_bytes != self.total_bytes {
+            return Err(HeapError::TotalBytes {
+                counted: total_bytes,
+                claimed: self.total_bytes,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Repair the inconsistencies of the pool in place.
+    ///
+    /// Adjacent blocks are merged, stray empty blocks are removed, and the byte count is
+    /// corrected, logging a warning for every repair. If a fatal inconsistency (see
+    /// `HeapError::is_repairable`) is found, it is returned, and the pool is left as is from
+    /// there.
+    pub fn repair(&mut self) -> Result<RepairReport, HeapError> {
+        let mut report = RepairReport::default();
+
+        loop {
+            match self.validate() {
+                Ok(()) => return Ok(report),
+                Err(HeapError::Unmerged(n)) => {
+                    log!(WARNING, "Repairing: merging the adjacent blocks {:?} and its neighbor.",
+                         self.pool[n]);
+
+                    // Merge with the block the empty blocks in between stand in for. These are
+                    // removed by later repairs.
+                    match (n + 1..self.pool.len()).find(|&m| !self.pool[m].is_empty()) {
+                        Some(m) => {
+                            let mut right = self.unlink_at(m);
+                            self.pool[n].merge_right(&mut right)
+                                .expect("Merging blocks, which are not adjacent.");
+                        },
+                        None => {
+                            self.unlink_at(n + 1);
+                        },
+                    }
+
+                    report.merged += 1;
+                },
+                Err(HeapError::StrayEmpty(n)) => {
+                    log!(WARNING, "Repairing: removing the stray empty block {:?}.",
+                         self.pool[n]);
+
+                    self.unlink_at(n);
+                    report.removed += 1;
+                },
+                Err(HeapError::TotalBytes { counted, claimed }) => {
+                    log!(WARNING, "Repairing: correcting the byte count from {} to {}.", claimed,
+                         counted);
+
+                    self.total_bytes = counted;
+                    report.recounted += 1;
+                },
+                Err(err) => {
+                    log!(ERROR, "Unrepairable inconsistency: {:?}.", err);
+
+                    return Err(err);
+                },
+            }
+        }
+    }
+
+    /// Remove the element at `ind` of the pool, shifting the following ones to the left.
+    ///
+    /// Unlike `remove_at`, this neither touches the byte count nor checks the pool, since it is
+    /// used while repairing it.
+    fn unlink_at(&mut self, ind: usize) -> Block {
+        let len = self.pool.len();
+        for n in ind..len - 1 {
+            self.pool.swap(n, n + 1);
+        }
+
+        self.pool.pop().expect("Unlinking from an empty pool.")
+    }
+}
+
+/// An allocator.
+///
+/// This provides the functionality of the memory bookkeeper, requiring only provision of two
+/// methods, defining the "breaker" (fresh allocator). The core functionality is provided by
+/// default methods, which aren't generally made to be overwritten.
+///
+/// The reason why these methods aren't implemented directly on the bookkeeper is the distinction
+/// between different forms of allocators (global, local, and so on). Any newtype of
+/// [`Bookkeeper`](./struct.Bookkeeper.html).
+///
+/// # Guarantees vs. assumptions
+///
+/// Please note that whenever a guarantee is mentioned, it relies on that the all the methods
+/// overwritten are upholding the guarantees specified in the documentation.
+pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
+    /// Allocate _fresh_ space.
+    ///
+    /// "Fresh" means that the space is allocated through some breaker (be it SBRK or the global
+    /// allocator).
+    ///
+    /// The returned pointer is assumed to be aligned to `align`. If this is not held, all future
+    /// guarantees are invalid.
+    ///
+    /// # Assumptions
+    ///
+    /// This is assumed to not modify the order. If some block `b` is associated with index `i`
+    /// prior to call of this function, it should be too after it.
+    fn alloc_fresh(&mut self, size: usize, align: Align) -> Block;
+
+    /// Called right before new memory is added to the pool.
+    fn on_new_memory(&mut self) {}
+
+    /// Called at the boundaries between the steps of the operations on the pool.
+    ///
+    /// This is for tests, which inject panics between the steps to check that the pool is never
+    /// left corrupt.
+    #[inline]
+    fn on_step(&mut self) {}
+
+    /// Add the memory of a region to the pool.
+    ///
+    /// The region is registered, and its block is freed into the pool. If the registry rejects
+    /// the region, the memory is still used, but it is never given back to the OS.
+    fn extend_from_region(&mut self, region: OwnedRegion) {
+        // Empty regions cannot be registered, and add nothing anyway.
+        if region.block.is_empty() { return; }
+
+        let block = region::register(region).unwrap_or_else(|region| {
+            // Logging.
+            log!(WARNING, "Unable to register {:?}.", region);
+
+            region::unregistered(region)
+        });
+
+        self.free(block);
+    }
+
+    /// Allocate a chunk of memory.
+    ///
+    /// This function takes a size and an alignment. From these a fitting block is found, to which
+    /// a pointer is returned. The block returned is guaranteed to be aligned to `align`.
+    ///
+    /// # Example
+    ///
+    /// We start with our initial segment.
+    ///
+    /// ```notrust
+    ///    Address space
+    ///   I---------------------------------I
+    /// B
+    /// l
+    /// k
+    /// s
+    /// ```
+    ///
+    /// We then split it at the aligner, which is used for making sure that
+    /// the pointer is aligned properly.
+    ///
+    /// ```notrust
+    ///    Address space
+    ///   I------I
+    /// B   ^    I--------------------------I
+    /// l  al
+    /// k
+    /// s
+    /// ```
+    ///
+    /// We then use the remaining block, but leave the excessive space.
+    ///
+    /// ```notrust
+    ///    Address space
+    ///   I------I
+    /// B                           I--------I
+    /// l        \_________________/
+    /// k        our allocated block.
+    /// s
+    /// ```
+    ///
+    /// A block representing the marked area is then returned.
+    fn alloc(&mut self, size: usize, align: Align) -> Block {
+        // Logging.
+        bk_log!(self, "Allocating {} bytes with alignment {}.", size, align);
+
+        match self.alloc_pooled(size, align) {
+            Some(res) => res,
+            // No fitting block found. Allocate a new block.
+            None => self.alloc_external(size, align),
+        }
+    }
+
+    /// Allocate a block from the pool, without allocating fresh space.
+    ///
+    /// This is `alloc` minus the fallback: if no block in the pool fits, `None` is returned.
+    fn alloc_pooled(&mut self, size: usize, align: Align) -> Option<Block> {
+        if let Some(b) = self.take_fitting(size, align) {
+            // Split and mark the block uninitialized to the debugger.
+            let (res, excessive) = b.mark_uninitialized().split(size);
+
+            // There are many corner cases that make knowing where to insert it difficult
+            // so we search instead.
+            self.free(excessive);
+
+            // Check consistency.
+            self.check();
+            debug_assert!(res.aligned_to(align), "Alignment failed.");
+            debug_assert!(res.size() == size, "Requested space does not match with the returned \
+                          block.");
+
+            Some(res)
+        } else {
+            None
+        }
+    }
+
+    /// Allocate a block, failing on sizes above the limit of this pool.
+    ///
+    /// This is like `alloc`, but if `size` exceeds the maximal allocation size of the pool (see
+    /// `set_max_allocation`), `AllocErr::TooLarge` is returned without touching the pool or
+    /// allocating fresh space.
+    fn try_alloc(&mut self, size: usize, align: Align) -> Result<Block, fail::AllocErr> {
+        let limit = self.max_allocation();
+        if size > limit {
+            bk_log!(self, "Rejecting an allocation of {} bytes (the limit is {}).", size, limit);
+            #[cfg(feature = "stats")]
+            {
+                if !self.raw {
+                    stats::record_too_large();
+                }
+            }
+
+            return Err(fail::AllocErr::TooLarge {
+                requested: size,
+                limit: limit,
+            });
+        }
+
+        Ok(self.alloc(size, align))
+    }
+
+    /// Allocate a run of equally sized objects from the pool.
+    ///
+    /// This finds the first block fitting at least one object of size `stride` aligned to
+    /// `align`, and carves as many objects (but no more than `count`) as possible out of it. The
+    /// returned block is the run, of size `stride` multiplied by the number of objects.
+    ///
+    /// In contrast to `alloc`, this never allocates fresh space. If no block in the pool can hold
+    /// an object, `None` is returned.
+    fn alloc_run(&mut self, stride: usize, count: usize, align: Align) -> Option<Block> {
+        // Logging.
+        bk_log!(self, "Allocating a run of at most {} objects of {} bytes with alignment {}.",
+                count, stride, align);
+
+        // Bound check.
+        assert!(stride != 0, "Allocating a run of zero-sized objects.");
+
+        self.take_fitting(stride, align).map(|b| {
+            // Calculate how many objects fit in the block.
+            let fit = cmp::min(count, b.size() / stride);
+
+            // Split and mark the run uninitialized to the debugger.
+            let (res, excessive) = b.mark_uninitialized().split(fit * stride);
+
+            // Free the excessive space.
+            self.free(excessive);
+
+            // Check consistency.
+            self.check();
+            debug_assert!(res.aligned_to(align), "Alignment failed.");
+
+            res
+        })
+    }
+
+    /// Take a chunk out of the largest free block of the pool.
+    ///
+    /// The chunk is aligned to `align`, and holds as many bytes as the block has past its
+    /// aligner, but no more than `max`, rounded down to a multiple of `align` (`min` must be such
+    /// a multiple). It leaves either nothing or at least `min` bytes of the block behind, if it
+    /// can, such that no sliver is left in the pool. If no block can hold `min` bytes, `None` is
+    /// returned. In contrast to `alloc`, this never allocates fresh space.
+    fn take_largest(&mut self, min: usize, max: usize, align: Align) -> Option<Block> {
+        // Logging.
+        bk_log!(self, "Taking {} to {} bytes from the largest block.", min, max);
+
+        // The bytes of a block past its aligner.
+        let usable = |block: &Block| {
+            block.size().saturating_sub(align.padding(Pointer::from(block.empty_left()).addr()))
+        };
+
+        let (ind, size) = match self.pool.iter().enumerate()
+            .map(|(n, x)| (n, usable(x)))
+            .fold(None, |best: Option<(usize, usize)>, (n, size)| match best {
+                Some((_, best_size)) if best_size >= size => best,
+                _ => Some((n, size)),
+            }) {
+            Some((ind, size)) if size >= min => (ind, size),
+            _ => return None,
+        };
+
+        let block = self.remove_at(ind).mark_uninitialized();
+        self.record(Mutation::taken(&block));
+
+        let mut take = cmp::min(size, max) & !align.mask();
+        // Rather than a sliver, leave `min` bytes, which can be taken later.
+        if size - take != 0 && size - take < min && (size - min) & !align.mask() >= min {
+            take = (size - min) & !align.mask();
+        }
+
+        let padding = block.size() - size;
+        let (aligner, rest) = block.split(padding);
+        let (res, excessive) = rest.split(take);
+
+        // Put the aligner and the excessive space back.
+        self.free(aligner);
+        self.free(excessive);
+
+        debug_assert!(res.aligned_to(align), "Alignment failed.");
+
+        Some(res)
+    }
+
+    /// Take `size` bytes off the block at an edge of the pool.
+    ///
+    /// The lowest (`Edge::Low`) or highest (`Edge::High`) free block must reach the frontier of
+    /// its region, i.e. the start or the end of the region, as registered. The `size` bytes at
+    /// the frontier are split off and returned, and the rest of the block stays in the pool.
+    ///
+    /// This is the primitive for heaps, which grow (or shrink) at an end of a region. The region
+    /// is left as is, so once the caller unregisters the bytes taken, the frontier moves along
+    /// with them.
+    ///
+    /// If the block doesn't reach the frontier, or the bytes don't fit in the block or the
+    /// region, `None` is returned, and the pool is left untouched. So is it, if `size` is zero.
+    fn take_boundary(&mut self, edge: Edge, size: usize) -> Option<Block> {
+        // Logging.
+        bk_log!(self, "Taking {} bytes from the {:?} edge.", size, edge);
+
+        // Leading empty blocks are allowed, but trailing are not.
+        let ind = match edge {
+            Edge::Low => match self.pool.iter().position(|x| !x.is_empty()) {
+                Some(ind) => ind,
+                None => return None,
+            },
+            Edge::High if !self.pool.is_empty() => self.pool.len() - 1,
+            Edge::High => return None,
+        };
+
+        let start = Pointer::from(self.pool[ind].empty_left()).addr();
+        let end = start + self.pool[ind].size();
+        if size == 0 || size > end - start { return None; }
+
+        // The bytes to take.
+        let taken = match edge {
+            Edge::Low => start..start + size,
+            Edge::High => end - size..end,
+        };
+        let reaches = region::lookup(taken.start).map_or(false, |region| {
+            taken.end <= region.end && match edge {
+                Edge::Low => region.start == start,
+                Edge::High => region.end == end,
+            }
+        });
+        if !reaches { return None; }
+
+        let block = self.remove_at(ind);
+        self.record(Mutation::taken(&block));
+
+        let (res, rest) = match edge {
+            Edge::Low => block.split(size),
+            Edge::High => {
+                let (rest, res) = block.split(end - start - size);
+                (res, rest)
+            },
+        };
+
+        // Put the rest back.
+        self.free(rest);
+
+        Some(res)
+    }
+
+    /// Allocate up to `total` bytes from the pool as chunks, taking the largest blocks first.
+    ///
+    /// Every chunk is aligned to `align` and holds at least `min` bytes, followed by `pad` bytes
+    /// of padding, which don't count towards the total. The chunks are multiples of `align` in
+    /// size, so the last one may hold more than the bytes left. At most `max_chunks` chunks are
+    /// taken, and they are passed to `f` along with the number of bytes counted towards the
+    /// total.
+    ///
+    /// Chunks are only taken as long as the rest can be allocated contiguously afterwards: At
+    /// least `min` bytes and a chunk are always left for it, unless the rest fits a single free
+    /// block. The number of bytes left is returned. In contrast to `alloc`, this never allocates
+    /// fresh space.
+    fn alloc_scatter<F>(&mut self, total: usize, min: usize, pad: usize, max_chunks: usize,
+                        align: Align, mut f: F) -> usize
+        where F: FnMut(Block, usize) {
+        // Logging.
+        bk_log!(self, "Scattering {} bytes into chunks of at least {} bytes.", total, min);
+
+        let mut left = total;
+        let mut chunks = 0;
+
+        while left > 0 && chunks < max_chunks {
+            // The rest might fit a single block.
+            let whole = align.round_up(left + pad).unwrap_or(!0);
+            if let Some(chunk) = self.take_largest(whole, whole, align) {
+                f(chunk, left);
+                return 0;
+            }
+
+            // Leave a chunk for the rest.
+            let lo = align.round_up(min + pad).unwrap_or(!0);
+            let hi = (left - min + pad) & !align.mask();
+            if chunks + 1 == max_chunks || left < 2 * min || hi < lo {
+                break;
+            }
+
+            // The largest block is smaller than the rest here.
+            match self.take_largest(lo, hi, align) {
+                Some(chunk) => {
+                    let len = chunk.size() - pad;
+                    left -= len;
+                    chunks += 1;
+                    f(chunk, len);
+                },
+                None => break,
+            }
+        }
+
+        left
+    }
+
+    /// Allocate the `size` bytes starting at `ptr`.
+    ///
+    /// This claims a fixed range of memory, if it happens to be free: The free block containing
+    /// the range is split, the bytes before and after the range stay in the pool, and the range
+    /// itself is returned.
+    ///
+    /// If the range is not entirely free, an error telling whether it is in use, only partially
+    /// free, or not owned by the allocator at all, is returned. In contrast to `alloc`, this never
+    /// allocates fresh space.
+    fn alloc_at(&mut self, ptr: Pointer<u8>, size: usize) -> Result<Block, AllocAtError> {
+        // Logging.
+        bk_log!(self, "Allocating {} bytes at {:?}.", size, ptr);
+
+        let addr = ptr.addr();
+        let end = addr.checked_add(size).ok_or(AllocAtError::OutsideRegions)?;
+        let start = |block: &Block| block.start().addr();
+
+        // Find the first block starting after `ptr`.
+        let right = match self.pool.binary_search_by(|x| {
+            if start(x) <= addr { cmp::Ordering::Less } else { cmp::Ordering::Greater }
+        }) {
+            Ok(n) | Err(n) => n,
+        };
+
+        // The last non-empty block starting at or before `ptr` is the only one, which can contain
+        // the range.
+        let mut left = right;
+        while left > 0 && self.pool[left - 1].is_empty() { left -= 1; }
+
+        if left > 0 {
+            let n = left - 1;
+            let block_end = start(&self.pool[n]) + self.pool[n].size();
+
+            if end <= block_end {
+                // Split off the bytes before and after the range.
+                let block = self.pool[n].pop();
+                let offset = addr - start(&block);
+                let (front, rest) = block.split(offset);
+                self.record(Mutation::taken(&rest));
+                let (res, back) = rest.split(size);
+
+                // Override the old block.
+                self.pool[n] = front;
+                // Update the pool byte count.
+                self.total_bytes -= res.size() + back.size();
+
+                if self.pool[n].is_empty() {
+                    // For empty alignment invariant.
+                    let _ = self.remove_at(n);
+                }
+
+                // The bytes after the range go back to the pool.
+                self.free(back);
+
+                // Check consistency.
+                self.check();
+                debug_assert!(res.size() == size, "Claimed block does not match the range.");
+
+                return Ok(res.mark_uninitialized());
+            } else if block_end > addr {
+                // The block contains the start, but not the end, of the range.
+                return Err(AllocAtError::PartiallyFree);
             }
+        }
 
-            // Make sure the sum is maintained properly.
-            assert!(total_bytes == self.total_bytes, "The sum is not equal to the 'total_bytes' \
-                    field: {} ≠ {}.", total_bytes, self.total_bytes);
+        // Check if a free block starts within the range.
+        let mut next = right;
+        while next < self.pool.len() && self.pool[next].is_empty() { next += 1; }
+        if self.pool.get(next).map_or(false, |block| start(block) < end) {
+            return Err(AllocAtError::PartiallyFree);
+        }
+
+        // Nothing in the range is free, so it is either in use or not ours at all.
+        match region::lookup(addr) {
+            Some(region) if end <= region.end => Err(AllocAtError::NotFree),
+            _ => Err(AllocAtError::OutsideRegions),
         }
     }
-}
 
-/// An allocator.
-///
-/// This provides the functionality of the memory bookkeeper, requiring only provision of two
-/// methods, defining the "breaker" (fresh allocator). The core functionality is provided by
-/// default methods, which aren't generally made to be overwritten.
-///
-/// The reason why these methods aren't implemented directly on the bookkeeper is the distinction
-/// between different forms of allocators (global, local, and so on). Any newtype of
-/// [`Bookkeeper`](./struct.Bookkeeper.html).
-///
-/// # Guarantees vs. assumptions
-///
-/// Please note that whenever a guarantee is mentioned, it relies on that the all the methods
-/// overwritten are upholding the guarantees specified in the documentation.
-pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
-    /// Allocate _fresh_ space.
+    /// Start recording the mutations of the pool, such that they can be undone.
     ///
-    /// "Fresh" means that the space is allocated through some breaker (be it SBRK or the global
-    /// allocator).
+    /// Every range of bytes taken out of or given to the pool from now on (allocations, frees,
+    /// and the parts of blocks split off and put back) is logged in `log`, until the checkpoint is
+    /// committed or rolled back. If `log` runs full, the recording stops, and the rollback fails.
     ///
-    /// The returned pointer is assumed to be aligned to `align`. If this is not held, all future
-    /// guarantees are invalid.
+    /// # Safety
     ///
-    /// # Assumptions
+    /// The checkpoint borrows `log`, but the pool keeps writing to it until the checkpoint is
+    /// committed or rolled back, so the checkpoint must not be forgotten.
     ///
-    /// This is assumed to not modify the order. If some block `b` is associated with index `i`
-    /// prior to call of this function, it should be too after it.
-    fn alloc_fresh(&mut self, size: usize, align: usize) -> Block;
+    /// # Panics
+    ///
+    /// This panics if a checkpoint is already active.
+    unsafe fn checkpoint<'a>(&mut self, log: &'a mut [Mutation]) -> Checkpoint<'a> {
+        // Logging.
+        bk_log!(self, "Checkpointing with room for {} mutations.", log.len());
 
-    /// Called right before new memory is added to the pool.
-    fn on_new_memory(&mut self) {}
+        assert!(self.journal.is_none(), "Checkpointing a pool with an active checkpoint.");
 
-    /// Allocate a chunk of memory.
+        self.journal = Some(Journal {
+            entries: Vec::from_raw_parts(Block::from_raw_parts(
+                Pointer::new(log.as_mut_ptr() as *mut u8),
+                log.len() * mem::size_of::<Mutation>()
+            ), 0),
+            overflowed: false,
+        });
+
+        Checkpoint {
+            _log: PhantomData,
+        }
+    }
+
+    /// Keep the mutations since the checkpoint, and stop recording.
+    fn commit(&mut self, _: Checkpoint) {
+        // Logging.
+        bk_log!(self, "Committing the checkpoint.");
+
+        self.journal = None;
+    }
+
+    /// Undo the mutations since the checkpoint, and stop recording.
     ///
-    /// This function takes a size and an alignment. From these a fitting block is found, to which
-    /// a pointer is returned. The block returned is guaranteed to be aligned to `align`.
+    /// The mutations are undone in reverse order, which restores the free blocks of the pool
+    /// exactly as they were at the checkpoint. The blocks allocated since must be forgotten, and
+    /// the blocks freed since are in use again. Fresh memory acquired since is not given back to
+    /// the OS, but it is taken out of the pool.
     ///
-    /// # Example
+    /// If the log ran full, or the metadata of the pool would have to be reallocated to undo the
+    /// mutations, nothing is undone, and an error is returned.
+    fn rollback(&mut self, _: Checkpoint) -> Result<(), RollbackError> {
+        // Stop recording, such that the undoing isn't recorded.
+        let journal = self.journal.take().expect("Rolling back a pool without a checkpoint.");
+
+        // Logging.
+        bk_log!(self, "Rolling back {} mutations.", journal.entries.len());
+
+        if journal.overflowed {
+            return Err(RollbackError::Overflow);
+        }
+        // Undoing a mutation adds at most one entry to the pool.
+        if self.pool.capacity() < self.pool.len() + journal.entries.len() + EXTRA_ELEMENTS {
+            return Err(RollbackError::Capacity);
+        }
+
+        for &mutation in journal.entries.iter().rev() {
+            match mutation {
+                Mutation::Taken { ptr, size } => {
+                    let block = unsafe {
+                        // The bytes were taken out of the pool, so they are ours to give back.
+                        Block::from_raw_parts(Pointer::new(ptr), size)
+                    };
+
+                    self.free(block);
+                },
+                Mutation::Given { ptr, size } => {
+                    // The bytes were given to the pool, and everything since is undone, so they
+                    // are free.
+                    let res = self.alloc_at(unsafe {
+                        // The pointer is that of a block, so it is non-null.
+                        Pointer::new(ptr)
+                    }, size);
+
+                    debug_assert!(res.is_ok(), "Unable to take back {} bytes at {:?}.", size, ptr);
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Make the allocations through `alloc_movable` relocatable by `defragment`.
     ///
-    /// We start with our initial segment.
+    /// `relocator` is called for every buffer moved. The movable allocations are kept in `table`,
+    /// taking three words each.
     ///
-    /// ```notrust
-    ///    Address space
-    ///   I---------------------------------I
-    /// B
-    /// l
-    /// k
-    /// s
-    /// ```
+    /// # Safety
     ///
-    /// We then split it at the aligner, which is used for making sure that
-    /// the pointer is aligned properly.
+    /// `table` must outlive the pool, and must not be accessed elsewhere.
     ///
-    /// ```notrust
-    ///    Address space
-    ///   I------I
-    /// B   ^    I--------------------------I
-    /// l  al
-    /// k
-    /// s
-    /// ```
+    /// # Panics
     ///
-    /// We then use the remaining block, but leave the excessive space.
+    /// This panics if a relocator is already set.
+    unsafe fn set_relocator(&mut self, relocator: Relocator, table: &mut [usize]) {
+        // Logging.
+        bk_log!(self, "Setting a relocator with a table of {} words.", table.len());
+
+        assert!(self.movables.is_none(), "Setting the relocator of a pool twice.");
+
+        self.movables = Some(Movables {
+            relocator: relocator,
+            entries: Vec::from_raw_parts(Block::from_raw_parts(
+                Pointer::new(table.as_mut_ptr() as *mut u8),
+                table.len() * mem::size_of::<usize>()
+            ), 0),
+        });
+    }
+
+    /// Allocate a movable block.
     ///
-    /// ```notrust
-    ///    Address space
-    ///   I------I
-    /// B                           I--------I
-    /// l        \_________________/
-    /// k        our allocated block.
-    /// s
-    /// ```
+    /// This is like `alloc`, but `defragment` may move the block (see `set_relocator`). The block
+    /// must not be reallocated, and must be freed whole through `free_movable`.
     ///
-    /// A block representing the marked area is then returned.
-    fn alloc(&mut self, size: usize, align: usize) -> Block {
+    /// If no relocator is set, or the table of movable allocations is full, the block is pinned,
+    /// as if allocated through `alloc`.
+    fn alloc_movable(&mut self, size: usize, align: Align) -> Block {
+        let res = self.alloc(size, align);
+
+        if let Some(ref mut movables) = self.movables {
+            if res.size() != 0
+               && movables.insert(*Pointer::from(res.empty_left()), size, align).is_err() {
+                log!(DEBUG, "The table of movable allocations is full, pinning {:?}.", res);
+            }
+        }
+
+        res
+    }
+
+    /// Free a block allocated through `alloc_movable`.
+    fn free_movable(&mut self, block: Block) {
+        if let Some(ref mut movables) = self.movables {
+            movables.remove(*Pointer::from(block.empty_left()));
+        }
+
+        self.free_used(block);
+    }
+
+    /// Move movable allocations down into free blocks, to coalesce the free space.
+    ///
+    /// Walking down from the top of the pool, every movable allocation next to a free block (as
+    /// found by `neighbors`) is copied into the lowest free block fitting it, if that is lower.
+    /// If the relocator agrees to the move, the old buffer is freed, merging with the free space
+    /// around it. Otherwise, the copy is freed, and the allocation stays. No more than `budget`
+    /// bytes are copied.
+    ///
+    /// The number of bytes moved is returned.
+    fn defragment(&mut self, budget: usize) -> usize {
         // Logging.
-        bk_log!(self, "Allocating {} bytes with alignment {}.", size, align);
+        bk_log!(self, "Defragmenting with a budget of {} bytes.", budget);
 
-        if let Some((n, b)) = self.pool.iter_mut().enumerate().filter_map(|(n, i)| {
-            if i.size() >= size {
-                // Try to split at the aligner.
-                i.align(align).and_then(|(mut a, mut b)| {
-                    if b.size() >= size {
-                        // Override the old block.
-                        *i = a;
-                        Some((n, b))
-                    } else {
-                        // Put the split block back together and place it back in its spot.
-                        a.merge_right(&mut b).expect("Unable to merge block right.");
-                        *i = a;
-                        None
-                    }
-                })
+        let relocator = match self.movables {
+            Some(ref movables) => movables.relocator,
+            None => return 0,
+        };
+
+        let mut copied = 0;
+        let mut moved = 0;
+        let mut top = !0;
+        loop {
+            let (ptr, size, align) = match self.movables {
+                Some(ref movables) => match movables.below(top) {
+                    Some(entry) => entry,
+                    None => break,
+                },
+                None => break,
+            };
+            let addr = ptr as usize;
+            top = addr;
+
+            if copied + size > budget {
+                break;
+            }
+
+            // Moving an allocation enclosed by other allocations opens no larger gap.
+            let mut near = [(Pointer::empty(), 0), (Pointer::empty(), 0)];
+            let n = self.neighbors(unsafe {
+                // The pointer is that of a block, so it is non-null.
+                Pointer::new(ptr)
+            }, 2, &mut near);
+            if !near[..n].iter().any(|&(ref x, len)| x.addr() + len == addr || x.addr() == addr + size) {
+                continue;
+            }
+
+            let new = match self.take_fitting(size, align) {
+                Some(new) => new,
+                None => continue,
+            };
+            if Pointer::from(new.empty_left()).addr() > addr {
+                // The lowest fitting block is above, so moving gains nothing.
+                self.free(new);
+                continue;
+            }
+
+            let (mut new, excessive) = new.mark_uninitialized().split(size);
+            self.free(excessive);
+
+            let old = unsafe {
+                // Movable allocations are owned by the pool until freed through `free_movable`.
+                Block::from_raw_parts(Pointer::new(ptr), size)
+            };
+            old.copy_to(&mut new);
+            copied += size;
+
+            let new_ptr = *Pointer::from(new.empty_left());
+            if relocator(ptr, new_ptr, size) {
+                bk_log!(self, "Moved {:?} to {:?}.", old, new);
+
+                if let Some(ref mut movables) = self.movables {
+                    movables.remove(ptr);
+                    // The table had room for the old entry.
+                    movables.insert(new_ptr, size, align)
+                        .expect("No room for a relocated allocation.");
+                }
+
+                self.free_used(old);
+                moved += size;
             } else {
-                None
+                self.free(new);
+            }
+        }
+
+        moved
+    }
+
+    /// Take a block from the pool, which can hold `size` bytes aligned to `align`.
+    ///
+    /// The aligner stays in the pool, while the aligned rest of the block is removed from the pool
+    /// and returned. The returned block is at least `size` bytes.
+    ///
+    /// Usually, the first fitting block is taken. See `find_fitting` for the `aslr` feature.
+    fn take_fitting(&mut self, size: usize, align: Align) -> Option<Block> {
+        self.saturate_ages();
+
+        if let Some(n) = self.find_fitting(size, align) {
+            // Split at the aligner. This cannot fail, as the block fits.
+            let (aligner, res) = self.pool[n].align(align).expect("Unable to align fitting block.");
+
+            // Point out alignments satisfied at a high cost.
+            if align > Align::BUFFER && aligner.size() > config::ALIGN_WASTE_LOG {
+                log!(DEBUG, "Aligning to {} left an aligner of {} bytes.", align, aligner.size());
+            }
+            #[cfg(feature = "stats")]
+            {
+                if !self.raw {
+                    stats::record_align_path(if aligner.is_empty() {
+                        AlignPath::Natural
+                    } else {
+                        AlignPath::Explicit
+                    });
+                }
             }
-        }).next() {
+            // Override the old block.
+            self.pool[n] = aligner;
+
             // Update the pool byte count.
-            self.total_bytes -= b.size();
+            self.total_bytes -= res.size();
+            self.record(Mutation::taken(&res));
 
             if self.pool[n].is_empty() {
                 // For empty alignment invariant.
                 let _ = self.remove_at(n);
             }
 
-            // Split and mark the block uninitialized to the debugger.
-            let (res, excessive) = b.mark_uninitialized().split(size);
+            Some(res)
+        } else {
+            None
+        }
+    }
 
-            // There are many corner cases that make knowing where to insert it difficult
-            // so we search instead.
-            self.free(excessive);
+    /// Find the index of a block, which can hold `size` bytes aligned to `align`.
+    ///
+    /// Blocks, which would be split into a fragment smaller than the minimal split remainder (see
+    /// `set_min_split_remainder`), are passed over.
+    ///
+    /// This is the first hot (recently freed) block among the first `config::HOT_CANDIDATES`
+    /// fitting blocks, or the first fitting block, if none of them is hot. Using the hot blocks
+    /// first leaves the cold ones to pile up, such that they can be trimmed or advised. Without
+    /// the preference (see `conf::set_hot_first`), the first fitting block is taken.
+    ///
+    /// If the `aslr` feature is enabled, a block is picked uniformly at random among the first
+    /// `config::ASLR_CANDIDATES` fitting blocks instead (except in deterministic mode).
+    fn find_fitting(&self, size: usize, align: Align) -> Option<usize> {
+        let min = self.min_split_remainder;
 
-            // Check consistency.
-            self.check();
-            debug_assert!(res.aligned_to(align), "Alignment failed.");
-            debug_assert!(res.size() == size, "Requested space does not match with the returned \
-                          block.");
+        #[cfg(feature = "aslr")]
+        {
+            if !random::deterministic() {
+                // Count the candidates.
+                let candidates = self.pool.iter()
+                    .filter(|x| x.fits_cleanly(size, align, min))
+                    .take(config::ASLR_CANDIDATES)
+                    .count();
 
-            res
-        } else {
-            // No fitting block found. Allocate a new block.
-            self.alloc_external(size, align)
+                if candidates == 0 {
+                    return None;
+                }
+
+                // Pick one of them.
+                return self.pool.iter()
+                    .enumerate()
+                    .filter(|&(_, x)| x.fits_cleanly(size, align, min))
+                    .nth(random::below(random::Stream::Placement, candidates))
+                    .map(|(n, _)| n);
+            }
+        }
+
+        // The first fitting block, in case none of the candidates is hot.
+        let hot_first = conf::hot_first() && !self.raw;
+        let mut first = None;
+        let mut candidates = 0;
+
+        for (n, x) in self.pool.iter().enumerate() {
+            if x.fits_cleanly(size, align, min) {
+                if hot_first && is_hot(x) {
+                    return Some(n);
+                }
+
+                first = first.or(Some(n));
+                candidates += 1;
+                if candidates == config::HOT_CANDIDATES {
+                    break;
+                }
+
+                continue;
+            }
+
+            // Count the blocks passed over, to make the cost of the threshold visible.
+            #[cfg(feature = "stats")]
+            {
+                if !self.raw && x.fits(size, align) {
+                    stats::record_split_skip();
+                }
+            }
         }
+
+        first
     }
 
     /// Free a memory block.
@@ -433,6 +1882,19 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         self.free_bound(bound, block);
     }
 
+    /// Free a block, which the program has been using.
+    ///
+    /// This stamps the block with the current generation (see `Block::generation`) before freeing
+    /// it, marking it hot. The blocks freed by the bookkeeper itself (e.g. the excess of a split
+    /// block) go through `free`, keeping the generation they had in the pool.
+    ///
+    /// See `Bookkeeper::stamp`.
+    #[inline]
+    fn free_used(&mut self, mut block: Block) {
+        self.stamp(&mut block);
+        self.free(block);
+    }
+
     /// Reallocate memory.
     ///
     /// If necessary (inplace reallocation is not possible or feasible) it will allocate a new
@@ -461,10 +1923,28 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
     ///
     /// We simply find the block next to our initial block. If this block is free and have
     /// sufficient size, we will simply merge it into our initial block, and leave the excessive
-    /// space as free. If these conditions are not met, we have to allocate a new list, and then
-    /// deallocate the old one, after which we use memmove to copy the data over to the newly
-    /// allocated list.
-    fn realloc(&mut self, block: Block, new_size: usize, align: usize) -> Block {
+    /// space as free. Otherwise, if the block to the left is free and large enough, we merge our
+    /// block into it, and move the data down (see
+    /// [`realloc_left_bound`](#method.realloc_left_bound.html)). If these conditions are not met,
+    /// we have to allocate a new list, and then deallocate the old one, after which we use memmove
+    /// to copy the data over to the newly allocated list.
+    fn realloc(&mut self, block: Block, new_size: usize, align: Align) -> Block {
+        match self.realloc_pooled(block, new_size, align) {
+            Ok(block) => block,
+            // Move the block to fresh space.
+            Err(block) => {
+                let res = self.alloc(new_size, align);
+                self.move_to(block, res)
+            },
+        }
+    }
+
+    /// Reallocate memory within the pool.
+    ///
+    /// This is like `realloc`, but if the block can't be resized inplace, and no free block fits,
+    /// `Err(Block)` is returned with the old _intact_ block, without allocating fresh space.
+    fn realloc_pooled(&mut self, block: Block, new_size: usize, align: Align)
+                      -> Result<Block, Block> {
         // Find the index bound.
         let ind = self.find_bound(&block);
 
@@ -472,29 +1952,101 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         bk_log!(self;ind, "Reallocating {:?} to size {} with align {}...", block, new_size, align);
 
         // Try to do an inplace reallocation.
-        match self.realloc_inplace_bound(ind, block, new_size) {
+        let block = match self.realloc_inplace_bound(ind.clone(), block, new_size) {
+            Ok(block) => {
+                #[cfg(feature = "stats")]
+                self.record_realloc(ReallocStrategy::Inplace);
+
+                return Ok(block);
+            },
+            Err(block) => block,
+        };
+
+        // Try to absorb the left neighbor.
+        match self.realloc_left_bound(ind, block, new_size, align) {
+            Ok(block) => {
+                #[cfg(feature = "stats")]
+                self.record_realloc(ReallocStrategy::Left);
+
+                Ok(block)
+            },
+            Err(block) => match self.alloc_pooled(new_size, align) {
+                Some(res) => Ok(self.move_to(block, res)),
+                None => Err(block),
+            },
+        }
+    }
+
+    /// Move the data of a used block to another block, and free it.
+    ///
+    /// This is the copying path of the reallocations. `res` is returned.
+    fn move_to(&mut self, block: Block, mut res: Block) -> Block {
+        // Reallocation cannot be done without copying.
+        #[cfg(feature = "stats")]
+        self.record_realloc(ReallocStrategy::Copy);
+
+        // Copy the old data to the new location.
+        block.copy_to(&mut res);
+
+        // Free the old block.
+        // Allocation may have moved insertion so we search again.
+        self.free_used(block);
+
+        // Check consistency.
+        self.check();
+
+        res
+    }
+
+    /// Reallocate memory to a size between `needed` and `preferred`.
+    ///
+    /// If the block can be extended inplace to at least `needed` bytes, it is extended as far as
+    /// possible (taking the whole free neighbor, if necessary), up to `preferred` bytes.
+    /// Otherwise, it is moved to a new block of `preferred` bytes.
+    ///
+    /// The size of the returned block is the granted size. This allows growing structures to use
+    /// the slack as capacity, instead of nibbling the neighbor away in small steps.
+    fn realloc_with_hint(&mut self, block: Block, needed: usize, preferred: usize, align: Align)
+                         -> Block {
+        match self.realloc_with_hint_pooled(block, needed, preferred, align) {
             Ok(block) => block,
             Err(block) => {
-                // Reallocation cannot be done inplace.
+                let res = self.alloc(preferred, align);
+                self.move_to(block, res)
+            },
+        }
+    }
 
-                // Allocate a new block with the same size.
-                let mut res = self.alloc(new_size, align);
+    /// Reallocate memory to a size between `needed` and `preferred` within the pool.
+    ///
+    /// This is like `realloc_with_hint`, but fails like `realloc_pooled`.
+    fn realloc_with_hint_pooled(&mut self, block: Block, needed: usize, preferred: usize,
+                                align: Align) -> Result<Block, Block> {
+        // Find the index bound.
+        let ind = self.find_bound(&block);
 
-                // Copy the old data to the new location.
-                block.copy_to(&mut res);
+        // Logging.
+        bk_log!(self;ind, "Reallocating {:?} to size {} (preferably {}) with align {}...", block,
+                needed, preferred, align);
 
-                // Free the old block.
-                // Allocation may have moved insertion so we search again.
-                self.free(block);
+        // Make some assertions.
+        debug_assert!(needed <= preferred, "The needed size is larger than the preferred size.");
 
-                // Check consistency.
-                self.check();
-                debug_assert!(res.aligned_to(align), "Alignment failed.");
-                debug_assert!(res.size() >= new_size, "Requested space does not match with the \
-                              returned block.");
+        // The space available inplace, i.e. the block and its free right neighbor.
+        let mut available = block.size();
+        if let Some(entry) = self.pool.get(ind.end) {
+            if block.left_to(entry) {
+                available += entry.size();
+            }
+        }
 
-                res
-            },
+        if available >= needed {
+            match self.realloc_inplace_bound(ind, block, cmp::min(available, preferred)) {
+                Ok(block) => Ok(block),
+                Err(_) => unreachable!(),
+            }
+        } else {
+            self.realloc_pooled(block, preferred, align)
         }
     }
 
@@ -542,8 +2094,9 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
             bk_log!(self;ind, "Shrinking {:?}.", block);
 
             // Split the block in two segments, the main segment and the excessive segment.
-            let (block, excessive) = block.split(new_size);
-            // Free the excessive segment.
+            let (block, mut excessive) = block.split(new_size);
+            // Free the excessive segment, which the program was using.
+            self.stamp(&mut excessive);
             self.free_bound(ind, excessive);
 
             // Make some assertions to avoid dumb bugs.
@@ -568,7 +2121,9 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
                 bk_log!(self;ind, "Merging {:?} to the right.", block);
 
                 // We'll merge it with the block at the end of the range.
-                block.merge_right(&mut self.remove_at(ind.end))
+                let mut entry = self.remove_at(ind.end);
+                self.record(Mutation::taken(&entry));
+                block.merge_right(&mut entry)
                     .expect("Unable to merge block right, to the end of the range.");
                 // Merge succeeded.
 
@@ -578,6 +2133,7 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
                 if ind.start == self.pool.len() {
                     self.push(excessive);
                 } else if !excessive.is_empty() {
+                    self.record(Mutation::given(&excessive));
                     self.pool[ind.start] = excessive;
                 }
                 // Block will still not be adjacent, due to `excessive` being guaranteed to not be
@@ -593,6 +2149,62 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         Err(block)
     }
 
+    /// Grow a block on a known index bound by absorbing its free left neighbor.
+    ///
+    /// The new block ends where the old one does (or up to `align` bytes before), and starts in
+    /// the neighbor, at an address aligned to `align`. The data is moved down to the new start,
+    /// and the rest of the neighbor stays in the pool. Since the ranges overlap, the data is moved
+    /// with `Block::copy_within`.
+    ///
+    /// This is only meant for growing. On failure (the neighbor isn't free or is too small),
+    /// return `Err(Block)` with the old _intact_ block.
+    fn realloc_left_bound(&mut self, ind: Range<usize>, mut block: Block, new_size: usize,
+                          align: Align) -> Result<Block, Block> {
+        // Logging.
+        bk_log!(self;ind, "Try reallocating {:?} to size {} by merging left.", block, new_size);
+
+        // Make some assertions.
+        debug_assert!(new_size > block.size(), "Merging left is only for growing.");
+
+        if ind.start == 0 || !self.pool[ind.start - 1].left_to(&block) {
+            return Err(block);
+        }
+
+        // Find the highest aligned start, which leaves room for the new size.
+        let left = Pointer::from(self.pool[ind.start - 1].empty_left()).addr();
+        let end = Pointer::from(block.empty_right()).addr();
+        let start = match end.checked_sub(new_size) {
+            Some(start) if start & !align.mask() >= left => start & !align.mask(),
+            _ => return Err(block),
+        };
+
+        // Logging...
+        bk_log!(self;ind, "Merging {:?} to the left.", block);
+
+        // Take the upper part of the neighbor.
+        let neighbor = self.remove_at(ind.start - 1);
+        self.record(Mutation::taken(&neighbor));
+        let (rest, mut res) = neighbor.split(start - left);
+        let old_size = block.size();
+        let offset = res.size();
+        res.merge_right(&mut block)
+            .expect("Unable to merge block left, to the neighbor.");
+
+        // Move the data down. Note that this must happen before anything is freed, since freeing
+        // might zero the old data.
+        res.copy_within(offset, 0, old_size);
+
+        // Place the excessive blocks back.
+        let (res, excessive) = res.split(new_size);
+        self.free(excessive);
+        self.free(rest);
+
+        // Make some assertions.
+        debug_assert!(res.aligned_to(align), "Alignment failed.");
+
+        Ok(res)
+    }
+
     /// Free a block placed in some index bound.
     ///
     /// This will at maximum insert one element.
@@ -618,29 +2230,56 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         debug_assert!(self.find(&block) == ind.start, "Block is not inserted at the appropriate \
                       index.");
 
-        // Try to merge it with the block to the right.
-        if ind.end < self.pool.len() && block.left_to(&self.pool[ind.end]) {
-            // Merge the block with the rightmost block in the range.
-            block.merge_right(&mut self.remove_at(ind.end))
-                .expect("Unable to merge block right to the block at the end of the range");
+        // Merging with the block to the right doesn't move the start, so we know up front, whether
+        // the block merges left.
+        let merges_left = ind.start != 0 && self.pool[ind.start - 1].left_to(&block);
 
-            // The merging succeeded. We proceed to try to close in the possible gap.
-            if ind.start != 0 && self.pool[ind.start - 1].merge_right(&mut block).is_ok() {
-                // Check consistency.
-                self.check();
+        // Otherwise, it is inserted, and inserting might reserve. We reserve now, such that a
+        // failing reservation leaves the pool untouched. The old buffer is freed, once the block
+        // is in. This does not break the range, due to the assumption that `reserve` never breaks
+        // order.
+        let old_buf = if merges_left {
+            None
+        } else {
+            let len = self.pool.len();
+            self.reserve(len + 1)
+        };
+        self.on_step();
 
-                return;
-            }
-        // Dammit, let's try to merge left.
-        } else if ind.start != 0 && self.pool[ind.start - 1].merge_right(&mut block).is_ok() {
-            // Check consistency.
-            self.check();
+        let guard = self.begin();
+
+        // Whether merged or inserted, the block ends up in the pool.
+        self.record(Mutation::given(&block));
 
-            return;
+        // Try to merge it with the block to the right.
+        if ind.end < self.pool.len() && block.left_to(&self.pool[ind.end]) {
+            // Merge the block with the rightmost block in the range. The merged block takes the
+            // older generation.
+            let mut right = self.remove_at(ind.end);
+            let generation = older(block.generation(), right.generation());
+            block.merge_right(&mut right)
+                .expect("Unable to merge block right to the block at the end of the range");
+            block.set_generation(generation);
         }
 
-        // Well, it failed, so we insert it the old-fashioned way.
-        self.insert(ind.start, block);
+        if merges_left {
+            // Close in the possible gap.
+            let generation = older(self.pool[ind.start - 1].generation(), block.generation());
+            self.pool[ind.start - 1].merge_right(&mut block)
+                .expect("Unable to merge block left to the block before the range");
+            self.pool[ind.start - 1].set_generation(generation);
+        } else {
+            // Well, it failed, so we insert it the old-fashioned way.
+            self.insert(ind.start, block);
+        }
+
+        guard.disarm();
+        self.on_step();
+
+        // Free the old buffer, if it exists.
+        if let Some(x) = old_buf {
+            self.free(x);
+        }
 
         // Check consistency.
         self.check();
@@ -651,7 +2290,7 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
     /// "Fresh" means that the space is allocated through the breaker.
     ///
     /// The returned pointer is guaranteed to be aligned to `align`.
-    fn alloc_external(&mut self, size: usize, align: usize) -> Block {
+    fn alloc_external(&mut self, size: usize, align: Align) -> Block {
         // Logging.
         bk_log!(self, "Fresh allocation of size {} with alignment {}.", size, align);
 
@@ -678,51 +2317,68 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
             // Trigger the new memory event handler.
             self.on_new_memory();
 
-            // Update the pool byte count.
-            self.total_bytes += block.size();
+            // The pool byte count is updated, once the block is in the pool (`free` updates it
+            // otherwise).
+            let size = block.size();
+            // Likewise for the record.
+            let given = Mutation::given(&block);
 
             // Some assertions...
             debug_assert!(self.pool.is_empty() || &block > self.pool.last().unwrap(), "Pushing will \
                           make the list unsorted.");
 
-            // We will try to simply merge it with the last block.
-            if let Some(x) = self.pool.last_mut() {
-                if x.merge_right(&mut block).is_ok() {
-                    return;
+            // The merged block takes the older generation.
+            let merge = |x: &mut Block, block: &mut Block| {
+                let generation = older(x.generation(), block.generation());
+                let merged = x.merge_right(block).is_ok();
+                if merged {
+                    x.set_generation(generation);
                 }
-            }
 
-            // Reserve space and free the old buffer.
-            if let Some(x) = unborrow!(self.reserve(self.pool.len() + 1)) {
-                // Note that we do not set the count down because this isn't setting back our
-                // pushed block.
+                merged
+            };
+
+            // We will try to simply merge it with the last block.
+            let guard = self.begin();
+            if self.pool.last_mut().map_or(false, |x| merge(x, &mut block)) {
+                self.total_bytes += size;
+                self.record(given);
+                guard.disarm();
+
+                return;
+            }
+            guard.disarm();
 
+            // Reserve space and free the old buffer. The block isn't in the pool yet, so a
+            // failing reservation leaves the pool as it was.
+            let len = self.pool.len();
+            if let Some(x) = self.reserve(len + 1) {
                 self.free(x);
             }
+            self.on_step();
 
             // Try again to merge with last block on the off chance reserve pushed something we can
             // merge with. This has actually happened in testing.
-            if let Some(x) = self.pool.last_mut() {
-                if x.merge_right(&mut block).is_ok() {
-                    return;
+            let guard = self.begin();
+            let merged = self.pool.last_mut().map_or(false, |x| merge(x, &mut block));
+
+            // If merging failed, note that trailing empty blocks are not allowed, hence the last
+            // block is the only non-empty candidate which may be adjacent to `block`. Check again
+            // that pushing is correct.
+            if merged || self.pool.is_empty() || &block > self.pool.last().unwrap() {
+                if !merged {
+                    // We push.
+                    let res = self.pool.push(block);
+
+                    // Make some assertions.
+                    debug_assert!(res.is_ok(), "Push failed (buffer full).");
                 }
-            }
 
-
-            // Merging failed. Note that trailing empty blocks are not allowed, hence the last block is
-            // the only non-empty candidate which may be adjacent to `block`.
-
-            // Check again that pushing is correct.
-            if self.pool.is_empty() || &block > self.pool.last().unwrap() {
-                // We push.
-                let res = self.pool.push(block);
-
-                // Make some assertions.
-                debug_assert!(res.is_ok(), "Push failed (buffer full).");
+                self.total_bytes += size;
+                self.record(given);
+                guard.disarm();
             } else {
-                // `free` handles the count, so we set it back.
-                // TODO: Find a better way to do so.
-                self.total_bytes -= block.size();
+                guard.disarm();
 
                 // Can't push because reserve changed the end of the pool.
                 self.free(block);
@@ -751,19 +2407,28 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
             // Catch 'em all.
             debug_assert!(new_cap > self.pool.capacity(), "Reserve shrinks?!");
 
-            // Make sure no unbounded reallocation happens.
+            // Make sure no unbounded reallocation happens. The guard goes back to the original
+            // state, even if the allocation panics.
             self.reserving = true;
+            let reserving = SetOnDrop::new(&mut self.reserving, false);
 
             // Break it to me!
-            let new_buf = self.alloc_external(new_cap * mem::size_of::<Block>(), mem::align_of::<Block>());
+            let layout = layout::array::<Block>(new_cap)
+                .unwrap_or_else(|_| fail::oom(fail::AllocErr::LimitReached));
+            let new_buf = self.alloc_external(layout.size(), Align::of::<Block>());
 
             // Go back to the original state.
-            self.reserving = false;
+            drop(reserving);
 
             // Check consistency.
             self.check();
 
-            Some(self.pool.refill(new_buf))
+            // The old buffer contains metadata (addresses and sizes of the free blocks), so it is
+            // always wiped.
+            let mut old_buf = self.pool.refill(new_buf);
+            old_buf.wipe();
+
+            Some(old_buf)
         } else {
             None
         }
@@ -861,40 +2526,49 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
             .next()
             .map(|(n, _)| n);
 
+        // The old vector's buffer.
+        let mut old_buf = None;
+        if gap.is_none() {
+            // We will only extend the length if we were unable to fit it into the current length.
+
+            // Loooooooging...
+            bk_log!(self;ind, "Block pool not long enough for shift. Extending.");
+
+            // Reserve space, before anything is moved, such that a failing reservation leaves
+            // the pool untouched. This does not break order (nor the gap), due to the
+            // assumption that `reserve` never breaks order.
+            let len = self.pool.len();
+            old_buf = self.reserve(len + 1);
+        }
+        self.on_step();
+
         // Log the operation.
         bk_log!(self;ind, "Moving all blocks right to {} blocks to the right.",
              gap.unwrap_or_else(|| self.pool.len()));
 
-        // The old vector's buffer.
-        let mut old_buf = None;
+        let guard = self.begin();
+
+        // The gap defaults to the end of the pool.
+        let gap = gap.unwrap_or_else(|| {
+            // We will move a block into reserved memory but outside of the vec's bounds. For
+            // that reason, we push a placeholder (an empty block) to extend the length, which
+            // will be overwritten in the memcpy.
+            let res = self.pool.push(block.empty_left());
+
+            // Just some assertions...
+            debug_assert!(res.is_ok(), "Push failed (buffer full).");
+
+            self.pool.len() - 1
+        });
 
         unsafe {
             // LAST AUDIT: 2016-08-21 (Ticki).
 
-            // Memmove the elements to make a gap to the new block.
+            // Memmove the elements to make a gap to the new block. The pointers are taken after
+            // the reservation, which might have moved the elements.
             ptr::copy(self.pool.get_unchecked(ind) as *const Block,
                       self.pool.get_unchecked_mut(ind + 1) as *mut Block,
-                      // The gap defaults to the end of the pool.
-                      gap.unwrap_or_else(|| {
-                          // We will only extend the length if we were unable to fit it into the current length.
-
-                          // Loooooooging...
-                          bk_log!(self;ind, "Block pool not long enough for shift. Extending.");
-
-                          // Reserve space. This does not break order, due to the assumption that
-                          // `reserve` never breaks order.
-                          old_buf = unborrow!(self.reserve(self.pool.len() + 1));
-
-                          // We will move a block into reserved memory but outside of the vec's bounds. For
-                          // that reason, we push an uninitialized element to extend the length, which will
-                          // be assigned in the memcpy.
-                          let res = self.pool.push(mem::uninitialized());
-
-                          // Just some assertions...
-                          debug_assert!(res.is_ok(), "Push failed (buffer full).");
-
-                          self.pool.len() - 1
-                      }) - ind);
+                      gap - ind);
 
             // Update the pool byte count.
             self.total_bytes += block.size();
@@ -903,6 +2577,9 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
             ptr::write(self.pool.get_unchecked_mut(ind), block.mark_free());
         }
 
+        guard.disarm();
+        self.on_step();
+
         // Free the old buffer, if it exists.
         if let Some(block) = old_buf {
             self.free(block);
@@ -917,6 +2594,8 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         // Logging.
         bk_log!(self;ind, "Removing block at {}.", ind);
 
+        let guard = self.begin();
+
         let res = if ind + 1 == self.pool.len() {
             let block = self.pool[ind].pop();
             // Make sure there are no trailing empty blocks.
@@ -933,6 +2612,7 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
 
             // Replace the block at `ind` with the left empty block from `ind + 1`.
             let block = mem::replace(&mut self.pool[ind], empty);
+            self.on_step();
 
             // Iterate over the pool from `ind` and down and set it to the  empty of our block.
             let skip = self.pool.len() - ind;
@@ -947,6 +2627,8 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         // Update the pool byte count.
         self.total_bytes -= res.size();
 
+        guard.disarm();
+
         // Check consistency.
         self.check();
 
@@ -954,3 +2636,1036 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         res.mark_uninitialized()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+    use super::*;
+
+    use core::ops;
+
+    use fail::AllocErr;
+    #[cfg(feature = "stats")]
+    use stats::{self, AlignPath, ReallocStrategy};
+    use test_util::{self, PoolOp, TestPool};
+
+    /// Create a pool of 16 non-adjacent 32 byte blocks from `data`, keeping its metadata in
+    /// `meta`.
+    fn test_pool(meta: &mut [usize; 256], data: &mut [u8; 1024]) -> TestPool {
+        let mut ops = [PoolOp::Free { start: 0, size: 0 }; 16];
+        for (i, op) in ops.iter_mut().enumerate() {
+            *op = PoolOp::Free { start: i * 64, size: 32 };
+        }
+
+        unsafe { test_util::scripted_pool(meta, data, &ops) }
+    }
+
+    #[test]
+    fn test_alloc_free() {
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let mut alloc = test_pool(&mut meta, &mut data);
+
+        assert_eq!(alloc.total_bytes(), 16 * 32);
+
+        let four = Align::new(4).unwrap();
+        let a = alloc.alloc(20, four);
+        let b = alloc.alloc(32, Align::MIN);
+        assert!(a.aligned_to(four));
+        assert_eq!(alloc.total_bytes() + a.size() + b.size(), 16 * 32);
+
+        alloc.free(a);
+        alloc.free(b);
+        assert_eq!(alloc.total_bytes(), 16 * 32);
+    }
+
+    /// Count the non-empty blocks in the pool.
+    fn free_blocks(bk: &Bookkeeper) -> usize {
+        bk.pool.iter().filter(|x| !x.is_empty()).count()
+    }
+
+    #[test]
+    fn test_position_of() {
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let mut alloc = test_pool(&mut meta, &mut data);
+
+        let block = alloc.alloc(32, Align::MIN);
+        let end = block.end();
+
+        // Searching leaves the block to the caller.
+        let ind = alloc.position_of(block.start());
+        assert_eq!(alloc.find(&block), ind);
+        assert_eq!(alloc.find_bound(&block).start, ind);
+        // The blocks from the position of the end are above the taken block.
+        let right = alloc.position_of(&end);
+        assert!(alloc.pool[right..].iter()
+            .all(|x| x.is_empty() || x.cmp_ptr(&end) != cmp::Ordering::Less));
+
+        // So the very same block can be put back.
+        alloc.free(block);
+        assert_eq!(alloc.total_bytes(), 16 * 32);
+        assert_eq!(free_blocks(&alloc), 16);
+    }
+
+    #[test]
+    fn test_free_merge() {
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let mut alloc = test_pool(&mut meta, &mut data);
+        let base = data.as_mut_ptr();
+
+        // Get the block of `size` bytes at `offset` in the data.
+        let block = |offset: usize, size: usize| unsafe {
+            Block::from_raw_parts(Pointer::new(base.offset(offset as isize)), size)
+        };
+
+        // Empty the pool.
+        for _ in 0..16 {
+            let _ = alloc.alloc(32, Align::MIN);
+        }
+        assert_eq!(alloc.total_bytes(), 0);
+        assert_eq!(free_blocks(&alloc), 0);
+
+        // Non-adjacent blocks are inserted.
+        alloc.free(block(5 * 64, 32));
+        alloc.free(block(3 * 64, 32));
+        alloc.free(block(7 * 64, 32));
+        assert_eq!(alloc.total_bytes(), 96);
+        assert_eq!(free_blocks(&alloc), 3);
+
+        // Blocks next to free blocks are merged (left, then right).
+        alloc.free(block(3 * 64 + 32, 32));
+        alloc.free(block(4 * 64 + 32, 32));
+        assert_eq!(alloc.total_bytes(), 160);
+        assert_eq!(free_blocks(&alloc), 3);
+
+        // Closing the gap merges both neighbors.
+        alloc.free(block(4 * 64, 32));
+        assert_eq!(alloc.total_bytes(), 192);
+        assert_eq!(free_blocks(&alloc), 2);
+
+        let first = alloc.pool.iter().find(|x| !x.is_empty()).unwrap();
+        assert_eq!(Pointer::from(first.empty_left()).addr(), base as usize + 3 * 64);
+        assert_eq!(first.size(), 160);
+    }
+
+    #[test]
+    fn test_realloc_strategies() {
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let mut alloc = test_pool(&mut meta, &mut data);
+        let eight = Align::new(8).unwrap();
+        // Work relative to an 8 byte aligned base.
+        let base = unsafe {
+            data.as_mut_ptr().offset(eight.padding(data.as_ptr() as usize) as isize)
+        };
+
+        // Get the block of `size` bytes at `offset` from the base.
+        let block = |offset: usize, size: usize| unsafe {
+            Block::from_raw_parts(Pointer::new(base.offset(offset as isize)), size)
+        };
+        // Get the offset of a block from the base.
+        let offset = |block: &Block| Pointer::from(block.empty_left()).addr() - base as usize;
+        // Fill a block with a pattern.
+        let fill = |block: &Block| for i in 0..block.size() {
+            unsafe { *base.offset((offset(block) + i) as isize) = i as u8; }
+        };
+        // Check the pattern in the first `size` bytes of a block.
+        let check = |block: &Block, size: usize| for i in 0..size {
+            assert_eq!(unsafe { *base.offset((offset(block) + i) as isize) }, i as u8);
+        };
+
+        // Empty the pool, and leave a block far away for the copies.
+        for _ in 0..16 {
+            let _ = alloc.alloc(32, Align::MIN);
+        }
+        alloc.free(block(768, 128));
+
+        // Right neighbor free: merge right.
+        let a = block(64, 32);
+        fill(&a);
+        alloc.free(block(96, 32));
+        let a = alloc.realloc(a, 64, eight);
+        assert_eq!((offset(&a), a.size()), (64, 64));
+        check(&a, 32);
+
+        // Only the left neighbor free: merge left, and move the data down.
+        #[cfg(feature = "stats")]
+        let before = stats::reallocs(ReallocStrategy::Left);
+        let a = block(256, 32);
+        fill(&a);
+        alloc.free(block(224, 32));
+        let a = alloc.realloc(a, 56, eight);
+        assert_eq!((offset(&a), a.size()), (232, 56));
+        check(&a, 32);
+        // The rest of the neighbor is still free.
+        assert_eq!(alloc.total_bytes(), 128 + 8);
+        #[cfg(feature = "stats")]
+        assert!(stats::reallocs(ReallocStrategy::Left) > before);
+
+        // The left neighbor is too small, once the start is aligned: copy.
+        let a = block(400, 32);
+        fill(&a);
+        alloc.free(block(392, 8));
+        let a = alloc.realloc(a, 41, eight);
+        assert_eq!(offset(&a), 768);
+        check(&a, 32);
+
+        // ... but just large enough for a bit less.
+        let a = block(480, 32);
+        fill(&a);
+        alloc.free(block(472, 8));
+        let a = alloc.realloc(a, 37, eight);
+        assert_eq!((offset(&a), a.size()), (472, 37));
+        check(&a, 32);
+    }
+
+    #[test]
+    fn test_extend_from_region() {
+        static mut EXTRA: [u8; 256] = [0; 256];
+
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let mut alloc = test_pool(&mut meta, &mut data);
+
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new(EXTRA.as_mut_ptr()), 256)
+        };
+        let addr = Pointer::from(block.empty_left()).addr();
+
+        alloc.extend_from_region(OwnedRegion::new(block, Origin::Static));
+        assert_eq!(alloc.total_bytes(), 16 * 32 + 256);
+
+        // Other static buffers might be adjacent, and merged into the region.
+        let region = region::lookup(addr + 255).unwrap();
+        assert_eq!(region.origin, Origin::Static);
+        assert!(region.contains(addr));
+
+        // Static memory is never advised.
+        assert_eq!(alloc.advise_free(), 0);
+    }
+
+    #[test]
+    fn test_alloc_at() {
+        static mut HEAP: [u8; 512] = [0; 512];
+
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let mut alloc = test_pool(&mut meta, &mut data);
+
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new(HEAP.as_mut_ptr()), 512)
+        };
+        let addr = Pointer::from(block.empty_left()).addr();
+        alloc.extend_from_region(OwnedRegion::new(block, Origin::Static));
+        let total = alloc.total_bytes();
+
+        let at = |offset: usize| unsafe { Pointer::new((addr + offset) as *mut u8) };
+
+        // Claim a range in the middle.
+        let claimed = alloc.alloc_at(at(100), 50).unwrap();
+        assert_eq!(Pointer::from(claimed.empty_left()).addr(), addr + 100);
+        assert_eq!(claimed.size(), 50);
+        assert_eq!(alloc.total_bytes(), total - 50);
+
+        // The range is now taken, and its edges are only partially free.
+        assert_eq!(alloc.alloc_at(at(100), 50), Err(AllocAtError::NotFree));
+        assert_eq!(alloc.alloc_at(at(110), 10), Err(AllocAtError::NotFree));
+        assert_eq!(alloc.alloc_at(at(90), 20), Err(AllocAtError::PartiallyFree));
+        assert_eq!(alloc.alloc_at(at(140), 20), Err(AllocAtError::PartiallyFree));
+
+        // Memory, which is not ours, is reported as such.
+        let x = 0u64;
+        let stack = unsafe { Pointer::new(&x as *const u64 as *mut u8) };
+        assert_eq!(alloc.alloc_at(stack, 8), Err(AllocAtError::OutsideRegions));
+
+        // The neighbors are still allocatable, by address too.
+        let front = alloc.alloc_at(at(0), 100).unwrap();
+        let back = alloc.alloc_at(at(150), 362).unwrap();
+        assert_eq!(alloc.total_bytes(), total - 512);
+
+        alloc.free(front);
+        alloc.free(back);
+        alloc.free(claimed);
+        assert_eq!(alloc.total_bytes(), total);
+        let heap = alloc.alloc_at(at(0), 512).unwrap();
+        assert_eq!(heap.size(), 512);
+
+        // The registry is global, so the region is not left behind for the other tests.
+        assert_eq!(region::unregister(&heap), Ok(Origin::Static));
+    }
+
+    #[test]
+    fn test_neighbors() {
+        extern crate std;
+
+        use self::std::vec::Vec;
+
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let base = data.as_ptr() as usize;
+        let mut alloc = test_pool(&mut meta, &mut data);
+
+        // Leave some empty entries in the pool.
+        for _ in 0..3 {
+            alloc.alloc(32, Align::MIN);
+        }
+
+        // Compare against a brute-force scan of the pool.
+        for offset in (0..1100).filter(|x| x % 13 == 0 || x % 32 == 0) {
+            let addr = base + offset;
+
+            let mut expected: Vec<(usize, usize, usize)> = alloc.pool.iter()
+                .filter(|x| !x.is_empty())
+                .map(|x| {
+                    let start = Pointer::from(x.empty_left()).addr();
+                    let dist = if start >= addr {
+                        start - addr
+                    } else {
+                        addr.saturating_sub(start + x.size())
+                    };
+
+                    (dist, start, x.size())
+                }).collect();
+            expected.sort();
+
+            for k in 0..20 {
+                let mut out: Vec<_> = (0..16).map(|_| (Pointer::empty(), 0)).collect();
+                let n = alloc.neighbors(unsafe { Pointer::new(addr as *mut u8) }, k, &mut out);
+
+                assert_eq!(n, cmp::min(k, expected.len()));
+                for (&(ref ptr, size), &(_, start, expected_size)) in out[..n].iter().zip(&expected) {
+                    assert_eq!(ptr.addr(), start);
+                    assert_eq!(size, expected_size);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_allocation() {
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let mut alloc = unsafe {
+            test_util::scripted_pool(&mut meta, &mut data, &[
+                PoolOp::Free { start: 0, size: 1024 },
+            ])
+        };
+        alloc.set_max_allocation(256);
+
+        // Rejected requests leave the pool alone.
+        assert_eq!(alloc.try_alloc(512, Align::BUFFER).err(),
+                   Some(AllocErr::TooLarge { requested: 512, limit: 256 }));
+        assert_eq!(alloc.total_bytes(), 1024);
+
+        let block = alloc.try_alloc(256, Align::new(1).unwrap()).unwrap();
+        assert_eq!(block.size(), 256);
+        alloc.free(block);
+
+        // The limit can be raised.
+        alloc.set_max_allocation(!0);
+        let block = alloc.try_alloc(512, Align::new(1).unwrap()).unwrap();
+        assert_eq!(block.size(), 512);
+        alloc.free(block);
+        assert_eq!(alloc.total_bytes(), 1024);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        extern crate std;
+
+        use self::std::vec::Vec;
+
+        /// Get the address and size of every free block.
+        fn snapshot(bk: &Bookkeeper) -> Vec<(usize, usize)> {
+            bk.iter().map(|x| (Pointer::from(x.empty_left()).addr(), x.size())).collect()
+        }
+
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let mut alloc = test_pool(&mut meta, &mut data);
+
+        // Take a block out, such that freeing it is a part of the plan.
+        let early = alloc.alloc(20, Align::MIN);
+        let before = snapshot(&alloc);
+        let total = alloc.total_bytes();
+
+        let mut log = [Mutation::Taken { ptr: ::core::ptr::null_mut(), size: 0 }; 64];
+        let cp = unsafe { alloc.checkpoint(&mut log) };
+        for n in 0..10 {
+            let align = if n % 2 == 0 { Align::MIN } else { Align::new(8).unwrap() };
+            let _ = alloc.alloc(1 + n * 7 % 16, align);
+        }
+        alloc.free(early);
+        assert!(snapshot(&alloc) != before);
+
+        // The free blocks are restored exactly.
+        alloc.rollback(cp).unwrap();
+        assert_eq!(snapshot(&alloc), before);
+        assert_eq!(alloc.total_bytes(), total);
+
+        // Committing keeps the mutations.
+        let mut log = [Mutation::Taken { ptr: ::core::ptr::null_mut(), size: 0 }; 64];
+        let cp = unsafe { alloc.checkpoint(&mut log) };
+        let _ = alloc.alloc(8, Align::MIN);
+        let after = snapshot(&alloc);
+        alloc.commit(cp);
+        assert_eq!(snapshot(&alloc), after);
+
+        // A log too small for the plan fails the rollback, leaving the pool alone.
+        let mut log = [Mutation::Taken { ptr: ::core::ptr::null_mut(), size: 0 }; 4];
+        let cp = unsafe { alloc.checkpoint(&mut log) };
+        for _ in 0..10 {
+            let _ = alloc.alloc(8, Align::MIN);
+        }
+        let after = snapshot(&alloc);
+        assert_eq!(alloc.rollback(cp), Err(RollbackError::Overflow));
+        assert_eq!(snapshot(&alloc), after);
+    }
+
+    #[test]
+    fn test_defragment() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        /// The addresses of the movable buffers, as known to their owners.
+        static OWNERS: [AtomicUsize; 8] = [
+            AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+            AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        ];
+
+        /// Update the owner of a moved buffer, refusing buffers filled with `0xEE`.
+        fn relocate(old: *mut u8, new: *mut u8, _: usize) -> bool {
+            if unsafe { *new } == 0xEE {
+                return false;
+            }
+
+            OWNERS.iter().any(|x| x.compare_and_swap(old as usize, new as usize, Ordering::SeqCst)
+                              == old as usize)
+        }
+
+        /// Get the byte filling the `n`th buffer.
+        fn pattern(n: usize) -> u8 {
+            if n == 10 { 0xEE } else { n as u8 + 1 }
+        }
+
+        /// Get the size of the largest free block.
+        fn largest(bk: &Bookkeeper) -> usize {
+            bk.iter().map(|x| x.size()).max().unwrap_or(0)
+        }
+
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let mut table = [0; 32];
+        let mut alloc = unsafe {
+            test_util::scripted_pool(&mut meta, &mut data, &[PoolOp::Free { start: 0, size: 1024 }])
+        };
+        unsafe { alloc.set_relocator(relocate, &mut table); }
+
+        // Lay out a checkerboard of movable and pinned buffers, and free every fourth of them.
+        for n in 0..16 {
+            let mut block = if n % 2 == 0 {
+                alloc.alloc_movable(64, Align::MIN)
+            } else {
+                alloc.alloc(64, Align::MIN)
+            };
+            block.fill(pattern(n));
+
+            if n % 2 == 0 {
+                OWNERS[n / 2].store(Pointer::from(block.empty_left()).addr(), Ordering::SeqCst);
+            } else if n % 4 == 3 {
+                alloc.free(block);
+            }
+        }
+        assert_eq!(largest(&alloc), 64);
+        let total = alloc.total_bytes();
+
+        // Nothing is moved without a budget.
+        assert_eq!(alloc.defragment(0), 0);
+        assert_eq!(largest(&alloc), 64);
+
+        assert!(alloc.defragment(!0) > 0);
+        alloc.check();
+        assert!(largest(&alloc) >= 128);
+        assert_eq!(alloc.total_bytes(), total);
+
+        // The buffers kept their contents, and the refused one stayed.
+        for (n, owner) in OWNERS.iter().enumerate() {
+            let block = unsafe {
+                Block::from_raw_parts(Pointer::new(owner.load(Ordering::SeqCst) as *mut u8), 64)
+            };
+            assert_eq!(block.verify_fill(pattern(2 * n)), Ok(()));
+
+            alloc.free_movable(block);
+        }
+        alloc.check();
+    }
+
+    #[test]
+    fn test_min_split_remainder() {
+        extern crate std;
+
+        use self::std::vec::Vec;
+
+        let mut meta = [0; 4096];
+        let mut data = [0; 65536];
+        let start = Align::BUFFER.padding(data.as_ptr() as usize);
+        let mut alloc = unsafe {
+            test_util::scripted_pool(&mut meta, &mut data, &[
+                PoolOp::Free { start: start, size: 65536 - 64 },
+            ])
+        };
+        alloc.set_min_split_remainder(32);
+
+        // A randomized workload of multiples of 16 bytes, which could leave 16 byte fragments.
+        let mut rng = test_util::Rng::new(3);
+        let mut live = Vec::new();
+        for _ in 0..2000 {
+            if live.is_empty() || rng.below(2) == 0 && live.len() < 64 {
+                let size = 16 * (rng.below(15) + 2);
+                live.push(alloc.alloc(size, Align::BUFFER));
+            } else {
+                let n = rng.below(live.len());
+                alloc.free(live.swap_remove(n));
+            }
+
+            // Every buffer is at least 32 bytes, so a smaller block can only be a fragment.
+            assert!(alloc.pool.iter().all(|x| x.is_empty() || x.size() >= 32));
+        }
+
+        for block in live {
+            alloc.free(block);
+        }
+        assert_eq!(alloc.total_bytes(), 65536 - 64);
+    }
+
+    /// Set the age of the `n`th free block of the pool.
+    #[cfg(target_pointer_width = "64")]
+    fn set_age(bk: &mut Bookkeeper, n: usize, age: usize) {
+        let now = GENERATION.load(atomic::Ordering::Relaxed);
+        bk.pool.iter_mut().filter(|x| !x.is_empty()).nth(n).unwrap()
+            .set_generation(now.wrapping_sub(age));
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_generations() {
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let mut alloc = test_pool(&mut meta, &mut data);
+        let base = data.as_mut_ptr();
+
+        // Get the block of `size` bytes at `offset` in the data.
+        let block = |offset: usize, size: usize| unsafe {
+            Block::from_raw_parts(Pointer::new(base.offset(offset as isize)), size)
+        };
+        // Other tests advance the clock meanwhile, but by far less than a cold age.
+        let hot = |bk: &Bookkeeper, n: usize| {
+            is_hot(bk.pool.iter().filter(|x| !x.is_empty()).nth(n).unwrap())
+        };
+
+        // Empty the pool.
+        for _ in 0..16 {
+            let _ = alloc.alloc(32, Align::MIN);
+        }
+
+        // Blocks freed by the program are stamped hot...
+        alloc.free_used(block(3 * 64, 32));
+        assert!(hot(&alloc, 0));
+        assert_eq!(alloc.stamped, 1);
+
+        // ...while the blocks freed by the bookkeeper keep their generation.
+        let mut cold = block(5 * 64, 32);
+        cold.set_generation(GENERATION.load(atomic::Ordering::Relaxed).wrapping_sub(100));
+        alloc.free(cold);
+        assert!(!hot(&alloc, 1));
+        assert!(is_cold(alloc.pool.iter().filter(|x| !x.is_empty()).nth(1).unwrap()));
+
+        // A merged block keeps the older generation, to the left...
+        alloc.free_used(block(5 * 64 + 32, 32));
+        assert_eq!(free_blocks(&alloc), 2);
+        assert!(!hot(&alloc, 1));
+        // ...and to the right.
+        set_age(&mut alloc, 0, 100);
+        alloc.free_used(block(3 * 64 - 32, 32));
+        assert!(!hot(&alloc, 0));
+        set_age(&mut alloc, 0, 0);
+        alloc.free_used(block(3 * 64 + 32, 32));
+        assert!(hot(&alloc, 0));
+
+        // The clock advances every `GENERATION_FREES` stamps.
+        let before = GENERATION.load(atomic::Ordering::Relaxed);
+        for _ in 0..config::GENERATION_FREES {
+            let buf = alloc.alloc(32, Align::MIN);
+            alloc.free_used(buf);
+        }
+        assert!(GENERATION.load(atomic::Ordering::Relaxed).wrapping_sub(before) >= 1);
+
+        // Every half wrap, the ages of the cold blocks are clamped...
+        let now = GENERATION.load(atomic::Ordering::Relaxed);
+        set_age(&mut alloc, 0, 0);
+        set_age(&mut alloc, 1, 100);
+        alloc.swept = now.wrapping_sub(GENERATIONS / 2);
+        alloc.saturate_ages();
+        assert_eq!(alloc.swept, now);
+        assert!(hot(&alloc, 0));
+        assert!(is_cold(alloc.pool.iter().filter(|x| !x.is_empty()).nth(1).unwrap()));
+        assert!(age(alloc.pool.iter().filter(|x| !x.is_empty()).nth(1).unwrap()) < 100);
+
+        // ...and after almost a whole wrap, every block is cold.
+        alloc.swept = now.wrapping_sub(GENERATIONS - 1);
+        alloc.saturate_ages();
+        assert!(!hot(&alloc, 0));
+    }
+
+    #[cfg(all(target_pointer_width = "64", not(feature = "aslr")))]
+    #[test]
+    fn test_hot_placement() {
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let base = data.as_ptr() as usize;
+        let mut alloc = test_pool(&mut meta, &mut data);
+
+        for n in 0..16 {
+            set_age(&mut alloc, n, 100);
+        }
+
+        // A hot block among the candidates is preferred over the first fitting block.
+        set_age(&mut alloc, 2, 0);
+        let block = alloc.alloc(32, Align::MIN);
+        assert_eq!(*Pointer::from(block) as usize - base, 2 * 64);
+
+        // Beyond the candidates, the first fitting block is used.
+        set_age(&mut alloc, 8, 0);
+        let block = alloc.alloc(32, Align::MIN);
+        assert_eq!(*Pointer::from(block) as usize - base, 0);
+
+        // The hot block is still out of reach.
+        let block = alloc.alloc(32, Align::MIN);
+        assert_eq!(*Pointer::from(block) as usize - base, 64);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn test_align_paths() {
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let sixty_four = Align::new(64).unwrap();
+        let start = sixty_four.padding(data.as_ptr() as usize);
+        let mut alloc = unsafe {
+            test_util::scripted_pool(&mut meta, &mut data, &[
+                PoolOp::Free { start: start, size: 256 },
+            ])
+        };
+
+        // Other tests might count concurrently, so only lower bounds are checked.
+        let natural = stats::align_paths(AlignPath::Natural);
+        let explicit = stats::align_paths(AlignPath::Explicit);
+
+        // The block is aligned already.
+        let a = alloc.alloc(16, sixty_four);
+        assert!(stats::align_paths(AlignPath::Natural) > natural);
+
+        // The rest starts 16 bytes past the alignment, so an aligner of 48 bytes is split off.
+        let b = alloc.alloc(16, sixty_four);
+        assert!(stats::align_paths(AlignPath::Explicit) > explicit);
+        assert_eq!(Pointer::from(b.empty_left()).addr() - Pointer::from(a.empty_left()).addr(),
+                   64);
+    }
+
+    #[test]
+    fn test_realloc_with_hint() {
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let mut alloc = test_pool(&mut meta, &mut data);
+
+        // Grow a vector one element at a time, asking for as much as possible.
+        let mut block = alloc.alloc(8, Align::MIN);
+        let ptr = *Pointer::from(block.empty_left());
+        let mut extensions = 0;
+
+        for len in 9..33 {
+            if len > block.size() {
+                block = alloc.realloc_with_hint(block, len, 64, Align::MIN);
+                extensions += 1;
+            }
+        }
+
+        // The whole neighbor was taken at once.
+        assert_eq!(extensions, 1);
+        assert_eq!(block.size(), 32);
+        assert_eq!(*Pointer::from(block.empty_left()), ptr);
+
+        // Without the space inplace, the preferred size is granted.
+        let block = alloc.realloc_with_hint(block, 33, 34, Align::MIN);
+        assert_eq!(block.size(), 34);
+        assert!(*Pointer::from(block.empty_left()) != ptr);
+
+        alloc.free(block);
+    }
+
+    #[test]
+    fn test_repair() {
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let mut alloc = test_pool(&mut meta, &mut data);
+        assert_eq!(alloc.validate(), Ok(()));
+
+        // An unmerged pair: the third block grows up to the fourth.
+        alloc.pool[2] = unsafe { test_util::buffer_block(&mut data[128..192]) };
+        assert_eq!(alloc.validate(), Err(HeapError::Unmerged(2)));
+        // A stray empty block, between the first two blocks.
+        let stray = unsafe { test_util::buffer_block(&mut data[40..40]) };
+        alloc.pool.push(stray).unwrap();
+        for n in (1..alloc.pool.len() - 1).rev() {
+            alloc.pool.swap(n, n + 1);
+        }
+        assert_eq!(alloc.validate(), Err(HeapError::StrayEmpty(1)));
+        // A miscount.
+        alloc.total_bytes += 100;
+
+        assert_eq!(alloc.repair(), Ok(RepairReport {
+            merged: 1,
+            removed: 1,
+            recounted: 1,
+        }));
+        assert_eq!(alloc.validate(), Ok(()));
+        alloc.check();
+
+        assert_eq!(free_blocks(&alloc), 15);
+        assert_eq!(alloc.pool[2].size(), 96);
+        assert_eq!(alloc.total_bytes(), 16 * 32 + 32);
+
+        // A consistent pool needs no repairs.
+        assert_eq!(alloc.repair(), Ok(RepairReport::default()));
+    }
+
+    #[test]
+    fn test_alloc_scatter() {
+        let mut meta = [0; 256];
+        let mut data = [0; 2048];
+        // A fragmented pool of 960 free bytes.
+        let mut alloc = unsafe {
+            test_util::scripted_pool(&mut meta, &mut data, &[
+                PoolOp::Free { start: 0, size: 512 },
+                PoolOp::Free { start: 576, size: 256 },
+                PoolOp::Free { start: 896, size: 128 },
+                PoolOp::Free { start: 1088, size: 64 },
+            ])
+        };
+
+        // No single block can hold 800 bytes.
+        assert!(alloc.pool.iter().all(|x| x.size() < 800));
+
+        let mut chunks = [(0, 0); 4];
+        let mut count = 0;
+        let rest = alloc.alloc_scatter(800, 64, 0, 4, Align::MIN, |chunk, len| {
+            assert_eq!(chunk.size(), len);
+            chunks[count] = (Pointer::from(chunk.empty_left()).addr(), chunk.size());
+            count += 1;
+        });
+        assert_eq!(rest, 0);
+        // The largest block first, then as few chunks as possible.
+        assert_eq!(count, 3);
+        assert_eq!(chunks[0].1, 512);
+        // The second block is not cut down to a sliver, but leaves a chunk's worth of bytes.
+        assert_eq!(chunks[1].1, 192);
+        assert_eq!(chunks[..count].iter().map(|x| x.1).sum::<usize>(), 800);
+        assert!(chunks[..count].iter().all(|x| x.1 >= 64));
+        assert_eq!(alloc.total_bytes(), 160);
+
+        // Freeing the chunks restores the pool.
+        for &(addr, size) in &chunks[..count] {
+            alloc.free(Block::from_raw_parts(Pointer::new(addr as *mut u8), size));
+        }
+        assert_eq!(alloc.total_bytes(), 960);
+        assert_eq!(free_blocks(&alloc), 4);
+
+        // A slot is always left for the rest.
+        count = 0;
+        let rest = alloc.alloc_scatter(800, 64, 0, 2, Align::MIN, |chunk, _| {
+            chunks[count] = (Pointer::from(chunk.empty_left()).addr(), chunk.size());
+            count += 1;
+        });
+        assert_eq!(rest, 288);
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_alloc_scatter_rounding() {
+        let mut meta = [0; 256];
+        let mut data = [0; 2048];
+        // The blocks are aligned to 16, such that no aligners are split off.
+        let base = Align::new(16).unwrap().padding(data.as_ptr() as usize);
+        let mut alloc = unsafe {
+            test_util::scripted_pool(&mut meta, &mut data, &[
+                PoolOp::Free { start: base, size: 512 },
+                PoolOp::Free { start: base + 576, size: 256 },
+            ])
+        };
+
+        let mut lens = [0; 4];
+        let mut count = 0;
+        let rest = alloc.alloc_scatter(600, 40, 8, 4, Align::new(16).unwrap(), |chunk, len| {
+            assert_eq!(chunk.size() % 16, 0);
+            assert!(chunk.size() >= len + 8);
+            lens[count] = len;
+            count += 1;
+        });
+        assert_eq!(rest, 0);
+        assert_eq!(lens[..count].iter().sum::<usize>(), 600);
+        // The last chunk is rounded up.
+        assert_eq!(alloc.total_bytes(), 768 - 512 - 112);
+    }
+
+    #[test]
+    fn test_alloc_scatter_rollback() {
+        let mut meta = [0; 256];
+        let mut data = [0; 2048];
+        let mut alloc = unsafe {
+            test_util::scripted_pool(&mut meta, &mut data, &[
+                PoolOp::Free { start: 0, size: 512 },
+                PoolOp::Free { start: 576, size: 256 },
+                PoolOp::Free { start: 896, size: 128 },
+            ])
+        };
+        let before: [(usize, usize); 3] = [
+            (Pointer::from(alloc.pool[0].empty_left()).addr(), alloc.pool[0].size()),
+            (Pointer::from(alloc.pool[1].empty_left()).addr(), alloc.pool[1].size()),
+            (Pointer::from(alloc.pool[2].empty_left()).addr(), alloc.pool[2].size()),
+        ];
+
+        let mut log = [Mutation::Taken { ptr: ::core::ptr::null_mut(), size: 0 }; 16];
+        let cp = unsafe { alloc.checkpoint(&mut log) };
+        let rest = alloc.alloc_scatter(800, 64, 0, 4, Align::MIN, |_, _| {});
+        assert_eq!(rest, 0);
+        assert_eq!(alloc.total_bytes(), 96);
+
+        // Every chunk taken, and every piece given back, is undone.
+        alloc.rollback(cp).unwrap();
+        assert_eq!(alloc.total_bytes(), 896);
+        assert_eq!(alloc.iter().filter(|x| !x.is_empty()).count(), 3);
+        for (block, &(addr, size)) in alloc.iter().filter(|x| !x.is_empty()).zip(before.iter()) {
+            assert_eq!((Pointer::from(block.empty_left()).addr(), block.size()), (addr, size));
+        }
+    }
+
+    #[test]
+    fn test_repair_fatal() {
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let mut alloc = test_pool(&mut meta, &mut data);
+
+        // The fifth block overlaps the sixth.
+        alloc.pool[4] = unsafe { test_util::buffer_block(&mut data[256..356]) };
+        assert!(!HeapError::Overlap(4).is_repairable());
+
+        // The miscount is fixed neither, since the overlap is found first.
+        assert_eq!(alloc.repair(), Err(HeapError::Overlap(4)));
+        assert_eq!(alloc.validate(), Err(HeapError::Overlap(4)));
+        assert_eq!(alloc.total_bytes(), 16 * 32);
+    }
+
+    /// Take chunks off an edge of a pool holding a synthetic region of `buf`.
+    ///
+    /// Every chunk taken is unregistered, such that the frontier moves along. The chunks and the
+    /// pool left are checked against the geometry expected.
+    fn take_boundaries(buf: &'static mut [u8; 4096 + 128], edge: Edge) {
+        let mut meta = [0; 256];
+        let mut alloc = unsafe { TestPool::new(&mut meta) };
+
+        // The guard bytes around the region keep other regions from being merged into it.
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new(buf.as_mut_ptr().offset(64)), 4096)
+        };
+        let start = Pointer::from(block.empty_left()).addr();
+        let end = start + 4096;
+        alloc.extend_from_region(OwnedRegion::new(block, Origin::Static));
+
+        // More than is left, or nothing at all, cannot be taken.
+        assert!(alloc.take_boundary(edge, 4097).is_none());
+        assert!(alloc.take_boundary(edge, 0).is_none());
+
+        for n in 1..8 {
+            let chunk = alloc.take_boundary(edge, 512).unwrap();
+            let addr = Pointer::from(chunk.empty_left()).addr();
+            assert_eq!(chunk.size(), 512);
+            assert_eq!(addr, match edge {
+                Edge::Low => start + (n - 1) * 512,
+                Edge::High => end - n * 512,
+            });
+
+            // The rest stays in the pool, as a single block.
+            assert_eq!(alloc.total_bytes(), 4096 - n * 512);
+            assert_eq!(free_blocks(&alloc), 1);
+            alloc.check();
+
+            // The block no longer reaches the frontier, until the chunk is given up.
+            assert!(alloc.take_boundary(edge, 1).is_none());
+            region::unregister(&chunk).unwrap();
+        }
+
+        // The region shrank to the last chunk.
+        let region = region::lookup(match edge {
+            Edge::Low => end - 512,
+            Edge::High => start,
+        }).unwrap();
+        assert_eq!(region.size(), 512);
+        assert_eq!(alloc.take_boundary(edge, 512).map(|x| x.size()), Some(512));
+        assert_eq!(alloc.total_bytes(), 0);
+        assert!(alloc.take_boundary(edge, 1).is_none());
+    }
+
+    #[test]
+    fn test_take_boundary_upward() {
+        static mut BUF: [u8; 4096 + 128] = [0; 4096 + 128];
+
+        // A heap growing upward has its frontier at the top.
+        take_boundaries(unsafe { &mut BUF }, Edge::High);
+    }
+
+    #[test]
+    fn test_take_boundary_downward() {
+        static mut BUF: [u8; 4096 + 128] = [0; 4096 + 128];
+
+        // A heap growing downward has its frontier at the bottom.
+        take_boundaries(unsafe { &mut BUF }, Edge::Low);
+    }
+
+    #[test]
+    fn test_take_boundary_unregistered() {
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+        let mut alloc = test_pool(&mut meta, &mut data);
+
+        // The blocks are in no region, so they have no frontier.
+        assert!(alloc.take_boundary(Edge::Low, 16).is_none());
+        assert!(alloc.take_boundary(Edge::High, 16).is_none());
+        assert_eq!(alloc.total_bytes(), 16 * 32);
+    }
+
+    /// A test pool, which panics at the `countdown`th step boundary of its operations.
+    struct PanickyPool {
+        /// The inner pool.
+        inner: TestPool,
+        /// The number of step boundaries left before the panic.
+        countdown: usize,
+    }
+
+    impl ops::Deref for PanickyPool {
+        type Target = Bookkeeper;
+
+        fn deref(&self) -> &Bookkeeper {
+            &*self.inner
+        }
+    }
+
+    impl ops::DerefMut for PanickyPool {
+        fn deref_mut(&mut self) -> &mut Bookkeeper {
+            &mut *self.inner
+        }
+    }
+
+    impl Allocator for PanickyPool {
+        fn alloc_fresh(&mut self, _: usize, _: Align) -> Block {
+            panic!("Fresh allocation in a test pool.");
+        }
+
+        fn on_step(&mut self) {
+            if self.countdown == 0 {
+                panic!("Injected panic.");
+            }
+
+            self.countdown -= 1;
+        }
+    }
+
+    /// Run operations covering removal, insertion and merging in both directions.
+    fn step_workload(pool: &mut PanickyPool, data: &mut [u8; 1024]) {
+        // An exact fit removes the block.
+        let a = pool.alloc(32, Align::MIN);
+        // It has no neighbors, so it is inserted back.
+        pool.free(a);
+
+        // Fill the gaps between the blocks, merging them to both sides.
+        for i in 0..4 {
+            pool.free(unsafe { test_util::buffer_block(&mut data[i * 64 + 32..i * 64 + 64]) });
+        }
+        // The last gap is past the last block, so it is pushed.
+        pool.free(unsafe { test_util::buffer_block(&mut data[15 * 64 + 32..]) });
+    }
+
+    #[test]
+    fn test_panic_at_steps() {
+        extern crate std;
+
+        use self::std::panic::{self, AssertUnwindSafe};
+
+        let mut poisoned = 0;
+        for steps in 0.. {
+            let mut meta = [0; 256];
+            let mut data = [0; 1024];
+            let mut pool = PanickyPool {
+                inner: test_pool(&mut meta, &mut data),
+                countdown: steps,
+            };
+
+            let res = panic::catch_unwind(AssertUnwindSafe(|| step_workload(&mut pool, &mut data)));
+
+            // Either the pool is still consistent, or it is marked as such.
+            if pool.is_poisoned() {
+                poisoned += 1;
+            } else {
+                assert_eq!(pool.validate(), Ok(()), "A panic after {} steps corrupted the pool.",
+                           steps);
+                pool.check();
+            }
+
+            if res.is_ok() {
+                assert!(!pool.is_poisoned());
+                assert_eq!(pool.total_bytes(), 21 * 32);
+                break;
+            }
+        }
+
+        // A panic midway through removing a block cannot be undone.
+        assert!(poisoned > 0);
+    }
+
+    #[test]
+    fn test_panicking_reserve() {
+        extern crate std;
+
+        use self::std::panic::{self, AssertUnwindSafe};
+
+        let mut meta = [0; 16];
+        let mut data = [0; 1024];
+        let mut pool = PanickyPool {
+            inner: unsafe { TestPool::new(&mut meta) },
+            countdown: usize::max_value(),
+        };
+
+        // Free non-adjacent blocks, until the metadata has to grow, which panics.
+        for i in 0..16 {
+            let blocks = free_blocks(&pool);
+            let bytes = pool.total_bytes();
+
+            let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                pool.free(unsafe { test_util::buffer_block(&mut data[i * 64..i * 64 + 32]) });
+            }));
+
+            if res.is_err() {
+                // The pool is left as it was.
+                assert!(!pool.is_poisoned());
+                assert!(!pool.reserving);
+                assert_eq!(pool.validate(), Ok(()));
+                assert_eq!(free_blocks(&pool), blocks);
+                assert_eq!(pool.total_bytes(), bytes);
+
+                return;
+            }
+        }
+
+        panic!("The metadata never grew.");
+    }
+}
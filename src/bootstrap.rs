@@ -0,0 +1,123 @@
+//! The bootstrap arena.
+//!
+//! Initializing an allocator requires some memory for its metadata (the block pool). Obtaining
+//! this memory from the allocator itself would recurse, so instead it is carved out of a static
+//! buffer, the bootstrap arena.
+//!
+//! The arena is a simple lock-free bump allocator. Memory is never given back to the arena, but
+//! blocks from it can still be freed to a bookkeeper, which will reuse them like any other block.
+//! The arena is registered as a static region on first use.
+
+use prelude::*;
+
+use atomic::{self, AtomicBool, AtomicUsize};
+
+use shim::config;
+
+use region::{self, OwnedRegion, Origin};
+
+/// The bootstrap arena's buffer.
+///
+/// This is only accessed through the blocks handed out by `alloc`, which are disjoint.
+static mut ARENA: [u8; config::BOOTSTRAP_SIZE] = [0; config::BOOTSTRAP_SIZE];
+/// The number of bytes used from the bootstrap arena.
+///
+/// This includes the padding used for alignment.
+static USED: AtomicUsize = AtomicUsize::new(0);
+/// Has the arena been registered as a region?
+static REGISTERED: AtomicBool = AtomicBool::new(false);
+
+/// Allocate a block from the bootstrap arena.
+///
+/// The returned block is of exactly size `size` and aligned to `align`. If the arena is
+/// exhausted, `None` is returned.
+pub fn alloc(size: usize, align: Align) -> Option<Block> {
+    // Logging.
+    log!(INTERNAL, "Allocating {} bytes with alignment {} from the bootstrap arena.", size, align);
+
+    let arena = unsafe {
+        // We only take a pointer, no reference is used.
+        &mut ARENA as *mut [u8; config::BOOTSTRAP_SIZE] as *mut u8
+    };
+    let base = arena as usize;
+
+    // Register the arena, if nobody did yet.
+    if !REGISTERED.swap(true, atomic::Ordering::SeqCst) {
+        let block = unsafe {
+            // The block merely describes the arena, and is discarded once registered.
+            Block::from_raw_parts(Pointer::new(arena), config::BOOTSTRAP_SIZE)
+        };
+
+        if let Err(region) = region::register(OwnedRegion::new(block, Origin::Static)) {
+            // Logging.
+            log!(WARNING, "Unable to register the bootstrap arena {:?}.", region.block);
+
+            region::unregistered(region);
+        }
+    }
+
+    loop {
+        let used = USED.load(atomic::Ordering::SeqCst);
+
+        // Calculate the aligner, which defines the padding required to align the block.
+        let aligner = align.padding(base + used);
+        let start = used + aligner;
+
+        // Bound check.
+        if start + size > config::BOOTSTRAP_SIZE || start + size < used {
+            // Logging.
+            log!(WARNING, "The bootstrap arena is exhausted.");
+
+            return None;
+        }
+
+        // Try to bump the pointer. If another thread came in between, we retry.
+        if USED.compare_and_swap(used, start + size, atomic::Ordering::SeqCst) == used {
+            return Some(unsafe {
+                // The bump pointer ensures that the segment is never handed out again, hence it
+                // is unaliased. The bound check ensures that it is valid.
+                Block::from_raw_parts(Pointer::new(arena.offset(start as isize)), size)
+            });
+        }
+    }
+}
+
+/// Get the number of bytes used from the bootstrap arena.
+pub fn used() -> usize {
+    USED.load(atomic::Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use prelude::*;
+
+    #[test]
+    fn test_alloc() {
+        let sixteen = Align::new(16).unwrap();
+        let a = alloc(17, Align::MIN).unwrap();
+        let b = alloc(64, sixteen).unwrap();
+
+        assert_eq!(a.size(), 17);
+        assert_eq!(b.size(), 64);
+        assert!(b.aligned_to(sixteen));
+        assert!(a < b);
+        assert!(used() >= 17 + 64);
+    }
+
+    #[test]
+    fn test_registered() {
+        let block = alloc(8, Align::MIN).unwrap();
+        let region = region::lookup(Pointer::from(block.empty_left()).addr()).unwrap();
+
+        assert_eq!(region.origin, Origin::Static);
+        assert_eq!(region.size(), config::BOOTSTRAP_SIZE);
+    }
+
+    #[test]
+    fn test_exhaust() {
+        assert!(alloc(config::BOOTSTRAP_SIZE + 1, Align::MIN).is_none());
+        assert!(alloc(!0, Align::MIN).is_none());
+    }
+}
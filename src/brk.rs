@@ -4,19 +4,31 @@
 
 use prelude::*;
 
-use core::ptr;
+use core::{cmp, ptr};
 use core::convert::TryInto;
 
-use shim::{syscalls, config};
+use shim::{config, syscalls};
+#[cfg(feature = "test_util")]
+use shim::inject;
+use shim::syscalls::GrowError;
 
-use {sync, fail};
+use region::{self, OwnedRegion, Origin};
+use fail::{self, AllocErr};
+use ptr::MAX_BLOCK;
+use {allocator, bookkeeper, conf, sync};
+
+#[cfg(feature = "aslr")]
+use random;
 
 /// The BRK mutex.
 ///
-/// This is used for avoiding data races in multiple allocator.
-static BRK_MUTEX: Mutex<BrkState> = Mutex::new(BrkState {
-    current_brk: None,
-});
+/// This is used for avoiding data races in multiple allocator. It is padded, such that it doesn't
+/// share a line with the global allocator's lock.
+static BRK_MUTEX: sync::CachePadded<Mutex<BrkState>> =
+    sync::CachePadded::new(Mutex::ranked("brk", sync::rank::BRK, BrkState {
+        current_brk: None,
+        growth: Growth::new(),
+    }));
 
 /// A cache of the BRK state.
 ///
@@ -24,6 +36,64 @@ static BRK_MUTEX: Mutex<BrkState> = Mutex::new(BrkState {
 struct BrkState {
     /// The program break's end
     current_brk: Option<Pointer<u8>>,
+    /// The growth rate of the heap.
+    growth: Growth,
+}
+
+/// The growth rate of the heap.
+///
+/// This is an exponentially weighted moving average of the bytes requested by the growths of the
+/// heap per generation (see `bookkeeper::generation`), which the extra space of the growths is
+/// chosen from (see `ralloc::set_adaptive_brk`).
+struct Growth {
+    /// The average number of bytes requested per generation.
+    rate: usize,
+    /// The number of bytes requested in the current generation.
+    bytes: usize,
+    /// The current generation.
+    generation: usize,
+}
+
+impl Growth {
+    /// Create the state of a heap, which never grew.
+    const fn new() -> Growth {
+        Growth {
+            rate: 0,
+            bytes: 0,
+            generation: 0,
+        }
+    }
+
+    /// Account a growth for `size` bytes in generation `now`, and get the extra space to take.
+    ///
+    /// The extra space covers the larger of the average rate and the bytes requested in the
+    /// current generation so far, within `config::BRK_EXTRA_MIN` and `config::BRK_EXTRA_MAX`. A
+    /// burst of growths thus quickly gets large extras, while a heap growing rarely gets small
+    /// ones.
+    fn extra(&mut self, size: usize, now: usize) -> usize {
+        if now != self.generation {
+            // Take in the finished generation, and decay the average through the idle ones. After
+            // some dozens of idle generations, nothing is left of it anyway.
+            let elapsed = now.wrapping_sub(self.generation);
+            self.rate = fold(self.rate, self.bytes);
+            for _ in 1..cmp::min(elapsed, 64) {
+                self.rate = fold(self.rate, 0);
+            }
+
+            self.bytes = 0;
+            self.generation = now;
+        }
+
+        self.bytes = self.bytes.saturating_add(size);
+
+        cmp::max(config::BRK_EXTRA_MIN,
+                 cmp::min(cmp::max(self.rate, self.bytes), config::BRK_EXTRA_MAX))
+    }
+}
+
+/// Take a sample into the moving average of the growth rate.
+fn fold(rate: usize, sample: usize) -> usize {
+    rate - rate / config::BRK_RATE_WEIGHT + sample / config::BRK_RATE_WEIGHT
 }
 
 /// A BRK lock.
@@ -35,12 +105,28 @@ pub struct BrkLock {
 impl BrkLock {
     /// Extend the program break.
     ///
+    /// Failures of growing the break can be injected (see `shim::inject`).
+    ///
     /// # Safety
     ///
     /// Due to being able shrink the program break, this method is unsafe.
-    unsafe fn sbrk(&mut self, size: isize) -> Result<Pointer<u8>, ()> {
+    unsafe fn sbrk(&mut self, size: isize) -> Result<Pointer<u8>, GrowError> {
         log!(NOTE, "Incrementing the program break by {} bytes.", size);
 
+        // There is no program break in bare-metal mode.
+        if allocator::bare_metal() {
+            return Err(GrowError::Brk(syscalls::ENOSYS));
+        }
+
+        #[cfg(feature = "test_util")]
+        {
+            if size > 0 {
+                if let Some(errno) = inject::take_brk() {
+                    return Err(GrowError::Brk(errno));
+                }
+            }
+        }
+
         // Calculate the new program break. To avoid making multiple syscalls, we make use of the
         // state cache.
         let expected_brk = self.current_brk().offset(size);
@@ -63,18 +149,37 @@ impl BrkLock {
             Ok(old_brk)
         } else {
             // BRK failed. This syscall is rather weird, but whenever it fails (e.g. OOM) it
-            // returns the old (unchanged) break.
-            Err(())
+            // returns the old (unchanged) break, so we don't know why. It is almost certainly
+            // out of memory.
+            Err(GrowError::Brk(syscalls::ENOMEM))
         }
     }
 
     /// Safely release memory to the OS.
     ///
-    /// If failed, we return the memory.
+    /// The memory is unregistered from the region registry. If failed, we return the memory.
+    /// Preallocated memory (see `preallocate`) is never released, so a block overlapping it
+    /// (e.g. merged with it in the pool) is returned as well. The caller has to split it off (see
+    /// `GlobalAllocator::trim_bounded`).
     #[allow(cast_possible_wrap)]
     pub fn release(&mut self, block: Block) -> Result<(), Block> {
+        let start = Pointer::from(block.empty_left()).addr();
+        let end = start + block.size();
+
+        let mut preallocated = false;
+        region::for_each(|x| if x.origin == Origin::Preallocated && x.start < end
+                                && start < x.end {
+            preallocated = true;
+        });
+        if preallocated {
+            // Logging...
+            log!(DEBUG, "Keeping the preallocated {:?}.", block);
+
+            return Err(block);
+        }
+
         // Check if we are actually next to the program break.
-        if self.current_brk() == Pointer::from(block.empty_right()) {
+        if !allocator::bare_metal() && self.current_brk() == Pointer::from(block.empty_right()) {
             // Logging...
             log!(DEBUG, "Releasing {:?} to the OS.", block);
 
@@ -90,6 +195,12 @@ impl BrkLock {
             // In debug mode, we want to check for WTF-worthy scenarios.
             debug_assert!(res.is_ok(), "Failed to set the program break back.");
 
+            // The memory is gone, so forget its region. It might never have been registered (the
+            // registry can be full), so failure is fine.
+            if res.is_ok() {
+                let _ = region::unregister(&block);
+            }
+
             Ok(())
         } else {
             // Logging...
@@ -122,33 +233,99 @@ impl BrkLock {
         cur
     }
 
+    /// Burn a random, page-granular gap at the program break.
+    ///
+    /// The gap is never used, making the placement of the following segment less predictable.
+    /// This is NOOP in deterministic mode.
+    #[cfg(feature = "aslr")]
+    fn burn_gap(&mut self) {
+        if random::deterministic() { return; }
+
+        let pages = random::below(random::Stream::Gap, config::ASLR_MAX_GAP_PAGES + 1);
+        if pages != 0 {
+            // Logging.
+            log!(DEBUG, "Burning a gap of {} pages.", pages);
+
+            // Failure is fine, as the gap is optional.
+            let _ = unsafe {
+                // The gap is bounded by the constant, so the cast cannot wrap.
+                self.sbrk((pages * syscalls::page_size()) as isize)
+            };
+        }
+    }
+
     /// BRK new space.
     ///
     /// The first block represents the aligner segment (that is the precursor aligning the middle
     /// block to `align`), the second one is the result and is of exactly size `size`. The last
     /// block is the excessive space.
     ///
+    /// The new segment is registered as a region originating from the program break.
+    ///
     /// # Failure
     ///
-    /// This method calls the OOM handler if it is unable to acquire the needed space.
+    /// This method calls the OOM handler with the error of the OS if it is unable to acquire the
+    /// needed space.
+    pub fn canonical_brk(&mut self, size: usize, align: Align) -> (Block, Block, Block) {
+        self.try_canonical_brk(size, align).unwrap_or_else(|err| fail::oom(err))
+    }
+
+    /// BRK new space, failing rather than calling the OOM handler.
+    ///
+    /// This is like `canonical_brk`, but returns the error, such that the caller can free some
+    /// memory and retry.
+    ///
+    /// # Errors
+    ///
+    /// If the segment is beyond the largest block, `AllocErr::TooLarge` is returned. If the OS
+    /// cannot provide the space, `AllocErr::Os` is.
     // TODO: This method is possibly unsafe.
-    pub fn canonical_brk(&mut self, size: usize, align: usize) -> (Block, Block, Block) {
+    pub fn try_canonical_brk(&mut self, size: usize, align: Align)
+                             -> Result<(Block, Block, Block), AllocErr> {
+        // Randomize the position of the segment.
+        #[cfg(feature = "aslr")]
+        self.burn_gap();
+
         // Calculate the canonical size (extra space is allocated to limit the number of system calls).
-        let brk_size = size + config::extra_brk(size) + align;
+        // The growth is accounted even with the fixed extra, such that switching to the adaptive
+        // one starts from the history. Segments beyond the largest block cannot be offset
+        // through, so they are rejected.
+        let adaptive = self.state.growth.extra(size, bookkeeper::generation());
+        let extra = if conf::adaptive_brk() { adaptive } else { config::extra_brk(size) };
+        let brk_size = match size.checked_add(extra)
+                                 .and_then(|x| x.checked_add(align.get())) {
+            Some(brk_size) if brk_size <= MAX_BLOCK => brk_size,
+            _ => return Err(AllocErr::TooLarge {
+                requested: size,
+                limit: MAX_BLOCK,
+            }),
+        };
 
         // Use SBRK to allocate extra data segment. The alignment is used as precursor for our
         // allocated block. This ensures that it is properly memory aligned to the requested value.
         // TODO: Audit the casts.
-        let (alignment_block, rest) = unsafe {
+        let segment = unsafe {
             // LAST AUDIT: 2016-08-21 (Ticki).
 
             Block::from_raw_parts(
                 // Important! The conversion is failable to avoid arithmetic overflow-based
                 // attacks.
-                self.sbrk(brk_size.try_into().unwrap()).unwrap_or_else(|()| fail::oom()),
+                self.sbrk(brk_size.try_into().unwrap()).map_err(AllocErr::Os)?,
                 brk_size,
             )
-        }.align(align).unwrap();
+        };
+
+        // Register the segment, such that it can be trimmed later on. An unregistered segment is
+        // still usable, it just stays with us for good.
+        let mut segment = region::register(OwnedRegion::new(segment, Origin::Brk))
+            .unwrap_or_else(|region| {
+                // Logging.
+                log!(WARNING, "Unable to register the segment {:?}.", region.block);
+
+                region::unregistered(region)
+            });
+
+        let (alignment_block, rest) = segment.align(align).unwrap();
 
         // Split the block to leave the excessive space.
         let (res, excessive) = rest.split(size);
@@ -157,7 +334,45 @@ impl BrkLock {
         debug_assert!(res.aligned_to(align), "Alignment failed.");
         debug_assert!(res.size() + alignment_block.size() + excessive.size() == brk_size, "BRK memory leak.");
 
-        (alignment_block, res, excessive)
+        Ok((alignment_block, res, excessive))
+    }
+
+    /// BRK a preallocated segment of `size` bytes.
+    ///
+    /// The segment is registered as preallocated, so it is never released. Unlike
+    /// `canonical_brk`, no extra space is taken, and no gap is burnt.
+    ///
+    /// # Errors
+    ///
+    /// If the size is beyond the largest block, `AllocErr::TooLarge` is returned. If the OS
+    /// cannot provide the space, `AllocErr::Os` is returned, and if the region registry is full,
+    /// `AllocErr::LimitReached` is (and the break is moved back).
+    #[allow(cast_possible_wrap)]
+    pub fn preallocate(&mut self, size: usize) -> Result<Block, AllocErr> {
+        if size > MAX_BLOCK {
+            return Err(AllocErr::TooLarge {
+                requested: size,
+                limit: MAX_BLOCK,
+            });
+        }
+
+        let segment = unsafe {
+            // The size is bounded by the largest block, so the cast cannot wrap.
+            Block::from_raw_parts(self.sbrk(size as isize).map_err(AllocErr::Os)?, size)
+        };
+
+        region::register(OwnedRegion::new(segment, Origin::Preallocated)).or_else(|region| {
+            // Logging.
+            log!(WARNING, "Unable to register the preallocated segment {:?}.", region.block);
+
+            // The segment was never handed out, and it is on top of the break.
+            let res = unsafe {
+                self.sbrk(-(size as isize))
+            };
+            debug_assert!(res.is_ok(), "Failed to set the program break back.");
+
+            Err(AllocErr::LimitReached)
+        })
     }
 }
 
@@ -180,7 +395,7 @@ pub fn lock() -> BrkLock {
 ///
 /// On failure the maximum pointer (`!0 as *mut u8`) is returned.
 pub unsafe extern fn sbrk(size: isize) -> *mut u8 {
-    *lock().sbrk(size).unwrap_or_else(|()| Pointer::new(!0 as *mut u8))
+    *lock().sbrk(size).unwrap_or_else(|_| Pointer::new(!0 as *mut u8))
 }
 
 /// Get the current program break.
@@ -196,14 +411,90 @@ fn current_brk() -> Pointer<u8> {
 mod test {
     use super::*;
 
+    use prelude::*;
+
+    use core::mem;
+
+    #[test]
+    fn test_layout() {
+        // The lock doesn't share a line with anything else.
+        assert_eq!(mem::size_of_val(&BRK_MUTEX), sync::CACHE_LINE);
+        assert_eq!(&BRK_MUTEX as *const _ as usize % sync::CACHE_LINE, 0);
+    }
+
     #[test]
     fn test_ordered() {
-        let brk = lock().canonical_brk(20, 1);
+        let brk = lock().canonical_brk(20, Align::MIN);
 
         assert!(brk.0 <= brk.1);
         assert!(brk.1 <= brk.2);
     }
 
+    #[test]
+    fn test_registered() {
+        let (_, block, _) = lock().canonical_brk(20, Align::MIN);
+        let addr = Pointer::from(block.empty_left()).addr();
+
+        let region = region::lookup(addr).unwrap();
+        assert_eq!(region.origin, Origin::Brk);
+        assert!(region.covers(&block));
+    }
+
+    #[test]
+    fn test_preallocated_kept() {
+        let mut brk = lock();
+        let block = brk.preallocate(4096).unwrap();
+        let addr = Pointer::from(block.empty_left()).addr();
+
+        let region = region::lookup(addr).unwrap();
+        assert_eq!(region.origin, Origin::Preallocated);
+        assert!(region.covers(&block));
+
+        // The segment is on top of the break, but stays.
+        let mut block = brk.release(block).unwrap_err();
+        assert!(region::lookup(addr).is_some());
+
+        // So does the memory above it, when merged with it.
+        let (mut aligner, mut res, mut excessive) = brk.canonical_brk(20, Align::MIN);
+        block.merge_right(&mut aligner).unwrap();
+        block.merge_right(&mut res).unwrap();
+        block.merge_right(&mut excessive).unwrap();
+        assert!(brk.release(block).is_err());
+        assert!(region::lookup(addr).is_some());
+    }
+
+    #[test]
+    fn test_growth_burst() {
+        let mut growth = Growth::new();
+
+        // A lone small growth gets the least extra.
+        assert_eq!(growth.extra(100, 1), config::BRK_EXTRA_MIN);
+
+        // A burst within a generation gets what it asked for so far, up to the ceiling.
+        assert_eq!(growth.extra(64 * 1024, 1), 64 * 1024 + 100);
+        assert_eq!(growth.extra(64 * 1024, 1), 128 * 1024 + 100);
+        for _ in 0..100 {
+            growth.extra(64 * 1024, 1);
+        }
+        assert_eq!(growth.extra(64 * 1024, 1), config::BRK_EXTRA_MAX);
+    }
+
+    #[test]
+    fn test_growth_average() {
+        let mut growth = Growth::new();
+
+        // A steady rate of 64 KiB per generation.
+        for n in 1..100 {
+            growth.extra(64 * 1024, n);
+        }
+        let steady = growth.extra(1, 100);
+        assert!(steady > 60 * 1024 && steady <= 64 * 1024, "The extra was {}.", steady);
+
+        // The average decays through the idle generations.
+        assert!(growth.extra(1, 102) < steady);
+        assert_eq!(growth.extra(1, 1000), config::BRK_EXTRA_MIN);
+    }
+
     #[test]
     fn test_brk_grow_up() {
         unsafe {
@@ -213,4 +504,17 @@ mod test {
             assert!(*brk1 < *brk2);
         }
     }
+
+    #[test]
+    #[cfg(feature = "test_util")]
+    fn test_brk_failure() {
+        let mut brk = lock();
+        inject::fail_brk(syscalls::ENOMEM);
+
+        unsafe {
+            assert_eq!(brk.sbrk(100).err(), Some(GrowError::Brk(syscalls::ENOMEM)));
+            // The failure is used up.
+            assert!(brk.sbrk(100).is_ok());
+        }
+    }
 }
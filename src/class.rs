@@ -0,0 +1,146 @@
+//! Size classes.
+//!
+//! Small sizes are grouped into classes, which the slabs serve and the statistics are kept by.
+//! Every size above `MAX_SIZE` belongs to a single "large" class.
+
+use core::{cmp, iter, ops};
+
+/// The number of small size classes.
+pub const COUNT: usize = 16;
+/// The sizes of the small classes.
+///
+/// All of them are multiples of 16.
+pub const SIZES: [usize; COUNT] = [16, 32, 48, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+                                   448, 512];
+/// The largest size in a small class.
+pub const MAX_SIZE: usize = 512;
+
+/// The granularity of the lookup table.
+const GRANULE: usize = 16;
+/// The number of entries of the lookup table.
+///
+/// The last entry is shared by every size above `MAX_SIZE`.
+const LOOKUP_LEN: usize = MAX_SIZE / GRANULE + 2;
+/// The class index of every size, by the number of granules it spans.
+///
+/// Entry `n` is the class of the sizes `16 * n - 15` to `16 * n` (and entry 0 is the class of
+/// zero-sized requests). This must agree with `SIZES`, which `test_lookup` checks for every size.
+static LOOKUP: [u8; LOOKUP_LEN] = [
+    0, 0, 1, 2, 3, 4, 5, 6, 7,
+    8, 8, 9, 9, 10, 10, 11, 11,
+    12, 12, 12, 12, 13, 13, 13, 13, 14, 14, 14, 14, 15, 15, 15, 15,
+    COUNT as u8,
+];
+
+/// An iterator over every size class.
+///
+/// See `SizeClass::iter`.
+pub type Iter = iter::Map<ops::Range<usize>, fn(usize) -> SizeClass>;
+
+/// A size class.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct SizeClass(usize);
+
+impl SizeClass {
+    /// Get the class of some size.
+    ///
+    /// Zero-sized requests belong to the smallest class.
+    ///
+    /// This is on the hot path, so it is a single table lookup, with the large sizes clamped to
+    /// the last entry.
+    #[inline]
+    pub fn of(size: usize) -> SizeClass {
+        let granules = cmp::min(size.saturating_add(GRANULE - 1) / GRANULE, LOOKUP_LEN - 1);
+
+        SizeClass(LOOKUP[granules] as usize)
+    }
+
+    /// Iterate over every size class, the small classes first, and the large class last.
+    pub fn iter() -> Iter {
+        (0..COUNT + 1).map(SizeClass::from_index as fn(usize) -> SizeClass)
+    }
+
+    /// Get the class with some index.
+    ///
+    /// The small classes have indexes below `COUNT`, and the large class has index `COUNT`.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the index is larger than `COUNT`.
+    pub fn from_index(index: usize) -> SizeClass {
+        assert!(index <= COUNT, "Invalid size class index {}.", index);
+
+        SizeClass(index)
+    }
+
+    /// Get the class of the sizes above `MAX_SIZE`.
+    pub fn large() -> SizeClass {
+        SizeClass(COUNT)
+    }
+
+    /// Get the index of this class.
+    pub fn index(self) -> usize {
+        self.0
+    }
+
+    /// Get the largest size in this class.
+    ///
+    /// `None` is returned for the large class.
+    pub fn size(self) -> Option<usize> {
+        SIZES.get(self.0).cloned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use allocator;
+
+    #[test]
+    fn test_of() {
+        assert_eq!(SizeClass::of(0).size(), Some(16));
+        assert_eq!(SizeClass::of(16).size(), Some(16));
+        assert_eq!(SizeClass::of(17).size(), Some(32));
+        assert_eq!(SizeClass::of(500).size(), Some(512));
+        assert_eq!(SizeClass::of(513), SizeClass::large());
+        assert_eq!(SizeClass::large().size(), None);
+
+        for index in 0..COUNT + 1 {
+            assert_eq!(SizeClass::from_index(index).index(), index);
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        // The classes by definition: the smallest class holding the size.
+        let reference = |size| {
+            SizeClass(SIZES.iter().position(|&x| x >= size).unwrap_or(COUNT))
+        };
+
+        for size in 0..65537 {
+            assert_eq!(SizeClass::of(size), reference(size), "Size {} is misclassified.", size);
+        }
+        for &size in &[!0, !0 - 15, !0 / 2] {
+            assert_eq!(SizeClass::of(size), SizeClass::large());
+        }
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut iter = SizeClass::iter();
+        for index in 0..COUNT {
+            assert_eq!(iter.next().unwrap().index(), index);
+        }
+        assert_eq!(iter.next(), Some(SizeClass::large()));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn test_min_align() {
+        // Cells of every class are aligned to the minimal buffer alignment.
+        for &size in SIZES.iter() {
+            assert_eq!(size % allocator::MIN_ALIGN, 0);
+        }
+    }
+}
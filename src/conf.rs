@@ -0,0 +1,445 @@
+//! Runtime configuration.
+//!
+//! Besides the compile-time configuration (the cargo features and `shim::config`), some policies
+//! can be set at runtime, either through the setters in this module, or through the `RALLOC_CONF`
+//! environment variable.
+//!
+//! `RALLOC_CONF` is a comma-separated list of `key:value` pairs, e.g. `RALLOC_CONF=zero:1`. It is
+//! read once, when the allocator initializes, and setters called afterwards override it.
+//!
+//! `auto_trim:N` trims the allocator incrementally every N frees. See `set_auto_trim`.
+//!
+//! `max_alloc:N` rejects the allocations of more than N bytes. See `set_max_allocation`.
+//!
+//! `ordered:1` keeps the candidate slabs of each size class in address order. See
+//! `set_address_ordered`.
+//!
+//! `slab_decay:N` returns the empty slabs of size classes idle for N generations to the pool. See
+//! `set_slab_decay`.
+//!
+//! `adaptive_brk:0` grows the heap by a fixed extra, rather than one adapting to the growth rate.
+//! See `set_adaptive_brk`.
+//!
+//! `hot_first:0` takes the first fitting free block, rather than preferring the recently freed
+//! ones. See `set_hot_first`.
+//!
+//! `staged_growth:0` grows the heap while holding the lock of the global allocator. See
+//! `set_staged_growth`.
+//!
+//! With the `debugger` feature, `leak_report:1` writes a report of the leaked allocations to the
+//! log (stderr by default) when the process exits.
+//!
+//! With the `numa` feature, `numa_interleave:1` interleaves large allocations over the NUMA nodes.
+//! See `set_numa_interleave`.
+//!
+//! With the `realloc_slack` feature, `realloc_slack:N` keeps the tails below N percent of a block,
+//! when buffers shrink. See `set_realloc_slack`.
+
+use atomic::{self, AtomicBool, AtomicUsize};
+
+use shim::{config, env};
+
+use random;
+use sync::CachePadded;
+#[cfg(feature = "log")]
+use log;
+#[cfg(feature = "debugger")]
+use live;
+#[cfg(feature = "numa")]
+use numa;
+#[cfg(feature = "realloc_slack")]
+use slack;
+
+/// The runtime flags.
+pub static FLAGS: CachePadded<Flags> = CachePadded::new(Flags {
+    loaded: AtomicBool::new(false),
+    zero_on_free: AtomicBool::new(cfg!(feature = "security")),
+    bare_metal: AtomicBool::new(false),
+    deterministic: AtomicBool::new(false),
+    address_ordered: AtomicBool::new(false),
+    adaptive_brk: AtomicBool::new(true),
+    hot_first: AtomicBool::new(true),
+    staged_growth: AtomicBool::new(true),
+    auto_trim: AtomicUsize::new(0),
+    max_allocation: AtomicUsize::new(!0),
+    slab_decay: AtomicUsize::new(config::SLAB_DECAY),
+});
+
+/// The runtime flags.
+///
+/// These are read on the hot paths, but rarely written, so they are grouped on a cache line of
+/// their own, where no write-hot counter can invalidate them.
+pub struct Flags {
+    /// Has `RALLOC_CONF` been loaded?
+    loaded: AtomicBool,
+    /// Zero blocks when they are freed?
+    ///
+    /// This defaults to on, when the `security` feature is set.
+    zero_on_free: AtomicBool,
+    /// Is the allocator in bare-metal mode?
+    ///
+    /// See `allocator::init_from_buffer`.
+    pub bare_metal: AtomicBool,
+    /// Is the deterministic mode active?
+    ///
+    /// See `random::set_deterministic`.
+    pub deterministic: AtomicBool,
+    /// Are the partial slabs reused in address order?
+    address_ordered: AtomicBool,
+    /// Does the extra space of the growths of the heap adapt to the growth rate?
+    adaptive_brk: AtomicBool,
+    /// Are the recently freed blocks preferred by the allocations?
+    hot_first: AtomicBool,
+    /// Is the heap grown outside the lock of the global allocator?
+    staged_growth: AtomicBool,
+    /// The number of frees between the automatic trims, or zero, if they are off.
+    auto_trim: AtomicUsize,
+    /// The maximal size of an allocation.
+    max_allocation: AtomicUsize,
+    /// The number of generations after which idle size classes release their empty slabs, or
+    /// zero, if they never do.
+    slab_decay: AtomicUsize,
+}
+
+/// Load the `RALLOC_CONF` environment variable.
+///
+/// This is only done once. Subsequent calls are NOOPs.
+pub fn load() {
+    if FLAGS.loaded.swap(true, atomic::Ordering::SeqCst) {
+        return;
+    }
+
+    // Logging.
+    log!(NOTE, "Loading the runtime configuration.");
+
+    if let Some(x) = get_bool(b"zero") {
+        set_zero_on_free(x);
+    }
+    if let Some(x) = get_bool(b"deterministic") {
+        random::set_deterministic(x);
+    }
+    if let Some(x) = get_bool(b"ordered") {
+        set_address_ordered(x);
+    }
+    if let Some(x) = get_usize(b"auto_trim") {
+        set_auto_trim(x);
+    }
+    if let Some(x) = get_usize(b"max_alloc") {
+        set_max_allocation(x);
+    }
+    if let Some(x) = get_usize(b"slab_decay") {
+        set_slab_decay(x);
+    }
+    if let Some(x) = get_bool(b"adaptive_brk") {
+        set_adaptive_brk(x);
+    }
+    if let Some(x) = get_bool(b"hot_first") {
+        set_hot_first(x);
+    }
+    if let Some(x) = get_bool(b"staged_growth") {
+        set_staged_growth(x);
+    }
+    #[cfg(feature = "debugger")]
+    {
+        if get_bool(b"leak_report") == Some(true) {
+            live::register_leak_report();
+        }
+    }
+    #[cfg(feature = "numa")]
+    {
+        if let Some(x) = get_bool(b"numa_interleave") {
+            numa::set_numa_interleave(x);
+        }
+    }
+    #[cfg(feature = "realloc_slack")]
+    {
+        if let Some(x) = get_usize(b"realloc_slack") {
+            slack::set_realloc_slack(x);
+        }
+    }
+    #[cfg(feature = "log")]
+    {
+        if let Some(x) = get_usize(b"log") {
+            log::internal::set_level(x as u8);
+        }
+    }
+}
+
+/// Find the value of `key` in a configuration string.
+fn parse<'a>(conf: &'a [u8], key: &[u8]) -> Option<&'a [u8]> {
+    conf.split(|&x| x == b',').filter_map(|pair| {
+        // Split the pair at the first colon.
+        pair.iter().position(|&x| x == b':').and_then(|n| {
+            if &pair[..n] == key { Some(&pair[n + 1..]) } else { None }
+        })
+    }).last()
+}
+
+/// Get the raw value of `key` in `RALLOC_CONF`.
+pub fn get(key: &[u8]) -> Option<&'static [u8]> {
+    env::var(b"RALLOC_CONF").and_then(|conf| parse(conf, key))
+}
+
+/// Get the value of `key` in `RALLOC_CONF` as an integer.
+///
+/// `None` is returned if the key isn't set or isn't a decimal integer.
+pub fn get_usize(key: &[u8]) -> Option<usize> {
+    get(key).and_then(parse_usize)
+}
+
+/// Get the value of `key` in `RALLOC_CONF` as a boolean (`0` or `1`).
+pub fn get_bool(key: &[u8]) -> Option<bool> {
+    get_usize(key).map(|x| x != 0)
+}
+
+/// Parse a decimal integer.
+fn parse_usize(s: &[u8]) -> Option<usize> {
+    if s.is_empty() { return None; }
+
+    let mut res: usize = 0;
+    for &x in s {
+        if x < b'0' || x > b'9' { return None; }
+
+        res = match res.checked_mul(10).and_then(|res| res.checked_add((x - b'0') as usize)) {
+            Some(res) => res,
+            None => return None,
+        };
+    }
+
+    Some(res)
+}
+
+/// Set whether blocks should be zeroed when freed.
+///
+/// This defaults to on, when the `security` feature is set. It can also be set with the `zero`
+/// key in `RALLOC_CONF`.
+#[inline]
+pub fn set_zero_on_free(zero: bool) {
+    // Logging.
+    log!(NOTE, "Setting zero-on-free to {}.", zero);
+
+    FLAGS.zero_on_free.store(zero, atomic::Ordering::Relaxed);
+}
+
+/// Are blocks zeroed when freed?
+#[inline]
+pub fn zero_on_free() -> bool {
+    FLAGS.zero_on_free.load(atomic::Ordering::Relaxed)
+}
+
+/// Set whether the slabs are reused in address order.
+///
+/// By default, the slab most recently given a free cell is reused first (LIFO), which scatters
+/// the objects of a size class across many pages. In address order, the slabs with free cells are
+/// kept approximately sorted, such that allocations reuse the lowest cells, and the slabs towards
+/// the top of the heap empty out and can be trimmed. Linking a slab costs a bounded scan (see
+/// `config::SLAB_ORDER_SCAN`).
+///
+/// This is off by default. It can also be set with the `ordered` key in `RALLOC_CONF`.
+#[inline]
+pub fn set_address_ordered(ordered: bool) {
+    // Logging.
+    log!(NOTE, "Setting the address ordering of the slabs to {}.", ordered);
+
+    FLAGS.address_ordered.store(ordered, atomic::Ordering::Relaxed);
+}
+
+/// Are the slabs reused in address order?
+#[inline]
+pub fn address_ordered() -> bool {
+    FLAGS.address_ordered.load(atomic::Ordering::Relaxed)
+}
+
+/// Set the number of generations after which idle size classes release their empty slabs.
+///
+/// The slabs keep a few empty slabs of every size class (see `config::SLAB_EMPTY_KEEP`), which
+/// would otherwise stay out of the pool forever after a burst. The slab allocations are counted
+/// in generations of `config::SLAB_GENERATION`, and every time the generation advances (or a
+/// slab is created), the empty slabs of one size class, which hasn't allocated in the last
+/// `generations` generations, are returned to the pool, where they can coalesce and be trimmed.
+/// Flushing a single class at a time keeps the latency of an allocation bounded.
+///
+/// This defaults to `config::SLAB_DECAY`. Zero turns the decay off. It can also be set with the
+/// `slab_decay` key in `RALLOC_CONF`.
+#[inline]
+pub fn set_slab_decay(generations: usize) {
+    // Logging.
+    log!(NOTE, "Setting the slab decay to {} generations.", generations);
+
+    FLAGS.slab_decay.store(generations, atomic::Ordering::Relaxed);
+}
+
+/// Get the number of generations after which idle size classes release their empty slabs, or
+/// zero, if they never do.
+#[inline]
+pub fn slab_decay() -> usize {
+    FLAGS.slab_decay.load(atomic::Ordering::Relaxed)
+}
+
+/// Set whether the extra space of the growths of the heap adapts to the growth rate.
+///
+/// Growing the heap takes some extra space beyond the request, to save system calls. With the
+/// adaptive extra, the growths keep a moving average of the bytes they request per generation,
+/// and take the larger of it and the bytes requested in the current generation so far (within
+/// `config::BRK_EXTRA_MIN` and `config::BRK_EXTRA_MAX`). A burst of allocations thus grows the
+/// heap in large steps, while a small process stays small. Otherwise, the extra is a fixed
+/// function of the request (see `config::extra_brk`).
+///
+/// This is on by default. It can also be set with the `adaptive_brk` key in `RALLOC_CONF`.
+#[inline]
+pub fn set_adaptive_brk(adaptive: bool) {
+    // Logging.
+    log!(NOTE, "Setting the adaptive growth extra to {}.", adaptive);
+
+    FLAGS.adaptive_brk.store(adaptive, atomic::Ordering::Relaxed);
+}
+
+/// Does the extra space of the growths of the heap adapt to the growth rate?
+#[inline]
+pub fn adaptive_brk() -> bool {
+    FLAGS.adaptive_brk.load(atomic::Ordering::Relaxed)
+}
+
+/// Set whether the allocations prefer the recently freed ("hot") blocks.
+///
+/// An allocation takes the first hot block among the first `config::HOT_CANDIDATES` fitting free
+/// blocks, leaving the cold ones to pile up, such that they can be trimmed or advised. Otherwise,
+/// the first fitting block is taken.
+///
+/// This is on by default. It can also be set with the `hot_first` key in `RALLOC_CONF`.
+#[inline]
+pub fn set_hot_first(hot_first: bool) {
+    // Logging.
+    log!(NOTE, "Setting the preference of the hot blocks to {}.", hot_first);
+
+    FLAGS.hot_first.store(hot_first, atomic::Ordering::Relaxed);
+}
+
+/// Do the allocations prefer the recently freed blocks?
+#[inline]
+pub fn hot_first() -> bool {
+    FLAGS.hot_first.load(atomic::Ordering::Relaxed)
+}
+
+/// Set whether the heap is grown outside the lock of the global allocator.
+///
+/// If on, the global allocator is unlocked while growing the heap, and checked anew afterwards
+/// (see `allocator::global_alloc`). Otherwise, every thread refilling from the global allocator
+/// waits for the system call. This is mostly useful to compare the two.
+///
+/// This is on by default. It can also be set with the `staged_growth` key in `RALLOC_CONF`.
+#[inline]
+pub fn set_staged_growth(staged_growth: bool) {
+    // Logging.
+    log!(NOTE, "Setting the staged growth to {}.", staged_growth);
+
+    FLAGS.staged_growth.store(staged_growth, atomic::Ordering::Relaxed);
+}
+
+/// Is the heap grown outside the lock of the global allocator?
+#[inline]
+pub fn staged_growth() -> bool {
+    FLAGS.staged_growth.load(atomic::Ordering::Relaxed)
+}
+
+/// Trim the allocator automatically every `interval` frees.
+///
+/// Every `interval`th free checks whether a lot of memory is free (more than
+/// `config::AUTO_TRIM_MIN_FREE` bytes, and more than `1 / config::AUTO_TRIM_FRACTION` of the
+/// memory held), and if so, gives some of it back to the OS. Unlike `purge`, a single trim moves
+/// the program break at most once and advises at most `config::AUTO_TRIM_ADVISE_MAX` blocks, so
+/// no free gets much slower.
+///
+/// Without the `stats` feature, the live bytes are unknown, so only the first condition is
+/// checked.
+///
+/// Zero (the default) turns the automatic trimming off. It can also be set with the `auto_trim`
+/// key in `RALLOC_CONF`.
+#[inline]
+pub fn set_auto_trim(interval: usize) {
+    // Logging.
+    log!(NOTE, "Setting the automatic trimming interval to {}.", interval);
+
+    FLAGS.auto_trim.store(interval, atomic::Ordering::Relaxed);
+}
+
+/// Get the number of frees between the automatic trims, or zero, if they are off.
+#[inline]
+pub fn auto_trim() -> usize {
+    FLAGS.auto_trim.load(atomic::Ordering::Relaxed)
+}
+
+/// Set the maximal size of an allocation, in bytes.
+///
+/// Allocations (and reallocations growing a buffer) of more than `limit` bytes are rejected
+/// before the pool or the OS is touched: `try_alloc` returns `AllocErr::TooLarge`, and the other
+/// allocation functions return a null pointer. The limit can be raised or lowered at any time,
+/// and applies to the buffers allocated before as well, when they grow.
+///
+/// There is no limit by default. It can also be set with the `max_alloc` key in `RALLOC_CONF`.
+#[inline]
+pub fn set_max_allocation(limit: usize) {
+    // Logging.
+    log!(NOTE, "Setting the maximal allocation size to {}.", limit);
+
+    FLAGS.max_allocation.store(limit, atomic::Ordering::Relaxed);
+}
+
+/// Get the maximal size of an allocation, in bytes.
+#[inline]
+pub fn max_allocation() -> usize {
+    FLAGS.max_allocation.load(atomic::Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use core::mem;
+
+    use sync;
+
+    #[test]
+    fn test_flags_layout() {
+        // The flags fit a single line, which they have for themselves.
+        assert_eq!(mem::size_of_val(&FLAGS), sync::CACHE_LINE);
+        assert_eq!(&FLAGS as *const _ as usize % sync::CACHE_LINE, 0);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(b"zero:1", b"zero"), Some(&b"1"[..]));
+        assert_eq!(parse(b"a:2,zero:0,b:", b"zero"), Some(&b"0"[..]));
+        assert_eq!(parse(b"a:2,zero:0,b:", b"b"), Some(&b""[..]));
+        assert_eq!(parse(b"zero:0,zero:1", b"zero"), Some(&b"1"[..]));
+        assert_eq!(parse(b"zero", b"zero"), None);
+        assert_eq!(parse(b"zeros:1", b"zero"), None);
+        assert_eq!(parse(b"", b"zero"), None);
+    }
+
+    #[test]
+    fn test_parse_usize() {
+        assert_eq!(parse_usize(b"0"), Some(0));
+        assert_eq!(parse_usize(b"4096"), Some(4096));
+        assert_eq!(parse_usize(b""), None);
+        assert_eq!(parse_usize(b"1k"), None);
+        assert_eq!(parse_usize(b"99999999999999999999999999"), None);
+    }
+
+    #[test]
+    fn test_zero_on_free() {
+        set_zero_on_free(true);
+        assert!(zero_on_free());
+        set_zero_on_free(false);
+        assert!(!zero_on_free());
+    }
+
+    #[test]
+    fn test_auto_trim() {
+        assert_eq!(auto_trim(), 0);
+        set_auto_trim(64);
+        assert_eq!(auto_trim(), 64);
+        set_auto_trim(0);
+        assert_eq!(auto_trim(), 0);
+    }
+}
@@ -0,0 +1,12 @@
+//! Debugging utilities.
+//!
+//! This module is only available with the `debugger` or the `shadow_accounting` feature.
+
+#[cfg(feature = "debugger")]
+pub use live::{find_allocation, write_leaks, allocations_older_than, generation};
+#[cfg(feature = "debugger")]
+pub use dump::{dump_heap, dump_heap_with_contents, RawFd, Section, VERSION};
+#[cfg(all(feature = "debugger", feature = "std"))]
+pub use dump::{read_dump, parse_dump, HeapDump, DumpAllocation, DumpClass, DumpRegion};
+#[cfg(feature = "shadow_accounting")]
+pub use shadow::{reconcile, Counter, Divergence, Op, HISTORY};
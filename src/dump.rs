@@ -0,0 +1,575 @@
+//! Heap dumps.
+//!
+//! `dump_heap` writes a self-describing binary dump of the heap to a file descriptor, for offline
+//! analysis (e.g. after a crash). The dump is a document of the wire format (see `wire`) with the
+//! magic `RALLOCHD`, holding a chunk per record, the kind of which is its section (see `Section`).
+//! The fields of the records are:
+//!
+//! - Regions: the start, the end, the origin (see `origin_code`) and the flags of the origin.
+//! - Free blocks: the start and the size of every free block of the pools (see
+//!   `allocator::for_each_free`).
+//! - Live allocations: the start, the size, the age in generations, the tag, the line and column
+//!   of the site, the length of the file of the site, and the number of content bytes. The file
+//!   and the contents end the payload, so fields added later go before them.
+//! - Classes (with the `stats` feature): the index, the size (zero for the large class), and the
+//!   statistics of every size class.
+//! - Totals: the free bytes claimed by the pools, and the live bytes (with the `stats` feature,
+//!   otherwise `!0`).
+//!
+//! The dump is written through a fixed buffer, and never allocates.
+
+use core::{cmp, slice};
+
+use shim::syscalls;
+
+use region::{self, Origin, Region};
+use wire::{self, Writer};
+#[cfg(feature = "std")]
+use wire::WireError;
+use {allocator, live};
+#[cfg(feature = "sites")]
+use site;
+#[cfg(feature = "stats")]
+use {class::SizeClass, stats};
+#[cfg(feature = "tagging")]
+use tag;
+
+/// A raw file descriptor.
+pub type RawFd = i32;
+
+/// The magic of heap dumps.
+const MAGIC: &'static [u8; 8] = b"RALLOCHD";
+/// The version of the format.
+///
+/// The versions before 2 predate the wire format, and cannot be read.
+pub const VERSION: u32 = 2;
+/// The largest number of content bytes dumped of an allocation.
+///
+/// This keeps the chunks within the bounds of the wire format.
+const MAX_CONTENTS: usize = 1 << 30;
+
+/// The section of a record, which is the kind of its chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Section {
+    /// The end of the dump.
+    ///
+    /// This is the end chunk of the wire format.
+    End = 0,
+    /// The region table.
+    Regions = 1,
+    /// The free blocks of the pools.
+    Free = 2,
+    /// The live allocations.
+    Live = 3,
+    /// The statistics of the size classes.
+    Classes = 4,
+    /// The totals at the time of the dump.
+    Totals = 5,
+}
+
+/// A writer of a document to a file descriptor through a fixed buffer.
+struct Sink<'a> {
+    /// The file descriptor.
+    fd: usize,
+    /// The writer filling the buffer.
+    writer: Writer<'a>,
+}
+
+impl<'a> Sink<'a> {
+    /// Write the chunk of a record.
+    ///
+    /// The payload holds `fields`, followed by `tail` bytes, which must be written next (see
+    /// `bytes`).
+    fn record(&mut self, section: Section, fields: &[u64], tail: usize) -> Result<(), usize> {
+        if self.writer.chunk(section as u32, fields, tail).is_err() {
+            self.flush()?;
+
+            // The buffer is empty now, and holds the head of any record.
+            self.writer.chunk(section as u32, fields, tail)
+                .expect("Record larger than the buffer.");
+        }
+
+        Ok(())
+    }
+
+    /// Write bytes of a payload.
+    ///
+    /// If the bytes don't fit the buffer, they are written directly.
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), usize> {
+        if self.writer.bytes(bytes).is_err() {
+            self.flush()?;
+            syscalls::write_all(self.fd, bytes)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write out the buffer.
+    fn flush(&mut self) -> Result<(), usize> {
+        syscalls::write_all(self.fd, self.writer.written())?;
+        self.writer.clear();
+
+        Ok(())
+    }
+}
+
+/// Get the code of an origin, along with its flags.
+///
+/// Brk is 0, mappings are 1 (with the flags 1 if anonymous, 2 if locked, and 4 if interleaved),
+/// static buffers are 2, and preallocated memory is 3.
+fn origin_code(origin: Origin) -> (u64, u64) {
+    match origin {
+        Origin::Brk => (0, 0),
+        Origin::Mmap { fd_less, locked, interleaved } => {
+            (1, fd_less as u64 | (locked as u64) << 1 | (interleaved as u64) << 2)
+        },
+        Origin::Static => (2, 0),
+        Origin::Preallocated => (3, 0),
+    }
+}
+
+/// Write a dump of the heap to a file descriptor.
+///
+/// See the module documentation for the format. No contents of the live allocations are written.
+///
+/// # Errors
+///
+/// If writing fails, the error number is returned, and the dump is cut short.
+pub fn dump_heap(fd: RawFd) -> Result<(), usize> {
+    unsafe {
+        // Without a budget, no allocation is read.
+        dump_heap_with_contents(fd, 0)
+    }
+}
+
+/// Write a dump of the heap to a file descriptor, including the contents of the live allocations.
+///
+/// This is like `dump_heap`, but at most `budget` bytes of contents are written, from the start of
+/// every live allocation, in address order, until the budget is used up.
+///
+/// # Errors
+///
+/// If writing fails, the error number is returned, and the dump is cut short.
+///
+/// # Safety
+///
+/// The contents are read without locking, so no live allocation may be freed meanwhile.
+pub unsafe fn dump_heap_with_contents(fd: RawFd, mut budget: usize) -> Result<(), usize> {
+    log!(CALL, "Dumping the heap to file descriptor {}.", fd);
+
+    let mut buf = [0; 4096];
+    let mut w = Sink {
+        fd: fd as usize,
+        writer: Writer::new(&mut buf),
+    };
+    let mut res = Ok(());
+
+    // The header.
+    w.writer.header(MAGIC, VERSION).expect("Header larger than the buffer.");
+
+    // The regions.
+    region::for_each(|&Region { start, end, origin }| if res.is_ok() {
+        let (code, flags) = origin_code(origin);
+        res = w.record(Section::Regions, &[start as u64, end as u64, code, flags], 0);
+    });
+    res?;
+
+    // The free blocks.
+    let free_bytes = allocator::for_each_free(|ptr, size| if res.is_ok() {
+        res = w.record(Section::Free, &[ptr as u64, size as u64], 0);
+    });
+    res?;
+
+    // The live allocations.
+    live::for_each(|ptr, size, age| if res.is_ok() {
+        #[cfg(feature = "tagging")]
+        let tag = tag::get(ptr);
+        #[cfg(not(feature = "tagging"))]
+        let tag = 0;
+
+        #[cfg(feature = "sites")]
+        let (line, column, file) = match site::get(ptr) {
+            Some(site) => (site.line, site.column, site.file),
+            None => (0, 0, ""),
+        };
+        #[cfg(not(feature = "sites"))]
+        let (line, column, file) = (0, 0, "");
+
+        let contents = cmp::min(cmp::min(size, budget), MAX_CONTENTS);
+        budget -= contents;
+
+        res = (|| {
+            w.record(Section::Live, &[ptr as u64, size as u64, age, tag as u64, line as u64,
+                                      column as u64, file.len() as u64, contents as u64],
+                     file.len() + contents)?;
+            w.bytes(file.as_bytes())?;
+
+            // The allocation is live (see the safety section), and `contents` is at most its size.
+            w.bytes(slice::from_raw_parts(ptr, contents))
+        })();
+    });
+    res?;
+
+    // The classes.
+    #[cfg(feature = "stats")]
+    {
+        for class in SizeClass::iter() {
+            let x = stats::class(class);
+            w.record(Section::Classes, &[class.index() as u64, class.size().unwrap_or(0) as u64,
+                                         x.count as u64, x.allocs as u64, x.frees as u64,
+                                         x.bytes as u64], 0)?;
+        }
+    }
+
+    // The totals.
+    #[cfg(feature = "stats")]
+    let live_bytes = stats::live_bytes() as u64;
+    #[cfg(not(feature = "stats"))]
+    let live_bytes = !0;
+    w.record(Section::Totals, &[free_bytes as u64, live_bytes], 0)?;
+
+    w.record(Section::End, &[], 0)?;
+    w.flush()
+}
+
+/// A region of a heap dump.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DumpRegion {
+    /// The address of the first byte.
+    pub start: u64,
+    /// The address after the last byte.
+    pub end: u64,
+    /// The origin (see `origin_code`).
+    pub origin: u64,
+    /// The flags of the origin.
+    pub flags: u64,
+}
+
+/// A live allocation of a heap dump.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DumpAllocation {
+    /// The address of the allocation.
+    pub start: u64,
+    /// The size of the allocation.
+    pub size: u64,
+    /// The age of the allocation, in generations.
+    pub age: u64,
+    /// The tag of the allocation (zero if untagged).
+    pub tag: u64,
+    /// The file of the site of the allocation (empty if unattributed).
+    pub file: ::std::string::String,
+    /// The line of the site.
+    pub line: u64,
+    /// The column of the site.
+    pub column: u64,
+    /// The first bytes of the allocation, as far as the budget allowed.
+    pub contents: ::std::vec::Vec<u8>,
+}
+
+/// The statistics of a size class in a heap dump.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DumpClass {
+    /// The index of the class.
+    pub index: u64,
+    /// The size of the class (zero for the large class).
+    pub size: u64,
+    /// The number of live allocations.
+    pub count: u64,
+    /// The cumulative number of allocations.
+    pub allocs: u64,
+    /// The cumulative number of frees.
+    pub frees: u64,
+    /// The number of bytes in live allocations.
+    pub bytes: u64,
+}
+
+/// A heap dump read back.
+///
+/// See `read_dump`.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeapDump {
+    /// The version of the format.
+    pub version: u32,
+    /// The regions.
+    pub regions: ::std::vec::Vec<DumpRegion>,
+    /// The free blocks as their start and size.
+    pub free: ::std::vec::Vec<(u64, u64)>,
+    /// The live allocations.
+    pub live: ::std::vec::Vec<DumpAllocation>,
+    /// The statistics of the size classes (empty without the `stats` feature).
+    pub classes: ::std::vec::Vec<DumpClass>,
+    /// The free bytes claimed by the pools.
+    pub free_bytes: u64,
+    /// The live bytes according to the statistics (`None` without the `stats` feature).
+    pub live_bytes: Option<u64>,
+}
+
+#[cfg(feature = "std")]
+impl HeapDump {
+    /// Get the total size of the free blocks.
+    pub fn free_total(&self) -> u64 {
+        self.free.iter().map(|&(_, size)| size).sum()
+    }
+
+    /// Get the total size of the live allocations.
+    pub fn live_total(&self) -> u64 {
+        self.live.iter().map(|x| x.size).sum()
+    }
+}
+
+/// Read a heap dump written by `dump_heap`.
+///
+/// This is meant for tests and offline tools, and is only available with the `std` feature. The
+/// whole dump is read, and then parsed (see `parse_dump`).
+///
+/// # Errors
+///
+/// If reading fails, or the dump is malformed (or of an unknown version), an error is returned.
+#[cfg(feature = "std")]
+pub fn read_dump<R: ::std::io::Read>(mut r: R) -> ::std::io::Result<HeapDump> {
+    let mut bytes = ::std::vec::Vec::new();
+    r.read_to_end(&mut bytes)?;
+
+    parse_dump(&bytes).map_err(|err| {
+        ::std::io::Error::new(::std::io::ErrorKind::InvalidData, match err {
+            WireError::Magic => "Not a heap dump.",
+            WireError::Version(_) => "Unknown version of the heap dump.",
+            WireError::Truncated => "The heap dump is truncated.",
+            WireError::Malformed => "The heap dump is malformed.",
+        })
+    })
+}
+
+/// Parse a heap dump written by `dump_heap`.
+///
+/// Chunks of unknown kinds, and fields unknown to this version, are skipped.
+///
+/// # Errors
+///
+/// If the dump is malformed (or of an unknown version), the error is returned. A file of a site,
+/// which is not UTF-8, is malformed.
+#[cfg(feature = "std")]
+pub fn parse_dump(bytes: &[u8]) -> Result<HeapDump, WireError> {
+    let chunks = wire::Reader::new(bytes, MAGIC)?;
+    if chunks.version() < 2 || chunks.version() > VERSION {
+        return Err(WireError::Version(chunks.version()));
+    }
+
+    let mut dump = HeapDump::default();
+    dump.version = chunks.version();
+
+    for chunk in chunks {
+        let chunk = chunk?;
+        let mut x = chunk.fields();
+
+        match chunk.kind {
+            1 => dump.regions.push(DumpRegion {
+                start: x.u64()?,
+                end: x.u64()?,
+                origin: x.u64()?,
+                flags: x.u64()?,
+            }),
+            2 => dump.free.push((x.u64()?, x.u64()?)),
+            3 => {
+                let (start, size, age, tag) = (x.u64()?, x.u64()?, x.u64()?, x.u64()?);
+                let (line, column) = (x.u64()?, x.u64()?);
+                let (file_len, contents_len) = (x.u64()?, x.u64()?);
+
+                // The file and the contents end the payload.
+                let rest = x.rest();
+                let tail = match file_len.checked_add(contents_len) {
+                    Some(tail) if tail <= rest.len() as u64 => &rest[rest.len() - tail as usize..],
+                    _ => return Err(WireError::Malformed),
+                };
+                let (file, contents) = tail.split_at(file_len as usize);
+
+                dump.live.push(DumpAllocation {
+                    start: start,
+                    size: size,
+                    age: age,
+                    tag: tag,
+                    file: ::std::string::String::from_utf8(file.to_vec())
+                        .map_err(|_| WireError::Malformed)?,
+                    line: line,
+                    column: column,
+                    contents: contents.to_vec(),
+                });
+            },
+            4 => dump.classes.push(DumpClass {
+                index: x.u64()?,
+                size: x.u64()?,
+                count: x.u64()?,
+                allocs: x.u64()?,
+                frees: x.u64()?,
+                bytes: x.u64()?,
+            }),
+            5 => {
+                dump.free_bytes = x.u64()?;
+                let live_bytes = x.u64()?;
+                dump.live_bytes = if live_bytes == !0 { None } else { Some(live_bytes) };
+            },
+            // Records of later versions.
+            _ => {},
+        }
+    }
+
+    Ok(dump)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+
+    use std::string::String;
+    use std::vec::Vec;
+
+    use test_util::Rng;
+
+    /// Write a dump into `buf`, with `f` writing the records, and parse it.
+    fn parse<F: FnOnce(&mut Writer)>(buf: &mut [u8], f: F) -> Result<HeapDump, WireError> {
+        let len = {
+            let mut w = Writer::new(buf);
+            w.header(MAGIC, VERSION).unwrap();
+            f(&mut w);
+            w.end().unwrap();
+
+            w.written().len()
+        };
+
+        parse_dump(&buf[..len])
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut buf = [0; 1024];
+        let dump = parse(&mut buf, |w| {
+            w.chunk(Section::Regions as u32, &[0x1000, 0x3000, 1, 5], 0).unwrap();
+            w.chunk(Section::Free as u32, &[0x1000, 0x100], 0).unwrap();
+            w.chunk(Section::Free as u32, &[0x2000, 0x80], 0).unwrap();
+            w.chunk(Section::Live as u32, &[0x1100, 3, 2, 7, 10, 20, 5, 3], 8).unwrap();
+            w.bytes(b"lib.rsabc").unwrap();
+            w.chunk(Section::Live as u32, &[0x1200, 9, 0, 0, 0, 0, 0, 0], 0).unwrap();
+            w.chunk(Section::Classes as u32, &[1, 16, 2, 5, 3, 32], 0).unwrap();
+            w.chunk(Section::Totals as u32, &[0x180, !0], 0).unwrap();
+        }).unwrap();
+
+        assert_eq!(dump, HeapDump {
+            version: VERSION,
+            regions: [DumpRegion { start: 0x1000, end: 0x3000, origin: 1, flags: 5 }].to_vec(),
+            free: [(0x1000, 0x100), (0x2000, 0x80)].to_vec(),
+            live: [
+                DumpAllocation {
+                    start: 0x1100,
+                    size: 3,
+                    age: 2,
+                    tag: 7,
+                    file: String::from("lib.rs"),
+                    line: 10,
+                    column: 20,
+                    contents: b"abc".to_vec(),
+                },
+                DumpAllocation {
+                    start: 0x1200,
+                    size: 9,
+                    age: 0,
+                    tag: 0,
+                    file: String::new(),
+                    line: 0,
+                    column: 0,
+                    contents: Vec::new(),
+                },
+            ].to_vec(),
+            classes: [DumpClass {
+                index: 1,
+                size: 16,
+                count: 2,
+                allocs: 5,
+                frees: 3,
+                bytes: 32,
+            }].to_vec(),
+            free_bytes: 0x180,
+            live_bytes: None,
+        });
+        assert_eq!(dump.free_total(), dump.free_bytes);
+    }
+
+    #[test]
+    fn test_forward_compatibility() {
+        let mut buf = [0; 1024];
+        let dump = parse(&mut buf, |w| {
+            // A section of a later version.
+            w.chunk(100, &[1, 2, 3], 2).unwrap();
+            w.bytes(b"??").unwrap();
+            // Records with fields of a later version.
+            w.chunk(Section::Free as u32, &[0x1000, 0x100, 42], 0).unwrap();
+            w.chunk(Section::Live as u32, &[0x1100, 2, 0, 0, 1, 1, 1, 2, 42, 43], 3).unwrap();
+            w.bytes(b"fxy").unwrap();
+            w.chunk(Section::Totals as u32, &[0x100, 2, 42], 0).unwrap();
+        }).unwrap();
+
+        assert_eq!(dump.free, [(0x1000, 0x100)]);
+        assert_eq!(dump.live.len(), 1);
+        assert_eq!(dump.live[0].file, "f");
+        assert_eq!(dump.live[0].contents, b"xy");
+        assert_eq!(dump.live_bytes, Some(2));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut buf = [0; 256];
+
+        // Missing fields.
+        assert_eq!(parse(&mut buf, |w| w.chunk(Section::Free as u32, &[1], 0).unwrap()),
+                   Err(WireError::Malformed));
+        assert_eq!(parse(&mut buf, |w| {
+            w.chunk(Section::Live as u32, &[0, 0, 0, 0, 0, 0, 4, !0], 4).unwrap();
+            w.bytes(b"file").unwrap();
+        }), Err(WireError::Malformed));
+        // A file, which is not UTF-8.
+        assert_eq!(parse(&mut buf, |w| {
+            w.chunk(Section::Live as u32, &[0, 0, 0, 0, 0, 0, 1, 0], 1).unwrap();
+            w.bytes(&[0xFF]).unwrap();
+        }), Err(WireError::Malformed));
+
+        // The versions before the wire format, and after this one, are unknown.
+        let len = {
+            let mut w = Writer::new(&mut buf);
+            w.header(MAGIC, 1).unwrap();
+            w.end().unwrap();
+            w.written().len()
+        };
+        assert_eq!(parse_dump(&buf[..len]), Err(WireError::Version(1)));
+        buf[8] = VERSION as u8 + 1;
+        assert_eq!(parse_dump(&buf[..len]), Err(WireError::Version(VERSION + 1)));
+        buf[8] = VERSION as u8;
+        assert!(parse_dump(&buf[..len]).is_ok());
+
+        assert_eq!(parse_dump(&buf[..len - 1]), Err(WireError::Truncated));
+        assert_eq!(parse_dump(b"RALLOCTR"), Err(WireError::Magic));
+    }
+
+    #[test]
+    fn test_fuzz() {
+        let mut rng = Rng::new(0xD0);
+        let mut buf = [0; 256];
+        {
+            let mut w = Writer::new(&mut buf);
+            w.header(MAGIC, VERSION).unwrap();
+        }
+
+        for _ in 0..20000 {
+            let len = wire::HEADER_SIZE + rng.below(buf.len() - wire::HEADER_SIZE + 1);
+            for x in buf[wire::HEADER_SIZE..len].iter_mut() {
+                // Small bytes make plausible sections, lengths and fields.
+                *x = if rng.below(2) == 0 { rng.get() as u8 } else { rng.below(6) as u8 };
+            }
+
+            // Parsing anything never panics.
+            let _ = parse_dump(&buf[..len]);
+        }
+    }
+}
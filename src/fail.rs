@@ -2,26 +2,86 @@
 
 use prelude::*;
 
-use core::sync::atomic::{self, AtomicPtr};
-use core::mem;
+use atomic::{self, AtomicPtr};
+use core::{fmt, mem};
 
 use shim::config;
+pub use shim::syscalls::GrowError;
 
 #[cfg(feature = "tls")]
 use tls;
 
 /// The global OOM handler.
-static OOM_HANDLER: AtomicPtr<()> = AtomicPtr::new(config::default_oom_handler as *mut ());
+static OOM_HANDLER: AtomicPtr<()> = AtomicPtr::new(default_oom_handler as *mut ());
 #[cfg(feature = "tls")]
 tls! {
     /// The thread-local OOM handler.
-    static THREAD_OOM_HANDLER: MoveCell<Option<fn() -> !>> = MoveCell::new(None);
+    static THREAD_OOM_HANDLER: MoveCell<Option<fn(AllocErr) -> !>> = MoveCell::new(None);
 }
 
-/// Call the OOM handler.
+/// An allocation error.
+///
+/// This is returned by the fallible allocation functions.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AllocErr {
+    /// The OS was unable to provide the memory.
+    ///
+    /// This carries the failing call and its error number.
+    Os(GrowError),
+    /// An internal limit of the allocator was reached.
+    ///
+    /// E.g. a table of the allocator (such as the region registry) is full and cannot grow.
+    LimitReached,
+    /// The OS was unable to lock the memory into RAM.
+    ///
+    /// This carries the error number (usually `ENOMEM`, when `RLIMIT_MEMLOCK` is exceeded).
+    MemoryLock(usize),
+    /// The allocation exceeds the maximal allocation size.
+    ///
+    /// See `set_max_allocation`.
+    TooLarge {
+        /// The requested size.
+        requested: usize,
+        /// The maximal allocation size.
+        limit: usize,
+    },
+    /// No backend can provide the alignment.
+    ///
+    /// Alignments beyond the pool's limit are mapped directly, so this only happens where there is
+    /// no memory mapping (e.g. in bare-metal mode). This carries the requested alignment.
+    UnsupportedAlignment(usize),
+}
+
+impl fmt::Display for AllocErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AllocErr::Os(err) => write!(f, "the OS couldn't provide the memory ({})", err),
+            AllocErr::LimitReached => write!(f, "an internal limit of the allocator was reached"),
+            AllocErr::MemoryLock(errno) => {
+                write!(f, "the memory couldn't be locked (error {})", errno)
+            },
+            AllocErr::TooLarge { requested, limit } => {
+                write!(f, "{} bytes exceed the maximal allocation size of {}", requested, limit)
+            },
+            AllocErr::UnsupportedAlignment(align) => {
+                write!(f, "no backend can provide the alignment {}", align)
+            },
+        }
+    }
+}
+
+/// The default OOM handler.
+///
+/// This defers to the one of the shim.
+#[cold]
+fn default_oom_handler(_err: AllocErr) -> ! {
+    config::default_oom_handler()
+}
+
+/// Call the OOM handler with the error.
 ///
 /// This is used one out-of-memory errors, and will never return. Usually, it simply consists
-/// of aborting the process.
+/// of aborting the process. The error is logged first.
 ///
 /// # An important note
 ///
@@ -30,14 +90,17 @@ tls! {
 ///
 /// The rule of thumb is that this should be called, if and only if unwinding (which allocates)
 /// will hit the same error.
-pub fn oom() -> ! {
+#[cold]
+pub fn oom(err: AllocErr) -> ! {
+    log!(ERROR, "Out of memory: {}.", err);
+
     // If TLS is enabled, we will use the thread-local OOM.
     #[cfg(feature = "tls")]
     {
         if let Some(handler) = THREAD_OOM_HANDLER.with(|x| x.replace(None)) {
             log!(DEBUG, "Calling the local OOM handler.");
 
-            handler();
+            handler(err);
         }
     }
 
@@ -47,15 +110,49 @@ pub fn oom() -> ! {
         // LAST AUDIT: 2016-08-21 (Ticki).
 
         // Transmute the atomic pointer to a function pointer and call it.
-        (mem::transmute::<_, fn() -> !>(OOM_HANDLER.load(atomic::Ordering::SeqCst)))()
+        (mem::transmute::<_, fn(AllocErr) -> !>(OOM_HANDLER.load(atomic::Ordering::SeqCst)))(err)
     }
 }
 
+/// Abort on the use of a poisoned pool.
+///
+/// An operation on the pool panicked midway, so its blocks can no longer be trusted. Going on
+/// (or panicking again, which allocates) could turn them into heap corruption, so the process is
+/// aborted, whatever the features.
+#[cold]
+pub fn poisoned() -> ! {
+    config::log("ralloc: aborting, as a pool was used after an operation on it panicked\n");
+
+    unsafe {
+        // Aborting is safe no matter what.
+        ::core::intrinsics::abort();
+    }
+}
+
+/// Handle heap corruption detected at `ptr`.
+///
+/// The corruption is logged. With the `security` feature, the process is aborted, since the heap
+/// can no longer be trusted (and unwinding might be exploitable). Otherwise, this panics.
+#[cold]
+pub fn corruption(ptr: *mut u8) -> ! {
+    log!(ERROR, "Heap corruption detected at {:?}.", ptr);
+
+    #[cfg(feature = "security")]
+    unsafe {
+        // Aborting is safe no matter what.
+        ::core::intrinsics::abort();
+    }
+
+    #[cfg(not(feature = "security"))]
+    panic!("Heap corruption detected at {:?}.", ptr);
+}
+
 /// Set the OOM handler.
 ///
-/// This is called when the process is out-of-memory.
+/// This is called when the process is out-of-memory, with the error (telling e.g. whether the OS
+/// refused to give more memory, or some limit was reached).
 #[inline]
-pub fn set_oom_handler(handler: fn() -> !) {
+pub fn set_oom_handler(handler: fn(AllocErr) -> !) {
     // Logging...
     log!(NOTE, "Setting the global OOM handler.");
 
@@ -69,7 +166,7 @@ pub fn set_oom_handler(handler: fn() -> !) {
 /// This might panic if a thread OOM handler already exists.
 #[inline]
 #[cfg(feature = "tls")]
-pub fn set_thread_oom_handler(handler: fn() -> !) {
+pub fn set_thread_oom_handler(handler: fn(AllocErr) -> !) {
     // Logging...
     log!(NOTE, "Setting the thread OOM handler.");
 
@@ -91,28 +188,30 @@ mod test {
     #[test]
     #[should_panic]
     fn test_panic_oom() {
-        fn panic() -> ! {
+        fn panic(_: AllocErr) -> ! {
             panic!("cats are not cute.");
         }
 
         set_oom_handler(panic);
-        oom();
+        oom(AllocErr::LimitReached);
     }
 
     #[test]
     #[should_panic]
     #[cfg(feature = "tls")]
     fn test_panic_thread_oom() {
-        fn infinite() -> ! {
+        fn infinite(_: AllocErr) -> ! {
             #[allow(empty_loop)]
             loop {}
         }
-        fn panic() -> ! {
+        fn panic(err: AllocErr) -> ! {
+            assert_eq!(err, AllocErr::Os(GrowError::Mmap(::shim::syscalls::EMFILE)));
+
             panic!("cats are not cute.");
         }
 
         set_oom_handler(infinite);
         set_thread_oom_handler(panic);
-        oom();
+        oom(AllocErr::Os(GrowError::Mmap(::shim::syscalls::EMFILE)));
     }
 }
@@ -0,0 +1,406 @@
+//! The C interface.
+//!
+//! This exposes the allocator and its independent heaps to C through the functions declared in
+//! `include/ralloc.h`. Every function returns an error code (`RALLOC_OK` on success) and hands
+//! its results through out-pointers.
+//!
+//! Heaps are passed around as opaque handles, which carry a magic number. Handles are validated
+//! before use, so null, foreign and destroyed handles are rejected with `RALLOC_EHANDLE` (the
+//! latter on a best-effort basis, as the memory of a destroyed handle can be reused).
+//!
+//! Panics cannot be caught in `no_std`, so rather than unwinding into C, every argument, which
+//! could make the allocator panic (e.g. an invalid alignment, or freeing a pointer to a heap not
+//! owning it), is checked at the boundary and rejected with an error code. Out-of-memory
+//! conditions go to the OOM handler, as usual.
+
+use prelude::*;
+
+use core::{cmp, mem, ptr};
+
+use allocator::{self, PurgeReport};
+use fail::AllocErr;
+use heap::{self, DropPolicy, Heap};
+use {mapped, region};
+
+/// The call succeeded.
+pub const RALLOC_OK: i32 = 0;
+/// An argument is invalid (e.g. the alignment is not a power of two).
+pub const RALLOC_EINVAL: i32 = -1;
+/// The heap handle is invalid (null, foreign or destroyed).
+pub const RALLOC_EHANDLE: i32 = -2;
+/// The memory couldn't be provided, or the request exceeds the maximal allocation size.
+pub const RALLOC_ENOMEM: i32 = -3;
+/// An internal limit was reached (e.g. `MAX_HEAPS` heaps are live).
+pub const RALLOC_ELIMIT: i32 = -4;
+/// The operation is unsupported in this build or on this platform.
+pub const RALLOC_EUNSUPPORTED: i32 = -5;
+
+/// The magic number of live heap handles.
+const MAGIC: u32 = 0x5241_4c48;
+
+/// A heap handle (`ralloc_heap` in C).
+pub struct HeapHandle {
+    /// The magic number, which is `MAGIC` while the handle is live.
+    magic: u32,
+    /// The heap.
+    heap: Heap,
+}
+
+/// The statistics of a heap (`ralloc_heap_stats` in C).
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct HeapStats {
+    /// The number of bytes taken by the heap.
+    pub owned_bytes: usize,
+    /// The number of free bytes in the heap.
+    pub free_bytes: usize,
+    /// The number of bytes in the live buffers of the heap.
+    pub live_bytes: usize,
+}
+
+/// The statistics of the allocator (`ralloc_stats` in C).
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct Stats {
+    /// The number of bytes in live allocations.
+    pub live_bytes: usize,
+    /// The number of bytes used from the bootstrap arena.
+    pub bootstrap_bytes: usize,
+    /// The number of live secure allocations.
+    pub secure_count: usize,
+    /// The number of bytes in live secure allocations.
+    pub secure_bytes: usize,
+    /// The number of slabs.
+    pub slab_count: usize,
+    /// The number of bytes in allocated slab cells.
+    pub slab_bytes: usize,
+}
+
+/// Check the layout of the structures shared with C against `include/ralloc.h` at compile time.
+///
+/// Transmuting between types of different sizes doesn't compile. Every field is a `size_t`, so
+/// equal sizes mean equal layouts. The header asserts the same sizes on the C side.
+#[allow(dead_code)]
+unsafe fn assert_layout() {
+    mem::transmute::<HeapStats, [usize; 3]>(mem::uninitialized());
+    mem::transmute::<Stats, [usize; 6]>(mem::uninitialized());
+    mem::transmute::<PurgeReport, [usize; 5]>(mem::uninitialized());
+}
+
+/// Convert an allocation error to an error code.
+fn code(err: AllocErr) -> i32 {
+    match err {
+        AllocErr::LimitReached => RALLOC_ELIMIT,
+        AllocErr::UnsupportedAlignment(_) => RALLOC_EUNSUPPORTED,
+        _ => RALLOC_ENOMEM,
+    }
+}
+
+/// Check an alignment passed from C.
+fn check_align(align: usize) -> Result<(), i32> {
+    if !align.is_power_of_two() {
+        Err(RALLOC_EINVAL)
+    } else if align > mapped::max_align() {
+        Err(RALLOC_EUNSUPPORTED)
+    } else {
+        Ok(())
+    }
+}
+
+/// Get the heap behind a handle.
+///
+/// The handle must lie in the memory of the allocator and carry the magic number.
+unsafe fn heap<'a>(handle: *mut HeapHandle) -> Result<&'a Heap, i32> {
+    let addr = handle as usize;
+    if handle.is_null() || addr % mem::align_of::<HeapHandle>() != 0 {
+        return Err(RALLOC_EHANDLE);
+    }
+
+    // The handle must be readable, so it must be within a single region of ours.
+    let end = addr.checked_add(mem::size_of::<HeapHandle>() - 1).ok_or(RALLOC_EHANDLE)?;
+    match (region::lookup(addr), region::lookup(end)) {
+        (Some(a), Some(b)) if a == b => (),
+        _ => return Err(RALLOC_EHANDLE),
+    }
+
+    if ptr::read_volatile(&(*handle).magic) != MAGIC {
+        return Err(RALLOC_EHANDLE);
+    }
+
+    Ok(&(*handle).heap)
+}
+
+/// Write a result to an out-pointer, failing if it is null.
+unsafe fn put<T>(out: *mut T, x: T) -> Result<(), i32> {
+    if out.is_null() {
+        return Err(RALLOC_EINVAL);
+    }
+
+    ptr::write(out, x);
+    Ok(())
+}
+
+/// Convert a result to an error code.
+fn done(res: Result<(), i32>) -> i32 {
+    match res {
+        Ok(()) => RALLOC_OK,
+        Err(code) => code,
+    }
+}
+
+/// Allocate `size` bytes aligned to `align`, storing the buffer in `*out`.
+#[no_mangle]
+pub unsafe extern fn ralloc_alloc(size: usize, align: usize, out: *mut *mut u8) -> i32 {
+    done((|| {
+        check_align(align)?;
+        if out.is_null() {
+            return Err(RALLOC_EINVAL);
+        }
+
+        let ptr = allocator::try_alloc(size, align).map_err(code)?;
+        put(out, ptr)
+    })())
+}
+
+/// Reallocate the buffer `ptr` of `old_size` bytes to `size` bytes, storing the new buffer in
+/// `*out`.
+///
+/// On failure, the buffer is left intact.
+#[no_mangle]
+pub unsafe extern fn ralloc_realloc(ptr: *mut u8, old_size: usize, size: usize, align: usize,
+                                    out: *mut *mut u8) -> i32 {
+    done((|| {
+        check_align(align)?;
+        if ptr.is_null() || out.is_null() || region::lookup(ptr as usize).is_none() {
+            return Err(RALLOC_EINVAL);
+        }
+        if size > old_size {
+            allocator::check_size(size).map_err(code)?;
+        }
+
+        let res = allocator::realloc(ptr, old_size, size, align);
+        if res.is_null() {
+            return Err(RALLOC_ENOMEM);
+        }
+
+        put(out, res)
+    })())
+}
+
+/// Free the buffer `ptr` of `size` bytes.
+///
+/// Null pointers are ignored. Pointers outside the memory of the allocator are rejected. Buffers
+/// of heaps are freed to their heap (see `route_free`).
+#[no_mangle]
+pub unsafe extern fn ralloc_free(ptr: *mut u8, size: usize) -> i32 {
+    if ptr.is_null() {
+        return RALLOC_OK;
+    }
+    if region::lookup(ptr as usize).is_none() {
+        return RALLOC_EINVAL;
+    }
+
+    heap::route_free(ptr, size);
+    RALLOC_OK
+}
+
+/// Create a heap, storing its handle in `*out`.
+#[no_mangle]
+pub unsafe extern fn ralloc_heap_new(out: *mut *mut HeapHandle) -> i32 {
+    done((|| {
+        if out.is_null() {
+            return Err(RALLOC_EINVAL);
+        }
+
+        // Destroying a heap frees its buffers, so it reclaims its memory.
+        let heap = Heap::try_with_policy(DropPolicy::Reclaim).map_err(code)?;
+        let block = allocator::pool_alloc(mem::size_of::<HeapHandle>(),
+                                          Align::of::<HeapHandle>());
+        let handle: Pointer<HeapHandle> = Pointer::from(block).cast();
+        ptr::write(*handle, HeapHandle {
+            magic: MAGIC,
+            heap: heap,
+        });
+
+        put(out, *handle)
+    })())
+}
+
+/// Destroy a heap, freeing all of its buffers.
+#[no_mangle]
+pub unsafe extern fn ralloc_heap_destroy(handle: *mut HeapHandle) -> i32 {
+    done((|| {
+        heap(handle)?;
+
+        // The magic number is cleared first, such that the handle is rejected from now on.
+        ptr::write_volatile(&mut (*handle).magic, 0);
+        drop(ptr::read(&(*handle).heap));
+        allocator::pool_free(Block::from_raw_parts(Pointer::new(handle).cast(),
+                                                   mem::size_of::<HeapHandle>()));
+
+        Ok(())
+    })())
+}
+
+/// Allocate `size` bytes aligned to `align` from a heap, storing the buffer in `*out`.
+#[no_mangle]
+pub unsafe extern fn ralloc_heap_alloc(handle: *mut HeapHandle, size: usize, align: usize,
+                                       out: *mut *mut u8) -> i32 {
+    done((|| {
+        let heap = heap(handle)?;
+        check_align(align)?;
+        if out.is_null() {
+            return Err(RALLOC_EINVAL);
+        }
+        allocator::check_size(size).map_err(code)?;
+
+        put(out, heap.alloc(size, align))
+    })())
+}
+
+/// Reallocate the buffer `ptr` of `old_size` bytes of a heap to `size` bytes, storing the new
+/// buffer in `*out`.
+///
+/// The buffer is always moved. On failure, it is left intact.
+#[no_mangle]
+pub unsafe extern fn ralloc_heap_realloc(handle: *mut HeapHandle, ptr: *mut u8, old_size: usize,
+                                         size: usize, align: usize, out: *mut *mut u8) -> i32 {
+    done((|| {
+        let heap = heap(handle)?;
+        check_align(align)?;
+        if ptr.is_null() || out.is_null() || !heap.owns(ptr) {
+            return Err(RALLOC_EINVAL);
+        }
+        allocator::check_size(size).map_err(code)?;
+
+        let res = heap.alloc(size, align);
+        ptr::copy_nonoverlapping(ptr, res, cmp::min(old_size, size));
+        heap.free(ptr, old_size);
+
+        put(out, res)
+    })())
+}
+
+/// Free the buffer `ptr` of `size` bytes to a heap.
+///
+/// Null pointers are ignored. Pointers not owned by the heap are rejected.
+#[no_mangle]
+pub unsafe extern fn ralloc_heap_free(handle: *mut HeapHandle, ptr: *mut u8, size: usize) -> i32 {
+    done((|| {
+        let heap = heap(handle)?;
+        if ptr.is_null() {
+            return Ok(());
+        }
+        if !heap.owns(ptr) {
+            return Err(RALLOC_EINVAL);
+        }
+
+        heap.free(ptr, size);
+        Ok(())
+    })())
+}
+
+/// Store the statistics of a heap in `*out`.
+#[no_mangle]
+pub unsafe extern fn ralloc_heap_stats_get(handle: *mut HeapHandle, out: *mut HeapStats) -> i32 {
+    done((|| {
+        let heap = heap(handle)?;
+
+        put(out, HeapStats {
+            owned_bytes: heap.owned_bytes(),
+            free_bytes: heap.free_bytes(),
+            live_bytes: heap.live_bytes(),
+        })
+    })())
+}
+
+/// Store the statistics of the allocator in `*out`.
+///
+/// Without the `stats` feature, this fails with `RALLOC_EUNSUPPORTED`.
+#[no_mangle]
+pub unsafe extern fn ralloc_stats_get(out: *mut Stats) -> i32 {
+    #[cfg(feature = "stats")]
+    {
+        use stats;
+
+        let snapshot = stats::snapshot();
+        done(put(out, Stats {
+            live_bytes: stats::live_bytes(),
+            bootstrap_bytes: snapshot.bootstrap_bytes,
+            secure_count: snapshot.secure_count,
+            secure_bytes: snapshot.secure_bytes,
+            slab_count: snapshot.slab_count,
+            slab_bytes: snapshot.slab_bytes,
+        }))
+    }
+    #[cfg(not(feature = "stats"))]
+    {
+        let _ = out;
+
+        RALLOC_EUNSUPPORTED
+    }
+}
+
+/// Give as much memory back to the OS as possible (see `purge`).
+///
+/// If `out` is not null, the memory reclaimed is stored in `*out`.
+#[no_mangle]
+pub unsafe extern fn ralloc_purge(out: *mut PurgeReport) -> i32 {
+    let report = allocator::purge();
+    if !out.is_null() {
+        ptr::write(out, report);
+    }
+
+    RALLOC_OK
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use core::ptr;
+
+    #[test]
+    fn test_heap() {
+        unsafe {
+            let mut handle = ptr::null_mut();
+            assert_eq!(ralloc_heap_new(&mut handle), RALLOC_OK);
+
+            let mut buf = ptr::null_mut();
+            assert_eq!(ralloc_heap_alloc(handle, 100, 16, &mut buf), RALLOC_OK);
+            assert_eq!(buf as usize % 16, 0);
+            *buf.offset(99) = 42;
+
+            assert_eq!(ralloc_heap_realloc(handle, buf, 100, 1000, 16, &mut buf), RALLOC_OK);
+            assert_eq!(*buf.offset(99), 42);
+
+            let mut stats = HeapStats::default();
+            assert_eq!(ralloc_heap_stats_get(handle, &mut stats), RALLOC_OK);
+            assert!(stats.owned_bytes >= 1000);
+
+            assert_eq!(ralloc_heap_free(handle, buf, 1000), RALLOC_OK);
+            assert_eq!(ralloc_heap_destroy(handle), RALLOC_OK);
+        }
+    }
+
+    #[test]
+    fn test_misuse() {
+        unsafe {
+            let mut handle = ptr::null_mut();
+            let mut buf = ptr::null_mut();
+            assert_eq!(ralloc_heap_alloc(ptr::null_mut(), 8, 8, &mut buf), RALLOC_EHANDLE);
+            assert_eq!(ralloc_alloc(8, 3, &mut buf), RALLOC_EINVAL);
+
+            // A buffer of the global allocator is not a handle, nor owned by a heap.
+            let foreign = allocator::alloc(64, 8);
+            ptr::write_bytes(foreign, 0, 64);
+            assert_eq!(ralloc_heap_destroy(foreign as *mut HeapHandle), RALLOC_EHANDLE);
+
+            assert_eq!(ralloc_heap_new(&mut handle), RALLOC_OK);
+            assert_eq!(ralloc_heap_free(handle, foreign, 64), RALLOC_EINVAL);
+            assert_eq!(ralloc_heap_destroy(handle), RALLOC_OK);
+
+            assert_eq!(ralloc_free(foreign, 64), RALLOC_OK);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Checked handles to allocations.
+//!
+//! `Ptr` wraps the pointers of the Rust API. With the `debugger` feature, a handle remembers the
+//! serial number of its allocation in the table of live allocations (its generation), and every
+//! use checks it against the table. A handle outliving its allocation (e.g. a copy of a handle,
+//! which was freed) aborts the process on its next use, before the allocator is handed a dead
+//! pointer. Without the feature, a handle is a bare pointer.
+
+#[cfg(feature = "debugger")]
+use core::fmt::Write;
+
+use allocator;
+#[cfg(feature = "debugger")]
+use live;
+#[cfg(feature = "debugger")]
+use log::NoAllocWriter;
+#[cfg(feature = "debugger")]
+use shim::config;
+#[cfg(feature = "tagging")]
+use tag;
+
+/// A handle to an allocation.
+///
+/// The handle is created by `Ptr::alloc` (or converted from a raw pointer by `Ptr::from_raw`), and
+/// is passed to the allocator functions in place of the raw pointer. The conversions to and from
+/// raw pointers are explicit.
+///
+/// With the `debugger` feature, the handle carries the generation of its allocation, and using a
+/// handle to a freed allocation aborts with a generation mismatch. Otherwise, it is exactly a
+/// pointer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ptr {
+    /// The pointer.
+    ptr: *mut u8,
+    /// The serial number of the allocation containing the pointer, if any.
+    #[cfg(feature = "debugger")]
+    serial: Option<u64>,
+}
+
+impl Ptr {
+    /// Allocate a buffer of `size` bytes aligned to `align`.
+    ///
+    /// See `ralloc::alloc`.
+    #[inline]
+    pub fn alloc(size: usize, align: usize) -> Ptr {
+        unsafe {
+            // The buffer was just allocated.
+            Ptr::from_raw(allocator::alloc(size, align))
+        }
+    }
+
+    /// Convert a raw pointer into a handle.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point into a live allocation of `ralloc`, or be a zero-sized allocation. The
+    /// handle takes the generation the allocation has now.
+    #[cfg(feature = "debugger")]
+    #[inline]
+    pub unsafe fn from_raw(ptr: *mut u8) -> Ptr {
+        Ptr {
+            ptr: ptr,
+            serial: live::serial(ptr),
+        }
+    }
+
+    /// Convert a raw pointer into a handle.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point into a live allocation of `ralloc`, or be a zero-sized allocation.
+    #[cfg(not(feature = "debugger"))]
+    #[inline]
+    pub unsafe fn from_raw(ptr: *mut u8) -> Ptr {
+        Ptr {
+            ptr: ptr,
+        }
+    }
+
+    /// Get the raw pointer.
+    ///
+    /// This is not a use of the allocation, so the handle isn't checked.
+    #[inline]
+    pub fn as_raw(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    /// Check the handle, and get the raw pointer.
+    ///
+    /// # Failure
+    ///
+    /// With the `debugger` feature, the process is aborted if the allocation of the handle has
+    /// been freed (in full, or the part containing the pointer) since the handle was created.
+    #[inline]
+    pub fn into_raw(self) -> *mut u8 {
+        self.check();
+
+        self.ptr
+    }
+
+    /// Free the buffer of `size` bytes.
+    ///
+    /// See `ralloc::free`. The handle (and every copy of it) is dead afterwards.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for `ralloc::free` apply, except that a dead handle is caught with the
+    /// `debugger` feature.
+    #[inline]
+    pub unsafe fn free(self, size: usize) {
+        allocator::free(self.into_raw(), size);
+    }
+
+    /// Reallocate the buffer of `old_size` bytes to `size` bytes aligned to `align`.
+    ///
+    /// See `ralloc::realloc`. The handle (and every copy of it) is dead afterwards, unless the
+    /// buffer was resized in place.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for `ralloc::realloc` apply, except that a dead handle is caught with the
+    /// `debugger` feature.
+    #[inline]
+    pub unsafe fn realloc(self, old_size: usize, size: usize, align: usize) -> Ptr {
+        Ptr::from_raw(allocator::realloc(self.into_raw(), old_size, size, align))
+    }
+
+    /// Get the usable size of the buffer.
+    ///
+    /// See `ralloc::usable_size`.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for `ralloc::usable_size` apply, except that a dead handle is caught with
+    /// the `debugger` feature.
+    #[cfg(any(feature = "header", feature = "sidetable"))]
+    #[inline]
+    pub unsafe fn usable_size(&self) -> usize {
+        allocator::usable_size(self.into_raw())
+    }
+
+    /// Get the tag of the buffer.
+    ///
+    /// Untagged buffers have tag 0.
+    #[cfg(feature = "tagging")]
+    #[inline]
+    pub fn tag(&self) -> u8 {
+        tag::get(self.into_raw())
+    }
+
+    /// Check that the allocation of the handle is still live.
+    #[cfg(feature = "debugger")]
+    #[inline]
+    fn check(&self) {
+        let serial = live::serial(self.ptr);
+        if serial != self.serial {
+            self.dead(serial);
+        }
+    }
+
+    /// Check that the allocation of the handle is still live.
+    ///
+    /// Without the `debugger` feature, this does nothing.
+    #[cfg(not(feature = "debugger"))]
+    #[inline]
+    fn check(&self) {}
+
+    /// Abort on the use of a dead handle.
+    ///
+    /// The mismatch is written to the log directly, since the logging might be compiled out.
+    #[cfg(feature = "debugger")]
+    #[cold]
+    fn dead(&self, serial: Option<u64>) -> ! {
+        let mut line = NoAllocWriter::new();
+        let _ = write!(line, "ralloc: generation mismatch: handle to {:?} is of generation ",
+                       self.ptr);
+        let _ = match self.serial {
+            Some(x) => write!(line, "{}", x),
+            None => write!(line, "none"),
+        };
+        let _ = match serial {
+            Some(x) => write!(line, ", but the allocation there is of generation {}", x),
+            None => write!(line, ", but no allocation is live there"),
+        };
+        line.end_line();
+        config::log(line.as_str());
+
+        unsafe {
+            // Aborting is safe no matter what.
+            ::core::intrinsics::abort();
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Allocation headers.
+//!
+//! With the `header` feature, every buffer is preceded by a header recording its size, size
+//! class, tag, and birth generation, such that buffers can be freed or measured without knowing
+//! their size (like C's `free` and `malloc_usable_size`). The header carries a checksum of
+//! itself, so corruption is detected when the buffer is freed.
+//!
+//! A buffer is laid out as follows:
+//!
+//! ```text
+//! [padding][header][data][redzone]
+//! ```
+//!
+//! The block starts at the padding, which makes the data aligned. The padding and the header are
+//! never part of the data.
+
+use core::ptr;
+
+use class::SizeClass;
+use meta::Metadata;
+use ptr::Align;
+use fail;
+
+/// The size of a header.
+///
+/// The header is an array of bytes, so it needs no alignment.
+pub const SIZE: usize = 16;
+
+/// The seed of the checksum.
+const SEED: u8 = 0xA5;
+
+/// A decoded header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// The size of the buffer.
+    pub size: usize,
+    /// The distance from the start of the block to the data.
+    pub offset: usize,
+    /// The index of the size class of the buffer.
+    pub class: u8,
+    /// The tag of the buffer.
+    pub tag: u8,
+    /// The generation the buffer was born in (see `meta::now`).
+    pub birth: u16,
+}
+
+impl Header {
+    /// Create the header of a buffer.
+    pub fn new(size: usize, offset: usize, tag: u8, birth: u16) -> Header {
+        Header {
+            size: size,
+            offset: offset,
+            // The class index is at most `class::COUNT`, so it fits a byte.
+            class: SizeClass::of(size).index() as u8,
+            tag: tag,
+            birth: birth,
+        }
+    }
+
+    /// Encode the header.
+    ///
+    /// The layout is the size (7 bytes, little endian), the offset (4 bytes, little endian), the
+    /// class, the tag, the birth generation (2 bytes, little endian), and the checksum. Sizes are
+    /// bounded by `ptr::MAX_BLOCK`, so they fit 7 bytes.
+    fn encode(&self) -> [u8; SIZE] {
+        // Make some assertions.
+        assert!(self.size as u64 >> 56 == 0, "The size is too large for the header.");
+        assert!(self.offset as u64 >> 32 == 0, "The alignment padding is too large for the \
+                header.");
+
+        let mut bytes = [0; SIZE];
+        for i in 0..7 {
+            bytes[i] = (self.size as u64 >> (8 * i)) as u8;
+        }
+        for i in 0..4 {
+            bytes[7 + i] = (self.offset >> (8 * i)) as u8;
+        }
+        bytes[11] = self.class;
+        bytes[12] = self.tag;
+        bytes[13] = self.birth as u8;
+        bytes[14] = (self.birth >> 8) as u8;
+        bytes[SIZE - 1] = checksum(&bytes);
+
+        bytes
+    }
+
+    /// Decode a header.
+    ///
+    /// If the checksum doesn't match, `Err(())` is returned.
+    fn decode(bytes: &[u8; SIZE]) -> Result<Header, ()> {
+        if bytes[SIZE - 1] != checksum(bytes) {
+            return Err(());
+        }
+
+        let mut size = 0;
+        for i in 0..7 {
+            size |= (bytes[i] as u64) << (8 * i);
+        }
+        let mut offset = 0;
+        for i in 0..4 {
+            offset |= (bytes[7 + i] as usize) << (8 * i);
+        }
+
+        Ok(Header {
+            size: size as usize,
+            offset: offset,
+            class: bytes[11],
+            tag: bytes[12],
+            birth: bytes[13] as u16 | (bytes[14] as u16) << 8,
+        })
+    }
+}
+
+/// Calculate the checksum of a header.
+///
+/// The last byte (the checksum itself) is ignored. Any single flipped byte changes the checksum.
+fn checksum(bytes: &[u8; SIZE]) -> u8 {
+    bytes[..SIZE - 1].iter().fold(SEED, |acc, &x| acc.rotate_left(1) ^ x)
+}
+
+/// Get the distance from the start of a block aligned to `align` to its data.
+///
+/// This is the size of the header rounded up to the alignment, such that the data is aligned as
+/// well.
+pub fn offset(align: Align) -> usize {
+    // The header is tiny, so this cannot overflow.
+    align.round_up(SIZE).unwrap()
+}
+
+/// Write the header of the buffer at `ptr`.
+///
+/// # Safety
+///
+/// The `SIZE` bytes before `ptr` must be owned by the buffer.
+pub unsafe fn write(ptr: *mut u8, header: &Header) {
+    let bytes = header.encode();
+    ptr::copy_nonoverlapping(bytes.as_ptr(), ptr.offset(-(SIZE as isize)), SIZE);
+}
+
+/// Read the header of the buffer at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must be a buffer with a header.
+///
+/// # Failure
+///
+/// If the header is corrupted, the heap corruption handler is called.
+pub unsafe fn read(ptr: *mut u8) -> Header {
+    let mut bytes = [0; SIZE];
+    ptr::copy_nonoverlapping(ptr.offset(-(SIZE as isize)), bytes.as_mut_ptr(), SIZE);
+
+    Header::decode(&bytes).unwrap_or_else(|()| fail::corruption(ptr))
+}
+
+/// The header metadata strategy.
+pub struct Headers;
+
+impl Metadata for Headers {
+    #[inline]
+    fn padding(align: Align) -> usize {
+        offset(align)
+    }
+
+    #[inline]
+    fn pool_size(size: usize) -> usize {
+        size
+    }
+
+    #[inline]
+    unsafe fn stamp(ptr: *mut u8, size: usize, padding: usize, tag: u8, birth: u16) {
+        write(ptr, &Header::new(size, padding, tag, birth));
+    }
+
+    #[inline]
+    unsafe fn birth(ptr: *mut u8) -> Option<u16> {
+        Some(read(ptr).birth)
+    }
+
+    /// Check the header of a buffer.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `size` doesn't match the header. In particular, partial frees aren't
+    /// supported with headers.
+    #[inline]
+    unsafe fn unstamp(ptr: *mut u8, size: usize) -> usize {
+        let header = read(ptr);
+        assert!(header.size == size, "The buffer {:?} of size {} was treated as being of size \
+                {}.", ptr, header.size, size);
+
+        header.offset
+    }
+
+    #[inline]
+    unsafe fn verify(ptr: *mut u8, size: usize, align: Align) -> bool {
+        let header = read(ptr);
+
+        header.size == size && header.offset == offset(align)
+    }
+
+    /// Get the padding of a buffer, without reading its header.
+    #[inline]
+    unsafe fn forget(_ptr: *mut u8, _size: usize, align: Align) -> usize {
+        offset(align)
+    }
+
+    #[inline]
+    unsafe fn size(ptr: *mut u8) -> usize {
+        read(ptr).size
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use ptr::{Align, MAX_BLOCK};
+
+    #[test]
+    fn test_layout() {
+        for align in (0..7).map(|x| Align::new(1 << x).unwrap()) {
+            let offset = offset(align);
+            let align = align.get();
+
+            // The header fits before the data.
+            assert!(offset >= SIZE);
+            // The data is aligned, since the block is.
+            assert_eq!(offset % align, 0);
+            // No more padding than needed.
+            assert!(offset - SIZE < align);
+
+            // Test against real addresses.
+            for base in (1..5).map(|x| x * align * 1000) {
+                let data = base + offset;
+                assert_eq!(data % align, 0);
+                assert!(data - SIZE >= base);
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for &(size, offset, tag, birth) in &[(0, 16, 0, 0), (1, 16, 3, 1), (1000, 64, 255, 128),
+                                             (MAX_BLOCK, 48, 7, 0xFFFF), (5, 16, 1, 0x1234)] {
+            let header = Header::new(size, offset, tag, birth);
+            assert_eq!(Header::decode(&header.encode()), Ok(header));
+        }
+    }
+
+    #[test]
+    fn test_corruption() {
+        let bytes = Header::new(1000, 32, 9, 17).encode();
+
+        // Flip every bit of every byte.
+        for i in 0..SIZE {
+            for bit in 0..8 {
+                let mut corrupted = bytes;
+                corrupted[i] ^= 1 << bit;
+
+                assert_eq!(Header::decode(&corrupted), Err(()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_write() {
+        let mut buf = [0u8; 64];
+
+        unsafe {
+            let ptr = buf.as_mut_ptr().offset(32);
+            write(ptr, &Header::new(32, 32, 1, 2));
+
+            assert_eq!(read(ptr), Header::new(32, 32, 1, 2));
+        }
+    }
+}
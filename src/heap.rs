@@ -0,0 +1,1070 @@
+//! Independent heaps.
+//!
+//! A `Heap` is a pool of its own, which serves allocations from chunks of (at least)
+//! `config::HEAP_CHUNK_SIZE` bytes taken from the allocator. The chunks are the regions of the
+//! heap, so it can tell whether it owns a pointer (see `Heap::owns`), and they are given back
+//! when the heap is dropped. A heap dropped with live buffers follows its `DropPolicy` instead:
+//! it leaks its regions (the default), gives them back anyway, or aborts. The regions of a leaked
+//! heap are remembered, such that freeing one of its buffers is ignored, rather than handed to the
+//! global allocator.
+//!
+//! Live heaps are kept in a process-wide registry, such that a buffer can be freed without knowing
+//! its heap (see `route_free`). The registry is read without locks. Instead, lookups are counted,
+//! and a dropped heap waits for the lookups in progress after deregistering, before doing anything
+//! with its memory.
+//!
+//! With the `debugger` feature, the buffers of the heaps are recorded in the table of live
+//! allocations too, such that tools can find them, and `DropPolicy::Abort` can list them.
+//! Snapshots hold the entries of the heap too, and `Heap::restore` puts them back in the table.
+//!
+//! A heap can be checkpointed: `Heap::snapshot` copies its pool and the contents of its regions,
+//! and `Heap::restore` copies them back in place. The regions of a heap never move, so pointers
+//! into it stay valid across a restore.
+//!
+//! With the `numa` feature, a heap can be pinned to a NUMA node (see `Heap::new_on_node`). Its
+//! chunks are then mappings of their own bound to the node, rather than blocks of the allocator,
+//! and they are unmapped when given back.
+
+use prelude::*;
+
+use atomic::{self, AtomicPtr, AtomicUsize};
+use core::fmt::Write;
+use core::{cmp, mem, ops, ptr};
+
+use shim::{config, syscalls};
+
+use bookkeeper::{self, Allocator, Bookkeeper};
+use fail::{self, AllocErr};
+#[cfg(feature = "debugger")]
+use live;
+#[cfg(feature = "numa")]
+use numa;
+#[cfg(feature = "numa")]
+use ptr::MAX_BLOCK;
+use log::NoAllocWriter;
+use {allocator, sync};
+
+/// The maximal number of live heaps.
+pub const MAX_HEAPS: usize = 16;
+
+/// The registered heaps, as pointers to their pools (or null for free slots).
+// The atomics aren't `Copy`, so we cannot use the repeat syntax.
+static HEAPS: [AtomicPtr<sync::Mutex<HeapPool>>; MAX_HEAPS] = [
+    AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()),
+];
+/// The regions of the registered heaps, in the slots of their pools (or null for free slots).
+///
+/// These are read by `route_free` without locking the heaps.
+// The atomics aren't `Copy`, so we cannot use the repeat syntax.
+static RANGES: [AtomicPtr<Ranges>; MAX_HEAPS] = [
+    AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()),
+];
+/// The identifier of the next heap.
+///
+/// Slots and pool blocks are reused, so this tells a heap apart from the ones before it.
+static IDS: AtomicUsize = AtomicUsize::new(0);
+/// The number of registry lookups in progress.
+static LOOKUPS: AtomicUsize = AtomicUsize::new(0);
+/// The number of bytes in the regions of the live heaps.
+static OWNED: AtomicUsize = AtomicUsize::new(0);
+/// The number of bytes in the regions of the heaps, which were leaked at their drop.
+static LEAKED: AtomicUsize = AtomicUsize::new(0);
+/// The regions of the heaps leaked at their drop, as a list linked through `Ranges::next`.
+///
+/// The regions stay allocated for good, so they can be read without counting the lookups.
+static TOMBSTONES: AtomicPtr<Ranges> = AtomicPtr::new(ptr::null_mut());
+
+/// What a heap does with its memory, when it is dropped with live buffers.
+///
+/// A heap without live buffers gives its memory back, whatever its policy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Keep the regions for good.
+    ///
+    /// The heap is deregistered, but its regions are never given back, so the live buffers stay
+    /// valid (though they can no longer be freed: `route_free` ignores them). The regions are
+    /// counted as leaked. This is the policy of `Heap::new`.
+    Leak,
+    /// Give the regions back anyway.
+    ///
+    /// The live buffers are gone along with the regions, so every pointer into them dangles after
+    /// the drop. This is why `Heap::with_policy` is unsafe.
+    Reclaim,
+    /// Log the live buffers, and abort the process.
+    ///
+    /// The number of live bytes is logged, and with the `debugger` feature, every live buffer of
+    /// the heap is listed with its size and address.
+    Abort,
+}
+
+/// The regions of a heap, readable without locking it.
+///
+/// The pool publishes its regions here whenever they change. The version is odd while they
+/// change, such that a reader can tell, that it read them halfway through a change, and retry.
+struct Ranges {
+    /// The version of the regions.
+    version: AtomicUsize,
+    /// The number of regions.
+    len: AtomicUsize,
+    /// The regions as `(start, size)`.
+    regions: [(AtomicUsize, AtomicUsize); config::HEAP_REGIONS],
+    /// The next regions in `TOMBSTONES`, if the heap was leaked.
+    next: AtomicPtr<Ranges>,
+}
+
+impl Ranges {
+    /// Publish some regions.
+    ///
+    /// The writers are serialized by the lock of the pool.
+    fn publish(&self, regions: &[(*mut u8, usize)]) {
+        self.version.fetch_add(1, atomic::Ordering::SeqCst);
+
+        for (&(ref start, ref size), &(x, y)) in self.regions.iter().zip(regions) {
+            start.store(x as usize, atomic::Ordering::SeqCst);
+            size.store(y, atomic::Ordering::SeqCst);
+        }
+        self.len.store(regions.len(), atomic::Ordering::SeqCst);
+
+        self.version.fetch_add(1, atomic::Ordering::SeqCst);
+    }
+
+    /// Is `addr` in one of the regions?
+    fn owns(&self, addr: usize) -> bool {
+        loop {
+            let version = self.version.load(atomic::Ordering::SeqCst);
+
+            if version % 2 == 0 {
+                let len = self.len.load(atomic::Ordering::SeqCst);
+                let res = self.regions[..len].iter().any(|&(ref start, ref size)| {
+                    let start = start.load(atomic::Ordering::SeqCst);
+                    start <= addr && addr - start < size.load(atomic::Ordering::SeqCst)
+                });
+
+                if self.version.load(atomic::Ordering::SeqCst) == version {
+                    return res;
+                }
+            }
+        }
+    }
+}
+
+/// The pool of a heap.
+struct HeapPool {
+    /// The inner bookkeeper.
+    inner: Bookkeeper,
+    /// The regions of the heap as `(start, size)`.
+    ///
+    /// Every block of the pool (its metadata included) lies within these. The starts are kept as
+    /// pointers, since the regions are given back through them.
+    regions: [(*mut u8, usize); config::HEAP_REGIONS],
+    /// The number of regions.
+    len: usize,
+    /// The published copy of the regions.
+    ranges: Pointer<Ranges>,
+    /// The number of bytes in live buffers.
+    live: usize,
+    /// The NUMA node, which the chunks are bound to, if any.
+    ///
+    /// This is always `None` without the `numa` feature.
+    node: Option<usize>,
+    /// The identifier of the heap.
+    ///
+    /// See `IDS`.
+    id: usize,
+}
+
+impl HeapPool {
+    /// Record a block as a region of the heap.
+    fn claim(&mut self, block: &Block) {
+        if self.len == self.regions.len() {
+            fail::oom(AllocErr::LimitReached);
+        }
+
+        self.regions[self.len] = (*Pointer::from(block.empty_left()), block.size());
+        self.len += 1;
+        self.publish();
+        OWNED.fetch_add(block.size(), atomic::Ordering::Relaxed);
+    }
+
+    /// Publish the regions of the heap to `route_free`.
+    fn publish(&self) {
+        unsafe {
+            // The ranges live as long as the pool.
+            (**self.ranges).publish(&self.regions[..self.len]);
+        }
+    }
+
+    /// Free a buffer of the heap.
+    fn free_buffer(&mut self, block: Block) {
+        self.live -= block.size();
+        self.free(block);
+    }
+
+    /// Is `addr` in a region of the heap?
+    fn owns(&self, addr: usize) -> bool {
+        within(&self.regions[..self.len], addr)
+    }
+
+    /// Get the number of bytes in the regions of the heap.
+    fn owned_bytes(&self) -> usize {
+        self.regions[..self.len].iter().fold(0, |acc, &(_, size)| acc + size)
+    }
+
+    /// Forget every buffer of the heap, and rebuild the pool from its regions.
+    ///
+    /// Every region is freed into the pool whole, except for the buffer of the pool itself.
+    fn reset(&mut self) {
+        let meta = self.inner.clear();
+        self.live = 0;
+
+        // Growing the metadata can claim new regions, but their free parts are in the pool
+        // already, so only the current regions are freed.
+        let (regions, len) = (self.regions, self.len);
+        for &(start, size) in &regions[..len] {
+            let region = unsafe {
+                // The region belongs to the heap, and its buffers are gone.
+                Block::from_raw_parts(Pointer::new(start), size)
+            };
+
+            // Adjacent regions merge in the pool, so the buffer of the pool can straddle them.
+            let low = cmp::max(meta.start, start as usize);
+            let high = cmp::min(meta.end, start as usize + size);
+            if low < high {
+                let (left, rest) = region.split(low - start as usize);
+                let (_, right) = rest.split(high - low);
+                self.free(left);
+                self.free(right);
+            } else {
+                self.free(region);
+            }
+        }
+    }
+}
+
+/// Is `addr` in one of some regions?
+fn within(regions: &[(*mut u8, usize)], addr: usize) -> bool {
+    regions.iter().any(|&(start, size)| start as usize <= addr && addr - (start as usize) < size)
+}
+
+impl ops::Deref for HeapPool {
+    type Target = Bookkeeper;
+
+    fn deref(&self) -> &Bookkeeper {
+        &self.inner
+    }
+}
+
+impl ops::DerefMut for HeapPool {
+    fn deref_mut(&mut self) -> &mut Bookkeeper {
+        &mut self.inner
+    }
+}
+
+impl Allocator for HeapPool {
+    /// Take a new chunk from the allocator.
+    ///
+    /// The chunk becomes a region of the heap, and the part of it, which isn't needed, is freed
+    /// into the pool.
+    fn alloc_fresh(&mut self, size: usize, align: Align) -> Block {
+        #[cfg(feature = "numa")]
+        {
+            if let Some(node) = self.node {
+                return self.alloc_on_node(size, align, node);
+            }
+        }
+
+        let chunk_size = cmp::max(size, config::HEAP_CHUNK_SIZE);
+        let chunk = allocator::pool_alloc(chunk_size, align);
+
+        // Logging.
+        log!(INTERNAL, "Taking chunk {:?} for a heap.", chunk);
+
+        self.claim(&chunk);
+
+        let (res, excessive) = chunk.split(size);
+        self.free(excessive);
+
+        res
+    }
+}
+
+impl HeapPool {
+    /// Map a new chunk bound to `node`.
+    ///
+    /// Mappings are only aligned to pages, so the chunk has room for the aligner of larger
+    /// alignments, which is freed into the pool along with the excess.
+    #[cfg(feature = "numa")]
+    fn alloc_on_node(&mut self, size: usize, align: Align, node: usize) -> Block {
+        let page = Align::page();
+        let chunk_size = size.checked_add(align.get().saturating_sub(page.get()))
+            .and_then(|x| page.round_up(cmp::max(x, config::HEAP_CHUNK_SIZE)))
+            .unwrap_or_else(|| fail::oom(AllocErr::TooLarge {
+                requested: size,
+                limit: MAX_BLOCK.saturating_sub(align.get()),
+            }));
+        let ptr = numa::map_on_node(chunk_size, node).unwrap_or_else(|err| {
+            fail::oom(AllocErr::Os(err))
+        });
+        let mut chunk = unsafe {
+            // The mapping was just acquired, and is `chunk_size` bytes long.
+            Block::from_raw_parts(Pointer::new(ptr), chunk_size)
+        };
+
+        // Logging.
+        log!(INTERNAL, "Mapping chunk {:?} for a heap on node {}.", chunk, node);
+
+        self.claim(&chunk);
+
+        let (aligner, rest) = chunk.align(align).expect("The chunk has no room for the aligner.");
+        self.free(aligner);
+        let (res, excessive) = rest.split(size);
+        self.free(excessive);
+
+        res
+    }
+}
+
+/// Give a region of a heap on `node` (if any) back.
+///
+/// The first region holds the initial metadata, which is a block of the allocator, like every
+/// region of the heaps on no node. The other regions of the heaps on a node are mappings.
+///
+/// # Safety
+///
+/// The region must not be used afterwards.
+#[allow(unused_variables)]
+unsafe fn release(node: Option<usize>, index: usize, start: *mut u8, size: usize) {
+    #[cfg(feature = "numa")]
+    {
+        match node {
+            Some(node) if index != 0 => {
+                numa::unmap_on_node(start, size, node);
+                return;
+            },
+            _ => {},
+        }
+    }
+
+    allocator::pool_free(Block::from_raw_parts(Pointer::new(start), size));
+}
+
+/// An independent heap.
+///
+/// Buffers allocated from a heap must be freed to it, either directly through `Heap::free`, or
+/// through `route_free`. Dropping the heap gives all of its memory back, if no buffers are live.
+/// Otherwise, its `DropPolicy` decides.
+///
+/// Heaps serve plain buffers from their pool, without the metadata, slabs and checks of the
+/// global allocator.
+pub struct Heap {
+    /// The pool, which is kept out of the handle, such that its address is stable.
+    pool: Pointer<sync::Mutex<HeapPool>>,
+    /// The slot of the heap in the registry.
+    slot: usize,
+    /// What to do when dropped with live buffers.
+    policy: DropPolicy,
+}
+
+// The pool is behind a mutex.
+unsafe impl Send for Heap {}
+unsafe impl Sync for Heap {}
+
+impl Heap {
+    /// Create a new heap, and register it.
+    ///
+    /// The heap leaks its regions, if it is dropped with live buffers (see `DropPolicy::Leak`).
+    ///
+    /// If `MAX_HEAPS` heaps are live, the OOM handler is called with `AllocErr::LimitReached`.
+    pub fn new() -> Heap {
+        Heap::try_new().unwrap_or_else(|err| fail::oom(err))
+    }
+
+    /// Create a new heap, and register it, failing if `MAX_HEAPS` heaps are live.
+    ///
+    /// The heap leaks its regions, if it is dropped with live buffers (see `DropPolicy::Leak`).
+    ///
+    /// # Errors
+    ///
+    /// If the registry is full, `AllocErr::LimitReached` is returned.
+    pub fn try_new() -> Result<Heap, AllocErr> {
+        Heap::register(DropPolicy::Leak, None)
+    }
+
+    /// Create a new heap pinned to the NUMA node `node`, and register it.
+    ///
+    /// The chunks of the heap are mapped bound to the node, so every buffer allocated from it
+    /// lives on the node. If the machine has a single node, or `node` doesn't exist, this is
+    /// `Heap::new`. The heap leaks its regions, if it is dropped with live buffers.
+    ///
+    /// If `MAX_HEAPS` heaps are live, the OOM handler is called with `AllocErr::LimitReached`. So
+    /// it is, if a chunk cannot be bound to the node.
+    #[cfg(feature = "numa")]
+    pub fn new_on_node(node: usize) -> Heap {
+        Heap::try_new_on_node(node).unwrap_or_else(|err| fail::oom(err))
+    }
+
+    /// Create a new heap pinned to the NUMA node `node`, and register it, failing if `MAX_HEAPS`
+    /// heaps are live.
+    ///
+    /// See `Heap::new_on_node`.
+    ///
+    /// # Errors
+    ///
+    /// If the registry is full, `AllocErr::LimitReached` is returned.
+    #[cfg(feature = "numa")]
+    pub fn try_new_on_node(node: usize) -> Result<Heap, AllocErr> {
+        let nodes = numa::nodes();
+        if node >= nodes {
+            // Logging.
+            log!(WARNING, "Not pinning a heap to node {}, as there are {} NUMA nodes.", node,
+                 nodes);
+        }
+
+        // With a single node, the memory is on it anyway.
+        Heap::register(DropPolicy::Leak, if node < nodes && nodes > 1 { Some(node) } else { None })
+    }
+
+    /// Create a new heap with a drop policy, and register it.
+    ///
+    /// If `MAX_HEAPS` heaps are live, the OOM handler is called with `AllocErr::LimitReached`.
+    ///
+    /// # Safety
+    ///
+    /// With `DropPolicy::Reclaim`, the buffers still live at the drop of the heap are gone, so
+    /// nothing may use them afterwards. The other policies are always safe.
+    pub unsafe fn with_policy(policy: DropPolicy) -> Heap {
+        Heap::try_with_policy(policy).unwrap_or_else(|err| fail::oom(err))
+    }
+
+    /// Create a new heap with a drop policy, and register it, failing if `MAX_HEAPS` heaps are
+    /// live.
+    ///
+    /// # Errors
+    ///
+    /// If the registry is full, `AllocErr::LimitReached` is returned.
+    ///
+    /// # Safety
+    ///
+    /// See `Heap::with_policy`.
+    pub unsafe fn try_with_policy(policy: DropPolicy) -> Result<Heap, AllocErr> {
+        Heap::register(policy, None)
+    }
+
+    /// Get the NUMA node, which the heap is pinned to, if any.
+    #[cfg(feature = "numa")]
+    pub fn node(&self) -> Option<usize> {
+        self.pool().lock().node
+    }
+
+    /// Create a new heap with a drop policy, and register it.
+    ///
+    /// If `node` is given, the chunks of the heap are bound to it.
+    fn register(policy: DropPolicy, node: Option<usize>) -> Result<Heap, AllocErr> {
+        // Logging.
+        log!(NOTE, "Creating a heap with drop policy {:?}.", policy);
+
+        // The initial metadata.
+        let size = 4 * bookkeeper::EXTRA_ELEMENTS * mem::size_of::<Block>();
+        let meta = allocator::pool_alloc(size, Align::of::<Block>());
+
+        // The metadata is the first region.
+        let mut regions = [(ptr::null_mut(), 0); config::HEAP_REGIONS];
+        regions[0] = (*Pointer::from(meta.empty_left()), meta.size());
+        let (meta_start, meta_size) = regions[0];
+
+        let ranges: Pointer<Ranges> = Pointer::from(allocator::pool_alloc(
+            mem::size_of::<Ranges>(), Align::of::<Ranges>())).cast();
+        unsafe {
+            // The block was just allocated for the ranges. Zero is a valid value of the atomics,
+            // so the zeroed ranges are empty.
+            ptr::write_bytes(*ranges, 0, 1);
+            (**ranges).publish(&regions[..1]);
+        }
+
+        let pool = HeapPool {
+            inner: Bookkeeper::new(unsafe {
+                Vec::from_raw_parts(meta, 0)
+            }),
+            regions: regions,
+            len: 1,
+            ranges: ranges.clone(),
+            live: 0,
+            node: node,
+            id: IDS.fetch_add(1, atomic::Ordering::Relaxed),
+        };
+
+        let block = allocator::pool_alloc(mem::size_of::<sync::Mutex<HeapPool>>(),
+                                          Align::of::<sync::Mutex<HeapPool>>());
+        let ptr: Pointer<sync::Mutex<HeapPool>> = Pointer::from(block).cast();
+        unsafe {
+            // The block was just allocated for the pool.
+            ptr::write(*ptr, sync::Mutex::ranked("heap", sync::rank::FRONT_END, pool));
+        }
+
+        // Register the heap.
+        match HEAPS.iter().position(|x| {
+            x.compare_and_swap(ptr::null_mut(), *ptr, atomic::Ordering::SeqCst).is_null()
+        }) {
+            Some(slot) => {
+                RANGES[slot].store(*ranges, atomic::Ordering::SeqCst);
+                OWNED.fetch_add(meta_size, atomic::Ordering::Relaxed);
+
+                Ok(Heap {
+                    pool: ptr,
+                    slot: slot,
+                    policy: policy,
+                })
+            },
+            None => {
+                // Logging.
+                log!(WARNING, "Unable to register the heap, as {} heaps are live.", MAX_HEAPS);
+
+                unsafe {
+                    // The heap was never registered, so nothing else refers to its memory.
+                    let size = mem::size_of::<sync::Mutex<HeapPool>>();
+                    allocator::pool_free(Block::from_raw_parts(Pointer::new(meta_start),
+                                                               meta_size));
+                    allocator::pool_free(Block::from_raw_parts(ptr.cast(), size));
+                    allocator::pool_free(Block::from_raw_parts(ranges.cast(),
+                                                               mem::size_of::<Ranges>()));
+                }
+
+                Err(AllocErr::LimitReached)
+            },
+        }
+    }
+
+    /// Get the pool.
+    fn pool(&self) -> &sync::Mutex<HeapPool> {
+        unsafe {
+            // The pool lives as long as the heap.
+            &**self.pool
+        }
+    }
+
+    /// Allocate a buffer of `size` bytes aligned to `align`.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `align` is not a power of two.
+    pub fn alloc(&self, size: usize, align: usize) -> *mut u8 {
+        let align = Align::new(align).expect("Invalid alignment.");
+
+        let ptr = {
+            let mut pool = self.pool().lock();
+            pool.live += size;
+
+            *Pointer::from(pool.alloc(size, align))
+        };
+
+        // The table of live allocations is locked at the rank of the pool, so the pool must be
+        // unlocked by now.
+        #[cfg(feature = "debugger")]
+        live::insert(ptr, size, 0);
+
+        ptr
+    }
+
+    /// Free a buffer of this heap.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be allocated from this heap with size `size`, and must not be used after the
+    /// free.
+    pub unsafe fn free(&self, ptr: *mut u8, size: usize) {
+        if size == 0 { return; }
+
+        debug_assert!(self.owns(ptr), "Freeing {:?} to a heap not owning it.", ptr);
+
+        #[cfg(feature = "debugger")]
+        live::remove(ptr, size);
+
+        self.pool().lock().free_buffer(Block::from_raw_parts(Pointer::new(ptr), size));
+    }
+
+    /// Does the heap own `ptr`?
+    ///
+    /// This is true for pointers into the memory of the heap (the buffers allocated from it), and
+    /// false for anything else.
+    pub fn owns(&self, ptr: *const u8) -> bool {
+        self.pool().lock().owns(ptr as usize)
+    }
+
+    /// Get the number of bytes taken by the heap.
+    pub fn owned_bytes(&self) -> usize {
+        self.pool().lock().owned_bytes()
+    }
+
+    /// Get the number of free bytes in the heap.
+    pub fn free_bytes(&self) -> usize {
+        self.pool().lock().total_bytes()
+    }
+
+    /// Get the number of bytes in the live buffers of the heap.
+    pub fn live_bytes(&self) -> usize {
+        self.pool().lock().live
+    }
+
+    /// Get the drop policy of the heap.
+    pub fn policy(&self) -> DropPolicy {
+        self.policy
+    }
+
+    /// Check the consistency of the heap.
+    ///
+    /// This is NOOP in release mode.
+    pub fn check(&self) {
+        self.pool().lock().check();
+    }
+
+    /// Take a snapshot of the heap.
+    ///
+    /// The snapshot holds a copy of the pool (and thus of the statistics of the heap) and of the
+    /// contents of every region, so it takes as much memory as the heap. With the `debugger`
+    /// feature, it holds the entries of the heap in the table of live allocations too.
+    pub fn snapshot(&self) -> HeapSnapshot {
+        // Logging.
+        log!(NOTE, "Taking a snapshot of a heap.");
+
+        // The table of live allocations cannot be read with the pool locked, so the entries are
+        // counted first, and copied after.
+        #[cfg(feature = "debugger")]
+        let entries = self.live_entries(|_, _| {});
+        #[cfg(not(feature = "debugger"))]
+        let entries = 0;
+
+        let pool = self.pool().lock();
+        let size = mem::size_of::<HeapPool>() + entries_size(entries) + pool.owned_bytes();
+        let buf = allocator::pool_alloc(size, Align::of::<HeapPool>());
+        let mut dest = *Pointer::from(buf.empty_left());
+
+        unsafe {
+            // The buffer fits the pool, the entries, and every region. The pool has no destructor,
+            // so its bytes can be copied. The entries are filled in below.
+            ptr::copy_nonoverlapping(&*pool as *const HeapPool as *const u8, dest,
+                                     mem::size_of::<HeapPool>());
+            dest = dest.offset(mem::size_of::<HeapPool>() as isize);
+            ptr::write(dest as *mut [usize; 2], [entries, 0]);
+            dest = dest.offset(entries_size(entries) as isize);
+
+            for &(start, size) in &pool.regions[..pool.len] {
+                ptr::copy_nonoverlapping(start, dest, size);
+                dest = dest.offset(size as isize);
+            }
+        }
+
+        drop(pool);
+
+        #[cfg(feature = "debugger")]
+        unsafe {
+            // The entries follow the pool and their header. Allocations made since the count are
+            // left out, as they are newer than the copy of the pool anyway.
+            let header = *Pointer::from(buf.empty_left())
+                .offset(mem::size_of::<HeapPool>() as isize).cast::<usize>();
+            let slots = header.offset(2) as *mut (*mut u8, usize);
+            self.live_entries(|ptr, size| {
+                if *header.offset(1) < entries {
+                    ptr::write(slots.offset(*header.offset(1) as isize), (ptr, size));
+                    *header.offset(1) += 1;
+                }
+            });
+        }
+
+        HeapSnapshot {
+            heap: *self.pool,
+            buf: buf,
+        }
+    }
+
+    /// Call `f` on every entry of the heap in the table of live allocations.
+    ///
+    /// The number of entries is returned. Neither the table nor the pool is locked, while `f`
+    /// runs.
+    #[cfg(feature = "debugger")]
+    fn live_entries<F: FnMut(*mut u8, usize)>(&self, mut f: F) -> usize {
+        // The regions are copied out, as the pool cannot be locked while the table is.
+        let (regions, len) = {
+            let pool = self.pool().lock();
+            (pool.regions, pool.len)
+        };
+
+        let mut count = 0;
+        live::for_each(|ptr, size, _| {
+            let addr = ptr as usize;
+            let within = |&(start, size): &(*mut u8, usize)| {
+                addr.wrapping_sub(start as usize) < size
+            };
+            if regions[..len].iter().any(within) {
+                f(ptr, size);
+                count += 1;
+            }
+        });
+
+        count
+    }
+
+    /// Free every buffer of the heap at once.
+    ///
+    /// The pool is rebuilt from the regions of the heap, which stay with it for reuse, and the
+    /// statistics of the heap start over. The freed memory is zeroed, if zero-on-free is enabled.
+    /// With the `debugger` feature, the buffers live at the reset are logged as warnings.
+    ///
+    /// This takes the heap mutably, so nothing else can allocate from it meanwhile.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may use (or free) the buffers allocated from the heap after the reset.
+    pub unsafe fn reset(&mut self) {
+        // Logging.
+        log!(NOTE, "Resetting a heap with {} live bytes.", self.live_bytes());
+
+        // The buffers leave the table of live allocations before the pool is locked.
+        #[cfg(feature = "debugger")]
+        {
+            self.live_entries(|ptr, size| {
+                // Logging.
+                log!(WARNING, "Resetting a heap with a live buffer of {} bytes at {:?}.", size,
+                     ptr);
+            });
+
+            let (regions, len) = {
+                let pool = self.pool().lock();
+                (pool.regions, pool.len)
+            };
+            for &(start, size) in &regions[..len] {
+                live::forget(start, size);
+            }
+        }
+
+        self.pool().lock().reset();
+    }
+
+    /// Restore the heap to a snapshot of it.
+    ///
+    /// The buffers allocated since the snapshot are gone, the ones freed since are live again,
+    /// and the contents of every buffer are back to the time of the snapshot. The regions taken
+    /// since the snapshot are given back to the allocator.
+    ///
+    /// A snapshot can be restored any number of times.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the snapshot was taken of another heap (including a dropped heap, whose pool
+    /// was reused).
+    ///
+    /// # Safety
+    ///
+    /// Nothing may refer to the buffers allocated since the snapshot after the restore.
+    pub unsafe fn restore(&self, snapshot: &HeapSnapshot) {
+        // Logging.
+        log!(NOTE, "Restoring a heap from a snapshot.");
+
+        let mut src = *Pointer::from(snapshot.buf.empty_left());
+        let saved = &*(src as *const HeapPool);
+
+        // The pool block can be reused by a later heap, so the identifier is checked as well.
+        assert!(snapshot.heap == *self.pool && saved.id == self.pool().lock().id,
+                "Restoring a heap from the snapshot of another heap.");
+
+        // The entries of the heap are dropped from the table of live allocations, before the pool
+        // is locked, and the saved ones are put back after.
+        #[cfg(feature = "debugger")]
+        {
+            let (regions, len) = {
+                let pool = self.pool().lock();
+                (pool.regions, pool.len)
+            };
+            for &(start, size) in &regions[..len] {
+                live::forget(start, size);
+            }
+        }
+
+        let mut pool = self.pool().lock();
+
+        // Regions are only ever added, so the ones of the snapshot come first.
+        debug_assert!(pool.regions[..saved.len] == saved.regions[..saved.len], "The regions of \
+                      the heap changed since the snapshot.");
+
+        // The regions taken since are unpublished before they go.
+        (**pool.ranges).publish(&saved.regions[..saved.len]);
+
+        // Give the regions taken since back.
+        for (n, &(start, size)) in pool.regions[..pool.len].iter().enumerate().skip(saved.len) {
+            release(pool.node, n, start, size);
+            OWNED.fetch_sub(size, atomic::Ordering::Relaxed);
+        }
+
+        // The regions are still ours, and the snapshot holds their contents after the pool and
+        // the entries.
+        src = src.offset(mem::size_of::<HeapPool>() as isize);
+        let header = *(src as *const [usize; 2]);
+        #[cfg(feature = "debugger")]
+        let entries = src.offset(2 * mem::size_of::<usize>() as isize) as *const (*mut u8, usize);
+        src = src.offset(entries_size(header[0]) as isize);
+        for &(start, size) in &saved.regions[..saved.len] {
+            ptr::copy_nonoverlapping(src, start, size);
+            src = src.offset(size as isize);
+        }
+
+        ptr::copy_nonoverlapping(saved as *const HeapPool, &mut *pool as *mut HeapPool, 1);
+        // The links are those of the snapshot, but a checkpoint or poisoning of that time is gone.
+        pool.reset_transient();
+
+        drop(pool);
+
+        #[cfg(feature = "debugger")]
+        for n in 0..header[1] {
+            let (ptr, size) = *entries.offset(n as isize);
+            live::insert(ptr, size, 0);
+        }
+    }
+}
+
+/// Get the size of the entries of a snapshot, including their header.
+///
+/// The header holds the number of slots and the number of entries in them, and the slots are
+/// `(start, size)` pairs. Without the `debugger` feature, there are no slots.
+fn entries_size(slots: usize) -> usize {
+    2 * mem::size_of::<usize>() + slots * mem::size_of::<(*mut u8, usize)>()
+}
+
+/// A snapshot of a heap.
+///
+/// See `Heap::snapshot`.
+pub struct HeapSnapshot {
+    /// The pool of the heap, which the snapshot was taken of.
+    heap: *mut sync::Mutex<HeapPool>,
+    /// The copy of the pool, followed by the entries (see `entries_size`) and the contents of the
+    /// regions of the heap in order.
+    buf: Block,
+}
+
+impl HeapSnapshot {
+    /// Get the number of bytes taken by the snapshot.
+    pub fn size(&self) -> usize {
+        self.buf.size()
+    }
+}
+
+impl Drop for HeapSnapshot {
+    fn drop(&mut self) {
+        let mut buf = mem::replace(&mut self.buf, Block::empty(Pointer::empty()));
+        // The snapshot holds a copy of the pool and the contents of the heap, so it is wiped
+        // before it is freed.
+        buf.wipe();
+        allocator::pool_free(buf);
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        // Logging.
+        log!(NOTE, "Dropping a heap.");
+
+        // Deregister the heap, and wait for the lookups, which might have found it. This comes
+        // first whatever the policy, such that no free is routed into memory about to go.
+        HEAPS[self.slot].store(ptr::null_mut(), atomic::Ordering::SeqCst);
+        RANGES[self.slot].store(ptr::null_mut(), atomic::Ordering::SeqCst);
+        while LOOKUPS.load(atomic::Ordering::SeqCst) != 0 {
+            syscalls::sched_yield();
+        }
+
+        let (regions, len, live, owned, node, ranges) = {
+            let pool = self.pool().lock();
+            (pool.regions, pool.len, pool.live, pool.owned_bytes(), pool.node, pool.ranges.clone())
+        };
+        OWNED.fetch_sub(owned, atomic::Ordering::Relaxed);
+
+        // Whether the regions are to be given back (or else leaked).
+        let reclaim = live == 0 || match self.policy {
+            DropPolicy::Leak => {
+                // Logging.
+                log!(WARNING, "Leaking the {} bytes of a heap dropped with {} live bytes.", owned,
+                     live);
+
+                LEAKED.fetch_add(owned, atomic::Ordering::Relaxed);
+                false
+            },
+            DropPolicy::Reclaim => {
+                // Logging.
+                log!(WARNING, "Reclaiming a heap dropped with {} live bytes.", live);
+
+                true
+            },
+            DropPolicy::Abort => abort_live(&regions[..len], live),
+        };
+
+        unsafe {
+            // The heap is unreachable now, so its memory can be given back. The metadata of the
+            // pool lies within the regions, so it is gone along with them. The pool itself isn't
+            // referred to by the buffers, so it is given back even if the regions are leaked.
+            if reclaim {
+                for (n, &(start, size)) in regions[..len].iter().enumerate() {
+                    #[cfg(feature = "debugger")]
+                    live::forget(start, size);
+
+                    release(node, n, start, size);
+                }
+
+                allocator::pool_free(Block::from_raw_parts(ranges.clone().cast(),
+                                                           mem::size_of::<Ranges>()));
+            }
+
+            allocator::pool_free(Block::from_raw_parts(self.pool.clone().cast(),
+                                                       mem::size_of::<sync::Mutex<HeapPool>>()));
+        }
+
+        // The ranges of a leaked heap are kept, such that its buffers are never freed to the
+        // global allocator.
+        if !reclaim {
+            bury(ranges);
+        }
+    }
+}
+
+/// Add the regions of a leaked heap to `TOMBSTONES`.
+fn bury(ranges: Pointer<Ranges>) {
+    loop {
+        let head = TOMBSTONES.load(atomic::Ordering::SeqCst);
+        unsafe {
+            // The ranges are no longer registered, so nothing else writes them.
+            (**ranges).next.store(head, atomic::Ordering::SeqCst);
+        }
+
+        if TOMBSTONES.compare_and_swap(head, *ranges, atomic::Ordering::SeqCst) == head {
+            break;
+        }
+    }
+}
+
+/// Is `addr` in the regions of a leaked heap?
+fn buried(addr: usize) -> bool {
+    let mut ranges = TOMBSTONES.load(atomic::Ordering::SeqCst);
+    while !ranges.is_null() {
+        unsafe {
+            // Tombstones are never freed.
+            if (*ranges).owns(addr) {
+                return true;
+            }
+            ranges = (*ranges).next.load(atomic::Ordering::SeqCst);
+        }
+    }
+
+    false
+}
+
+/// Abort on the drop of a heap with live buffers.
+///
+/// The buffers are written to the log directly, since the logging might be compiled out.
+#[cold]
+fn abort_live(regions: &[(*mut u8, usize)], live: usize) -> ! {
+    log_buffers(regions);
+
+    let mut line = NoAllocWriter::new();
+    let _ = write!(line, "ralloc: aborting, as a heap was dropped with {} live bytes", live);
+    line.end_line();
+    config::log(line.as_str());
+
+    unsafe {
+        // Aborting is safe no matter what.
+        ::core::intrinsics::abort();
+    }
+}
+
+/// Write the live buffers in some regions to the log.
+#[cfg(feature = "debugger")]
+fn log_buffers(regions: &[(*mut u8, usize)]) {
+    live::for_each(|ptr, size, _| if within(regions, ptr as usize) {
+        let mut line = NoAllocWriter::new();
+        let _ = write!(line, "ralloc: live heap buffer of {} bytes at {:?}", size, ptr);
+        line.end_line();
+        config::log(line.as_str());
+    });
+}
+
+/// Write the live buffers in some regions to the log.
+///
+/// Without the `debugger` feature, the buffers aren't recorded, so this does nothing.
+#[cfg(not(feature = "debugger"))]
+fn log_buffers(_: &[(*mut u8, usize)]) {}
+
+/// Get the number of live heaps.
+pub fn count() -> usize {
+    HEAPS.iter().filter(|x| !x.load(atomic::Ordering::SeqCst).is_null()).count()
+}
+
+/// Get the number of bytes in the regions of the live heaps.
+pub fn owned() -> usize {
+    OWNED.load(atomic::Ordering::Relaxed)
+}
+
+/// Get the number of bytes in the regions of the heaps, which were leaked at their drop.
+pub fn leaked() -> usize {
+    LEAKED.load(atomic::Ordering::Relaxed)
+}
+
+/// Free a buffer to the heap owning it.
+///
+/// The live heaps are searched for the owner of `ptr`. If no heap owns it, it is freed to the
+/// global allocator, as with `free`, unless it belongs to a heap leaked at its drop (see
+/// `DropPolicy::Leak`), in which case the free is ignored. The search reads the published regions
+/// of the heaps, so only the owner is locked.
+///
+/// # Safety
+///
+/// `ptr` must be allocated from a live heap or the global allocator with size `size`, and must not
+/// be used after the free.
+pub unsafe fn route_free(ptr: *mut u8, size: usize) {
+    log!(CALL, "Routing the free of a buffer of size {}.", size);
+
+    LOOKUPS.fetch_add(1, atomic::Ordering::SeqCst);
+
+    // The heap cannot go away, while the lookup is counted. The pool of a slot is registered
+    // before its ranges, so the pool is there, if the ranges are.
+    let owner = RANGES.iter().position(|slot| {
+        let ranges = slot.load(atomic::Ordering::SeqCst);
+        !ranges.is_null() && (*ranges).owns(ptr as usize)
+    }).and_then(|slot| {
+        let pool = HEAPS[slot].load(atomic::Ordering::SeqCst);
+        if pool.is_null() { None } else { Some(pool) }
+    });
+
+    if let Some(pool) = owner {
+        // The table of live allocations shares the rank of the pool, so the buffer leaves it
+        // before the pool is locked (and the buffer can be reused).
+        #[cfg(feature = "debugger")]
+        live::remove(ptr, size);
+
+        if size != 0 {
+            (*pool).lock().free_buffer(Block::from_raw_parts(Pointer::new(ptr), size));
+        }
+    }
+
+    LOOKUPS.fetch_sub(1, atomic::Ordering::SeqCst);
+
+    if owner.is_none() {
+        if buried(ptr as usize) {
+            // Logging.
+            log!(WARNING, "Ignoring the free of {:?}, as its heap was leaked.", ptr);
+
+            #[cfg(feature = "debugger")]
+            live::remove(ptr, size);
+        } else {
+            allocator::free(ptr, size);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Memory layouts.
+//!
+//! Every size computed from untrusted numbers (element counts, alignments) goes through this
+//! module, so overflows are caught rather than silently yielding a tiny buffer, which would then
+//! be overrun. The alignment padding math lives here as well.
+
+use core::{fmt, mem};
+
+/// The layout of a buffer: its size and alignment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+    /// The size of the buffer.
+    size: usize,
+    /// The alignment of the buffer.
+    align: usize,
+}
+
+impl Layout {
+    /// Create a layout from a size and an alignment.
+    ///
+    /// The alignment must be nonzero, and the size rounded up to the alignment must not overflow.
+    /// Otherwise, `Err(LayoutErr)` is returned.
+    pub fn from_size_align(size: usize, align: usize) -> Result<Layout, LayoutErr> {
+        if align == 0 || checked_round_up(size, align).is_none() {
+            return Err(LayoutErr);
+        }
+
+        Ok(Layout {
+            size: size,
+            align: align,
+        })
+    }
+
+    /// Get the size.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Get the alignment.
+    #[inline]
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    /// Get the size rounded up to the alignment.
+    ///
+    /// This is the distance between consecutive elements of an array with this layout.
+    #[inline]
+    pub fn padded_size(&self) -> usize {
+        // This was checked on creation.
+        round_up(self.size, self.align)
+    }
+}
+
+/// A layout error.
+///
+/// The size of the layout overflows, or the alignment is zero.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LayoutErr;
+
+impl fmt::Display for LayoutErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid layout (overflowing size or zero alignment).")
+    }
+}
+
+/// Get the layout of an array of `n` elements of size `elem_size` aligned to `align`.
+///
+/// The elements are `elem_size` rounded up to the alignment apart, such that every element is
+/// aligned, and the size of the array is that stride times `n`. Note that Rust types always have
+/// sizes divisible by their alignment, so their arrays are simply `elem_size * n` bytes.
+///
+/// If the size overflows or the alignment is zero, `Err(LayoutErr)` is returned.
+pub fn checked_array_layout(elem_size: usize, n: usize, align: usize) -> Result<Layout, LayoutErr> {
+    let stride = Layout::from_size_align(elem_size, align)?.padded_size();
+    let size = stride.checked_mul(n).ok_or(LayoutErr)?;
+
+    Layout::from_size_align(size, align)
+}
+
+/// Get the layout of an array of `n` elements of type `T`.
+pub fn array<T>(n: usize) -> Result<Layout, LayoutErr> {
+    checked_array_layout(mem::size_of::<T>(), n, mem::align_of::<T>())
+}
+
+/// Round `x` up to a multiple of `align`.
+///
+/// If the result overflows, `None` is returned.
+///
+/// # Panics
+///
+/// This panics if `align` is zero.
+#[inline]
+pub fn checked_round_up(x: usize, align: usize) -> Option<usize> {
+    x.checked_add(padding_to(x, align))
+}
+
+/// Round `x` up to a multiple of `align`.
+///
+/// This is for sizes known not to overflow. Use `checked_round_up` for anything else.
+///
+/// # Panics
+///
+/// This panics if `align` is zero or (with debug assertions) if the result overflows.
+#[inline]
+pub fn round_up(x: usize, align: usize) -> usize {
+    x + padding_to(x, align)
+}
+
+/// Get the padding needed to bring `addr` up to a multiple of `align`.
+///
+/// # Panics
+///
+/// This panics if `align` is zero.
+#[inline]
+pub fn padding_to(addr: usize, align: usize) -> usize {
+    (align - addr % align) % align
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use core::usize;
+
+    #[test]
+    fn test_padding() {
+        assert_eq!(padding_to(0, 8), 0);
+        assert_eq!(padding_to(1, 8), 7);
+        assert_eq!(padding_to(8, 8), 0);
+        assert_eq!(padding_to(10, 3), 2);
+        assert_eq!(padding_to(usize::MAX, 1), 0);
+        assert_eq!(padding_to(usize::MAX, 2), 1);
+
+        for align in 1..70 {
+            for x in 0..200 {
+                let padded = round_up(x, align);
+                assert_eq!(padded % align, 0);
+                assert!(padded >= x && padded - x < align);
+                assert_eq!(checked_round_up(x, align), Some(padded));
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_up_overflow() {
+        assert_eq!(checked_round_up(usize::MAX, 1), Some(usize::MAX));
+        assert_eq!(checked_round_up(usize::MAX, 2), None);
+        assert_eq!(checked_round_up(usize::MAX - 14, 16), None);
+        assert_eq!(checked_round_up(usize::MAX - 14, 8), Some(usize::MAX - 7));
+
+        // The largest multiple of an alignment is the last size rounding up to it.
+        for align in (0..12).map(|x| 1 << x) {
+            let max = usize::MAX / align * align;
+            assert_eq!(checked_round_up(max, align), Some(max));
+            assert_eq!(checked_round_up(max - (align - 1), align), Some(max));
+
+            if align > 1 {
+                assert_eq!(checked_round_up(max + 1, align), None);
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_size_align() {
+        assert_eq!(Layout::from_size_align(10, 0), Err(LayoutErr));
+        assert_eq!(Layout::from_size_align(usize::MAX, 2), Err(LayoutErr));
+
+        let layout = Layout::from_size_align(10, 8).unwrap();
+        assert_eq!(layout.size(), 10);
+        assert_eq!(layout.align(), 8);
+        assert_eq!(layout.padded_size(), 16);
+    }
+
+    #[test]
+    fn test_array_boundaries() {
+        for &elem_size in &[1, 2, 3, 7, 8, 24, 1000, usize::MAX / 2, usize::MAX] {
+            let max = usize::MAX / elem_size;
+
+            // The largest count fits, and the next one overflows.
+            assert_eq!(checked_array_layout(elem_size, max, 1).map(|x| x.size()),
+                       Ok(max * elem_size));
+            if max < usize::MAX {
+                assert_eq!(checked_array_layout(elem_size, max + 1, 1), Err(LayoutErr));
+            }
+
+            assert_eq!(checked_array_layout(elem_size, 0, 1).map(|x| x.size()), Ok(0));
+            assert_eq!(checked_array_layout(elem_size, usize::MAX, 1).is_ok(), elem_size <= 1);
+        }
+    }
+
+    #[test]
+    fn test_array_padding_overflow() {
+        // The stride is padded to the alignment, so counts fitting unpadded can overflow.
+        let max = usize::MAX / 24;
+        assert!(checked_array_layout(20, max, 8).is_ok());
+        assert_eq!(checked_array_layout(20, max + 1, 8), Err(LayoutErr));
+        assert!(checked_array_layout(20, max, 1).is_ok());
+        assert!(checked_array_layout(20, max + 1, 1).is_ok());
+
+        // The element itself cannot be padded.
+        assert_eq!(checked_array_layout(usize::MAX, 1, 2), Err(LayoutErr));
+        assert_eq!(checked_array_layout(usize::MAX - 6, 1, 8), Err(LayoutErr));
+        assert_eq!(checked_array_layout(usize::MAX - 7, 1, 8).map(|x| x.size()),
+                   Ok(usize::MAX - 7));
+
+        // Zero alignments are rejected.
+        assert_eq!(checked_array_layout(8, 1, 0), Err(LayoutErr));
+
+        // Padding is applied between the elements.
+        assert_eq!(checked_array_layout(5, 3, 4).map(|x| x.size()), Ok(24));
+        assert_eq!(checked_array_layout(5, 3, 4).map(|x| x.padded_size()), Ok(24));
+    }
+
+    #[test]
+    fn test_typed_array() {
+        assert_eq!(array::<u64>(4).map(|x| x.size()), Ok(32));
+        assert_eq!(array::<u64>(usize::MAX / 8 + 1), Err(LayoutErr));
+        assert_eq!(array::<(usize, usize, u8)>(2).map(|x| x.size()),
+                   Ok(2 * mem::size_of::<(usize, usize, u8)>()));
+    }
+}
@@ -53,6 +53,20 @@ impl<F: FnMut() -> T, T> LazyInit<F, T> {
         }
     }
 
+    /// Is the inner value initialized?
+    #[inline]
+    pub fn is_initialized(&self) -> bool {
+        if let State::Initialized(_) = self.state { true } else { false }
+    }
+
+    /// Set the inner value, skipping the initializer.
+    ///
+    /// If the container is already initialized, the old value is replaced.
+    #[inline]
+    pub fn set(&mut self, inner: T) {
+        self.state = State::Initialized(inner);
+    }
+
     /// Get the inner of the container.
     ///
     /// This won't mutate the container itself, since it consumes it. The initializer will (if
@@ -81,6 +95,21 @@ mod test {
         assert_eq!(*lazy.get(), 400);
     }
 
+    #[test]
+    fn test_set() {
+        let is_called = Cell::new(false);
+        let mut lazy = LazyInit::new(|| {
+            is_called.set(true);
+            1
+        });
+
+        assert!(!lazy.is_initialized());
+        lazy.set(2);
+        assert!(lazy.is_initialized());
+        assert_eq!(*lazy.get(), 2);
+        assert!(!is_called.get());
+    }
+
     #[test]
     fn test_laziness() {
         let is_called = Cell::new(false);
@@ -15,15 +15,22 @@
 
 #![no_std]
 
-#![feature(allocator, const_fn, core_intrinsics, stmt_expr_attributes, drop_types_in_const,
-           nonzero, optin_builtin_traits, type_ascription, thread_local, linkage,
-           try_from)]
+#![feature(allocator, associated_consts, const_fn, core_intrinsics, stmt_expr_attributes,
+           drop_types_in_const, nonzero, optin_builtin_traits, type_ascription, thread_local,
+           linkage, try_from, integer_atomics, repr_simd, fmt_flags_align)]
 #![warn(missing_docs, cast_precision_loss, cast_sign_loss, cast_possible_wrap,
         cast_possible_truncation, filter_map, if_not_else, items_after_statements,
         invalid_upcast_comparisons, mutex_integer, nonminimal_bool, shadow_same, shadow_unrelated,
         single_match_else, string_add, string_add_assign, wrong_pub_self_convention)]
 
+#[macro_use]
 extern crate ralloc_shim as shim;
+#[cfg(feature = "std")]
+extern crate std;
+
+// The thread-local allocators are per thread, which makes no sense in a single-threaded build.
+#[cfg(all(feature = "single_threaded", feature = "tls"))]
+compile_error!("The `single_threaded` feature cannot be combined with `tls` (or `debug_locks`).");
 
 #[macro_use]
 mod log;
@@ -33,24 +40,111 @@ mod tls;
 #[cfg(feature = "allocator")]
 mod symbols;
 
-#[macro_use]
-mod unborrow;
-
 mod allocator;
+#[cfg(feature = "arenas")]
+mod arena;
+mod atomic;
 mod block;
 mod bookkeeper;
+mod bootstrap;
 mod brk;
 mod cell;
+mod class;
+mod conf;
+#[cfg(feature = "debugger")]
+mod dump;
 mod fail;
+mod handle;
+#[cfg(feature = "header")]
+mod header;
+mod heap;
 mod lazy_init;
 mod leak;
+#[cfg(feature = "debugger")]
+mod live;
+mod mapped;
+mod meta;
+#[cfg(feature = "mte")]
+mod mte;
+#[cfg(feature = "numa")]
+mod numa;
 mod prelude;
 mod ptr;
+mod random;
+mod region;
+mod secure;
+#[cfg(feature = "shadow_accounting")]
+mod shadow;
+#[cfg(feature = "sidetable")]
+mod sidetable;
+#[macro_use]
+#[cfg(feature = "sites")]
+mod site;
+#[cfg(feature = "slab")]
+mod slab;
+#[cfg(feature = "realloc_slack")]
+mod slack;
+mod sort;
 mod sync;
+#[cfg(feature = "tagging")]
+mod tag;
 mod vec;
+mod watermark;
+
+#[cfg(feature = "bench")]
+pub mod bench;
+#[cfg(any(feature = "debugger", feature = "shadow_accounting"))]
+pub mod debug;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod layout;
+#[cfg(feature = "raw")]
+pub mod raw;
+#[cfg(feature = "tls")]
+pub mod scratch;
+pub mod sig;
+#[cfg(feature = "stats")]
+pub mod stats;
+#[cfg(any(test, feature = "test_util"))]
+pub mod test_util;
+#[cfg(feature = "trace")]
+pub mod trace;
+#[cfg(any(feature = "debugger", feature = "trace"))]
+pub mod wire;
+
+// Initialize the allocator before `main`. Miri doesn't run constructors.
+#[cfg(all(feature = "early_init", not(feature = "miri")))]
+constructor!(__RALLOC_EARLY_INIT, allocator::early_init);
 
-pub use allocator::{alloc, free, realloc, realloc_inplace};
+pub use allocator::{alloc, try_alloc, calloc, free, dealloc_sized, realloc, realloc_inplace,
+                    realloc_with_hint, alloc_many, dealloc_many, alloc_scatter,
+                    dealloc_scatter, purge, PurgeReport, init_from_buffer,
+                    AlreadyInitialized, validate_and_repair, preallocate};
+pub use bookkeeper::{HeapError, RepairReport};
+pub use allocator::MIN_ALIGN;
+#[cfg(feature = "tagging")]
+pub use allocator::alloc_tagged;
+#[cfg(feature = "sites")]
+pub use allocator::alloc_at_site;
+#[cfg(feature = "sites")]
+pub use site::Site;
+#[cfg(any(feature = "header", feature = "sidetable"))]
+pub use allocator::{free_unsized, usable_size};
 pub use brk::sbrk;
-pub use fail::set_oom_handler;
+pub use conf::{set_zero_on_free, set_auto_trim, set_max_allocation, max_allocation,
+               set_address_ordered, set_slab_decay, set_adaptive_brk, set_hot_first,
+               set_staged_growth};
+pub use fail::{set_oom_handler, AllocErr, GrowError};
+pub use handle::Ptr;
+pub use heap::{DropPolicy, Heap, HeapSnapshot, route_free, MAX_HEAPS};
+pub use mapped::max_align;
+#[cfg(feature = "numa")]
+pub use numa::{set_numa_interleave, numa_interleave, nodes as numa_nodes};
+#[cfg(feature = "realloc_slack")]
+pub use slack::{set_realloc_slack, realloc_slack};
+pub use watermark::{set_watermark_callback, heap_usage, Direction};
 #[cfg(feature = "tls")]
 pub use fail::set_thread_oom_handler;
+#[cfg(feature = "bounded_free")]
+pub use allocator::{maintenance, free_ops_peak, FREE_OPS_BOUND};
+pub use secure::{secure_alloc, secure_free};
@@ -0,0 +1,579 @@
+//! The table of live allocations.
+//!
+//! With the `debugger` feature, every allocation made through the front end is recorded in an
+//! address-ordered table, allowing tools to map arbitrary pointers to their allocation.
+//!
+//! The table also backs the leak report, which lists the allocations still live at exit (enabled
+//! with `leak_report:1` in `RALLOC_CONF`).
+//!
+//! Every allocation is stamped with its serial number: the number of allocations made before it.
+//! Its generation is the serial number divided by `config::LIVE_GENERATION`, and the age of an
+//! allocation (in generations) is a cheap hint of whether it has been leaked.
+//!
+//! The serial numbers tell apart allocations reusing the same address, which lets `Ptr` catch
+//! handles outliving their allocation.
+
+use prelude::*;
+
+use core::{cmp, fmt, mem, ptr, slice};
+
+use shim::{atexit, config, syscalls, valgrind};
+
+use {sort, sync};
+use log::NoAllocWriter;
+use ptr::with_addr;
+#[cfg(feature = "tagging")]
+use tag;
+
+/// The live allocations.
+static LIVE: sync::Mutex<Table> = sync::Mutex::ranked("live allocations", sync::rank::FRONT_END,
+                                                      Table::new());
+
+/// An address-ordered table of allocations.
+struct Table {
+    /// The allocations as `(address, size, redzone, serial)`, sorted by address.
+    ///
+    /// The redzone is the number of bytes reserved after the allocation, and the serial is the
+    /// serial number of the allocation.
+    entries: Vec<(usize, usize, usize, u64)>,
+    /// The number of allocations recorded so far.
+    allocations: u64,
+}
+
+impl Table {
+    /// Create a new empty table.
+    const fn new() -> Table {
+        Table {
+            entries: Vec::new(),
+            allocations: 0,
+        }
+    }
+
+    /// Get the current generation.
+    fn generation(&self) -> u64 {
+        birth(self.allocations)
+    }
+
+    /// Find the entry containing `addr`.
+    fn find(&self, addr: usize) -> Option<(usize, usize, usize)> {
+        self.find_entry(addr).map(|(base, size, redzone, _)| (base, size, redzone))
+    }
+
+    /// Find the entry containing `addr`, including its serial number.
+    fn find_entry(&self, addr: usize) -> Option<(usize, usize, usize, u64)> {
+        sort::predecessor(&self.entries, &addr, |x| x.0)
+            .map(|n| self.entries[n])
+            .and_then(|entry| if addr - entry.0 < entry.1 { Some(entry) } else { None })
+    }
+
+    /// Insert an entry for a new allocation.
+    ///
+    /// This counts the allocation towards the generations.
+    fn insert(&mut self, addr: usize, size: usize, redzone: usize) {
+        let serial = self.allocations;
+        self.allocations += 1;
+
+        self.insert_serial(addr, size, redzone, serial);
+    }
+
+    /// Insert an entry of some serial number.
+    fn insert_serial(&mut self, addr: usize, size: usize, redzone: usize, serial: u64) {
+        // Zero-sized allocations contain no bytes.
+        if size == 0 { return; }
+
+        sort::insert(&mut self.entries, (addr, size, redzone, serial), |x| x.0);
+    }
+
+    /// Remove the range `addr..addr + size` from the table.
+    ///
+    /// Since partial frees are allowed, this can shrink or split an entry. If the left part
+    /// remains, it takes its redzone from the start of the range. The remaining parts keep their
+    /// serial number. Ranges outside the table are ignored.
+    ///
+    /// The range of memory to be released (in place of the given range) is returned.
+    fn remove(&mut self, addr: usize, size: usize) -> (usize, usize) {
+        if size == 0 { return (addr, size); }
+
+        let n = match sort::predecessor(&self.entries, &addr, |x| x.0) {
+            Some(n) => n,
+            None => return (addr, size),
+        };
+
+        let (base, old_size, redzone, serial) = self.entries[n];
+        if addr - base >= old_size { return (addr, size); }
+
+        // The parts of the entry surrounding the range.
+        let left = addr - base;
+        let right = (base + old_size).saturating_sub(addr + size);
+
+        match (left, right) {
+            // The whole allocation is freed, including the redzone.
+            (0, 0) => {
+                sort::remove(&mut self.entries, n);
+                valgrind::freelike_block(base as *const u8, 0);
+
+                (addr, size + redzone)
+            },
+            // The head is freed.
+            (0, right) => {
+                sort::remove(&mut self.entries, n);
+                self.insert_serial(addr + size, right, redzone, serial);
+                valgrind::freelike_block(base as *const u8, 0);
+                valgrind::malloclike_block((addr + size) as *const u8, right, 0, true);
+
+                (addr, size)
+            },
+            // The tail is freed, so the redzone moves to the new end.
+            (left, 0) => {
+                let new_redzone = cmp::min(config::VALGRIND_REDZONE, size + redzone);
+                self.entries[n] = (base, left, new_redzone, serial);
+                valgrind::resizeinplace_block(base as *const u8, old_size, left, 0);
+                valgrind::make_mem_noaccess(addr as *const u8, new_redzone);
+
+                (addr + new_redzone, size + redzone - new_redzone)
+            },
+            // The middle is freed, so the left part takes its redzone from the range.
+            (left, right) => {
+                let new_redzone = cmp::min(config::VALGRIND_REDZONE, size);
+                self.entries[n] = (base, left, new_redzone, serial);
+                self.insert_serial(addr + size, right, redzone, serial);
+                valgrind::resizeinplace_block(base as *const u8, old_size, left, 0);
+                valgrind::make_mem_noaccess(addr as *const u8, new_redzone);
+                valgrind::malloclike_block((addr + size) as *const u8, right, 0, true);
+
+                (addr + new_redzone, size - new_redzone)
+            },
+        }
+    }
+
+    /// Remove every entry starting in the range `addr..addr + size`.
+    fn remove_within(&mut self, addr: usize, size: usize) {
+        loop {
+            let n = match self.entries.binary_search_by(|&(x, _, _, _)| x.cmp(&addr)) {
+                Ok(n) | Err(n) => n,
+            };
+
+            match self.entries.get(n).cloned() {
+                Some((base, _, _, _)) if base - addr < size => {
+                    sort::remove(&mut self.entries, n);
+                    valgrind::freelike_block(base as *const u8, 0);
+                },
+                _ => break,
+            }
+        }
+    }
+}
+
+/// Get the generation of an allocation of some serial number.
+#[inline]
+fn birth(serial: u64) -> u64 {
+    serial / config::LIVE_GENERATION as u64
+}
+
+/// Record an allocation, followed by a redzone of `redzone` bytes.
+pub fn insert(ptr: *mut u8, size: usize, redzone: usize) {
+    LIVE.lock().insert(ptr as usize, size, redzone);
+
+    valgrind::malloclike_block(ptr, size, 0, false);
+    valgrind::make_mem_noaccess(unsafe {
+        // The redzone directly follows the allocation.
+        ptr.offset(size as isize)
+    }, redzone);
+}
+
+/// Record a (possibly partial) free.
+///
+/// The range of memory to be released in place of the freed range (which differs from it, when
+/// redzones are involved) is returned.
+pub fn remove(ptr: *mut u8, size: usize) -> (*mut u8, usize) {
+    // The table is keyed by address, so the pointer is rebuilt from the freed one.
+    let (addr, size) = LIVE.lock().remove(ptr as usize, size);
+    (with_addr(ptr, addr), size)
+}
+
+/// Forget every allocation starting in the range of `size` bytes from `ptr`.
+///
+/// This is for memory given back in bulk (e.g. the regions of a reclaimed heap), whose
+/// allocations were never freed.
+pub fn forget(ptr: *mut u8, size: usize) {
+    LIVE.lock().remove_within(ptr as usize, size);
+}
+
+/// Get the size of the redzone following the allocation containing `ptr`.
+///
+/// Pointers, which are not in any live allocation, give zero.
+pub fn redzone(ptr: *const u8) -> usize {
+    LIVE.lock().find(ptr as usize).map_or(0, |(_, _, redzone)| redzone)
+}
+
+/// Find the live allocation containing some pointer.
+///
+/// The start and size of the allocation are returned. Pointers, which are not in any live
+/// allocation (including one-past-the-end pointers and freed pointers), give `None`.
+///
+/// This is a binary search, and thus cheap enough to be called in a loop (e.g. by a conservative
+/// garbage collector).
+pub fn find_allocation(ptr: *const u8) -> Option<(*mut u8, usize)> {
+    LIVE.lock().find(ptr as usize).map(|(base, size, _)| {
+        (with_addr(ptr as *mut u8, base), size)
+    })
+}
+
+/// Get the serial number of the live allocation containing some pointer.
+///
+/// Pointers, which are not in any live allocation, give `None`.
+pub fn serial(ptr: *const u8) -> Option<u64> {
+    LIVE.lock().find_entry(ptr as usize).map(|(_, _, _, serial)| serial)
+}
+
+/// Get the current generation of the allocations.
+///
+/// See `config::LIVE_GENERATION`.
+pub fn generation() -> u64 {
+    LIVE.lock().generation()
+}
+
+/// Call `f` on every live allocation older than `generations` generations.
+///
+/// `f` gets the start, size, and age (in generations) of the allocations. Old allocations are the
+/// likely leaks of a long-running process.
+///
+/// The table is only locked while reading each entry, so `f` may allocate and free. The entries
+/// can move under concurrent frees, so the listing is only approximate then.
+pub fn allocations_older_than<F: FnMut(*mut u8, usize, u64)>(generations: u64, mut f: F) {
+    for_each(|ptr, size, age| if age > generations {
+        f(ptr, size, age);
+    });
+}
+
+/// Call `f` on every live allocation, in address order.
+///
+/// `f` gets the start, size, and age (in generations) of the allocations. Like in
+/// `allocations_older_than`, the table is only locked while reading each entry.
+pub fn for_each<F: FnMut(*mut u8, usize, u64)>(mut f: F) {
+    let count = LIVE.lock().entries.len();
+
+    for n in 0..count {
+        let (now, (addr, size, _, serial)) = {
+            let table = LIVE.lock();
+            match table.entries.get(n).cloned() {
+                Some(entry) => (table.generation(), entry),
+                None => break,
+            }
+        };
+
+        f(addr as *mut u8, size, now - birth(serial));
+    }
+}
+
+/// Write a report of the live allocations.
+///
+/// Every allocation is listed with its address, size, tag (untagged allocations, and every
+/// allocation without the `tagging` feature, have tag 0), and age in generations, followed by the
+/// totals. The oldest allocations are listed first. When called at exit, these are the leaks.
+///
+/// This never allocates from the heap. The table is copied into a memory mapping of its own
+/// (while locked), and the copy is sorted by age, so other threads (or other exit handlers) can
+/// keep freeing while the report is written. If nothing can be mapped, the table is listed in
+/// place instead (see `write_leaks_in_place`).
+pub fn write_leaks<W: fmt::Write>(w: &mut W) -> fmt::Result {
+    /// An entry of the copy, as `(address, size, serial)`.
+    type Entry = (usize, usize, u64);
+
+    // Copy the table.
+    let (copy, size, now) = {
+        let table = LIVE.lock();
+        let count = table.entries.len();
+        if count == 0 {
+            return writeln!(w, "ralloc: 0 bytes leaked in 0 allocations");
+        }
+
+        let size = count * mem::size_of::<Entry>();
+        let buf = match syscalls::mmap(size) {
+            Ok(buf) => buf as *mut Entry,
+            Err(_) => {
+                drop(table);
+                return write_leaks_in_place(w);
+            },
+        };
+
+        let copy = unsafe {
+            // The mapping is fresh, page-aligned and large enough for `count` entries.
+            slice::from_raw_parts_mut(buf, count)
+        };
+        for (x, &(addr, size, _, serial)) in copy.iter_mut().zip(table.entries.iter()) {
+            *x = (addr, size, serial);
+        }
+
+        (copy, size, table.generation())
+    };
+
+    // The serial numbers are unique, and the older allocations have the lower ones.
+    sort::sort_by(copy, |a, b| a.2 < b.2);
+
+    let mut res = Ok(());
+    let mut bytes = 0;
+    for &(addr, size, serial) in copy.iter() {
+        #[cfg(feature = "tagging")]
+        let tag = tag::get(addr as *mut u8);
+        #[cfg(not(feature = "tagging"))]
+        let tag = 0;
+
+        res = writeln!(w, "ralloc: leaked {} bytes at {:#x} (tag {}, age {})", size, addr, tag,
+                       now.saturating_sub(birth(serial)));
+        if res.is_err() { break; }
+        bytes += size;
+    }
+    let listed = copy.len();
+
+    unsafe {
+        // The copy is not used anymore.
+        let _ = syscalls::munmap(copy.as_mut_ptr() as *mut u8, size);
+    }
+
+    res?;
+    writeln!(w, "ralloc: {} bytes leaked in {} allocations", bytes, listed)
+}
+
+/// Write a report of the live allocations, without copying the table.
+///
+/// This is the fallback of `write_leaks`, where memory cannot be mapped. The table is only locked
+/// while reading each entry, and the listing takes a pass over the table per generation present in
+/// it.
+fn write_leaks_in_place<W: fmt::Write>(w: &mut W) -> fmt::Result {
+    // Snapshot the counts, and find the oldest generation.
+    let (count, now, mut next) = {
+        let table = LIVE.lock();
+        (table.entries.len(), table.generation(),
+         table.entries.iter().map(|&(_, _, _, serial)| birth(serial)).min())
+    };
+
+    let mut bytes = 0;
+    let mut listed = 0;
+    while let Some(current) = next {
+        // List the allocations of this generation, while finding the next younger one.
+        next = None;
+
+        for n in 0..count {
+            // The entries can move under concurrent frees, so the listing is only approximate
+            // then.
+            let (addr, size, entry_birth) = match LIVE.lock().entries.get(n).cloned() {
+                Some((addr, size, _, serial)) => (addr, size, birth(serial)),
+                None => break,
+            };
+
+            if entry_birth > current {
+                next = Some(next.map_or(entry_birth, |x| cmp::min(x, entry_birth)));
+            }
+            if entry_birth != current { continue; }
+
+            #[cfg(feature = "tagging")]
+            let tag = tag::get(addr as *mut u8);
+            #[cfg(not(feature = "tagging"))]
+            let tag = 0;
+
+            writeln!(w, "ralloc: leaked {} bytes at {:#x} (tag {}, age {})", size, addr, tag,
+                     now.saturating_sub(entry_birth))?;
+            bytes += size;
+            listed += 1;
+        }
+    }
+
+    writeln!(w, "ralloc: {} bytes leaked in {} allocations", bytes, listed)
+}
+
+/// A writer to the log (stderr by default), which never allocates.
+///
+/// Every line is written at once.
+struct LogWriter {
+    /// The current line.
+    line: NoAllocWriter,
+}
+
+impl fmt::Write for LogWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        fmt::Write::write_str(&mut self.line, s)?;
+
+        if s.ends_with('\n') {
+            self.line.end_line();
+            config::log(self.line.as_str());
+            self.line = NoAllocWriter::new();
+        }
+
+        Ok(())
+    }
+}
+
+/// Write the leak report to the log.
+unsafe extern fn report_leaks(_: *mut u8) {
+    let _ = write_leaks(&mut LogWriter {
+        line: NoAllocWriter::new(),
+    });
+}
+
+/// Register the leak report to be written when the process exits.
+pub fn register_leak_report() {
+    // Logging.
+    log!(NOTE, "Registering the leak report.");
+
+    if atexit::register(ptr::null_mut(), report_leaks).is_err() {
+        log!(WARNING, "Exit handlers are unsupported, so no leak report is written.");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use shim::config;
+
+    #[test]
+    fn test_find() {
+        let mut table = Table::new();
+        table.insert(300, 50, 0);
+        table.insert(100, 100, 0);
+        table.insert(200, 10, 0);
+
+        // The first, interior, and last bytes.
+        assert_eq!(table.find(100), Some((100, 100, 0)));
+        assert_eq!(table.find(150), Some((100, 100, 0)));
+        assert_eq!(table.find(199), Some((100, 100, 0)));
+        // Adjacent allocations.
+        assert_eq!(table.find(200), Some((200, 10, 0)));
+        // One past the end.
+        assert_eq!(table.find(210), None);
+        assert_eq!(table.find(350), None);
+        // Before the first.
+        assert_eq!(table.find(99), None);
+        assert_eq!(table.find(0), None);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut table = Table::new();
+        table.insert(100, 100, 0);
+        table.insert(300, 50, 0);
+
+        // Freed allocations are gone.
+        assert_eq!(table.remove(300, 50), (300, 50));
+        assert_eq!(table.find(300), None);
+
+        // Partial frees split the allocation.
+        table.remove(120, 30);
+        assert_eq!(table.find(100).map(|(base, size, _)| (base, size)), Some((100, 20)));
+        assert_eq!(table.find(120), None);
+        assert_eq!(table.find(149), None);
+        assert_eq!(table.find(150), Some((150, 50, 0)));
+
+        // Foreign frees are ignored.
+        assert_eq!(table.remove(1000, 10), (1000, 10));
+        assert_eq!(table.remove(10, 10), (10, 10));
+        assert_eq!(table.entries.len(), 2);
+    }
+
+    #[test]
+    fn test_remove_within() {
+        let mut table = Table::new();
+        table.insert(100, 100, 0);
+        table.insert(200, 10, 0);
+        table.insert(240, 20, 0);
+        table.insert(300, 50, 0);
+
+        // Only the entries starting in the range go.
+        table.remove_within(150, 150);
+        assert_eq!(table.find(100), Some((100, 100, 0)));
+        assert_eq!(table.find(200), None);
+        assert_eq!(table.find(240), None);
+        assert_eq!(table.find(300), Some((300, 50, 0)));
+    }
+
+    #[test]
+    #[cfg(feature = "security")]
+    fn test_wiped_entry() {
+        let mut table = Table::new();
+        table.insert(100, 100, 0);
+        table.insert(300, 50, 0);
+
+        // Freeing the first allocation moves the second one down, emptying the last entry.
+        table.remove(100, 100);
+        assert_eq!(table.entries.len(), 1);
+
+        // Neither allocation is left in the emptied entry.
+        let dead = unsafe { table.entries.dead_slots() };
+        assert_eq!(dead[0], (0, 0, 0, 0));
+        assert!(dead.iter().all(|&(addr, _, _, _)| addr != 100 && addr != 300));
+    }
+
+    #[test]
+    fn test_zero_sized() {
+        let mut table = Table::new();
+        table.insert(100, 0, 0);
+
+        assert_eq!(table.find(100), None);
+        assert_eq!(table.entries.len(), 0);
+    }
+
+    #[test]
+    fn test_generations() {
+        let mut table = Table::new();
+        let generation = config::LIVE_GENERATION;
+
+        // Two phases, separated by three generations of churn.
+        table.insert(100, 10, 0);
+        table.insert(200, 10, 0);
+        for _ in 0..3 * generation {
+            table.insert(1000, 10, 0);
+            table.remove(1000, 10);
+        }
+        table.insert(300, 10, 0);
+
+        assert_eq!(table.generation(), 3);
+        let births: [u64; 3] = [birth(table.entries[0].3), birth(table.entries[1].3),
+                                birth(table.entries[2].3)];
+        assert_eq!(births, [0, 0, 3]);
+
+        // Split parts keep their serial number, and thus their generation.
+        table.remove(103, 4);
+        assert_eq!(table.entries[0], (100, 3, 4, 0));
+        assert_eq!(table.entries[1], (107, 3, 0, 0));
+    }
+
+    #[test]
+    fn test_serial() {
+        let mut table = Table::new();
+        table.insert(100, 10, 0);
+        table.insert(200, 10, 0);
+        assert_eq!(table.find_entry(105).map(|x| x.3), Some(0));
+        assert_eq!(table.find_entry(200).map(|x| x.3), Some(1));
+
+        // An allocation reusing the address is told apart.
+        table.remove(100, 10);
+        assert_eq!(table.find_entry(100), None);
+        table.insert(100, 10, 0);
+        assert_eq!(table.find_entry(100).map(|x| x.3), Some(2));
+    }
+
+    #[test]
+    fn test_redzone() {
+        let mut table = Table::new();
+        table.insert(1000, 100, 16);
+
+        // Freeing the tail moves the redzone to the new end.
+        assert_eq!(table.remove(1080, 20), (1096, 20));
+        assert_eq!(table.find(1000), Some((1000, 80, 16)));
+
+        // Freeing the middle gives the left part a redzone from the range.
+        assert_eq!(table.remove(1010, 30), (1026, 14));
+        assert_eq!(table.find(1000), Some((1000, 10, 16)));
+        assert_eq!(table.find(1040), Some((1040, 40, 16)));
+
+        // Freeing the head leaves the redzone to the rest.
+        assert_eq!(table.remove(1040, 20), (1040, 20));
+        assert_eq!(table.find(1060), Some((1060, 20, 16)));
+
+        // Freeing everything releases the redzones.
+        assert_eq!(table.remove(1060, 20), (1060, 36));
+        assert_eq!(table.remove(1000, 10), (1000, 26));
+        assert_eq!(table.entries.len(), 0);
+    }
+}
@@ -1,6 +1,30 @@
 //! Allocator logging.
 //!
 //! This allows for detailed logging for `ralloc`.
+//!
+//! Only the `WARNING` and `ERROR` levels are compiled in by default. The `log_debug` feature adds
+//! `DEBUG`, `CALL`, and `NOTE`, and `log_internal` adds `INTERNAL` on top. Levels which are not
+//! compiled in cost nothing, not even the evaluation of the arguments. The compiled in levels can
+//! further be filtered at runtime (see `internal::set_level`).
+//!
+//! Logging must never allocate, as it happens with the locks of the allocator held. Every line is
+//! formatted into a buffer on the stack (see `NoAllocWriter`), and written with a single syscall.
+//! In debug builds with TLS, entering the allocator while a line is emitted aborts, naming the
+//! line.
+
+use core::{fmt, str};
+
+use shim::config;
+
+/// The lowest log level compiled in.
+#[cfg(feature = "log_internal")]
+pub const MIN_LEVEL: u8 = 1;
+/// The lowest log level compiled in.
+#[cfg(all(feature = "log_debug", not(feature = "log_internal")))]
+pub const MIN_LEVEL: u8 = 2;
+/// The lowest log level compiled in.
+#[cfg(not(feature = "log_debug"))]
+pub const MIN_LEVEL: u8 = 5;
 
 /// Log to the appropriate source.
 ///
@@ -32,15 +56,20 @@ macro_rules! log {
             use core::fmt::Write;
 
             use log::internal::{LogWriter, level};
-
-            // Set the level.
-            if level($lv) {
-                // Print the pool state.
-                let mut log = LogWriter::new();
-                // Print the log message.
-                let _ = write!(log, $kind);
+            use log::MIN_LEVEL;
+
+            // The first condition is constant, so levels which aren't compiled in are removed
+            // entirely, arguments and all.
+            #[allow(absurd_extreme_comparisons)]
+            let enabled = $lv >= MIN_LEVEL && level($lv);
+
+            if enabled {
+                // The line is written, when the writer is dropped.
+                let mut log = LogWriter::new(file!(), line!());
+                // Print the log message. The constant parts skip the formatting machinery.
+                let _ = log.write_str($kind);
                 let _ = write!(log, $( $arg ),*);
-                let _ = writeln!(log, " (at {}:{})", file!(), line!());
+                let _ = log.write_str(concat!(" (at ", file!(), ":", line!(), ")"));
             }
         }
     };
@@ -134,6 +163,153 @@ macro_rules! assert_eq {
     })
 }
 
+/// The size of the digit buffers of `fmt_hex_addr` and `fmt_size`.
+///
+/// This fits the decimal digits of a 64-bit `usize`, and the hexadecimal ones with the prefix.
+const DIGITS: usize = 20;
+
+/// Write an address in hexadecimal, like `{:#x}`.
+///
+/// This bypasses `core::fmt`, which dominates the cost of the log lines, when formatting
+/// addresses. Writing to a `NoAllocWriter` never fails, nor panics on truncation.
+pub fn fmt_hex_addr<W: fmt::Write>(addr: usize, w: &mut W) -> fmt::Result {
+    let mut buf = [0; DIGITS];
+    let mut n = buf.len();
+    let mut x = addr;
+    loop {
+        n -= 1;
+        buf[n] = b"0123456789abcdef"[x & 0xF];
+        x >>= 4;
+
+        if x == 0 { break; }
+    }
+    n -= 2;
+    buf[n] = b'0';
+    buf[n + 1] = b'x';
+
+    w.write_str(digits(&buf[n..]))
+}
+
+/// Write a size in decimal, like `{}`.
+///
+/// See `fmt_hex_addr`.
+pub fn fmt_size<W: fmt::Write>(size: usize, w: &mut W) -> fmt::Result {
+    let mut buf = [0; DIGITS];
+    let mut n = buf.len();
+    let mut x = size;
+    loop {
+        n -= 1;
+        buf[n] = b'0' + (x % 10) as u8;
+        x /= 10;
+
+        if x == 0 { break; }
+    }
+
+    w.write_str(digits(&buf[n..]))
+}
+
+/// Get the digits written to a digit buffer as a string.
+fn digits(buf: &[u8]) -> &str {
+    unsafe {
+        // Only ASCII digits, letters, and prefixes are written to digit buffers.
+        str::from_utf8_unchecked(buf)
+    }
+}
+
+/// The mark ending output, which was cut off.
+#[cfg_attr(not(any(feature = "log", feature = "stats", feature = "debugger")), allow(dead_code))]
+const ELLIPSIS: &'static str = "…";
+
+/// A writer formatting a line into a fixed buffer on the stack.
+///
+/// This never allocates. Output beyond the buffer is cut off (at a character boundary), and
+/// replaced by an ellipsis. Writing never fails, such that the formatting continues, but the rest
+/// is discarded.
+#[cfg_attr(not(any(feature = "log", feature = "stats", feature = "debugger")), allow(dead_code))]
+pub struct NoAllocWriter {
+    /// The buffer.
+    ///
+    /// The last byte is kept spare for `end_line`.
+    buf: [u8; config::LOG_BUFFER_SIZE],
+    /// The number of bytes written.
+    len: usize,
+    /// Was the output cut off?
+    truncated: bool,
+}
+
+#[cfg_attr(not(any(feature = "log", feature = "stats", feature = "debugger")), allow(dead_code))]
+impl NoAllocWriter {
+    /// Create an empty writer.
+    pub fn new() -> NoAllocWriter {
+        NoAllocWriter {
+            buf: [0; config::LOG_BUFFER_SIZE],
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    /// Was the output cut off?
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// End the line, unless it ends already.
+    pub fn end_line(&mut self) {
+        if !self.as_str().ends_with('\n') {
+            // The spare byte guarantees room for the newline.
+            self.buf[self.len] = b'\n';
+            self.len += 1;
+        }
+    }
+
+    /// Get the output written so far.
+    pub fn as_str(&self) -> &str {
+        unsafe {
+            // Only whole strings and prefixes cut at character boundaries are written.
+            str::from_utf8_unchecked(&self.buf[..self.len])
+        }
+    }
+}
+
+impl fmt::Write for NoAllocWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.truncated { return Ok(()); }
+
+        // Keep the spare byte.
+        let cap = self.buf.len() - 1;
+        if self.len + s.len() <= cap {
+            self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+
+            return Ok(());
+        }
+
+        // Cut the output off, leaving room for the ellipsis.
+        let keep = cap - ELLIPSIS.len();
+        if self.len < keep {
+            let mut n = keep - self.len;
+            while !s.is_char_boundary(n) {
+                n -= 1;
+            }
+
+            self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+            self.len += n;
+        } else {
+            // The previous writes went past the cut, so we back off to a character boundary.
+            self.len = keep;
+            while self.buf[self.len] & 0xC0 == 0x80 {
+                self.len -= 1;
+            }
+        }
+
+        self.buf[self.len..self.len + ELLIPSIS.len()].copy_from_slice(ELLIPSIS.as_bytes());
+        self.len += ELLIPSIS.len();
+        self.truncated = true;
+
+        Ok(())
+    }
+}
+
 /// Top-secret module.
 #[cfg(feature = "log")]
 pub mod internal {
@@ -143,9 +319,17 @@ pub mod internal {
     use core::cell::Cell;
     use core::ops::Range;
 
+    use atomic::{self, AtomicU8};
+
     use shim::config;
 
-    use sync;
+    #[cfg(all(feature = "tls", debug_assertions))]
+    use tls;
+
+    use super::NoAllocWriter;
+
+    /// The minimum log level, as set at runtime.
+    static LEVEL: AtomicU8 = AtomicU8::new(config::MIN_LOG_LEVEL);
 
     /// The log lock.
     ///
@@ -153,36 +337,82 @@ pub mod internal {
     #[cfg(not(feature = "no_log_lock"))]
     pub static LOG_LOCK: Mutex<()> = Mutex::new(());
 
+    #[cfg(all(feature = "tls", debug_assertions))]
+    tls! {
+        /// The location of the log line, which the current thread is emitting.
+        ///
+        /// This is used to detect allocations while logging.
+        static EMITTING: Cell<Option<(&'static str, u32)>> = Cell::new(None);
+    }
+
     /// A log writer.
     ///
-    /// This writes to the shim logger.
+    /// This formats a line on the stack, and writes it to the shim logger when dropped.
     pub struct LogWriter {
-        /// The inner lock.
-        #[cfg(not(feature = "no_log_lock"))]
-        _lock: sync::MutexGuard<'static, ()>,
+        /// The line.
+        buf: NoAllocWriter,
+        /// The line emitted before this one, if this line is logged while emitting another.
+        #[cfg(all(feature = "tls", debug_assertions))]
+        outer: Option<(&'static str, u32)>,
     }
 
     impl LogWriter {
-        /// Standard error output.
-        pub fn new() -> LogWriter {
-            #[cfg(feature = "no_log_lock")]
-            {
-                LogWriter {}
-            }
+        /// Start a log line, logged at `file:line`.
+        pub fn new(file: &'static str, line: u32) -> LogWriter {
+            // Mark the line as being emitted. Lines can nest (e.g. an assertion failing while a
+            // line is formatted), so the outer line is restored afterwards.
+            #[cfg(all(feature = "tls", debug_assertions))]
+            let outer = EMITTING.with_unlogged(|x| {
+                let outer = x.get();
+                x.set(Some((file, line)));
+                outer
+            });
+            #[cfg(not(all(feature = "tls", debug_assertions)))]
+            let _ = (file, line);
 
-            #[cfg(not(feature = "no_log_lock"))]
             LogWriter {
-                _lock: LOG_LOCK.lock(),
+                buf: NoAllocWriter::new(),
+                #[cfg(all(feature = "tls", debug_assertions))]
+                outer: outer,
             }
         }
     }
 
     impl fmt::Write for LogWriter {
         fn write_str(&mut self, s: &str) -> fmt::Result {
-            if config::log(s) == !0 { Err(fmt::Error) } else { Ok(()) }
+            fmt::Write::write_str(&mut self.buf, s)
+        }
+    }
+
+    impl Drop for LogWriter {
+        fn drop(&mut self) {
+            self.buf.end_line();
+
+            {
+                #[cfg(not(feature = "no_log_lock"))]
+                let _lock = LOG_LOCK.lock();
+
+                config::log(self.buf.as_str());
+            }
+
+            #[cfg(all(feature = "tls", debug_assertions))]
+            EMITTING.with_unlogged(|x| x.set(self.outer));
         }
     }
 
+    /// Make sure that no log line is being emitted by the current thread.
+    ///
+    /// This is called when the allocator is entered, as logging must not allocate. It is only
+    /// checked in debug builds with TLS.
+    #[inline]
+    pub fn check_not_emitting() {
+        #[cfg(all(feature = "tls", debug_assertions))]
+        EMITTING.with_unlogged(|x| if let Some((file, line)) = x.get() {
+            assert!(false, "The allocator was entered while emitting the log line at {}:{}. Does \
+                    a formatter allocate?", file, line);
+        });
+    }
+
     /// A "cursor".
     ///
     /// Cursors represents a block or an interval in the log output. This trait is implemented for
@@ -340,10 +570,168 @@ pub mod internal {
         }
     }
 
-    /// Check if this log level is enabled.
-    #[allow(absurd_extreme_comparisons)]
+    /// Check if this log level is enabled at runtime.
     #[inline]
     pub fn level(lv: u8) -> bool {
-        lv >= config::MIN_LOG_LEVEL
+        lv >= LEVEL.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Set the minimum log level at runtime.
+    ///
+    /// This only filters the levels compiled in, and cannot enable others.
+    pub fn set_level(lv: u8) {
+        LEVEL.store(lv, atomic::Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use core::fmt;
+    use core::cell::Cell;
+    use core::fmt::Write;
+
+    use shim::config;
+
+    #[test]
+    fn test_compiled_out() {
+        let evaluated = Cell::new(false);
+        log!(INTERNAL, "{}", {
+            evaluated.set(true);
+            0
+        });
+
+        // The arguments of a level, which isn't compiled in, are never evaluated.
+        assert_eq!(evaluated.get(), cfg!(feature = "log_internal"));
+    }
+
+    #[test]
+    fn test_no_alloc_writer() {
+        let mut w = NoAllocWriter::new();
+        write!(w, "{} {}", 42, "abc").unwrap();
+        w.end_line();
+        w.end_line();
+        assert_eq!(w.as_str(), "42 abc\n");
+        assert!(!w.is_truncated());
+
+        // Long output is cut off at a character boundary, and ends with an ellipsis.
+        let mut w = NoAllocWriter::new();
+        for _ in 0..config::LOG_BUFFER_SIZE {
+            write!(w, "æ").unwrap();
+        }
+        write!(w, "discarded").unwrap();
+        w.end_line();
+        assert!(w.is_truncated());
+        assert!(w.as_str().len() <= config::LOG_BUFFER_SIZE);
+        assert!(w.as_str().ends_with("æ…\n"));
+
+        // A single long write is cut off too.
+        let mut w = NoAllocWriter::new();
+        write!(w, "x").unwrap();
+        write!(w, "{:1$}", "æ", config::LOG_BUFFER_SIZE).unwrap();
+        assert!(w.is_truncated());
+        assert!(w.as_str().starts_with("xæ "));
+        assert!(w.as_str().ends_with(" …"));
+    }
+
+    #[test]
+    fn test_fmt_numbers() {
+        use prelude::*;
+
+        use core::mem;
+
+        /// Check the formatters against `core::fmt` for `x`.
+        fn check(x: usize) {
+            let mut fast = NoAllocWriter::new();
+            let mut slow = NoAllocWriter::new();
+            fmt_hex_addr(x, &mut fast).unwrap();
+            write!(slow, "{:#x}", x).unwrap();
+            assert_eq!(fast.as_str(), slow.as_str());
+
+            let mut fast = NoAllocWriter::new();
+            let mut slow = NoAllocWriter::new();
+            fmt_size(x, &mut fast).unwrap();
+            write!(slow, "{}", x).unwrap();
+            assert_eq!(fast.as_str(), slow.as_str());
+        }
+
+        for x in 0..1000 {
+            check(x);
+        }
+        for shift in 0..mem::size_of::<usize>() * 8 {
+            check((1 << shift) - 1);
+            check(1 << shift);
+            check((1 << shift) + 1);
+        }
+        let mut pow = 1usize;
+        while let Some(x) = pow.checked_mul(10) {
+            check(x - 1);
+            check(x);
+            pow = x;
+        }
+        check(!0);
+
+        // Blocks are formatted through them.
+        let mut buf = [0u8; 16];
+        let block = unsafe { ::test_util::buffer_block(&mut buf) };
+        let mut fast = NoAllocWriter::new();
+        let mut slow = NoAllocWriter::new();
+        write!(fast, "{:?}", block).unwrap();
+        write!(slow, "0x{:x}[{}]", Pointer::from(block.empty_left()).addr(), 16).unwrap();
+        assert_eq!(fast.as_str(), slow.as_str());
+
+        // They are cut off like any other output.
+        let mut w = NoAllocWriter::new();
+        for _ in 0..config::LOG_BUFFER_SIZE - 8 {
+            write!(w, "x").unwrap();
+        }
+        fmt_hex_addr(!0, &mut w).unwrap();
+        fmt_size(!0, &mut w).unwrap();
+        assert!(w.is_truncated());
+        assert!(w.as_str().ends_with("…"));
+    }
+
+    #[test]
+    #[cfg(all(feature = "log", feature = "tls", debug_assertions))]
+    fn test_alloc_while_logging() {
+        extern crate std;
+
+        use self::std::{env, process};
+        use self::std::string::String;
+
+        use allocator;
+
+        /// A value, which allocates when formatted.
+        struct Allocating;
+
+        impl fmt::Display for Allocating {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                let ptr = allocator::alloc(16, 1);
+                unsafe { allocator::free(ptr, 16); }
+
+                write!(f, "allocated")
+            }
+        }
+
+        if env::var("RALLOC_TEST_LOG_CHILD").is_ok() {
+            log!(WARNING, "{}", Allocating);
+
+            return;
+        }
+
+        // Log in a child process, which must abort.
+        let out = process::Command::new(env::current_exe().unwrap())
+            .arg("log::test::test_alloc_while_logging")
+            .arg("--exact")
+            .env("RALLOC_TEST_LOG_CHILD", "1")
+            .env_remove("RALLOC_CONF")
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&out.stderr);
+
+        assert!(!out.status.success());
+        assert!(stderr.contains("The allocator was entered while emitting the log line at \
+                                 src/log.rs:"));
     }
 }
@@ -0,0 +1,264 @@
+//! Over-aligned allocations.
+//!
+//! Carving a buffer aligned to `align` from the pool wastes up to `align` bytes of padding, which
+//! is fine for small alignments, but not for, say, a megabyte. Alignments beyond the pool's limit
+//! (`config::POOL_ALIGN_PAGES` pages) are therefore mapped directly: the mapping is made large
+//! enough to hold an aligned buffer anywhere in it, and the slop before and after the buffer is
+//! unmapped again. Only the aligned interior is registered as a region, so `free` recognizes and
+//! unmaps it.
+//!
+//! Where there is no memory mapping (e.g. in bare-metal mode), the pool's limit is the largest
+//! alignment supported, and larger ones fail with `AllocErr::UnsupportedAlignment`.
+//!
+//! With the `numa` feature, large buffers are mapped as well while they are interleaved over the
+//! NUMA nodes (see `numa`), whatever their alignment.
+
+use prelude::*;
+
+use core::mem;
+
+use shim::{config, syscalls};
+
+use allocator;
+use fail::{AllocErr, GrowError};
+#[cfg(feature = "numa")]
+use numa;
+use ptr::MAX_BLOCK;
+use region::{self, OwnedRegion, Origin};
+
+/// Get the largest alignment carved from the pool.
+#[inline]
+pub fn pool_limit() -> Align {
+    Align::new(config::POOL_ALIGN_PAGES * syscalls::page_size())
+        .expect("The pool alignment limit is not a power of two.")
+}
+
+/// Is a buffer of `size` bytes aligned to `align` mapped, rather than carved from the pool?
+#[inline]
+pub fn maps(size: usize, align: Align) -> bool {
+    align > pool_limit() || interleaves(size)
+}
+
+/// Are buffers of `size` bytes interleaved over the NUMA nodes?
+#[cfg(feature = "numa")]
+#[inline]
+fn interleaves(size: usize) -> bool {
+    numa::interleaves(size) && !allocator::bare_metal()
+}
+
+/// Are buffers of `size` bytes interleaved over the NUMA nodes?
+///
+/// Without the `numa` feature, they never are.
+#[cfg(not(feature = "numa"))]
+#[inline]
+fn interleaves(_size: usize) -> bool {
+    false
+}
+
+/// Interleave a fresh mapping over the NUMA nodes, returning whether it is.
+#[cfg(feature = "numa")]
+unsafe fn interleave(ptr: *mut u8, size: usize) -> bool {
+    numa::interleave(ptr, size)
+}
+
+/// Interleave a fresh mapping over the NUMA nodes (NOOP without the `numa` feature).
+#[cfg(not(feature = "numa"))]
+unsafe fn interleave(_ptr: *mut u8, _size: usize) -> bool {
+    false
+}
+
+/// Get the largest alignment supported.
+///
+/// With memory mapping, any alignment (up to half the address space) is supported. Otherwise,
+/// this is the pool's limit.
+pub fn max_align() -> usize {
+    if allocator::bare_metal() {
+        pool_limit().get()
+    } else {
+        1 << (mem::size_of::<usize>() * 8 - 1)
+    }
+}
+
+/// Get the size of the mapping of `ptr`, if it was mapped by `alloc`.
+///
+/// Secure allocations are mappings too, but they are not ours, so `None` is returned for them.
+pub fn size(ptr: *mut u8) -> Option<usize> {
+    match region::mapping(ptr) {
+        Some(region) if match region.origin {
+            Origin::Mmap { fd_less: true, locked: false, .. } => true,
+            _ => false,
+        } => {
+            Some(region.size())
+        },
+        _ => None,
+    }
+}
+
+/// Map a buffer of `size` bytes aligned to `align`.
+///
+/// The size is rounded up to whole pages.
+///
+/// # Errors
+///
+/// If there is no memory mapping, `AllocErr::UnsupportedAlignment` is returned. If the OS cannot
+/// map the memory, `AllocErr::Os` is returned, and if the region registry is full,
+/// `AllocErr::LimitReached` is.
+pub fn alloc(size: usize, align: Align) -> Result<*mut u8, AllocErr> {
+    // Logging.
+    log!(NOTE, "Mapping a buffer of size {} with alignment {}.", size, align);
+
+    let page = Align::page();
+    let too_large = AllocErr::TooLarge {
+        requested: size,
+        limit: MAX_BLOCK.saturating_sub(align.get()),
+    };
+
+    if allocator::bare_metal() {
+        return Err(AllocErr::UnsupportedAlignment(align.get()));
+    }
+
+    // Any page of the mapping is a possible start, so the mapping needs room for the buffer past
+    // all but one page of the alignment.
+    let size = page.round_up(size).ok_or(too_large)?;
+    let total = size.checked_add(align.get() - page.get()).ok_or(too_large)?;
+    // The mapping must be addressable through offsets.
+    if total > MAX_BLOCK {
+        return Err(too_large);
+    }
+
+    let ptr = match syscalls::mmap(total) {
+        Ok(ptr) => ptr,
+        Err(GrowError::Mmap(syscalls::ENOSYS)) => {
+            return Err(AllocErr::UnsupportedAlignment(align.get()));
+        },
+        Err(err) => return Err(AllocErr::Os(err)),
+    };
+    let head = align.padding(ptr as usize);
+    let tail = total - head - size;
+
+    let block = unsafe {
+        // The mapping was just acquired, so it is ours to trim. The interior is within it.
+        if head != 0 {
+            let _ = syscalls::munmap(ptr, head);
+        }
+        if tail != 0 {
+            let _ = syscalls::munmap(ptr.offset((head + size) as isize), tail);
+        }
+
+        Block::from_raw_parts(Pointer::new(ptr.offset(head as isize)), size)
+    };
+
+    // No page of the interior is placed yet, so it can still be interleaved.
+    let interleaved = interleaves(size) && unsafe {
+        // The interior is ours, and nothing else is in it.
+        interleave(*Pointer::from(block.empty_left()), size)
+    };
+
+    // Register the interior.
+    match region::register(OwnedRegion::new(block, Origin::Mmap {
+        fd_less: true,
+        locked: false,
+        interleaved: interleaved,
+    })) {
+        Ok(block) => Ok(*Pointer::from(block)),
+        Err(region) => {
+            // Logging.
+            log!(WARNING, "Unable to register the mapping {:?}.", region.block);
+
+            unsafe {
+                // The mapping was never handed out.
+                let _ = syscalls::munmap(*Pointer::from(region.block), size);
+            }
+            #[cfg(feature = "numa")]
+            {
+                if interleaved {
+                    numa::forget_interleaved(size);
+                }
+            }
+
+            Err(AllocErr::LimitReached)
+        },
+    }
+}
+
+/// Unmap a buffer mapped by `alloc`.
+///
+/// `size` is the size of the registered region.
+///
+/// # Safety
+///
+/// `ptr` must be mapped through `alloc`, and must not be used after the free.
+pub unsafe fn free(ptr: *mut u8, size: usize) {
+    // Logging.
+    log!(NOTE, "Unmapping the buffer {:?} of size {}.", ptr, size);
+
+    let block = Block::from_raw_parts(Pointer::new(ptr), size);
+
+    // Forget the mapping.
+    let res = region::unregister(&block);
+    debug_assert!(res.is_ok(), "Unmapping an unregistered buffer.");
+    #[cfg(feature = "numa")]
+    {
+        if let Ok(Origin::Mmap { interleaved: true, .. }) = res {
+            numa::forget_interleaved(size);
+        }
+    }
+
+    let res = syscalls::munmap(*Pointer::from(block), size);
+    debug_assert!(res.is_ok(), "Unable to unmap the buffer.");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "test_util")]
+    use shim::inject;
+
+    #[test]
+    fn test_trimmed() {
+        let align = Align::new(1 << 21).unwrap();
+        let ptr = alloc(5000, align).unwrap();
+        assert!(align.is_aligned(ptr as usize));
+
+        // Only the aligned interior (rounded to pages) is registered.
+        let region = region::mapping(ptr).unwrap();
+        assert_eq!(region.origin, Origin::Mmap {
+            fd_less: true,
+            locked: false,
+            interleaved: false,
+        });
+        assert_eq!(region.start, ptr as usize);
+        assert_eq!(region.size(), 8192);
+        assert_eq!(size(ptr), Some(8192));
+
+        unsafe {
+            *ptr.offset(8191) = 1;
+            free(ptr, region.size());
+        }
+        assert!(region::lookup(ptr as usize).is_none());
+        assert_eq!(size(ptr), None);
+    }
+
+    #[test]
+    #[cfg(feature = "test_util")]
+    fn test_no_mmap() {
+        extern crate std;
+
+        use self::std::thread;
+
+        inject::fail_mmap(syscalls::ENOSYS);
+        let res = alloc(64, Align::new(1 << 20).unwrap());
+
+        // The failure is injected into this thread only, so another thread maps as usual.
+        let other = thread::spawn(|| {
+            let ptr = alloc(64, Align::new(1 << 20).unwrap()).unwrap();
+            unsafe { free(ptr, size(ptr).unwrap()); }
+        }).join();
+
+        inject::fail_mmap(0);
+
+        assert_eq!(res, Err(AllocErr::UnsupportedAlignment(1 << 20)));
+        assert!(other.is_ok(), "The injected failure leaked into another thread.");
+    }
+}
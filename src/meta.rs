@@ -0,0 +1,152 @@
+//! Allocation metadata.
+//!
+//! Recording the size of every buffer allows freeing and measuring buffers without knowing their
+//! size (like C's `free` and `malloc_usable_size`). There are two strategies, selected by cargo
+//! features:
+//!
+//! - `header`: a checksummed header precedes every buffer (see `header`).
+//! - `sidetable`: the sizes are kept in a radix table keyed by address (see `sidetable`), so the
+//!   buffers are exactly what the pool returned.
+//!
+//! Without either, no metadata is kept. If both are enabled, headers are used.
+//!
+//! Both strategies record the generation a buffer was born in (see `now`) in their spare bits,
+//! from which the statistics tell the age of the buffer when it is freed.
+
+use bookkeeper;
+use ptr::Align;
+
+#[cfg(feature = "header")]
+pub use header::Headers as Active;
+#[cfg(all(feature = "sidetable", not(feature = "header")))]
+pub use sidetable::SideTable as Active;
+#[cfg(not(any(feature = "header", feature = "sidetable")))]
+pub use self::Nothing as Active;
+
+/// A metadata strategy.
+pub trait Metadata {
+    /// Get the padding before buffers aligned to `align`.
+    ///
+    /// The padding is part of the block, but not of the buffer.
+    fn padding(align: Align) -> usize;
+
+    /// Get the size of the pool block holding `size` bytes.
+    ///
+    /// This is applied both when allocating and when freeing blocks in the pool (not the slabs),
+    /// so it must only depend on `size`.
+    fn pool_size(size: usize) -> usize;
+
+    /// Record the metadata of a buffer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a buffer of `size` bytes preceded by `padding` bytes of padding.
+    unsafe fn stamp(ptr: *mut u8, size: usize, padding: usize, tag: u8, birth: u16);
+
+    /// Get the generation a buffer was born in from its metadata.
+    ///
+    /// If the birth isn't recorded, `None` is returned.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a buffer with metadata.
+    unsafe fn birth(ptr: *mut u8) -> Option<u16>;
+
+    /// Check and forget the metadata of a buffer, which is about to be freed or moved.
+    ///
+    /// The padding before the buffer is returned.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a buffer with metadata.
+    unsafe fn unstamp(ptr: *mut u8, size: usize) -> usize;
+
+    /// Check the metadata of a buffer against its size and alignment.
+    ///
+    /// This is used to verify sized frees in debug builds.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a buffer with metadata.
+    unsafe fn verify(ptr: *mut u8, size: usize, align: Align) -> bool;
+
+    /// Forget the metadata of a buffer of known size and alignment, which is about to be freed.
+    ///
+    /// In contrast to `unstamp`, the metadata isn't checked (and need not be read at all), since
+    /// the alignment gives the padding, which is returned.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a buffer with metadata of `size` bytes aligned to `align`.
+    unsafe fn forget(ptr: *mut u8, size: usize, align: Align) -> usize;
+
+    /// Get the size of a buffer from its metadata.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a buffer with metadata.
+    unsafe fn size(ptr: *mut u8) -> usize;
+}
+
+/// Get the current generation, as recorded in the metadata.
+///
+/// This is the generation clock of the pools (see `bookkeeper::generation`), truncated to 16
+/// bits.
+#[inline]
+pub fn now() -> u16 {
+    bookkeeper::generation() as u16
+}
+
+/// Get the age (in generations) of a buffer born in the generation `birth`.
+///
+/// Only 16 bits of the generation are recorded, so the age wraps around after 65536 generations
+/// (with the clock advancing every `config::GENERATION_FREES` frees of a pool, that is about 2^26
+/// frees).
+#[inline]
+pub fn age(birth: u16) -> usize {
+    now().wrapping_sub(birth) as usize
+}
+
+/// No metadata.
+#[cfg(not(any(feature = "header", feature = "sidetable")))]
+pub struct Nothing;
+
+#[cfg(not(any(feature = "header", feature = "sidetable")))]
+impl Metadata for Nothing {
+    #[inline]
+    fn padding(_align: Align) -> usize {
+        0
+    }
+
+    #[inline]
+    fn pool_size(size: usize) -> usize {
+        size
+    }
+
+    #[inline]
+    unsafe fn stamp(_ptr: *mut u8, _size: usize, _padding: usize, _tag: u8, _birth: u16) {}
+
+    #[inline]
+    unsafe fn birth(_ptr: *mut u8) -> Option<u16> {
+        None
+    }
+
+    #[inline]
+    unsafe fn unstamp(_ptr: *mut u8, _size: usize) -> usize {
+        0
+    }
+
+    #[inline]
+    unsafe fn verify(_ptr: *mut u8, _size: usize, _align: Align) -> bool {
+        true
+    }
+
+    #[inline]
+    unsafe fn forget(_ptr: *mut u8, _size: usize, _align: Align) -> usize {
+        0
+    }
+
+    unsafe fn size(_ptr: *mut u8) -> usize {
+        panic!("No allocation metadata is kept.");
+    }
+}
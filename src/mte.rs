@@ -0,0 +1,164 @@
+//! Memory tagging.
+//!
+//! With the `mte` feature, every buffer is padded to whole tag granules and handed out through a
+//! pointer with a random (nonzero) tag, which the memory of the buffer is tagged with. Freeing the
+//! buffer tags its memory back to zero, so accesses through stale pointers fault.
+//!
+//! The pool and the regions only ever see untagged addresses: tags are stripped at the boundary,
+//! and the memory of the allocator itself (the free blocks, the metadata and the padding) keeps
+//! tag zero, matching the untagged pointers used internally.
+//!
+//! On hardware without MTE, no tags are generated (see `shim::mte`), and the only effect is the
+//! rounding of the sizes.
+
+use shim::mte as arch;
+
+use ptr::with_addr;
+
+/// The size of a tag granule.
+pub const GRANULE: usize = arch::GRANULE;
+
+/// The tag bits of a pointer.
+#[cfg(target_pointer_width = "64")]
+const TAG_MASK: usize = 0xF << arch::TAG_SHIFT;
+/// The tag bits of a pointer.
+///
+/// There is no room for tags in 32-bit pointers.
+#[cfg(not(target_pointer_width = "64"))]
+const TAG_MASK: usize = 0;
+
+/// Round a size up to whole granules.
+///
+/// Sizes which would overflow are returned as is, as they are rejected anyway.
+#[inline]
+pub fn round(size: usize) -> usize {
+    size.checked_add(GRANULE - 1).map_or(size, |x| x & !(GRANULE - 1))
+}
+
+/// Get the tag of a pointer.
+#[cfg(target_pointer_width = "64")]
+#[inline]
+pub fn tag_of(ptr: *mut u8) -> u8 {
+    ((ptr as usize & TAG_MASK) >> arch::TAG_SHIFT) as u8
+}
+
+/// Get the tag of a pointer.
+///
+/// 32-bit pointers are never tagged.
+#[cfg(not(target_pointer_width = "64"))]
+#[inline]
+pub fn tag_of(_ptr: *mut u8) -> u8 {
+    0
+}
+
+/// Strip the tag of a pointer.
+#[inline]
+pub fn strip(ptr: *mut u8) -> *mut u8 {
+    with_addr(ptr, ptr as usize & !TAG_MASK)
+}
+
+/// Set the tag of a pointer.
+#[cfg(target_pointer_width = "64")]
+#[inline]
+pub fn apply(ptr: *mut u8, tag: u8) -> *mut u8 {
+    with_addr(ptr, (ptr as usize & !TAG_MASK) | ((tag as usize) << arch::TAG_SHIFT & TAG_MASK))
+}
+
+/// Set the tag of a pointer.
+///
+/// 32-bit pointers are never tagged, so `ptr` is returned as is.
+#[cfg(not(target_pointer_width = "64"))]
+#[inline]
+pub fn apply(ptr: *mut u8, _tag: u8) -> *mut u8 {
+    ptr
+}
+
+/// Tag a fresh buffer of `size` bytes at the untagged `ptr`, returning the tagged pointer.
+///
+/// # Safety
+///
+/// The buffer must be live, granule aligned and `size` must be rounded.
+#[inline]
+pub unsafe fn tag(ptr: *mut u8, size: usize) -> *mut u8 {
+    let tagged = arch::random_tag(ptr);
+    arch::store_tags(tagged, size);
+
+    tagged
+}
+
+/// Tag the `size` bytes of a buffer at `ptr` with the tag of `ptr`.
+///
+/// This extends the tag of a buffer over its new memory, when it grows inplace.
+///
+/// # Safety
+///
+/// The memory must belong to the buffer, and `size` must be rounded.
+#[inline]
+pub unsafe fn extend(ptr: *mut u8, size: usize) {
+    arch::store_tags(ptr, size);
+}
+
+/// Tag the memory of a freed buffer back to zero.
+///
+/// # Safety
+///
+/// The buffer must be granule aligned, and `size` must be rounded.
+#[inline]
+pub unsafe fn untag(ptr: *mut u8, size: usize) {
+    arch::store_tags(strip(ptr), size);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round() {
+        assert_eq!(round(0), 0);
+        assert_eq!(round(1), GRANULE);
+        assert_eq!(round(GRANULE), GRANULE);
+        assert_eq!(round(GRANULE + 1), 2 * GRANULE);
+        assert_eq!(round(1000), 1008);
+        assert_eq!(round(!0), !0);
+
+        for size in 0..200 {
+            let x = round(size);
+            assert_eq!(x % GRANULE, 0);
+            assert!(x >= size && x < size + GRANULE);
+        }
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_tags() {
+        // Synthetic tagged pointers, never dereferenced.
+        let ptr = 0x0000_7f00_1234_5670 as *mut u8;
+        for tag in 0..16 {
+            let tagged = apply(ptr, tag);
+            assert_eq!(tag_of(tagged), tag);
+            assert_eq!(strip(tagged), ptr);
+            assert_eq!(tagged as usize & !TAG_MASK, ptr as usize);
+        }
+
+        // Retagging replaces the tag, and only the tag bits are touched.
+        assert_eq!(apply(apply(ptr, 5), 9), apply(ptr, 9));
+        assert_eq!(strip(apply(ptr, 0)), ptr);
+        let top = 0xF000_0000_0000_0000 as *mut u8;
+        assert_eq!(strip(apply(top, 0xA)), top);
+    }
+
+    #[test]
+    #[cfg(not(all(target_arch = "aarch64", target_feature = "mte")))]
+    fn test_noop_tagging() {
+        let mut buf = [0u8; 64];
+        let ptr = buf.as_mut_ptr();
+
+        unsafe {
+            let tagged = tag(ptr, 32);
+            assert_eq!(strip(tagged), ptr);
+            *tagged = 1;
+            untag(tagged, 32);
+        }
+        assert_eq!(buf[0], 1);
+    }
+}
@@ -0,0 +1,256 @@
+//! NUMA placement.
+//!
+//! On machines with several NUMA nodes, memory can be placed on the nodes explicitly. A heap
+//! created with `Heap::new_on_node` maps its chunks bound to a node, and with interleaving on (see
+//! `set_numa_interleave`), the allocations of at least `config::NUMA_INTERLEAVE_MIN` bytes are
+//! mapped with their pages spread over all nodes.
+//!
+//! The nodes are discovered on first use, from `/sys/devices/system/node/online`. If it cannot be
+//! read, the machine is taken to have a single node. With a single node, there is nothing to
+//! place, so the policies are NOOPs: heaps on a node are plain heaps, and large allocations stay
+//! in the pool.
+//!
+//! The bytes placed on every node are counted (see `node_bytes`). The pages of an interleaved
+//! mapping are counted evenly over the nodes.
+
+use atomic::{self, AtomicBool, AtomicUsize};
+use core::cmp;
+
+use shim::{config, syscalls};
+
+use fail::GrowError;
+
+/// The maximal number of nodes.
+///
+/// The nodes beyond are never used.
+pub const MAX_NODES: usize = 16;
+
+/// The number of nodes, or zero, if they are not discovered yet.
+static NODES: AtomicUsize = AtomicUsize::new(0);
+/// Are large allocations interleaved over the nodes?
+static INTERLEAVE: AtomicBool = AtomicBool::new(false);
+/// The number of bytes placed on every node.
+// The atomics aren't `Copy`, so we cannot use the repeat syntax.
+static BYTES: [AtomicUsize; MAX_NODES] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+/// Get the number of nodes.
+///
+/// The nodes are discovered on the first call.
+pub fn nodes() -> usize {
+    match NODES.load(atomic::Ordering::Relaxed) {
+        0 => {
+            // Racing discoveries find the same nodes, so the last store wins harmlessly.
+            let nodes = discover();
+            NODES.store(nodes, atomic::Ordering::Relaxed);
+
+            nodes
+        },
+        nodes => nodes,
+    }
+}
+
+/// Pretend that the machine has `nodes` nodes.
+///
+/// Binding memory to nodes, which don't exist, fails, so this is meant for mocked `mbind` calls
+/// (see `inject::mock_mbind`).
+#[cfg(any(test, feature = "test_util"))]
+pub fn set_nodes(nodes: usize) {
+    assert!(nodes != 0 && nodes <= MAX_NODES, "Invalid number of nodes: {}.", nodes);
+
+    NODES.store(nodes, atomic::Ordering::Relaxed);
+}
+
+/// Discover the nodes of the machine.
+fn discover() -> usize {
+    let mut buf = [0; 64];
+    let len = match syscalls::open_read(b"/sys/devices/system/node/online\0") {
+        Ok(fd) => {
+            let res = syscalls::read(fd, &mut buf);
+            syscalls::close(fd);
+
+            res.unwrap_or(0)
+        },
+        Err(_) => 0,
+    };
+
+    match parse_online(&buf[..len]) {
+        Some(nodes) if nodes > MAX_NODES => {
+            // Logging.
+            log!(WARNING, "Only using {} of the {} NUMA nodes.", MAX_NODES, nodes);
+
+            MAX_NODES
+        },
+        Some(nodes) => {
+            // Logging.
+            log!(NOTE, "Found {} NUMA nodes.", nodes);
+
+            nodes
+        },
+        None => 1,
+    }
+}
+
+/// Parse a list of online nodes, e.g. `0-1,3`, into the number of nodes.
+///
+/// Nodes are numbered from zero, so the number of nodes is the largest listed plus one, gaps
+/// included. If the list is empty or malformed, `None` is returned.
+fn parse_online(list: &[u8]) -> Option<usize> {
+    let mut nodes = 0;
+    // The node currently parsed, if any.
+    let mut node = None;
+
+    for &byte in list {
+        match byte {
+            b'0'...b'9' => {
+                let digit = (byte - b'0') as usize;
+                node = match node.unwrap_or(0usize).checked_mul(10)
+                    .and_then(|x| x.checked_add(digit)) {
+                    Some(x) => Some(x),
+                    None => return None,
+                };
+            },
+            b'-' | b',' | b'\n' => match node.take() {
+                Some(x) => nodes = cmp::max(nodes, x.saturating_add(1)),
+                None => return None,
+            },
+            _ => return None,
+        }
+    }
+    if let Some(x) = node {
+        nodes = cmp::max(nodes, x.saturating_add(1));
+    }
+
+    if nodes == 0 { None } else { Some(nodes) }
+}
+
+/// Interleave the large allocations over the nodes, or stop doing so.
+///
+/// While on, the allocations of at least `config::NUMA_INTERLEAVE_MIN` bytes are mapped with
+/// their pages spread round-robin over the nodes, such that threads on any node get the same
+/// bandwidth on average. The mappings taken meanwhile stay interleaved after it is turned off.
+///
+/// This is off by default. It can also be set with the `numa_interleave` key in `RALLOC_CONF`. On
+/// a machine with a single node, it is a NOOP.
+pub fn set_numa_interleave(interleave: bool) {
+    // Logging.
+    log!(NOTE, "Setting the NUMA interleaving to {}.", interleave);
+
+    INTERLEAVE.store(interleave, atomic::Ordering::Relaxed);
+}
+
+/// Are large allocations interleaved over the nodes?
+pub fn numa_interleave() -> bool {
+    INTERLEAVE.load(atomic::Ordering::Relaxed)
+}
+
+/// Is a buffer of `size` bytes to be interleaved?
+#[inline]
+pub fn interleaves(size: usize) -> bool {
+    size >= config::NUMA_INTERLEAVE_MIN && numa_interleave() && nodes() > 1
+}
+
+/// Interleave a fresh mapping over the nodes.
+///
+/// This returns whether the mapping is interleaved, which it is not, if `mbind` fails.
+pub unsafe fn interleave(ptr: *mut u8, size: usize) -> bool {
+    let nodes = nodes();
+
+    match syscalls::mbind(ptr, size, syscalls::MPOL_INTERLEAVE, (1 << nodes) - 1) {
+        Ok(()) => {
+            spread(size, nodes, |node, bytes| {
+                BYTES[node].fetch_add(bytes, atomic::Ordering::Relaxed);
+            });
+
+            true
+        },
+        Err(errno) => {
+            // Logging.
+            log!(WARNING, "Unable to interleave the mapping {:?}: error {}.", ptr, errno);
+
+            false
+        },
+    }
+}
+
+/// Forget an interleaved mapping, which is being unmapped.
+pub fn forget_interleaved(size: usize) {
+    spread(size, nodes(), |node, bytes| {
+        BYTES[node].fetch_sub(bytes, atomic::Ordering::Relaxed);
+    });
+}
+
+/// Divide `size` bytes evenly over `nodes` nodes.
+///
+/// The first node gets the remainder.
+fn spread<F: FnMut(usize, usize)>(size: usize, nodes: usize, mut f: F) {
+    for node in 0..nodes {
+        f(node, size / nodes + if node == 0 { size % nodes } else { 0 });
+    }
+}
+
+/// Map `size` bytes bound to `node`.
+///
+/// The node must exist. On failure (e.g. if the node cannot be bound), the error is returned.
+pub fn map_on_node(size: usize, node: usize) -> Result<*mut u8, GrowError> {
+    debug_assert!(node < nodes(), "Mapping on the missing node {}.", node);
+
+    let ptr = syscalls::map_on_node(size, node)?;
+    BYTES[node].fetch_add(size, atomic::Ordering::Relaxed);
+
+    Ok(ptr)
+}
+
+/// Unmap memory mapped by `map_on_node`.
+///
+/// # Safety
+///
+/// The memory must not be used afterwards.
+pub unsafe fn unmap_on_node(ptr: *mut u8, size: usize, node: usize) {
+    BYTES[node].fetch_sub(size, atomic::Ordering::Relaxed);
+
+    let res = syscalls::munmap(ptr, size);
+    debug_assert!(res.is_ok(), "Unable to unmap the memory of node {}.", node);
+}
+
+/// Get the number of bytes placed on every node.
+///
+/// The nodes beyond `nodes()` have no bytes.
+pub fn node_bytes() -> [usize; MAX_NODES] {
+    let mut res = [0; MAX_NODES];
+    for (x, bytes) in res.iter_mut().zip(BYTES.iter()) {
+        *x = bytes.load(atomic::Ordering::Relaxed);
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_online() {
+        assert_eq!(parse_online(b"0\n"), Some(1));
+        assert_eq!(parse_online(b"0-1\n"), Some(2));
+        assert_eq!(parse_online(b"0-3,8-11\n"), Some(12));
+        assert_eq!(parse_online(b"0,2"), Some(3));
+
+        assert_eq!(parse_online(b""), None);
+        assert_eq!(parse_online(b"\n"), None);
+        assert_eq!(parse_online(b"0-x"), None);
+        assert_eq!(parse_online(b"99999999999999999999999"), None);
+    }
+
+    #[test]
+    fn test_spread() {
+        let mut bytes = [0; 3];
+        spread(10, 3, |node, x| bytes[node] += x);
+
+        assert_eq!(bytes, [4, 3, 3]);
+    }
+}
@@ -6,5 +6,5 @@ pub use block::Block;
 pub use cell::MoveCell;
 pub use lazy_init::LazyInit;
 pub use sync::Mutex;
-pub use ptr::Pointer;
+pub use ptr::{Align, Pointer};
 pub use vec::Vec;
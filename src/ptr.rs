@@ -1,7 +1,34 @@
 //! Pointer wrappers.
+//!
+//! This also provides the provenance-preserving address manipulation of the crate (`with_addr`
+//! and `map_addr`). Addresses are computed as integers throughout, but pointers used for access
+//! should be derived from a pointer into the same allocation rather than cast from an integer,
+//! so that the crate can run under tools enforcing strict provenance.
 
 use core::nonzero::NonZero;
-use core::{ops, marker};
+use core::{fmt, intrinsics, isize, ops, marker, mem};
+
+use shim::syscalls;
+
+use allocator;
+
+/// The largest size of a block (and thus of an allocation), in bytes.
+///
+/// Pointer offsets are signed, so a block larger than `isize::MAX` bytes couldn't be walked with
+/// them (e.g. on 32-bit targets, an offset of 3 GiB turns negative, walking backwards). Blocks are
+/// checked against this on construction, and requests beyond it fail with
+/// `AllocErr::TooLarge`.
+///
+/// On 64-bit targets, the bound is further lowered to 2^56 - 1 bytes, such that the high bits of
+/// the size of a block can hold its generation (see `Block::generation`).
+#[cfg(target_pointer_width = "64")]
+pub const MAX_BLOCK: usize = (1 << 56) - 1;
+/// The largest size of a block (and thus of an allocation), in bytes.
+///
+/// Pointer offsets are signed, so a block larger than `isize::MAX` bytes couldn't be walked with
+/// them (e.g. on 32-bit targets, an offset of 3 GiB turns negative, walking backwards).
+#[cfg(not(target_pointer_width = "64"))]
+pub const MAX_BLOCK: usize = isize::MAX as usize;
 
 /// A pointer wrapper type.
 ///
@@ -78,6 +105,115 @@ impl<T> Pointer<T> {
     pub unsafe fn offset(self, diff: isize) -> Pointer<T> {
         Pointer::new(self.ptr.offset(diff))
     }
+
+    /// Get the address of this pointer.
+    #[inline]
+    pub fn addr(&self) -> usize {
+        *self.ptr as usize
+    }
+
+    /// Create a pointer with the address `addr` and the provenance of this pointer.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `addr` is zero.
+    #[inline]
+    pub fn with_addr(self, addr: usize) -> Pointer<T> {
+        // Make some assertions.
+        assert!(addr != 0, "Null pointer!");
+
+        Pointer {
+            ptr: unsafe {
+                // We just checked that it is non-null.
+                NonZero::new(with_addr(*self.ptr, addr))
+            },
+            _phantom: marker::PhantomData,
+        }
+    }
+
+    /// Map the address of this pointer, keeping its provenance.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the new address is zero.
+    #[inline]
+    pub fn map_addr<F: FnOnce(usize) -> usize>(self, f: F) -> Pointer<T> {
+        let addr = f(self.addr());
+        self.with_addr(addr)
+    }
+
+    /// Offset this pointer by `diff` bytes, wrapping around the address space.
+    ///
+    /// This never invokes undefined behavior, but the result must only be dereferenced if it stays
+    /// within the allocation.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the result is null.
+    #[inline]
+    pub fn wrapping_offset_bytes(self, diff: isize) -> Pointer<T> {
+        let ptr = unsafe {
+            // Wrapping offsets are defined for any pointer and any offset.
+            intrinsics::arith_offset(*self.ptr as *const u8, diff) as *mut T
+        };
+
+        // Make some assertions.
+        assert!(!ptr.is_null(), "Offsetting {:?} by {} gives a null pointer.", *self.ptr, diff);
+
+        Pointer {
+            ptr: unsafe {
+                // We just checked that it is non-null.
+                NonZero::new(ptr)
+            },
+            _phantom: marker::PhantomData,
+        }
+    }
+
+    /// Offset this pointer by `diff` bytes forward.
+    ///
+    /// If the result overflows the address space, `None` is returned.
+    #[inline]
+    #[allow(cast_possible_wrap)]
+    pub fn checked_offset_bytes(self, diff: usize) -> Option<Pointer<T>> {
+        if self.addr().checked_add(diff).is_some() {
+            // The result is above a non-null address, so it is non-null. Offsets beyond
+            // `isize::MAX` wrap to the same address.
+            Some(self.wrapping_offset_bytes(diff as isize))
+        } else {
+            None
+        }
+    }
+
+    /// Offset this pointer by `diff` bytes forward.
+    ///
+    /// # Safety
+    ///
+    /// The result must stay within the allocation of this pointer (e.g. its block), which is never
+    /// larger than `MAX_BLOCK` bytes. The bound is checked in debug builds.
+    #[inline]
+    #[allow(cast_possible_wrap)]
+    pub unsafe fn offset_bytes(self, diff: usize) -> Pointer<T> {
+        // Make some assertions.
+        debug_assert!(diff <= MAX_BLOCK, "Offsetting {:?} by {} bytes, beyond the largest block.",
+                      *self.ptr, diff);
+
+        // The offset is within the allocation, so it is positive and the result is non-null.
+        Pointer::new((*self.ptr as *mut u8).offset(diff as isize) as *mut T)
+    }
+
+    /// Get the number of bytes needed to bring this pointer up to the alignment `align`.
+    #[inline]
+    pub fn align_offset(&self, align: Align) -> usize {
+        align.padding(self.addr())
+    }
+
+    /// Get the distance in bytes from this pointer to `to`.
+    ///
+    /// If `to` is below this pointer, `None` is returned.
+    #[inline]
+    pub fn distance_to(&self, to: &Pointer<T>) -> Option<usize> {
+        to.addr().checked_sub(self.addr())
+    }
 }
 
 impl<T> Default for Pointer<T> {
@@ -98,10 +234,132 @@ impl<T> ops::Deref for Pointer<T> {
     }
 }
 
+/// Create a pointer with the address `addr` and the provenance of `ptr`.
+///
+/// With the `strict_provenance` feature, the result is derived from `ptr` by a wrapping offset,
+/// such that it may be used to access the allocation of `ptr` under strict provenance. Otherwise,
+/// it is simply cast from the address.
+#[inline]
+#[cfg(feature = "strict_provenance")]
+pub fn with_addr<T>(ptr: *mut T, addr: usize) -> *mut T {
+    let diff = addr.wrapping_sub(ptr as usize) as isize;
+
+    unsafe {
+        // Wrapping offsets are defined for any pointer and any offset.
+        intrinsics::arith_offset(ptr as *const u8, diff) as *mut T
+    }
+}
+
+/// Create a pointer with the address `addr` and the provenance of `ptr`.
+///
+/// With the `strict_provenance` feature, the result is derived from `ptr` by a wrapping offset,
+/// such that it may be used to access the allocation of `ptr` under strict provenance. Otherwise,
+/// it is simply cast from the address.
+#[inline]
+#[cfg(not(feature = "strict_provenance"))]
+pub fn with_addr<T>(_ptr: *mut T, addr: usize) -> *mut T {
+    addr as *mut T
+}
+
+/// Map the address of `ptr`, keeping its provenance.
+#[inline]
+pub fn map_addr<T, F: FnOnce(usize) -> usize>(ptr: *mut T, f: F) -> *mut T {
+    with_addr(ptr, f(ptr as usize))
+}
+
+/// An alignment.
+///
+/// Alignments are nonzero powers of two, which is checked on construction, so the alignment math
+/// reduces to masking.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Align(usize);
+
+impl Align {
+    /// The trivial (byte) alignment.
+    pub const MIN: Align = Align(1);
+    /// The minimal alignment of the buffers handed out by the API.
+    ///
+    /// See `allocator::MIN_ALIGN`.
+    pub const BUFFER: Align = Align(allocator::MIN_ALIGN);
+
+    /// Create an alignment.
+    ///
+    /// If `align` is zero or not a power of two, `None` is returned.
+    #[inline]
+    pub fn new(align: usize) -> Option<Align> {
+        if align != 0 && align & (align - 1) == 0 {
+            Some(Align(align))
+        } else {
+            None
+        }
+    }
+
+    /// Get the alignment of `T`.
+    #[inline]
+    pub fn of<T>() -> Align {
+        // Type alignments are always powers of two.
+        Align(mem::align_of::<T>())
+    }
+
+    /// Get the page alignment.
+    pub fn page() -> Align {
+        Align::new(syscalls::page_size()).expect("The page size is not a power of two.")
+    }
+
+    /// Get the alignment in bytes.
+    #[inline]
+    pub fn get(self) -> usize {
+        self.0
+    }
+
+    /// Get the mask of the bits, which must be zero in aligned addresses.
+    #[inline]
+    pub fn mask(self) -> usize {
+        self.0 - 1
+    }
+
+    /// Is `addr` aligned?
+    #[inline]
+    pub fn is_aligned(self, addr: usize) -> bool {
+        addr & self.mask() == 0
+    }
+
+    /// Get the padding needed to bring `addr` up to the alignment.
+    #[inline]
+    pub fn padding(self, addr: usize) -> usize {
+        addr.wrapping_neg() & self.mask()
+    }
+
+    /// Round `addr` up to the alignment.
+    ///
+    /// If the result overflows, `None` is returned.
+    #[inline]
+    pub fn round_up(self, addr: usize) -> Option<usize> {
+        addr.checked_add(self.mask()).map(|x| x & !self.mask())
+    }
+}
+
+impl fmt::Display for Align {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    use core::mem;
+
+    use allocator;
+
+    #[test]
+    fn test_buffer_align() {
+        assert_eq!(Align::new(allocator::MIN_ALIGN), Some(Align::BUFFER));
+        assert!(Align::BUFFER >= Align::of::<u64>());
+        assert!(Align::BUFFER >= Align::of::<usize>());
+    }
+
     #[test]
     fn test_pointer() {
         let mut x = [b'a', b'b'];
@@ -126,4 +384,137 @@ mod test {
     fn test_empty() {
         assert_eq!(*Pointer::<u8>::empty() as usize, 1);
     }
+
+    #[test]
+    fn test_byte_offsets() {
+        use core::isize;
+
+        let mut buf = [0u8; 64];
+        let base = unsafe { Pointer::new(buf.as_mut_ptr()) };
+
+        assert_eq!(base.addr(), buf.as_ptr() as usize);
+
+        let end = base.clone().checked_offset_bytes(64).unwrap();
+        assert_eq!(base.distance_to(&end), Some(64));
+        assert_eq!(end.distance_to(&base), None);
+        assert_eq!(base.distance_to(&base), Some(0));
+
+        let mid = end.clone().wrapping_offset_bytes(-40);
+        assert_eq!(base.distance_to(&mid), Some(24));
+        unsafe {
+            **mid = 7;
+        }
+        assert_eq!(buf[24], 7);
+
+        // Wrapping there and back is the identity.
+        let far = base.clone().wrapping_offset_bytes(isize::MIN);
+        assert_eq!(far.wrapping_offset_bytes(isize::MIN), base);
+    }
+
+    #[test]
+    fn test_byte_offset_overflow() {
+        use core::usize;
+
+        let top = unsafe { Pointer::new(usize::MAX as *mut u8) };
+        assert_eq!(top.clone().checked_offset_bytes(0), Some(top.clone()));
+        assert_eq!(top.clone().checked_offset_bytes(1), None);
+        assert_eq!(top.clone().checked_offset_bytes(usize::MAX), None);
+
+        let low = unsafe { Pointer::new(16 as *mut u8) };
+        assert_eq!(low.clone().checked_offset_bytes(usize::MAX - 16).map(|x| x.addr()),
+                   Some(usize::MAX));
+        assert_eq!(low.clone().checked_offset_bytes(usize::MAX - 15), None);
+        assert_eq!(low.distance_to(&top), Some(usize::MAX - 16));
+        assert_eq!(top.distance_to(&low), None);
+    }
+
+    #[test]
+    fn test_offset_bytes() {
+        let mut buf = [0u64; 8];
+        let base = unsafe { Pointer::new(buf.as_mut_ptr()) };
+
+        let end = unsafe { base.clone().offset_bytes(64) };
+        assert_eq!(base.distance_to(&end), Some(64));
+        unsafe {
+            **base.clone().offset_bytes(8) = 7;
+        }
+        assert_eq!(buf[1], 7);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "beyond the largest block")]
+    fn test_offset_bytes_bound() {
+        // The offset is checked before the pointer is ever offset.
+        let ptr = unsafe { Pointer::new(16 as *mut u8) };
+        let _ = unsafe { ptr.offset_bytes(MAX_BLOCK + 1) };
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_wrapping_offset_null() {
+        let ptr = unsafe { Pointer::new(16 as *mut u8) };
+        let _ = ptr.wrapping_offset_bytes(-16);
+    }
+
+    #[test]
+    fn test_align_offset() {
+        let buf = [0u64; 8];
+        let base = unsafe { Pointer::new(buf.as_ptr() as *mut u8) };
+        let eight = Align::new(8).unwrap();
+
+        assert_eq!(base.align_offset(eight), 0);
+        assert_eq!(base.align_offset(Align::MIN), 0);
+        for i in 1..8 {
+            let ptr = base.clone().checked_offset_bytes(i).unwrap();
+            assert_eq!(ptr.align_offset(eight), 8 - i);
+        }
+    }
+
+    #[test]
+    fn test_align_new() {
+        use core::usize;
+
+        for n in 0..mem::size_of::<usize>() * 8 {
+            assert_eq!(Align::new(1 << n).map(Align::get), Some(1 << n));
+            if n > 1 {
+                assert_eq!(Align::new((1 << n) - 1), None);
+            }
+        }
+
+        assert_eq!(Align::new(0), None);
+        assert_eq!(Align::new(3), None);
+        assert_eq!(Align::new(15), None);
+        assert_eq!(Align::new(24), None);
+        assert_eq!(Align::new(usize::MAX), None);
+
+        assert_eq!(Align::MIN.get(), 1);
+        assert_eq!(Align::of::<u8>(), Align::MIN);
+        assert_eq!(Align::of::<u64>().get(), mem::align_of::<u64>());
+        assert!(Align::page().get() >= 4096);
+    }
+
+    #[test]
+    fn test_align_math() {
+        use core::usize;
+
+        for n in 0..8 {
+            let align = Align::new(1 << n).unwrap();
+
+            for addr in 0..300 {
+                let padding = align.padding(addr);
+                assert!(padding < align.get());
+                assert!(align.is_aligned(addr + padding));
+                assert_eq!(align.round_up(addr), Some(addr + padding));
+                assert_eq!(align.is_aligned(addr), padding == 0);
+            }
+        }
+
+        let align = Align::new(16).unwrap();
+        assert_eq!(align.mask(), 15);
+        assert_eq!(align.round_up(usize::MAX - 14), None);
+        assert_eq!(align.round_up(usize::MAX - 15), Some(usize::MAX - 15));
+        assert_eq!(align.padding(usize::MAX), 1);
+        assert_eq!(Align::MIN.round_up(usize::MAX), Some(usize::MAX));
+    }
 }
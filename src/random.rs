@@ -0,0 +1,266 @@
+//! Pseudorandom number generation.
+//!
+//! The generator is xorshift128+, seeded once per process from the best entropy source the shim
+//! finds (see `shim::entropy`). It is _not_ cryptographically secure, but it is good enough for
+//! randomizing placement.
+//!
+//! Every purpose draws from a stream of its own, derived from the seed, so that consuming the
+//! numbers of one purpose doesn't perturb the others (e.g. the placement of blocks doesn't
+//! depend on how often the program break was extended).
+
+use atomic;
+
+use shim::entropy::{self, Source};
+
+use {allocator, conf, sync};
+
+/// A stream of random numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    /// The placement of allocations among the fitting blocks.
+    Placement = 0,
+    /// The gaps burnt at the program break.
+    Gap = 1,
+}
+
+/// The number of streams.
+const STREAMS: usize = 2;
+
+/// The state of the generator.
+static GENERATOR: sync::Mutex<Generator> = sync::Mutex::ranked("random", sync::rank::RANDOM,
+                                                               Generator::new());
+
+/// A generator with a xorshift128+ state per stream.
+struct Generator {
+    /// Has the generator been seeded?
+    seeded: bool,
+    /// The source of the seed, unless it was given explicitly (or the generator is unseeded).
+    source: Option<Source>,
+    /// The states of the streams.
+    streams: [[u64; 2]; STREAMS],
+}
+
+impl Generator {
+    /// Create an unseeded generator.
+    const fn new() -> Generator {
+        Generator {
+            seeded: false,
+            source: None,
+            streams: [[0; 2]; STREAMS],
+        }
+    }
+
+    /// Seed the streams.
+    fn seed(&mut self, seed: entropy::Seed) {
+        for (n, stream) in self.streams.iter_mut().enumerate() {
+            // Derive the stream from the seed by SplitMix64, such that similar seeds and
+            // neighboring streams give unrelated states.
+            let salt = (n as u64 + 1).wrapping_mul(0xD1B54A32D192ED03);
+            let (mut a, mut b) = (seed[0] ^ salt, seed[1] ^ salt);
+            *stream = [split_mix(&mut a), split_mix(&mut b)];
+
+            // Zero is a fixed point of xorshift.
+            if *stream == [0, 0] {
+                *stream = [0x2545F4914F6CDD1D, 0];
+            }
+        }
+
+        self.seeded = true;
+    }
+
+    /// Draw a number from a stream.
+    fn draw(&mut self, stream: Stream) -> u64 {
+        let state = &mut self.streams[stream as usize];
+
+        let mut x = state[0];
+        let y = state[1];
+        state[0] = y;
+        x ^= x << 23;
+        state[1] = x ^ y ^ (x >> 17) ^ (y >> 26);
+
+        state[1].wrapping_add(y)
+    }
+}
+
+/// Advance a SplitMix64 state, returning the next output.
+fn split_mix(x: &mut u64) -> u64 {
+    *x = x.wrapping_add(0x9E3779B97F4A7C15);
+
+    let mut z = *x;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// Seed the generator.
+///
+/// This overrides the seed from the environment, making the streams reproducible.
+pub fn seed(seed: usize) {
+    // Logging.
+    log!(NOTE, "Seeding the random number generator.");
+
+    let mut generator = GENERATOR.lock();
+    generator.seed([seed as u64, 0]);
+    generator.source = None;
+}
+
+/// Get the source, which the generator was seeded from.
+///
+/// If the generator is unseeded or was seeded explicitly, `None` is returned.
+pub fn source() -> Option<Source> {
+    GENERATOR.lock().source
+}
+
+/// Advance a xorshift state.
+///
+/// This is the small generator of `Rng`, which is independent of the streams.
+#[inline]
+pub fn next(mut x: usize) -> usize {
+    #[cfg(target_pointer_width = "64")]
+    {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+    }
+    #[cfg(not(target_pointer_width = "64"))]
+    {
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+    }
+
+    x
+}
+
+/// A deterministic pseudorandom number generator.
+///
+/// This uses the same xorshift as the allocator, but has its own state, so it is unaffected by
+/// the allocations (and vice versa).
+#[derive(Clone, Debug)]
+pub struct Rng {
+    /// The state of the generator.
+    state: usize,
+}
+
+impl Rng {
+    /// Create a generator from a seed.
+    ///
+    /// A zero seed is replaced by an arbitrary non-zero constant, since zero is a fixed point of
+    /// xorshift.
+    pub fn new(seed: usize) -> Rng {
+        Rng {
+            state: if seed == 0 { 0x2545F491 } else { seed },
+        }
+    }
+
+    /// Get a pseudorandom number.
+    pub fn get(&mut self) -> usize {
+        self.state = next(self.state);
+        self.state
+    }
+
+    /// Get a pseudorandom number in the range `0..n`.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `n` is zero.
+    pub fn below(&mut self, n: usize) -> usize {
+        self.get() % n
+    }
+}
+
+/// Get a pseudorandom number from a stream.
+///
+/// If the generator is unseeded, it will be seeded from the environment.
+pub fn get(stream: Stream) -> usize {
+    let mut generator = GENERATOR.lock();
+
+    if !generator.seeded {
+        // There are no syscalls in bare-metal mode.
+        let (seed, source) = entropy::seed(!allocator::bare_metal());
+
+        // Logging.
+        log!(NOTE, "Seeding the random number generator from {}.", source.name());
+
+        generator.seed(seed);
+        generator.source = Some(source);
+    }
+
+    // On 32-bit platforms, this takes the lower half.
+    generator.draw(stream) as usize
+}
+
+/// Get a pseudorandom number from a stream in the range `0..n`.
+///
+/// # Panics
+///
+/// This panics if `n` is zero.
+#[inline]
+pub fn below(stream: Stream, n: usize) -> usize {
+    get(stream) % n
+}
+
+/// Enable or disable the deterministic mode.
+///
+/// In deterministic mode, the allocator never uses randomness, making its behavior reproducible,
+/// whatever the seed. It can also be enabled with the `deterministic` key in `RALLOC_CONF`.
+pub fn set_deterministic(deterministic: bool) {
+    conf::FLAGS.deterministic.store(deterministic, atomic::Ordering::SeqCst);
+}
+
+/// Is the deterministic mode active?
+#[inline]
+pub fn deterministic() -> bool {
+    conf::FLAGS.deterministic.load(atomic::Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::Generator;
+
+    #[test]
+    fn test_next() {
+        let mut x = 1;
+        for _ in 0..1000 {
+            let y = next(x);
+            assert!(y != 0);
+            assert!(y != x);
+            x = y;
+        }
+    }
+
+    #[test]
+    fn test_below() {
+        for n in 1..100 {
+            assert!(below(Stream::Placement, n) < n);
+        }
+    }
+
+    #[test]
+    fn test_streams() {
+        let mut a = Generator::new();
+        let mut b = Generator::new();
+        a.seed([42, 7]);
+        b.seed([42, 7]);
+
+        // Drawing from one stream doesn't perturb the other.
+        for _ in 0..100 {
+            b.draw(Stream::Gap);
+        }
+        for _ in 0..100 {
+            assert_eq!(a.draw(Stream::Placement), b.draw(Stream::Placement));
+        }
+
+        // The streams differ, and so do the seeds.
+        a.seed([42, 7]);
+        b.seed([43, 7]);
+        let first = a.draw(Stream::Placement);
+        assert!(first != a.draw(Stream::Gap));
+        assert!(first != b.draw(Stream::Placement));
+
+        // The zero seed is fine too.
+        a.seed([0, 0]);
+        assert!((0..10).any(|_| a.draw(Stream::Placement) != 0));
+    }
+}
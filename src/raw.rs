@@ -0,0 +1,369 @@
+//! Raw access to the pool.
+//!
+//! This is for building allocators of your own on the machinery of ralloc, without the policy of
+//! its front end. Memory is requested from the OS in regions (see `request_region`), and handed to
+//! a `Pool`, which serves blocks from it by the same first-fit, merging bookkeeping as the
+//! allocator.
+//!
+//! A `Pool` is separate from the global allocator: it has its own metadata, and the global pool
+//! never sees its blocks. Nor does it touch the state shared by the allocator: its blocks are not
+//! aged by the generation clock, and its operations are not counted in the statistics. Regions
+//! are mappings of their own rather than growths of the program break, which belongs to the
+//! global allocator. They are registered like any mapping, so the statistics and heap dumps cover
+//! them.
+//!
+//! Sizes are plain `usize`s in bytes. Nothing here is synchronized: a `Pool` is used from one
+//! thread at a time, and the caller upholds the invariants of the blocks (see `Block`).
+
+use prelude::*;
+
+use core::{cmp, mem, ops, ptr};
+
+use shim::config;
+
+use bookkeeper::{self, Allocator, Bookkeeper};
+use {fail, mapped};
+
+pub use block::Block;
+pub use bookkeeper::Iter;
+pub use fail::AllocErr;
+pub use ptr::{Align, Pointer};
+
+/// Request a region of (at least) `size` bytes from the OS.
+///
+/// The region is a fresh mapping aligned to pages, and its size is rounded up to whole pages. It
+/// is owned by the caller, until it is given back through `return_region`.
+///
+/// # Errors
+///
+/// If there is no memory mapping (e.g. on bare metal), `AllocErr::UnsupportedAlignment` is
+/// returned. If the OS cannot map the memory, `AllocErr::Os` is returned, and if the region
+/// registry is full, `AllocErr::LimitReached` is.
+pub fn request_region(size: usize) -> Result<Block, AllocErr> {
+    // Logging.
+    log!(CALL, "Requesting a raw region of {} bytes.", size);
+
+    let page = Align::page();
+    let ptr = mapped::alloc(size, page)?;
+
+    Ok(unsafe {
+        // The mapping was just made, and the rounding cannot overflow, as it succeeded in it.
+        Block::from_raw_parts(Pointer::new(ptr), page.round_up(size).unwrap())
+    })
+}
+
+/// Give a region back to the OS.
+///
+/// # Safety
+///
+/// The region must be one returned by `request_region`, whole, and nothing may use it afterwards.
+/// In particular, it must not be in a live `Pool` (see `Pool::insert`).
+///
+/// # Panics
+///
+/// This panics, if the block is not a region from `request_region`.
+pub unsafe fn return_region(region: Block) {
+    // Logging.
+    log!(CALL, "Returning the raw region {:?}.", region);
+
+    let ptr = *Pointer::from(region.empty_left());
+    assert_eq!(mapped::size(ptr), Some(region.size()), "{:?} is not a raw region.", region);
+
+    mapped::free(ptr, region.size());
+}
+
+/// A pool of free blocks.
+///
+/// The pool keeps the blocks inserted into it, and serves allocations by splitting them, merging
+/// them again on free. It never requests memory for allocations by itself: when nothing fits,
+/// `Pool::alloc` returns `None`, and the caller inserts more (e.g. a region from
+/// `request_region`).
+///
+/// The metadata of the pool is the exception. It is kept in regions, which the pool requests and
+/// owns itself, every one at least twice as large as the last. These regions are given back, when
+/// the pool is dropped, and the parts of them left behind by the metadata are served by the pool
+/// like any block, so no block allocated from a pool may be used after its drop. The blocks
+/// inserted by the caller are left alone, and are the caller's again after the drop.
+pub struct Pool {
+    /// The inner pool.
+    inner: RawPool,
+}
+
+impl Pool {
+    /// Create a new empty pool.
+    ///
+    /// # Errors
+    ///
+    /// The pool requests a region for its metadata, and if that fails, the error is returned.
+    pub fn new() -> Result<Pool, AllocErr> {
+        // Logging.
+        log!(NOTE, "Creating a raw pool.");
+
+        let meta = request_region(4 * bookkeeper::EXTRA_ELEMENTS * mem::size_of::<Block>())?;
+
+        let mut regions = [(ptr::null_mut(), 0); config::RAW_REGIONS];
+        regions[0] = (*Pointer::from(meta.empty_left()), meta.size());
+        let spare = meta.empty_right();
+
+        let mut inner = Bookkeeper::new(unsafe {
+            // The region was just requested, and is aligned to pages.
+            Vec::from_raw_parts(meta, 0)
+        });
+        inner.set_raw();
+
+        Ok(Pool {
+            inner: RawPool {
+                inner: inner,
+                regions: regions,
+                len: 1,
+                spare: spare,
+            },
+        })
+    }
+
+    /// Set the minimal size of the fragments left when splitting a free block.
+    ///
+    /// Allocations pass over the free blocks, which they would split into a fragment smaller than
+    /// `min` bytes. This is off (zero) by default, such that every fitting block is used.
+    pub fn set_min_split_remainder(&mut self, min: usize) {
+        self.inner.set_min_split_remainder(min);
+    }
+
+    /// Insert a block into the pool.
+    ///
+    /// The block is merged with its free neighbors, and served by `Pool::alloc` from then on.
+    ///
+    /// # Errors
+    ///
+    /// If the metadata of the pool must grow for the block, and no region can be requested for it,
+    /// the error is returned, and the block is left to the caller.
+    ///
+    /// # Safety
+    ///
+    /// The block must be valid memory owned by the caller, and it must not overlap any block of
+    /// the pool (free or allocated). It belongs to the pool until the pool is dropped, after which
+    /// the caller owns it again (e.g. to give it back through `return_region`).
+    pub unsafe fn insert(&mut self, block: Block) -> Result<(), AllocErr> {
+        // Logging.
+        log!(CALL, "Inserting {:?} into a raw pool.", block);
+
+        self.inner.prepare()?;
+        self.inner.free(block);
+
+        Ok(())
+    }
+
+    /// Allocate a block of `size` bytes aligned to `align` from the pool.
+    ///
+    /// If no free block fits (or the metadata of the pool must grow, and no region can be
+    /// requested for it), `None` is returned, and the pool is left as is.
+    pub fn alloc(&mut self, size: usize, align: Align) -> Option<Block> {
+        if self.inner.prepare().is_err() {
+            return None;
+        }
+
+        self.inner.alloc_pooled(size, align)
+    }
+
+    /// Free a block allocated from the pool.
+    ///
+    /// # Failure
+    ///
+    /// If the block is not merged with a neighbor, the metadata of the pool might have to grow.
+    /// If no region can be requested for it, the OOM handler is called.
+    ///
+    /// # Safety
+    ///
+    /// The block must be allocated by `Pool::alloc` on this pool (or be a part of such a block),
+    /// and must not be used afterwards.
+    pub unsafe fn free(&mut self, block: Block) {
+        self.inner.free_used(block);
+    }
+
+    /// Iterate over the free blocks of the pool, in address order.
+    pub fn iter(&self) -> Iter {
+        self.inner.iter()
+    }
+
+    /// Get the number of free bytes in the pool.
+    pub fn total_bytes(&self) -> usize {
+        self.inner.total_bytes()
+    }
+}
+
+/// The bookkeeping of a `Pool`.
+struct RawPool {
+    /// The inner bookkeeper.
+    inner: Bookkeeper,
+    /// The regions of the metadata as `(start, size)`.
+    regions: [(*mut u8, usize); config::RAW_REGIONS],
+    /// The number of regions.
+    len: usize,
+    /// The part of the last region, which the metadata has not taken yet.
+    spare: Block,
+}
+
+impl RawPool {
+    /// Make sure the spare holds the metadata, which an operation inserting a block might reserve.
+    ///
+    /// If a region cannot be requested for it, the error is returned, and the pool is left as is.
+    fn prepare(&mut self) -> Result<(), AllocErr> {
+        let size = self.reservation(1);
+        if self.spare.size() < size {
+            self.refill(size)?;
+        }
+
+        Ok(())
+    }
+
+    /// Request a new region for the metadata, with room for (at least) `size` bytes.
+    ///
+    /// The region becomes the spare, and the old spare is freed into the pool. If the region
+    /// cannot be requested (or the pool has as many regions as it can hold), the error is
+    /// returned, and the pool is left as is.
+    fn refill(&mut self, size: usize) -> Result<(), AllocErr> {
+        if self.len == self.regions.len() {
+            return Err(AllocErr::LimitReached);
+        }
+
+        let size = cmp::max(size, self.regions[self.len - 1].1.saturating_mul(2));
+        let region = request_region(size)?;
+
+        // Logging.
+        log!(INTERNAL, "Requesting {:?} for the metadata of a raw pool.", region);
+
+        self.regions[self.len] = (*Pointer::from(region.empty_left()), region.size());
+        self.len += 1;
+
+        let spare = mem::replace(&mut self.spare, region);
+        if !spare.is_empty() {
+            self.free(spare);
+        }
+
+        Ok(())
+    }
+}
+
+impl ops::Deref for RawPool {
+    type Target = Bookkeeper;
+
+    fn deref(&self) -> &Bookkeeper {
+        &self.inner
+    }
+}
+
+impl ops::DerefMut for RawPool {
+    fn deref_mut(&mut self) -> &mut Bookkeeper {
+        &mut self.inner
+    }
+}
+
+impl Allocator for RawPool {
+    /// Take space for the metadata from the spare.
+    ///
+    /// If the spare is too small, a new region is requested first (see `refill`). Only frees get
+    /// here without a prepared spare (see `prepare`), so only they can call the OOM handler.
+    fn alloc_fresh(&mut self, size: usize, align: Align) -> Block {
+        debug_assert!(align.get() <= Align::page().get(), "The metadata is overaligned.");
+
+        if self.spare.size() < size || !self.spare.aligned_to(align) {
+            if let Err(err) = self.refill(size) {
+                fail::oom(err);
+            }
+        }
+
+        let (res, spare) = self.spare.pop().split(size);
+        self.spare = spare;
+
+        res
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        // Logging.
+        log!(NOTE, "Dropping a raw pool.");
+
+        unsafe {
+            // The regions were requested by the pool, and it is gone now. The metadata lies
+            // within them, so it is gone along with them.
+            for &(start, size) in &self.inner.regions[..self.inner.len] {
+                return_region(Block::from_raw_parts(Pointer::new(start), size));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_alloc_free() {
+        let region = request_region(4096).unwrap();
+        let start = *Pointer::from(region.empty_left());
+        let size = region.size();
+
+        {
+            let mut pool = Pool::new().unwrap();
+            assert!(pool.alloc(8, Align::of::<u64>()).is_none());
+
+            unsafe { pool.insert(region).unwrap(); }
+            assert_eq!(pool.total_bytes(), size);
+
+            let a = pool.alloc(64, Align::of::<u64>()).unwrap();
+            let b = pool.alloc(64, Align::of::<u64>()).unwrap();
+            assert_eq!(a.size(), 64);
+            assert_eq!(pool.total_bytes(), size - 128);
+            assert!(pool.alloc(size, Align::of::<u64>()).is_none());
+
+            unsafe {
+                pool.free(a);
+                pool.free(b);
+            }
+
+            // The blocks are merged back into the region.
+            assert_eq!(pool.iter().count(), 1);
+            assert_eq!(pool.iter().next().unwrap().size(), size);
+        }
+
+        unsafe {
+            return_region(Block::from_raw_parts(Pointer::new(start), size));
+        }
+    }
+
+    #[test]
+    fn test_apart() {
+        let region = request_region(4096).unwrap();
+        let start = *Pointer::from(region.empty_left());
+        let size = region.size();
+
+        {
+            let mut pool = Pool::new().unwrap();
+            unsafe { pool.insert(region).unwrap(); }
+
+            // The clock is shared, so make sure it doesn't read zero, as the blocks do.
+            if bookkeeper::generation() % ::block::GENERATIONS == 0 {
+                bookkeeper::advance_generation(1);
+            }
+
+            // The freed blocks are not stamped.
+            let a = pool.alloc(64, Align::of::<u64>()).unwrap();
+            let b = pool.alloc(64, Align::of::<u64>()).unwrap();
+            unsafe {
+                pool.free(a);
+                pool.free(b);
+            }
+            assert!(pool.iter().all(|x| x.generation() == 0));
+
+            // Fragments of any size are left, as the minimal split remainder is off.
+            let a = pool.alloc(size - 8, Align::of::<u64>()).unwrap();
+            assert_eq!(pool.total_bytes(), 8);
+            unsafe { pool.free(a); }
+        }
+
+        unsafe {
+            return_region(Block::from_raw_parts(Pointer::new(start), size));
+        }
+    }
+}
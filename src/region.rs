@@ -0,0 +1,779 @@
+//! Memory regions.
+//!
+//! Every piece of memory entering the allocator is registered as a region, recording where it came
+//! from: the program break, a memory mapping, a static buffer, or a preallocation. The origin
+//! decides what can be done with free memory in the region. Only the top of the program break can
+//! be trimmed, only mappings are unmapped, and static buffers and preallocated memory are never
+//! given back at all.
+//!
+//! The registry is a sorted table of fixed capacity, such that registering never allocates (it is
+//! done while the pool and the program break are locked). Adjacent regions of the same origin are
+//! merged, except mappings, which are unmapped one by one.
+//!
+//! With the `arenas` feature, parts of the regions are also assigned to the arenas owning them
+//! (see `assign`). Such a part takes an entry of its own, but the lookups see the regions whole.
+
+use prelude::*;
+
+#[cfg(any(test, feature = "arenas"))]
+use core::cmp;
+
+use atomic::{self, AtomicUsize};
+
+use shim::config;
+
+use {fail, sort, sync, watermark};
+use fail::AllocErr;
+#[cfg(feature = "security")]
+use secure;
+#[cfg(feature = "shadow_accounting")]
+use shadow;
+
+/// The region registry.
+static REGIONS: sync::Mutex<Table> = sync::Mutex::ranked("regions", sync::rank::REGION,
+                                                         Table::new());
+/// The number of registered mappings.
+///
+/// This allows skipping the lookup when freeing, in the common case of no mappings.
+static MAPPINGS: AtomicUsize = AtomicUsize::new(0);
+
+/// The origin of a region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Origin {
+    /// The program break.
+    ///
+    /// The top of the program break can be given back to the OS by moving the break.
+    Brk,
+    /// A memory mapping.
+    ///
+    /// The mapping is unmapped, when freed.
+    Mmap {
+        /// Is the mapping anonymous (not backed by a file descriptor)?
+        fd_less: bool,
+        /// Is the mapping locked into RAM (i.e. a secure allocation)?
+        locked: bool,
+        /// Are the pages of the mapping interleaved over the NUMA nodes (see `numa`)?
+        interleaved: bool,
+    },
+    /// A static buffer.
+    ///
+    /// This can never be given back.
+    Static,
+    /// Memory obtained up front by `preallocate` (through the program break or a mapping).
+    ///
+    /// This is kept for good, like a static buffer, but it counts as heap usage.
+    Preallocated,
+}
+
+/// A region along with the ownership of its memory.
+///
+/// This is how memory is handed to the registry (see `register`).
+#[derive(Debug)]
+pub struct OwnedRegion {
+    /// The memory of the region.
+    pub block: Block,
+    /// The origin of the memory.
+    pub origin: Origin,
+}
+
+impl OwnedRegion {
+    /// Create a new owned region.
+    pub fn new(block: Block, origin: Origin) -> OwnedRegion {
+        OwnedRegion {
+            block: block,
+            origin: origin,
+        }
+    }
+}
+
+/// A registered region.
+///
+/// This is merely a description of the region, and owns no memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    /// The address of the first byte of the region.
+    pub start: usize,
+    /// The address after the last byte of the region.
+    pub end: usize,
+    /// The origin of the region.
+    pub origin: Origin,
+}
+
+impl Region {
+    /// Get the size of the region.
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Does the region contain `addr`?
+    pub fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// Does the region contain all of `block`?
+    pub fn covers(&self, block: &Block) -> bool {
+        let (start, end) = bounds(block);
+        self.start <= start && end <= self.end
+    }
+}
+
+/// Get the start and end address of a block.
+fn bounds(block: &Block) -> (usize, usize) {
+    let start = Pointer::from(block.empty_left()).addr();
+    (start, start + block.size())
+}
+
+/// A sorted table of disjoint regions.
+struct Table {
+    /// The entries.
+    ///
+    /// Only the first `len` entries are used, and they are sorted by address.
+    entries: [Region; config::REGION_CAPACITY],
+    /// The owners of the entries.
+    ///
+    /// This is the arena owning the entry plus one, or zero, if it is unowned.
+    owners: [u8; config::REGION_CAPACITY],
+    /// The number of entries.
+    len: usize,
+}
+
+impl Table {
+    /// Create a new empty table.
+    const fn new() -> Table {
+        Table {
+            entries: [Region {
+                start: 0,
+                end: 0,
+                origin: Origin::Static,
+            }; config::REGION_CAPACITY],
+            owners: [0; config::REGION_CAPACITY],
+            len: 0,
+        }
+    }
+
+    /// Can the entries at `n` and `n + 1` be parts of the same region?
+    ///
+    /// They must be adjacent and of the same origin, which is not a mapping.
+    fn joined(&self, n: usize) -> bool {
+        let (left, right) = (self.entries[n], self.entries[n + 1]);
+
+        left.end == right.start && left.origin == right.origin && match left.origin {
+            Origin::Mmap { .. } => false,
+            _ => true,
+        }
+    }
+
+    /// Get the whole region of the entry at `n`.
+    ///
+    /// This joins the entry with its neighbors of other owners.
+    fn extent(&self, n: usize) -> Region {
+        let mut res = self.entries[n];
+
+        let mut i = n;
+        while i > 0 && self.joined(i - 1) {
+            i -= 1;
+            res.start = self.entries[i].start;
+        }
+        let mut i = n;
+        while i + 1 < self.len && self.joined(i) {
+            i += 1;
+            res.end = self.entries[i].end;
+        }
+
+        res
+    }
+
+    /// Merge the entries at `n` and `n + 1`, if they are parts of the same region, with the same
+    /// owner.
+    #[cfg(any(test, feature = "arenas"))]
+    fn merge_at(&mut self, n: usize) {
+        if n + 1 < self.len && self.joined(n) && self.owners[n] == self.owners[n + 1] {
+            self.entries[n].end = self.entries[n + 1].end;
+            self.remove_at(n + 1);
+        }
+    }
+
+    /// Find the index of the last region starting at or before `addr`.
+    fn predecessor(&self, addr: usize) -> Option<usize> {
+        sort::predecessor(&self.entries[..self.len], &addr, |x| x.start)
+    }
+
+    /// Find the region containing `addr`.
+    fn find(&self, addr: usize) -> Option<usize> {
+        self.predecessor(addr).and_then(|n| {
+            if self.entries[n].contains(addr) { Some(n) } else { None }
+        })
+    }
+
+    /// Insert an entry with some owner at index `n`.
+    ///
+    /// If the table is full, `Err(())` is returned.
+    fn insert_at(&mut self, n: usize, region: Region, owner: u8) -> Result<(), ()> {
+        if self.len == config::REGION_CAPACITY {
+            return Err(());
+        }
+
+        self.entries[self.len] = region;
+        self.owners[self.len] = owner;
+        self.len += 1;
+
+        sort::shift_in(&mut self.entries[..self.len], n);
+        sort::shift_in(&mut self.owners[..self.len], n);
+
+        Ok(())
+    }
+
+    /// Remove the entry at index `n`.
+    fn remove_at(&mut self, n: usize) {
+        sort::shift_out(&mut self.entries[..self.len], n);
+        sort::shift_out(&mut self.owners[..self.len], n);
+        self.len -= 1;
+
+        // The slot is dead, so it is wiped.
+        #[cfg(feature = "security")]
+        unsafe {
+            // The slot is past the end, so it is overwritten before it is read again.
+            secure::secure_wipe(&mut self.entries[self.len]);
+            secure::secure_wipe(&mut self.owners[self.len]);
+        }
+    }
+
+    /// Insert a region.
+    ///
+    /// Adjacent regions of the same origin are merged, unless they are mappings. If the region is
+    /// empty, overlaps a registered region, or the table is full, `Err(())` is returned.
+    fn insert(&mut self, region: Region) -> Result<(), ()> {
+        if region.start >= region.end {
+            return Err(());
+        }
+
+        // The regions are disjoint, so only the predecessor and its successor can overlap.
+        let pred = self.predecessor(region.start);
+        let next = pred.map_or(0, |n| n + 1);
+        if pred.map_or(false, |n| self.entries[n].end > region.start)
+            || (next < self.len && self.entries[next].start < region.end) {
+            return Err(());
+        }
+
+        // New regions are unowned, so they only merge with unowned neighbors.
+        let mergeable = |x: &Region, owner: u8| owner == 0 && x.origin == region.origin
+            && match x.origin {
+                Origin::Mmap { .. } => false,
+                _ => true,
+            };
+
+        // Merge with the neighbors, if possible.
+        let left = pred.map_or(false, |n| {
+            self.entries[n].end == region.start && mergeable(&self.entries[n], self.owners[n])
+        });
+        let right = next < self.len && self.entries[next].start == region.end
+            && mergeable(&self.entries[next], self.owners[next]);
+
+        match (left, right) {
+            (true, true) => {
+                self.entries[next - 1].end = self.entries[next].end;
+                self.remove_at(next);
+            },
+            (true, false) => self.entries[next - 1].end = region.end,
+            (false, true) => self.entries[next].start = region.start,
+            (false, false) => return self.insert_at(next, region, 0),
+        }
+
+        Ok(())
+    }
+
+    /// Remove the range `start..end` from the table.
+    ///
+    /// The range must be within a single region, which is shrunk or split. The origin of the
+    /// region is returned. If the range isn't registered, or the region must be split and the
+    /// table is full, `Err(())` is returned.
+    fn remove(&mut self, start: usize, end: usize) -> Result<Origin, ()> {
+        let n = self.find(start).ok_or(())?;
+        let origin = self.entries[n].origin;
+        if end > self.extent(n).end || start >= end {
+            return Err(());
+        }
+
+        // The region might consist of several entries of different owners, which are cut one by
+        // one. Only a range within a single entry splits it, so nothing is cut, if that fails.
+        let mut n = n;
+        while n < self.len && self.entries[n].start < end {
+            let old = self.entries[n];
+            match (old.start >= start, old.end <= end) {
+                (true, true) => {
+                    self.remove_at(n);
+                    continue;
+                },
+                (true, false) => self.entries[n].start = end,
+                (false, true) => self.entries[n].end = start,
+                (false, false) => {
+                    let owner = self.owners[n];
+                    self.insert_at(n + 1, Region {
+                        start: end,
+                        end: old.end,
+                        origin: old.origin,
+                    }, owner)?;
+                    self.entries[n].end = start;
+                },
+            }
+
+            n += 1;
+        }
+
+        Ok(origin)
+    }
+
+    /// Assign the range `start..end` to an owner.
+    ///
+    /// The parts of the range outside the regions, and in mappings, are left alone. The owner is
+    /// zero for none, or the arena plus one. If the table has no room for splitting the entries
+    /// at the ends of the range, `Err(())` is returned, and nothing is assigned.
+    #[cfg(any(test, feature = "arenas"))]
+    fn assign(&mut self, start: usize, end: usize, owner: u8) -> Result<(), ()> {
+        if start >= end || self.len == 0 {
+            return Ok(());
+        }
+
+        let ownable = |x: &Region| match x.origin {
+            Origin::Mmap { .. } => false,
+            _ => true,
+        };
+
+        // The entries at the ends are split, if they stick out of the range.
+        let first = self.find(start).and_then(|n| {
+            if self.entries[n].start < start && ownable(&self.entries[n]) { Some(n) } else { None }
+        });
+        let last = self.find(end - 1).and_then(|n| {
+            if self.entries[n].end > end && ownable(&self.entries[n]) { Some(n) } else { None }
+        });
+        let needed = first.map_or(0, |_| 1) + last.map_or(0, |_| 1);
+        if self.len + needed > config::REGION_CAPACITY {
+            return Err(());
+        }
+
+        if let Some(n) = last {
+            let old = self.entries[n];
+            let owner = self.owners[n];
+            self.insert_at(n + 1, Region {
+                start: end,
+                end: old.end,
+                origin: old.origin,
+            }, owner)?;
+            self.entries[n].end = end;
+        }
+        if let Some(n) = first {
+            let old = self.entries[n];
+            let owner = self.owners[n];
+            self.insert_at(n + 1, Region {
+                start: start,
+                end: old.end,
+                origin: old.origin,
+            }, owner)?;
+            self.entries[n].end = start;
+        }
+
+        // Every entry in the range is within it now.
+        let mut n = self.predecessor(start).map_or(0, |n| {
+            if self.entries[n].end <= start { n + 1 } else { n }
+        });
+        let from = n.saturating_sub(1);
+        while n < self.len && self.entries[n].start < end {
+            if ownable(&self.entries[n]) {
+                self.owners[n] = owner;
+            }
+            n += 1;
+        }
+
+        // Merge the neighbors of the same owner, from the right, so the indices stay valid.
+        let mut n = cmp::min(n, self.len - 1);
+        while n > from {
+            n -= 1;
+            self.merge_at(n);
+        }
+
+        Ok(())
+    }
+
+    /// Get the owner of `addr`.
+    ///
+    /// The owner (or `None`, if the address is unowned) is returned along with the end of the
+    /// ownership, i.e. the end of the entry, or the start of the next entry.
+    #[cfg(any(test, feature = "arenas"))]
+    fn owner(&self, addr: usize) -> (Option<usize>, usize) {
+        let pred = self.predecessor(addr);
+
+        if let Some(n) = pred {
+            if self.entries[n].contains(addr) {
+                return (match self.owners[n] {
+                    0 => None,
+                    x => Some(x as usize - 1),
+                }, self.entries[n].end);
+            }
+        }
+
+        // The address is in no region, so it ends where the next region starts.
+        let next = pred.map_or(0, |n| n + 1);
+        (None, if next < self.len { self.entries[next].start } else { !0 })
+    }
+}
+
+/// Register a region.
+///
+/// On success, the block of the region is given back, now known to the registry. If the region
+/// overlaps a registered region, or the registry is full, the region is returned in `Err`.
+pub fn register(region: OwnedRegion) -> Result<Block, OwnedRegion> {
+    // Logging.
+    log!(INTERNAL, "Registering {:?}.", region);
+
+    let (start, end) = bounds(&region.block);
+    let res = REGIONS.lock().insert(Region {
+        start: start,
+        end: end,
+        origin: region.origin,
+    });
+
+    match res {
+        Ok(()) => {
+            if let Origin::Mmap { .. } = region.origin {
+                MAPPINGS.fetch_add(1, atomic::Ordering::Relaxed);
+            }
+            if region.origin != Origin::Static {
+                #[cfg(feature = "shadow_accounting")]
+                let _shadow = shadow::record(shadow::Op::Grow(end - start));
+                watermark::grow(end - start);
+            }
+
+            Ok(region.block)
+        },
+        Err(()) => Err(region),
+    }
+}
+
+/// Use the block of a region, which could not be registered.
+///
+/// The block is usable, but its buffers lie outside every region, so they look like buffers of
+/// another allocator. With the `interpose` feature, such buffers are handed to the interposed
+/// allocator when freed, so memory must never go unregistered, and this calls the OOM handler
+/// with `AllocErr::LimitReached` instead.
+pub fn unregistered(region: OwnedRegion) -> Block {
+    if cfg!(feature = "interpose") {
+        // Logging.
+        log!(ERROR, "Unable to register {:?}, whose buffers would be taken for foreign ones.",
+             region);
+
+        fail::oom(AllocErr::LimitReached);
+    }
+
+    region.block
+}
+
+/// Unregister the memory of `block`.
+///
+/// The block must be within a single region, which is shrunk (or split) accordingly. The origin
+/// of the memory is returned. If the block isn't registered, `Err(())` is returned.
+pub fn unregister(block: &Block) -> Result<Origin, ()> {
+    // Logging.
+    log!(INTERNAL, "Unregistering {:?}.", block);
+
+    let (start, end) = bounds(block);
+    let origin = REGIONS.lock().remove(start, end)?;
+
+    if let Origin::Mmap { .. } = origin {
+        MAPPINGS.fetch_sub(1, atomic::Ordering::Relaxed);
+    }
+    if origin != Origin::Static {
+        #[cfg(feature = "shadow_accounting")]
+        let _shadow = shadow::record(shadow::Op::Trim(end - start));
+        watermark::shrink(end - start);
+    }
+
+    Ok(origin)
+}
+
+/// Find the region containing `addr`.
+pub fn lookup(addr: usize) -> Option<Region> {
+    let table = REGIONS.lock();
+    table.find(addr).map(|n| table.extent(n))
+}
+
+/// Call `f` on every region, in address order.
+///
+/// The registry is locked meanwhile, so `f` must not allocate.
+pub fn for_each<F: FnMut(&Region)>(mut f: F) {
+    let table = REGIONS.lock();
+
+    let mut n = 0;
+    while n < table.len {
+        let region = table.extent(n);
+        f(&region);
+
+        // Skip the other entries of the region.
+        while n < table.len && table.entries[n].start < region.end {
+            n += 1;
+        }
+    }
+}
+
+/// Assign the memory of `block` to an arena.
+///
+/// Blocks freed later within it are routed to the arena (see `owner`). Memory in mappings is left
+/// unowned. If the registry has no room for the assignment, a warning is logged, and the memory
+/// stays with its former owner.
+#[cfg(feature = "arenas")]
+pub fn assign(block: &Block, arena: usize) {
+    // Logging.
+    log!(INTERNAL, "Assigning {:?} to arena {}.", block, arena);
+
+    let (start, end) = bounds(block);
+    if REGIONS.lock().assign(start, end, arena as u8 + 1).is_err() {
+        log!(WARNING, "The region registry is full, so {:?} is not assigned to arena {}.", block,
+             arena);
+    }
+}
+
+/// Find the arena owning `addr`.
+///
+/// The owning arena (or `None`, if the address is unowned) is returned along with the end of the
+/// ownership (see `assign`).
+#[cfg(feature = "arenas")]
+pub fn owner(addr: usize) -> (Option<usize>, usize) {
+    REGIONS.lock().owner(addr)
+}
+
+/// Find the mapping starting at `ptr`.
+///
+/// This is cheap when no mappings are registered.
+pub fn mapping(ptr: *mut u8) -> Option<Region> {
+    if MAPPINGS.load(atomic::Ordering::Relaxed) == 0 {
+        return None;
+    }
+
+    lookup(ptr as usize).and_then(|region| match region.origin {
+        Origin::Mmap { .. } if region.start == ptr as usize => Some(region),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn region(start: usize, end: usize, origin: Origin) -> Region {
+        Region {
+            start: start,
+            end: end,
+            origin: origin,
+        }
+    }
+
+    fn regions(table: &Table) -> &[Region] {
+        &table.entries[..table.len]
+    }
+
+    #[test]
+    fn test_overlap() {
+        let mut table = Table::new();
+
+        assert!(table.insert(region(100, 200, Origin::Brk)).is_ok());
+        assert!(table.insert(region(300, 400, Origin::Static)).is_ok());
+
+        // Overlapping either region, or both, is rejected.
+        assert!(table.insert(region(150, 250, Origin::Brk)).is_err());
+        assert!(table.insert(region(50, 101, Origin::Brk)).is_err());
+        assert!(table.insert(region(399, 500, Origin::Static)).is_err());
+        assert!(table.insert(region(120, 180, Origin::Brk)).is_err());
+        assert!(table.insert(region(0, 1000, Origin::Static)).is_err());
+        assert!(table.insert(region(199, 301, Origin::Static)).is_err());
+        // So are empty regions.
+        assert!(table.insert(region(250, 250, Origin::Brk)).is_err());
+
+        assert_eq!(regions(&table), &[region(100, 200, Origin::Brk),
+                                      region(300, 400, Origin::Static)]);
+    }
+
+    #[test]
+    fn test_boundaries() {
+        let mut table = Table::new();
+        let mmap = Origin::Mmap { fd_less: true, locked: false, interleaved: false };
+
+        table.insert(region(100, 200, mmap)).unwrap();
+        table.insert(region(200, 300, Origin::Brk)).unwrap();
+
+        assert_eq!(table.find(99), None);
+        assert_eq!(table.find(100), Some(0));
+        assert_eq!(table.find(199), Some(0));
+        assert_eq!(table.find(200), Some(1));
+        assert_eq!(table.find(299), Some(1));
+        assert_eq!(table.find(300), None);
+        assert_eq!(table.find(0), None);
+        assert_eq!(table.find(!0), None);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut table = Table::new();
+        let mmap = Origin::Mmap { fd_less: true, locked: false, interleaved: false };
+
+        table.insert(region(100, 200, Origin::Brk)).unwrap();
+        table.insert(region(300, 400, Origin::Brk)).unwrap();
+        // Fill the gap, merging all three.
+        table.insert(region(200, 300, Origin::Brk)).unwrap();
+        assert_eq!(regions(&table), &[region(100, 400, Origin::Brk)]);
+
+        // Different origins are kept apart.
+        table.insert(region(400, 500, Origin::Static)).unwrap();
+        assert_eq!(table.len, 2);
+
+        // Mappings are never merged.
+        table.insert(region(500, 600, mmap)).unwrap();
+        table.insert(region(600, 700, mmap)).unwrap();
+        assert_eq!(table.len, 4);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut table = Table::new();
+
+        table.insert(region(100, 400, Origin::Brk)).unwrap();
+
+        // Shrink from the top.
+        assert_eq!(table.remove(300, 400), Ok(Origin::Brk));
+        assert_eq!(regions(&table), &[region(100, 300, Origin::Brk)]);
+
+        // Split in two.
+        assert_eq!(table.remove(150, 200), Ok(Origin::Brk));
+        assert_eq!(regions(&table), &[region(100, 150, Origin::Brk),
+                                      region(200, 300, Origin::Brk)]);
+
+        // Unregistered or straddling ranges are rejected.
+        assert_eq!(table.remove(150, 200), Err(()));
+        assert_eq!(table.remove(120, 250), Err(()));
+        assert_eq!(table.remove(250, 350), Err(()));
+
+        // Remove whole regions.
+        assert_eq!(table.remove(100, 150), Ok(Origin::Brk));
+        assert_eq!(table.remove(200, 300), Ok(Origin::Brk));
+        assert_eq!(table.len, 0);
+    }
+
+    #[test]
+    fn test_full() {
+        let mut table = Table::new();
+
+        // Leave gaps, so nothing is merged.
+        for i in 0..config::REGION_CAPACITY {
+            table.insert(region(10 * i + 10, 10 * i + 15, Origin::Brk)).unwrap();
+        }
+        assert!(table.insert(region(1, 2, Origin::Brk)).is_err());
+        // Splitting needs an entry as well.
+        assert_eq!(table.remove(11, 12), Err(()));
+        // Merging doesn't.
+        assert!(table.insert(region(15, 17, Origin::Brk)).is_ok());
+    }
+
+    #[test]
+    fn test_owner() {
+        let mut table = Table::new();
+        table.insert(region(1000, 5000, Origin::Brk)).unwrap();
+        table.assign(1000, 2000, 2).unwrap();
+        table.assign(3000, 4000, 3).unwrap();
+
+        assert_eq!(table.owner(0), (None, 1000));
+        assert_eq!(table.owner(1000), (Some(1), 2000));
+        assert_eq!(table.owner(1999), (Some(1), 2000));
+        assert_eq!(table.owner(2000), (None, 3000));
+        assert_eq!(table.owner(3500), (Some(2), 4000));
+        assert_eq!(table.owner(4000), (None, 5000));
+        assert_eq!(table.owner(5000), (None, !0));
+
+        // The lookups see the region whole.
+        assert_eq!(table.len, 4);
+        assert_eq!(table.extent(table.find(3500).unwrap()), region(1000, 5000, Origin::Brk));
+    }
+
+    #[test]
+    fn test_assign_merge() {
+        let mut table = Table::new();
+        table.insert(region(1000, 5000, Origin::Brk)).unwrap();
+        table.assign(1000, 2000, 2).unwrap();
+        table.assign(2000, 3000, 2).unwrap();
+        table.assign(3000, 4000, 3).unwrap();
+
+        assert_eq!(regions(&table), &[region(1000, 3000, Origin::Brk),
+                                      region(3000, 4000, Origin::Brk),
+                                      region(4000, 5000, Origin::Brk)]);
+        assert_eq!(table.owner(1500), (Some(1), 3000));
+
+        // Unowned again, the entries merge back into the region.
+        table.assign(0, 6000, 0).unwrap();
+        assert_eq!(regions(&table), &[region(1000, 5000, Origin::Brk)]);
+    }
+
+    #[test]
+    fn test_assign_overlap() {
+        let mut table = Table::new();
+        table.insert(region(1000, 4000, Origin::Brk)).unwrap();
+        table.assign(1000, 4000, 2).unwrap();
+
+        // The middle was handed out again.
+        table.assign(2000, 3000, 3).unwrap();
+        assert_eq!(table.owner(1500), (Some(1), 2000));
+        assert_eq!(table.owner(2500), (Some(2), 3000));
+        assert_eq!(table.owner(3500), (Some(1), 4000));
+
+        // Overlapping several entries at once.
+        table.assign(1500, 3500, 4).unwrap();
+        assert_eq!(table.owner(1000), (Some(1), 1500));
+        assert_eq!(table.owner(1500), (Some(3), 3500));
+        assert_eq!(table.owner(3500), (Some(1), 4000));
+        assert_eq!(table.len, 3);
+    }
+
+    #[test]
+    fn test_assign_mapping() {
+        let mut table = Table::new();
+        let mmap = Origin::Mmap { fd_less: true, locked: false, interleaved: false };
+        table.insert(region(1000, 2000, mmap)).unwrap();
+        table.insert(region(2000, 3000, Origin::Brk)).unwrap();
+
+        // Mappings stay unowned.
+        table.assign(1500, 2500, 1).unwrap();
+        assert_eq!(table.owner(1500), (None, 2000));
+        assert_eq!(table.owner(2000), (Some(0), 2500));
+        assert_eq!(table.extent(0), region(1000, 2000, mmap));
+    }
+
+    #[test]
+    fn test_remove_owned() {
+        let mut table = Table::new();
+        table.insert(region(100, 400, Origin::Brk)).unwrap();
+        table.assign(200, 300, 1).unwrap();
+
+        // The range is cut out of every entry of the region.
+        assert_eq!(table.remove(150, 350), Ok(Origin::Brk));
+        assert_eq!(regions(&table), &[region(100, 150, Origin::Brk),
+                                      region(350, 400, Origin::Brk)]);
+        assert_eq!(&table.owners[..table.len], &[0, 0]);
+
+        // Splitting an owned entry keeps the owner on both sides.
+        table.assign(100, 150, 2).unwrap();
+        assert_eq!(table.remove(110, 120), Ok(Origin::Brk));
+        assert_eq!(table.owner(100), (Some(1), 110));
+        assert_eq!(table.owner(120), (Some(1), 150));
+    }
+
+    #[test]
+    #[cfg(feature = "security")]
+    fn test_wiped_slots() {
+        let mut table = Table::new();
+        table.insert(region(1000, 2000, Origin::Brk)).unwrap();
+        table.insert(region(3000, 4000, Origin::Static)).unwrap();
+
+        table.remove(3000, 4000).unwrap();
+        assert_eq!(table.len, 1);
+
+        // No copy of the released region is left behind.
+        assert_eq!(table.entries[1], region(0, 0, Origin::Brk));
+        assert!(table.entries.iter().all(|x| x.start != 3000 && x.end != 4000));
+    }
+}
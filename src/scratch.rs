@@ -0,0 +1,275 @@
+//! Scratch allocations.
+//!
+//! Short-lived buffers with nested lifetimes need not go through the bookkeeper one by one. A
+//! `ScratchGuard` takes a region from the pool and hands out buffers by bumping a pointer through
+//! it. Dropping the guard gives all of its buffers back at once. If the region runs out, another
+//! one is chained to it.
+//!
+//! Guards can be nested, but must be dropped in the reverse order of their creation, which is
+//! checked through a per-thread depth counter. Scratch buffers are never freed individually, and
+//! must not be used after their guard is dropped. With the `debugger` feature, freeing a buffer
+//! of a live scratch region through the allocator is caught.
+
+use prelude::*;
+
+use core::cell::Cell;
+use core::{cmp, marker, mem, ptr};
+
+use allocator;
+use ptr::with_addr;
+
+tls! {
+    /// The number of live scratch guards on the current thread.
+    static DEPTH: Cell<usize> = Cell::new(0);
+}
+tls! {
+    /// The last scratch region of the current thread, or null if there is none.
+    ///
+    /// The regions of all the guards of the thread are chained through their headers, innermost
+    /// guard first.
+    static TOP: Cell<*mut Header> = Cell::new(ptr::null_mut());
+}
+
+/// The header at the start of every scratch region.
+struct Header {
+    /// The previous region of the thread, or null if there is none.
+    prev: *mut Header,
+    /// The size of the region in bytes, including the header.
+    size: usize,
+}
+
+/// A scope for scratch allocations.
+///
+/// All the buffers allocated through the guard are freed when it is dropped. Guards are bound to
+/// the thread which created them.
+pub struct ScratchGuard {
+    /// The number of guards, which were live when this one was created.
+    depth: usize,
+    /// The last region of the thread when this guard was created.
+    ///
+    /// The regions above this one in the chain belong to this guard.
+    base: *mut Header,
+    /// The preferred size of the regions.
+    hint: usize,
+    /// The current region.
+    region: *mut u8,
+    /// The bump pointer into the current region.
+    next: usize,
+    /// The end of the current region.
+    end: usize,
+    /// Guards must stay on their thread.
+    _thread: marker::PhantomData<*const ()>,
+}
+
+impl ScratchGuard {
+    /// Create a guard with a region of at least `size_hint` bytes.
+    ///
+    /// Allocations exceeding the region chain another one of at least `size_hint` bytes.
+    pub fn new(size_hint: usize) -> ScratchGuard {
+        let depth = DEPTH.with(|x| {
+            let depth = x.get();
+            x.set(depth + 1);
+            depth
+        });
+
+        let mut guard = ScratchGuard {
+            depth: depth,
+            base: TOP.with(|x| x.get()),
+            hint: size_hint,
+            region: ptr::null_mut(),
+            next: 0,
+            end: 0,
+            _thread: marker::PhantomData,
+        };
+        guard.chain(size_hint);
+
+        guard
+    }
+
+    /// Allocate `size` bytes aligned to `align` from the guard.
+    ///
+    /// The buffer lives until the guard is dropped.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `align` is not a power of two, or if an inner guard is live.
+    pub fn alloc(&mut self, size: usize, align: usize) -> *mut u8 {
+        let align = Align::new(align).expect("Invalid alignment.");
+        // The regions of an inner guard are above ours in the chain, so a new region of ours
+        // would be freed along with them.
+        assert!(DEPTH.with(|x| x.get()) == self.depth + 1, "Allocating from a scratch guard \
+                while an inner guard is live.");
+
+        loop {
+            if let Some(start) = align.round_up(self.next) {
+                if start.checked_add(size).map_or(false, |end| end <= self.end) {
+                    self.next = start + size;
+                    return with_addr(self.region, start);
+                }
+            }
+
+            // The buffer doesn't fit, so we chain a region with room for it.
+            let size = size.checked_add(align.get()).expect("Scratch allocation too large.");
+            self.chain(cmp::max(size, self.hint));
+        }
+    }
+
+    /// Chain a new region with room for `size` bytes to the thread's regions.
+    fn chain(&mut self, size: usize) {
+        let size = size.checked_add(mem::size_of::<Header>()).expect("Scratch region too large.");
+        let block = allocator::pool_alloc(size, Align::of::<Header>());
+        let region = *Pointer::from(block.empty_left());
+        let start = region as usize;
+        let size = block.size();
+
+        // Logging.
+        log!(INTERNAL, "Chaining scratch region {:?}.", block);
+
+        unsafe {
+            // The block is fresh and fits the header.
+            ptr::write(region as *mut Header, Header {
+                prev: TOP.with(|x| x.get()),
+                size: size,
+            });
+        }
+        TOP.with(|x| x.set(region as *mut Header));
+
+        self.region = region;
+        self.next = start + mem::size_of::<Header>();
+        self.end = start + size;
+    }
+}
+
+impl Drop for ScratchGuard {
+    fn drop(&mut self) {
+        // Freeing our regions would free those of the inner guards along with them.
+        assert!(DEPTH.with(|x| x.get()) == self.depth + 1, "Scratch guards dropped out of order.");
+
+        // Free our regions, which are on the top of the chain.
+        loop {
+            let region = TOP.with(|x| x.get());
+            if region == self.base {
+                break;
+            }
+
+            let header = unsafe {
+                // Every region of the chain starts with a header.
+                ptr::read(region)
+            };
+            TOP.with(|x| x.set(header.prev));
+            allocator::pool_free(Block::from_raw_parts(Pointer::new(region as *mut u8),
+                                                       header.size));
+        }
+
+        DEPTH.with(|x| x.set(self.depth));
+    }
+}
+
+/// Is `ptr` in a live scratch region of the current thread?
+#[cfg(feature = "debugger")]
+pub fn owns(ptr: *const u8) -> bool {
+    let addr = ptr as usize;
+    let mut region = TOP.with(|x| x.get());
+
+    while !region.is_null() {
+        let header = unsafe {
+            // Every region of the chain starts with a header.
+            ptr::read(region)
+        };
+        if region as usize <= addr && addr < region as usize + header.size {
+            return true;
+        }
+
+        region = header.prev;
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use core::ptr;
+
+    /// Count the scratch regions of the current thread.
+    fn regions() -> usize {
+        let mut count = 0;
+        let mut region = super::TOP.with(|x| x.get());
+        while !region.is_null() {
+            count += 1;
+            region = unsafe { ptr::read(region) }.prev;
+        }
+
+        count
+    }
+
+    #[test]
+    fn test_nesting() {
+        let mut outer = ScratchGuard::new(256);
+        let a = outer.alloc(64, 8);
+        unsafe { ptr::write_bytes(a, 0xAB, 64); }
+
+        {
+            let mut inner = ScratchGuard::new(256);
+            let b = inner.alloc(64, 16);
+            assert!(b as usize % 16 == 0);
+            unsafe { ptr::write_bytes(b, 0xCD, 64); }
+            assert_eq!(regions(), 2);
+        }
+
+        assert_eq!(regions(), 1);
+        let c = outer.alloc(64, 8);
+        assert!(c as usize >= a as usize + 64);
+        for i in 0..64 {
+            assert_eq!(unsafe { *a.offset(i) }, 0xAB);
+        }
+
+        drop(outer);
+        assert_eq!(regions(), 0);
+        assert_eq!(super::DEPTH.with(|x| x.get()), 0);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut guard = ScratchGuard::new(64);
+        let a = guard.alloc(48, 8);
+        let b = guard.alloc(48, 8);
+        assert_eq!(regions(), 2);
+
+        // Larger than the hint.
+        let c = guard.alloc(1024, 64);
+        assert!(c as usize % 64 == 0);
+        assert_eq!(regions(), 3);
+
+        unsafe {
+            ptr::write_bytes(a, 1, 48);
+            ptr::write_bytes(b, 2, 48);
+            ptr::write_bytes(c, 3, 1024);
+            assert_eq!(*a.offset(47), 1);
+            assert_eq!(*b.offset(47), 2);
+        }
+
+        drop(guard);
+        assert_eq!(regions(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "dropped out of order")]
+    fn test_out_of_order() {
+        let outer = ScratchGuard::new(64);
+        let _inner = ScratchGuard::new(64);
+        drop(outer);
+    }
+
+    #[test]
+    #[cfg(feature = "debugger")]
+    #[should_panic(expected = "scratch allocation")]
+    fn test_free_scratch() {
+        let mut guard = ScratchGuard::new(64);
+        let a = guard.alloc(16, 8);
+        assert!(super::owns(a));
+
+        unsafe { ::free(a, 16); }
+    }
+}
@@ -0,0 +1,251 @@
+//! Secure allocations.
+//!
+//! Secure allocations are meant for sensitive data, such as key material. They bypass the block
+//! pool entirely, and are mapped directly from the OS, locked into RAM (such that they never hit
+//! swap), excluded from core dumps where possible, and always wiped when freed, regardless of
+//! the zero-on-free policy.
+//!
+//! The mappings are registered as regions, so `free` recognizes and unmaps them as well.
+
+use prelude::*;
+
+use core::intrinsics;
+use atomic::{self, AtomicUsize};
+
+use shim::syscalls;
+
+use allocator;
+use fail::{AllocErr, GrowError};
+use leak::Leak;
+use region::{self, OwnedRegion, Origin};
+
+/// The number of live secure allocations.
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+/// The number of bytes (including the page rounding) in live secure allocations.
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Round `size` up to the page size.
+fn page_round(size: usize) -> Option<usize> {
+    Align::page().round_up(size)
+}
+
+/// Allocate a secure buffer of size `size` aligned to `align`.
+///
+/// The buffer is locked into RAM and excluded from core dumps. It must be freed with
+/// `secure_free`.
+///
+/// # Errors
+///
+/// If the OS cannot map the memory, `AllocErr::Os` is returned. If the memory cannot be locked
+/// (e.g. because `RLIMIT_MEMLOCK` is exceeded), `AllocErr::MemoryLock` is returned. Locking is
+/// never silently skipped. If the region registry is full, `AllocErr::LimitReached` is returned.
+/// If `size` exceeds the maximal allocation size, `AllocErr::TooLarge` is returned.
+///
+/// # Panics
+///
+/// This panics if `align` is larger than the page size.
+pub fn secure_alloc(size: usize, align: usize) -> Result<*mut u8, AllocErr> {
+    log!(CALL, "Allocating secure buffer of size {} (align {}).", size, align);
+
+    // Mappings are page aligned, so larger alignments cannot be satisfied.
+    assert!(align <= Align::page().get(), "Secure allocations cannot be aligned beyond the page \
+            size.");
+
+    allocator::check_size(size)?;
+
+    // There is no memory mapping in bare-metal mode.
+    if allocator::bare_metal() {
+        return Err(AllocErr::Os(GrowError::Mmap(syscalls::ENOSYS)));
+    }
+
+    let size = page_round(size).unwrap_or_else(|| !0);
+
+    // Map the memory.
+    let ptr = syscalls::mmap(size).map_err(AllocErr::Os)?;
+
+    unsafe {
+        // The mapping was just acquired, so it is valid and unaliased.
+        if let Err(errno) = lock(ptr, size) {
+            // Logging.
+            log!(WARNING, "Unable to lock secure buffer ({}).", errno);
+
+            // Give the mapping back.
+            let _ = syscalls::munmap(ptr, size);
+
+            return Err(AllocErr::MemoryLock(errno));
+        }
+
+        // Exclude the buffer from core dumps.
+        syscalls::madvise_dontdump(ptr, size);
+
+        // Register the mapping.
+        let block = Block::from_raw_parts(Pointer::new(ptr), size);
+        if let Err(mut region) = region::register(OwnedRegion::new(block, Origin::Mmap {
+            fd_less: true,
+            locked: true,
+            interleaved: false,
+        })) {
+            // Logging.
+            log!(WARNING, "Unable to register secure buffer.");
+
+            // Give the mapping back.
+            wipe_unlock(&mut region.block);
+            let _ = syscalls::munmap(ptr, size);
+
+            return Err(AllocErr::LimitReached);
+        }
+    }
+
+    // Update the statistics.
+    COUNT.fetch_add(1, atomic::Ordering::Relaxed);
+    BYTES.fetch_add(size, atomic::Ordering::Relaxed);
+
+    Ok(ptr)
+}
+
+/// Free a secure buffer.
+///
+/// The buffer is wiped, unlocked, and unmapped.
+///
+/// # Safety
+///
+/// `ptr` must be allocated through `secure_alloc` with the same `size`, and must not be used
+/// after the free.
+pub unsafe fn secure_free(ptr: *mut u8, size: usize) {
+    log!(CALL, "Freeing secure buffer of size {}.", size);
+
+    let mut block = Block::from_raw_parts(Pointer::new(ptr), page_round(size).unwrap_or_else(|| !0));
+
+    // Forget the mapping.
+    let res = region::unregister(&block);
+    debug_assert!(res.is_ok(), "Freeing an unregistered secure buffer.");
+
+    // Wipe and unlock it.
+    wipe_unlock(&mut block);
+
+    // Update the statistics.
+    COUNT.fetch_sub(1, atomic::Ordering::Relaxed);
+    BYTES.fetch_sub(block.size(), atomic::Ordering::Relaxed);
+
+    // Finally, unmap it.
+    let size = block.size();
+    let res = syscalls::munmap(*Pointer::from(block), size);
+    debug_assert!(res.is_ok(), "Unable to unmap secure buffer.");
+}
+
+/// Lock memory into RAM.
+#[cfg(not(test))]
+unsafe fn lock(ptr: *mut u8, size: usize) -> Result<(), usize> {
+    syscalls::mlock(ptr, size)
+}
+
+/// Lock memory into RAM.
+///
+/// For testing, failure can be injected through `FAIL_LOCK`.
+#[cfg(test)]
+unsafe fn lock(ptr: *mut u8, size: usize) -> Result<(), usize> {
+    if test::FAIL_LOCK.load(atomic::Ordering::SeqCst) {
+        Err(syscalls::ENOMEM)
+    } else {
+        syscalls::mlock(ptr, size)
+    }
+}
+
+/// Wipe and unlock a secure block.
+fn wipe_unlock(block: &mut Block) {
+    // The wipe is volatile, so it isn't optimized away.
+    block.wipe();
+
+    unsafe {
+        // The block is valid by its invariants.
+        let _ = syscalls::munlock(*Pointer::from(block.empty_left()), block.size());
+    }
+}
+
+/// Volatile zero a value.
+///
+/// This is the wipe of released metadata with the `security` feature, since the addresses and
+/// sizes kept there can be sensitive as well (see `Vec`, which holds the tables of the allocator).
+///
+/// # Safety
+///
+/// The value is left zeroed, which is not necessarily a valid `T`. It must not be read before it
+/// is overwritten.
+pub unsafe fn secure_wipe<T: Leak>(x: &mut T) {
+    // The wipe is volatile, so it isn't optimized away, even though the value is dead.
+    intrinsics::volatile_set_memory(x as *mut T, 0, 1);
+}
+
+/// Get the number of live secure allocations.
+pub fn count() -> usize {
+    COUNT.load(atomic::Ordering::Relaxed)
+}
+
+/// Get the number of bytes in live secure allocations.
+///
+/// This includes the rounding to the page size.
+pub fn bytes() -> usize {
+    BYTES.load(atomic::Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use core::sync::atomic::AtomicBool;
+
+    #[cfg(feature = "test_util")]
+    use shim::inject;
+
+    /// Make locking fail with `ENOMEM`.
+    pub static FAIL_LOCK: AtomicBool = AtomicBool::new(false);
+
+    #[test]
+    fn test_secure_alloc() {
+        let ptr = secure_alloc(100, 8).unwrap();
+
+        unsafe {
+            let mut block = Block::from_raw_parts(Pointer::new(ptr), 100);
+            block.fill(0xAB);
+
+            // Peek at the memory between the wipe and the unmap.
+            wipe_unlock(&mut block);
+            assert_eq!(block.verify_fill(0), Ok(()));
+
+            secure_free(ptr, 100);
+        }
+
+        // Inject a locking failure.
+        FAIL_LOCK.store(true, atomic::Ordering::SeqCst);
+        let res = secure_alloc(100, 8);
+        FAIL_LOCK.store(false, atomic::Ordering::SeqCst);
+
+        assert_eq!(res, Err(AllocErr::MemoryLock(syscalls::ENOMEM)));
+    }
+
+    #[test]
+    #[cfg(feature = "test_util")]
+    fn test_mmap_failure() {
+        for &errno in &[syscalls::ENOMEM, syscalls::EMFILE] {
+            // The failure is injected into this thread only, so no other test can use it up.
+            inject::fail_mmap(errno);
+            let res = secure_alloc(100, 8);
+
+            assert_eq!(res, Err(AllocErr::Os(GrowError::Mmap(errno))));
+        }
+    }
+
+    #[test]
+    fn test_registered() {
+        let ptr = secure_alloc(5000, 8).unwrap();
+
+        // The whole mapping (rounded to pages) is registered.
+        let region = region::lookup(ptr as usize + 4999).unwrap();
+        assert_eq!(region.origin, Origin::Mmap { fd_less: true, locked: true, interleaved: false });
+        assert_eq!(region.start, ptr as usize);
+        assert_eq!(region.size(), page_round(5000).unwrap());
+        assert_eq!(region::mapping(ptr), Some(region));
+
+        unsafe { secure_free(ptr, 5000); }
+    }
+}
@@ -0,0 +1,401 @@
+//! Shadow accounting.
+//!
+//! With the `shadow_accounting` feature (meant for testing the allocator), a naive accountant
+//! shadows the production counters: Every allocation, free, heap growth and trim is recorded a
+//! second time in plain integers behind a lock.
+//!
+//! The allocations and frees are recorded at the public entry points (see `enter`), from their
+//! arguments and results, rather than where the production counters are updated, such that a
+//! path counting twice (or not at all) shows. The calls in progress are counted, and the counters
+//! are compared to the shadow, whenever no call is in progress (both sides then describe the
+//! same operations). The heap growths and trims are recorded by the region registry, with the
+//! production counters updated under the lock of the accountant.
+//!
+//! The first divergence is kept, along with the sequence number of the last operation and the
+//! operations leading up to it. `reconcile` holds off new calls, until the ones in progress are
+//! done, and compares every counter.
+
+use core::cell::Cell;
+#[cfg(test)]
+use atomic::AtomicUsize;
+
+use shim::syscalls;
+
+use {class, sync, tls, watermark};
+use stats::{self, SizeClass};
+
+/// The number of operations kept for the report of a divergence.
+pub const HISTORY: usize = 16;
+
+/// The shadow accountant.
+static SHADOW: sync::Mutex<Shadow> = sync::Mutex::ranked("shadow accounting", sync::rank::SHADOW,
+                                                         Shadow::new());
+
+/// The size of the next allocation to count twice in the production counters, or zero.
+///
+/// This is for testing, that a path counting twice is caught.
+#[cfg(test)]
+pub static DOUBLE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+tls! {
+    /// The number of nested public calls of the current thread.
+    ///
+    /// Only the outermost call is accounted, as the inner ones (e.g. the frees of `dealloc_many`
+    /// falling back to freeing one by one) are part of it.
+    static DEPTH: Cell<usize> = Cell::new(0);
+}
+
+/// An accounted operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// An allocation (or the allocating half of a reallocation) of some size.
+    Alloc(usize),
+    /// A (possibly partial) free of some size.
+    Free(usize),
+    /// The heap grew by some number of bytes.
+    Grow(usize),
+    /// The heap shrunk by some number of bytes.
+    Trim(usize),
+}
+
+/// A production counter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counter {
+    /// The cumulative number of allocations of a class.
+    Allocs(SizeClass),
+    /// The cumulative number of frees of a class.
+    Frees(SizeClass),
+    /// The number of bytes in live allocations of a class.
+    Bytes(SizeClass),
+    /// The number of bytes in live allocations, over every class (see `stats::live_bytes`).
+    LiveBytes,
+    /// The number of bytes held from the OS (see `heap_usage`).
+    HeapUsage,
+}
+
+/// A production counter disagreeing with the shadow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// The sequence number of the last operation before the divergence was found.
+    ///
+    /// The operations are numbered from 1, in the order they were recorded. The counters are only
+    /// compared, when no call is in progress, so the diverging operation is one of the history.
+    pub seq: u64,
+    /// The diverging counter.
+    pub counter: Counter,
+    /// The value of the shadow.
+    pub expected: usize,
+    /// The value of the production counter.
+    pub found: usize,
+    /// The last operations up to (and including) `seq`, the oldest first.
+    ///
+    /// The entries before the first operation are `None`.
+    pub history: [Option<(u64, Op)>; HISTORY],
+}
+
+/// The state of the shadow accountant.
+struct Shadow {
+    /// The cumulative number of allocations, for each class.
+    allocs: [usize; class::COUNT + 1],
+    /// The cumulative number of frees, for each class.
+    frees: [usize; class::COUNT + 1],
+    /// The number of bytes in live allocations, for each class.
+    bytes: [usize; class::COUNT + 1],
+    /// The number of bytes in live allocations.
+    live_bytes: usize,
+    /// The number of bytes held from the OS.
+    heap: usize,
+    /// The sequence number of the last operation.
+    seq: u64,
+    /// The last operations, as a ring indexed by the sequence number.
+    history: [Option<(u64, Op)>; HISTORY],
+    /// The first divergence found.
+    divergence: Option<Divergence>,
+    /// The number of public calls in progress.
+    calls: usize,
+    /// The number of reconciliations waiting for the calls in progress.
+    ///
+    /// New calls wait, while this is nonzero.
+    draining: usize,
+}
+
+impl Shadow {
+    /// Create a new shadow of no operations.
+    const fn new() -> Shadow {
+        Shadow {
+            allocs: [0; class::COUNT + 1],
+            frees: [0; class::COUNT + 1],
+            bytes: [0; class::COUNT + 1],
+            live_bytes: 0,
+            heap: 0,
+            seq: 0,
+            history: [None; HISTORY],
+            divergence: None,
+            calls: 0,
+            draining: 0,
+        }
+    }
+
+    /// Account an operation.
+    fn apply(&mut self, op: Op) {
+        self.seq += 1;
+        self.history[(self.seq % HISTORY as u64) as usize] = Some((self.seq, op));
+
+        match op {
+            Op::Alloc(size) => {
+                let class = SizeClass::of(size).index();
+                self.allocs[class] += 1;
+                self.bytes[class] = self.bytes[class].wrapping_add(size);
+                self.live_bytes = self.live_bytes.wrapping_add(size);
+            },
+            Op::Free(size) => {
+                let class = SizeClass::of(size).index();
+                self.frees[class] += 1;
+                self.bytes[class] = self.bytes[class].wrapping_sub(size);
+                self.live_bytes = self.live_bytes.wrapping_sub(size);
+            },
+            Op::Grow(size) => self.heap += size,
+            Op::Trim(size) => self.heap = self.heap.wrapping_sub(size),
+        }
+    }
+
+    /// Compare the counters of a class.
+    fn compare_class(&self, class: SizeClass) -> Result<(), (Counter, usize, usize)> {
+        let stats = stats::class(class);
+        let n = class.index();
+
+        compare(Counter::Allocs(class), self.allocs[n], stats.allocs)?;
+        compare(Counter::Frees(class), self.frees[n], stats.frees)?;
+        compare(Counter::Bytes(class), self.bytes[n], stats.bytes)
+    }
+
+    /// Compare the totals.
+    fn compare_totals(&self) -> Result<(), (Counter, usize, usize)> {
+        compare(Counter::LiveBytes, self.live_bytes, stats::live_bytes())?;
+        compare(Counter::HeapUsage, self.heap, watermark::heap_usage())
+    }
+
+    /// Compare every counter.
+    fn compare_all(&self) -> Result<(), (Counter, usize, usize)> {
+        for class in SizeClass::iter() {
+            self.compare_class(class)?;
+        }
+
+        self.compare_totals()
+    }
+
+    /// Describe a divergence after the last operation.
+    fn divergence(&self, (counter, expected, found): (Counter, usize, usize)) -> Divergence {
+        let mut history = [None; HISTORY];
+        for (n, entry) in history.iter_mut().enumerate() {
+            // The oldest operation kept comes first.
+            *entry = self.history[((self.seq + 1 + n as u64) % HISTORY as u64) as usize];
+        }
+
+        Divergence {
+            seq: self.seq,
+            counter: counter,
+            expected: expected,
+            found: found,
+            history: history,
+        }
+    }
+
+    /// Check every counter, if no call is in progress, keeping the first divergence.
+    fn check(&mut self) {
+        if self.divergence.is_some() || self.calls != 0 {
+            return;
+        }
+
+        if let Err(err) = self.compare_all() {
+            let divergence = self.divergence(err);
+            log!(ERROR, "The accounting diverged after operation {}: {:?} is {}, but the shadow \
+                         has {}.", divergence.seq, divergence.counter, divergence.found,
+                 divergence.expected);
+
+            self.divergence = Some(divergence);
+        }
+    }
+}
+
+/// Compare a production counter to its shadow.
+#[inline]
+fn compare(counter: Counter, expected: usize, found: usize) -> Result<(), (Counter, usize, usize)> {
+    if expected == found { Ok(()) } else { Err((counter, expected, found)) }
+}
+
+/// A heap growth or trim in progress.
+///
+/// The production counters must be updated while this is held. When it is dropped, the counters
+/// are checked against the shadow (if no call is in progress).
+#[must_use]
+pub struct Guard {
+    /// The locked shadow.
+    shadow: sync::MutexGuard<'static, Shadow>,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.shadow.check();
+    }
+}
+
+/// Record a heap growth or trim.
+///
+/// The production counters of the operation must be updated while the returned guard is held.
+#[inline]
+pub fn record(op: Op) -> Guard {
+    let mut shadow = SHADOW.lock();
+    shadow.apply(op);
+
+    Guard {
+        shadow: shadow,
+    }
+}
+
+/// A public call in progress.
+///
+/// See `enter`.
+#[must_use]
+pub struct Call {
+    /// Is this the outermost call of the thread?
+    outermost: bool,
+}
+
+impl Call {
+    /// Account an operation done by the call.
+    ///
+    /// The operations are told from the arguments and the result of the call.
+    pub fn op(&self, op: Op) {
+        if self.outermost {
+            SHADOW.lock().apply(op);
+        }
+    }
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
+
+        if self.outermost {
+            let mut shadow = SHADOW.lock();
+            shadow.calls -= 1;
+            shadow.check();
+        }
+    }
+}
+
+/// Enter a public call.
+///
+/// The production counters may only be updated by calls in progress (or under a `Guard`). When
+/// the returned call is dropped, and no other call is in progress, every counter is checked
+/// against the shadow.
+///
+/// While a reconciliation is waiting, new (outermost) calls wait for it.
+pub fn enter() -> Call {
+    let outermost = DEPTH.with(|depth| {
+        depth.set(depth.get() + 1);
+        depth.get() == 1
+    });
+
+    if outermost {
+        loop {
+            {
+                let mut shadow = SHADOW.lock();
+                if shadow.draining == 0 {
+                    shadow.calls += 1;
+                    break;
+                }
+            }
+
+            syscalls::sched_yield();
+        }
+    }
+
+    Call {
+        outermost: outermost,
+    }
+}
+
+/// Compare every production counter to the shadow.
+///
+/// New calls are held off, until the calls in progress are done. If a counter diverged from the
+/// shadow before, the first divergence is returned. Otherwise every counter is compared, and the
+/// number of operations reconciled is returned. A divergence found at this point is reported
+/// after the last operation.
+pub fn reconcile() -> Result<u64, Divergence> {
+    SHADOW.lock().draining += 1;
+
+    loop {
+        {
+            let mut shadow = SHADOW.lock();
+            if shadow.calls == 0 {
+                shadow.check();
+                shadow.draining -= 1;
+
+                return match shadow.divergence {
+                    Some(divergence) => Err(divergence),
+                    None => Ok(shadow.seq),
+                };
+            }
+        }
+
+        syscalls::sched_yield();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use {allocator, atomic};
+    use stats::SizeClass;
+
+    #[test]
+    fn test_history() {
+        let mut shadow = Shadow::new();
+        for n in 0..20 {
+            shadow.apply(Op::Alloc(n));
+        }
+
+        let divergence = shadow.divergence((Counter::LiveBytes, 0, 1));
+        assert_eq!(divergence.seq, 20);
+        for (n, &entry) in divergence.history.iter().enumerate() {
+            let seq = 20 - HISTORY as u64 + 1 + n as u64;
+            assert_eq!(entry, Some((seq, Op::Alloc(seq as usize - 1))));
+        }
+
+        // Before the ring fills up, the oldest entries are empty.
+        let mut shadow = Shadow::new();
+        shadow.apply(Op::Grow(4096));
+        shadow.apply(Op::Trim(4096));
+        let divergence = shadow.divergence((Counter::HeapUsage, 0, 1));
+        assert!(divergence.history[..HISTORY - 2].iter().all(Option::is_none));
+        assert_eq!(divergence.history[HISTORY - 2], Some((1, Op::Grow(4096))));
+        assert_eq!(divergence.history[HISTORY - 1], Some((2, Op::Trim(4096))));
+    }
+
+    #[test]
+    fn test_double_count() {
+        // An allocation counted twice on its way through the allocator, which the shadow only
+        // sees at the entry point.
+        let size = 4321;
+        DOUBLE_COUNT.store(size, atomic::Ordering::Relaxed);
+        let ptr = allocator::alloc(size, 8);
+        assert_eq!(DOUBLE_COUNT.load(atomic::Ordering::Relaxed), 0);
+
+        let divergence = reconcile().unwrap_err();
+        assert_eq!(divergence.counter, Counter::Allocs(SizeClass::of(size)));
+        assert_eq!(divergence.found, divergence.expected + 1);
+
+        // Accounting the extra count in the shadow too brings the sides back in line, such that
+        // the other tests can reconcile.
+        unsafe { allocator::free(ptr, size); }
+        {
+            let mut shadow = SHADOW.lock();
+            shadow.apply(Op::Alloc(size));
+            shadow.divergence = None;
+        }
+        assert!(reconcile().is_ok());
+    }
+}
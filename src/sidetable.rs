@@ -0,0 +1,323 @@
+//! Side-table allocation metadata.
+//!
+//! With the `sidetable` feature, the sizes of the buffers are kept in a radix table keyed by
+//! address, rather than in headers before the buffers. This keeps the buffers exactly as the pool
+//! returned them, which matters for workloads with many small, tightly aligned buffers.
+//!
+//! The table is keyed by granules of `GRANULE` bytes. Slab cells are never stored, since the slabs
+//! know the size of their cells, and pool blocks are at least one granule and one byte large (see
+//! `SideTable::pool_size`), so no two buffers in the table start in the same granule.
+//!
+//! The table has two levels: a static root of pointers to leaves, and the leaves, which are
+//! mapped on demand and never unmapped. Every operation is lock-free.
+//!
+//! On 64-bit targets, the sizes of pool blocks are bounded by the address space (`ADDRESS_BITS`),
+//! which leaves the top 16 bits of an entry free. They hold the birth generation of the buffer.
+//! 32-bit targets don't record it.
+
+use atomic::{self, AtomicPtr, AtomicUsize};
+use core::{cmp, mem, ptr};
+
+use shim::syscalls;
+
+use meta::Metadata;
+use ptr::Align;
+use {class, fail, slab};
+
+/// The size of a granule.
+///
+/// This is the largest slab cell, so buffers of other sizes go to the pool.
+pub const GRANULE: usize = class::MAX_SIZE;
+/// The number of bits in a granule offset.
+const GRANULE_BITS: usize = 9;
+/// The number of bits in the addresses.
+#[cfg(target_pointer_width = "64")]
+const ADDRESS_BITS: usize = 48;
+/// The number of bits in the addresses.
+#[cfg(not(target_pointer_width = "64"))]
+const ADDRESS_BITS: usize = 32;
+/// The number of bits in a key.
+const KEY_BITS: usize = ADDRESS_BITS - GRANULE_BITS;
+/// The number of bits indexing a leaf.
+const LEAF_BITS: usize = (KEY_BITS + 1) / 2;
+/// The number of bits indexing the root.
+const ROOT_BITS: usize = KEY_BITS - LEAF_BITS;
+/// The number of entries in a leaf.
+const LEAF_LEN: usize = 1 << LEAF_BITS;
+/// The number of entries in the root.
+const ROOT_LEN: usize = 1 << ROOT_BITS;
+/// The shift of the birth generation in an entry.
+#[cfg(target_pointer_width = "64")]
+const BIRTH_SHIFT: usize = ADDRESS_BITS;
+/// The mask of the size in an entry.
+#[cfg(target_pointer_width = "64")]
+const SIZE_MASK: usize = (1 << BIRTH_SHIFT) - 1;
+/// The mask of the size in an entry.
+#[cfg(not(target_pointer_width = "64"))]
+const SIZE_MASK: usize = !0;
+
+/// The root of the table.
+///
+/// Every entry points to a leaf, or is null, if the leaf isn't mapped yet.
+static mut ROOT: [*mut AtomicUsize; ROOT_LEN] = [0 as *mut AtomicUsize; ROOT_LEN];
+
+/// Split an address into its root and leaf index.
+fn split(addr: usize) -> (usize, usize) {
+    let key = (addr >> GRANULE_BITS) & ((1 << KEY_BITS) - 1);
+    (key >> LEAF_BITS, key & (LEAF_LEN - 1))
+}
+
+/// Get the root entry at `n`.
+fn root(n: usize) -> &'static AtomicPtr<AtomicUsize> {
+    unsafe {
+        // `AtomicPtr` has the same layout as a raw pointer, and the root is only accessed
+        // atomically.
+        &*(&ROOT[n] as *const *mut AtomicUsize as *const AtomicPtr<AtomicUsize>)
+    }
+}
+
+/// Get the entry of the address `addr`.
+///
+/// If the leaf isn't mapped, `None` is returned, unless `create` is set, in which case it is
+/// mapped.
+fn entry(addr: usize, create: bool) -> Option<&'static AtomicUsize> {
+    let (n, m) = split(addr);
+
+    let mut leaf = root(n).load(atomic::Ordering::Acquire);
+    if leaf.is_null() {
+        if !create {
+            return None;
+        }
+
+        let size = LEAF_LEN * mem::size_of::<usize>();
+        // Fresh mappings are zeroed, which is the empty leaf.
+        let new = syscalls::mmap(size)
+            .unwrap_or_else(|err| fail::oom(fail::AllocErr::Os(err))) as *mut AtomicUsize;
+
+        leaf = root(n).compare_and_swap(ptr::null_mut(), new, atomic::Ordering::AcqRel);
+        if leaf.is_null() {
+            leaf = new;
+        } else {
+            // Another thread installed the leaf first.
+            unsafe {
+                // The mapping was never published, so nobody else refers to it.
+                let _ = syscalls::munmap(new as *mut u8, size);
+            }
+        }
+    }
+
+    unsafe {
+        // Leaves are mapped, word-aligned arrays of `LEAF_LEN` words, which are never unmapped.
+        Some(&*leaf.offset(m as isize))
+    }
+}
+
+/// Encode the entry of a buffer.
+///
+/// The entry stores the size plus one, so zero means that there is no entry.
+#[cfg(target_pointer_width = "64")]
+fn encode(size: usize, birth: u16) -> usize {
+    (size + 1) | (birth as usize) << BIRTH_SHIFT
+}
+
+/// Encode the entry of a buffer.
+///
+/// The entry stores the size plus one, so zero means that there is no entry.
+#[cfg(not(target_pointer_width = "64"))]
+fn encode(size: usize, _birth: u16) -> usize {
+    size + 1
+}
+
+/// Get the birth generation from an entry.
+#[cfg(target_pointer_width = "64")]
+fn decode_birth(x: usize) -> Option<u16> {
+    Some((x >> BIRTH_SHIFT) as u16)
+}
+
+/// Get the birth generation from an entry.
+#[cfg(not(target_pointer_width = "64"))]
+fn decode_birth(_x: usize) -> Option<u16> {
+    None
+}
+
+/// Record the size and birth generation of the buffer at `ptr`.
+pub fn insert(ptr: *mut u8, size: usize, birth: u16) {
+    // Make some assertions.
+    assert!(size < SIZE_MASK, "The buffer is too large for the side table.");
+
+    let old = entry(ptr as usize, true).unwrap()
+        .swap(encode(size, birth), atomic::Ordering::Relaxed);
+    debug_assert!(old == 0, "Two buffers share the granule of {:?}.", ptr);
+}
+
+/// Remove the size of the buffer at `ptr`.
+///
+/// The size is returned, or `None`, if there is no entry for the buffer.
+pub fn remove(ptr: *mut u8) -> Option<usize> {
+    entry(ptr as usize, false).and_then(|entry| {
+        match entry.swap(0, atomic::Ordering::Relaxed) {
+            0 => None,
+            x => Some((x & SIZE_MASK) - 1),
+        }
+    })
+}
+
+/// Get the size of the buffer at `ptr`.
+///
+/// If there is no entry for the buffer, `None` is returned.
+pub fn get(ptr: *mut u8) -> Option<usize> {
+    entry(ptr as usize, false).and_then(|entry| {
+        match entry.load(atomic::Ordering::Relaxed) {
+            0 => None,
+            x => Some((x & SIZE_MASK) - 1),
+        }
+    })
+}
+
+/// Get the birth generation of the buffer at `ptr`.
+///
+/// If there is no entry for the buffer, or the birth isn't recorded, `None` is returned.
+pub fn birth(ptr: *mut u8) -> Option<u16> {
+    entry(ptr as usize, false).and_then(|entry| {
+        match entry.load(atomic::Ordering::Relaxed) {
+            0 => None,
+            x => decode_birth(x),
+        }
+    })
+}
+
+/// The side-table metadata strategy.
+pub struct SideTable;
+
+impl Metadata for SideTable {
+    #[inline]
+    fn padding(_align: Align) -> usize {
+        0
+    }
+
+    /// Round the size of a pool block up to more than a granule.
+    ///
+    /// Pool blocks are thus more than a granule apart, so they start in distinct granules.
+    #[inline]
+    fn pool_size(size: usize) -> usize {
+        cmp::max(size, GRANULE + 1)
+    }
+
+    #[inline]
+    unsafe fn stamp(ptr: *mut u8, size: usize, _padding: usize, _tag: u8, birth: u16) {
+        // Slab cells know their size.
+        if slab::cell_size(ptr, 0).is_none() {
+            insert(ptr, size, birth);
+        }
+    }
+
+    /// Get the birth generation of a buffer.
+    ///
+    /// Slab cells aren't in the table, so their birth isn't recorded.
+    #[inline]
+    unsafe fn birth(ptr: *mut u8) -> Option<u16> {
+        birth(ptr)
+    }
+
+    /// Forget the size of a buffer.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `size` doesn't match the table. In particular, partial frees aren't
+    /// supported with the side table.
+    #[inline]
+    unsafe fn unstamp(ptr: *mut u8, size: usize) -> usize {
+        if let Some(old) = remove(ptr) {
+            assert!(old == size, "The buffer {:?} of size {} was treated as being of size {}.",
+                    ptr, old, size);
+        }
+
+        0
+    }
+
+    /// Check the size of a buffer against the table.
+    ///
+    /// Slab cells aren't in the table, so they always pass.
+    #[inline]
+    unsafe fn verify(ptr: *mut u8, size: usize, _align: Align) -> bool {
+        get(ptr).map_or(true, |old| old == size)
+    }
+
+    /// Remove the size of a buffer from the table, without checking it.
+    ///
+    /// The entry must go, since the address can be reused by a slab cell.
+    #[inline]
+    unsafe fn forget(ptr: *mut u8, _size: usize, _align: Align) -> usize {
+        remove(ptr);
+
+        0
+    }
+
+    /// Get the size of a buffer.
+    ///
+    /// Slab buffers give the size of their cell.
+    ///
+    /// # Failure
+    ///
+    /// If the buffer is neither in the table nor in a slab, the heap corruption handler is called.
+    #[inline]
+    unsafe fn size(ptr: *mut u8) -> usize {
+        get(ptr).or_else(|| slab::cell_size(ptr, 0)).unwrap_or_else(|| fail::corruption(ptr))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split(0), (0, 0));
+        assert_eq!(split(GRANULE - 1), (0, 0));
+        assert_eq!(split(GRANULE), (0, 1));
+        assert_eq!(split(GRANULE * LEAF_LEN), (1, 0));
+
+        // Distinct granules give distinct keys.
+        let (n, m) = split(GRANULE * (LEAF_LEN * 3 + 7) + 100);
+        assert_eq!((n, m), (3, 7));
+        assert!(n < ROOT_LEN && m < LEAF_LEN);
+    }
+
+    #[test]
+    fn test_insert_remove() {
+        // Use addresses far from the heap, so the allocator's own entries aren't hit.
+        let ptr = (GRANULE * LEAF_LEN * 5 + GRANULE * 3) as *mut u8;
+
+        assert_eq!(get(ptr), None);
+        insert(ptr, 1000, 0);
+        assert_eq!(get(ptr), Some(1000));
+        assert_eq!(remove(ptr), Some(1000));
+        assert_eq!(get(ptr), None);
+        assert_eq!(remove(ptr), None);
+
+        // Zero-sized buffers are distinct from missing entries.
+        insert(ptr, 0, 0);
+        assert_eq!(remove(ptr), Some(0));
+
+        // The birth doesn't leak into the size.
+        insert(ptr, 1000, 0xFFFF);
+        assert_eq!(get(ptr), Some(1000));
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(birth(ptr), Some(0xFFFF));
+        assert_eq!(remove(ptr), Some(1000));
+        assert_eq!(birth(ptr), None);
+    }
+
+    #[test]
+    fn test_neighbors() {
+        let base = GRANULE * LEAF_LEN * 6;
+
+        // Blocks more than a granule apart never collide.
+        for i in 0..8 {
+            insert((base + i * (GRANULE + 1)) as *mut u8, i, i as u8);
+        }
+        for i in 0..8 {
+            assert_eq!(remove((base + i * (GRANULE + 1)) as *mut u8), Some(i));
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Async-signal-safe allocation.
+//!
+//! Signal handlers cannot use the main allocator, since the interrupted thread might hold one of
+//! its locks. This module provides a small emergency pool for such handlers instead: a static
+//! buffer (of `config::SIG_POOL_SIZE` bytes), which is never touched by the main allocator.
+//!
+//! The pool is managed entirely through atomic operations. Fresh buffers are taken from a bump
+//! pointer, and freed buffers are put on lock-free freelists, one per power-of-two size class, so
+//! every function in this module is async-signal-safe.
+
+use atomic::{self, AtomicUsize};
+use core::mem;
+
+use shim::config;
+
+use ptr::with_addr;
+
+/// The size of the header preceding every buffer.
+///
+/// The first word is the size class, and the second word is the freelist link.
+const HEADER: usize = 16;
+/// The alignment of the buffers.
+const ALIGN: usize = 16;
+/// The number of size classes.
+///
+/// The classes are the powers of two from 16 bytes to 512 KiB.
+const CLASSES: usize = 16;
+/// The number of bits in the index half of a freelist head.
+///
+/// The other half is a generation counter, which is bumped on every change, avoiding the ABA
+/// problem.
+const INDEX_BITS: usize = mem::size_of::<usize>() * 4;
+/// The mask of the index half of a freelist head.
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+
+/// The emergency pool.
+static mut POOL: [u8; config::SIG_POOL_SIZE] = [0; config::SIG_POOL_SIZE];
+/// The number of bytes used from the (aligned) pool.
+static BUMP: AtomicUsize = AtomicUsize::new(0);
+/// The freelists.
+///
+/// The index half is one plus the offset (in units of `ALIGN`) of the first free buffer's header,
+/// or zero if the list is empty.
+// Atomics aren't `Copy`, so we cannot use the repeat syntax.
+static FREE: [AtomicUsize; CLASSES] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+/// Get a pointer to the start of the pool.
+fn pool() -> *mut u8 {
+    unsafe {
+        // We only take a pointer, so no reference to the contents is used.
+        &mut POOL as *mut [u8; config::SIG_POOL_SIZE] as *mut u8
+    }
+}
+
+/// Get the start of the pool.
+fn start() -> usize {
+    pool() as usize
+}
+
+/// Get a pointer to the address `addr` in the pool.
+///
+/// The freelists and the bump pointer deal in addresses, so the pointers handed out are derived
+/// from the pool through this.
+fn at(addr: usize) -> *mut u8 {
+    with_addr(pool(), addr)
+}
+
+/// Get the aligned start of the pool.
+fn base() -> usize {
+    (start() + ALIGN - 1) / ALIGN * ALIGN
+}
+
+/// Get the number of usable bytes in the aligned pool.
+fn capacity() -> usize {
+    config::SIG_POOL_SIZE - (base() - start())
+}
+
+/// Get the size class of a buffer of `size` bytes.
+///
+/// Sizes beyond the largest class give `None`.
+fn class_of(size: usize) -> Option<usize> {
+    let mut class = 0;
+    while (ALIGN << class) < size {
+        class += 1;
+
+        if class == CLASSES {
+            return None;
+        }
+    }
+
+    Some(class)
+}
+
+/// Get the header word `n` of the buffer with the header at `header`.
+fn word(header: usize, n: usize) -> &'static AtomicUsize {
+    unsafe {
+        // Headers are aligned and in the static pool, so this is a valid, word-aligned pointer.
+        &*(at(header) as *const AtomicUsize).offset(n as isize)
+    }
+}
+
+/// Pop a header from the freelist of some class.
+fn pop(class: usize) -> Option<usize> {
+    let list = &FREE[class];
+
+    let mut head = list.load(atomic::Ordering::Acquire);
+    loop {
+        let index = head & INDEX_MASK;
+        if index == 0 {
+            return None;
+        }
+
+        let header = base() + (index - 1) * ALIGN;
+        // The buffer might have been popped concurrently, in which case the link is garbage, but
+        // then the generation has changed, and the exchange fails.
+        let next = word(header, 1).load(atomic::Ordering::Relaxed);
+        let new = (next & INDEX_MASK) | (head >> INDEX_BITS).wrapping_add(1) << INDEX_BITS;
+
+        let old = list.compare_and_swap(head, new, atomic::Ordering::AcqRel);
+        if old == head {
+            return Some(header);
+        }
+        head = old;
+    }
+}
+
+/// Push a header to the freelist of some class.
+fn push(class: usize, header: usize) {
+    let list = &FREE[class];
+    let index = (header - base()) / ALIGN + 1;
+
+    let mut head = list.load(atomic::Ordering::Relaxed);
+    loop {
+        word(header, 1).store(head & INDEX_MASK, atomic::Ordering::Relaxed);
+        let new = index | (head >> INDEX_BITS).wrapping_add(1) << INDEX_BITS;
+
+        let old = list.compare_and_swap(head, new, atomic::Ordering::AcqRel);
+        if old == head {
+            return;
+        }
+        head = old;
+    }
+}
+
+/// Take a fresh header from the bump pointer.
+fn bump(class: usize) -> Option<usize> {
+    let size = HEADER + (ALIGN << class);
+
+    let mut used = BUMP.load(atomic::Ordering::Relaxed);
+    loop {
+        if capacity() - used < size {
+            return None;
+        }
+
+        let old = BUMP.compare_and_swap(used, used + size, atomic::Ordering::Relaxed);
+        if old == used {
+            let header = base() + used;
+            word(header, 0).store(class, atomic::Ordering::Relaxed);
+
+            return Some(header);
+        }
+        used = old;
+    }
+}
+
+/// Allocate a buffer of `size` bytes from the emergency pool.
+///
+/// The buffer is aligned to 16 bytes. If the pool is exhausted, `None` is returned.
+///
+/// This is async-signal-safe: it never takes a lock nor makes a system call, so it can be called
+/// from signal handlers, even if the interrupted thread is inside the allocator.
+pub fn alloc(size: usize) -> Option<*mut u8> {
+    class_of(size).and_then(|class| {
+        pop(class).or_else(|| bump(class)).map(|header| at(header + HEADER))
+    })
+}
+
+/// Free a buffer allocated with `alloc`.
+///
+/// This is async-signal-safe.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `alloc`, and must not have been freed since. The buffer must
+/// not be passed to the main allocator (e.g. `ralloc::free`).
+pub unsafe fn dealloc(ptr: *mut u8) {
+    // Make some assertions.
+    debug_assert!(contains(ptr), "The buffer is not from the emergency pool.");
+
+    let header = ptr as usize - HEADER;
+    push(word(header, 0).load(atomic::Ordering::Relaxed), header);
+}
+
+/// Check if a pointer is in the emergency pool.
+pub fn contains(ptr: *const u8) -> bool {
+    let addr = ptr as usize;
+    addr >= start() && addr < start() + config::SIG_POOL_SIZE
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_class_of() {
+        assert_eq!(class_of(0), Some(0));
+        assert_eq!(class_of(16), Some(0));
+        assert_eq!(class_of(17), Some(1));
+        assert_eq!(class_of(4096), Some(8));
+        assert_eq!(class_of(512 * 1024), Some(CLASSES - 1));
+        assert_eq!(class_of(512 * 1024 + 1), None);
+    }
+
+    #[test]
+    fn test_reuse() {
+        let a = alloc(100).unwrap();
+        assert!(contains(a));
+        assert_eq!(a as usize % ALIGN, 0);
+
+        unsafe {
+            dealloc(a);
+        }
+
+        // Other threads might take the buffer first, but it must stay in the pool.
+        let b = alloc(100).unwrap();
+        assert!(contains(b));
+
+        unsafe {
+            *b = 42;
+            *b.offset(99) = 43;
+            assert_eq!(*b, 42);
+            assert_eq!(*b.offset(99), 43);
+
+            dealloc(b);
+        }
+    }
+
+    #[test]
+    fn test_too_large() {
+        assert_eq!(alloc(config::SIG_POOL_SIZE), None);
+    }
+}
@@ -0,0 +1,310 @@
+//! Allocation sites.
+//!
+//! With the `sites` feature, allocations can be attributed to the source location allocating
+//! them (see `alloc_at_site` and the `alloc_here!` macro). The counters of every site are kept in
+//! a fixed open-addressing table keyed by the address of the site, so recording never allocates
+//! (beyond the side table of the live attributed allocations, which works like the one of the
+//! tags). When the table is full, new sites are counted together in a last bucket.
+
+use prelude::*;
+
+use core::fmt;
+
+use shim::config;
+
+use {sort, sync};
+
+/// The sites and the live attributed allocations.
+static SITES: sync::Mutex<Table> = sync::Mutex::ranked("sites", sync::rank::FRONT_END,
+                                                       Table::new());
+
+/// The index of the bucket of the sites, which didn't fit the table.
+const OTHER: usize = config::SITE_CAPACITY - 1;
+
+/// A source location allocating memory.
+///
+/// These are made by the `alloc_here!` macro, and must be statics, since sites are told apart by
+/// their addresses.
+#[derive(Debug)]
+pub struct Site {
+    /// The file.
+    pub file: &'static str,
+    /// The line.
+    pub line: u32,
+    /// The column.
+    pub column: u32,
+}
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// Allocate a buffer attributed to the current source location.
+///
+/// This is `alloc_at_site(size, align, site)` with a static site made for the invocation.
+#[macro_export]
+macro_rules! alloc_here {
+    ($size:expr, $align:expr) => {{
+        static SITE: $crate::Site = $crate::Site {
+            file: file!(),
+            line: line!(),
+            column: column!(),
+        };
+
+        $crate::alloc_at_site($size, $align, &SITE)
+    }};
+}
+
+/// The statistics of an allocation site.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SiteStats {
+    /// The number of allocations made.
+    pub count: usize,
+    /// The number of bytes in live allocations.
+    pub bytes: usize,
+    /// The maximal number of bytes in live allocations.
+    pub peak: usize,
+}
+
+/// The sites and the live attributed allocations.
+struct Table {
+    /// The sites as the address of the site (or zero for free slots) and its statistics.
+    ///
+    /// The last slot is the bucket of the sites, which didn't fit. The addresses only serve as
+    /// keys, the sites themselves are kept in `names`.
+    sites: [(usize, SiteStats); config::SITE_CAPACITY],
+    /// The site of every claimed slot.
+    names: [Option<&'static Site>; config::SITE_CAPACITY],
+    /// The live attributed allocations as `(address, size, slot)`, sorted by address.
+    entries: Vec<(usize, usize, usize)>,
+}
+
+impl Table {
+    /// Create a new empty table.
+    const fn new() -> Table {
+        Table {
+            sites: [(0, SiteStats { count: 0, bytes: 0, peak: 0 }); config::SITE_CAPACITY],
+            names: [None; config::SITE_CAPACITY],
+            entries: Vec::new(),
+        }
+    }
+
+    /// Find the slot of a site, claiming one if it is new.
+    ///
+    /// If the table is full, the bucket of the other sites is returned.
+    fn slot(&mut self, site: usize) -> usize {
+        // Fibonacci hashing of the address.
+        let mut n = (site >> 3).wrapping_mul(0x9E3779B9) % OTHER;
+        for _ in 0..OTHER {
+            if self.sites[n].0 == site {
+                return n;
+            }
+            if self.sites[n].0 == 0 {
+                self.sites[n].0 = site;
+                return n;
+            }
+
+            n = (n + 1) % OTHER;
+        }
+
+        OTHER
+    }
+
+    /// Find the index of the entry containing `addr`.
+    fn find(&self, addr: usize) -> Option<usize> {
+        sort::predecessor(&self.entries, &addr, |x| x.0).and_then(|n| {
+            let (base, size, _) = self.entries[n];
+            if addr - base < size { Some(n) } else { None }
+        })
+    }
+
+    /// Record an allocation in some slot.
+    ///
+    /// If `fresh` is false, the allocation is a reallocated one, and isn't counted again.
+    fn insert(&mut self, addr: usize, size: usize, slot: usize, fresh: bool) {
+        {
+            let stats = &mut self.sites[slot].1;
+            if fresh {
+                stats.count += 1;
+            }
+            stats.bytes += size;
+            if stats.bytes > stats.peak {
+                stats.peak = stats.bytes;
+            }
+        }
+
+        // Zero-sized allocations contain no bytes to look up.
+        if size != 0 {
+            sort::insert(&mut self.entries, (addr, size, slot), |x| x.0);
+        }
+    }
+
+    /// Record a (possibly partial) free of the range `addr..addr + size`.
+    ///
+    /// The slot of the range is returned. Ranges outside the table give `None`.
+    fn remove(&mut self, addr: usize, size: usize) -> Option<usize> {
+        let n = match if size == 0 { None } else { self.find(addr) } {
+            Some(n) => n,
+            None => return None,
+        };
+
+        let (base, old_size, slot) = self.entries[n];
+
+        // The parts of the entry surrounding the range.
+        let left = addr - base;
+        let right = (base + old_size).saturating_sub(addr + size);
+
+        self.sites[slot].1.bytes -= old_size - left - right;
+
+        if left == 0 {
+            sort::remove(&mut self.entries, n);
+        } else {
+            self.entries[n].1 = left;
+        }
+        if right != 0 {
+            sort::insert(&mut self.entries, (addr + size, right, slot), |x| x.0);
+        }
+
+        Some(slot)
+    }
+
+    /// Write the `n` sites with the most live bytes.
+    fn write_top<W: fmt::Write>(&self, n: usize, w: &mut W) -> fmt::Result {
+        writeln!(w, "  {:>12} {:>12} {:>10} site", "live", "peak", "allocs")?;
+
+        // Pick the heaviest site below the last one picked, over and over.
+        let mut last = None;
+        for _ in 0..n {
+            let next = (0..config::SITE_CAPACITY)
+                .filter(|&i| self.sites[i].1.count != 0)
+                .filter(|&i| last.map_or(true, |last| rank(&self.sites[i], i) < last))
+                .max_by_key(|&i| rank(&self.sites[i], i));
+            let i = match next {
+                Some(i) => i,
+                None => break,
+            };
+            last = Some(rank(&self.sites[i], i));
+
+            let stats = self.sites[i].1;
+            write!(w, "  {:>12} {:>12} {:>10} ", stats.bytes, stats.peak, stats.count)?;
+            match self.names[i] {
+                Some(site) if i != OTHER => writeln!(w, "{}", site)?,
+                _ => writeln!(w, "(other sites)")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The order of the sites in `Table::write_top`.
+///
+/// Sites are ordered by their live bytes, ties broken by the slot.
+fn rank(site: &(usize, SiteStats), slot: usize) -> (usize, usize) {
+    (site.1.bytes, slot)
+}
+
+/// Record an allocation made at some site.
+pub fn insert(ptr: *mut u8, size: usize, site: &'static Site) {
+    let mut table = SITES.lock();
+    let slot = table.slot(site as *const Site as usize);
+    if slot != OTHER {
+        table.names[slot] = Some(site);
+    }
+    table.insert(ptr as usize, size, slot, true);
+}
+
+/// Record a (possibly partial) free.
+///
+/// The slot of the site of the freed range is returned, if it is attributed.
+pub fn remove(ptr: *mut u8, size: usize) -> Option<usize> {
+    SITES.lock().remove(ptr as usize, size)
+}
+
+/// Record the new place of a reallocated buffer.
+///
+/// This gives the buffer back to the site, which `remove` took it from.
+pub fn restore(ptr: *mut u8, size: usize, slot: Option<usize>) {
+    if let Some(slot) = slot {
+        SITES.lock().insert(ptr as usize, size, slot, false);
+    }
+}
+
+/// Get the site of the allocation containing `ptr`.
+///
+/// Pointers outside attributed allocations, and allocations of the sites, which didn't fit the
+/// table, give `None`.
+pub fn get(ptr: *mut u8) -> Option<&'static Site> {
+    let table = SITES.lock();
+
+    table.find(ptr as usize).and_then(|n| table.names[table.entries[n].2])
+}
+
+/// Get the statistics of a site.
+pub fn stats(site: &'static Site) -> SiteStats {
+    let table = SITES.lock();
+
+    table.sites.iter().find(|x| x.0 == site as *const Site as usize).map_or(SiteStats::default(),
+                                                                              |x| x.1)
+}
+
+/// Write the `n` sites with the most live bytes.
+pub fn write_top<W: fmt::Write>(n: usize, w: &mut W) -> fmt::Result {
+    SITES.lock().write_top(n, w)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use log::NoAllocWriter;
+
+    #[test]
+    fn test_balance() {
+        static A: Site = Site { file: "a.rs", line: 1, column: 1 };
+        static B: Site = Site { file: "b.rs", line: 2, column: 1 };
+
+        let mut table = Table::new();
+        let a = table.slot(&A as *const Site as usize);
+        let b = table.slot(&B as *const Site as usize);
+        assert!(a != b);
+        assert_eq!(table.slot(&A as *const Site as usize), a);
+        table.names[a] = Some(&A);
+        table.names[b] = Some(&B);
+
+        table.insert(100, 100, a, true);
+        table.insert(300, 50, b, true);
+        table.insert(500, 20, a, true);
+        assert_eq!(table.remove(300, 50), Some(b));
+        assert_eq!(table.remove(500, 20), Some(a));
+        assert_eq!(table.remove(1000, 10), None);
+
+        assert_eq!(table.sites[a].1, SiteStats { count: 2, bytes: 100, peak: 120 });
+        assert_eq!(table.sites[b].1, SiteStats { count: 1, bytes: 0, peak: 50 });
+
+        // The heaviest site comes first.
+        let mut w = NoAllocWriter::new();
+        table.write_top(1, &mut w).unwrap();
+        assert!(w.as_str().contains("a.rs:1:1"));
+        assert!(!w.as_str().contains("b.rs"));
+    }
+
+    #[test]
+    fn test_full() {
+        let mut table = Table::new();
+
+        // Fill the table with fake sites (they are never dereferenced by `slot`).
+        for site in 1..config::SITE_CAPACITY + 10 {
+            let slot = table.slot(site * 8);
+            table.insert(site * 1000, 10, slot, true);
+        }
+
+        // The sites beyond the table share the last bucket.
+        assert_eq!(table.sites[OTHER].1.count, 10);
+        assert_eq!(table.sites[OTHER].1.bytes, 100);
+        assert_eq!(table.remove((config::SITE_CAPACITY + 9) * 1000, 10), Some(OTHER));
+        assert_eq!(table.sites[OTHER].1.bytes, 90);
+    }
+}
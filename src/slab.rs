@@ -0,0 +1,1436 @@
+//! The slab layer.
+//!
+//! Small allocations are served from slabs rather than the pool. A slab is a block of
+//! `config::SLAB_SIZE` bytes aligned to its size, carved into equally sized cells of a single size
+//! class. The slab starts with a header, holding (among other things) a bitmap of the allocated
+//! cells. Since slabs are aligned, the header of the slab containing some pointer is found by
+//! simply masking the pointer.
+//!
+//! The slabs of a class are coloured: The first cell of every new slab is placed at the next of a
+//! rotating set of offsets, fitting in the slack left after packing the cells. Otherwise the same
+//! field of the objects in different slabs would always map to the same cache sets.
+//!
+//! Slabs are obtained from (and returned to) the pool through the ordinary allocator.
+//!
+//! Whether a pointer is in a slab is answered without the slab lock, by a bitmap of the slabs
+//! keyed by address (see `is_slab`). With the `tls` feature, every thread caches a few free cells
+//! of every class (see `Cache`), such that most allocations and frees don't take the lock either.
+
+use prelude::*;
+
+use core::{cmp, mem, ptr};
+#[cfg(feature = "tls")]
+use core::cell::{Cell, UnsafeCell};
+
+use shim::config;
+
+use atomic::{self, AtomicPtr, AtomicUsize};
+use bookkeeper::HeapError;
+use fail::{self, AllocErr};
+use ptr::with_addr;
+use {allocator, conf, layout, sort, sync};
+#[cfg(feature = "tls")]
+use tls;
+#[cfg(all(feature = "security", feature = "tls"))]
+use secure;
+use class::{SizeClass, COUNT as CLASS_COUNT, SIZES as CLASSES, MAX_SIZE};
+
+/// The largest alignment served by the slabs.
+///
+/// Every class size is a multiple of this, such that every cell is aligned to it.
+pub const MAX_ALIGN: usize = 16;
+
+/// The number of words in the cell bitmap.
+///
+/// This is large enough for the cells of the smallest class.
+const BITMAP_WORDS: usize = config::SLAB_SIZE / 16 / 64;
+
+/// The partial list of slabs with plenty of free cells.
+const SPACE: usize = 0;
+/// The partial list of slabs with only a few free cells left.
+///
+/// Allocation prefers the slabs with space, such that the search rarely touches an almost full
+/// bitmap.
+const NEARLY_FULL: usize = 1;
+
+/// The slabs.
+static SLABS: sync::Mutex<Slabs> = sync::Mutex::ranked("slabs", sync::rank::FRONT_END, Slabs::new());
+
+/// The number of bits in an offset into a slab.
+const SLAB_BITS: usize = 14;
+/// The number of bits in the addresses.
+#[cfg(target_pointer_width = "64")]
+const ADDRESS_BITS: usize = 48;
+/// The number of bits in the addresses.
+#[cfg(not(target_pointer_width = "64"))]
+const ADDRESS_BITS: usize = 32;
+/// The number of bits in a word of the slab map.
+#[cfg(target_pointer_width = "64")]
+const WORD_BITS: usize = 64;
+/// The number of bits in a word of the slab map.
+#[cfg(not(target_pointer_width = "64"))]
+const WORD_BITS: usize = 32;
+/// The number of bits indexing a slab within a leaf of the slab map.
+const LEAF_BITS: usize = 18;
+/// The number of bits indexing the root of the slab map.
+const ROOT_BITS: usize = ADDRESS_BITS - SLAB_BITS - LEAF_BITS;
+/// The number of words in a leaf of the slab map.
+const LEAF_WORDS: usize = (1 << LEAF_BITS) / WORD_BITS;
+/// The number of entries in the root of the slab map.
+const ROOT_LEN: usize = 1 << ROOT_BITS;
+
+/// The root of the slab map.
+///
+/// Every entry points to a leaf, a bitmap with a bit set for every slab in its part of the address
+/// space, or is null, if no slab was ever placed there. The map is only modified behind the slab
+/// lock, but read without it. Leaves are never freed.
+static mut MAP: [*mut AtomicUsize; ROOT_LEN] = [0 as *mut AtomicUsize; ROOT_LEN];
+
+/// Get the root entry of the slab map at `n`.
+fn map_root(n: usize) -> &'static AtomicPtr<AtomicUsize> {
+    unsafe {
+        // `AtomicPtr` has the same layout as a raw pointer, and the root is only accessed
+        // atomically.
+        &*(&MAP[n] as *const *mut AtomicUsize as *const AtomicPtr<AtomicUsize>)
+    }
+}
+
+/// Split the address of a slab into its root index, word index and bit in the slab map.
+///
+/// If the address is outside the map, `None` is returned.
+fn map_split(addr: usize) -> Option<(usize, usize, usize)> {
+    if addr.checked_shr(ADDRESS_BITS as u32).map_or(false, |x| x != 0) {
+        return None;
+    }
+
+    let key = addr >> SLAB_BITS;
+    let bit = key & ((1 << LEAF_BITS) - 1);
+    Some((key >> LEAF_BITS, bit / WORD_BITS, 1 << (bit % WORD_BITS)))
+}
+
+/// Is some pointer in a slab?
+///
+/// This doesn't take the slab lock. A slab might be released right after, so the answer is only
+/// reliable for pointers to cells, which are allocated (or cached), keeping the slab alive.
+pub fn is_slab(ptr: *mut u8) -> bool {
+    let (n, word, bit) = match map_split(ptr as usize) {
+        Some(x) => x,
+        None => return false,
+    };
+
+    let leaf = map_root(n).load(atomic::Ordering::Acquire);
+    !leaf.is_null() && unsafe {
+        // Leaves are arrays of `LEAF_WORDS` words, which are never freed.
+        (*leaf.offset(word as isize)).load(atomic::Ordering::Acquire) & bit != 0
+    }
+}
+
+/// Set or clear the bit of a slab in the slab map.
+///
+/// This must only be called behind the slab lock, which serializes the writers.
+fn map_mark(slab: *mut Header, set: bool) {
+    let (n, word, bit) = map_split(slab as usize).unwrap_or_else(|| {
+        // Logging.
+        log!(ERROR, "The slab {:?} is outside the address space of the slab map.", slab);
+
+        fail::corruption(slab as *mut u8)
+    });
+
+    let mut leaf = map_root(n).load(atomic::Ordering::Acquire);
+    if leaf.is_null() {
+        let mut block = allocator::pool_alloc(LEAF_WORDS * mem::size_of::<usize>(),
+                                              Align::of::<usize>());
+        // An empty leaf has no bits set.
+        block.wipe();
+        leaf = *Pointer::from(block) as *mut AtomicUsize;
+
+        // The zeroes are published along with the leaf.
+        map_root(n).store(leaf, atomic::Ordering::Release);
+    }
+
+    unsafe {
+        // Leaves are arrays of `LEAF_WORDS` words, which are never freed. The writers are
+        // serialized by the lock, so loading and storing the word loses no update.
+        let word = &*leaf.offset(word as isize);
+        let old = word.load(atomic::Ordering::Relaxed);
+        // The header is published along with the bit.
+        word.store(if set { old | bit } else { old & !bit }, atomic::Ordering::Release);
+    }
+}
+
+/// The header of a slab.
+///
+/// This is placed in the start of the slab.
+#[repr(C)]
+struct Header {
+    /// The size class of the slab.
+    class: usize,
+    /// The colour of the slab.
+    ///
+    /// The cells are shifted by this many bytes, a multiple of `MAX_ALIGN`.
+    colour: usize,
+    /// The number of allocated cells.
+    used: usize,
+    /// The index of the first bitmap word which might have free cells.
+    ///
+    /// Every word before this is full.
+    hint: usize,
+    /// The previous slab in the partial list of the class.
+    prev: *mut Header,
+    /// The next slab in the partial list of the class.
+    next: *mut Header,
+    /// The cell bitmap.
+    ///
+    /// A set bit denotes an allocated cell.
+    bitmap: [u64; BITMAP_WORDS],
+}
+
+impl Header {
+    /// Find the first free cell.
+    ///
+    /// The search starts at the hint, and tests a whole word at a time.
+    fn find_free(&mut self) -> Option<usize> {
+        for n in self.hint..BITMAP_WORDS {
+            let word = self.bitmap[n];
+            if word != !0 {
+                self.hint = n;
+                let cell = n * 64 + (!word).trailing_zeros() as usize;
+
+                // The bits past the last cell are never set, so the search ends here.
+                return if cell < cells(self.class) { Some(cell) } else { None };
+            }
+        }
+
+        self.hint = BITMAP_WORDS;
+        None
+    }
+
+    /// Is the cell allocated?
+    fn is_set(&self, cell: usize) -> bool {
+        self.bitmap[cell / 64] & (1 << (cell % 64)) != 0
+    }
+
+    /// Flip the allocation bit of a cell.
+    fn flip(&mut self, cell: usize) {
+        self.bitmap[cell / 64] ^= 1 << (cell % 64);
+    }
+
+    /// Get the partial list the slab belongs in.
+    ///
+    /// Full slabs belong in no list.
+    fn list(&self) -> Option<usize> {
+        let free = cells(self.class) - self.used;
+
+        if free == 0 {
+            None
+        } else if free <= cells(self.class) / 8 {
+            Some(NEARLY_FULL)
+        } else {
+            Some(SPACE)
+        }
+    }
+}
+
+/// The offset of the first cell in a slab.
+#[inline]
+fn first_cell() -> usize {
+    layout::round_up(mem::size_of::<Header>(), MAX_ALIGN)
+}
+
+/// The number of cells in a slab of some class.
+#[inline]
+fn cells(class: usize) -> usize {
+    (config::SLAB_SIZE - first_cell()) / CLASSES[class]
+}
+
+/// The number of colours of the slabs of some class.
+///
+/// The colours are the offsets (in steps of `MAX_ALIGN`) fitting in the slack after the cells.
+#[inline]
+fn colours(class: usize) -> usize {
+    (config::SLAB_SIZE - first_cell() - cells(class) * CLASSES[class]) / MAX_ALIGN + 1
+}
+
+/// The offset of some cell from the start of its slab.
+#[inline]
+fn cell_offset(class: usize, colour: usize, cell: usize) -> usize {
+    first_cell() + colour + cell * CLASSES[class]
+}
+
+/// The index of the cell at some offset from the start of its slab.
+///
+/// If the offset is not the start of a cell, `None` is returned.
+#[inline]
+fn cell_index(class: usize, colour: usize, offset: usize) -> Option<usize> {
+    // Offsets into the header or the colour wrap around, and end up past the last cell.
+    let offset = offset.wrapping_sub(first_cell() + colour);
+
+    if offset % CLASSES[class] == 0 && offset / CLASSES[class] < cells(class) {
+        Some(offset / CLASSES[class])
+    } else {
+        None
+    }
+}
+
+/// The base address of the slab containing some address (if it is in a slab).
+#[inline]
+fn base_of(addr: usize) -> usize {
+    addr & !(config::SLAB_SIZE - 1)
+}
+
+/// Get the header of the slab containing some pointer (if it is in a slab).
+///
+/// The header is derived from the pointer, keeping its provenance.
+#[inline]
+fn slab_of(ptr: *mut u8) -> *mut Header {
+    with_addr(ptr, base_of(ptr as usize)) as *mut Header
+}
+
+/// Get the index of the cell starting at some pointer in a slab.
+///
+/// If the pointer is not the start of a cell (e.g. a partial free), the heap is corrupt.
+fn cell_of(header: &Header, ptr: *mut u8) -> usize {
+    cell_index(header.class, header.colour, ptr as usize - base_of(ptr as usize))
+        .unwrap_or_else(|| {
+            // Logging.
+            log!(ERROR, "Freeing {:?}, which is not the start of a slab cell.", ptr);
+
+            fail::corruption(ptr)
+        })
+}
+
+/// Get the size class of some size and alignment.
+///
+/// If the request isn't served by the slabs, `None` is returned.
+pub fn class_of(size: usize, align: Align) -> Option<usize> {
+    if size == 0 || size > MAX_SIZE || align.get() > MAX_ALIGN {
+        None
+    } else {
+        Some(SizeClass::of(size).index())
+    }
+}
+
+/// The slab state.
+struct Slabs {
+    /// The partial lists (`SPACE` and `NEARLY_FULL`), for each class.
+    partial: [[*mut Header; 2]; CLASS_COUNT],
+    /// The number of empty slabs, for each class.
+    empty: [usize; CLASS_COUNT],
+    /// The colour of the next slab, for each class.
+    colour: [usize; CLASS_COUNT],
+    /// The generation of the last allocation, for each class.
+    touched: [usize; CLASS_COUNT],
+    /// The current generation.
+    ///
+    /// This advances every `config::SLAB_GENERATION` allocations.
+    generation: usize,
+    /// The number of allocations made.
+    allocations: usize,
+    /// Every slab, sorted by address.
+    registry: Vec<*mut Header>,
+    /// The number of bytes in allocated cells.
+    used_bytes: usize,
+}
+
+// The headers are only accessed behind the slab lock.
+unsafe impl Send for Slabs {}
+
+impl Slabs {
+    /// Create a new slab state with no slabs.
+    const fn new() -> Slabs {
+        Slabs {
+            partial: [[ptr::null_mut(); 2]; CLASS_COUNT],
+            empty: [0; CLASS_COUNT],
+            colour: [0; CLASS_COUNT],
+            touched: [0; CLASS_COUNT],
+            generation: 0,
+            allocations: 0,
+            registry: Vec::new(),
+            used_bytes: 0,
+        }
+    }
+
+    /// Allocate a cell of some class.
+    fn alloc(&mut self, class: usize) -> Block {
+        self.touched[class] = self.generation;
+        self.allocations = self.allocations.wrapping_add(1);
+        if self.allocations % config::SLAB_GENERATION == 0 {
+            self.advance();
+        }
+
+        let slab = if !self.partial[class][SPACE].is_null() {
+            self.partial[class][SPACE]
+        } else if !self.partial[class][NEARLY_FULL].is_null() {
+            self.partial[class][NEARLY_FULL]
+        } else {
+            self.new_slab(class)
+        };
+
+        let res = unsafe {
+            // The slabs of the partial lists are live and only accessed behind the lock.
+            let header = &mut *slab;
+            let list = header.list();
+
+            let cell = header.find_free().expect("Slab in partial list has no free cells.");
+            header.flip(cell);
+            header.used += 1;
+
+            // The slab is no longer empty.
+            if header.used == 1 {
+                self.empty[class] -= 1;
+            }
+            self.relist(slab, list);
+
+            Block::from_raw_parts(Pointer::new((slab as *mut u8)
+                                  .offset(cell_offset(class, header.colour, cell) as isize)),
+                                  CLASSES[class])
+        };
+
+        self.used_bytes += CLASSES[class];
+        self.check();
+
+        res.mark_uninitialized()
+    }
+
+    /// Free a cell.
+    ///
+    /// If the pointer isn't in a slab, `Err(())` is returned.
+    fn free(&mut self, ptr: *mut u8) -> Result<(), ()> {
+        let slab = slab_of(ptr);
+        if self.registry.binary_search(&slab).is_err() {
+            return Err(());
+        }
+
+        unsafe {
+            // The slab is registered, hence live.
+            let header = &mut *slab;
+            let class = header.class;
+            let size = CLASSES[class];
+
+            // Find the cell.
+            let cell = cell_of(header, ptr);
+            if !header.is_set(cell) {
+                // Logging.
+                log!(ERROR, "Double free of slab cell {:?}.", ptr);
+
+                fail::corruption(ptr);
+            }
+
+            // Zero and release the cell.
+            // The slack of the cell might be poisoned, so we unpoison it before zeroing.
+            let mut block = Block::from_raw_parts(Pointer::new(ptr), size).mark_uninitialized();
+            block.sec_zero();
+            // The cell is tracked by the bitmap, so the block itself is forgotten.
+            let _ = block.mark_free();
+
+            let list = header.list();
+
+            header.flip(cell);
+            header.used -= 1;
+            self.used_bytes -= size;
+            // The word of the cell might have been full, in which case it is now the first word
+            // with free cells.
+            header.hint = cmp::min(header.hint, cell / 64);
+
+            self.relist(slab, list);
+
+            if header.used == 0 {
+                // Keep a few empty slabs, so that a workload freeing and allocating around a slab
+                // boundary doesn't create and destroy slabs over and over.
+                if self.empty[class] < config::SLAB_EMPTY_KEEP {
+                    self.empty[class] += 1;
+                } else {
+                    self.release(slab);
+                }
+            }
+        }
+
+        self.check();
+
+        Ok(())
+    }
+
+    /// Create a new empty slab and put it in the partial list of its class.
+    fn new_slab(&mut self, class: usize) -> *mut Header {
+        log!(DEBUG, "Creating a new slab of class {} (size {}).", class, CLASSES[class]);
+
+        // We are on the slow path anyway.
+        self.decay();
+
+        let block = allocator::pool_alloc(config::SLAB_SIZE, Align::new(config::SLAB_SIZE)
+            .expect("The slab size is not a power of two."));
+        let slab = *Pointer::from(block) as *mut Header;
+
+        // Cycle through the colours.
+        let colour = self.colour[class];
+        self.colour[class] = (colour + 1) % colours(class);
+
+        unsafe {
+            // The block was just allocated for this slab, and is large enough and aligned for the
+            // header.
+            ptr::write(slab, Header {
+                class: class,
+                colour: colour * MAX_ALIGN,
+                used: 0,
+                hint: 0,
+                prev: ptr::null_mut(),
+                next: ptr::null_mut(),
+                bitmap: [0; BITMAP_WORDS],
+            });
+        }
+
+        self.register(slab);
+        self.link(slab);
+        self.empty[class] += 1;
+
+        slab
+    }
+
+    /// Return an empty slab to the pool.
+    fn release(&mut self, slab: *mut Header) {
+        log!(DEBUG, "Releasing slab at {:?}.", slab);
+
+        // Empty slabs are always in the list of slabs with space.
+        self.unlink(slab, SPACE);
+        self.unregister(slab);
+
+        allocator::pool_free(unsafe {
+            // The slab is unreachable now, so we own the block.
+            Block::from_raw_parts(Pointer::new(slab as *mut u8), config::SLAB_SIZE)
+        });
+    }
+
+    /// Move a slab to the partial list it belongs in, given the list it is currently in.
+    fn relist(&mut self, slab: *mut Header, old: Option<usize>) {
+        let new = unsafe {
+            // The slab is live.
+            (*slab).list()
+        };
+
+        if old != new {
+            if let Some(old) = old {
+                self.unlink(slab, old);
+            }
+            if new.is_some() {
+                self.link(slab);
+            }
+        }
+    }
+
+    /// Return every empty slab to the pool.
+    ///
+    /// The number of bytes returned is returned.
+    fn release_empty(&mut self) -> usize {
+        let mut released = 0;
+        let mut n = 0;
+
+        while n < self.registry.len() {
+            let slab = self.registry[n];
+            let Header { class, used, .. } = unsafe {
+                // Registered slabs are live.
+                *slab
+            };
+
+            if used == 0 {
+                // This unregisters the slab, moving the next one to index `n`.
+                self.empty[class] -= 1;
+                self.release(slab);
+                released += config::SLAB_SIZE;
+            } else {
+                n += 1;
+            }
+        }
+
+        self.check();
+
+        released
+    }
+
+    /// Advance the generation.
+    fn advance(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        self.decay();
+    }
+
+    /// Return the empty slabs of the first idle class to the pool.
+    ///
+    /// A class is idle if it hasn't allocated for `conf::slab_decay()` generations. At most one
+    /// class is flushed, to bound the latency.
+    fn decay(&mut self) {
+        let limit = conf::slab_decay();
+        if limit == 0 {
+            return;
+        }
+
+        let class = match (0..CLASS_COUNT).find(|&class| {
+            self.empty[class] > 0 && self.generation.wrapping_sub(self.touched[class]) >= limit
+        }) {
+            Some(class) => class,
+            None => return,
+        };
+
+        // Logging.
+        log!(DEBUG, "Flushing the {} empty slabs of idle class {}.", self.empty[class], class);
+
+        // Empty slabs are always in the list of slabs with space.
+        let mut slab = self.partial[class][SPACE];
+        while !slab.is_null() {
+            let Header { used, next, .. } = unsafe {
+                // The slabs of the partial lists are live.
+                *slab
+            };
+
+            if used == 0 {
+                self.empty[class] -= 1;
+                self.release(slab);
+            }
+
+            slab = next;
+        }
+    }
+
+    /// Add a slab to the partial list it belongs in.
+    ///
+    /// The slab goes to the front of the list, unless the slabs are reused in address order (see
+    /// `conf::set_address_ordered`), in which case it goes past the slabs below it, scanning at
+    /// most `config::SLAB_ORDER_SCAN` of them.
+    fn link(&mut self, slab: *mut Header) {
+        unsafe {
+            // The slab and the slabs of the partial list are live.
+            let class = (*slab).class;
+            let list = (*slab).list().expect("Linking a full slab.");
+
+            // Find the slab to link after, if any.
+            let mut prev = ptr::null_mut();
+            let mut next = self.partial[class][list];
+            if conf::address_ordered() {
+                for _ in 0..config::SLAB_ORDER_SCAN {
+                    if next.is_null() || next > slab { break; }
+
+                    prev = next;
+                    next = (*next).next;
+                }
+            }
+
+            (*slab).prev = prev;
+            (*slab).next = next;
+            if !next.is_null() {
+                (*next).prev = slab;
+            }
+            if prev.is_null() {
+                self.partial[class][list] = slab;
+            } else {
+                (*prev).next = slab;
+            }
+        }
+    }
+
+    /// Remove a slab from some partial list of its class.
+    fn unlink(&mut self, slab: *mut Header, list: usize) {
+        unsafe {
+            // The slab and its neighbors are live.
+            let Header { class, prev, next, .. } = *slab;
+
+            if prev.is_null() {
+                self.partial[class][list] = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+
+            (*slab).prev = ptr::null_mut();
+            (*slab).next = ptr::null_mut();
+        }
+    }
+
+    /// Register a slab.
+    fn register(&mut self, slab: *mut Header) {
+        if self.registry.push(slab).is_err() {
+            // The registry is full, so we move it to a bigger buffer.
+            let cap = cmp::max(2 * self.registry.capacity(), 32);
+            let layout = layout::array::<*mut Header>(cap)
+                .unwrap_or_else(|_| fail::oom(AllocErr::LimitReached));
+            let block = allocator::pool_alloc(layout.size(), Align::of::<*mut Header>());
+            // The old buffer holds the addresses of the slabs, so it is wiped before it is freed.
+            let mut old = self.registry.refill(block);
+            if !old.is_empty() {
+                old.wipe();
+                allocator::pool_free(old);
+            }
+
+            self.registry.push(slab).expect("Refilled registry is still full.");
+        }
+        map_mark(slab, true);
+
+        // Move the slab into place.
+        sort::place_last(&mut self.registry, |&x| x);
+    }
+
+    /// Unregister a slab.
+    fn unregister(&mut self, slab: *mut Header) {
+        let n = self.registry.binary_search(&slab).expect("Unregistering an unknown slab.");
+        let len = self.registry.len();
+
+        sort::shift_out(&mut self.registry, n);
+        self.registry.truncate(len - 1);
+        map_mark(slab, false);
+    }
+
+    /// Perform consistency checks.
+    ///
+    /// This only checks in debug mode, and panics on an inconsistency (see `validate`).
+    fn check(&self) {
+        if cfg!(debug_assertions) {
+            if let Err(err) = self.validate() {
+                panic!("The slabs are inconsistent: {:?}.", err);
+            }
+        }
+    }
+
+    /// Validate the slabs.
+    ///
+    /// This will check for the following conditions:
+    ///
+    /// 1. The registry is sorted and contains no duplicates, and every slab is in the slab map.
+    /// 2. Every slab is aligned and has a valid class and colour.
+    /// 3. The cell counts match the bitmaps, with no bits set past the last cell, and every word
+    ///    before the hint is full.
+    /// 4. The partial lists contain exactly the non-full slabs of their class, each in the right
+    ///    list, and are properly linked.
+    /// 5. The empty slab counts and the number of bytes in use are correct.
+    ///
+    /// The first inconsistency found is logged and returned.
+    fn validate(&self) -> Result<(), HeapError> {
+        /// Return `HeapError::Slab` of some slab, logging a message, unless a condition holds.
+        macro_rules! ensure {
+            ($cond:expr, $slab:expr, $( $arg:expr ),*) => {
+                if !$cond {
+                    log!(ERROR, $( $arg ),*);
+
+                    return Err(HeapError::Slab($slab as usize));
+                }
+            };
+        }
+
+        let mut used_bytes = 0;
+        let mut partial = [0; CLASS_COUNT];
+        let mut empty = [0; CLASS_COUNT];
+
+        for (n, &slab) in self.registry.iter().enumerate() {
+            let addr = slab as usize;
+            ensure!(n == 0 || self.registry[n - 1] < slab, addr,
+                    "The slab registry is not sorted.");
+            ensure!(base_of(addr) == addr, addr, "Slab {:x} is not aligned.", addr);
+            ensure!(is_slab(slab as *mut u8), addr, "Slab {:x} is not in the slab map.", addr);
+
+            let header = unsafe {
+                // Registered slabs are live.
+                &*slab
+            };
+            ensure!(header.class < CLASS_COUNT, addr, "Slab {:x} has invalid class {}.", addr,
+                    header.class);
+            ensure!(header.colour % MAX_ALIGN == 0
+                    && header.colour / MAX_ALIGN < colours(header.class), addr,
+                    "Slab {:x} has invalid colour {}.", addr, header.colour);
+
+            let used = header.bitmap.iter().map(|x| x.count_ones() as usize).sum::<usize>();
+            ensure!(used == header.used, addr, "Slab {:x} has {} cells set in its bitmap, but \
+                    claims {}.", addr, used, header.used);
+            ensure!((cells(header.class)..BITMAP_WORDS * 64).all(|x| !header.is_set(x)), addr,
+                    "Slab {:x} has bits set past its last cell.", addr);
+            let hint = cmp::min(header.hint, BITMAP_WORDS);
+            ensure!(header.bitmap[..hint].iter().all(|&x| x == !0), addr,
+                    "Slab {:x} has free cells before its hint.", addr);
+
+            used_bytes += header.used * CLASSES[header.class];
+            if header.list().is_some() {
+                partial[header.class] += 1;
+            }
+            if header.used == 0 {
+                empty[header.class] += 1;
+            }
+        }
+
+        for class in 0..CLASS_COUNT {
+            let mut len = 0;
+
+            for list in 0..2 {
+                let mut prev = ptr::null_mut();
+                let mut slab = self.partial[class][list];
+
+                while !slab.is_null() {
+                    ensure!(self.registry.binary_search(&slab).is_ok(), slab, "Slab {:?} in \
+                            partial list is not registered.", slab);
+
+                    let header = unsafe {
+                        // The slab is registered, hence live.
+                        &*slab
+                    };
+
+                    ensure!(header.class == class, slab, "Slab {:?} of class {} is in the \
+                            partial list of class {}.", slab, header.class, class);
+                    ensure!(header.list() == Some(list), slab, "Slab {:?} is in partial list {}, \
+                            but belongs in {:?}.", slab, list, header.list());
+                    ensure!(header.prev == prev, slab, "Broken partial list link at {:?}.", slab);
+
+                    len += 1;
+                    prev = slab;
+                    slab = header.next;
+                }
+            }
+
+            ensure!(len == partial[class], 0, "The partial list of class {} has {} slabs, but {} \
+                    slabs have free cells.", class, len, partial[class]);
+            ensure!(empty[class] == self.empty[class], 0, "Class {} has {} empty slabs, but \
+                    claims {}.", class, empty[class], self.empty[class]);
+        }
+
+        ensure!(used_bytes == self.used_bytes, 0, "{} bytes are allocated in slabs, but {} are \
+                claimed.", used_bytes, self.used_bytes);
+
+        Ok(())
+    }
+}
+
+/// The state of the cell cache of a thread.
+#[cfg(feature = "tls")]
+#[derive(Clone, Copy)]
+enum State {
+    /// The cache is unused, and its destructor is not registered yet.
+    Fresh,
+    /// The cache is ready.
+    Idle,
+    /// The cache is in use, so a reentrant call must go through the lock.
+    Busy,
+    /// The thread is exiting, and the cache was flushed for good.
+    Dead,
+}
+
+/// The cell cache of a thread.
+///
+/// Cached cells stay allocated in the bitmaps of their slabs, so the slabs can't see them. They
+/// are taken from the slabs and given back in batches of half the cache, behind one lock.
+#[cfg(feature = "tls")]
+struct Cache {
+    /// The state of the cache.
+    state: Cell<State>,
+    /// The cached cells.
+    cells: UnsafeCell<Cells>,
+}
+
+/// The cached cells of a thread.
+#[cfg(feature = "tls")]
+struct Cells {
+    /// The free cells, for each class.
+    cells: [[*mut u8; config::SLAB_CACHE]; CLASS_COUNT],
+    /// The number of cached cells, for each class.
+    len: [usize; CLASS_COUNT],
+}
+
+#[cfg(feature = "tls")]
+tls! {
+    /// The cell cache of the current thread.
+    static CACHE: Cache = Cache {
+        state: Cell::new(State::Fresh),
+        cells: UnsafeCell::new(Cells {
+            cells: [[0 as *mut u8; config::SLAB_CACHE]; CLASS_COUNT],
+            len: [0; CLASS_COUNT],
+        }),
+    };
+}
+
+#[cfg(feature = "tls")]
+impl Cells {
+    /// Take a cell of some class from the cache.
+    ///
+    /// If the cache of the class is empty, it is refilled from the slabs first.
+    fn pop(&mut self, class: usize) -> Block {
+        if self.len[class] == 0 {
+            let mut slabs = SLABS.lock();
+            while self.len[class] < config::SLAB_CACHE / 2 {
+                self.cells[class][self.len[class]] = *Pointer::from(slabs.alloc(class));
+                self.len[class] += 1;
+            }
+        }
+
+        self.len[class] -= 1;
+        let ptr = self.cells[class][self.len[class]];
+
+        // The slot is dead, so it is wiped.
+        #[cfg(feature = "security")]
+        unsafe {
+            // The slot is past the end of the cache, so it isn't read before it is overwritten.
+            secure::secure_wipe(&mut self.cells[class][self.len[class]]);
+        }
+
+        unsafe {
+            // The cell was allocated from the slabs, and is not used by anyone.
+            Block::from_raw_parts(Pointer::new(ptr), CLASSES[class]).mark_uninitialized()
+        }
+    }
+
+    /// Put a cell into the cache.
+    ///
+    /// If the cache of the class is full, the older half of it is given back to the slabs first.
+    fn push(&mut self, ptr: *mut u8) {
+        let (class, size) = unsafe {
+            // The pointer is in a slab, which is kept alive by the cell, if it is allocated. If it
+            // isn't, the cell is caught as a double free by the slabs, when it is flushed.
+            let header = &*slab_of(ptr);
+            cell_of(header, ptr);
+
+            (header.class, CLASSES[header.class])
+        };
+
+        if self.cells[class][..self.len[class]].contains(&ptr) {
+            // Logging.
+            log!(ERROR, "Double free of slab cell {:?}.", ptr);
+
+            fail::corruption(ptr);
+        }
+
+        if self.len[class] == config::SLAB_CACHE {
+            self.flush(class, config::SLAB_CACHE / 2);
+        }
+
+        let mut block = unsafe {
+            // The cell is given to the cache, so nobody else uses it.
+            Block::from_raw_parts(Pointer::new(ptr), size).mark_uninitialized()
+        };
+        // Cached cells are zeroed like the cells of the slabs.
+        block.sec_zero();
+        let _ = block.mark_free();
+
+        self.cells[class][self.len[class]] = ptr;
+        self.len[class] += 1;
+    }
+
+    /// Give the `n` oldest cells of some class back to the slabs.
+    fn flush(&mut self, class: usize, n: usize) {
+        let mut slabs = SLABS.lock();
+
+        for &ptr in &self.cells[class][..n] {
+            if slabs.free(ptr).is_err() {
+                fail::corruption(ptr);
+            }
+        }
+
+        let len = self.len[class];
+        for i in n..len {
+            self.cells[class][i - n] = self.cells[class][i];
+        }
+        self.len[class] = len - n;
+
+        // The slots past the end are dead, so they are wiped.
+        #[cfg(feature = "security")]
+        for slot in &mut self.cells[class][len - n..len] {
+            unsafe {
+                // The slot is past the end of the cache, so it isn't read before it is
+                // overwritten.
+                secure::secure_wipe(slot);
+            }
+        }
+    }
+
+    /// Give every cell back to the slabs.
+    fn flush_all(&mut self) {
+        for class in 0..CLASS_COUNT {
+            let len = self.len[class];
+            if len > 0 {
+                self.flush(class, len);
+            }
+        }
+    }
+}
+
+/// Run `f` on the cell cache of the current thread.
+///
+/// If the cache is busy (i.e. this is a reentrant call) or the thread is exiting, `None` is
+/// returned, and the caller goes through the lock.
+#[cfg(feature = "tls")]
+fn with_cache<F, R>(f: F) -> Option<R>
+    where F: FnOnce(&mut Cells) -> R {
+    /// The destructor of the cache.
+    ///
+    /// This gives the cells back to the slabs, and marks the cache dead, such that later calls
+    /// on this thread go through the lock.
+    extern fn dtor(cache: &Cache) {
+        // Logging.
+        log!(NOTE, "Flushing the slab cell cache of the thread.");
+
+        cache.state.set(State::Dead);
+        unsafe {
+            // The cache is dead, so nothing else accesses the cells.
+            (*cache.cells.get()).flush_all();
+        }
+    }
+
+    CACHE.with(|cache| {
+        match cache.state.get() {
+            State::Busy | State::Dead => return None,
+            State::Fresh => CACHE.register_thread_destructor(dtor),
+            State::Idle => (),
+        }
+
+        cache.state.set(State::Busy);
+        let res = f(unsafe {
+            // The cache is busy, so this is the only reference to the cells.
+            &mut *cache.cells.get()
+        });
+        cache.state.set(State::Idle);
+
+        Some(res)
+    })
+}
+
+/// Allocate a block from the slabs.
+///
+/// If the request isn't served by the slabs, `None` is returned.
+pub fn alloc(size: usize, align: Align) -> Option<Block> {
+    class_of(size, align).map(|class| {
+        // With address-ordered reuse, the cache is skipped, since it hands out the cells in LIFO
+        // order.
+        #[cfg(feature = "tls")]
+        {
+            if !conf::address_ordered() {
+                if let Some(block) = with_cache(|cells| cells.pop(class)) {
+                    return block;
+                }
+            }
+        }
+
+        SLABS.lock().alloc(class)
+    })
+}
+
+/// Free a slab cell.
+///
+/// If the pointer isn't in a slab, `Err(())` is returned, and the buffer should be freed to the
+/// pool. Slab cells cannot be partially freed, and doing so is reported as corruption.
+pub fn free(ptr: *mut u8, size: usize) -> Result<(), ()> {
+    // Larger buffers cannot be slab cells, so we can avoid looking them up.
+    if size > MAX_SIZE || !is_slab(ptr) {
+        return Err(());
+    }
+
+    #[cfg(feature = "tls")]
+    {
+        if !conf::address_ordered() && with_cache(|cells| cells.push(ptr)).is_some() {
+            return Ok(());
+        }
+    }
+
+    SLABS.lock().free(ptr)
+}
+
+/// Get the size of the slab cell starting at `ptr`.
+///
+/// If the pointer isn't in a slab, `None` is returned. This doesn't take the slab lock, so the
+/// pointer must be an allocated cell, if it is in a slab at all.
+pub fn cell_size(ptr: *mut u8, size: usize) -> Option<usize> {
+    if size > MAX_SIZE || !is_slab(ptr) {
+        None
+    } else {
+        Some(CLASSES[unsafe {
+            // The cell is allocated, which keeps its slab alive.
+            (*slab_of(ptr)).class
+        }])
+    }
+}
+
+/// Return every empty slab to the pool.
+///
+/// The cell cache of the current thread is flushed first. The number of bytes returned is
+/// returned.
+pub fn release_empty() -> usize {
+    #[cfg(feature = "tls")]
+    with_cache(Cells::flush_all);
+
+    SLABS.lock().release_empty()
+}
+
+/// Get the number of slabs.
+pub fn count() -> usize {
+    SLABS.lock().registry.len()
+}
+
+/// Get the number of bytes in allocated slab cells.
+///
+/// The cells cached by the threads count as allocated.
+pub fn bytes() -> usize {
+    SLABS.lock().used_bytes
+}
+
+/// Check the consistency of the slabs.
+///
+/// This only checks in debug mode.
+pub fn check() {
+    SLABS.lock().check();
+}
+
+/// Validate the slabs.
+///
+/// The first inconsistency found is returned. See `validate_and_repair`.
+pub fn validate() -> Result<(), HeapError> {
+    SLABS.lock().validate()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use prelude::*;
+
+    use core::{cmp, ptr};
+
+    use allocator;
+    use class::{COUNT as CLASS_COUNT, SIZES as CLASSES, MAX_SIZE};
+    use shim::config;
+
+    /// Get the alignment `n`.
+    fn align(n: usize) -> Align {
+        Align::new(n).unwrap()
+    }
+
+    #[test]
+    fn test_class_of() {
+        assert_eq!(class_of(0, align(1)), None);
+        assert_eq!(class_of(1, align(1)), Some(0));
+        assert_eq!(class_of(16, align(16)), Some(0));
+        assert_eq!(class_of(17, align(8)), Some(1));
+        assert_eq!(class_of(500, align(4)), Some(CLASS_COUNT - 1));
+        assert_eq!(class_of(512, align(1)), Some(CLASS_COUNT - 1));
+        assert_eq!(class_of(513, align(1)), None);
+        assert_eq!(class_of(16, align(32)), None);
+    }
+
+    #[test]
+    fn test_layout() {
+        // The slabs serve every request not asking for more than the minimal alignment.
+        assert!(MAX_ALIGN >= allocator::MIN_ALIGN);
+        assert_eq!(1 << SLAB_BITS, config::SLAB_SIZE);
+        assert!(first_cell() % MAX_ALIGN == 0);
+        assert!(cells(0) <= BITMAP_WORDS * 64);
+
+        for class in 0..CLASS_COUNT {
+            assert!(CLASSES[class] % MAX_ALIGN == 0);
+            assert!(colours(class) >= 1);
+
+            // The cells of every colour fit in the slab, and the next colour wouldn't.
+            let last = (colours(class) - 1) * MAX_ALIGN;
+            assert!(cell_offset(class, last, cells(class)) <= config::SLAB_SIZE);
+            assert!(cell_offset(class, last + MAX_ALIGN, cells(class)) > config::SLAB_SIZE);
+        }
+    }
+
+    #[test]
+    fn test_cell_index() {
+        for class in 0..CLASS_COUNT {
+            for colour in (0..colours(class)).map(|x| x * MAX_ALIGN) {
+                // Every cell maps to its address and back.
+                for cell in 0..cells(class) {
+                    let offset = cell_offset(class, colour, cell);
+                    assert_eq!(cell_index(class, colour, offset), Some(cell));
+                    assert_eq!(offset % MAX_ALIGN, 0);
+
+                    // Addresses inside the cell are not cell starts.
+                    for x in 1..CLASSES[class] {
+                        assert_eq!(cell_index(class, colour, offset + x), None);
+                    }
+                }
+
+                // Neither the header, the colour, nor the tail.
+                for offset in 0..first_cell() + colour {
+                    assert_eq!(cell_index(class, colour, offset), None);
+                }
+                for offset in cell_offset(class, colour, cells(class))..config::SLAB_SIZE {
+                    assert_eq!(cell_index(class, colour, offset), None);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_colours() {
+        let mut slabs = Slabs::new();
+        let class = class_of(512, align(1)).unwrap();
+        let n = colours(class) + 1;
+        assert!(n > 2);
+        let mut ptrs = [0 as *mut u8; 1024];
+        assert!(n * cells(class) <= ptrs.len());
+
+        // Fill a slab of every colour, and then one more.
+        for slab in 0..n {
+            for cell in 0..cells(class) {
+                let ptr = *Pointer::from(slabs.alloc(class));
+                ptrs[slab * cells(class) + cell] = ptr;
+
+                // The slabs cycle through the colours.
+                let colour = slab % colours(class) * MAX_ALIGN;
+                assert_eq!(ptr as usize - base_of(ptr as usize), cell_offset(class, colour, cell));
+            }
+        }
+        slabs.check();
+
+        // The cells of every colour are found when freed.
+        for &ptr in &ptrs[..n * cells(class)] {
+            slabs.free(ptr).unwrap();
+        }
+        slabs.check();
+        assert_eq!(slabs.release_empty(), config::SLAB_EMPTY_KEEP * config::SLAB_SIZE);
+        assert_eq!(slabs.registry.len(), 0);
+    }
+
+    #[test]
+    fn test_find_free() {
+        let mut header = Header {
+            class: 0,
+            colour: 0,
+            used: 0,
+            hint: 0,
+            prev: ptr::null_mut(),
+            next: ptr::null_mut(),
+            bitmap: [0; BITMAP_WORDS],
+        };
+
+        // Fill the slab in order.
+        for cell in 0..cells(0) {
+            assert_eq!(header.find_free(), Some(cell));
+            header.flip(cell);
+        }
+        assert_eq!(header.find_free(), None);
+
+        // Free a cell in a full word.
+        header.flip(130);
+        header.hint = cmp::min(header.hint, 130 / 64);
+        assert_eq!(header.find_free(), Some(130));
+    }
+
+    #[test]
+    fn test_lists() {
+        let class = class_of(512, align(1)).unwrap();
+        let mut ptrs = [0 as *mut u8; 64];
+        let n = cells(class);
+
+        // Fill a slab until it is nearly full and then full.
+        for ptr in &mut ptrs[..n] {
+            *ptr = *Pointer::from(alloc(512, align(1)).unwrap());
+        }
+        check();
+
+        for &ptr in &ptrs[..n] {
+            free(ptr, 512).unwrap();
+        }
+        check();
+    }
+
+    #[test]
+    fn test_alloc_free() {
+        let class = class_of(100, align(4)).unwrap();
+        let n = cells(class) + 3;
+        let mut ptrs = [0 as *mut u8; 256];
+
+        // Fill more than one slab.
+        for ptr in &mut ptrs[..n] {
+            let block = alloc(100, align(4)).unwrap();
+            assert!(block.aligned_to(align(MAX_ALIGN)));
+            assert_eq!(block.size(), CLASSES[class]);
+
+            *ptr = *Pointer::from(block);
+            assert_eq!(cell_size(*ptr, 100), Some(CLASSES[class]));
+
+            unsafe {
+                *ptr.offset(99) = 0xAB;
+            }
+        }
+
+        // The cells are distinct.
+        for i in 0..n {
+            for j in 0..i {
+                assert!(ptrs[i] != ptrs[j]);
+            }
+        }
+
+        for &ptr in &ptrs[..n] {
+            assert_eq!(unsafe { *ptr.offset(99) }, 0xAB);
+            free(ptr, 100).unwrap();
+        }
+
+        check();
+    }
+
+    #[test]
+    fn test_address_ordered() {
+        // The slabs of the other tests would be in the way, so the test has slabs of its own.
+        let mut slabs = Slabs::new();
+        let class = class_of(440, align(8)).unwrap();
+        let n = 3 * cells(class);
+        let mut ptrs = [0 as *mut u8; 256];
+
+        conf::set_address_ordered(true);
+
+        // Fill three slabs.
+        for ptr in &mut ptrs[..n] {
+            *ptr = *Pointer::from(slabs.alloc(class));
+        }
+
+        // Free a cell of every slab, the lowest slab first, such that it ends up last in LIFO
+        // order.
+        let mut freed = [0 as *mut u8; 3];
+        for (i, x) in freed.iter_mut().enumerate() {
+            *x = ptrs[i * cells(class)];
+        }
+        for i in 0..2 {
+            for j in 0..2 - i {
+                if freed[j] > freed[j + 1] {
+                    freed.swap(j, j + 1);
+                }
+            }
+        }
+        for &ptr in freed.iter() {
+            slabs.free(ptr).unwrap();
+        }
+        slabs.check();
+
+        // The lowest free cell is reused first.
+        let ptr = *Pointer::from(slabs.alloc(class));
+        assert_eq!(ptr, freed[0]);
+
+        conf::set_address_ordered(false);
+
+        slabs.free(ptr).unwrap();
+        for &ptr in ptrs[..n].iter().filter(|&&x| !freed.contains(&x)) {
+            slabs.free(ptr).unwrap();
+        }
+        slabs.check();
+
+        // Every slab is given back.
+        slabs.release_empty();
+        assert_eq!(slabs.registry.len(), 0);
+    }
+
+    #[test]
+    fn test_decay() {
+        let mut slabs = Slabs::new();
+        let mut ptrs = [0 as *mut u8; 256];
+
+        // A burst in a few classes, leaving an empty slab in each.
+        for &class in &[11, 13, 15] {
+            let n = cells(class) + 1;
+            for ptr in &mut ptrs[..n] {
+                *ptr = *Pointer::from(slabs.alloc(class));
+            }
+            for &ptr in &ptrs[..n] {
+                slabs.free(ptr).unwrap();
+            }
+        }
+        let cached = |slabs: &Slabs| slabs.empty.iter().fold(0, |acc, &x| acc + x);
+        assert_eq!(cached(&slabs), 3 * config::SLAB_EMPTY_KEEP);
+
+        // Idleness, with only one class in use.
+        let limit = conf::slab_decay();
+        for _ in 0..limit - 1 {
+            let ptr = *Pointer::from(slabs.alloc(0));
+            slabs.free(ptr).unwrap();
+            slabs.advance();
+        }
+        assert_eq!(cached(&slabs), 3 * config::SLAB_EMPTY_KEEP + 1);
+
+        // One class is flushed at a time.
+        slabs.advance();
+        assert_eq!(slabs.empty[11], 0);
+        assert_eq!(cached(&slabs), 2 * config::SLAB_EMPTY_KEEP + 1);
+        slabs.advance();
+        slabs.advance();
+        slabs.check();
+
+        // Only the class in use is left.
+        assert_eq!(cached(&slabs), 1);
+        assert_eq!(slabs.empty[0], 1);
+        assert_eq!(slabs.release_empty(), config::SLAB_SIZE);
+        assert_eq!(slabs.registry.len(), 0);
+    }
+
+    #[test]
+    fn test_foreign() {
+        let mut x = [0u8; 16];
+
+        assert_eq!(cell_size(x.as_mut_ptr(), 16), None);
+        assert!(free(x.as_mut_ptr(), 16).is_err());
+        assert!(free(x.as_mut_ptr(), MAX_SIZE + 1).is_err());
+        assert!(!is_slab(x.as_mut_ptr()));
+    }
+
+    #[test]
+    fn test_slab_map() {
+        let ptr = *Pointer::from(alloc(64, align(8)).unwrap());
+        assert!(is_slab(ptr));
+        assert!(is_slab(slab_of(ptr) as *mut u8));
+        assert!(map_split(!0).is_none() || ADDRESS_BITS == WORD_BITS);
+
+        free(ptr, 64).unwrap();
+        check();
+        assert_eq!(validate(), Ok(()));
+    }
+
+    #[test]
+    #[cfg(feature = "tls")]
+    fn test_cache() {
+        let mut cells = Cells {
+            cells: [[ptr::null_mut(); config::SLAB_CACHE]; CLASS_COUNT],
+            len: [0; CLASS_COUNT],
+        };
+        let class = class_of(48, align(16)).unwrap();
+
+        // The cache is refilled by half.
+        let ptr = *Pointer::from(cells.pop(class));
+        assert_eq!(cells.len[class], config::SLAB_CACHE / 2 - 1);
+
+        // The cell is reused first.
+        cells.push(ptr);
+        assert_eq!(cells.len[class], config::SLAB_CACHE / 2);
+        assert_eq!(*Pointer::from(cells.pop(class)), ptr);
+        cells.push(ptr);
+
+        // A cached cell is still allocated in its slab.
+        let header = unsafe { &*slab_of(ptr) };
+        assert!(header.is_set(cell_of(header, ptr)));
+
+        // A full cache gives back its older half.
+        for _ in 0..config::SLAB_CACHE / 2 {
+            let ptr = *Pointer::from(alloc(48, align(16)).unwrap());
+            cells.push(ptr);
+        }
+        assert_eq!(cells.len[class], config::SLAB_CACHE);
+        let ptr = *Pointer::from(alloc(48, align(16)).unwrap());
+        cells.push(ptr);
+        assert_eq!(cells.len[class], config::SLAB_CACHE / 2 + 1);
+
+        cells.flush_all();
+        assert!(cells.len.iter().all(|&x| x == 0));
+        check();
+    }
+
+    #[test]
+    #[cfg(not(feature = "security"))]
+    #[should_panic(expected = "Heap corruption detected")]
+    fn test_partial_free() {
+        let ptr = *Pointer::from(alloc(32, align(8)).unwrap());
+
+        let _ = free(unsafe { ptr.offset(8) }, 24);
+    }
+
+    #[test]
+    #[cfg(not(feature = "security"))]
+    #[should_panic(expected = "Heap corruption detected")]
+    fn test_double_free() {
+        // Keep another cell alive, so the slab isn't released.
+        let _live = alloc(32, align(8)).unwrap();
+        let ptr = *Pointer::from(alloc(32, align(8)).unwrap());
+
+        free(ptr, 32).unwrap();
+        free(ptr, 32).unwrap();
+    }
+}
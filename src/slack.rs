@@ -0,0 +1,314 @@
+//! Realloc slack.
+//!
+//! Buffers reallocated up and down repeatedly (e.g. the buffers of parsers) would give their tail
+//! back to the pool on every shrink, only to fight for it on the next growth. With the
+//! `realloc_slack` feature, a shrink freeing less than some share of the block (see
+//! `set_realloc_slack`) keeps the tail after the buffer instead. The next growth within the tail
+//! takes it back without touching the pool. The tail isn't usable by the buffer meanwhile, and
+//! `usable_size` leaves it out: it can be given back (and handed to another buffer) by another
+//! thread at any time, so writes into it would corrupt that buffer.
+//!
+//! The tails are kept in a fixed hash table keyed by the start of the block, so keeping them never
+//! allocates, and a free looks up a few slots at most. They are neither live nor free: The
+//! statistics count them as slack (see
+//! `stats::Stats::realloc_slack`). They are given back to the pool by `purge` and `maintenance`,
+//! when the heap cannot grow, and along with their buffer.
+
+use prelude::*;
+
+use core::cmp;
+
+use atomic::{self, AtomicUsize};
+
+use shim::config;
+
+use sync;
+
+/// The kept tails.
+static TABLE: sync::Mutex<Table> = sync::Mutex::ranked("realloc slack", sync::rank::SLACK,
+                                                       Table::new());
+/// The number of kept tails.
+///
+/// This lets the buffers skip the table, when nothing is kept.
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+/// The number of bytes in the kept tails.
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+/// The share of a block (in percent), below which the tail freed by a shrink is kept.
+static PERCENT: AtomicUsize = AtomicUsize::new(config::REALLOC_SLACK_PERCENT);
+
+/// The number of slots of the table.
+const CAPACITY: usize = config::REALLOC_SLACK_CAPACITY;
+
+/// A fixed hash table of kept tails.
+///
+/// The entries are placed by linear probing from the slot of their start (see `Table::slot`), and
+/// a probe stops at the first unused entry.
+struct Table {
+    /// The tails as `(start, size, tail)`, where `start` and `size` describe the block of the
+    /// buffer, which the tail of `tail` bytes follows.
+    ///
+    /// Unused entries have an empty tail.
+    entries: [(usize, usize, usize); CAPACITY],
+}
+
+impl Table {
+    /// Create a new empty table.
+    const fn new() -> Table {
+        Table {
+            entries: [(0, 0, 0); CAPACITY],
+        }
+    }
+
+    /// Get the slot, which the probe for the block starting at `start` begins at.
+    fn slot(start: usize) -> usize {
+        // The blocks are aligned, so the lowest bits carry nothing.
+        (start >> 3).wrapping_mul(0x9E3779B9) % CAPACITY
+    }
+
+    /// Find the entry of the block starting at `start`.
+    fn find(&self, start: usize) -> Option<usize> {
+        let mut n = Table::slot(start);
+        for _ in 0..CAPACITY {
+            let (x, _, tail) = self.entries[n];
+            if tail == 0 {
+                return None;
+            }
+            if x == start {
+                return Some(n);
+            }
+
+            n = (n + 1) % CAPACITY;
+        }
+
+        None
+    }
+
+    /// Insert an entry.
+    ///
+    /// If the table is full, `false` is returned.
+    fn insert(&mut self, entry: (usize, usize, usize)) -> bool {
+        let mut n = Table::slot(entry.0);
+        for _ in 0..CAPACITY {
+            if self.entries[n].2 == 0 {
+                self.entries[n] = entry;
+
+                COUNT.fetch_add(1, atomic::Ordering::Relaxed);
+                BYTES.fetch_add(entry.2, atomic::Ordering::Relaxed);
+
+                return true;
+            }
+
+            n = (n + 1) % CAPACITY;
+        }
+
+        false
+    }
+
+    /// Remove the entry at `n`, and get it.
+    ///
+    /// The entries probed past it are moved back into the hole, such that no probe stops short of
+    /// its entry.
+    fn remove(&mut self, n: usize) -> (usize, usize, usize) {
+        let entry = self.entries[n];
+
+        let mut hole = n;
+        let mut next = (n + 1) % CAPACITY;
+        while next != n && self.entries[next].2 != 0 {
+            // The entry stays, if its slot lies (cyclically) after the hole, up to itself.
+            let slot = Table::slot(self.entries[next].0);
+            let stays = if hole <= next {
+                hole < slot && slot <= next
+            } else {
+                hole < slot || slot <= next
+            };
+            if !stays {
+                self.entries[hole] = self.entries[next];
+                hole = next;
+            }
+
+            next = (next + 1) % CAPACITY;
+        }
+        self.entries[hole] = (0, 0, 0);
+
+        COUNT.fetch_sub(1, atomic::Ordering::Relaxed);
+        BYTES.fetch_sub(entry.2, atomic::Ordering::Relaxed);
+
+        entry
+    }
+}
+
+/// Keep the tails below `percent` percent of the block, when buffers shrink.
+///
+/// Shrinking a buffer in the pool frees the tail of its block. If the tail is less than `percent`
+/// percent of the block, it is kept after the buffer instead, such that growing the buffer again
+/// needn't go through the pool. Kept tails are given back to the pool by `purge` and
+/// `maintenance`, or when the heap cannot grow. Shares above 100 percent are clamped.
+///
+/// The default is `config::REALLOC_SLACK_PERCENT`. Zero turns the slack off, and the tails kept
+/// already stay until they are given back. It can also be set with the `realloc_slack` key in
+/// `RALLOC_CONF`.
+pub fn set_realloc_slack(percent: usize) {
+    // Logging.
+    log!(NOTE, "Setting the realloc slack to {} percent.", percent);
+
+    PERCENT.store(cmp::min(percent, 100), atomic::Ordering::Relaxed);
+}
+
+/// Get the share of a block (in percent), below which the tail freed by a shrink is kept.
+pub fn realloc_slack() -> usize {
+    PERCENT.load(atomic::Ordering::Relaxed)
+}
+
+/// Get the number of bytes in the kept tails.
+pub fn bytes() -> usize {
+    BYTES.load(atomic::Ordering::Relaxed)
+}
+
+/// Is any tail kept?
+#[inline]
+pub fn held() -> bool {
+    COUNT.load(atomic::Ordering::Relaxed) != 0
+}
+
+/// Take the tail kept after the block starting at `ptr` back.
+///
+/// The size of the tail is returned (zero, if none is kept). The tail follows the block directly,
+/// so the caller owns the block grown by that much.
+#[inline]
+pub fn take(ptr: *mut u8) -> usize {
+    if !held() {
+        return 0;
+    }
+
+    let mut table = TABLE.lock();
+    match table.find(ptr as usize) {
+        Some(n) => table.remove(n).2,
+        None => 0,
+    }
+}
+
+/// Take the tail kept after the block starting at `ptr`, such that it can be given back to the
+/// pool.
+///
+/// The tail is detached from its buffer for good. If no tail is kept, `None` is returned.
+pub fn detach(ptr: *mut u8) -> Option<Block> {
+    if !held() {
+        return None;
+    }
+
+    let mut table = TABLE.lock();
+    table.find(ptr as usize).map(|n| tail_block(table.remove(n)))
+}
+
+/// Try to keep a block of `size` bytes starting at `ptr`, while resizing it to `new_size` bytes.
+///
+/// If the block is resized within itself, and the part left over is below the share of the block
+/// set by `set_realloc_slack`, that part is kept as its tail, and `true` is returned. The block
+/// must have no tail kept already (see `take`).
+///
+/// Otherwise (or if the table is full), `false` is returned, and the block is left to the pool.
+pub fn keep(ptr: *mut u8, size: usize, new_size: usize, align: Align) -> bool {
+    if new_size > size || !align.is_aligned(ptr as usize) {
+        return false;
+    }
+
+    let tail = size - new_size;
+    // Growing into the whole tail leaves nothing to keep.
+    if tail == 0 {
+        return true;
+    }
+    if tail as u64 * 100 >= size as u64 * realloc_slack() as u64 {
+        return false;
+    }
+
+    insert(ptr, new_size, tail)
+}
+
+/// Keep a tail of `tail` bytes after the block of `size` bytes starting at `ptr`.
+///
+/// If the table is full, `false` is returned, and the tail is left to the caller.
+pub fn insert(ptr: *mut u8, size: usize, tail: usize) -> bool {
+    if TABLE.lock().insert((ptr as usize, size, tail)) {
+        true
+    } else {
+        // Logging.
+        log!(DEBUG, "The realloc slack table is full. Freeing the tail of {:?}.", ptr);
+
+        false
+    }
+}
+
+/// Take any kept tail, such that it can be given back to the pool.
+///
+/// The tail is detached from its buffer for good. If no tail is kept, `None` is returned.
+pub fn pop() -> Option<Block> {
+    if !held() {
+        return None;
+    }
+
+    let mut table = TABLE.lock();
+    let kept = table.entries.iter().position(|&(_, _, tail)| tail != 0);
+    kept.map(|n| tail_block(table.remove(n)))
+}
+
+/// Get the tail of a removed entry as a block.
+fn tail_block((start, size, tail): (usize, usize, usize)) -> Block {
+    unsafe {
+        // The tail was kept out of the pool, and no buffer owns it any longer.
+        Block::from_raw_parts(Pointer::new((start + size) as *mut u8), tail)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_probing() {
+        let mut table = Table::new();
+
+        // Find blocks probing from the same slot.
+        let mut starts = [0; 4];
+        let mut found = 0;
+        let mut start = 8;
+        while found < starts.len() {
+            if Table::slot(start) == Table::slot(8) {
+                starts[found] = start;
+                found += 1;
+            }
+            start += 8;
+        }
+
+        for (n, &start) in starts.iter().enumerate() {
+            assert!(table.insert((start, 64, n + 1)));
+        }
+        for (n, &start) in starts.iter().enumerate() {
+            let at = table.find(start).unwrap();
+            assert_eq!(table.entries[at], (start, 64, n + 1));
+        }
+
+        // Removing the first entry moves the others back, so they are still found.
+        let at = table.find(starts[0]).unwrap();
+        assert_eq!(table.remove(at), (starts[0], 64, 1));
+        assert!(table.find(starts[0]).is_none());
+        for &start in &starts[1..] {
+            assert!(table.find(start).is_some());
+        }
+
+        for &start in &starts[1..] {
+            let at = table.find(start).unwrap();
+            table.remove(at);
+        }
+        assert!(table.entries.iter().all(|&(_, _, tail)| tail == 0));
+
+        // The table fills up.
+        for n in 0..CAPACITY {
+            assert!(table.insert((8 * (n + 1), 64, 1)));
+        }
+        assert!(!table.insert((8 * (CAPACITY + 1), 64, 1)));
+        for n in 0..CAPACITY {
+            let at = table.find(8 * (n + 1)).unwrap();
+            table.remove(at);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Sorting and sorted tables.
+//!
+//! The allocator cannot allocate while sorting its own tables, so this is an in-place heap sort,
+//! which needs no buffer and is `O(n log n)` in the worst case.
+//!
+//! The internal tables (of live allocations, regions, slabs, and so on) are kept sorted by
+//! address. The helpers at the end maintain that order as entries come and go.
+
+use prelude::*;
+
+use fail::{self, AllocErr};
+use {allocator, layout};
+
+/// Sort a slice by a comparison.
+///
+/// `less(a, b)` tells if `a` goes before `b`. The sort is not stable.
+pub fn sort_by<T, F>(xs: &mut [T], mut less: F)
+    where F: FnMut(&T, &T) -> bool {
+    /// Move the element at `root` down the heap (which spans `xs[..end]`).
+    fn sift_down<T, F>(xs: &mut [T], less: &mut F, mut root: usize, end: usize)
+        where F: FnMut(&T, &T) -> bool {
+        loop {
+            let mut child = 2 * root + 1;
+            if child >= end { break; }
+
+            // Pick the larger child.
+            if child + 1 < end && less(&xs[child], &xs[child + 1]) {
+                child += 1;
+            }
+
+            if !less(&xs[root], &xs[child]) { break; }
+
+            xs.swap(root, child);
+            root = child;
+        }
+    }
+
+    // Build the heap.
+    for i in (0..xs.len() / 2).rev() {
+        let len = xs.len();
+        sift_down(xs, &mut less, i, len);
+    }
+
+    // Pop the maximum to the end, one by one.
+    for end in (1..xs.len()).rev() {
+        xs.swap(0, end);
+        sift_down(xs, &mut less, 0, end);
+    }
+}
+
+/// Find the index of the last element with a key at or below `key`.
+///
+/// The slice must be sorted by `f`.
+pub fn predecessor<T, K, F>(xs: &[T], key: &K, f: F) -> Option<usize>
+    where K: Ord, F: Fn(&T) -> K {
+    match xs.binary_search_by(|x| f(x).cmp(key)) {
+        Ok(n) => Some(n),
+        Err(0) => None,
+        Err(n) => Some(n - 1),
+    }
+}
+
+/// Move the last element of a slice into place, and return its index.
+///
+/// The rest of the slice must be sorted by `f`. Fresh entries tend to be at the top, so this is
+/// usually short.
+pub fn place_last<T, K, F>(xs: &mut [T], f: F) -> usize
+    where K: Ord, F: Fn(&T) -> K {
+    let mut n = xs.len() - 1;
+    while n > 0 && f(&xs[n - 1]) > f(&xs[n]) {
+        xs.swap(n - 1, n);
+        n -= 1;
+    }
+
+    n
+}
+
+/// Move the last element of a slice to index `n`.
+///
+/// The elements from `n` on are moved one place to the right.
+pub fn shift_in<T: Copy>(xs: &mut [T], n: usize) {
+    let len = xs.len();
+    let x = xs[len - 1];
+
+    let mut i = len - 1;
+    while i > n {
+        xs[i] = xs[i - 1];
+        i -= 1;
+    }
+    xs[n] = x;
+}
+
+/// Move the element at index `n` of a slice to the end.
+///
+/// The following elements are moved one place to the left, so the element can be popped (or the
+/// slice truncated) without breaking the order.
+pub fn shift_out<T: Copy>(xs: &mut [T], n: usize) {
+    let len = xs.len();
+    let x = xs[n];
+
+    for i in n..len - 1 {
+        xs[i] = xs[i + 1];
+    }
+    xs[len - 1] = x;
+}
+
+/// Insert an element into a table sorted by `f`, and return its index.
+///
+/// If the table is full, it is moved to a bigger buffer from the pool.
+pub fn insert<T: Copy, K, F>(xs: &mut Vec<T>, x: T, f: F) -> usize
+    where K: Ord, F: Fn(&T) -> K {
+    if xs.push(x).is_err() {
+        // The table is full, so we move it to a bigger buffer.
+        let cap = 2 * xs.capacity() + 64;
+        let layout = layout::array::<T>(cap).unwrap_or_else(|_| fail::oom(AllocErr::LimitReached));
+        let block = allocator::pool_alloc(layout.size(), Align::of::<T>());
+        // The old buffer holds addresses and sizes, so it is wiped before it is freed.
+        let mut old = xs.refill(block);
+        if !old.is_empty() {
+            old.wipe();
+            allocator::pool_free(old);
+        }
+
+        xs.push(x).expect("Refilled table is still full.");
+    }
+
+    // Move the element into place.
+    place_last(xs, f)
+}
+
+/// Remove the element at index `n` of a sorted table.
+pub fn remove<T: Copy>(xs: &mut Vec<T>, n: usize) {
+    let len = xs.len();
+
+    shift_out(xs, n);
+    xs.truncate(len - 1);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sort_pointers() {
+        let mut ptrs = [5 as *mut u8, 1 as *mut u8, 4 as *mut u8, 4 as *mut u8, 9 as *mut u8,
+                        2 as *mut u8];
+        sort_by(&mut ptrs, |a, b| a < b);
+
+        assert_eq!(ptrs, [1 as *mut u8, 2 as *mut u8, 4 as *mut u8, 4 as *mut u8, 5 as *mut u8,
+                          9 as *mut u8]);
+
+        let mut empty: [*mut u8; 0] = [];
+        sort_by(&mut empty, |a, b| a < b);
+    }
+
+    #[test]
+    fn test_sort_by_key() {
+        let mut xs = [(3, 'c'), (1, 'a'), (7, 'g'), (2, 'b'), (5, 'e'), (4, 'd'), (6, 'f')];
+        sort_by(&mut xs, |a, b| a.0 > b.0);
+
+        assert_eq!(xs, [(7, 'g'), (6, 'f'), (5, 'e'), (4, 'd'), (3, 'c'), (2, 'b'), (1, 'a')]);
+    }
+
+    #[test]
+    fn test_table() {
+        let mut xs = [(2, 'b'), (4, 'd'), (8, 'h'), (3, 'c')];
+
+        assert_eq!(place_last(&mut xs, |x| x.0), 1);
+        assert_eq!(xs, [(2, 'b'), (3, 'c'), (4, 'd'), (8, 'h')]);
+
+        assert_eq!(predecessor(&xs, &1, |x| x.0), None);
+        assert_eq!(predecessor(&xs, &3, |x| x.0), Some(1));
+        assert_eq!(predecessor(&xs, &7, |x| x.0), Some(2));
+        assert_eq!(predecessor(&xs, &9, |x| x.0), Some(3));
+
+        shift_out(&mut xs, 1);
+        assert_eq!(xs, [(2, 'b'), (4, 'd'), (8, 'h'), (3, 'c')]);
+
+        shift_in(&mut xs, 1);
+        assert_eq!(xs, [(2, 'b'), (3, 'c'), (4, 'd'), (8, 'h')]);
+
+        shift_out(&mut xs, 3);
+        assert_eq!(xs, [(2, 'b'), (3, 'c'), (4, 'd'), (8, 'h')]);
+        shift_in(&mut xs, 0);
+        assert_eq!(xs, [(8, 'h'), (2, 'b'), (3, 'c'), (4, 'd')]);
+    }
+}
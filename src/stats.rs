@@ -0,0 +1,1274 @@
+//! Allocator statistics.
+//!
+//! This module is only available with the `stats` feature.
+//!
+//! # Staleness
+//!
+//! With the `tls` feature, every thread buffers its counts of allocations and frees (and the size,
+//! alignment and lifetime histograms), and adds them to the shared counters in batches, such that
+//! allocating threads don't contend on the counters. A thread flushes its buffer every
+//! `config::STATS_BATCH` counts, when its local allocator refills or spills, and when it exits.
+//!
+//! Reading the counts (`class`, `live_bytes`, `size_histogram`, `align_histogram`,
+//! `lifetime_histogram`, or `write_report`) flushes the buffer of the calling thread, so its own
+//! counts are exact, and asks every other thread to flush its buffer at its next allocation or
+//! free. The counts of another thread thus lag behind by at most `config::STATS_BATCH - 1` counts,
+//! which it made before the read, and never flushed, since it has been idle.
+
+#[cfg(all(feature = "tls", not(feature = "shadow_accounting")))]
+use core::cell::UnsafeCell;
+use core::{cmp, fmt, mem};
+use atomic::{self, AtomicUsize};
+#[cfg(all(feature = "tls", not(feature = "shadow_accounting")))]
+use shim::config;
+
+use sync::CachePadded;
+use {allocator, bootstrap, class, heap, random, secure, watermark};
+#[cfg(feature = "slab")]
+use slab;
+#[cfg(feature = "tagging")]
+use tag;
+#[cfg(feature = "sites")]
+use site;
+#[cfg(feature = "arenas")]
+use arena;
+#[cfg(feature = "numa")]
+use numa;
+#[cfg(feature = "realloc_slack")]
+use slack;
+
+pub use class::SizeClass;
+#[cfg(feature = "tagging")]
+pub use tag::{TagStats, TagTable, Tags};
+#[cfg(feature = "sites")]
+pub use site::{Site, SiteStats};
+#[cfg(feature = "arenas")]
+pub use arena::{ArenaStats, COUNT as ARENA_COUNT};
+#[cfg(feature = "cpu_shards")]
+pub use arena::shards;
+#[cfg(feature = "numa")]
+pub use numa::MAX_NODES;
+pub use shim::entropy::Source as SeedSource;
+
+/// The per-class counters of the allocator.
+static CLASSES: ClassCounters = ClassCounters::new();
+/// The number of reallocations by strategy, indexed by the strategy.
+static REALLOCS: CachePadded<[AtomicUsize; 3]> = CachePadded::new([
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+]);
+
+/// The number of aligned blocks by alignment path, indexed by the path.
+static ALIGN_PATHS: CachePadded<[AtomicUsize; 2]> = CachePadded::new([
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+]);
+/// The histogram of the requested alignments.
+static ALIGNS: CachePadded<[AtomicUsize; BUCKETS]> = CachePadded::new(buckets());
+/// The number of fitting free blocks passed over, since they would leave a small fragment.
+static SPLIT_SKIPS: AtomicUsize = AtomicUsize::new(0);
+/// The number of growths of the heap, after which the global allocator had a fitting block.
+static RACED_GROWTHS: AtomicUsize = AtomicUsize::new(0);
+/// The number of allocations rejected for exceeding the maximal allocation size.
+static TOO_LARGE: AtomicUsize = AtomicUsize::new(0);
+/// The histograms of the allocation sizes.
+static SIZES: Histograms = Histograms::new();
+/// The lifetime histograms, indexed by the class index.
+///
+/// These are counted on frees only, and the classes share lines.
+static LIFETIMES: [[AtomicUsize; LIFETIME_BUCKETS]; class::COUNT + 1] = [
+    lifetime_buckets(), lifetime_buckets(), lifetime_buckets(), lifetime_buckets(),
+    lifetime_buckets(), lifetime_buckets(), lifetime_buckets(), lifetime_buckets(),
+    lifetime_buckets(), lifetime_buckets(), lifetime_buckets(), lifetime_buckets(),
+    lifetime_buckets(), lifetime_buckets(), lifetime_buckets(), lifetime_buckets(),
+    lifetime_buckets(),
+];
+/// The number of flushes requested.
+///
+/// Reading the counts bumps this, and the threads flush their buffers, when they see it changed.
+#[cfg(all(feature = "tls", not(feature = "shadow_accounting")))]
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+#[cfg(all(feature = "tls", not(feature = "shadow_accounting")))]
+tls! {
+    /// The counts buffered by the current thread.
+    static BUFFER: UnsafeCell<Buffer> = UnsafeCell::new(Buffer::new());
+}
+
+/// The number of buckets of a size histogram.
+///
+/// Bucket 0 counts zero, and bucket `n` counts the values in `2^(n - 1)..2^n`. The last bucket
+/// counts everything above as well.
+pub const BUCKETS: usize = 64;
+/// The number of buckets of a lifetime histogram.
+///
+/// The buckets are those of the size histograms (see `BUCKETS`), over the ages in generations.
+/// Only 16 bits of the birth generation are recorded (see `meta::age`), so the ages are below
+/// 65536, and the last bucket counts the ages in `32768..65536`.
+/// and the last bucket counts the ages in `128..256`.
+pub const LIFETIME_BUCKETS: usize = 17;
+
+/// A strategy for growing or shrinking a block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReallocStrategy {
+    /// The block was shrunk, or extended into its free right neighbor.
+    Inplace = 0,
+    /// The block was extended into its free left neighbor, moving the data down.
+    Left = 1,
+    /// The data was copied to a new block.
+    Copy = 2,
+}
+
+/// The path taken to align a block taken from the pool or the program break.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlignPath {
+    /// The block was aligned already.
+    Natural = 0,
+    /// An aligner was split off the block (see `Block::align`), or off the fresh memory.
+    Explicit = 1,
+}
+
+/// The statistics of a size class.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClassStats {
+    /// The number of live allocations.
+    pub count: usize,
+    /// The cumulative number of allocations.
+    pub allocs: usize,
+    /// The cumulative number of frees.
+    pub frees: usize,
+    /// The number of bytes in live allocations.
+    ///
+    /// This counts the requested sizes, not the sizes of the classes.
+    pub bytes: usize,
+}
+
+/// The counters of a single size class.
+struct Counter {
+    /// The cumulative number of allocations.
+    allocs: AtomicUsize,
+    /// The cumulative number of frees.
+    frees: AtomicUsize,
+    /// The number of bytes in live allocations.
+    bytes: AtomicUsize,
+}
+
+impl Counter {
+    /// Create a new zeroed counter.
+    const fn new() -> Counter {
+        Counter {
+            allocs: AtomicUsize::new(0),
+            frees: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// The counters of every size class.
+///
+/// The counters are relaxed atomics, so they are only approximately consistent with each other
+/// while allocations are happening. Every class has its own cache line, so threads allocating from
+/// different classes don't contend.
+struct ClassCounters {
+    /// The counters, indexed by the class index.
+    counters: [CachePadded<Counter>; class::COUNT + 1],
+}
+
+impl ClassCounters {
+    /// Create a new set of zeroed counters.
+    const fn new() -> ClassCounters {
+        ClassCounters {
+            // Atomics aren't `Copy`, so we cannot use the repeat syntax.
+            counters: [CachePadded::new(Counter::new()), CachePadded::new(Counter::new()),
+                       CachePadded::new(Counter::new()), CachePadded::new(Counter::new()),
+                       CachePadded::new(Counter::new()), CachePadded::new(Counter::new()),
+                       CachePadded::new(Counter::new()), CachePadded::new(Counter::new()),
+                       CachePadded::new(Counter::new()), CachePadded::new(Counter::new()),
+                       CachePadded::new(Counter::new()), CachePadded::new(Counter::new()),
+                       CachePadded::new(Counter::new()), CachePadded::new(Counter::new()),
+                       CachePadded::new(Counter::new()), CachePadded::new(Counter::new()),
+                       CachePadded::new(Counter::new())],
+        }
+    }
+
+    /// Count an allocation.
+    #[cfg(not(all(feature = "tls", not(feature = "shadow_accounting"))))]
+    fn alloc(&self, size: usize) {
+        let counter = &self.counters[SizeClass::of(size).index()];
+
+        counter.allocs.fetch_add(1, atomic::Ordering::Relaxed);
+        counter.bytes.fetch_add(size, atomic::Ordering::Relaxed);
+    }
+
+    /// Count a free.
+    #[cfg(not(all(feature = "tls", not(feature = "shadow_accounting"))))]
+    fn free(&self, size: usize) {
+        let counter = &self.counters[SizeClass::of(size).index()];
+
+        counter.frees.fetch_add(1, atomic::Ordering::Relaxed);
+        counter.bytes.fetch_sub(size, atomic::Ordering::Relaxed);
+    }
+
+    /// Get the statistics of a class.
+    fn get(&self, class: SizeClass) -> ClassStats {
+        let counter = &self.counters[class.index()];
+
+        let allocs = counter.allocs.load(atomic::Ordering::Relaxed);
+        let frees = counter.frees.load(atomic::Ordering::Relaxed);
+
+        ClassStats {
+            count: allocs.wrapping_sub(frees),
+            allocs: allocs,
+            frees: frees,
+            bytes: counter.bytes.load(atomic::Ordering::Relaxed),
+        }
+    }
+}
+
+/// The counts of allocations and frees buffered by a thread.
+///
+/// The shadow accountant compares the counters, whenever no call is in progress, so nothing is
+/// buffered with the `shadow_accounting` feature.
+#[cfg(all(feature = "tls", not(feature = "shadow_accounting")))]
+struct Buffer {
+    /// The buffered counts as `(allocs, frees, bytes)`, indexed by the class index.
+    ///
+    /// The bytes wrap around, as frees subtract from them.
+    counts: [(usize, usize, usize); class::COUNT + 1],
+    /// The buffered histogram of the requested sizes.
+    requested: [usize; BUCKETS],
+    /// The buffered histogram of the wasted bytes.
+    waste: [usize; BUCKETS],
+    /// The buffered histogram of the requested alignments.
+    aligns: [usize; BUCKETS],
+    /// The buffered lifetime histograms, indexed by the class index.
+    lifetimes: [[usize; LIFETIME_BUCKETS]; class::COUNT + 1],
+    /// The number of operations buffered.
+    ops: usize,
+    /// The number of operations, after which the buffer is flushed.
+    ///
+    /// After the thread destructor ran, this is one, such that nothing is buffered.
+    batch: usize,
+    /// The flush request, which the buffer was last flushed after.
+    epoch: usize,
+    /// Is the thread destructor registered?
+    registered: bool,
+}
+
+#[cfg(all(feature = "tls", not(feature = "shadow_accounting")))]
+impl Buffer {
+    /// Create an empty buffer.
+    const fn new() -> Buffer {
+        Buffer {
+            counts: [(0, 0, 0); class::COUNT + 1],
+            requested: [0; BUCKETS],
+            waste: [0; BUCKETS],
+            aligns: [0; BUCKETS],
+            lifetimes: [[0; LIFETIME_BUCKETS]; class::COUNT + 1],
+            ops: 0,
+            batch: config::STATS_BATCH,
+            epoch: 0,
+            registered: false,
+        }
+    }
+
+    /// Count an operation, flushing the buffer, if it is full or a flush was requested.
+    #[inline]
+    fn count(&mut self) {
+        self.ops += 1;
+        if self.ops >= self.batch || EPOCH.load(atomic::Ordering::Relaxed) != self.epoch {
+            self.flush();
+        }
+    }
+
+    /// Add the buffered counts to the shared counters.
+    fn flush(&mut self) {
+        self.epoch = EPOCH.load(atomic::Ordering::Relaxed);
+        if self.ops == 0 { return; }
+
+        for (counter, counts) in CLASSES.counters.iter().zip(self.counts.iter_mut()) {
+            let (allocs, frees, bytes) = mem::replace(counts, (0, 0, 0));
+
+            if allocs != 0 {
+                counter.allocs.fetch_add(allocs, atomic::Ordering::Relaxed);
+            }
+            if frees != 0 {
+                counter.frees.fetch_add(frees, atomic::Ordering::Relaxed);
+            }
+            if bytes != 0 {
+                counter.bytes.fetch_add(bytes, atomic::Ordering::Relaxed);
+            }
+        }
+
+        flush_buckets(&mut self.requested, &SIZES.requested[..]);
+        flush_buckets(&mut self.waste, &SIZES.waste[..]);
+        flush_buckets(&mut self.aligns, &ALIGNS[..]);
+        for (buckets, buffered) in LIFETIMES.iter().zip(self.lifetimes.iter_mut()) {
+            flush_buckets(buffered, buckets);
+        }
+
+        self.ops = 0;
+    }
+}
+
+/// Add the buffered counts of a histogram to its shared buckets.
+#[cfg(all(feature = "tls", not(feature = "shadow_accounting")))]
+fn flush_buckets(buffered: &mut [usize], buckets: &[AtomicUsize]) {
+    for (bucket, count) in buckets.iter().zip(buffered.iter_mut()) {
+        let count = mem::replace(count, 0);
+        if count != 0 {
+            bucket.fetch_add(count, atomic::Ordering::Relaxed);
+        }
+    }
+}
+
+/// Run a closure on the buffer of the current thread.
+///
+/// The first time, this registers the thread destructor flushing the buffer.
+#[cfg(all(feature = "tls", not(feature = "shadow_accounting")))]
+#[inline]
+fn with_buffer<F: FnOnce(&mut Buffer)>(f: F) {
+    /// Flush the buffer, and stop buffering, as the thread exits.
+    extern fn dtor(buffer: &UnsafeCell<Buffer>) {
+        let buffer = unsafe {
+            // The destructor runs on the owning thread, outside of any access to the buffer.
+            &mut *buffer.get()
+        };
+
+        buffer.flush();
+        // Other destructors may still allocate and free afterwards.
+        buffer.batch = 1;
+    }
+
+    let registered = BUFFER.with(|buffer| {
+        let buffer = unsafe {
+            // The buffer belongs to the current thread, and the closures never reenter the
+            // allocator, so this is the only reference.
+            &mut *buffer.get()
+        };
+
+        f(buffer);
+        mem::replace(&mut buffer.registered, true)
+    });
+
+    if !registered {
+        BUFFER.register_thread_destructor(dtor);
+    }
+}
+
+/// Flush the counts buffered by the current thread.
+///
+/// This is called when the local allocator refills or spills.
+#[cfg(all(feature = "tls", not(feature = "shadow_accounting")))]
+pub fn flush() {
+    with_buffer(Buffer::flush);
+}
+
+/// Flush the counts buffered by the current thread.
+///
+/// Without buffering, this does nothing.
+#[cfg(not(all(feature = "tls", not(feature = "shadow_accounting"))))]
+#[inline]
+pub fn flush() {}
+
+/// Flush the counts of the current thread, and ask the other threads to flush theirs.
+///
+/// This is called before the counts are read.
+#[inline]
+fn request_flush() {
+    #[cfg(all(feature = "tls", not(feature = "shadow_accounting")))]
+    EPOCH.fetch_add(1, atomic::Ordering::Relaxed);
+
+    flush();
+}
+
+/// Get the bucket of a value in a size histogram.
+#[inline]
+pub fn bucket(x: usize) -> usize {
+    cmp::min(mem::size_of::<usize>() * 8 - x.leading_zeros() as usize, BUCKETS - 1)
+}
+
+/// Create a zeroed array of buckets.
+const fn buckets() -> [AtomicUsize; BUCKETS] {
+    // Atomics aren't `Copy`, so we cannot use the repeat syntax.
+    [
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)
+    ]
+}
+
+/// Create a zeroed array of lifetime buckets.
+const fn lifetime_buckets() -> [AtomicUsize; LIFETIME_BUCKETS] {
+    [
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+        AtomicUsize::new(0)
+    ]
+}
+
+/// A snapshot of a log-scale histogram.
+///
+/// See `BUCKETS` for the ranges of the buckets.
+#[derive(Copy)]
+pub struct Histogram {
+    /// The counts, indexed by the bucket.
+    counts: [usize; BUCKETS],
+}
+
+impl Histogram {
+    /// Get the count of a bucket.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `bucket` is not below `BUCKETS`.
+    pub fn count(&self, bucket: usize) -> usize {
+        self.counts[bucket]
+    }
+
+    /// Get the counts of every bucket.
+    pub fn counts(&self) -> &[usize] {
+        &self.counts
+    }
+
+    /// Get the sum of the counts.
+    pub fn total(&self) -> usize {
+        self.counts.iter().fold(0, |acc, &x| acc.wrapping_add(x))
+    }
+}
+
+// Arrays of more than 32 elements implement neither `Clone` nor `Debug`.
+impl Clone for Histogram {
+    fn clone(&self) -> Histogram {
+        *self
+    }
+}
+
+impl fmt::Debug for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.counts.iter()).finish()
+    }
+}
+
+/// The size histograms of the allocations.
+#[derive(Clone, Copy, Debug)]
+pub struct SizeHistogram {
+    /// The requested sizes.
+    pub requested: Histogram,
+    /// The wasted bytes, that is, the granted size less the requested size.
+    pub waste: Histogram,
+}
+
+/// The size histograms, as counters.
+///
+/// Every histogram spans whole cache lines of its own.
+struct Histograms {
+    /// The requested sizes.
+    requested: CachePadded<[AtomicUsize; BUCKETS]>,
+    /// The wasted bytes.
+    waste: CachePadded<[AtomicUsize; BUCKETS]>,
+}
+
+impl Histograms {
+    /// Create a new set of zeroed histograms.
+    const fn new() -> Histograms {
+        Histograms {
+            requested: CachePadded::new(buckets()),
+            waste: CachePadded::new(buckets()),
+        }
+    }
+
+    /// Count an allocation.
+    #[cfg(not(all(feature = "tls", not(feature = "shadow_accounting"))))]
+    fn record(&self, size: usize, granted: usize) {
+        self.requested[bucket(size)].fetch_add(1, atomic::Ordering::Relaxed);
+        self.waste[bucket(granted - size)].fetch_add(1, atomic::Ordering::Relaxed);
+    }
+
+    /// Take a snapshot of the histograms.
+    fn get(&self) -> SizeHistogram {
+        let mut res = SizeHistogram {
+            requested: Histogram { counts: [0; BUCKETS] },
+            waste: Histogram { counts: [0; BUCKETS] },
+        };
+
+        for i in 0..BUCKETS {
+            res.requested.counts[i] = self.requested[i].load(atomic::Ordering::Relaxed);
+            res.waste.counts[i] = self.waste[i].load(atomic::Ordering::Relaxed);
+        }
+
+        res
+    }
+}
+
+/// Count an allocation of some size.
+///
+/// With the `tls` feature, the count is buffered (see the module documentation).
+#[inline]
+pub fn record_alloc(size: usize) {
+    #[cfg(all(feature = "tls", not(feature = "shadow_accounting")))]
+    with_buffer(|buffer| {
+        let counts = &mut buffer.counts[SizeClass::of(size).index()];
+        counts.0 += 1;
+        counts.2 = counts.2.wrapping_add(size);
+
+        buffer.count();
+    });
+
+    #[cfg(not(all(feature = "tls", not(feature = "shadow_accounting"))))]
+    CLASSES.alloc(size);
+}
+
+/// Count a free of some size.
+///
+/// Partial frees are counted as frees of the freed size, so the live counts of a class can wrap
+/// around when partial frees are used. With the `tls` feature, the count is buffered (see the
+/// module documentation).
+#[inline]
+pub fn record_free(size: usize) {
+    #[cfg(all(feature = "tls", not(feature = "shadow_accounting")))]
+    with_buffer(|buffer| {
+        let counts = &mut buffer.counts[SizeClass::of(size).index()];
+        counts.1 += 1;
+        counts.2 = counts.2.wrapping_sub(size);
+
+        buffer.count();
+    });
+
+    #[cfg(not(all(feature = "tls", not(feature = "shadow_accounting"))))]
+    CLASSES.free(size);
+}
+
+/// Count the age (in generations) of a freed allocation of some size.
+///
+/// The ages are only known with allocation metadata (the `header` or `sidetable` feature). With
+/// the `tls` feature, the count is buffered (see the module documentation).
+#[inline]
+pub fn record_lifetime(size: usize, age: usize) {
+    let class = SizeClass::of(size).index();
+    let n = cmp::min(bucket(age), LIFETIME_BUCKETS - 1);
+
+    #[cfg(all(feature = "tls", not(feature = "shadow_accounting")))]
+    with_buffer(|buffer| {
+        buffer.lifetimes[class][n] += 1;
+
+        buffer.count();
+    });
+
+    #[cfg(not(all(feature = "tls", not(feature = "shadow_accounting"))))]
+    LIFETIMES[class][n].fetch_add(1, atomic::Ordering::Relaxed);
+}
+
+/// The lifetime histograms of the size classes.
+///
+/// See `LIFETIME_BUCKETS` for the ranges of the buckets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LifetimeHistogram {
+    /// The counts, indexed by the class index and the bucket.
+    counts: [[usize; LIFETIME_BUCKETS]; class::COUNT + 1],
+}
+
+impl LifetimeHistogram {
+    /// Get the count of a bucket of a class.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `bucket` is not below `LIFETIME_BUCKETS`.
+    pub fn count(&self, class: SizeClass, bucket: usize) -> usize {
+        self.counts[class.index()][bucket]
+    }
+
+    /// Get the counts of every bucket of a class.
+    pub fn counts(&self, class: SizeClass) -> &[usize] {
+        &self.counts[class.index()]
+    }
+}
+
+/// Get the lifetime histograms of the size classes.
+///
+/// Every freed allocation with metadata is counted by the age it was freed at, in generations of
+/// the pools (the clock advances every `GENERATION_FREES` frees of a pool, see the shim config).
+/// Reallocated buffers keep their age. See the module documentation for the staleness of the
+/// counts.
+pub fn lifetime_histogram() -> LifetimeHistogram {
+    request_flush();
+
+    let mut res = LifetimeHistogram { counts: [[0; LIFETIME_BUCKETS]; class::COUNT + 1] };
+    for class in SizeClass::iter() {
+        for (count, x) in res.counts[class.index()].iter_mut()
+            .zip(LIFETIMES[class.index()].iter()) {
+            *count = x.load(atomic::Ordering::Relaxed);
+        }
+    }
+
+    res
+}
+
+/// Count the requested and the granted size of a new buffer in the size histograms.
+///
+/// Reallocations are not counted. With the `tls` feature, the count is buffered (see the module
+/// documentation).
+#[inline]
+pub fn record_grant(size: usize, granted: usize) {
+    #[cfg(all(feature = "tls", not(feature = "shadow_accounting")))]
+    with_buffer(|buffer| {
+        buffer.requested[bucket(size)] += 1;
+        buffer.waste[bucket(granted - size)] += 1;
+
+        buffer.count();
+    });
+
+    #[cfg(not(all(feature = "tls", not(feature = "shadow_accounting"))))]
+    SIZES.record(size, granted);
+}
+
+/// Get the size histograms of the allocations.
+///
+/// See the module documentation for the staleness of the counts.
+pub fn size_histogram() -> SizeHistogram {
+    request_flush();
+
+    SIZES.get()
+}
+
+/// Count a reallocation in the pool.
+#[inline]
+pub fn record_realloc(strategy: ReallocStrategy) {
+    REALLOCS[strategy as usize].fetch_add(1, atomic::Ordering::Relaxed);
+}
+
+/// Count an allocation with some requested alignment.
+///
+/// With the `tls` feature, the count is buffered (see the module documentation).
+#[inline]
+pub fn record_align(align: usize) {
+    #[cfg(all(feature = "tls", not(feature = "shadow_accounting")))]
+    with_buffer(|buffer| {
+        buffer.aligns[bucket(align)] += 1;
+
+        buffer.count();
+    });
+
+    #[cfg(not(all(feature = "tls", not(feature = "shadow_accounting"))))]
+    ALIGNS[bucket(align)].fetch_add(1, atomic::Ordering::Relaxed);
+}
+
+/// Get the histogram of the requested alignments.
+///
+/// Since alignments are powers of two, an alignment of `2^n` is counted in bucket `n + 1`. See
+/// the module documentation for the staleness of the counts.
+pub fn align_histogram() -> Histogram {
+    request_flush();
+
+    let mut res = Histogram { counts: [0; BUCKETS] };
+    for i in 0..BUCKETS {
+        res.counts[i] = ALIGNS[i].load(atomic::Ordering::Relaxed);
+    }
+
+    res
+}
+
+/// Count a block taking some alignment path.
+#[inline]
+pub fn record_align_path(path: AlignPath) {
+    ALIGN_PATHS[path as usize].fetch_add(1, atomic::Ordering::Relaxed);
+}
+
+/// Get the number of blocks taking some alignment path.
+pub fn align_paths(path: AlignPath) -> usize {
+    ALIGN_PATHS[path as usize].load(atomic::Ordering::Relaxed)
+}
+
+/// Get the number of bytes in live allocations, over every class.
+///
+/// See the module documentation for the staleness of the count.
+pub fn live_bytes() -> usize {
+    request_flush();
+
+    SizeClass::iter().map(|x| CLASSES.get(x).bytes).fold(0, usize::wrapping_add)
+}
+
+/// Count a fitting free block passed over by an allocation, since it would leave a fragment
+/// below the minimal split remainder.
+#[inline]
+pub fn record_split_skip() {
+    SPLIT_SKIPS.fetch_add(1, atomic::Ordering::Relaxed);
+}
+
+/// Get the number of fitting free blocks passed over, since they would leave a small fragment.
+pub fn split_skips() -> usize {
+    SPLIT_SKIPS.load(atomic::Ordering::Relaxed)
+}
+
+/// Count a growth of the heap, after which the global allocator had a fitting block.
+#[inline]
+pub fn record_raced_growth() {
+    RACED_GROWTHS.fetch_add(1, atomic::Ordering::Relaxed);
+}
+
+/// Get the number of growths of the heap, after which the global allocator had a fitting block.
+pub fn raced_growths() -> usize {
+    RACED_GROWTHS.load(atomic::Ordering::Relaxed)
+}
+
+/// Count an allocation rejected for exceeding the maximal allocation size.
+#[inline]
+pub fn record_too_large() {
+    TOO_LARGE.fetch_add(1, atomic::Ordering::Relaxed);
+}
+
+/// Get the number of allocations rejected for exceeding the maximal allocation size.
+pub fn too_large() -> usize {
+    TOO_LARGE.load(atomic::Ordering::Relaxed)
+}
+
+/// Get the number of reallocations in the pool using some strategy.
+pub fn reallocs(strategy: ReallocStrategy) -> usize {
+    REALLOCS[strategy as usize].load(atomic::Ordering::Relaxed)
+}
+
+/// Get the statistics of a size class.
+///
+/// See the module documentation for the staleness of the counts.
+pub fn class(class: SizeClass) -> ClassStats {
+    request_flush();
+
+    CLASSES.get(class)
+}
+
+/// Get the statistics of every allocation tag.
+///
+/// Untagged allocations are counted under tag 0.
+#[cfg(feature = "tagging")]
+pub fn by_tag() -> TagTable {
+    tag::stats()
+}
+
+/// Get the statistics of an allocation site.
+///
+/// Sites, which never allocated (or didn't fit the table of sites), have zeroed statistics.
+#[cfg(feature = "sites")]
+pub fn by_site(site: &'static Site) -> SiteStats {
+    site::stats(site)
+}
+
+/// Write the `n` allocation sites with the most live bytes.
+///
+/// The sites, which didn't fit the table of sites, are counted together as "other sites".
+#[cfg(feature = "sites")]
+pub fn top_sites<W: fmt::Write>(n: usize, w: &mut W) -> fmt::Result {
+    site::write_top(n, w)
+}
+
+/// Get the statistics of every arena.
+///
+/// Arenas, which no thread has used yet, have zeroed statistics.
+#[cfg(feature = "arenas")]
+pub fn arenas() -> [ArenaStats; ARENA_COUNT] {
+    arena::stats()
+}
+
+/// Get the number of bytes placed on every NUMA node.
+///
+/// This counts the chunks of the heaps pinned to a node, and the interleaved allocations, evenly
+/// over the nodes. Memory left to the OS to place is not counted, so with a single node, nothing
+/// is.
+#[cfg(feature = "numa")]
+pub fn by_node() -> [usize; MAX_NODES] {
+    numa::node_bytes()
+}
+
+/// A snapshot of the allocator statistics.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    /// The number of bytes used from the bootstrap arena.
+    ///
+    /// This includes the padding used for alignment.
+    pub bootstrap_bytes: usize,
+    /// The number of bytes of the heap extension reserved by the initialization.
+    ///
+    /// This is always zero without the `early_init` feature.
+    pub early_reserved: usize,
+    /// The number of bytes obtained up front by `preallocate`.
+    pub preallocated: usize,
+    /// The number of preallocated bytes, which are free in the pool of the global allocator.
+    ///
+    /// The blocks held by the local allocators are counted as used.
+    pub preallocated_free: usize,
+    /// The number of bytes taken from the OS on demand, i.e. the heap usage beyond the
+    /// preallocated bytes.
+    pub demand_grown: usize,
+    /// The number of free bytes in the pool of the global allocator.
+    ///
+    /// The blocks held by the local allocators are counted as used.
+    pub global_free: usize,
+    /// The number of growths of the heap, after which the global allocator had a fitting block
+    /// freed by another thread meanwhile.
+    ///
+    /// The fresh memory is freed into the pool then.
+    pub raced_growths: usize,
+    /// The number of bytes kept after shrunk buffers (see `ralloc::set_realloc_slack`).
+    ///
+    /// These are neither live nor free. This is always zero without the `realloc_slack` feature.
+    pub realloc_slack: usize,
+    /// The number of live secure allocations.
+    pub secure_count: usize,
+    /// The number of bytes in live secure allocations.
+    pub secure_bytes: usize,
+    /// The number of live independent heaps.
+    pub heap_count: usize,
+    /// The number of bytes taken by the live independent heaps.
+    pub heap_bytes: usize,
+    /// The number of bytes taken by the independent heaps, which were leaked at their drop.
+    pub heap_leaked: usize,
+    /// The number of slabs.
+    ///
+    /// This is always zero without the `slab` feature.
+    pub slab_count: usize,
+    /// The number of bytes in allocated slab cells.
+    ///
+    /// This includes the rounding up to the size class.
+    pub slab_bytes: usize,
+    /// The number of reallocations in the pool done inplace.
+    pub realloc_inplace: usize,
+    /// The number of reallocations in the pool absorbing the left neighbor.
+    pub realloc_left: usize,
+    /// The number of reallocations in the pool copying the data.
+    pub realloc_copy: usize,
+    /// The number of fitting free blocks passed over by allocations, since they would leave a
+    /// fragment below the minimal split remainder.
+    pub split_skips: usize,
+    /// The number of allocations rejected for exceeding the maximal allocation size.
+    pub too_large: usize,
+    /// The number of blocks taken from the pool or the program break, which were aligned
+    /// already.
+    pub align_natural: usize,
+    /// The number of blocks taken from the pool or the program break, which needed an aligner.
+    pub align_explicit: usize,
+}
+
+/// Get the entropy source, which the random number generator was seeded from.
+///
+/// If nothing was drawn yet, or the generator was seeded explicitly, `None` is returned.
+pub fn seed_source() -> Option<SeedSource> {
+    random::source()
+}
+
+/// Take a snapshot of the allocator statistics.
+pub fn snapshot() -> Stats {
+    Stats {
+        bootstrap_bytes: bootstrap::used(),
+        #[cfg(feature = "early_init")]
+        early_reserved: allocator::reserved(),
+        #[cfg(not(feature = "early_init"))]
+        early_reserved: 0,
+        preallocated: allocator::preallocated(),
+        preallocated_free: allocator::preallocated_free(),
+        demand_grown: watermark::heap_usage().saturating_sub(allocator::preallocated()),
+        global_free: allocator::global_free_bytes(),
+        raced_growths: raced_growths(),
+        #[cfg(feature = "realloc_slack")]
+        realloc_slack: slack::bytes(),
+        #[cfg(not(feature = "realloc_slack"))]
+        realloc_slack: 0,
+        secure_count: secure::count(),
+        secure_bytes: secure::bytes(),
+        heap_count: heap::count(),
+        heap_bytes: heap::owned(),
+        heap_leaked: heap::leaked(),
+        #[cfg(feature = "slab")]
+        slab_count: slab::count(),
+        #[cfg(not(feature = "slab"))]
+        slab_count: 0,
+        #[cfg(feature = "slab")]
+        slab_bytes: slab::bytes(),
+        #[cfg(not(feature = "slab"))]
+        slab_bytes: 0,
+        realloc_inplace: reallocs(ReallocStrategy::Inplace),
+        realloc_left: reallocs(ReallocStrategy::Left),
+        realloc_copy: reallocs(ReallocStrategy::Copy),
+        split_skips: split_skips(),
+        too_large: too_large(),
+        align_natural: align_paths(AlignPath::Natural),
+        align_explicit: align_paths(AlignPath::Explicit),
+    }
+}
+
+/// A byte count, displayed with binary units (B, KiB, MiB, GiB).
+///
+/// Anything but plain bytes is shown with one decimal, rounded down. Width and alignment flags
+/// are respected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bytes(pub usize);
+
+impl Bytes {
+    /// Write the byte count without padding.
+    fn write<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        let units = [(1 << 30, "GiB"), (1 << 20, "MiB"), (1 << 10, "KiB")];
+        match units.iter().find(|&&(unit, _)| self.0 >= unit) {
+            // The remainder is below 2^30, so the decimal cannot overflow 64 bits.
+            Some(&(unit, name)) => write!(w, "{}.{} {}", self.0 / unit,
+                                          (self.0 % unit) as u64 * 10 / unit as u64, name),
+            None => write!(w, "{} B", self.0),
+        }
+    }
+}
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use core::fmt::Write;
+
+        // This is used in log lines, so it must not allocate. The count is written straight to
+        // the formatter, so the padding is found by measuring it first.
+        let mut len = Counter(0);
+        self.write(&mut len)?;
+        let (before, after) = match f.width() {
+            Some(width) if width > len.0 => {
+                let pad = width - len.0;
+                match f.align() {
+                    fmt::Alignment::Right => (pad, 0),
+                    fmt::Alignment::Center => (pad / 2, pad - pad / 2),
+                    // Like strings, byte counts are aligned left by default.
+                    fmt::Alignment::Left | fmt::Alignment::Unknown => (0, pad),
+                }
+            },
+            _ => (0, 0),
+        };
+
+        let fill = f.fill();
+        for _ in 0..before {
+            f.write_char(fill)?;
+        }
+        self.write(f)?;
+        for _ in 0..after {
+            f.write_char(fill)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A writer counting the written bytes.
+struct Counter(usize);
+
+impl fmt::Write for Counter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+/// Write a human readable report of the allocator statistics.
+pub fn write_report<W: fmt::Write>(w: &mut W) -> fmt::Result {
+    let stats = snapshot();
+
+    writeln!(w, "ralloc statistics:")?;
+    writeln!(w, "  bootstrap arena: {}", Bytes(stats.bootstrap_bytes))?;
+    writeln!(w, "  reserved at initialization: {}", Bytes(stats.early_reserved))?;
+    writeln!(w, "  preallocated: {} ({} free), grown on demand: {}", Bytes(stats.preallocated),
+             Bytes(stats.preallocated_free), Bytes(stats.demand_grown))?;
+    writeln!(w, "  free in the global pool: {} ({} growths raced)", Bytes(stats.global_free),
+             stats.raced_growths)?;
+    writeln!(w, "  realloc slack: {}", Bytes(stats.realloc_slack))?;
+    writeln!(w, "  secure allocations: {} ({})", stats.secure_count, Bytes(stats.secure_bytes))?;
+    writeln!(w, "  heaps: {} ({}), leaked: {}", stats.heap_count, Bytes(stats.heap_bytes),
+             Bytes(stats.heap_leaked))?;
+    writeln!(w, "  slabs: {} ({} in cells)", stats.slab_count, Bytes(stats.slab_bytes))?;
+    writeln!(w, "  reallocations: {} inplace, {} left, {} copied", stats.realloc_inplace,
+             stats.realloc_left, stats.realloc_copy)?;
+    writeln!(w, "  blocks passed over to avoid fragments: {}", stats.split_skips)?;
+    writeln!(w, "  allocations above the size limit: {}", stats.too_large)?;
+    writeln!(w, "  alignment: {} natural, {} explicit", stats.align_natural, stats.align_explicit)?;
+    writeln!(w, "  random seed: {}", random::source().map_or("none drawn (or explicit)",
+                                                            |x| x.name()))?;
+
+    writeln!(w, "  {:>10} {:>10} {:>10} {:>10} {:>12}", "class", "live", "allocs", "frees", "bytes")?;
+    for class in SizeClass::iter() {
+        let stats = self::class(class);
+
+        // Skip the classes, which have never been used.
+        if stats.allocs == 0 && stats.frees == 0 { continue; }
+
+        match class.size() {
+            Some(size) => write!(w, "  {:>10}", size)?,
+            None => write!(w, "  {:>10}", "large")?,
+        }
+        writeln!(w, " {:>10} {:>10} {:>10} {:>12}", stats.count, stats.allocs, stats.frees,
+                 Bytes(stats.bytes))?;
+    }
+
+    let histogram = size_histogram();
+    writeln!(w, "  {:>10} {:>10} {:>10}", "size", "requests", "waste")?;
+    for i in 0..BUCKETS {
+        let (requested, waste) = (histogram.requested.count(i), histogram.waste.count(i));
+
+        // Skip the empty buckets.
+        if requested == 0 && waste == 0 { continue; }
+
+        // Show the lower bound of the bucket.
+        writeln!(w, "  {:>10} {:>10} {:>10}", Bytes(if i == 0 { 0 } else { 1 << (i - 1) }),
+                 requested, waste)?;
+    }
+
+    let aligns = align_histogram();
+    writeln!(w, "  {:>10} {:>10}", "align", "requests")?;
+    for i in 1..BUCKETS {
+        // Skip the unused alignments.
+        if aligns.count(i) == 0 { continue; }
+
+        writeln!(w, "  {:>10} {:>10}", 1usize << (i - 1), aligns.count(i))?;
+    }
+
+    let lifetimes = lifetime_histogram();
+    write!(w, "  {:>10}", "lifetime")?;
+    for i in 0..LIFETIME_BUCKETS {
+        // Show the lower bound of the bucket, in generations.
+        write!(w, " {:>6}", if i == 0 { 0 } else { 1 << (i - 1) })?;
+    }
+    writeln!(w, "")?;
+    for class in SizeClass::iter() {
+        // Skip the classes without a freed allocation of known age.
+        if lifetimes.counts(class).iter().all(|&x| x == 0) { continue; }
+
+        match class.size() {
+            Some(size) => write!(w, "  {:>10}", size)?,
+            None => write!(w, "  {:>10}", "large")?,
+        }
+        for &count in lifetimes.counts(class) {
+            write!(w, " {:>6}", count)?;
+        }
+        writeln!(w, "")?;
+    }
+
+    #[cfg(feature = "tagging")]
+    {
+        writeln!(w, "  {:>10} {:>10} {:>12}", "tag", "live", "bytes")?;
+        for (tag, stats) in by_tag().iter() {
+            writeln!(w, "  {:>10} {:>10} {:>12}", tag, stats.count, Bytes(stats.bytes))?;
+        }
+    }
+
+    #[cfg(feature = "arenas")]
+    {
+        writeln!(w, "  {:>10} {:>12} {:>12} {:>10} {:>10}", "arena", "owned", "free", "refills",
+                 "stolen")?;
+        for (n, stats) in arenas().iter().enumerate() {
+            writeln!(w, "  {:>10} {:>12} {:>12} {:>10} {:>10}", n, Bytes(stats.owned),
+                     Bytes(stats.free), stats.refills, stats.stolen)?;
+        }
+    }
+
+    #[cfg(feature = "numa")]
+    {
+        writeln!(w, "  {:>10} {:>12}", "node", "placed")?;
+        for (n, &bytes) in by_node().iter().enumerate().take(numa::nodes()) {
+            writeln!(w, "  {:>10} {:>12}", n, Bytes(bytes))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use core::{fmt, mem};
+
+    use sync;
+
+    #[test]
+    fn test_counters_layout() {
+        // Every class has a line of its own.
+        assert_eq!(mem::size_of::<ClassCounters>(), (class::COUNT + 1) * sync::CACHE_LINE);
+        assert_eq!(&CLASSES as *const _ as usize % sync::CACHE_LINE, 0);
+    }
+
+    #[test]
+    fn test_class_counters() {
+        let counters = ClassCounters::new();
+
+        // A scripted workload.
+        counters.alloc(8);
+        counters.alloc(16);
+        counters.alloc(100);
+        counters.alloc(1000);
+        counters.free(16);
+        counters.alloc(2000);
+        counters.free(1000);
+
+        assert_eq!(counters.get(SizeClass::of(1)), ClassStats {
+            count: 1,
+            allocs: 2,
+            frees: 1,
+            bytes: 8,
+        });
+        assert_eq!(counters.get(SizeClass::of(100)), ClassStats {
+            count: 1,
+            allocs: 1,
+            frees: 0,
+            bytes: 100,
+        });
+        assert_eq!(counters.get(SizeClass::large()), ClassStats {
+            count: 1,
+            allocs: 2,
+            frees: 1,
+            bytes: 2000,
+        });
+        assert_eq!(counters.get(SizeClass::of(300)), ClassStats::default());
+    }
+
+    #[test]
+    fn test_bucket() {
+        assert_eq!(bucket(0), 0);
+        assert_eq!(bucket(1), 1);
+        assert_eq!(bucket(2), 2);
+        assert_eq!(bucket(3), 2);
+        assert_eq!(bucket(4), 3);
+        assert_eq!(bucket(127), 7);
+        assert_eq!(bucket(128), 8);
+        assert_eq!(bucket(!0), BUCKETS - 1);
+    }
+
+    #[test]
+    fn test_histograms() {
+        let histograms = Histograms::new();
+
+        // A scripted workload.
+        histograms.record(0, 0);
+        histograms.record(1, 16);
+        histograms.record(100, 112);
+        histograms.record(100, 128);
+        histograms.record(1000, 1000);
+        histograms.record(1024, 1024);
+
+        let histogram = histograms.get();
+        assert_eq!(histogram.requested.total(), 6);
+        assert_eq!(histogram.requested.count(0), 1);
+        assert_eq!(histogram.requested.count(1), 1);
+        assert_eq!(histogram.requested.count(7), 2);
+        assert_eq!(histogram.requested.count(10), 1);
+        assert_eq!(histogram.requested.count(11), 1);
+
+        // The waste is 0, 15, 12, 28, 0, and 0 bytes.
+        assert_eq!(histogram.waste.total(), 6);
+        assert_eq!(histogram.waste.count(0), 3);
+        assert_eq!(histogram.waste.count(4), 2);
+        assert_eq!(histogram.waste.count(5), 1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "single_threaded"))]
+    fn test_class_counters_threaded() {
+        extern crate std;
+
+        use self::std::thread;
+        use self::std::sync::Arc;
+
+        let counters = Arc::new(ClassCounters::new());
+
+        let mut handles = self::std::vec::Vec::new();
+        for i in 0..8 {
+            let counters = counters.clone();
+            handles.push(thread::spawn(move || {
+                for _ in 0..1000 {
+                    counters.alloc(40 + i);
+                    counters.alloc(4000);
+                    counters.free(4000);
+                }
+            }));
+        }
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // Once every thread is done, the counters must agree exactly.
+        let small = counters.get(SizeClass::of(40));
+        assert_eq!(small.allocs, 8000);
+        assert_eq!(small.count, 8000);
+        assert_eq!(small.bytes, (0..8).map(|i| 1000 * (40 + i)).sum::<usize>());
+
+        let large = counters.get(SizeClass::large());
+        assert_eq!(large.count, 0);
+        assert_eq!(large.allocs, 8000);
+        assert_eq!(large.frees, 8000);
+        assert_eq!(large.bytes, 0);
+    }
+
+    /// A writer into a fixed buffer.
+    struct Buf([u8; 64], usize);
+
+    impl Buf {
+        fn as_str(&self) -> &str {
+            ::core::str::from_utf8(&self.0[..self.1]).unwrap()
+        }
+    }
+
+    impl fmt::Write for Buf {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.0[self.1..self.1 + s.len()].copy_from_slice(s.as_bytes());
+            self.1 += s.len();
+            Ok(())
+        }
+    }
+
+    /// Format `bytes` with the format string `fmt`.
+    macro_rules! check {
+        ($fmt:expr, $bytes:expr, $expected:expr) => {{
+            use core::fmt::Write;
+
+            let mut buf = Buf([0; 64], 0);
+            write!(buf, $fmt, Bytes($bytes)).unwrap();
+            assert_eq!(buf.as_str(), $expected);
+        }};
+    }
+
+    #[test]
+    fn test_bytes() {
+        use core::usize;
+
+        check!("{}", 0, "0 B");
+        check!("{}", 1023, "1023 B");
+        check!("{}", 1024, "1.0 KiB");
+        check!("{}", 1536, "1.5 KiB");
+        check!("{}", (1 << 20) - 1, "1023.9 KiB");
+        check!("{}", 5 << 20, "5.0 MiB");
+        check!("{}", (3 << 30) + (1 << 29), "3.5 GiB");
+        // The largest count doesn't overflow.
+        check!("{}", usize::MAX, if cfg!(target_pointer_width = "64") {
+            "17179869183.9 GiB"
+        } else {
+            "3.9 GiB"
+        });
+
+        // Padding is respected.
+        check!("{:>10}", 1536, "   1.5 KiB");
+        check!("{:<8}|", 10, "10 B    |");
+        check!("{:*^11}", 1536, "**1.5 KiB**");
+        check!("{:4}", 5 << 20, "5.0 MiB");
+    }
+
+    #[test]
+    fn test_report() {
+        let mut counter = Counter(0);
+        write_report(&mut counter).unwrap();
+
+        assert!(counter.0 > 0);
+    }
+}
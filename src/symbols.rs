@@ -17,8 +17,21 @@ pub extern fn __rust_allocate(size: usize, align: usize) -> *mut u8 {
 #[linkage = "external"]
 #[no_mangle]
 #[inline]
-pub unsafe extern fn __rust_deallocate(ptr: *mut u8, size: usize, _align: usize) {
-    allocator::free(ptr, size);
+pub unsafe extern fn __rust_deallocate(ptr: *mut u8, size: usize, align: usize) {
+    allocator::dealloc_sized(ptr, size, align);
+}
+
+/// Sized deallocation symbol for C and C++.
+///
+/// This is like `sdallocx` of jemalloc: `size` and `align` must be the ones the buffer was
+/// allocated with. Null pointers are ignored.
+#[linkage = "external"]
+#[no_mangle]
+#[inline]
+pub unsafe extern fn ralloc_sized_free(ptr: *mut u8, size: usize, align: usize) {
+    if !ptr.is_null() {
+        allocator::dealloc_sized(ptr, size, align);
+    }
 }
 
 /// Rust reallocation symbol.
@@ -1,32 +1,209 @@
 //! Synchronization primitives.
 
 use core::cell::UnsafeCell;
-use core::sync::atomic::{self, AtomicBool};
+use atomic::{self, AtomicU32, AtomicUsize};
+use core::cmp;
 use core::ops;
 
 use shim;
+use shim::config;
+
+#[cfg(feature = "debug_locks")]
+use core::cell::Cell;
+
+#[cfg(feature = "debug_locks")]
+use tls;
+
+/// The rank of a lock.
+///
+/// Ranked locks must be acquired in strictly increasing rank order. When the `debug_locks`
+/// feature is enabled, this order is checked on every acquisition, catching potential deadlocks
+/// (order inversion) and reentrancy (e.g. allocating from inside the allocator) early.
+///
+/// Rank 0 denotes an unranked lock, which is never checked.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Rank(u8);
+
+/// The canonical lock order.
+///
+/// A lock with a lower rank must always be acquired before a lock with a higher one.
+pub mod rank {
+    use super::Rank;
+
+    /// Unranked locks.
+    ///
+    /// These are not checked at all (the log lock is one such, since the checker itself logs).
+    pub const UNRANKED: Rank = Rank(0);
+    /// The front end (thread-local allocator state).
+    pub const FRONT_END: Rank = Rank(1);
+    /// The arenas between the local allocators and the global pool.
+    ///
+    /// Arenas are never nested, so they share a rank.
+    pub const ARENA: Rank = Rank(2);
+    /// The registry of the lists of deferred frees (see `allocator::relieve`).
+    ///
+    /// The lists are flushed from under the arenas, when the heap cannot grow.
+    pub const PENDING_LISTS: Rank = Rank(3);
+    /// A list of deferred frees.
+    pub const PENDING: Rank = Rank(4);
+    /// The global pool (the global allocator).
+    pub const POOL: Rank = Rank(5);
+    /// Refilling a local allocator from its upstream.
+    pub const ARENA_REFILL: Rank = Rank(6);
+    /// The program break.
+    pub const BRK: Rank = Rank(7);
+    /// The region registry.
+    pub const REGION: Rank = Rank(8);
+    /// The random number generator.
+    ///
+    /// It is drawn from under any of the other locks.
+    pub const RANDOM: Rank = Rank(9);
+    /// The shadow accountant.
+    ///
+    /// It records from under any of the other locks.
+    pub const SHADOW: Rank = Rank(10);
+    /// The tails kept after shrunk buffers (see `slack`).
+    ///
+    /// They are given back from under the arenas, when the heap cannot grow.
+    pub const SLACK: Rank = Rank(11);
+}
+
+/// The maximal number of ranked locks a thread can hold at once.
+#[cfg(feature = "debug_locks")]
+const MAX_HELD_LOCKS: usize = 8;
+
+/// The set of ranked locks held by a thread.
+///
+/// The locks are stored in acquisition order.
+#[cfg(feature = "debug_locks")]
+#[derive(Clone, Copy)]
+struct HeldLocks {
+    /// The name, rank and address of the held locks.
+    ///
+    /// Distinct locks can share a rank, so a lock is told apart by its address.
+    locks: [(&'static str, Rank, usize); MAX_HELD_LOCKS],
+    /// The number of held locks.
+    len: usize,
+}
+
+#[cfg(feature = "debug_locks")]
+tls! {
+    /// The ranked locks held by the current thread.
+    static HELD_LOCKS: Cell<HeldLocks> = Cell::new(HeldLocks {
+        locks: [("", rank::UNRANKED, 0); MAX_HELD_LOCKS],
+        len: 0,
+    });
+}
+
+/// Check the lock order and register the acquisition of a lock.
+///
+/// This aborts (through the assertion) if the lock at `addr` is already held by the current
+/// thread, or if another lock of higher or equal rank is held.
+#[cfg(feature = "debug_locks")]
+fn acquire_rank(name: &'static str, rank: Rank, addr: usize) {
+    if rank == rank::UNRANKED { return; }
+
+    HELD_LOCKS.with(|held_locks| {
+        let mut held = held_locks.get();
+
+        for &(held_name, held_rank, held_addr) in &held.locks[..held.len] {
+            // Check for reentrancy.
+            assert!(held_addr != addr, "Reentrant acquisition of lock '{}' (rank {:?}), which is \
+                    already held by this thread.", name, rank);
+            // Check for order inversion.
+            assert!(held_rank < rank, "Lock order violation: acquiring '{}' (rank {:?}) while \
+                    holding '{}' (rank {:?}).", name, rank, held_name, held_rank);
+        }
+
+        assert!(held.len < MAX_HELD_LOCKS, "Too many locks held by this thread.");
+
+        held.locks[held.len] = (name, rank, addr);
+        held.len += 1;
+        held_locks.set(held);
+    });
+}
+
+/// Register the release of the lock at `addr`.
+#[cfg(feature = "debug_locks")]
+fn release_rank(rank: Rank, addr: usize) {
+    if rank == rank::UNRANKED { return; }
+
+    HELD_LOCKS.with(|held_locks| {
+        let mut held = held_locks.get();
+
+        // Locks need not be released in acquisition order, so we search for the lock.
+        if let Some(n) = held.locks[..held.len].iter().position(|&(_, _, x)| x == addr) {
+            // Move the following locks one place to the left.
+            for i in n..held.len - 1 {
+                held.locks[i] = held.locks[i + 1];
+            }
+            held.len -= 1;
+
+            held_locks.set(held);
+        } else {
+            debug_assert!(false, "Releasing a lock (rank {:?}), which is not held.", rank);
+        }
+    });
+}
+
+/// The mutex is unlocked.
+const UNLOCKED: u32 = 0;
+/// The mutex is locked, and no thread is parked on it.
+const LOCKED: u32 = 1;
+/// The mutex is locked, and threads might be parked on it.
+const CONTENDED: u32 = 2;
 
 /// A mutual exclusive container.
 ///
 /// This assures that only one holds mutability of the inner value. To get the inner value, you
 /// need acquire the "lock". If you try to lock it while a lock is already held elsewhere, it will
 /// block the thread until the lock is released.
+///
+/// The lock is adaptive: It first spins with exponential backoff for a bounded number of rounds,
+/// and then parks the thread on a futex (or yields, where futexes aren't available). Since no
+/// thread-local state is involved, it is usable before TLS is initialized.
+///
+/// With the `critical_section` feature, interrupts are disabled while the lock is held, and the
+/// lock never parks (there is nothing to park on without an OS).
+///
+/// With the `single_threaded` feature, the state is a plain cell (see the `atomic` module), and
+/// finding the lock held means that it is being reacquired, which panics rather than spinning
+/// forever.
 pub struct Mutex<T> {
     /// The inner value.
     inner: UnsafeCell<T>,
-    /// The lock boolean.
+    /// The lock state.
+    ///
+    /// This is either `UNLOCKED`, `LOCKED`, or `CONTENDED`.
+    state: AtomicU32,
+    /// The name of the lock.
     ///
-    /// This is true, if and only if the lock is currently held.
-    locked: AtomicBool,
+    /// This is used in the lock order violation messages.
+    #[cfg_attr(not(any(feature = "debug_locks", feature = "single_threaded")), allow(dead_code))]
+    name: &'static str,
+    /// The rank of the lock.
+    rank: Rank,
 }
 
 impl<T> Mutex<T> {
     /// Create a new mutex with some inner value.
+    ///
+    /// The mutex is unranked, meaning that it will not take part in the lock order checks.
     #[inline]
     pub const fn new(inner: T) -> Mutex<T> {
+        Mutex::ranked("unranked", rank::UNRANKED, inner)
+    }
+
+    /// Create a new ranked mutex with some inner value.
+    ///
+    /// See [`Rank`](./struct.Rank.html) for details.
+    #[inline]
+    pub const fn ranked(name: &'static str, rank: Rank, inner: T) -> Mutex<T> {
         Mutex {
             inner: UnsafeCell::new(inner),
-            locked: AtomicBool::new(false),
+            state: AtomicU32::new(UNLOCKED),
+            name: name,
+            rank: rank,
         }
     }
 
@@ -35,18 +212,97 @@ impl<T> Mutex<T> {
     /// If another lock is held, this will block the thread until it is released.
     #[inline]
     pub fn lock(&self) -> MutexGuard<T> {
+        // Check the lock order. This must happen before locking, since a reentrant acquisition
+        // would otherwise spin forever.
+        #[cfg(feature = "debug_locks")]
+        acquire_rank(self.name, self.rank, self as *const Mutex<T> as usize);
+
+        // Disable interrupts, such that an interrupt handler can't deadlock on the lock.
+        #[cfg(feature = "critical_section")]
+        let critical = shim::critical::enter();
+
         // Lock the mutex.
         #[cfg(not(feature = "unsafe_no_mutex_lock"))]
-        while self.locked.compare_and_swap(false, true, atomic::Ordering::SeqCst) {
-            // ,___,
-            // {O,o}
-            // |)``)
-            // SRSLY?
-            shim::syscalls::sched_yield();
+        {
+            // Try the uncontended fast path first.
+            if self.state.compare_and_swap(UNLOCKED, LOCKED, atomic::Ordering::Acquire) != UNLOCKED {
+                self.lock_contended();
+            }
         }
 
         MutexGuard {
             mutex: self,
+            #[cfg(feature = "critical_section")]
+            critical: critical,
+        }
+    }
+
+    /// Lock the mutex in the contended case.
+    ///
+    /// This spins for a while, and then parks the thread until the lock is released.
+    #[cfg(not(feature = "single_threaded"))]
+    #[cold]
+    #[inline(never)]
+    fn lock_contended(&self) {
+        // Spin with exponential backoff.
+        let mut backoff = 1;
+        for _ in 0..config::MUTEX_SPIN_LIMIT {
+            for _ in 0..backoff {
+                shim::syscalls::cpu_relax();
+            }
+            backoff = cmp::min(2 * backoff, config::MUTEX_MAX_BACKOFF);
+
+            // Only try to acquire it if it seems to be unlocked to avoid bouncing the cache line.
+            if self.state.load(atomic::Ordering::Relaxed) == UNLOCKED
+                && self.state.compare_and_swap(UNLOCKED, LOCKED, atomic::Ordering::Acquire) == UNLOCKED {
+                return;
+            }
+        }
+
+        // ,___,
+        // {O,o}
+        // |)``)
+        // SRSLY?
+
+        // Without an OS, there is nothing to park on, so we keep spinning.
+        #[cfg(feature = "critical_section")]
+        {
+            while self.state.compare_and_swap(UNLOCKED, LOCKED, atomic::Ordering::Acquire) != UNLOCKED {
+                shim::syscalls::cpu_relax();
+            }
+        }
+
+        // Park the thread. We mark the lock contended, such that the releaser knows it needs to
+        // wake us up. Since we cannot know if other threads are parked, we must keep it
+        // contended when we acquire it this way.
+        #[cfg(not(feature = "critical_section"))]
+        {
+            while self.state.swap(CONTENDED, atomic::Ordering::Acquire) != UNLOCKED {
+                shim::syscalls::futex_wait(&self.state as *const AtomicU32 as *const u32, CONTENDED);
+            }
+        }
+    }
+
+    /// Fail on a held lock.
+    ///
+    /// There are no other threads to release it, so the current thread is already holding it.
+    #[cfg(feature = "single_threaded")]
+    #[cold]
+    #[inline(never)]
+    fn lock_contended(&self) -> ! {
+        panic!("Reentrant acquisition of the {} lock.", self.name);
+    }
+
+    /// Unlock this mutex.
+    ///
+    /// If threads are parked on it, one of them is woken up.
+    #[inline]
+    fn unlock(&self) {
+        #[cfg(not(feature = "unsafe_no_mutex_lock"))]
+        {
+            if self.state.swap(UNLOCKED, atomic::Ordering::Release) == CONTENDED {
+                shim::syscalls::futex_wake(&self.state as *const AtomicU32 as *const u32, 1);
+            }
         }
     }
 }
@@ -58,13 +314,22 @@ impl<T> Mutex<T> {
 pub struct MutexGuard<'a, T: 'a> {
     /// The parent mutex.
     mutex: &'a Mutex<T>,
+    /// The interrupt state to restore, when the lock is released.
+    #[cfg(feature = "critical_section")]
+    critical: usize,
 }
 
 /// Release the mutex.
 impl<'a, T> Drop for MutexGuard<'a, T> {
     #[inline]
     fn drop(&mut self) {
-        self.mutex.locked.store(false, atomic::Ordering::SeqCst);
+        self.mutex.unlock();
+
+        #[cfg(feature = "debug_locks")]
+        release_rank(self.mutex.rank, self.mutex as *const Mutex<T> as usize);
+
+        #[cfg(feature = "critical_section")]
+        shim::critical::exit(self.critical);
     }
 }
 
@@ -96,6 +361,124 @@ impl<'a, T> ops::DerefMut for MutexGuard<'a, T> {
 unsafe impl<T: Send> Send for Mutex<T> {}
 unsafe impl<T: Send> Sync for Mutex<T> {}
 
+/// The size of a cache line.
+pub const CACHE_LINE: usize = 64;
+
+/// A cache line.
+///
+/// Vectors are aligned to their size, so this is aligned to `CACHE_LINE`.
+#[repr(simd)]
+#[derive(Clone, Copy)]
+struct CacheLine(u64, u64, u64, u64, u64, u64, u64, u64);
+
+/// A value on cache lines of its own.
+///
+/// The value is aligned to the cache line, and padded to a whole number of cache lines, such that
+/// no other value shares its lines. This avoids false sharing, where threads writing unrelated
+/// values keep stealing the line from each other (or from threads reading them).
+pub struct CachePadded<T> {
+    /// The inner value.
+    inner: T,
+    /// Force the alignment (and thus the padding).
+    _line: [CacheLine; 0],
+}
+
+impl<T> CachePadded<T> {
+    /// Pad a value.
+    #[inline]
+    pub const fn new(inner: T) -> CachePadded<T> {
+        CachePadded {
+            inner: inner,
+            _line: [],
+        }
+    }
+}
+
+impl<T> ops::Deref for CachePadded<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> ops::DerefMut for CachePadded<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+/// The initializer of a `Once` hasn't run.
+const ONCE_UNINIT: usize = 0;
+/// The initializer of a `Once` is running.
+const ONCE_INITIALIZING: usize = 1;
+/// The initializer of a `Once` has completed.
+const ONCE_READY: usize = 2;
+
+/// A one-time initialization.
+///
+/// The state goes from uninitialized, over initializing, to ready, exactly once. The first caller
+/// runs the initializer, while the callers racing it wait for it to complete, such that no caller
+/// returns before the initialization is done.
+pub struct Once {
+    /// The state (`ONCE_UNINIT`, `ONCE_INITIALIZING`, or `ONCE_READY`).
+    state: AtomicUsize,
+}
+
+impl Once {
+    /// Create a new `Once`, whose initializer hasn't run.
+    #[inline]
+    pub const fn new() -> Once {
+        Once {
+            state: AtomicUsize::new(ONCE_UNINIT),
+        }
+    }
+
+    /// Has the initializer completed?
+    #[inline]
+    pub fn is_ready(&self) -> bool {
+        self.state.load(atomic::Ordering::Acquire) == ONCE_READY
+    }
+
+    /// Run `init`, unless the initializer has already run (or is running).
+    ///
+    /// If another thread is running the initializer, this waits for it to complete. Whether this
+    /// call ran `init` is returned.
+    ///
+    /// `init` must not call `call_once` on the same `Once`, as it would wait for itself forever.
+    #[inline]
+    pub fn call_once<F: FnOnce()>(&self, init: F) -> bool {
+        // The fast path: a single load.
+        if self.is_ready() {
+            return false;
+        }
+
+        self.call_once_slow(init)
+    }
+
+    /// Run `init` or wait for the initializer in the uncommon case.
+    #[cold]
+    #[inline(never)]
+    fn call_once_slow<F: FnOnce()>(&self, init: F) -> bool {
+        if self.state.compare_and_swap(ONCE_UNINIT, ONCE_INITIALIZING, atomic::Ordering::Acquire)
+            == ONCE_UNINIT {
+            init();
+            self.state.store(ONCE_READY, atomic::Ordering::Release);
+
+            true
+        } else {
+            // Initializers are short, so we simply spin, which works without an OS as well.
+            while !self.is_ready() {
+                shim::syscalls::cpu_relax();
+            }
+
+            false
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -111,4 +494,160 @@ mod test {
         *mutex.lock() = 0xFF;
         assert_eq!(*mutex.lock(), 0xFF);
     }
+
+    #[test]
+    #[cfg(not(feature = "single_threaded"))]
+    fn test_wake() {
+        extern crate std;
+
+        use self::std::{thread, time};
+        use self::std::sync::Arc;
+        use self::std::sync::atomic::{AtomicUsize, Ordering};
+
+        // The events are numbered in the order they happen.
+        let events = Arc::new(AtomicUsize::new(0));
+        let mutex = Arc::new(Mutex::new(None));
+        let mut guard = mutex.lock();
+
+        let handle = {
+            let events = events.clone();
+            let mutex = mutex.clone();
+            thread::spawn(move || {
+                let blocked = events.fetch_add(1, Ordering::SeqCst);
+                // This blocks (and eventually parks) until the main thread releases the lock.
+                let released = *mutex.lock();
+                let acquired = events.fetch_add(1, Ordering::SeqCst);
+
+                (blocked, released, acquired)
+            })
+        };
+
+        // Give the thread time to park. This is no condition of the test, which holds either way.
+        while events.load(Ordering::SeqCst) == 0 {
+            thread::yield_now();
+        }
+        thread::sleep(time::Duration::from_millis(100));
+
+        // Record the release and release the lock.
+        *guard = Some(events.fetch_add(1, Ordering::SeqCst));
+        drop(guard);
+
+        // The thread wakes, and acquires the lock only after the release.
+        assert_eq!(handle.join().unwrap(), (0, Some(1), 2));
+    }
+
+    #[test]
+    fn test_cache_padded() {
+        use core::mem;
+
+        // Small values take a single line.
+        assert_eq!(mem::size_of::<CachePadded<u8>>(), CACHE_LINE);
+        assert_eq!(mem::align_of::<CachePadded<u8>>(), CACHE_LINE);
+        assert_eq!(mem::size_of::<CachePadded<[usize; 3]>>(), CACHE_LINE);
+        // Larger values are rounded up to whole lines.
+        assert_eq!(mem::size_of::<CachePadded<[u8; CACHE_LINE + 1]>>(), 2 * CACHE_LINE);
+        // Mutexes are no exception.
+        assert_eq!(mem::size_of::<CachePadded<Mutex<usize>>>(), CACHE_LINE);
+
+        // Consecutive values never share a line.
+        let pair = [CachePadded::new(1u8), CachePadded::new(2u8)];
+        assert_eq!(&*pair[1] as *const u8 as usize - &*pair[0] as *const u8 as usize, CACHE_LINE);
+        assert_eq!(*pair[0] + *pair[1], 3);
+    }
+
+    #[test]
+    #[cfg(not(feature = "single_threaded"))]
+    fn test_once_race() {
+        extern crate std;
+
+        use self::std::{thread, time};
+        use self::std::sync::{Arc, Barrier};
+        use self::std::vec::Vec;
+
+        static ONCE: Once = Once::new();
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        static DONE: AtomicUsize = AtomicUsize::new(0);
+
+        let barrier = Arc::new(Barrier::new(16));
+        let handles: Vec<_> = (0..16).map(|_| {
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+
+                let ran = ONCE.call_once(|| {
+                    RUNS.fetch_add(1, atomic::Ordering::SeqCst);
+                    // Keep the others waiting for a while.
+                    thread::sleep(time::Duration::from_millis(20));
+                    DONE.store(1, atomic::Ordering::SeqCst);
+                });
+
+                // Nobody returns before the initializer completed.
+                assert!(ONCE.is_ready());
+                assert_eq!(DONE.load(atomic::Ordering::SeqCst), 1);
+
+                ran
+            })
+        }).collect();
+
+        let ran = handles.into_iter().map(|x| x.join().unwrap()).filter(|&x| x).count();
+        assert_eq!(ran, 1);
+        assert_eq!(RUNS.load(atomic::Ordering::SeqCst), 1);
+
+        // Later calls are NOOPs.
+        assert!(!ONCE.call_once(|| panic!("The initializer ran twice.")));
+    }
+
+    #[test]
+    fn test_ranked_order() {
+        let outer = Mutex::ranked("outer", rank::POOL, 1);
+        let inner = Mutex::ranked("inner", rank::BRK, 2);
+
+        let a = outer.lock();
+        let b = inner.lock();
+        assert_eq!(*a + *b, 3);
+
+        // Release in non-acquisition order.
+        drop(a);
+        drop(b);
+
+        // The locks are released, so we can acquire them again.
+        let _a = outer.lock();
+        let _b = inner.lock();
+    }
+
+    #[test]
+    #[should_panic(expected = "acquiring 'outer' (rank Rank(5)) while holding 'inner' (rank Rank(7))")]
+    #[cfg(feature = "debug_locks")]
+    fn test_order_inversion() {
+        let outer = Mutex::ranked("outer", rank::POOL, ());
+        let inner = Mutex::ranked("inner", rank::BRK, ());
+
+        let _b = inner.lock();
+        let _a = outer.lock();
+    }
+
+    #[test]
+    #[should_panic(expected = "Reentrant acquisition of lock 'pool'")]
+    #[cfg(feature = "debug_locks")]
+    fn test_reentrancy() {
+        let mutex = Mutex::ranked("pool", rank::POOL, ());
+
+        let _a = mutex.lock();
+        let _b = mutex.lock();
+    }
+
+    #[test]
+    #[should_panic(expected = "acquiring 'right' (rank Rank(1)) while holding 'left' (rank Rank(1))")]
+    #[cfg(feature = "debug_locks")]
+    fn test_shared_rank() {
+        // Distinct locks of the same rank are no reentrancy, but they cannot be nested either.
+        let left = Mutex::ranked("left", rank::FRONT_END, ());
+        let right = Mutex::ranked("right", rank::FRONT_END, ());
+
+        drop(left.lock());
+        drop(right.lock());
+
+        let _a = left.lock();
+        let _b = right.lock();
+    }
 }
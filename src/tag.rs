@@ -0,0 +1,250 @@
+//! Allocation tags.
+//!
+//! With the `tagging` feature, allocations can carry a tag, attributing their memory to some
+//! subsystem. The tags are kept in an address-ordered side table, so untagged allocations carry
+//! no extra space, and the per-tag statistics are maintained alongside it.
+
+use prelude::*;
+
+use {sort, sync};
+
+/// The tags of the live allocations.
+static TAGS: sync::Mutex<Table> = sync::Mutex::ranked("tags", sync::rank::FRONT_END, Table::new());
+
+/// The untagged tag.
+pub const UNTAGGED: u8 = 0;
+
+/// The statistics of a tag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TagStats {
+    /// The number of live allocations.
+    pub count: usize,
+    /// The number of bytes in live allocations.
+    pub bytes: usize,
+}
+
+/// A snapshot of the statistics of every tag.
+#[derive(Clone, Copy)]
+pub struct TagTable {
+    /// The statistics, indexed by the tag.
+    stats: [TagStats; 256],
+}
+
+impl TagTable {
+    /// Get the statistics of a tag.
+    pub fn get(&self, tag: u8) -> TagStats {
+        self.stats[tag as usize]
+    }
+
+    /// Iterate over the tags in use, and their statistics.
+    ///
+    /// Tags without live allocations are skipped.
+    pub fn iter(&self) -> Tags {
+        Tags {
+            table: self,
+            tag: 0,
+        }
+    }
+}
+
+/// An iterator over the tags in use.
+pub struct Tags<'a> {
+    /// The table.
+    table: &'a TagTable,
+    /// The next tag to look at.
+    tag: usize,
+}
+
+impl<'a> Iterator for Tags<'a> {
+    type Item = (u8, TagStats);
+
+    fn next(&mut self) -> Option<(u8, TagStats)> {
+        while self.tag < 256 {
+            let tag = self.tag as u8;
+            self.tag += 1;
+
+            let stats = self.table.get(tag);
+            if stats != TagStats::default() {
+                return Some((tag, stats));
+            }
+        }
+
+        None
+    }
+}
+
+/// An address-ordered table of tagged allocations, with the per-tag statistics.
+struct Table {
+    /// The tagged allocations as `(address, size, tag)`, sorted by address.
+    ///
+    /// Untagged allocations are not stored.
+    entries: Vec<(usize, usize, u8)>,
+    /// The number of live allocations of each tag.
+    counts: [usize; 256],
+    /// The number of bytes in live allocations of each tag.
+    bytes: [usize; 256],
+}
+
+impl Table {
+    /// Create a new empty table.
+    const fn new() -> Table {
+        Table {
+            entries: Vec::new(),
+            counts: [0; 256],
+            bytes: [0; 256],
+        }
+    }
+
+    /// Find the index of the entry containing `addr`.
+    fn find(&self, addr: usize) -> Option<usize> {
+        sort::predecessor(&self.entries, &addr, |x| x.0).and_then(|n| {
+            let (base, size, _) = self.entries[n];
+            if addr - base < size { Some(n) } else { None }
+        })
+    }
+
+    /// Record an allocation.
+    fn insert(&mut self, addr: usize, size: usize, tag: u8) {
+        self.counts[tag as usize] += 1;
+        self.bytes[tag as usize] += size;
+
+        // Zero-sized allocations contain no bytes to look up.
+        if tag != UNTAGGED && size != 0 {
+            sort::insert(&mut self.entries, (addr, size, tag), |x| x.0);
+        }
+    }
+
+    /// Record a (possibly partial) free of the range `addr..addr + size`.
+    ///
+    /// The tag of the range is returned. Ranges outside the table are untagged. Partial frees of
+    /// untagged allocations are counted as frees, so the untagged count can wrap around when
+    /// partial frees are used.
+    fn remove(&mut self, addr: usize, size: usize) -> u8 {
+        let n = match if size == 0 { None } else { self.find(addr) } {
+            Some(n) => n,
+            None => {
+                self.counts[UNTAGGED as usize] = self.counts[UNTAGGED as usize].wrapping_sub(1);
+                self.bytes[UNTAGGED as usize] = self.bytes[UNTAGGED as usize].wrapping_sub(size);
+
+                return UNTAGGED;
+            },
+        };
+
+        let (base, old_size, tag) = self.entries[n];
+
+        // The parts of the entry surrounding the range.
+        let left = addr - base;
+        let right = (base + old_size).saturating_sub(addr + size);
+
+        self.bytes[tag as usize] -= old_size - left - right;
+
+        if left == 0 {
+            sort::remove(&mut self.entries, n);
+        } else {
+            self.entries[n].1 = left;
+        }
+        if right != 0 {
+            sort::insert(&mut self.entries, (addr + size, right, tag), |x| x.0);
+        }
+
+        // The allocation is only gone, when nothing is left of it.
+        if left == 0 && right == 0 {
+            self.counts[tag as usize] -= 1;
+        }
+
+        tag
+    }
+
+    /// Get the statistics of every tag.
+    fn stats(&self) -> TagTable {
+        let mut table = TagTable {
+            stats: [TagStats::default(); 256],
+        };
+        for tag in 0..256 {
+            table.stats[tag] = TagStats {
+                count: self.counts[tag],
+                bytes: self.bytes[tag],
+            };
+        }
+
+        table
+    }
+}
+
+/// Record an allocation with some tag.
+pub fn insert(ptr: *mut u8, size: usize, tag: u8) {
+    TAGS.lock().insert(ptr as usize, size, tag);
+}
+
+/// Record a (possibly partial) free.
+///
+/// The tag of the freed range is returned.
+pub fn remove(ptr: *mut u8, size: usize) -> u8 {
+    TAGS.lock().remove(ptr as usize, size)
+}
+
+/// Get the tag of the allocation containing `ptr`.
+///
+/// Pointers outside tagged allocations are untagged.
+pub fn get(ptr: *mut u8) -> u8 {
+    let table = TAGS.lock();
+    table.find(ptr as usize).map_or(UNTAGGED, |n| table.entries[n].2)
+}
+
+/// Get the statistics of every tag.
+pub fn stats() -> TagTable {
+    TAGS.lock().stats()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_balance() {
+        let mut table = Table::new();
+        table.insert(100, 100, 1);
+        table.insert(300, 50, 2);
+        table.insert(400, 10, 0);
+        table.insert(500, 20, 1);
+
+        assert_eq!(table.remove(300, 50), 2);
+        assert_eq!(table.remove(400, 10), 0);
+        assert_eq!(table.remove(500, 20), 1);
+
+        let stats = table.stats();
+        assert_eq!(stats.get(0), TagStats { count: 0, bytes: 0 });
+        assert_eq!(stats.get(1), TagStats { count: 1, bytes: 100 });
+        assert_eq!(stats.get(2), TagStats { count: 0, bytes: 0 });
+
+        // Only the tags in use are iterated.
+        let mut tags = stats.iter();
+        assert_eq!(tags.next(), Some((1, TagStats { count: 1, bytes: 100 })));
+        assert_eq!(tags.next(), None);
+    }
+
+    #[test]
+    fn test_partial_free() {
+        let mut table = Table::new();
+        table.insert(100, 100, 3);
+
+        // Free the middle, then the sides.
+        assert_eq!(table.remove(120, 30), 3);
+        assert_eq!(table.stats().get(3), TagStats { count: 1, bytes: 70 });
+        assert_eq!(table.remove(100, 20), 3);
+        assert_eq!(table.remove(150, 50), 3);
+        assert_eq!(table.stats().get(3), TagStats { count: 0, bytes: 0 });
+        assert_eq!(table.entries.len(), 0);
+    }
+
+    #[test]
+    fn test_untagged() {
+        let mut table = Table::new();
+        table.insert(100, 100, UNTAGGED);
+
+        // Untagged allocations are not stored.
+        assert_eq!(table.entries.len(), 0);
+        assert_eq!(table.remove(100, 100), UNTAGGED);
+        assert_eq!(table.stats().get(UNTAGGED), TagStats { count: 0, bytes: 0 });
+    }
+}
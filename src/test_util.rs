@@ -0,0 +1,164 @@
+//! Utilities for testing code managing blocks.
+//!
+//! This module is only available with the `test_util` feature. It exposes what the tests of this
+//! crate use themselves: blocks backed by plain buffers, pools built from a script of operations,
+//! a deterministic random number generator, and the consistency checks of the pool.
+
+use core::{mem, ops};
+
+use vec::Vec;
+
+pub use block::Block;
+pub use bookkeeper::{AllocAtError, Allocator, Bookkeeper, Checkpoint, Edge, Mutation, Relocator,
+                     RollbackError, advance_generation};
+pub use ptr::{Align, Pointer};
+pub use random::{seed as seed_random, set_deterministic, Rng};
+pub use shim::inject;
+#[cfg(feature = "numa")]
+pub use numa::set_nodes as set_numa_nodes;
+
+/// Create a block spanning a buffer.
+///
+/// # Safety
+///
+/// The block must not outlive the buffer, and the buffer must not be accessed through anything
+/// else while the block (or any block split off it) is in use.
+pub unsafe fn buffer_block(buf: &mut [u8]) -> Block {
+    Block::from_raw_parts(Pointer::new(buf.as_mut_ptr()), buf.len())
+}
+
+/// An operation of a pool script.
+///
+/// See `scripted_pool`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PoolOp {
+    /// Free the bytes `start..start + size` of the data buffer into the pool.
+    Free {
+        /// The offset into the data buffer.
+        start: usize,
+        /// The number of bytes.
+        size: usize,
+    },
+    /// Allocate a buffer from the pool, and forget it.
+    Alloc {
+        /// The size of the buffer.
+        size: usize,
+        /// The alignment of the buffer.
+        align: usize,
+    },
+}
+
+/// A pool over fixed buffers.
+///
+/// This never acquires fresh memory. When it runs out of memory, it panics.
+///
+/// Its minimal split remainder is zero, such that blocks are split exactly as scripted. It can be
+/// raised with `set_min_split_remainder`.
+pub struct TestPool {
+    /// The inner bookkeeper.
+    inner: Bookkeeper,
+}
+
+impl TestPool {
+    /// Create an empty pool, keeping its metadata in `meta`.
+    ///
+    /// # Safety
+    ///
+    /// The pool must not outlive `meta`.
+    pub unsafe fn new(meta: &mut [usize]) -> TestPool {
+        let mut inner = Bookkeeper::new(Vec::from_raw_parts(Block::from_raw_parts(
+            Pointer::new(meta.as_mut_ptr() as *mut u8),
+            meta.len() * mem::size_of::<usize>()
+        ), 0));
+        inner.set_min_split_remainder(0);
+
+        TestPool {
+            inner: inner,
+        }
+    }
+}
+
+impl ops::Deref for TestPool {
+    type Target = Bookkeeper;
+
+    fn deref(&self) -> &Bookkeeper {
+        &self.inner
+    }
+}
+
+impl ops::DerefMut for TestPool {
+    fn deref_mut(&mut self) -> &mut Bookkeeper {
+        &mut self.inner
+    }
+}
+
+impl Allocator for TestPool {
+    fn alloc_fresh(&mut self, _: usize, _: Align) -> Block {
+        panic!("Fresh allocation in a test pool.");
+    }
+}
+
+/// Build a pool by running a script.
+///
+/// The pool metadata lives in `meta`, and the blocks are taken from `data`. The pool is checked
+/// for consistency after every operation.
+///
+/// # Safety
+///
+/// The pool must not outlive the buffers, and the same range of `data` must not be freed twice.
+///
+/// # Panics
+///
+/// This panics if an alignment is invalid, an allocation doesn't fit, or a range is outside
+/// `data`.
+pub unsafe fn scripted_pool(meta: &mut [usize], data: &mut [u8], ops: &[PoolOp]) -> TestPool {
+    let mut pool = TestPool::new(meta);
+
+    for op in ops {
+        match *op {
+            PoolOp::Free { start, size } => pool.free(buffer_block(&mut data[start..start + size])),
+            PoolOp::Alloc { size, align } => {
+                pool.alloc(size, Align::new(align).expect("Invalid alignment in a pool script."));
+            },
+        }
+
+        pool.check();
+    }
+
+    pool
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rng() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        for _ in 0..100 {
+            assert_eq!(a.get(), b.get());
+        }
+
+        assert!(Rng::new(0).get() != 0);
+        assert!(Rng::new(7).below(10) < 10);
+    }
+
+    #[test]
+    fn test_scripted_pool() {
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+
+        let pool = unsafe {
+            scripted_pool(&mut meta, &mut data, &[
+                PoolOp::Free { start: 0, size: 64 },
+                PoolOp::Free { start: 128, size: 64 },
+                // Merges with both neighbors.
+                PoolOp::Free { start: 64, size: 64 },
+                PoolOp::Alloc { size: 16, align: 1 },
+            ])
+        };
+
+        assert_eq!(pool.total_bytes(), 192 - 16);
+    }
+}
@@ -37,6 +37,15 @@ impl<T: 'static> Key<T> {
         f(&self.inner)
     }
 
+    /// Obtain a reference temporarily, without logging the access.
+    ///
+    /// This is for the logger itself, which would otherwise recurse.
+    #[inline]
+    pub fn with_unlogged<F, R>(&'static self, f: F) -> R
+        where F: FnOnce(&T) -> R {
+        f(&self.inner)
+    }
+
     /// Register a TLS destructor on the current thread.
     ///
     /// Note that this has to be registered for every thread, it is needed for.
@@ -0,0 +1,777 @@
+//! Allocation traces.
+//!
+//! With the `trace` feature, every call of the public allocation functions is recorded in a ring
+//! of compact records, which can be read out and replayed against a pool. This allows capturing a
+//! workload (e.g. one blowing up the fragmentation) and reproducing it in a benchmark.
+//!
+//! The pointers are replaced by ids, assigned in sequence to the allocations. Recording never
+//! allocates: the ring and the table mapping live buffers to their ids have fixed capacity (see
+//! `config::TRACE_CAPACITY` and `config::TRACE_IDS`). When the ring is full, the oldest records are
+//! dropped and counted. When the id table is full, new buffers go untracked, and their records
+//! carry `UNTRACKED`, which replaying skips.
+//!
+//! The records are read out as trace documents of the wire format (see `wire`), holding chunks of
+//! records (`RECORDS`). Such a chunk starts with the size of its records, of which readers decode
+//! the first `RECORD_SIZE` bytes, so later versions can extend the records.
+
+use prelude::*;
+
+use core::{cmp, mem, usize};
+
+use shim::config;
+
+use bookkeeper::Allocator;
+use wire::{self, Writer};
+use {sort, sync};
+
+/// The trace.
+static TRACE: sync::Mutex<Recorder> = sync::Mutex::ranked("trace", sync::rank::FRONT_END,
+                                                           Recorder::new());
+
+/// The magic of trace documents.
+const MAGIC: &'static [u8; 8] = b"RALLOCTR";
+/// The version of trace documents.
+pub const VERSION: u32 = 1;
+/// The kind of the chunks of records.
+pub const RECORDS: u32 = 1;
+/// The size of an encoded record.
+pub const RECORD_SIZE: usize = 16;
+/// The id of untracked buffers.
+pub const UNTRACKED: u32 = !0;
+
+/// A traced operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// A buffer was allocated.
+    Alloc,
+    /// A buffer was freed.
+    Free,
+    /// A buffer was reallocated.
+    ///
+    /// The buffer keeps its id, even if it was moved.
+    Realloc,
+}
+
+/// A trace record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// The operation.
+    pub op: Op,
+    /// The id of the buffer.
+    pub id: u32,
+    /// The (new) size of the buffer.
+    pub size: usize,
+    /// The alignment of the buffer.
+    ///
+    /// Frees carry no alignment, so this is one for them.
+    pub align: usize,
+}
+
+impl Record {
+    /// Encode the record.
+    ///
+    /// The encoding is the operation and the base-two logarithm of the alignment as single
+    /// bytes, two bytes of padding, the id as a little-endian `u32`, and the size as a
+    /// little-endian `u64`.
+    pub fn encode(&self) -> [u8; RECORD_SIZE] {
+        let mut res = [0; RECORD_SIZE];
+
+        res[0] = match self.op {
+            Op::Alloc => 0,
+            Op::Free => 1,
+            Op::Realloc => 2,
+        };
+        res[1] = self.align.trailing_zeros() as u8;
+        for i in 0..4 {
+            res[4 + i] = (self.id >> (8 * i)) as u8;
+        }
+        for i in 0..8 {
+            res[8 + i] = (self.size as u64 >> (8 * i)) as u8;
+        }
+
+        res
+    }
+
+    /// Decode a record from the start of `bytes`.
+    ///
+    /// If `bytes` is too short or doesn't start with a valid record, `None` is returned.
+    pub fn decode(bytes: &[u8]) -> Option<Record> {
+        if bytes.len() < RECORD_SIZE {
+            return None;
+        }
+
+        let op = match bytes[0] {
+            0 => Op::Alloc,
+            1 => Op::Free,
+            2 => Op::Realloc,
+            _ => return None,
+        };
+        if bytes[1] as usize >= 8 * mem::size_of::<usize>() {
+            return None;
+        }
+
+        let mut id = 0;
+        for i in 0..4 {
+            id |= (bytes[4 + i] as u32) << (8 * i);
+        }
+        let mut size = 0;
+        for i in 0..8 {
+            size |= (bytes[8 + i] as u64) << (8 * i);
+        }
+        // Traces from 64-bit targets can hold sizes, which don't fit 32-bit ones.
+        if size > usize::MAX as u64 {
+            return None;
+        }
+
+        Some(Record {
+            op: op,
+            id: id,
+            size: size as usize,
+            align: 1 << bytes[1],
+        })
+    }
+}
+
+/// An iterator over the records of a trace document.
+///
+/// Chunks of unknown kinds are skipped. The iteration stops at the first invalid record, or where
+/// the document is malformed.
+pub struct Records<'a> {
+    /// The chunks left, or `None`, if the iteration stopped.
+    chunks: Option<wire::Reader<'a>>,
+    /// The records left of the current chunk.
+    bytes: &'a [u8],
+    /// The size of the records of the current chunk.
+    size: usize,
+}
+
+impl<'a> Records<'a> {
+    /// Stop the iteration.
+    fn stop(&mut self) -> Option<Record> {
+        self.chunks = None;
+        self.bytes = &[];
+
+        None
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Record;
+
+    fn next(&mut self) -> Option<Record> {
+        loop {
+            if self.bytes.len() >= self.size {
+                let record = match Record::decode(&self.bytes[..self.size]) {
+                    Some(record) => record,
+                    None => return self.stop(),
+                };
+                self.bytes = &self.bytes[self.size..];
+
+                return Some(record);
+            }
+
+            // The current chunk is done (an incomplete record at its end is ignored).
+            let chunk = match self.chunks.as_mut().and_then(|x| x.next()) {
+                Some(Ok(chunk)) => chunk,
+                _ => return self.stop(),
+            };
+            if chunk.kind != RECORDS { continue; }
+
+            let mut fields = chunk.fields();
+            match fields.u64() {
+                Ok(size) if size >= RECORD_SIZE as u64 && size <= usize::MAX as u64 => {
+                    self.size = size as usize;
+                    self.bytes = fields.rest();
+                },
+                _ => return self.stop(),
+            }
+        }
+    }
+}
+
+/// Iterate over the records of a trace document.
+///
+/// This reads traces written by `read_bytes`. If `bytes` is not a trace document of this version,
+/// there are no records.
+pub fn records(bytes: &[u8]) -> Records {
+    Records {
+        chunks: match wire::Reader::new(bytes, MAGIC) {
+            Ok(chunks) if chunks.version() == VERSION => Some(chunks),
+            _ => None,
+        },
+        bytes: &[],
+        // Nothing is read before the first chunk.
+        size: usize::MAX,
+    }
+}
+
+/// Get the size of a trace document holding `records` records.
+///
+/// A buffer of this size takes `records` records at a time in `read_bytes`.
+pub fn encoded_len(records: usize) -> usize {
+    wire::HEADER_SIZE + wire::head_size(1) + records * RECORD_SIZE + wire::CHUNK_HEAD_SIZE
+}
+
+/// The trace recorder.
+struct Recorder {
+    /// The ring of records.
+    ///
+    /// The `len` records starting at `start` (wrapping around) are used, oldest first.
+    ring: [Record; config::TRACE_CAPACITY],
+    /// The index of the oldest record.
+    start: usize,
+    /// The number of records.
+    len: usize,
+    /// The number of records dropped, since the ring was full.
+    dropped: u64,
+    /// The live buffers as `(address, id)`.
+    ///
+    /// Only the first `live` entries are used, and they are sorted by address.
+    ids: [(usize, u32); config::TRACE_IDS],
+    /// The number of live buffers.
+    live: usize,
+    /// The number of buffers, which went untracked, since the id table was full.
+    untracked: u64,
+    /// The next id.
+    next_id: u32,
+}
+
+impl Recorder {
+    /// Create a new empty recorder.
+    const fn new() -> Recorder {
+        Recorder {
+            ring: [Record {
+                op: Op::Alloc,
+                id: 0,
+                size: 0,
+                align: 1,
+            }; config::TRACE_CAPACITY],
+            start: 0,
+            len: 0,
+            dropped: 0,
+            ids: [(0, 0); config::TRACE_IDS],
+            live: 0,
+            untracked: 0,
+            next_id: 0,
+        }
+    }
+
+    /// Append a record, dropping the oldest one, if the ring is full.
+    fn push(&mut self, record: Record) {
+        if self.len == config::TRACE_CAPACITY {
+            self.start = (self.start + 1) % config::TRACE_CAPACITY;
+            self.len -= 1;
+            self.dropped += 1;
+        }
+
+        self.ring[(self.start + self.len) % config::TRACE_CAPACITY] = record;
+        self.len += 1;
+    }
+
+    /// Remove the oldest record.
+    fn pop(&mut self) -> Option<Record> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let res = self.ring[self.start];
+        self.start = (self.start + 1) % config::TRACE_CAPACITY;
+        self.len -= 1;
+
+        Some(res)
+    }
+
+    /// Search the id table for `addr`.
+    fn search(&self, addr: usize) -> Result<usize, usize> {
+        self.ids[..self.live].binary_search_by(|&(x, _)| x.cmp(&addr))
+    }
+
+    /// Assign a new id to the buffer at `addr`.
+    ///
+    /// If the id table is full, the buffer goes untracked, and `UNTRACKED` is returned.
+    fn assign(&mut self, addr: usize) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        if self.next_id == UNTRACKED {
+            self.next_id = 0;
+        }
+
+        self.track(addr, id)
+    }
+
+    /// Map the buffer at `addr` to `id` in the id table.
+    ///
+    /// If the id table is full, the buffer goes untracked, and `UNTRACKED` is returned.
+    fn track(&mut self, addr: usize, id: u32) -> u32 {
+        match self.search(addr) {
+            // A stale entry (e.g. of a zero-sized buffer sharing the address) is replaced.
+            Ok(n) => self.ids[n].1 = id,
+            Err(_) if self.live == config::TRACE_IDS => {
+                self.untracked += 1;
+                return UNTRACKED;
+            },
+            Err(n) => {
+                self.ids[self.live] = (addr, id);
+                self.live += 1;
+                sort::shift_in(&mut self.ids[..self.live], n);
+            },
+        }
+
+        id
+    }
+
+    /// Take the id of the buffer at `addr` out of the id table.
+    ///
+    /// If the buffer is untracked, `UNTRACKED` is returned.
+    fn take(&mut self, addr: usize) -> u32 {
+        match self.search(addr) {
+            Ok(n) => {
+                let id = self.ids[n].1;
+
+                sort::shift_out(&mut self.ids[..self.live], n);
+                self.live -= 1;
+
+                id
+            },
+            Err(_) => UNTRACKED,
+        }
+    }
+}
+
+/// Record the allocation of a buffer.
+pub fn record_alloc(ptr: *mut u8, size: usize, align: usize) {
+    let mut trace = TRACE.lock();
+    let id = trace.assign(ptr as usize);
+    trace.push(Record {
+        op: Op::Alloc,
+        id: id,
+        size: size,
+        align: align,
+    });
+}
+
+/// Record the free of a buffer.
+///
+/// This must be called before the buffer is freed, such that its address cannot be reused in
+/// between.
+pub fn record_free(ptr: *mut u8, size: usize) {
+    let mut trace = TRACE.lock();
+    let id = trace.take(ptr as usize);
+    trace.push(Record {
+        op: Op::Free,
+        id: id,
+        size: size,
+        align: 1,
+    });
+}
+
+/// Begin recording the reallocation of a buffer.
+///
+/// This must be called before the buffer is reallocated, such that its address cannot be reused
+/// in between. The id of the buffer is returned, and must be passed to `end_realloc`.
+pub fn begin_realloc(ptr: *mut u8) -> u32 {
+    TRACE.lock().take(ptr as usize)
+}
+
+/// Finish recording the reallocation of a buffer to `ptr`.
+pub fn end_realloc(id: u32, ptr: *mut u8, size: usize, align: usize) {
+    let mut trace = TRACE.lock();
+
+    // The buffer stays untracked, if it was so before.
+    let id = if id == UNTRACKED { id } else { trace.track(ptr as usize, id) };
+    trace.push(Record {
+        op: Op::Realloc,
+        id: id,
+        size: size,
+        align: align,
+    });
+}
+
+/// Take the oldest records out of the trace into `out`.
+///
+/// The number of records taken is returned.
+pub fn read(out: &mut [Record]) -> usize {
+    let mut trace = TRACE.lock();
+
+    let mut n = 0;
+    while n < out.len() {
+        match trace.pop() {
+            Some(record) => out[n] = record,
+            None => break,
+        }
+        n += 1;
+    }
+
+    n
+}
+
+/// Take the oldest records out of the trace, writing them as a trace document into `out`.
+///
+/// As many whole records as fit are taken (see `encoded_len`). The number of bytes written is
+/// returned, which is zero, if not even an empty document fits. The records can be read back with
+/// `records`.
+pub fn read_bytes(out: &mut [u8]) -> usize {
+    drain(&mut TRACE.lock(), out)
+}
+
+/// Take the oldest records out of a recorder, writing them as a trace document into `out`.
+fn drain(trace: &mut Recorder, out: &mut [u8]) -> usize {
+    if out.len() < encoded_len(0) {
+        return 0;
+    }
+
+    let n = cmp::min(trace.len, (out.len() - encoded_len(0)) / RECORD_SIZE);
+    let mut w = Writer::new(out);
+    let res = (|| {
+        w.header(MAGIC, VERSION)?;
+        w.chunk(RECORDS, &[RECORD_SIZE as u64], n * RECORD_SIZE)?;
+        for _ in 0..n {
+            w.bytes(&trace.pop().expect("The trace shrank while held.").encode())?;
+        }
+
+        w.end()
+    })();
+    debug_assert!(res.is_ok(), "The trace document doesn't fit its size.");
+
+    encoded_len(n)
+}
+
+/// Discard the records of the trace.
+///
+/// The ids of the live buffers are kept, such that their later records stay consistent.
+pub fn clear() {
+    let mut trace = TRACE.lock();
+    trace.start = 0;
+    trace.len = 0;
+}
+
+/// Get the number of records dropped, since the ring was full.
+pub fn dropped() -> u64 {
+    TRACE.lock().dropped
+}
+
+/// Get the number of buffers, which went untracked, since the id table was full.
+pub fn untracked() -> u64 {
+    TRACE.lock().untracked
+}
+
+/// The statistics of a replay.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReplayStats {
+    /// The number of allocations replayed.
+    pub allocs: usize,
+    /// The number of frees replayed.
+    pub frees: usize,
+    /// The number of reallocations replayed.
+    pub reallocs: usize,
+    /// The number of records skipped.
+    ///
+    /// These are records of untracked buffers, of buffers allocated before the trace started, or
+    /// allocations not fitting the slots.
+    pub skipped: usize,
+    /// The number of live buffers at the end.
+    pub live: usize,
+    /// The number of bytes in live buffers at the end.
+    pub live_bytes: usize,
+    /// The largest number of bytes in live buffers at any point.
+    pub peak_bytes: usize,
+}
+
+/// Get the home slot of an id.
+fn home(id: u32, len: usize) -> usize {
+    id as usize % len
+}
+
+/// Put a buffer into the slots.
+///
+/// If the slots are full, the block is given back in `Err`.
+fn insert(slots: &mut [Option<(u32, Block)>], id: u32, block: Block) -> Result<(), Block> {
+    let len = slots.len();
+    let mut n = home(id, len);
+
+    for _ in 0..len {
+        if slots[n].is_none() {
+            slots[n] = Some((id, block));
+            return Ok(());
+        }
+
+        n = (n + 1) % len;
+    }
+
+    Err(block)
+}
+
+/// Take a buffer out of the slots.
+fn remove(slots: &mut [Option<(u32, Block)>], id: u32) -> Option<Block> {
+    let len = slots.len();
+    if len == 0 {
+        return None;
+    }
+
+    // Find the slot by linear probing.
+    let mut hole = home(id, len);
+    let mut probes = 0;
+    loop {
+        match slots[hole] {
+            Some((x, _)) if x == id => break,
+            Some(_) if probes < len => {
+                hole = (hole + 1) % len;
+                probes += 1;
+            },
+            _ => return None,
+        }
+    }
+    let res = slots[hole].take().map(|(_, block)| block);
+
+    // Shift the following entries of the cluster back, such that probing never stops early.
+    let mut n = (hole + 1) % len;
+    loop {
+        let x = match slots[n] {
+            Some((x, _)) => x,
+            None => break,
+        };
+
+        // The entry can fill the hole, unless its home lies between the hole and it.
+        if (n + len - home(x, len)) % len >= (n + len - hole) % len {
+            slots[hole] = slots[n].take();
+            hole = n;
+        }
+
+        n = (n + 1) % len;
+    }
+
+    res
+}
+
+/// Replay a trace against a pool.
+///
+/// Every record is executed against `pool`, mapping the ids back to live blocks. Since this
+/// doesn't allocate either, the live blocks are kept in `slots`, which must be all `None`, and
+/// should be well larger than the number of simultaneously live buffers of the trace. The sizes
+/// are those requested, so neither the metadata nor the slabs are replayed.
+///
+/// The blocks still live at the end are left in `slots`. Replaying the same trace against equal
+/// pools gives equal pools and statistics.
+pub fn replay<I, A>(records: I, pool: &mut A, slots: &mut [Option<(u32, Block)>]) -> ReplayStats
+    where I: IntoIterator<Item = Record>,
+          A: Allocator {
+    let mut stats = ReplayStats::default();
+
+    for record in records {
+        let align = Align::new(record.align).unwrap_or(Align::BUFFER);
+
+        match record.op {
+            Op::Alloc if record.id != UNTRACKED => {
+                let block = pool.alloc(record.size, align);
+                let size = block.size();
+
+                if let Err(block) = insert(slots, record.id, block) {
+                    pool.free(block);
+                    stats.skipped += 1;
+                    continue;
+                }
+
+                stats.allocs += 1;
+                stats.live += 1;
+                stats.live_bytes += size;
+            },
+            Op::Free => match remove(slots, record.id) {
+                Some(block) => {
+                    stats.frees += 1;
+                    stats.live -= 1;
+                    stats.live_bytes -= block.size();
+
+                    pool.free(block);
+                },
+                None => stats.skipped += 1,
+            },
+            Op::Realloc => match remove(slots, record.id) {
+                Some(block) => {
+                    let old_size = block.size();
+                    let block = pool.realloc(block, record.size, align);
+                    let size = block.size();
+
+                    stats.reallocs += 1;
+                    stats.live_bytes = stats.live_bytes - old_size + size;
+
+                    // The slot of the old block was freed, so there is room.
+                    insert(slots, record.id, block).expect("No slot for the reallocated block.");
+                },
+                None => stats.skipped += 1,
+            },
+            _ => stats.skipped += 1,
+        }
+
+        if stats.live_bytes > stats.peak_bytes {
+            stats.peak_bytes = stats.live_bytes;
+        }
+    }
+
+    stats
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use shim::config;
+    use test_util;
+
+    #[test]
+    fn test_encoding() {
+        let expected = [
+            Record { op: Op::Alloc, id: 0, size: 0, align: 1 },
+            Record { op: Op::Free, id: 0x12345678, size: 100, align: 1 },
+            Record { op: Op::Realloc, id: UNTRACKED, size: 1 << 20, align: 4096 },
+        ];
+
+        let mut recorder = Recorder::new();
+        for record in &expected {
+            recorder.push(*record);
+        }
+
+        // Nothing is taken, unless an empty document fits.
+        assert_eq!(drain(&mut recorder, &mut [0; 39]), 0);
+        assert_eq!(recorder.len, 3);
+
+        let mut bytes = [0; 128];
+        let len = drain(&mut recorder, &mut bytes);
+        assert_eq!(len, encoded_len(3));
+        assert_eq!(recorder.len, 0);
+
+        let mut iter = records(&bytes[..len]);
+        for record in &expected {
+            assert_eq!(iter.next(), Some(*record));
+        }
+        assert_eq!(iter.next(), None);
+
+        // Invalid operations end the records.
+        bytes[encoded_len(1) - wire::CHUNK_HEAD_SIZE] = 3;
+        assert_eq!(Record::decode(&bytes[encoded_len(1) - wire::CHUNK_HEAD_SIZE..]), None);
+        assert_eq!(records(&bytes[..len]).count(), 1);
+
+        // A truncated document has no records.
+        assert_eq!(records(&bytes[..len - wire::CHUNK_HEAD_SIZE - 1]).count(), 0);
+        assert_eq!(records(b"RALLOCHD").count(), 0);
+    }
+
+    #[test]
+    fn test_partial_read() {
+        let mut recorder = Recorder::new();
+        for i in 0..5 {
+            recorder.push(Record { op: Op::Alloc, id: i, size: 8, align: 8 });
+        }
+
+        // Only whole records are taken.
+        let mut bytes = [0; 128];
+        let len = drain(&mut recorder, &mut bytes[..encoded_len(2) + RECORD_SIZE - 1]);
+        assert_eq!(len, encoded_len(2));
+        assert!(records(&bytes[..len]).map(|x| x.id).eq(0..2));
+        let len = drain(&mut recorder, &mut bytes);
+        assert!(records(&bytes[..len]).map(|x| x.id).eq(2..5));
+    }
+
+    #[test]
+    fn test_forward_compatibility() {
+        let record = Record { op: Op::Realloc, id: 7, size: 1000, align: 16 };
+
+        // A later version might add chunks, and extend the records.
+        let mut bytes = [0; 256];
+        let len = {
+            let mut w = Writer::new(&mut bytes);
+            w.header(MAGIC, VERSION).unwrap();
+            w.chunk(RECORDS + 100, &[1, 2, 3], 3).unwrap();
+            w.bytes(b"new").unwrap();
+            w.chunk(RECORDS, &[RECORD_SIZE as u64 + 8], 2 * (RECORD_SIZE + 8)).unwrap();
+            for _ in 0..2 {
+                w.bytes(&record.encode()).unwrap();
+                w.bytes(&[0xFF; 8]).unwrap();
+            }
+            w.end().unwrap();
+
+            w.written().len()
+        };
+
+        assert!(records(&bytes[..len]).eq([record, record].iter().cloned()));
+
+        // A later version of the format is not read.
+        bytes[8] = VERSION as u8 + 1;
+        assert_eq!(records(&bytes[..len]).count(), 0);
+    }
+
+    #[test]
+    fn test_fuzz() {
+        let mut rng = test_util::Rng::new(0x7ACE);
+        let mut bytes = [0; 160];
+        {
+            let mut w = Writer::new(&mut bytes);
+            w.header(MAGIC, VERSION).unwrap();
+        }
+
+        for _ in 0..20000 {
+            let len = rng.below(bytes.len() - wire::HEADER_SIZE + 1);
+            for x in bytes[wire::HEADER_SIZE..wire::HEADER_SIZE + len].iter_mut() {
+                // Small bytes make plausible kinds, lengths and records.
+                *x = if rng.below(2) == 0 { rng.get() as u8 } else { rng.below(4) as u8 };
+            }
+
+            // Reading anything never panics.
+            for record in records(&bytes[..wire::HEADER_SIZE + len]) {
+                assert!(record.align.is_power_of_two());
+            }
+        }
+    }
+
+    #[test]
+    fn test_ring() {
+        let mut recorder = Recorder::new();
+
+        // Overfill the ring.
+        for i in 0..config::TRACE_CAPACITY + 10 {
+            let id = recorder.assign(i);
+            recorder.push(Record { op: Op::Alloc, id: id, size: i, align: 1 });
+        }
+
+        // The oldest records were dropped.
+        assert_eq!(recorder.dropped, 10);
+        assert_eq!(recorder.pop().map(|x| x.size), Some(10));
+        assert_eq!(recorder.len, config::TRACE_CAPACITY - 1);
+
+        // The id table overflowed too.
+        assert_eq!(recorder.live, config::TRACE_IDS);
+        assert_eq!(recorder.untracked, (config::TRACE_CAPACITY + 10 - config::TRACE_IDS) as u64);
+        assert_eq!(recorder.take(5), 5);
+        assert_eq!(recorder.take(5), UNTRACKED);
+        assert_eq!(recorder.take(config::TRACE_IDS), UNTRACKED);
+    }
+
+    #[test]
+    fn test_slots() {
+        extern crate std;
+
+        use self::std::vec::Vec;
+
+        let mut data = [0u8; 64];
+        let mut block = |n: usize| unsafe {
+            Block::from_raw_parts(Pointer::new(&mut data[n] as *mut u8), 1)
+        };
+        let addr = |block: &Block| Pointer::from(block.empty_left()).addr();
+
+        // Ids colliding in their home slot.
+        let mut slots: Vec<Option<(u32, Block)>> = (0..4).map(|_| None).collect();
+        let base = addr(&block(0));
+        assert!(insert(&mut slots, 0, block(0)).is_ok());
+        assert!(insert(&mut slots, 4, block(1)).is_ok());
+        assert!(insert(&mut slots, 8, block(2)).is_ok());
+        assert!(insert(&mut slots, 3, block(3)).is_ok());
+        assert!(insert(&mut slots, 7, block(4)).is_err());
+
+        // Removing the head of the cluster keeps the rest reachable.
+        assert_eq!(remove(&mut slots, 0).map(|x| addr(&x) - base), Some(0));
+        assert_eq!(remove(&mut slots, 0).map(|x| addr(&x) - base), None);
+        assert_eq!(remove(&mut slots, 8).map(|x| addr(&x) - base), Some(2));
+        assert_eq!(remove(&mut slots, 3).map(|x| addr(&x) - base), Some(3));
+        assert_eq!(remove(&mut slots, 4).map(|x| addr(&x) - base), Some(1));
+        assert!(slots.iter().all(|x| x.is_none()));
+    }
+}
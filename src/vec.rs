@@ -5,6 +5,8 @@ use prelude::*;
 use core::{slice, ops, mem, ptr};
 
 use leak::Leak;
+#[cfg(feature = "security")]
+use secure;
 
 /// A low-level vector primitive.
 ///
@@ -24,6 +26,18 @@ pub struct Vec<T: Leak> {
 }
 
 impl<T: Leak> Vec<T> {
+    /// Create a new empty vector.
+    ///
+    /// This is the same as `Vec::default`, but usable in constants.
+    #[inline]
+    pub const fn new() -> Vec<T> {
+        Vec {
+            ptr: Pointer::empty(),
+            cap: 0,
+            len: 0,
+        }
+    }
+
     /// Create a vector from a block.
     ///
     /// # Safety
@@ -55,7 +69,7 @@ impl<T: Leak> Vec<T> {
 
         // Make some assertions.
         assert!(self.len <= new_cap, "Block not large enough to cover the vector.");
-        assert!(block.aligned_to(mem::align_of::<T>()), "Block not aligned.");
+        assert!(block.aligned_to(Align::of::<T>()), "Block not aligned.");
 
         let old = mem::replace(self, Vec::default());
 
@@ -108,6 +122,7 @@ impl<T: Leak> Vec<T> {
     ///
     /// If the vector is empty, `None` is returned.
     #[inline]
+    #[allow(cast_possible_wrap)]
     pub fn pop(&mut self) -> Option<T> {
         if self.len == 0 {
             None
@@ -120,25 +135,53 @@ impl<T: Leak> Vec<T> {
 
                 // We use `ptr::read` since the element is unaccessible due to the decrease in the
                 // length.
-                Some(ptr::read(self.get_unchecked(self.len)))
+                let res = ptr::read(self.get_unchecked(self.len));
+
+                // The slot is dead, so it is wiped.
+                #[cfg(feature = "security")]
+                secure::secure_wipe(&mut *(*self.ptr).offset(self.len as isize));
+
+                Some(res)
             }
         }
     }
 
     /// Truncate this vector.
     ///
-    /// This is O(1).
+    /// This is O(1), unless the `security` feature is set, in which case the dead slots are wiped.
     ///
     /// # Panics
     ///
     /// Panics on out-of-bound.
+    #[allow(cast_possible_wrap)]
     pub fn truncate(&mut self, len: usize) {
         // Bound check.
         assert!(len <= self.len, "Out of bound.");
 
+        #[cfg(feature = "security")]
+        for n in len..self.len {
+            unsafe {
+                // The slot is within the vector, and dead after the truncation.
+                secure::secure_wipe(&mut *(*self.ptr).offset(n as isize));
+            }
+        }
+
         self.len = len;
     }
 
+    /// Get the slots past the end of the vector.
+    ///
+    /// This is used to check, that released entries are wiped.
+    ///
+    /// # Safety
+    ///
+    /// The slots are not necessarily initialized, so they must be valid for any bit pattern.
+    #[cfg(test)]
+    #[allow(cast_possible_wrap)]
+    pub unsafe fn dead_slots(&self) -> &[T] {
+        slice::from_raw_parts((*self.ptr as *const T).offset(self.len as isize), self.cap - self.len)
+    }
+
     /// Yield an iterator popping from the vector.
     pub fn pop_iter(&mut self) -> PopIter<T> {
         PopIter {
@@ -0,0 +1,219 @@
+//! Heap usage watermarks.
+//!
+//! The memory taken from the OS (the program break and the mappings, but not static buffers) is
+//! accounted as it is registered and unregistered (see `region`). A callback can be installed to
+//! be told when this usage crosses some levels, e.g. to shed load before the limit is reached,
+//! without polling the statistics.
+//!
+//! The accounting is done while the allocator is locked, so it only flags the crossing. The
+//! callback is invoked afterwards (see `flush`), outside the locks. Once a level is reached, the
+//! usage must drop a margin (see `config::WATERMARK_HYSTERESIS_LOG`) below it, before it is
+//! reported crossed downwards, such that a usage hovering around a level doesn't flap.
+
+use core::{mem, ptr, slice};
+
+use atomic::{self, AtomicBool, AtomicPtr, AtomicUsize};
+use shim::config;
+
+/// The heap usage, in bytes.
+static USAGE: AtomicUsize = AtomicUsize::new(0);
+/// The start of the levels.
+static LEVELS: AtomicPtr<usize> = AtomicPtr::new(ptr::null_mut());
+/// The number of levels.
+static LEVELS_LEN: AtomicUsize = AtomicUsize::new(0);
+/// The callback, or null if none is installed.
+static CALLBACK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+/// Has a callback been installed?
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+/// The number of levels reached.
+static REACHED: AtomicUsize = AtomicUsize::new(0);
+/// Is a crossing waiting to be reported?
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+/// The direction of a crossing.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    /// The usage rose to or above the level.
+    Up,
+    /// The usage dropped below the margin of the level.
+    Down,
+}
+
+/// Install a callback for heap usage watermarks.
+///
+/// `cb` is called with the level and the direction, whenever the heap usage (see `heap_usage`)
+/// crosses one of the `levels`, which must be sorted. Every level crossed is reported separately,
+/// in order. The levels already reached when the callback is installed are not reported.
+///
+/// The callback is invoked right after the allocation or free, which crossed the level, outside
+/// the locks of the allocator. It must not allocate.
+///
+/// # Panics
+///
+/// This panics if a callback is already installed, or if the levels are not sorted.
+pub fn set_watermark_callback(levels: &'static [usize], cb: fn(usize, Direction)) {
+    // Logging.
+    log!(NOTE, "Setting the watermark callback for {} levels.", levels.len());
+
+    assert!(levels.windows(2).all(|x| x[0] <= x[1]), "The watermarks are not sorted.");
+    assert!(!INSTALLED.swap(true, atomic::Ordering::SeqCst),
+            "A watermark callback is already installed.");
+
+    LEVELS.store(levels.as_ptr() as *mut usize, atomic::Ordering::SeqCst);
+    LEVELS_LEN.store(levels.len(), atomic::Ordering::SeqCst);
+    REACHED.store(reached(levels, 0, USAGE.load(atomic::Ordering::SeqCst)),
+                  atomic::Ordering::SeqCst);
+    // The callback is stored last, as it marks the levels as set.
+    CALLBACK.store(cb as *mut (), atomic::Ordering::SeqCst);
+}
+
+/// Get the heap usage.
+///
+/// This is the number of bytes taken from the OS through the program break or memory mappings,
+/// and not yet given back.
+#[inline]
+pub fn heap_usage() -> usize {
+    USAGE.load(atomic::Ordering::Relaxed)
+}
+
+/// Account `bytes` bytes taken from the OS.
+#[inline]
+pub fn grow(bytes: usize) {
+    let usage = USAGE.fetch_add(bytes, atomic::Ordering::SeqCst) + bytes;
+    check(usage);
+}
+
+/// Account `bytes` bytes given back to the OS.
+#[inline]
+pub fn shrink(bytes: usize) {
+    let usage = USAGE.fetch_sub(bytes, atomic::Ordering::SeqCst) - bytes;
+    check(usage);
+}
+
+/// Flag a crossing, if the usage crossed a level.
+#[inline]
+fn check(usage: usize) {
+    if let Some((levels, _)) = installed() {
+        let old = REACHED.load(atomic::Ordering::SeqCst);
+        if reached(levels, old, usage) != old {
+            PENDING.store(true, atomic::Ordering::SeqCst);
+        }
+    }
+}
+
+/// Report the pending crossings.
+///
+/// This must be called without any locks of the allocator held.
+#[inline]
+pub fn flush() {
+    if PENDING.load(atomic::Ordering::Relaxed) {
+        report();
+    }
+}
+
+/// Invoke the callback for every level crossed.
+#[cold]
+fn report() {
+    if !PENDING.swap(false, atomic::Ordering::SeqCst) {
+        return;
+    }
+
+    let (levels, cb) = match installed() {
+        Some(x) => x,
+        None => return,
+    };
+
+    loop {
+        let old = REACHED.load(atomic::Ordering::SeqCst);
+        let new = reached(levels, old, USAGE.load(atomic::Ordering::SeqCst));
+
+        // Step a single level at a time, so every level is reported, and only once, even when
+        // other threads report at the same time.
+        let (next, level, direction) = if new > old {
+            (old + 1, levels[old], Direction::Up)
+        } else if new < old {
+            (old - 1, levels[old - 1], Direction::Down)
+        } else {
+            break;
+        };
+
+        if REACHED.compare_and_swap(old, next, atomic::Ordering::SeqCst) == old {
+            // Logging.
+            log!(DEBUG, "The heap usage crossed the watermark {} ({:?}).", level, direction);
+
+            cb(level, direction);
+        }
+    }
+}
+
+/// Get the levels and the callback, if installed.
+#[inline]
+fn installed() -> Option<(&'static [usize], fn(usize, Direction))> {
+    let cb = CALLBACK.load(atomic::Ordering::SeqCst);
+    if cb.is_null() {
+        return None;
+    }
+
+    unsafe {
+        // The callback is only stored after the levels, which are static, and it is a function
+        // pointer of this very type.
+        Some((slice::from_raw_parts(LEVELS.load(atomic::Ordering::SeqCst),
+                                    LEVELS_LEN.load(atomic::Ordering::SeqCst)),
+              mem::transmute::<_, fn(usize, Direction)>(cb)))
+    }
+}
+
+/// Get the number of levels reached at `usage`, given that `old` levels were reached before.
+///
+/// A level is reached when the usage is at or above it, and left again when the usage drops below
+/// its margin.
+fn reached(levels: &[usize], old: usize, usage: usize) -> usize {
+    let mut n = old;
+
+    while n < levels.len() && usage >= levels[n] {
+        n += 1;
+    }
+    while n > 0 && usage < levels[n - 1] - (levels[n - 1] >> config::WATERMARK_HYSTERESIS_LOG) {
+        n -= 1;
+    }
+
+    n
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reached() {
+        let levels = [1600, 3200];
+
+        // Rising.
+        assert_eq!(reached(&levels, 0, 0), 0);
+        assert_eq!(reached(&levels, 0, 1599), 0);
+        assert_eq!(reached(&levels, 0, 1600), 1);
+        assert_eq!(reached(&levels, 0, 5000), 2);
+        assert_eq!(reached(&levels, 1, 3200), 2);
+
+        // Falling, with a margin of a sixteenth.
+        assert_eq!(reached(&levels, 2, 3199), 2);
+        assert_eq!(reached(&levels, 2, 3000), 2);
+        assert_eq!(reached(&levels, 2, 2999), 1);
+        assert_eq!(reached(&levels, 1, 1500), 1);
+        assert_eq!(reached(&levels, 1, 1499), 0);
+        assert_eq!(reached(&levels, 2, 0), 0);
+
+        // Hovering around a level doesn't flap.
+        let mut n = 0;
+        for &usage in &[1600, 1550, 1610, 1520, 1600] {
+            n = reached(&levels, n, usage);
+            assert_eq!(n, 1);
+        }
+    }
+
+    #[test]
+    fn test_no_levels() {
+        assert_eq!(reached(&[], 0, 0), 0);
+        assert_eq!(reached(&[], 0, !0), 0);
+    }
+}
@@ -0,0 +1,430 @@
+//! The binary format of traces and heap dumps.
+//!
+//! Traces (see `trace`) and heap dumps (see `dump`) are written as documents of a small chunked
+//! format, such that a document written by one version can be read by the next:
+//!
+//! 1. The header: an eight byte magic telling the kind of the document, its version as a `u32`,
+//!    and four reserved bytes (zero).
+//! 2. The chunks, each starting with its kind and the length of its payload as `u32`s, followed
+//!    by the payload. The payload is a sequence of `u64` fields, possibly followed by bytes.
+//! 3. The end chunk (of kind `END`), with an empty payload.
+//!
+//! Every integer is little-endian, whatever the machine. Readers skip chunks of unknown kinds, and
+//! the fields of a payload beyond those they know, so later versions can add either without
+//! bumping the version. The version is only bumped, when the meaning of existing fields changes.
+//!
+//! The writer encodes into a byte buffer with plain stores (no formatting, and no allocation), and
+//! the reader decodes by slicing its input, without copying.
+
+use core::u32;
+
+/// The size of the header.
+pub const HEADER_SIZE: usize = 16;
+/// The size of the head of a chunk (its kind and length).
+pub const CHUNK_HEAD_SIZE: usize = 8;
+/// The kind of the end chunk.
+pub const END: u32 = 0;
+
+/// An error reading a document.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WireError {
+    /// The document is of another kind (or not a document at all).
+    Magic,
+    /// The version of the document is unknown to the reader.
+    Version(u32),
+    /// The document ends before its end chunk.
+    Truncated,
+    /// A payload lacks fields, which its kind requires.
+    Malformed,
+}
+
+/// The buffer of a writer is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Full;
+
+/// Store a little-endian `u32` at the start of `out`.
+fn put_u32(out: &mut [u8], x: u32) {
+    for i in 0..4 {
+        out[i] = (x >> (8 * i)) as u8;
+    }
+}
+
+/// Store a little-endian `u64` at the start of `out`.
+fn put_u64(out: &mut [u8], x: u64) {
+    for i in 0..8 {
+        out[i] = (x >> (8 * i)) as u8;
+    }
+}
+
+/// Load a little-endian `u32` from the start of `bytes`.
+fn get_u32(bytes: &[u8]) -> u32 {
+    let mut res = 0;
+    for i in 0..4 {
+        res |= (bytes[i] as u32) << (8 * i);
+    }
+
+    res
+}
+
+/// Load a little-endian `u64` from the start of `bytes`.
+fn get_u64(bytes: &[u8]) -> u64 {
+    let mut res = 0;
+    for i in 0..8 {
+        res |= (bytes[i] as u64) << (8 * i);
+    }
+
+    res
+}
+
+/// Get the size of a chunk head with `fields` fields.
+pub fn head_size(fields: usize) -> usize {
+    CHUNK_HEAD_SIZE + 8 * fields
+}
+
+/// A writer of a document into a byte buffer.
+pub struct Writer<'a> {
+    /// The buffer.
+    buf: &'a mut [u8],
+    /// The number of bytes written.
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    /// Create a writer filling `buf` from the start.
+    pub fn new(buf: &'a mut [u8]) -> Writer<'a> {
+        Writer {
+            buf: buf,
+            len: 0,
+        }
+    }
+
+    /// Get the bytes written.
+    pub fn written(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Get the number of bytes, which still fit the buffer.
+    pub fn room(&self) -> usize {
+        self.buf.len() - self.len
+    }
+
+    /// Empty the buffer (e.g. after its bytes were passed on).
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Take the next `len` bytes of the buffer, or `Err(Full)`, if they don't fit.
+    fn take(&mut self, len: usize) -> Result<&mut [u8], Full> {
+        if self.room() < len {
+            return Err(Full);
+        }
+
+        self.len += len;
+        Ok(&mut self.buf[self.len - len..self.len])
+    }
+
+    /// Write the header.
+    pub fn header(&mut self, magic: &[u8; 8], version: u32) -> Result<(), Full> {
+        let out = self.take(HEADER_SIZE)?;
+        out[..8].copy_from_slice(magic);
+        put_u32(&mut out[8..], version);
+        put_u32(&mut out[12..], 0);
+
+        Ok(())
+    }
+
+    /// Write the head of a chunk along with its fields.
+    ///
+    /// The payload holds `fields`, followed by `tail` bytes, which the caller must write next
+    /// (see `bytes`). If the head doesn't fit, nothing is written.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the payload is larger than a `u32` can tell.
+    pub fn chunk(&mut self, kind: u32, fields: &[u64], tail: usize) -> Result<(), Full> {
+        let len = 8 * fields.len() + tail;
+        assert!(len <= u32::MAX as usize, "Chunk of {} bytes is too large.", len);
+
+        let out = self.take(head_size(fields.len()))?;
+        put_u32(out, kind);
+        put_u32(&mut out[4..], len as u32);
+        for (n, &x) in fields.iter().enumerate() {
+            put_u64(&mut out[CHUNK_HEAD_SIZE + 8 * n..], x);
+        }
+
+        Ok(())
+    }
+
+    /// Write bytes of a payload.
+    ///
+    /// If they don't fit, nothing is written.
+    pub fn bytes(&mut self, bytes: &[u8]) -> Result<(), Full> {
+        self.take(bytes.len())?.copy_from_slice(bytes);
+
+        Ok(())
+    }
+
+    /// Write the end chunk.
+    pub fn end(&mut self) -> Result<(), Full> {
+        self.chunk(END, &[], 0)
+    }
+}
+
+/// A chunk read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Chunk<'a> {
+    /// The kind of the chunk.
+    pub kind: u32,
+    /// The payload.
+    pub payload: &'a [u8],
+}
+
+impl<'a> Chunk<'a> {
+    /// Read the fields of the payload.
+    pub fn fields(&self) -> Fields<'a> {
+        Fields {
+            bytes: self.payload,
+        }
+    }
+}
+
+/// A reader of the fields of a payload.
+#[derive(Clone, Copy, Debug)]
+pub struct Fields<'a> {
+    /// The bytes left.
+    bytes: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    /// Read a field.
+    pub fn u64(&mut self) -> Result<u64, WireError> {
+        self.bytes(8).map(get_u64)
+    }
+
+    /// Read `len` bytes.
+    pub fn bytes(&mut self, len: u64) -> Result<&'a [u8], WireError> {
+        if (self.bytes.len() as u64) < len {
+            return Err(WireError::Malformed);
+        }
+
+        let (res, rest) = self.bytes.split_at(len as usize);
+        self.bytes = rest;
+
+        Ok(res)
+    }
+
+    /// Get the bytes left.
+    pub fn rest(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+/// A reader of a document.
+///
+/// This iterates over the chunks up to the end chunk, skipping nothing: chunks of unknown kinds
+/// are left to the caller to skip. After an error, the iteration stops.
+pub struct Reader<'a> {
+    /// The version of the document.
+    version: u32,
+    /// The bytes left, or `None`, if the end chunk (or an error) was reached.
+    bytes: Option<&'a [u8]>,
+}
+
+impl<'a> Reader<'a> {
+    /// Start reading a document of the kind told by `magic`.
+    ///
+    /// Every version is accepted, so the caller must check `version`.
+    pub fn new(bytes: &'a [u8], magic: &[u8; 8]) -> Result<Reader<'a>, WireError> {
+        if bytes.len() < 8 || &bytes[..8] != magic {
+            return Err(WireError::Magic);
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err(WireError::Truncated);
+        }
+
+        Ok(Reader {
+            version: get_u32(&bytes[8..]),
+            bytes: Some(&bytes[HEADER_SIZE..]),
+        })
+    }
+
+    /// Get the version of the document.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Split the next chunk off `bytes`.
+    ///
+    /// At the end chunk, `None` is returned along with the bytes after it.
+    fn split_chunk(bytes: &'a [u8]) -> Result<(Option<Chunk<'a>>, &'a [u8]), WireError> {
+        if bytes.len() < CHUNK_HEAD_SIZE {
+            return Err(WireError::Truncated);
+        }
+
+        let kind = get_u32(bytes);
+        let len = get_u32(&bytes[4..]) as usize;
+        let bytes = &bytes[CHUNK_HEAD_SIZE..];
+        if bytes.len() < len {
+            return Err(WireError::Truncated);
+        }
+
+        let (payload, rest) = bytes.split_at(len);
+        Ok((if kind == END {
+            None
+        } else {
+            Some(Chunk {
+                kind: kind,
+                payload: payload,
+            })
+        }, rest))
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = Result<Chunk<'a>, WireError>;
+
+    fn next(&mut self) -> Option<Result<Chunk<'a>, WireError>> {
+        let bytes = match self.bytes.take() {
+            Some(bytes) => bytes,
+            None => return None,
+        };
+
+        match Reader::split_chunk(bytes) {
+            Ok((Some(chunk), rest)) => {
+                self.bytes = Some(rest);
+                Some(Ok(chunk))
+            },
+            Ok((None, _)) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use test_util::Rng;
+
+    /// The magic of the test documents.
+    const MAGIC: &'static [u8; 8] = b"RALLOCWT";
+
+    #[test]
+    fn test_round_trip() {
+        let mut buf = [0; 256];
+        let len = {
+            let mut w = Writer::new(&mut buf);
+            w.header(MAGIC, 3).unwrap();
+            w.chunk(1, &[], 0).unwrap();
+            w.chunk(2, &[0x0102030405060708, !0], 0).unwrap();
+            w.chunk(3, &[42], 5).unwrap();
+            w.bytes(b"hello").unwrap();
+            w.end().unwrap();
+
+            assert_eq!(w.room(), 256 - w.written().len());
+            w.written().len()
+        };
+
+        // The integers are little-endian.
+        assert_eq!(&buf[8..16], &[3, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&buf[HEADER_SIZE + CHUNK_HEAD_SIZE..HEADER_SIZE + CHUNK_HEAD_SIZE + 4],
+                   &[2, 0, 0, 0]);
+
+        let mut r = Reader::new(&buf[..len], MAGIC).unwrap();
+        assert_eq!(r.version(), 3);
+
+        assert_eq!(r.next(), Some(Ok(Chunk { kind: 1, payload: &[] })));
+
+        let chunk = r.next().unwrap().unwrap();
+        assert_eq!(chunk.kind, 2);
+        let mut fields = chunk.fields();
+        assert_eq!(fields.u64(), Ok(0x0102030405060708));
+        assert_eq!(fields.u64(), Ok(!0));
+        assert_eq!(fields.u64(), Err(WireError::Malformed));
+
+        let chunk = r.next().unwrap().unwrap();
+        let mut fields = chunk.fields();
+        assert_eq!(fields.u64(), Ok(42));
+        assert_eq!(fields.bytes(5), Ok(&b"hello"[..]));
+        assert!(fields.rest().is_empty());
+
+        // The end chunk ends the document.
+        assert_eq!(r.next(), None);
+        assert_eq!(r.next(), None);
+    }
+
+    #[test]
+    fn test_full() {
+        let mut buf = [0; HEADER_SIZE + CHUNK_HEAD_SIZE + 4];
+        let mut w = Writer::new(&mut buf);
+        w.header(MAGIC, 1).unwrap();
+
+        // What doesn't fit isn't written at all.
+        assert_eq!(w.chunk(1, &[1], 0), Err(Full));
+        assert_eq!(w.written().len(), HEADER_SIZE);
+        w.chunk(1, &[], 4).unwrap();
+        assert_eq!(w.bytes(b"12345"), Err(Full));
+        w.bytes(b"1234").unwrap();
+        assert_eq!(w.room(), 0);
+
+        w.clear();
+        assert_eq!(w.room(), HEADER_SIZE + CHUNK_HEAD_SIZE + 4);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut buf = [0; 64];
+        let len = {
+            let mut w = Writer::new(&mut buf);
+            w.header(MAGIC, 1).unwrap();
+            w.chunk(1, &[7], 0).unwrap();
+            w.end().unwrap();
+            w.written().len()
+        };
+
+        assert_eq!(Reader::new(&buf[..len], b"RALLOCXX").err(), Some(WireError::Magic));
+        assert_eq!(Reader::new(&buf[..4], MAGIC).err(), Some(WireError::Magic));
+        assert_eq!(Reader::new(&buf[..12], MAGIC).err(), Some(WireError::Truncated));
+
+        // Without the end chunk, or with a cut payload, the document is truncated.
+        for &cut in &[HEADER_SIZE, HEADER_SIZE + 4, HEADER_SIZE + 12] {
+            let mut r = Reader::new(&buf[..cut], MAGIC).unwrap();
+            assert_eq!(r.next(), Some(Err(WireError::Truncated)));
+            assert_eq!(r.next(), None);
+        }
+        let mut r = Reader::new(&buf[..len - 1], MAGIC).unwrap();
+        assert!(r.next().unwrap().is_ok());
+        assert_eq!(r.next(), Some(Err(WireError::Truncated)));
+    }
+
+    #[test]
+    fn test_fuzz() {
+        let mut rng = Rng::new(0x3173);
+        let mut buf = [0; 128];
+
+        for _ in 0..20000 {
+            let len = rng.below(buf.len() + 1);
+            for x in buf[..len].iter_mut() {
+                // Small bytes make plausible kinds and lengths.
+                *x = if rng.below(2) == 0 { rng.get() as u8 } else { rng.below(4) as u8 };
+            }
+            // Mostly start with a valid header, to get past it.
+            if rng.below(4) != 0 && len >= 8 {
+                buf[..8].copy_from_slice(MAGIC);
+            }
+
+            // Reading anything never panics.
+            if let Ok(r) = Reader::new(&buf[..len], MAGIC) {
+                for chunk in r {
+                    let mut fields = match chunk {
+                        Ok(chunk) => chunk.fields(),
+                        Err(_) => break,
+                    };
+                    while let Ok(x) = fields.u64() {
+                        if fields.bytes(x % 16).is_err() { break; }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,45 @@
+extern crate ralloc;
+
+// The growths are counted globally, so this is the only test in its process.
+#[cfg(feature = "test_util")]
+mod adaptive_brk {
+    use ralloc;
+    use ralloc::test_util::inject;
+
+    /// The size of the buffers.
+    const SIZE: usize = 4096;
+    /// The number of buffers in a burst.
+    const COUNT: usize = 4096;
+
+    /// Allocate a burst of buffers, and count the growths of the heap it takes.
+    ///
+    /// The buffers are kept, such that the next burst has to grow the heap as well.
+    fn burst(bufs: &mut Vec<*mut u8>) -> usize {
+        let grows = inject::grows();
+        for _ in 0..COUNT {
+            let ptr = ralloc::alloc(SIZE, 8);
+            unsafe { *ptr = 0xAA; }
+            bufs.push(ptr);
+        }
+
+        inject::grows() - grows
+    }
+
+    #[test]
+    fn fewer_grows() {
+        let mut bufs = Vec::with_capacity(2 * COUNT);
+
+        ralloc::set_adaptive_brk(true);
+        let adaptive = burst(&mut bufs);
+        ralloc::set_adaptive_brk(false);
+        let fixed = burst(&mut bufs);
+        ralloc::set_adaptive_brk(true);
+
+        assert!(adaptive * 4 < fixed, "{} growths with the adaptive extra, {} without.", adaptive,
+                fixed);
+
+        for ptr in bufs {
+            unsafe { ralloc::free(ptr, SIZE); }
+        }
+    }
+}
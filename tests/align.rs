@@ -0,0 +1,76 @@
+extern crate ralloc;
+
+mod util;
+
+use std::ptr;
+
+#[test]
+fn valid_alignments() {
+    util::multiply(|| {
+        for shift in 0..12 {
+            let align = 1 << shift;
+            let ptr = ralloc::alloc(100, align);
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % align, 0);
+
+            unsafe {
+                util::acid(|| {
+                    *ptr.offset(99) = 1;
+                });
+
+                ralloc::free(ptr, 100);
+            }
+        }
+    });
+}
+
+#[test]
+fn invalid_alignments() {
+    for &align in &[0, 3, 6, 15, 24, 100, !0] {
+        assert!(ralloc::alloc(8, align).is_null());
+        assert!(ralloc::calloc(4, 2, align).is_null());
+
+        let mut ptrs = [0 as *mut u8; 4];
+        assert_eq!(ralloc::alloc_many(8, align, &mut ptrs), 0);
+    }
+}
+
+#[test]
+fn invalid_realloc_alignment() {
+    unsafe {
+        let ptr = ralloc::alloc(16, 8);
+        *ptr = 42;
+
+        // The buffer is left intact.
+        assert!(ralloc::realloc(ptr, 16, 32, 12).is_null());
+        assert_eq!(ralloc::realloc_with_hint(ptr, 16, 32, 64, 0), (0 as *mut u8, 0));
+        assert_eq!(*ptr, 42);
+
+        let ptr = ralloc::realloc(ptr, 16, 32, 8);
+        assert_eq!(*ptr, 42);
+        ralloc::free(ptr, 32);
+    }
+}
+
+#[test]
+fn large_alignments() {
+    for &align in &[1 << 12, 1 << 16, 1 << 21] {
+        let ptr = ralloc::try_alloc(5000, align).unwrap();
+        assert_eq!(ptr as usize % align, 0);
+
+        unsafe {
+            ptr::write_bytes(ptr, 0xAB, 5000);
+            assert_eq!(*ptr.offset(4999), 0xAB);
+
+            // Growing keeps the contents and the alignment.
+            let ptr = ralloc::realloc(ptr, 5000, 100000, align);
+            assert_eq!(ptr as usize % align, 0);
+            assert_eq!(*ptr.offset(4999), 0xAB);
+
+            ralloc::free(ptr, 100000);
+        }
+    }
+
+    // Any alignment short of half the address space can be mapped.
+    assert!(ralloc::max_align() > 1 << 21);
+}
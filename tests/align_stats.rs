@@ -0,0 +1,39 @@
+extern crate ralloc;
+
+#[cfg(feature = "stats")]
+#[test]
+fn align_stats() {
+    use ralloc::stats::{self, bucket, AlignPath};
+
+    let before = stats::align_histogram();
+    let explicit = stats::align_paths(AlignPath::Explicit);
+
+    // A known mix of alignments.
+    let mut ptrs = Vec::new();
+    for &(align, n) in &[(8, 10), (32, 5), (4096, 3)] {
+        for _ in 0..n {
+            let ptr = ralloc::alloc(100, align);
+            assert_eq!(ptr as usize % align, 0);
+            ptrs.push(ptr);
+        }
+    }
+
+    // Other threads might allocate meanwhile, so only lower bounds are checked.
+    let after = stats::align_histogram();
+    assert!(after.count(bucket(8)) >= before.count(bucket(8)) + 10);
+    assert!(after.count(bucket(32)) >= before.count(bucket(32)) + 5);
+    assert!(after.count(bucket(4096)) >= before.count(bucket(4096)) + 3);
+    assert!(after.total() >= before.total() + 18);
+
+    // Consecutive page aligned buffers cannot all be aligned by chance.
+    assert!(stats::align_paths(AlignPath::Explicit) > explicit);
+
+    for ptr in ptrs {
+        unsafe { ralloc::free(ptr, 100); }
+    }
+
+    let mut report = String::new();
+    stats::write_report(&mut report).unwrap();
+    assert!(report.contains("natural"));
+    assert!(report.contains("align"));
+}
@@ -0,0 +1,97 @@
+extern crate ralloc;
+
+mod util;
+
+#[test]
+fn alloc_many() {
+    util::multiply(|| {
+        let mut ptrs = [0 as *mut u8; 100];
+        let n = ralloc::alloc_many(24, 8, &mut ptrs);
+
+        // Buffers with metadata are never batched.
+        if cfg!(not(any(feature = "header", feature = "sidetable"))) {
+            assert!(n > 0);
+        }
+
+        unsafe {
+            for (i, &ptr) in ptrs[..n].iter().enumerate() {
+                assert_eq!(ptr as usize % 8, 0);
+                util::acid(|| {
+                    *ptr = i as u8;
+                    *ptr.offset(23) = i as u8;
+                });
+            }
+
+            for (i, &ptr) in ptrs[..n].iter().enumerate() {
+                assert_eq!(*ptr, i as u8);
+                assert_eq!(*ptr.offset(23), i as u8);
+            }
+
+            // Fall back to single allocations for the rest.
+            for ptr in &mut ptrs[n..] {
+                *ptr = ralloc::alloc(24, 8);
+            }
+
+            util::acid(|| {
+                ralloc::dealloc_many(&mut ptrs, 24);
+            });
+        }
+    });
+}
+
+#[test]
+fn alloc_many_padded() {
+    util::multiply(|| {
+        let mut ptrs = [0 as *mut u8; 33];
+        let n = ralloc::alloc_many(5, 16, &mut ptrs);
+
+        unsafe {
+            for &ptr in &ptrs[..n] {
+                assert_eq!(ptr as usize % 16, 0);
+                util::acid(|| {
+                    *ptr.offset(4) = 0xFF;
+                });
+            }
+
+            for &ptr in &ptrs[..n] {
+                ralloc::free(ptr, 5);
+            }
+        }
+    });
+}
+
+#[test]
+#[cfg(feature = "tls")]
+fn alloc_many_partial() {
+    use std::thread;
+
+    thread::spawn(|| {
+        unsafe {
+            // Leave a free block of 1000 bytes in the pool of this (fresh) thread.
+            let buf = ralloc::alloc(1000, 1);
+            ralloc::free(buf, 1000);
+        }
+
+        let mut ptrs = [0 as *mut u8; 64];
+        let n = ralloc::alloc_many(100, 1, &mut ptrs);
+
+        // The pool can only satisfy a part of the batch.
+        assert!(n >= 10);
+        assert!(n < 64);
+
+        unsafe {
+            for &ptr in &ptrs[..n] {
+                *ptr = 1;
+            }
+
+            ralloc::dealloc_many(&mut ptrs[..n], 100);
+        }
+    }).join().unwrap();
+}
+
+#[test]
+fn alloc_many_zero() {
+    let mut ptrs = [0 as *mut u8; 4];
+    assert_eq!(ralloc::alloc_many(0, 1, &mut ptrs), 0);
+    assert_eq!(ralloc::alloc_many(8, 1, &mut []), 0);
+}
@@ -1,5 +1,7 @@
 //! This test is a more subtle one. It is one which can hit thread destructors unexpectedly.
 
+#![cfg(not(feature = "single_threaded"))]
+
 extern crate ralloc;
 
 use std::sync::Arc;
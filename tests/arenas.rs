@@ -0,0 +1,164 @@
+extern crate ralloc;
+
+mod util;
+
+#[cfg(not(feature = "single_threaded"))]
+use std::thread;
+
+#[test]
+fn medium_churn() {
+    util::multiply(|| {
+        let mut ptrs = Vec::new();
+
+        for i in 0..64 {
+            let size = 1024 + i * 1000;
+            let ptr = ralloc::alloc(size, 8);
+
+            unsafe {
+                util::acid(|| {
+                    *ptr = i as u8;
+                    *ptr.offset(size as isize - 1) = i as u8;
+                });
+            }
+
+            ptrs.push((ptr, size));
+        }
+
+        for (i, &(ptr, size)) in ptrs.iter().enumerate() {
+            unsafe {
+                assert_eq!(*ptr, i as u8);
+                assert_eq!(*ptr.offset(size as isize - 1), i as u8);
+
+                ralloc::free(ptr, size);
+            }
+        }
+    });
+}
+
+#[test]
+#[cfg(not(feature = "single_threaded"))]
+fn cross_thread_free() {
+    // Blocks freed on another thread go back to the arena owning them.
+    let handles: Vec<_> = (0..16).map(|_| {
+        thread::spawn(|| {
+            (0..32).map(|_| ralloc::alloc(8192, 8) as usize).collect::<Vec<_>>()
+        })
+    }).collect();
+
+    for handle in handles {
+        for ptr in handle.join().unwrap() {
+            unsafe { ralloc::free(ptr as *mut u8, 8192); }
+        }
+    }
+}
+
+#[test]
+#[cfg(all(feature = "arenas", feature = "stats"))]
+fn arena_stats() {
+    let handles: Vec<_> = (0..16).map(|_| {
+        thread::spawn(|| {
+            let ptr = ralloc::alloc(32768, 8);
+            unsafe { ralloc::free(ptr, 32768); }
+        })
+    }).collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    // Every arena refills in whole chunks.
+    let stats = ralloc::stats::arenas();
+    assert!(stats.iter().map(|x| x.refills).sum::<usize>() > 0);
+    assert!(stats.iter().all(|x| x.owned >= x.refills * 256 * 1024));
+}
+
+/// The environment variable marking the child process.
+#[cfg(all(feature = "arenas", feature = "stats"))]
+const CHILD_VAR: &'static str = "RALLOC_TEST_ARENAS_CHILD";
+
+/// The body of the child process.
+#[test]
+#[cfg(all(feature = "arenas", feature = "stats"))]
+fn purge_to_arenas_child() {
+    if std::env::var(CHILD_VAR).is_err() { return; }
+
+    let free = || ralloc::stats::arenas().iter().map(|x| x.free).sum::<usize>();
+
+    // The buffer is held by the local allocator after the free.
+    let ptr = ralloc::alloc(32768, 8);
+    unsafe { ralloc::free(ptr, 32768); }
+
+    // Purging gives it back to the arena owning it, rather than to the global allocator.
+    let before = free();
+    assert!(ralloc::purge().local >= 32768);
+    assert!(free() >= before + 32768, "{} bytes went back to the arenas.", free() - before);
+    assert_eq!(ralloc::validate_and_repair().map(|x| x.total()), Ok(0));
+}
+
+#[test]
+#[cfg(all(feature = "arenas", feature = "stats"))]
+fn purge_to_arenas() {
+    // The arena statistics are global, so the purge runs in a child process.
+    let status = std::process::Command::new(std::env::current_exe().unwrap())
+        .arg("purge_to_arenas_child")
+        .arg("--exact")
+        .env(CHILD_VAR, "1")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+}
+
+#[test]
+#[cfg(all(feature = "cpu_shards", feature = "stats"))]
+fn shard_consistency() {
+    use std::sync::mpsc;
+
+    const THREADS: usize = 64;
+
+    // Every thread frees the buffers of its neighbor, which were likely taken from another shard.
+    let (senders, receivers): (Vec<_>, Vec<_>) = (0..THREADS).map(|_| mpsc::channel()).unzip();
+    let handles: Vec<_> = receivers.into_iter().enumerate().map(|(n, rx)| {
+        let tx: mpsc::Sender<Vec<(usize, usize)>> = senders[(n + 1) % THREADS].clone();
+        thread::spawn(move || {
+            let ptrs = (0..32).map(|i| {
+                let id = n * 32 + i;
+                let size = 4096 + i * 512;
+                let ptr = ralloc::alloc(size, 8);
+                unsafe {
+                    *(ptr as *mut usize) = id;
+                    *(ptr.offset((size - 8) as isize) as *mut usize) = id;
+                }
+
+                (ptr as usize, size)
+            }).collect();
+            tx.send(ptrs).unwrap();
+
+            let mut freed = 0;
+            for (i, (ptr, size)) in rx.recv().unwrap().into_iter().enumerate() {
+                let ptr = ptr as *mut u8;
+                // No buffer was handed out twice.
+                let id = (n + THREADS - 1) % THREADS * 32 + i;
+                unsafe {
+                    assert_eq!(*(ptr as *const usize), id);
+                    assert_eq!(*(ptr.offset((size - 8) as isize) as *const usize), id);
+
+                    ralloc::free(ptr, size);
+                }
+                freed += 1;
+            }
+
+            freed
+        })
+    }).collect();
+    drop(senders);
+
+    let freed: usize = handles.into_iter().map(|x| x.join().unwrap()).sum();
+    assert_eq!(freed, THREADS * 32);
+
+    // Nothing was lost or duplicated between the shards.
+    assert_eq!(ralloc::validate_and_repair().map(|x| x.total()), Ok(0));
+    // Only the shards of the CPUs are used.
+    let stats = ralloc::stats::arenas();
+    assert!(stats[ralloc::stats::shards()..].iter().all(|x| x.owned == 0));
+}
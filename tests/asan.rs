@@ -0,0 +1,76 @@
+//! AddressSanitizer integration.
+//!
+//! These only do something in sanitizer builds, e.g.:
+//!
+//! ```
+//! RUSTFLAGS="-Z sanitizer=address" cargo test --features sanitize \
+//!     --target x86_64-unknown-linux-gnu --test asan
+//! ```
+
+#![feature(linkage)]
+
+extern crate ralloc;
+
+#[cfg(feature = "sanitize")]
+mod asan {
+    use std::{env, mem, process};
+
+    extern {
+        #[linkage = "extern_weak"]
+        static __asan_address_is_poisoned: *const u8;
+    }
+
+    /// Check if a byte is poisoned, or `None` without ASan.
+    fn is_poisoned(ptr: *const u8) -> Option<bool> {
+        unsafe {
+            if __asan_address_is_poisoned.is_null() {
+                None
+            } else {
+                let f: unsafe extern fn(*const u8) -> i32 = mem::transmute(__asan_address_is_poisoned);
+                Some(f(ptr) != 0)
+            }
+        }
+    }
+
+    #[test]
+    fn poisoned_after_free() {
+        let ptr = ralloc::alloc(64, 8);
+        if is_poisoned(ptr).is_none() { return; }
+
+        unsafe {
+            assert_eq!(is_poisoned(ptr), Some(false));
+            assert_eq!(is_poisoned(ptr.offset(63)), Some(false));
+
+            ralloc::free(ptr, 64);
+
+            assert_eq!(is_poisoned(ptr), Some(true));
+            assert_eq!(is_poisoned(ptr.offset(63)), Some(true));
+        }
+    }
+
+    #[test]
+    fn use_after_free_reported() {
+        if env::var("RALLOC_ASAN_CHILD").is_ok() {
+            // Write to freed memory, which ASan must abort on.
+            let ptr = ralloc::alloc(64, 8);
+            unsafe {
+                ralloc::free(ptr, 64);
+                *ptr.offset(8) = 42;
+            }
+
+            return;
+        }
+
+        if is_poisoned(&0u8).is_none() { return; }
+
+        // Run the test again in a child process.
+        let out = process::Command::new(env::current_exe().unwrap())
+            .arg("use_after_free_reported")
+            .env("RALLOC_ASAN_CHILD", "1")
+            .output()
+            .unwrap();
+
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("use-after-poison"));
+    }
+}
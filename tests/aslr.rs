@@ -0,0 +1,46 @@
+extern crate ralloc;
+
+// The generator and the deterministic mode are global, so this is the only test in its process.
+#[cfg(all(feature = "aslr", feature = "test_util"))]
+mod aslr {
+    use ralloc::test_util::{self, Allocator, Align, PoolOp, Pointer};
+
+    /// Perform eight allocations with a given seed, and return the offsets of the results.
+    ///
+    /// The pool has 16 equally fitting blocks to pick from.
+    fn placement_pattern(seed: usize) -> [usize; 8] {
+        test_util::seed_random(seed);
+
+        let mut meta = [0; 256];
+        let mut data = [0u8; 1024];
+        let base = data.as_ptr() as usize;
+
+        let mut ops = [PoolOp::Free { start: 0, size: 0 }; 16];
+        for (i, op) in ops.iter_mut().enumerate() {
+            *op = PoolOp::Free { start: i * 64, size: 32 };
+        }
+        let mut pool = unsafe { test_util::scripted_pool(&mut meta, &mut data, &ops) };
+
+        let mut res = [0; 8];
+        for i in res.iter_mut() {
+            *i = *Pointer::from(pool.alloc(8, Align::new(1).unwrap())) as usize - base;
+        }
+
+        res
+    }
+
+    #[test]
+    fn seeded_patterns() {
+        // Different seeds should give different patterns.
+        let first = placement_pattern(1);
+        assert!((2..10).any(|seed| placement_pattern(seed) != first));
+
+        // In deterministic mode, the patterns must be equal.
+        test_util::set_deterministic(true);
+        let first = placement_pattern(1);
+        for seed in 2..10 {
+            assert_eq!(placement_pattern(seed), first);
+        }
+        test_util::set_deterministic(false);
+    }
+}
@@ -0,0 +1,42 @@
+extern crate ralloc;
+
+/// The size of the buffers.
+const SIZE: usize = 16 * 1024;
+/// The number of buffers in a tooth.
+const COUNT: usize = 256;
+
+/// Get the current program break.
+fn current_brk() -> usize {
+    unsafe { ralloc::sbrk(0) as usize }
+}
+
+#[test]
+fn sawtooth() {
+    ralloc::set_auto_trim(16);
+
+    let mut ptrs = Vec::with_capacity(COUNT);
+
+    for _ in 0..4 {
+        for _ in 0..COUNT {
+            let ptr = ralloc::alloc(SIZE, 8);
+            unsafe { *ptr = 0xAA; }
+            ptrs.push(ptr);
+        }
+
+        let peak = current_brk();
+
+        // Free the top buffers first, such that the top of the heap becomes free early.
+        while let Some(ptr) = ptrs.pop() {
+            unsafe {
+                ralloc::free(ptr, SIZE);
+            }
+        }
+
+        // Without any purging, most of the tooth went back to the OS.
+        let after = current_brk();
+        assert!(after < peak);
+        assert!(peak - after >= SIZE * COUNT / 2, "Only {} bytes were trimmed.", peak - after);
+    }
+
+    ralloc::set_auto_trim(0);
+}
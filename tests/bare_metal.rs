@@ -0,0 +1,15 @@
+extern crate ralloc;
+
+#[cfg(feature = "allocator")]
+#[test]
+fn init_after_allocation() {
+    // The test harness has already allocated, so the allocator is initialized.
+    let buf = Box::new([0u8; 4096]);
+    let buf: &'static mut [u8; 4096] = unsafe { &mut *Box::into_raw(buf) };
+
+    assert_eq!(ralloc::init_from_buffer(buf), Err(ralloc::AlreadyInitialized));
+
+    // The allocator is untouched.
+    let v = vec![1u8; 100000];
+    assert_eq!(v[99999], 1);
+}
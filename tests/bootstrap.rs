@@ -0,0 +1,33 @@
+extern crate ralloc;
+
+use std::{env, process};
+
+/// The environment variable marking the child process.
+const CHILD_VAR: &'static str = "RALLOC_TEST_BOOTSTRAP_CHILD";
+
+/// The body of the child process.
+///
+/// By the time this runs, the process has already performed its very first allocation.
+#[test]
+fn bootstrap_child() {
+    if env::var(CHILD_VAR).is_err() { return; }
+
+    let a = Box::new(1);
+    assert_eq!(*a, 1);
+
+    #[cfg(feature = "stats")]
+    assert!(ralloc::stats::snapshot().bootstrap_bytes > 0);
+}
+
+#[test]
+fn bootstrap_fresh_process() {
+    // Run the child test in a fresh process, in which ralloc serves the very first allocation.
+    let status = process::Command::new(env::current_exe().unwrap())
+        .arg("bootstrap_child")
+        .arg("--exact")
+        .env(CHILD_VAR, "1")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+}
@@ -0,0 +1,55 @@
+extern crate ralloc;
+
+#[cfg(feature = "bounded_free")]
+mod bounded_free {
+    use ralloc;
+
+    use std::cmp;
+
+    /// The size of the `i`th buffer.
+    fn size(i: usize) -> usize {
+        64 + i % 7 * 16
+    }
+
+    #[test]
+    fn bounded_frees() {
+        let mut ptrs = Vec::with_capacity(1000);
+        for i in 0..1000 {
+            let ptr = ralloc::alloc(size(i), 16);
+            unsafe { *ptr = i as u8; }
+            ptrs.push(ptr);
+        }
+
+        // Far more frees than can be deferred, in a row. The work of every free is measured by
+        // the list, rather than taken from the count of the free itself: the blocks merged are
+        // the ones queued before, which are not queued anymore.
+        let mut most = 0;
+        for (i, &ptr) in ptrs.iter().enumerate() {
+            let before = ralloc::maintenance(0);
+            unsafe { ralloc::free(ptr, size(i)); }
+            let merged = before + 1 - ralloc::maintenance(0);
+
+            most = cmp::max(most, merged + 1);
+        }
+
+        // The list filled up, yet no free did more than a slice of merging.
+        assert!(most > 1);
+        assert!(most <= ralloc::FREE_OPS_BOUND, "A free did {} operations.", most);
+        assert!(ralloc::free_ops_peak() >= most);
+        assert!(ralloc::free_ops_peak() <= ralloc::FREE_OPS_BOUND);
+
+        // Every operation merges one deferred free.
+        let left = ralloc::maintenance(0);
+        assert!(left > 1);
+        assert_eq!(ralloc::maintenance(1), left - 1);
+
+        // Maintenance drains the list completely.
+        while ralloc::maintenance(3) != 0 {}
+        assert_eq!(ralloc::maintenance(0), 0);
+
+        // The memory is reused.
+        let ptr = ralloc::alloc(64, 16);
+        unsafe { ralloc::free(ptr, 64); }
+        assert_eq!(ralloc::maintenance(!0), 0);
+    }
+}
@@ -0,0 +1,60 @@
+extern crate ralloc;
+
+mod util;
+
+use std::usize;
+
+#[test]
+fn calloc() {
+    util::multiply(|| {
+        let ptr = ralloc::calloc(100, 24, 8);
+        assert_eq!(ptr as usize % 8, 0);
+
+        unsafe {
+            for i in 0..2400 {
+                assert_eq!(*ptr.offset(i), 0);
+            }
+
+            util::acid(|| {
+                *ptr.offset(2399) = 1;
+            });
+
+            ralloc::free(ptr, 2400);
+        }
+    });
+}
+
+#[test]
+fn calloc_reused() {
+    util::multiply(|| {
+        unsafe {
+            // Dirty some memory, and make sure it comes back zeroed.
+            let ptr = ralloc::alloc(4096, 8);
+            *ptr.offset(100) = 0xFF;
+            ralloc::free(ptr, 4096);
+
+            let ptr = ralloc::calloc(512, 8, 8);
+            assert!((0..4096).all(|i| *ptr.offset(i) == 0));
+            ralloc::free(ptr, 4096);
+        }
+    });
+}
+
+#[test]
+fn calloc_overflow() {
+    for &size in &[2, 3, 24, 1000, usize::MAX / 2] {
+        assert!(ralloc::calloc(usize::MAX / size + 1, size, 1).is_null());
+        assert!(ralloc::calloc(size, usize::MAX / size + 1, 1).is_null());
+    }
+
+    // The padding between the elements overflows.
+    assert!(ralloc::calloc(usize::MAX / 24 + 1, 20, 8).is_null());
+    assert!(ralloc::calloc(1, usize::MAX - 6, 8).is_null());
+}
+
+#[test]
+fn alloc_many_overflow() {
+    let mut ptrs = [0 as *mut u8; 4];
+    assert_eq!(ralloc::alloc_many(usize::MAX / 2, 8, &mut ptrs), 0);
+    assert_eq!(ralloc::alloc_many(usize::MAX, 1, &mut ptrs), 0);
+}
@@ -0,0 +1,27 @@
+extern crate ralloc;
+
+#[cfg(feature = "stats")]
+#[test]
+fn class_stats() {
+    use ralloc::stats::{self, SizeClass};
+
+    // 392 bytes is in the 448 class, which nothing else in this test allocates.
+    let class = SizeClass::of(392);
+    let before = stats::class(class);
+
+    unsafe {
+        let a = ralloc::alloc(392, 8);
+        let b = ralloc::alloc(392, 8);
+        ralloc::free(a, 392);
+
+        let after = stats::class(class);
+        assert!(after.allocs >= before.allocs + 2);
+        assert!(after.frees >= before.frees + 1);
+
+        ralloc::free(b, 392);
+    }
+
+    let mut report = String::new();
+    stats::write_report(&mut report).unwrap();
+    assert!(report.contains("448"));
+}
@@ -0,0 +1,84 @@
+extern crate ralloc;
+
+#[cfg(all(feature = "debugger", feature = "std"))]
+mod dump {
+    use ralloc;
+    use ralloc::debug::{self, HeapDump};
+
+    use std::{env, fs};
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom};
+    use std::os::unix::io::AsRawFd;
+
+    /// Dump the heap to a file with some budget, and read it back.
+    fn round_trip(name: &str, budget: usize) -> HeapDump {
+        let path = env::temp_dir().join(name);
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(true)
+            .open(&path).unwrap();
+
+        unsafe {
+            debug::dump_heap_with_contents(file.as_raw_fd(), budget).unwrap();
+        }
+
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let dump = debug::read_dump(&file).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        dump
+    }
+
+    // Every check is in a single test, such that no other test allocates during the dumps.
+    #[test]
+    fn dump_heap() {
+        let ptrs = [ralloc::alloc(100, 8), ralloc::alloc(3000, 64), ralloc::alloc(7, 1)];
+        unsafe {
+            for i in 0..100 {
+                *ptrs[0].offset(i) = i as u8;
+            }
+        }
+
+        let dump = round_trip("ralloc_dump_test", 1 << 20);
+
+        assert_eq!(dump.version, debug::VERSION);
+
+        // The totals match the statistics at the time of the dump.
+        assert_eq!(dump.free_total(), dump.free_bytes);
+        #[cfg(feature = "stats")]
+        assert_eq!(dump.live_bytes, Some(dump.live_total()));
+        #[cfg(not(feature = "stats"))]
+        assert_eq!(dump.live_bytes, None);
+
+        // The regions are sorted and disjoint, and contain the blocks.
+        assert!(!dump.regions.is_empty());
+        assert!(dump.regions.windows(2).all(|x| x[0].end <= x[1].start));
+        for &(start, size) in &dump.free {
+            assert!(dump.regions.iter().any(|x| x.start <= start && start + size <= x.end));
+        }
+
+        // Every buffer is listed, and the first one with its contents.
+        for (&ptr, &size) in ptrs.iter().zip(&[100, 3000, 7]) {
+            let allocation = dump.live.iter().find(|x| x.start == ptr as u64).unwrap();
+            assert_eq!(allocation.size, size);
+            assert!(dump.regions.iter().any(|x| x.start <= allocation.start
+                                                && allocation.start + size <= x.end));
+        }
+        let first = dump.live.iter().find(|x| x.start == ptrs[0] as u64).unwrap();
+        assert_eq!(first.contents, (0..100).collect::<Vec<u8>>());
+
+        // The contents are capped by the budget.
+        let dump = round_trip("ralloc_dump_test_budget", 10);
+        assert_eq!(dump.live.iter().map(|x| x.contents.len()).sum::<usize>(), 10);
+        let dump = round_trip("ralloc_dump_test_empty", 0);
+        assert!(dump.live.iter().all(|x| x.contents.is_empty()));
+
+        unsafe {
+            ralloc::free(ptrs[0], 100);
+            ralloc::free(ptrs[1], 3000);
+            ralloc::free(ptrs[2], 7);
+        }
+
+        // Truncated and foreign files are rejected.
+        assert!(debug::read_dump(&b"RALLOCHD"[..]).is_err());
+        assert!(debug::read_dump(&b"not a dump at all"[..]).is_err());
+    }
+}
@@ -0,0 +1,43 @@
+extern crate ralloc;
+
+#[cfg(not(feature = "single_threaded"))]
+use std::sync::{Arc, Barrier};
+#[cfg(not(feature = "single_threaded"))]
+use std::thread;
+
+#[cfg(all(feature = "early_init", feature = "stats"))]
+#[test]
+fn reserved_before_first_allocation() {
+    // Nothing is allocated by the test itself before this.
+    let stats = ralloc::stats::snapshot();
+    assert!(stats.early_reserved > 0);
+}
+
+#[test]
+#[cfg(not(feature = "single_threaded"))]
+fn racing_first_allocations() {
+    let barrier = Arc::new(Barrier::new(16));
+
+    let handles: Vec<_> = (0..16).map(|i| {
+        let barrier = barrier.clone();
+        thread::spawn(move || {
+            // Release every thread at once, such that their first allocations race.
+            barrier.wait();
+
+            let ptrs: Vec<_> = (1..64).map(|j| (ralloc::alloc(i * 64 + j, 8), i * 64 + j)).collect();
+            for (ptr, size) in ptrs {
+                unsafe {
+                    *ptr = i as u8;
+                    *ptr.offset(size as isize - 1) = i as u8;
+                    assert_eq!(*ptr, i as u8);
+
+                    ralloc::free(ptr, size);
+                }
+            }
+        })
+    }).collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
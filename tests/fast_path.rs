@@ -0,0 +1,79 @@
+extern crate ralloc;
+
+#[test]
+fn fast_path() {
+    // Whichever path they take, buffers are aligned to at least the minimal alignment.
+    for &(size, align) in &[(1, 1), (24, 8), (100, 16), (512, 16), (513, 16), (32, 64)] {
+        let ptr = ralloc::alloc(size, align);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % align, 0);
+        assert_eq!(ptr as usize % 16, 0);
+
+        unsafe {
+            for i in 0..size {
+                *ptr.offset(i as isize) = i as u8;
+            }
+
+            ralloc::free(ptr, size);
+        }
+    }
+
+    // Invalid alignments are rejected, even for small requests.
+    assert!(ralloc::alloc(8, 0).is_null());
+    assert!(ralloc::alloc(8, 3).is_null());
+
+    // The maximal allocation size applies to small requests too.
+    ralloc::set_max_allocation(256);
+    assert!(ralloc::alloc(300, 8).is_null());
+    ralloc::set_max_allocation(!0);
+
+    // With the statistics, small requests are counted, as they take the slow path.
+    #[cfg(feature = "stats")]
+    {
+        use ralloc::stats::{self, SizeClass};
+
+        let class = SizeClass::of(200);
+        let before = stats::class(class);
+
+        let ptr = ralloc::alloc(200, 8);
+        assert!(stats::class(class).allocs >= before.allocs + 1);
+
+        unsafe { ralloc::free(ptr, 200); }
+    }
+}
+
+#[test]
+fn odd_sizes_disjoint() {
+    // Odd sizes are freed with their rounded size, so they must be allocated with it as well, or
+    // the pool owns the bytes after them twice.
+    for size in 1..16 {
+        let ptr = ralloc::alloc(size, 8);
+        unsafe { ralloc::free(ptr, size); }
+    }
+
+    let mut bufs = Vec::new();
+    for _ in 0..4 {
+        for size in 1..16 {
+            let ptr = ralloc::alloc(size, 8);
+            unsafe {
+                for i in 0..size {
+                    *ptr.offset(i as isize) = size as u8;
+                }
+            }
+            bufs.push((ptr as usize, size));
+        }
+    }
+
+    for &(ptr, size) in &bufs {
+        for i in 0..size {
+            assert_eq!(unsafe { *((ptr + i) as *const u8) }, size as u8);
+        }
+    }
+
+    bufs.sort();
+    assert!(bufs.windows(2).all(|x| x[0].0 + x[0].1 <= x[1].0), "The buffers overlap.");
+
+    for (ptr, size) in bufs {
+        unsafe { ralloc::free(ptr as *mut u8, size); }
+    }
+}
@@ -0,0 +1,62 @@
+/* Drive the C interface of ralloc (see `tests/ffi.rs`). */
+
+#include <stdint.h>
+#include <string.h>
+
+#include "ralloc.h"
+
+/* Fail the test with the line number. */
+#define CHECK(x) do { if (!(x)) return __LINE__; } while (0)
+
+/* Run the test, returning 0 on success, and the failing line otherwise. */
+int ralloc_ffi_test(void) {
+    void *buf = NULL;
+    ralloc_heap *heap = NULL;
+
+    /* The global allocator. */
+    CHECK(ralloc_alloc(100, 64, &buf) == RALLOC_OK);
+    CHECK((uintptr_t)buf % 64 == 0);
+    memset(buf, 0xAB, 100);
+    CHECK(ralloc_realloc(buf, 100, 5000, 64, &buf) == RALLOC_OK);
+    CHECK(((unsigned char *)buf)[99] == 0xAB);
+    CHECK(ralloc_free(buf, 5000) == RALLOC_OK);
+    CHECK(ralloc_free(NULL, 0) == RALLOC_OK);
+
+    /* A heap. */
+    CHECK(ralloc_heap_new(&heap) == RALLOC_OK);
+    CHECK(ralloc_heap_alloc(heap, 256, 16, &buf) == RALLOC_OK);
+    CHECK((uintptr_t)buf % 16 == 0);
+    memset(buf, 0xCD, 256);
+    CHECK(ralloc_heap_realloc(heap, buf, 256, 1024, 16, &buf) == RALLOC_OK);
+    CHECK(((unsigned char *)buf)[255] == 0xCD);
+
+    ralloc_heap_stats heap_stats;
+    CHECK(ralloc_heap_stats_get(heap, &heap_stats) == RALLOC_OK);
+    CHECK(heap_stats.owned_bytes >= 1024);
+    CHECK(heap_stats.live_bytes >= 1024);
+
+    CHECK(ralloc_heap_free(heap, buf, 1024) == RALLOC_OK);
+
+    /* The allocator statistics need the `stats` feature. */
+    ralloc_stats stats;
+    int res = ralloc_stats_get(&stats);
+    CHECK(res == RALLOC_OK || res == RALLOC_EUNSUPPORTED);
+
+    ralloc_purge_report report;
+    CHECK(ralloc_purge(&report) == RALLOC_OK);
+    CHECK(ralloc_purge(NULL) == RALLOC_OK);
+
+    /* Misuse is reported rather than crashing. */
+    int local;
+    CHECK(ralloc_alloc(8, 3, &buf) == RALLOC_EINVAL);
+    CHECK(ralloc_alloc(8, 8, NULL) == RALLOC_EINVAL);
+    CHECK(ralloc_heap_alloc(NULL, 8, 8, &buf) == RALLOC_EHANDLE);
+    CHECK(ralloc_heap_alloc(heap, 8, 0, &buf) == RALLOC_EINVAL);
+    CHECK(ralloc_heap_free(heap, &local, sizeof(local)) == RALLOC_EINVAL);
+    CHECK(ralloc_heap_destroy((ralloc_heap *)&local) == RALLOC_EHANDLE);
+    CHECK(ralloc_free(&local, sizeof(local)) == RALLOC_EINVAL);
+
+    CHECK(ralloc_heap_destroy(heap) == RALLOC_OK);
+
+    return 0;
+}
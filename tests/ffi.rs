@@ -0,0 +1,17 @@
+//! Run the C test of the FFI (`tests/ffi.c`), which the build script compiles with the
+//! `ffi_test` feature.
+
+#![cfg(feature = "ffi_test")]
+
+extern crate ralloc;
+
+#[link(name = "ralloc_ffi_test", kind = "static")]
+extern {
+    fn ralloc_ffi_test() -> i32;
+}
+
+#[test]
+fn c_surface() {
+    // The failing line of the C test, if any.
+    assert_eq!(unsafe { ralloc_ffi_test() }, 0);
+}
@@ -0,0 +1,38 @@
+extern crate ralloc;
+
+#[cfg(feature = "debugger")]
+#[cfg(not(any(feature = "header", feature = "sidetable")))]
+#[test]
+fn find_allocation() {
+    use ralloc::debug::find_allocation;
+
+    unsafe {
+        // Free the tail, such that the byte after the allocation is not in another allocation.
+        let ptr = ralloc::alloc(200, 8);
+        ralloc::free(ptr.offset(100), 100);
+
+        assert_eq!(find_allocation(ptr), Some((ptr, 100)));
+        assert_eq!(find_allocation(ptr.offset(37)), Some((ptr, 100)));
+        assert_eq!(find_allocation(ptr.offset(99)), Some((ptr, 100)));
+        assert_eq!(find_allocation(ptr.offset(100)), None);
+
+        ralloc::free(ptr, 100);
+        assert_eq!(find_allocation(ptr), None);
+        assert_eq!(find_allocation(ptr.offset(50)), None);
+    }
+}
+
+#[cfg(feature = "debugger")]
+#[test]
+fn find_allocation_realloc() {
+    use ralloc::debug::find_allocation;
+
+    unsafe {
+        let ptr = ralloc::alloc(16, 8);
+        let ptr = ralloc::realloc(ptr, 16, 4000, 8);
+
+        assert_eq!(find_allocation(ptr.offset(3999)), Some((ptr, 4000)));
+
+        ralloc::free(ptr, 4000);
+    }
+}
@@ -0,0 +1,56 @@
+extern crate ralloc;
+
+#[cfg(feature = "test_util")]
+mod grow_error {
+    use std::{env, process};
+
+    use ralloc::{self, AllocErr, GrowError};
+    use ralloc::test_util::inject;
+
+    /// The environment variable marking the child process.
+    const CHILD_VAR: &'static str = "RALLOC_TEST_GROW_ERROR_CHILD";
+    /// The exit code of the child, when the OOM handler got the right error.
+    const CLASSIFIED: i32 = 42;
+
+    /// The OOM handler of the child.
+    fn handler(err: AllocErr) -> ! {
+        if err == AllocErr::Os(GrowError::Brk(12)) {
+            process::exit(CLASSIFIED);
+        }
+
+        process::abort();
+    }
+
+    /// The body of the child process.
+    #[test]
+    fn brk_child() {
+        if env::var(CHILD_VAR).is_err() { return; }
+
+        ralloc::set_oom_handler(handler);
+        inject::fail_brk(12);
+
+        // Nothing this large is in the pool, so the program break must grow.
+        ralloc::alloc(1 << 30, 8);
+    }
+
+    #[test]
+    fn brk_failure() {
+        let status = process::Command::new(env::current_exe().unwrap())
+            .arg("grow_error::brk_child")
+            .arg("--exact")
+            .env(CHILD_VAR, "1")
+            .status()
+            .unwrap();
+
+        assert_eq!(status.code(), Some(CLASSIFIED));
+    }
+
+    #[test]
+    fn mmap_failure() {
+        // Each failure is injected into the next mapping, which is the one of the secure buffer.
+        for &errno in &[12, 24] {
+            inject::fail_mmap(errno);
+            assert_eq!(ralloc::secure_alloc(100, 8), Err(AllocErr::Os(GrowError::Mmap(errno))));
+        }
+    }
+}
@@ -0,0 +1,78 @@
+extern crate ralloc;
+
+#[test]
+fn round_trip() {
+    let ptr = ralloc::Ptr::alloc(100, 8);
+
+    unsafe {
+        *ptr.as_raw() = 42;
+        let ptr = ptr.realloc(100, 1000, 8);
+        assert_eq!(*ptr.as_raw(), 42);
+        ptr.free(1000);
+
+        // Conversions are explicit, both ways.
+        let raw = ralloc::alloc(16, 8);
+        let ptr = ralloc::Ptr::from_raw(raw);
+        assert_eq!(ptr.as_raw(), raw);
+        ralloc::free(ptr.into_raw(), 16);
+    }
+}
+
+#[test]
+#[cfg(not(feature = "debugger"))]
+fn bare_pointer() {
+    use std::mem;
+
+    // Without the debugger, nothing is added to the pointer.
+    assert_eq!(mem::size_of::<ralloc::Ptr>(), mem::size_of::<*mut u8>());
+    assert_eq!(mem::align_of::<ralloc::Ptr>(), mem::align_of::<*mut u8>());
+}
+
+#[cfg(feature = "debugger")]
+mod handle {
+    use std::{env, process};
+
+    use ralloc;
+
+    #[test]
+    fn generation() {
+        let ptr = ralloc::Ptr::alloc(64, 8);
+        let copy = ptr.clone();
+
+        unsafe {
+            // Copies of a live handle are usable.
+            assert_eq!(copy.into_raw(), ptr.as_raw());
+            ptr.free(64);
+
+            // A new allocation at the same address is told apart.
+            let new = ralloc::Ptr::alloc(64, 8);
+            assert!(new != copy);
+            new.free(64);
+        }
+    }
+
+    #[test]
+    fn dead_handle_aborts() {
+        if env::var("RALLOC_HANDLE_CHILD").is_ok() {
+            // Free through a copy, and use the original.
+            let ptr = ralloc::Ptr::alloc(64, 8);
+            let copy = ptr.clone();
+            unsafe {
+                copy.free(64);
+                ptr.free(64);
+            }
+
+            return;
+        }
+
+        // Run the test again in a child process.
+        let out = process::Command::new(env::current_exe().unwrap())
+            .arg("dead_handle_aborts")
+            .env("RALLOC_HANDLE_CHILD", "1")
+            .output()
+            .unwrap();
+
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("ralloc: generation mismatch"));
+    }
+}
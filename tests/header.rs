@@ -0,0 +1,37 @@
+extern crate ralloc;
+
+#[cfg(feature = "header")]
+mod header {
+    use ralloc;
+
+    #[test]
+    fn free_unsized() {
+        for align in 1..65 {
+            let ptr = ralloc::alloc(100, align);
+            assert_eq!(ptr as usize % align, 0);
+
+            unsafe {
+                assert_eq!(ralloc::usable_size(ptr), 100);
+
+                let ptr = ralloc::realloc(ptr, 100, 1000, align);
+                assert_eq!(ralloc::usable_size(ptr), 1000);
+
+                ralloc::free_unsized(ptr);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(not(feature = "security"))]
+    fn corrupted_header() {
+        let ptr = ralloc::alloc(100, 8);
+
+        unsafe {
+            // Overrun the previous buffer into the header.
+            *ptr.offset(-3) ^= 0x10;
+
+            ralloc::free(ptr, 100);
+        }
+    }
+}
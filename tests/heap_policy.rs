@@ -0,0 +1,131 @@
+extern crate ralloc;
+
+mod heap_policy {
+    use std::{env, process};
+
+    use ralloc::{DropPolicy, Heap};
+
+    /// The environment variable marking the child process.
+    const CHILD_VAR: &'static str = "RALLOC_TEST_HEAP_POLICY_CHILD";
+
+    // The heap counts are global, so this is the only test creating heaps in the parent process.
+    #[test]
+    #[cfg(feature = "stats")]
+    fn leak_and_reclaim() {
+        use ralloc::stats;
+
+        let before = stats::snapshot();
+
+        // A leaked heap keeps its regions.
+        let heap = Heap::new();
+        assert_eq!(heap.policy(), DropPolicy::Leak);
+        let ptr = heap.alloc(100, 8);
+        unsafe { *ptr = 42; }
+        let owned = heap.owned_bytes();
+        assert_eq!(stats::snapshot().heap_count, before.heap_count + 1);
+        assert_eq!(stats::snapshot().heap_bytes, before.heap_bytes + owned);
+
+        drop(heap);
+        let leaked = stats::snapshot();
+        assert_eq!(leaked.heap_count, before.heap_count);
+        assert_eq!(leaked.heap_bytes, before.heap_bytes);
+        assert_eq!(leaked.heap_leaked, before.heap_leaked + owned);
+        // The buffer outlives its heap, and stays recorded.
+        unsafe { assert_eq!(*ptr, 42); }
+        #[cfg(feature = "debugger")]
+        assert_eq!(ralloc::debug::find_allocation(ptr), Some((ptr, 100)));
+
+        // A reclaimed heap gives every region back, the one taken for a large buffer included.
+        let heap = unsafe { Heap::with_policy(DropPolicy::Reclaim) };
+        let small = heap.alloc(1000, 8);
+        let large = heap.alloc(256 * 1024, 8);
+        let owned = heap.owned_bytes();
+        assert_eq!(stats::snapshot().heap_count, before.heap_count + 1);
+        assert_eq!(stats::snapshot().heap_bytes, before.heap_bytes + owned);
+        #[cfg(feature = "debugger")]
+        assert_eq!(ralloc::debug::find_allocation(large), Some((large, 256 * 1024)));
+
+        drop(heap);
+        let reclaimed = stats::snapshot();
+        assert_eq!(reclaimed.heap_count, before.heap_count);
+        assert_eq!(reclaimed.heap_bytes, before.heap_bytes);
+        assert_eq!(reclaimed.heap_leaked, leaked.heap_leaked);
+
+        // The buffers are gone from the registry of live allocations.
+        #[cfg(feature = "debugger")]
+        {
+            assert_eq!(ralloc::debug::find_allocation(small), None);
+            assert_eq!(ralloc::debug::find_allocation(large), None);
+        }
+        let _ = (small, large);
+    }
+
+    /// The body of the child process.
+    #[test]
+    #[cfg(feature = "stats")]
+    fn leaked_free_child() {
+        use ralloc::stats::{self, SizeClass};
+
+        if env::var(CHILD_VAR).is_err() { return; }
+
+        let heap = Heap::new();
+        let ptr = heap.alloc(100, 8);
+        unsafe { *ptr = 42; }
+        drop(heap);
+
+        // Freeing a buffer of the leaked heap is ignored, rather than handed to the global
+        // allocator.
+        let frees = stats::class(SizeClass::of(100)).frees;
+        unsafe { ralloc::route_free(ptr, 100); }
+        assert_eq!(stats::class(SizeClass::of(100)).frees, frees);
+        unsafe { assert_eq!(*ptr, 42); }
+        #[cfg(feature = "debugger")]
+        assert_eq!(ralloc::debug::find_allocation(ptr), None);
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn leaked_free() {
+        // The class counts are global, so the free runs in a child process.
+        let status = process::Command::new(env::current_exe().unwrap())
+            .arg("heap_policy::leaked_free_child")
+            .arg("--exact")
+            .env(CHILD_VAR, "1")
+            .status()
+            .unwrap();
+
+        assert!(status.success());
+    }
+
+    /// The body of the child process.
+    #[test]
+    fn abort_child() {
+        if env::var(CHILD_VAR).is_err() { return; }
+
+        let heap = unsafe { Heap::with_policy(DropPolicy::Abort) };
+        heap.alloc(123, 8);
+        heap.alloc(4567, 8);
+
+        drop(heap);
+    }
+
+    #[test]
+    fn abort() {
+        let out = process::Command::new(env::current_exe().unwrap())
+            .arg("heap_policy::abort_child")
+            .arg("--exact")
+            .env(CHILD_VAR, "1")
+            .output()
+            .unwrap();
+
+        assert!(!out.status.success());
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains("ralloc: aborting, as a heap was dropped with 4690 live bytes"));
+
+        // The buffers are only recorded by the debugger.
+        if cfg!(feature = "debugger") {
+            assert!(stderr.contains("ralloc: live heap buffer of 123 bytes"));
+            assert!(stderr.contains("ralloc: live heap buffer of 4567 bytes"));
+        }
+    }
+}
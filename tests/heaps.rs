@@ -0,0 +1,201 @@
+extern crate ralloc;
+
+mod heaps {
+    use ralloc::{self, Heap};
+
+    use std::ptr;
+
+    #[test]
+    fn route_free() {
+        let heaps = [Heap::new(), Heap::new(), Heap::new()];
+
+        // Allocate from the heaps and the global allocator in turns.
+        let mut bufs = Vec::new();
+        for n in 0..600 {
+            let size = 1 + n * 37 % 3000;
+            let owner = n % 4;
+            let ptr = if owner == 3 {
+                ralloc::alloc(size, 8)
+            } else {
+                heaps[owner].alloc(size, 8)
+            };
+            unsafe { *ptr = owner as u8; }
+
+            bufs.push((ptr, size, owner));
+        }
+
+        for &(ptr, _, owner) in &bufs {
+            for (n, heap) in heaps.iter().enumerate() {
+                assert_eq!(heap.owns(ptr), n == owner);
+            }
+        }
+
+        // Shuffle the buffers.
+        let mut state = 7usize;
+        for n in (1..bufs.len()).rev() {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            bufs.swap(n, (state >> 16) % (n + 1));
+        }
+
+        for (ptr, size, owner) in bufs {
+            unsafe {
+                assert_eq!(*ptr, owner as u8);
+                ralloc::route_free(ptr, size);
+            }
+        }
+
+        // Every heap got all of its buffers back.
+        for heap in &heaps {
+            heap.check();
+            assert_eq!(heap.live_bytes(), 0);
+            assert!(heap.free_bytes() > 0 && heap.free_bytes() < heap.owned_bytes());
+        }
+    }
+
+    #[test]
+    fn drop_deregisters() {
+        // More heaps than the registry can hold are created over time, but never at once.
+        for _ in 0..2 * ralloc::MAX_HEAPS {
+            let heap = Heap::new();
+            let ptr = heap.alloc(100, 8);
+            assert!(heap.owns(ptr));
+
+            unsafe { ralloc::route_free(ptr, 100); }
+            assert_eq!(heap.live_bytes(), 0);
+        }
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let heap = Heap::new();
+        let a = heap.alloc(1000, 8);
+        let b = heap.alloc(500, 8);
+        unsafe {
+            ptr::write_bytes(a, 1, 1000);
+            ptr::write_bytes(b, 2, 500);
+        }
+
+        let snapshot = heap.snapshot();
+        let stats = (heap.owned_bytes(), heap.free_bytes(), heap.live_bytes());
+        assert!(snapshot.size() >= stats.0);
+
+        // Mutate the data and the bookkeeping: free a buffer, and take a new region.
+        let c = heap.alloc(256 * 1024, 8);
+        unsafe {
+            ptr::write_bytes(a, 3, 1000);
+            heap.free(b, 500);
+            ptr::write_bytes(c, 4, 256 * 1024);
+        }
+        assert!(heap.owned_bytes() > stats.0);
+
+        for _ in 0..2 {
+            unsafe { heap.restore(&snapshot); }
+            heap.check();
+
+            // The contents and the statistics are back, and the new region is gone.
+            assert_eq!((heap.owned_bytes(), heap.free_bytes(), heap.live_bytes()), stats);
+            assert!(!heap.owns(c));
+            unsafe {
+                assert!((0..1000).all(|i| *a.offset(i) == 1));
+                assert!((0..500).all(|i| *b.offset(i) == 2));
+            }
+
+            // The buffers are live again, so they can be freed.
+            unsafe {
+                ptr::write_bytes(a, 5, 1000);
+                heap.free(b, 500);
+            }
+        }
+
+        unsafe {
+            heap.restore(&snapshot);
+            heap.free(a, 1000);
+            heap.free(b, 500);
+        }
+        assert_eq!(heap.live_bytes(), 0);
+    }
+
+    #[test]
+    fn reset() {
+        let mut heap = Heap::new();
+
+        let mut bufs = Vec::new();
+        for n in 0..4000 {
+            let size = 8 + n * 13 % 200;
+            let ptr = heap.alloc(size, 8);
+            unsafe { ptr::write_bytes(ptr, 1, size); }
+
+            bufs.push((ptr, size));
+        }
+        let owned = heap.owned_bytes();
+
+        unsafe { heap.reset(); }
+        heap.check();
+
+        // Everything but the metadata is free, and the regions stayed.
+        assert_eq!(heap.live_bytes(), 0);
+        assert_eq!(heap.owned_bytes(), owned);
+        assert!(heap.free_bytes() > bufs.iter().fold(0, |acc, &(_, size)| acc + size));
+
+        // The same buffers fit again, in the memory of the old ones.
+        let mut old: Vec<_> = bufs.iter().map(|&(ptr, _)| ptr).collect();
+        old.sort();
+        let mut reused = 0;
+        for &(_, size) in &bufs {
+            let ptr = heap.alloc(size, 8);
+            assert!(heap.owns(ptr));
+            if old.binary_search(&ptr).is_ok() {
+                reused += 1;
+            }
+        }
+        heap.check();
+        assert_eq!(heap.owned_bytes(), owned);
+        assert!(reused > 0);
+
+        unsafe { heap.reset(); }
+        assert_eq!(heap.live_bytes(), 0);
+    }
+
+    #[cfg(feature = "debugger")]
+    #[test]
+    fn reset_live() {
+        use ralloc::debug::find_allocation;
+
+        let mut heap = Heap::new();
+        let a = heap.alloc(100, 8);
+        assert_eq!(find_allocation(a), Some((a, 100)));
+
+        unsafe { heap.reset(); }
+        assert_eq!(find_allocation(a), None);
+
+        // The memory is handed out again.
+        let b = heap.alloc(100, 8);
+        assert!(heap.owns(b));
+        unsafe { heap.free(b, 100); }
+        assert_eq!(heap.live_bytes(), 0);
+    }
+
+    #[cfg(feature = "debugger")]
+    #[test]
+    fn snapshot_restore_live() {
+        use ralloc::debug::find_allocation;
+
+        let heap = Heap::new();
+        let a = heap.alloc(100, 8);
+
+        let snapshot = heap.snapshot();
+
+        let b = heap.alloc(200, 8);
+        unsafe { heap.free(a, 100); }
+        assert_eq!(find_allocation(a), None);
+        assert_eq!(find_allocation(b), Some((b, 200)));
+
+        // The table is back to the time of the snapshot.
+        unsafe { heap.restore(&snapshot); }
+        assert_eq!(find_allocation(a), Some((a, 100)));
+        assert_eq!(find_allocation(b), None);
+
+        unsafe { heap.free(a, 100); }
+        assert_eq!(heap.live_bytes(), 0);
+    }
+}
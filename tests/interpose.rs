@@ -0,0 +1,65 @@
+extern crate ralloc;
+
+#[cfg(feature = "interpose")]
+mod interpose {
+    use ralloc;
+
+    extern {
+        fn malloc(size: usize) -> *mut u8;
+        #[cfg(any(feature = "header", feature = "sidetable"))]
+        fn malloc_usable_size(ptr: *mut u8) -> usize;
+    }
+
+    /// Allocate a buffer with the system allocator, which `ralloc` doesn't replace here.
+    fn foreign(size: usize) -> *mut u8 {
+        let ptr = unsafe { malloc(size) };
+        assert!(!ptr.is_null());
+
+        unsafe {
+            for i in 0..size {
+                *ptr.offset(i as isize) = i as u8;
+            }
+        }
+
+        ptr
+    }
+
+    #[test]
+    fn free() {
+        for _ in 0..1000 {
+            unsafe { ralloc::free(foreign(100), 100); }
+        }
+
+        // The system allocator is still intact.
+        unsafe { ralloc::free(foreign(100), 100); }
+    }
+
+    #[test]
+    fn realloc() {
+        unsafe {
+            let ptr = ralloc::realloc(foreign(64), 64, 4096, 8);
+            for i in 0..64 {
+                assert_eq!(*ptr.offset(i), i as u8);
+            }
+
+            // The buffer is ours now, so it can be resized inplace.
+            assert!(ralloc::realloc_inplace(ptr, 4096, 100).is_ok());
+            ralloc::free(ptr, 100);
+
+            // Foreign buffers are never resized inplace.
+            let ptr = foreign(64);
+            assert!(ralloc::realloc_inplace(ptr, 64, 32).is_err());
+            ralloc::free(ptr, 64);
+        }
+    }
+
+    #[cfg(any(feature = "header", feature = "sidetable"))]
+    #[test]
+    fn usable_size() {
+        unsafe {
+            let ptr = foreign(100);
+            assert_eq!(ralloc::usable_size(ptr), malloc_usable_size(ptr));
+            ralloc::free_unsized(ptr);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+extern crate ralloc;
+
+#[cfg(feature = "debugger")]
+mod leak_age {
+    use ralloc;
+
+    #[test]
+    fn allocations_older_than() {
+        let old: Vec<_> = (0..10).map(|_| ralloc::alloc(777, 8)).collect();
+
+        // Churn for a few generations.
+        let start = ralloc::debug::generation();
+        while ralloc::debug::generation() < start + 4 {
+            let ptr = ralloc::alloc(16, 8);
+            unsafe { ralloc::free(ptr, 16); }
+        }
+
+        let young: Vec<_> = (0..10).map(|_| ralloc::alloc(778, 8)).collect();
+
+        let mut found = Vec::new();
+        ralloc::debug::allocations_older_than(2, |ptr, size, age| {
+            assert!(age > 2);
+            found.push((ptr, size));
+        });
+
+        for &ptr in &old {
+            assert!(found.contains(&(ptr, 777)));
+        }
+        for &ptr in &young {
+            assert!(!found.contains(&(ptr, 778)));
+        }
+
+        unsafe {
+            for ptr in old {
+                ralloc::free(ptr, 777);
+            }
+            for ptr in young {
+                ralloc::free(ptr, 778);
+            }
+        }
+    }
+}
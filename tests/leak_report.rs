@@ -0,0 +1,64 @@
+extern crate ralloc;
+
+#[cfg(feature = "debugger")]
+mod leak_report {
+    use ralloc;
+
+    use std::{env, process};
+
+    #[test]
+    fn leak_report() {
+        if env::var("RALLOC_LEAK_CHILD").is_ok() {
+            // Leak a recognizable buffer, and free another one.
+            ralloc::alloc(12345, 8);
+            let ptr = ralloc::alloc(54321, 8);
+            unsafe { ralloc::free(ptr, 54321); }
+
+            // Leak a younger one, a few generations later.
+            for _ in 0..4096 {
+                let ptr = ralloc::alloc(64, 8);
+                unsafe { ralloc::free(ptr, 64); }
+            }
+            ralloc::alloc(23456, 8);
+
+            return;
+        }
+
+        // Run the test again in a child process, which reports its leaks at exit.
+        let out = process::Command::new(env::current_exe().unwrap())
+            .arg("leak_report")
+            .env("RALLOC_LEAK_CHILD", "1")
+            .env("RALLOC_CONF", "leak_report:1")
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&out.stderr);
+
+        assert!(out.status.success());
+        assert!(stderr.contains("ralloc: leaked 12345 bytes at 0x"));
+        assert!(!stderr.contains("ralloc: leaked 54321 bytes"));
+        assert!(stderr.contains("bytes leaked in"));
+
+        // The oldest leaks are listed first.
+        let old = stderr.find("ralloc: leaked 12345 bytes").unwrap();
+        let young = stderr.find("ralloc: leaked 23456 bytes").unwrap();
+        assert!(old < young);
+    }
+
+    #[test]
+    fn no_leak_report() {
+        if env::var("RALLOC_LEAK_CHILD").is_ok() {
+            return;
+        }
+
+        // Without the configuration, nothing is reported.
+        let out = process::Command::new(env::current_exe().unwrap())
+            .arg("no_leak_report")
+            .env("RALLOC_LEAK_CHILD", "1")
+            .env_remove("RALLOC_CONF")
+            .output()
+            .unwrap();
+
+        assert!(out.status.success());
+        assert!(!String::from_utf8_lossy(&out.stderr).contains("bytes leaked in"));
+    }
+}
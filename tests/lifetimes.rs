@@ -0,0 +1,59 @@
+extern crate ralloc;
+
+// The side table only records the birth on 64-bit targets.
+#[cfg(all(feature = "stats", feature = "test_util",
+          any(feature = "header", all(feature = "sidetable", target_pointer_width = "64"))))]
+mod lifetimes {
+    use ralloc;
+    use ralloc::stats::{self, SizeClass};
+    use ralloc::test_util::advance_generation;
+
+    /// The size of the buffers.
+    ///
+    /// This is beyond the slabs, whose cells have no entries in the side table.
+    const SIZE: usize = 4000;
+    /// The number of buffers of every age.
+    const COUNT: usize = 10;
+
+    /// Allocate buffers, let them age `age` generations, and free them.
+    fn churn(age: usize) {
+        let ptrs: Vec<usize> = (0..COUNT).map(|_| ralloc::alloc(SIZE, 8) as usize).collect();
+        advance_generation(age);
+        for ptr in ptrs {
+            unsafe { ralloc::free(ptr as *mut u8, SIZE); }
+        }
+    }
+
+    // The generation clock is shared, so everything is in a single test.
+    #[test]
+    fn buckets() {
+        let class = SizeClass::of(SIZE);
+        let before = stats::lifetime_histogram();
+
+        // Ages 4 to 7, 32 to 63, 128 to 255, and 512 to 1023 (beyond a byte), with room for the
+        // clock to advance on its own.
+        churn(5);
+        churn(40);
+        churn(200);
+        churn(600);
+
+        let after = stats::lifetime_histogram();
+        for &bucket in &[3, 6, 8, 10] {
+            assert_eq!(after.count(class, bucket), before.count(class, bucket) + COUNT);
+        }
+
+        // Reallocated buffers keep their birth.
+        let before = after;
+
+        let ptr = ralloc::alloc(SIZE, 8);
+        advance_generation(20);
+        unsafe {
+            let ptr = ralloc::realloc(ptr, SIZE, 2 * SIZE, 8);
+            ralloc::free(ptr, 2 * SIZE);
+        }
+
+        // Ages 16 to 31.
+        let after = stats::lifetime_histogram();
+        assert_eq!(after.count(class, 5), before.count(class, 5) + 1);
+    }
+}
@@ -7,11 +7,11 @@ use std::ptr;
 #[test]
 fn manual() {
     util::multiply(|| {
-        let ptr1 = ralloc::alloc(30, 3);
-        let ptr2 = ralloc::alloc(500, 20);
+        let ptr1 = ralloc::alloc(30, 4);
+        let ptr2 = ralloc::alloc(500, 32);
 
-        assert_eq!(0, ptr1 as usize % 3);
-        assert_eq!(0, ptr2 as usize % 20);
+        assert_eq!(0, ptr1 as usize % 4);
+        assert_eq!(0, ptr2 as usize % 32);
 
         unsafe {
             util::acid(|| {
@@ -29,7 +29,7 @@ fn manual() {
             assert_eq!(*ptr2, 0);
             assert_eq!(*ptr2.offset(15), 15);
 
-            let ptr1 = ralloc::realloc(ptr1, 30, 300, 3);
+            let ptr1 = ralloc::realloc(ptr1, 30, 300, 4);
             for i in 0..300 {
                 util::acid(|| {
                     *ptr1.offset(i) = i as u8;
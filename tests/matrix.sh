@@ -0,0 +1,35 @@
+#!/bin/sh
+# Run the test suite under every metadata mode.
+
+set -e
+
+cargo test
+cargo test --features header
+cargo test --features sidetable
+# The allocator must not be shared between threads, so the tests run one at a time.
+cargo test --no-default-features --features "allocator single_threaded" -- --test-threads=1
+# The C interface, driven from C.
+cargo test --features "ffi_test stats"
+# Heap dumps, read back through the standard library.
+cargo test --features "debugger stats std"
+# Granule rounding (the tags themselves need MTE hardware).
+cargo test --features mte
+# Deferred frees.
+cargo test --features bounded_free
+# The counters, reconciled against a shadow accountant.
+cargo test --features shadow_accounting
+# Preallocation, with the growths of the heap counted.
+cargo test --features "test_util stats"
+# Allocation lifetimes, read from the headers.
+cargo test --features "header stats test_util"
+# NUMA placement, with mbind mocked.
+cargo test --features "numa stats test_util"
+# Traces and heap dumps, in the wire format.
+cargo test --features "debugger std test_util trace"
+# Realloc slack, given back under an injected failure to grow.
+cargo test --features "realloc_slack stats test_util"
+# The same, with the slack going to the global pool directly.
+cargo test --no-default-features --features "allocator realloc_slack stats test_util"
+# Raw pools, and the fixed-size allocator built on them.
+cargo test --features raw
+cargo test --example fixed_size --features raw
@@ -0,0 +1,46 @@
+extern crate ralloc;
+
+use ralloc::AllocErr;
+
+#[test]
+fn max_allocation() {
+    #[cfg(feature = "stats")]
+    let rejected = ralloc::stats::snapshot().too_large;
+
+    // Other tests of this file might run meanwhile, so the limit leaves room for them.
+    ralloc::set_max_allocation(1 << 20);
+    assert_eq!(ralloc::max_allocation(), 1 << 20);
+
+    assert_eq!(ralloc::try_alloc(2 << 20, 8),
+               Err(AllocErr::TooLarge { requested: 2 << 20, limit: 1 << 20 }));
+    assert!(ralloc::alloc(2 << 20, 8).is_null());
+    assert!(ralloc::calloc(2 << 10, 1 << 10, 8).is_null());
+
+    let ptr = ralloc::try_alloc(1 << 10, 8).unwrap();
+    unsafe {
+        *ptr = 42;
+
+        // Growing beyond the limit fails, and leaves the buffer intact.
+        assert!(ralloc::realloc(ptr, 1 << 10, 2 << 20, 8).is_null());
+        assert!(ralloc::realloc_inplace(ptr, 1 << 10, 2 << 20).is_err());
+        assert_eq!(*ptr, 42);
+
+        // Shrinking and growing below the limit is fine.
+        let ptr = ralloc::realloc(ptr, 1 << 10, 1 << 20, 8);
+        assert!(!ptr.is_null());
+        assert_eq!(*ptr, 42);
+
+        // The limit can be raised at runtime.
+        ralloc::set_max_allocation(4 << 20);
+        let ptr = ralloc::realloc(ptr, 1 << 20, 2 << 20, 8);
+        assert!(!ptr.is_null());
+        assert_eq!(*ptr, 42);
+
+        ralloc::free(ptr, 2 << 20);
+    }
+
+    ralloc::set_max_allocation(!0);
+
+    #[cfg(feature = "stats")]
+    assert!(ralloc::stats::snapshot().too_large >= rejected + 5);
+}
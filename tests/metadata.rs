@@ -0,0 +1,68 @@
+extern crate ralloc;
+
+#[cfg(any(feature = "header", feature = "sidetable"))]
+mod metadata {
+    use ralloc;
+
+    #[test]
+    fn usable_size() {
+        for &size in &[1, 24, 100, 512, 513, 4000, 100000] {
+            let ptr = ralloc::alloc(size, 8);
+
+            unsafe {
+                // Slab buffers can be rounded up to their cell.
+                assert!(ralloc::usable_size(ptr) >= size);
+
+                ralloc::free_unsized(ptr);
+            }
+        }
+    }
+
+    #[test]
+    fn realloc() {
+        unsafe {
+            let mut ptr = ralloc::alloc(1000, 16);
+            let mut size = 1000;
+
+            for &new in &[2000, 600, 50000, 1000] {
+                ptr = ralloc::realloc(ptr, size, new, 16);
+                size = new;
+
+                assert_eq!(ralloc::usable_size(ptr), size);
+            }
+
+            ralloc::free_unsized(ptr);
+        }
+    }
+
+    #[test]
+    fn many_large() {
+        let mut ptrs = Vec::new();
+        for i in 0..200 {
+            ptrs.push((ralloc::alloc(600 + i, 64), 600 + i));
+        }
+
+        unsafe {
+            for &(ptr, size) in &ptrs {
+                assert_eq!(ptr as usize % 64, 0);
+                assert_eq!(ralloc::usable_size(ptr), size);
+            }
+            for &(ptr, _) in &ptrs {
+                ralloc::free_unsized(ptr);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "sidetable", not(feature = "header")))]
+    fn exact_pointers() {
+        // Without headers, buffers carry no padding, so page alignment wastes nothing before them.
+        let ptr = ralloc::alloc(4096, 4096);
+        assert_eq!(ptr as usize % 4096, 0);
+
+        unsafe {
+            assert_eq!(ralloc::usable_size(ptr), 4096);
+            ralloc::free(ptr, 4096);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+extern crate ralloc;
+
+mod util;
+
+/// A xorshift generator for the sizes.
+fn next(x: &mut u32) -> usize {
+    *x ^= *x << 13;
+    *x ^= *x >> 17;
+    *x ^= *x << 5;
+
+    *x as usize
+}
+
+#[test]
+fn min_align() {
+    util::multiply(|| {
+        let mut seed = 0x2545F491;
+        let mut ptrs = Vec::with_capacity(2000);
+
+        for i in 0..2000 {
+            // Mostly small sizes, with some larger ones, and every small alignment.
+            let max = if i % 10 == 0 { 10000 } else { 600 };
+            let size = next(&mut seed) % max + 1;
+            let align = 1 << (i % 4);
+
+            let ptr = ralloc::alloc(size, align);
+            assert_eq!(ptr as usize % ralloc::MIN_ALIGN, 0);
+
+            unsafe {
+                util::acid(|| {
+                    *ptr = 1;
+                    *ptr.offset(size as isize - 1) = 2;
+                });
+            }
+
+            ptrs.push((ptr, size));
+        }
+
+        for (ptr, size) in ptrs {
+            unsafe {
+                // Reallocations keep the guarantee.
+                let ptr = ralloc::realloc(ptr, size, size + 24, 1);
+                assert_eq!(ptr as usize % ralloc::MIN_ALIGN, 0);
+                assert_eq!(*ptr, 1);
+
+                ralloc::free(ptr, size + 24);
+            }
+        }
+
+        // So do zeroed arrays, which are still laid out with the requested alignment.
+        let ptr = ralloc::calloc(3, 5, 1);
+        assert_eq!(ptr as usize % ralloc::MIN_ALIGN, 0);
+        unsafe {
+            ralloc::free(ptr, 15);
+        }
+    });
+}
+
+#[test]
+fn over_aligned() {
+    util::multiply(|| {
+        for &size in &[1, 100, 5000] {
+            let ptr = ralloc::alloc(size, 4096);
+            assert_eq!(ptr as usize % 4096, 0);
+
+            unsafe {
+                util::acid(|| {
+                    *ptr.offset(size as isize - 1) = 1;
+                });
+
+                ralloc::free(ptr, size);
+            }
+        }
+    });
+}
@@ -0,0 +1,186 @@
+//! Tests meant to be run under Miri.
+//!
+//! Run with `cargo miri test --no-default-features --features "miri test_util" --test miri`. The
+//! allocator must not be the global allocator here, since its backing buffer comes from the host
+//! allocator.
+//!
+//! With the `strict_provenance` feature, these also pass under `-Zmiri-strict-provenance`. The
+//! tests from `aligned` on target the paths, which used to cast integers to pointers.
+
+extern crate ralloc;
+
+#[cfg(all(feature = "miri", feature = "test_util"))]
+mod miri {
+    use ralloc;
+    use ralloc::test_util::{self, Allocator, Align, Pointer, PoolOp};
+
+    #[test]
+    fn block_split() {
+        let mut buf = [0u8; 64];
+        let block = unsafe { test_util::buffer_block(&mut buf) };
+
+        let (mut left, right) = block.split(24);
+        let (mut middle, mut right) = right.split(8);
+        assert_eq!((left.size(), middle.size(), right.size()), (24, 8, 32));
+
+        assert!(middle.merge_right(&mut right).is_ok());
+        assert!(left.merge_right(&mut middle).is_ok());
+        assert_eq!(left.size(), 64);
+        assert!(middle.is_empty() && right.is_empty());
+    }
+
+    #[test]
+    fn pool_coalesce() {
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+
+        let mut pool = unsafe {
+            test_util::scripted_pool(&mut meta, &mut data, &[
+                PoolOp::Free { start: 0, size: 128 },
+                PoolOp::Free { start: 256, size: 128 },
+                // Merges with both neighbors.
+                PoolOp::Free { start: 128, size: 128 },
+            ])
+        };
+        assert_eq!(pool.total_bytes(), 384);
+
+        // Take blocks out, and put them back in reverse order.
+        let a = pool.alloc(100, Align::new(8).unwrap());
+        let b = pool.alloc(100, Align::new(8).unwrap());
+        pool.check();
+
+        pool.free(b);
+        pool.free(a);
+        pool.check();
+        assert_eq!(pool.total_bytes(), 384);
+    }
+
+    #[test]
+    fn pool_insert_remove() {
+        let mut meta = [0; 256];
+        let mut data = [0; 1024];
+
+        // Insert at the end, the front and the middle.
+        let mut pool = unsafe {
+            test_util::scripted_pool(&mut meta, &mut data, &[
+                PoolOp::Free { start: 512, size: 64 },
+                PoolOp::Free { start: 0, size: 64 },
+                PoolOp::Free { start: 256, size: 64 },
+            ])
+        };
+        assert_eq!(pool.total_bytes(), 192);
+
+        // Allocating whole blocks removes them.
+        let a = pool.alloc(64, Align::new(8).unwrap());
+        let b = pool.alloc(64, Align::new(8).unwrap());
+        pool.check();
+        assert_eq!(pool.total_bytes(), 64);
+
+        pool.free(a);
+        pool.free(b);
+        pool.check();
+        assert_eq!(pool.total_bytes(), 192);
+    }
+
+    #[test]
+    fn workload() {
+        let mut ptrs = Vec::new();
+        for i in 1..32 {
+            let ptr = ralloc::alloc(i * 8, 8);
+            unsafe {
+                for j in 0..i * 8 {
+                    *ptr.offset(j as isize) = i as u8;
+                }
+            }
+            ptrs.push((ptr, i * 8));
+        }
+
+        for (n, (ptr, size)) in ptrs.into_iter().enumerate() {
+            unsafe {
+                // Grow every other buffer, keeping its contents.
+                let (ptr, size) = if n % 2 == 0 {
+                    (ralloc::realloc(ptr, size, 2 * size, 8), 2 * size)
+                } else {
+                    (ptr, size)
+                };
+                assert_eq!(*ptr, (n + 1) as u8);
+
+                ralloc::free(ptr, size);
+            }
+        }
+    }
+
+    #[test]
+    fn with_addr() {
+        let mut buf = [0u8; 16];
+        let ptr = unsafe { Pointer::new(buf.as_mut_ptr()) };
+        let addr = ptr.addr();
+
+        // Derived pointers can be written through.
+        let last = ptr.clone().with_addr(addr + 15);
+        unsafe { **last = 7; }
+        let back = last.map_addr(|x| x - 15);
+        assert_eq!(back.addr(), addr);
+        unsafe { **back = 3; }
+
+        assert_eq!((buf[0], buf[15]), (3, 7));
+    }
+
+    #[test]
+    fn aligned() {
+        // Over-aligned buffers are padded, and the padding is stripped by address arithmetic.
+        for &align in &[32, 64, 256, 1024] {
+            let ptr = ralloc::alloc(40, align);
+            assert_eq!(ptr as usize % align, 0);
+            unsafe {
+                *ptr.offset(39) = 1;
+
+                let ptr = ralloc::realloc(ptr, 40, 400, align);
+                assert_eq!(ptr as usize % align, 0);
+                assert_eq!(*ptr.offset(39), 1);
+                *ptr.offset(399) = 2;
+
+                ralloc::free(ptr, 400);
+            }
+        }
+    }
+
+    #[test]
+    fn heap() {
+        let heap = ralloc::Heap::new();
+        let a = heap.alloc(64, 8);
+        let b = heap.alloc(4096, 8);
+        unsafe {
+            *a = 1;
+            *b.offset(4095) = 2;
+
+            // The heap is found through its registry.
+            ralloc::route_free(a, 64);
+            heap.free(b, 4096);
+        }
+        assert_eq!(heap.live_bytes(), 0);
+
+        // The regions are given back through the pointers kept for them.
+        drop(heap);
+    }
+
+    #[test]
+    fn signal_pool() {
+        let ptrs: Vec<_> = (0..4).map(|n| {
+            let ptr = ralloc::sig::alloc(24).expect("The emergency pool is exhausted.");
+            unsafe { *ptr.offset(23) = n; }
+            ptr
+        }).collect();
+
+        for (n, &ptr) in ptrs.iter().enumerate() {
+            unsafe {
+                assert_eq!(*ptr.offset(23), n as u8);
+                ralloc::sig::dealloc(ptr);
+            }
+        }
+
+        // Freed buffers come back from the freelist.
+        let ptr = ralloc::sig::alloc(24).unwrap();
+        unsafe { ralloc::sig::dealloc(ptr); }
+    }
+}
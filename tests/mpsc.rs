@@ -1,3 +1,6 @@
+// Every test of this file spawns threads.
+#![cfg(not(feature = "single_threaded"))]
+
 extern crate ralloc;
 
 mod util;
@@ -0,0 +1,117 @@
+extern crate ralloc;
+
+// The mock and the number of nodes are global, so this is the only test in its process.
+#[cfg(all(feature = "numa", feature = "test_util"))]
+mod numa {
+    use ralloc::{self, Heap};
+    use ralloc::test_util::{inject, set_numa_nodes};
+
+    /// `MPOL_BIND`.
+    const BIND: usize = 2;
+    /// `MPOL_INTERLEAVE`.
+    const INTERLEAVE: usize = 3;
+    /// The size of the interleaved buffers.
+    const LARGE: usize = 4 * 1024 * 1024;
+
+    /// Get the bytes placed on a node.
+    #[cfg(feature = "stats")]
+    fn placed(node: usize) -> usize {
+        ralloc::stats::by_node()[node]
+    }
+
+    /// Get the bytes placed on a node (unknown without the `stats` feature).
+    #[cfg(not(feature = "stats"))]
+    fn placed(_node: usize) -> usize {
+        0
+    }
+
+    #[test]
+    fn placement() {
+        // Whatever the machine, the heaps on the first node and large buffers work.
+        let heap = Heap::new_on_node(0);
+        let ptr = heap.alloc(100, 8);
+        unsafe {
+            *ptr = 1;
+            heap.free(ptr, 100);
+        }
+        drop(heap);
+
+        // Pretend to have two nodes.
+        inject::mock_mbind(true);
+        set_numa_nodes(2);
+
+        // The chunks of a heap on a node are bound to it.
+        let calls = inject::mbinds();
+        let placed_before = placed(1);
+        let heap = Heap::new_on_node(1);
+        assert_eq!(heap.node(), Some(1));
+        let ptr = heap.alloc(100, 8);
+        unsafe { *ptr = 2; }
+        assert!(heap.owns(ptr));
+
+        let call = inject::last_mbind().unwrap();
+        assert_eq!(inject::mbinds(), calls + 1);
+        assert_eq!((call.mode, call.mask), (BIND, 1 << 1));
+        assert!(call.addr <= ptr as usize && ptr as usize - call.addr < call.size);
+        if cfg!(feature = "stats") {
+            assert_eq!(placed(1), placed_before + call.size);
+        }
+
+        // Over-aligned buffers get a chunk of their own.
+        let aligned = heap.alloc(100, 1 << 20);
+        assert_eq!(aligned as usize % (1 << 20), 0);
+        assert_eq!(inject::mbinds(), calls + 2);
+
+        unsafe {
+            heap.free(ptr, 100);
+            heap.free(aligned, 100);
+        }
+        drop(heap);
+        assert_eq!(placed(1), placed_before);
+
+        // Missing nodes are ignored.
+        assert_eq!(Heap::new_on_node(2).node(), None);
+
+        // Large buffers are interleaved over both nodes, while it is on.
+        let calls = inject::mbinds();
+        let ptr = ralloc::alloc(LARGE, 8);
+        assert_eq!(inject::mbinds(), calls);
+
+        ralloc::set_numa_interleave(true);
+        let interleaved = ralloc::alloc(LARGE, 8);
+        let call = inject::last_mbind().unwrap();
+        assert_eq!(inject::mbinds(), calls + 1);
+        assert_eq!(call, inject::Mbind {
+            addr: interleaved as usize,
+            size: LARGE,
+            mode: INTERLEAVE,
+            mask: 0b11,
+        });
+        if cfg!(feature = "stats") {
+            assert_eq!(placed(0) + placed(1), LARGE);
+        }
+
+        // Small buffers stay in the pool.
+        let small = ralloc::alloc(1000, 8);
+        assert_eq!(inject::mbinds(), calls + 1);
+
+        unsafe {
+            *interleaved = 3;
+            ralloc::free(ptr, LARGE);
+            ralloc::free(interleaved, LARGE);
+            ralloc::free(small, 1000);
+        }
+        assert_eq!(placed(0) + placed(1), 0);
+
+        // With a single node, the policies are NOOPs.
+        set_numa_nodes(1);
+        let calls = inject::mbinds();
+        let ptr = ralloc::alloc(LARGE, 8);
+        assert_eq!(Heap::new_on_node(0).node(), None);
+        assert_eq!(inject::mbinds(), calls);
+
+        unsafe { ralloc::free(ptr, LARGE); }
+        ralloc::set_numa_interleave(false);
+        inject::mock_mbind(false);
+    }
+}
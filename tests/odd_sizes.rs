@@ -0,0 +1,32 @@
+extern crate ralloc;
+
+// The heap usage is global, so this is the only test in its process.
+
+/// Allocate and free a round of buffers of odd sizes.
+fn round() {
+    let mut ptrs = [(0 as *mut u8, 0); 64];
+
+    for (i, x) in ptrs.iter_mut().enumerate() {
+        // 1, 3, 5, ..., 127, none of which is a multiple of the minimal alignment.
+        let size = 2 * i + 1;
+        *x = (ralloc::alloc(size, 1), size);
+    }
+
+    for &(ptr, size) in ptrs.iter() {
+        unsafe {
+            ralloc::free(ptr, size);
+        }
+    }
+}
+
+#[test]
+fn odd_sizes() {
+    // The first round fills the pool.
+    round();
+    let usage = ralloc::heap_usage();
+
+    for _ in 0..1000 {
+        round();
+        assert_eq!(ralloc::heap_usage(), usage, "The heap grew on odd sizes.");
+    }
+}
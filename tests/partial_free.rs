@@ -5,9 +5,10 @@ mod util;
 use std::ptr;
 
 #[test]
+#[cfg(not(any(feature = "header", feature = "sidetable")))]
 fn partial_free() {
     util::multiply(|| {
-        let buf = ralloc::alloc(63, 3);
+        let buf = ralloc::alloc(63, 4);
 
         unsafe {
             util::acid(|| {
@@ -31,6 +32,7 @@ fn partial_free() {
 }
 
 #[test]
+#[cfg(not(any(feature = "header", feature = "sidetable")))]
 fn partial_free_double() {
     util::multiply(|| {
         let buf = ralloc::alloc(64, 4);
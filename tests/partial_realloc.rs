@@ -5,9 +5,10 @@ mod util;
 use std::ptr;
 
 #[test]
+#[cfg(not(any(feature = "header", feature = "sidetable")))]
 fn partial_realloc() {
     util::multiply(|| {
-        let buf = ralloc::alloc(63, 3);
+        let buf = ralloc::alloc(63, 4);
 
         unsafe {
             util::acid(|| {
@@ -15,7 +16,7 @@ fn partial_realloc() {
                 *buf = 4;
             });
 
-            ralloc::realloc(buf.offset(8), 75, 0, 23);
+            ralloc::realloc(buf.offset(8), 75, 0, 8);
             *buf = 5;
 
             *ralloc::realloc(buf, 4, 10, 2) = 10;
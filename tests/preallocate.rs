@@ -0,0 +1,104 @@
+extern crate ralloc;
+
+#[cfg(feature = "test_util")]
+mod preallocate {
+    use std::{env, process, thread};
+
+    use ralloc;
+    use ralloc::test_util::inject;
+
+    /// The environment variable marking the child process.
+    const CHILD_VAR: &'static str = "RALLOC_TEST_PREALLOCATE_CHILD";
+    /// The number of bytes preallocated.
+    const PREALLOCATED: usize = 16 * 1024 * 1024;
+
+    /// Get the size of the `n`th buffer of the workload.
+    fn size(n: usize) -> usize {
+        16 + n * 37 % 4096
+    }
+
+    /// Allocate and free well below the preallocated bytes.
+    fn workload() {
+        let mut ptrs = Vec::with_capacity(1024);
+        for round in 0..16 {
+            for n in 0..1024 {
+                ptrs.push(ralloc::alloc(size(n + round), 8));
+            }
+            for (n, ptr) in ptrs.drain(..).enumerate() {
+                unsafe { ralloc::free(ptr, size(n + round)); }
+            }
+        }
+
+        // A large buffer, grown in place or moved.
+        let mut ptr = ralloc::alloc(1024, 8);
+        for n in 1..12 {
+            ptr = unsafe { ralloc::realloc(ptr, 1024 << (n - 1), 1024 << n, 8) };
+        }
+        unsafe { ralloc::free(ptr, 1024 << 11); }
+    }
+
+    /// The body of the child process.
+    #[test]
+    fn no_grows_child() {
+        if env::var(CHILD_VAR).is_err() { return; }
+
+        ralloc::preallocate(PREALLOCATED).unwrap();
+        let grows = inject::grows();
+
+        workload();
+        // Another thread fills its local allocator from the preallocated memory.
+        thread::spawn(workload).join().unwrap();
+
+        assert_eq!(inject::grows(), grows);
+    }
+
+    /// Run a test in a child process.
+    fn run_child(test: &str) {
+        let status = process::Command::new(env::current_exe().unwrap())
+            .arg(test)
+            .arg("--exact")
+            .env(CHILD_VAR, "1")
+            .status()
+            .unwrap();
+
+        assert!(status.success());
+    }
+
+    #[test]
+    fn no_grows() {
+        // Nothing else may grow the heap meanwhile, so the workload runs in a child process.
+        run_child("preallocate::no_grows_child");
+    }
+
+    /// The body of the child process.
+    #[test]
+    #[cfg(feature = "stats")]
+    fn preallocated_stats_child() {
+        use ralloc::stats;
+
+        if env::var(CHILD_VAR).is_err() { return; }
+
+        assert_eq!(stats::snapshot().preallocated, 0);
+        ralloc::preallocate(1024 * 1024).unwrap();
+
+        // Nothing was allocated since, so all of it is free.
+        let stats = stats::snapshot();
+        assert_eq!(stats.preallocated, 1024 * 1024);
+        assert_eq!(stats.preallocated_free, 1024 * 1024);
+        assert_eq!(stats.demand_grown, ralloc::heap_usage() - 1024 * 1024);
+
+        // Preallocated memory is never trimmed, even when merged with the memory below it.
+        ralloc::purge();
+        let stats = stats::snapshot();
+        assert_eq!(stats.preallocated, 1024 * 1024);
+        assert_eq!(stats.preallocated_free, 1024 * 1024);
+        assert_eq!(stats.demand_grown, ralloc::heap_usage() - 1024 * 1024);
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn preallocated_stats() {
+        // Nothing else may allocate the preallocated memory, so the checks run in a child process.
+        run_child("preallocate::preallocated_stats_child");
+    }
+}
@@ -0,0 +1,60 @@
+// Every test of this file spawns threads.
+#![cfg(not(feature = "single_threaded"))]
+
+extern crate ralloc;
+
+use std::thread;
+
+/// The size of the chunks allocated.
+const CHUNK: usize = 1024 * 1024;
+/// The number of frees aging the chunks.
+///
+/// The generation clock advances every `config::GENERATION_FREES` (1024) frees, and free blocks
+/// turn cold after `config::COLD_GENERATIONS` (8) generations.
+const AGING: usize = 16 * 1024;
+
+#[test]
+fn purge() {
+    let threads: Vec<_> = (0..8).map(|_| thread::spawn(|| {
+        let mut chunks = [0 as *mut u8; 8];
+
+        unsafe {
+            for ptr in chunks.iter_mut() {
+                *ptr = ralloc::alloc(CHUNK, 8);
+                // Touch every page.
+                for i in 0..CHUNK / 4096 {
+                    *ptr.offset(i as isize * 4096) = 0xFF;
+                }
+            }
+
+            for &ptr in chunks.iter() {
+                ralloc::free(ptr, CHUNK);
+            }
+        }
+    })).collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // Only the cold blocks are advised, so let the chunks age.
+    for _ in 0..AGING {
+        let ptr = ralloc::alloc(4096, 8);
+        unsafe { ralloc::free(ptr, 4096); }
+    }
+
+    let report = ralloc::purge();
+
+    // 64 MiB were freed, and most of it should be given back (the rest is rounded off to pages or
+    // was in use before the test).
+    assert!(report.total() >= 48 * CHUNK, "Only {:?} was purged.", report);
+    assert!(report.total() <= 96 * CHUNK, "{:?} is more than was ever allocated.", report);
+
+    // The heap is still usable.
+    let mut v = vec![0u8; 3 * CHUNK];
+    v[3 * CHUNK - 1] = 1;
+    assert_eq!(v.iter().map(|&x| x as usize).sum::<usize>(), 1);
+
+    // Purging again is harmless.
+    ralloc::purge();
+}
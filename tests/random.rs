@@ -0,0 +1,65 @@
+extern crate ralloc;
+
+#[cfg(all(feature = "aslr", feature = "test_util"))]
+mod random {
+    use std::{env, process};
+
+    use ralloc::test_util::{self, Allocator, Align, PoolOp, Pointer};
+
+    /// The environment variable marking the child process.
+    const CHILD_VAR: &'static str = "RALLOC_TEST_RANDOM_CHILD";
+
+    /// The body of the child process.
+    ///
+    /// This prints the placement of eight allocations among 16 equally fitting blocks, which the
+    /// unseeded generator picks.
+    #[test]
+    fn random_child() {
+        if env::var(CHILD_VAR).is_err() { return; }
+
+        let mut meta = [0; 256];
+        let mut data = [0u8; 1024];
+        let base = data.as_ptr() as usize;
+
+        let mut ops = [PoolOp::Free { start: 0, size: 0 }; 16];
+        for (i, op) in ops.iter_mut().enumerate() {
+            *op = PoolOp::Free { start: i * 64, size: 32 };
+        }
+        let mut pool = unsafe { test_util::scripted_pool(&mut meta, &mut data, &ops) };
+
+        let placement: Vec<_> = (0..8).map(|_| {
+            (*Pointer::from(pool.alloc(8, Align::new(1).unwrap())) as usize - base).to_string()
+        }).collect();
+        println!("placement: {}", placement.join(" "));
+    }
+
+    /// Run the child process, returning the placement it printed.
+    fn placement(conf: &str) -> String {
+        let output = process::Command::new(env::current_exe().unwrap())
+            .arg("random_child")
+            .arg("--exact")
+            .arg("--nocapture")
+            .env(CHILD_VAR, "1")
+            .env("RALLOC_CONF", conf)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        String::from_utf8(output.stdout).unwrap().lines()
+            .find(|x| x.starts_with("placement: "))
+            .expect("The child printed no placement.")
+            .to_owned()
+    }
+
+    #[test]
+    fn seeds_differ_between_processes() {
+        // Two processes seed their generators independently. The chance that 8 picks among 8
+        // candidates coincide is negligible.
+        assert!(placement("") != placement(""));
+    }
+
+    #[test]
+    fn deterministic_mode_overrides_seed() {
+        assert_eq!(placement("deterministic:1"), placement("deterministic:1"));
+    }
+}
@@ -0,0 +1,91 @@
+extern crate ralloc;
+
+// The slack is kept globally, so this is the only test in its process.
+#[cfg(all(feature = "realloc_slack", feature = "stats", feature = "test_util"))]
+mod realloc_slack {
+    use ralloc;
+    use ralloc::stats;
+    use ralloc::test_util::inject;
+
+    /// The size of the buffer reallocated up and down.
+    const SIZE: usize = 64 * 1024;
+    /// The bytes the buffer shrinks by.
+    const SHRINK: usize = 4 * 1024;
+    /// The number of rounds of the ping-pong.
+    const ROUNDS: usize = 256;
+    /// The size of the buffer, whose slack is given back under pressure.
+    const LARGE: usize = 4 * 1024 * 1024;
+    /// The bytes the large buffer shrinks by.
+    const LARGE_SHRINK: usize = 768 * 1024;
+    /// The size of the allocation failing to grow the heap.
+    const PRESSURE: usize = 700 * 1024;
+
+    /// Get the number of reallocations done in the pool.
+    fn pool_reallocs() -> usize {
+        let stats = stats::snapshot();
+
+        stats.realloc_inplace + stats.realloc_left + stats.realloc_copy
+    }
+
+    #[test]
+    fn ping_pong() {
+        assert_eq!(ralloc::realloc_slack(), 25);
+
+        // Growing and shrinking never touches the pool.
+        let ptr = ralloc::alloc(SIZE, 8);
+        let reallocs = pool_reallocs();
+        for _ in 0..ROUNDS {
+            unsafe {
+                assert_eq!(ralloc::realloc(ptr, SIZE, SIZE - SHRINK, 8), ptr);
+                assert_eq!(stats::snapshot().realloc_slack, SHRINK);
+                assert_eq!(ralloc::realloc(ptr, SIZE - SHRINK, SIZE, 8), ptr);
+                assert_eq!(stats::snapshot().realloc_slack, 0);
+            }
+        }
+        assert_eq!(pool_reallocs(), reallocs);
+
+        // Purging gives the tail back.
+        unsafe {
+            assert_eq!(ralloc::realloc(ptr, SIZE, SIZE - SHRINK, 8), ptr);
+        }
+        assert_eq!(ralloc::purge().slack, SHRINK);
+        assert_eq!(stats::snapshot().realloc_slack, 0);
+
+        // Shrinking beyond the share of the block frees the tail.
+        unsafe {
+            assert_eq!(ralloc::realloc(ptr, SIZE - SHRINK, SIZE / 4, 8), ptr);
+            assert_eq!(stats::snapshot().realloc_slack, 0);
+            ralloc::free(ptr, SIZE / 4);
+        }
+
+        // Freeing a batch gives the tails back along with the buffers.
+        let ptr = ralloc::alloc(SIZE, 8);
+        unsafe {
+            assert_eq!(ralloc::realloc(ptr, SIZE, SIZE - SHRINK, 8), ptr);
+            assert_eq!(stats::snapshot().realloc_slack, SHRINK);
+            ralloc::dealloc_many(&mut [ptr], SIZE - SHRINK);
+        }
+        assert_eq!(stats::snapshot().realloc_slack, 0);
+
+        // Keep a large tail at the top of the heap, with the excess of the growth trimmed.
+        let large = ralloc::alloc(LARGE, 8);
+        ralloc::purge();
+        unsafe {
+            assert_eq!(ralloc::realloc(large, LARGE, LARGE - LARGE_SHRINK, 8), large);
+        }
+        assert_eq!(stats::snapshot().realloc_slack, LARGE_SHRINK);
+
+        // The heap cannot grow, so the allocation is served by the tail.
+        inject::fail_brk(12);
+        let ptr = ralloc::alloc(PRESSURE, 8);
+        assert_eq!(stats::snapshot().realloc_slack, 0);
+        assert!(ptr as usize >= large as usize + LARGE - LARGE_SHRINK &&
+                ptr as usize + PRESSURE <= large as usize + LARGE,
+                "{:?} is not in the tail of {:?}.", ptr, large);
+
+        unsafe {
+            ralloc::free(ptr, PRESSURE);
+            ralloc::free(large, LARGE - LARGE_SHRINK);
+        }
+    }
+}
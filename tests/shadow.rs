@@ -0,0 +1,88 @@
+extern crate ralloc;
+
+#[cfg(feature = "shadow_accounting")]
+mod shadow {
+    use std::thread;
+
+    use ralloc;
+
+    /// The number of threads.
+    const THREADS: usize = 8;
+    /// The number of operations per thread.
+    const OPS: usize = 20000;
+    /// The number of buffers a thread keeps.
+    const SLOTS: usize = 64;
+
+    /// A xorshift generator.
+    struct Rng(u64);
+
+    impl Rng {
+        /// Get the next number.
+        fn next(&mut self) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+
+            self.0 as usize
+        }
+
+        /// Get a random size, mostly small, sometimes large.
+        fn size(&mut self) -> usize {
+            match self.next() % 16 {
+                0 => self.next() % (256 * 1024) + 1,
+                1...3 => self.next() % 4096 + 1,
+                _ => self.next() % 512 + 1,
+            }
+        }
+    }
+
+    /// Allocate, reallocate and free randomly.
+    fn churn(seed: u64) {
+        let mut rng = Rng(seed);
+        let mut slots = [(0 as *mut u8, 0); SLOTS];
+
+        for _ in 0..OPS {
+            let slot = &mut slots[rng.next() % SLOTS];
+
+            unsafe {
+                if slot.0.is_null() {
+                    let size = rng.size();
+                    *slot = (ralloc::alloc(size, 1 << (rng.next() % 5)), size);
+                    *slot.0 = 0xAA;
+                } else if rng.next() % 3 == 0 {
+                    let size = rng.size();
+                    slot.0 = ralloc::realloc(slot.0, slot.1, size, 16);
+                    slot.1 = size;
+                } else {
+                    ralloc::free(slot.0, slot.1);
+                    *slot = (0 as *mut u8, 0);
+                }
+            }
+        }
+
+        for &(ptr, size) in slots.iter().filter(|x| !x.0.is_null()) {
+            unsafe { ralloc::free(ptr, size); }
+        }
+    }
+
+    #[test]
+    fn stress() {
+        for round in 0..4 {
+            let handles: Vec<_> = (0..THREADS).map(|n| {
+                let seed = 0x9E37_79B9_7F4A_7C15 ^ (round * THREADS + n + 1) as u64;
+                thread::spawn(move || churn(seed))
+            }).collect();
+
+            // Reconcile while the threads are running.
+            let seq = ralloc::debug::reconcile().unwrap();
+
+            for handle in handles {
+                handle.join().unwrap();
+            }
+
+            // And after they finished, trimming too.
+            ralloc::purge();
+            assert!(ralloc::debug::reconcile().unwrap() > seq);
+        }
+    }
+}
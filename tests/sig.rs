@@ -0,0 +1,45 @@
+extern crate ralloc;
+
+mod util;
+
+use ralloc::sig;
+
+#[test]
+fn sig_alloc() {
+    util::multiply(|| {
+        let mut ptrs = Vec::new();
+
+        for i in 0..16 {
+            let ptr = sig::alloc(i * 4).unwrap();
+            assert!(sig::contains(ptr));
+
+            unsafe {
+                for j in 0..i * 4 {
+                    *ptr.offset(j as isize) = i as u8;
+                }
+            }
+            ptrs.push((i, ptr));
+        }
+
+        for (i, ptr) in ptrs {
+            unsafe {
+                for j in 0..i * 4 {
+                    assert_eq!(*ptr.offset(j as isize), i as u8);
+                }
+
+                sig::dealloc(ptr);
+            }
+        }
+    });
+}
+
+#[test]
+fn sig_exhaustion() {
+    // The pool is never larger than the largest class, so this cannot fit.
+    assert_eq!(sig::alloc(1 << 20), None);
+
+    // The heap is disjoint from the pool.
+    let ptr = ralloc::alloc(100, 1);
+    assert!(!sig::contains(ptr));
+    unsafe { ralloc::free(ptr, 100); }
+}
@@ -0,0 +1,51 @@
+#[macro_use]
+extern crate ralloc;
+
+#[cfg(feature = "sites")]
+mod sites {
+    use ralloc;
+
+    /// Allocate three buffers of 1000 bytes.
+    fn small() -> Vec<(*mut u8, usize)> {
+        (0..3).map(|_| (alloc_here!(1000, 8), 1000)).collect()
+    }
+
+    /// Allocate two buffers of 7777 bytes, and grow one of them.
+    fn large() -> Vec<(*mut u8, usize)> {
+        let a = alloc_here!(7777, 8);
+        let b = alloc_here!(7777, 8);
+
+        vec![(a, 7777), (unsafe { ralloc::realloc(b, 7777, 8000, 8) }, 8000)]
+    }
+
+    /// Get the statistics (live bytes, peak and count) of the sites of this file.
+    fn sites() -> Vec<(usize, usize, usize)> {
+        let mut report = String::new();
+        ralloc::stats::top_sites(1000, &mut report).unwrap();
+
+        let mut res: Vec<_> = report.lines()
+            .filter(|x| x.contains("tests/sites.rs"))
+            .map(|x| {
+                let mut fields = x.split_whitespace().map(|x| x.parse().unwrap_or(0));
+                (fields.next().unwrap(), fields.next().unwrap(), fields.next().unwrap())
+            }).collect();
+        res.sort();
+
+        res
+    }
+
+    #[test]
+    fn attribution() {
+        let mut bufs = small();
+        bufs.extend(large());
+
+        // The sites of `large` are told apart, and the reallocated buffer stays with its site.
+        assert_eq!(sites(), vec![(3000, 3000, 3), (7777, 7777, 1), (8000, 8000, 1)]);
+
+        for (ptr, size) in bufs {
+            unsafe { ralloc::free(ptr, size); }
+        }
+
+        assert_eq!(sites(), vec![(0, 3000, 3), (0, 7777, 1), (0, 8000, 1)]);
+    }
+}
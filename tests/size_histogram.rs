@@ -0,0 +1,29 @@
+extern crate ralloc;
+
+#[cfg(feature = "stats")]
+#[test]
+fn size_histogram() {
+    use ralloc::stats::{self, bucket};
+
+    let before = stats::size_histogram();
+
+    let ptrs: Vec<_> = (0..10).map(|_| ralloc::alloc(100, 8)).collect();
+    let big = ralloc::alloc(5000, 8);
+
+    let after = stats::size_histogram();
+    assert!(after.requested.count(bucket(100)) >= before.requested.count(bucket(100)) + 10);
+    assert!(after.requested.count(bucket(5000)) >= before.requested.count(bucket(5000)) + 1);
+    // Every allocation is counted in the waste histogram as well.
+    assert!(after.waste.total() >= before.waste.total() + 11);
+
+    unsafe {
+        for ptr in ptrs {
+            ralloc::free(ptr, 100);
+        }
+        ralloc::free(big, 5000);
+    }
+
+    let mut report = String::new();
+    stats::write_report(&mut report).unwrap();
+    assert!(report.contains("requests"));
+}
@@ -0,0 +1,41 @@
+extern crate ralloc;
+
+mod sized_free {
+    use ralloc;
+
+    #[test]
+    fn sized_free() {
+        // Every power of two up to 64.
+        for align in (0..7).map(|s| 1 << s) {
+            let ptr = ralloc::alloc(100, align);
+
+            unsafe {
+                let ptr = ralloc::realloc(ptr, 100, 1000, align);
+                ralloc::dealloc_sized(ptr, 1000, align);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(all(any(feature = "header", feature = "sidetable"), debug_assertions,
+              not(feature = "security")))]
+    fn size_mismatch() {
+        let ptr = ralloc::alloc(1000, 8);
+
+        unsafe {
+            ralloc::dealloc_sized(ptr, 2000, 8);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(all(feature = "header", debug_assertions, not(feature = "security")))]
+    fn align_mismatch() {
+        let ptr = ralloc::alloc(1000, 4096);
+
+        unsafe {
+            ralloc::dealloc_sized(ptr, 1000, 8);
+        }
+    }
+}
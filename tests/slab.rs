@@ -0,0 +1,75 @@
+extern crate ralloc;
+
+mod util;
+
+#[test]
+fn small_objects() {
+    util::multiply(|| {
+        let mut ptrs = [(0 as *mut u8, 0); 256];
+
+        unsafe {
+            for (i, x) in ptrs.iter_mut().enumerate() {
+                // Cover every size class, and a few sizes served by the pool.
+                let size = i * 3 % 600 + 1;
+                let ptr = ralloc::alloc(size, 8);
+                assert_eq!(ptr as usize % 8, 0);
+
+                util::acid(|| {
+                    *ptr = i as u8;
+                    *ptr.offset(size as isize - 1) = i as u8;
+                });
+
+                *x = (ptr, size);
+            }
+
+            for (i, &(ptr, size)) in ptrs.iter().enumerate() {
+                assert_eq!(*ptr, i as u8);
+                assert_eq!(*ptr.offset(size as isize - 1), i as u8);
+
+                util::acid(|| {
+                    ralloc::free(ptr, size);
+                });
+            }
+        }
+    });
+}
+
+#[test]
+fn small_realloc() {
+    util::multiply(|| {
+        unsafe {
+            let mut ptr = ralloc::alloc(10, 1);
+            for i in 0..10 {
+                *ptr.offset(i) = i as u8;
+            }
+
+            // Grow within the cell, then out of the slabs entirely.
+            let mut size = 10;
+            for &new in &[16, 100, 512, 1000, 20] {
+                ptr = ralloc::realloc(ptr, size, new, 1);
+                size = new;
+
+                for i in 0..10 {
+                    assert_eq!(*ptr.offset(i), i as u8);
+                }
+            }
+
+            assert!(ralloc::realloc_inplace(ptr, size, 10).is_ok());
+            ralloc::free(ptr, 10);
+        }
+    });
+}
+
+#[test]
+#[cfg(not(any(feature = "header", feature = "sidetable")))]
+fn partial_free_of_pool_buffer() {
+    util::multiply(|| {
+        unsafe {
+            // Small frees into a large buffer go to the pool, not the slabs.
+            let ptr = ralloc::alloc(4096, 8);
+            ralloc::free(ptr.offset(64), 64);
+            ralloc::free(ptr, 64);
+            ralloc::free(ptr.offset(128), 4096 - 128);
+        }
+    });
+}
@@ -0,0 +1,80 @@
+extern crate ralloc;
+
+// The byte counts are global, so this is the only test in its process.
+#[cfg(feature = "stats")]
+mod staged_growth {
+    use std::thread;
+
+    use ralloc;
+    use ralloc::stats;
+
+    /// The number of threads.
+    const THREADS: usize = 8;
+    /// The number of buffers allocated by every thread.
+    const ROUNDS: usize = 64;
+    /// The number of bytes, which the metadata of the pools can take meanwhile.
+    const SLACK: isize = 64 * 1024;
+    /// The number of times the threads are run, until a growth raced with a free.
+    const ATTEMPTS: usize = 16;
+
+    /// Get the heap usage and the free bytes of the global pool.
+    fn usage() -> (isize, isize) {
+        (ralloc::heap_usage() as isize, stats::snapshot().global_free as isize)
+    }
+
+    /// Allocate large buffers, and free them in batches.
+    ///
+    /// Every buffer is beyond what the local allocators hold on to, so the threads keep growing
+    /// the heap, while the others free enough to make the recheck after the growth succeed. Every
+    /// other buffer is grown by a reallocation, which takes the same path without a local
+    /// allocator.
+    fn churn(n: usize) {
+        let mut bufs = Vec::with_capacity(4);
+        for round in 0..ROUNDS {
+            let mut size = (256 + (n * 7 + round * 13) % 256) * 1024;
+            let mut ptr = ralloc::alloc(size, 8);
+            unsafe {
+                *ptr = n as u8;
+                if round % 2 == 1 {
+                    ptr = ralloc::realloc(ptr, size, size + 256 * 1024, 8);
+                    size += 256 * 1024;
+                }
+                *ptr.offset(size as isize - 1) = n as u8;
+            }
+            bufs.push((ptr, size));
+
+            if round % 4 == 3 {
+                for (ptr, size) in bufs.drain(..) {
+                    unsafe {
+                        assert_eq!(*ptr, n as u8);
+                        assert_eq!(*ptr.offset(size as isize - 1), n as u8);
+                        ralloc::free(ptr, size);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn no_memory_lost() {
+        let (usage, free) = usage();
+        let raced = stats::snapshot().raced_growths;
+
+        // The race is timing dependent, so the threads are run anew, until it was hit.
+        for _ in 0..ATTEMPTS {
+            let threads: Vec<_> = (0..THREADS).map(|n| thread::spawn(move || churn(n))).collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+
+            if stats::snapshot().raced_growths > raced { break; }
+        }
+        assert!(stats::snapshot().raced_growths > raced, "No growth raced with a free.");
+
+        // The exited threads gave their pools back, so everything grown meanwhile is free in the
+        // global pool.
+        let (new_usage, new_free) = usage();
+        let lost = (new_usage - usage) - (new_free - free);
+        assert!(lost <= SLACK, "{} bytes went missing.", lost);
+    }
+}
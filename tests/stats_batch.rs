@@ -0,0 +1,38 @@
+extern crate ralloc;
+
+#[cfg(feature = "stats")]
+mod stats_batch {
+    use std::thread;
+
+    use ralloc;
+    use ralloc::stats::{self, SizeClass};
+
+    /// The size of the buffers.
+    ///
+    /// This is in the 448 class, which nothing else in this test allocates.
+    const SIZE: usize = 392;
+
+    #[test]
+    fn exact_after_read() {
+        let class = SizeClass::of(SIZE);
+        let before = stats::class(class);
+
+        // Far fewer allocations than a batch, which stay buffered until the read.
+        let ptrs: Vec<usize> = (0..10).map(|_| ralloc::alloc(SIZE, 8) as usize).collect();
+
+        let after = stats::class(class);
+        assert_eq!(after.allocs, before.allocs + 10);
+        assert_eq!(after.frees, before.frees);
+        assert_eq!(after.bytes, before.bytes + 10 * SIZE);
+
+        // The counts of an exited thread were flushed on the way out.
+        thread::spawn(move || for ptr in ptrs {
+            unsafe { ralloc::free(ptr as *mut u8, SIZE); }
+        }).join().unwrap();
+
+        let after = stats::class(class);
+        assert_eq!(after.allocs, before.allocs + 10);
+        assert_eq!(after.frees, before.frees + 10);
+        assert_eq!(after.bytes, before.bytes);
+    }
+}
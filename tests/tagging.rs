@@ -0,0 +1,44 @@
+extern crate ralloc;
+
+#[cfg(feature = "tagging")]
+#[test]
+fn tag_balances() {
+    use ralloc::stats::{self, TagStats};
+
+    // Nothing else in the test suite uses these tags.
+    const NET: u8 = 17;
+    const GFX: u8 = 18;
+    const IO: u8 = 19;
+
+    unsafe {
+        let net = [ralloc::alloc_tagged(100, 8, NET), ralloc::alloc_tagged(200, 8, NET)];
+        let gfx = [ralloc::alloc_tagged(4000, 16, GFX), ralloc::alloc_tagged(30, 1, GFX),
+                   ralloc::alloc_tagged(70, 1, GFX)];
+        let io = ralloc::alloc_tagged(1000, 8, IO);
+
+        ralloc::free(net[0], 100);
+        ralloc::free(gfx[1], 30);
+        ralloc::free(io, 1000);
+
+        // The tag follows the buffer through reallocation.
+        let gfx2 = ralloc::realloc(gfx[2], 70, 7000, 1);
+
+        let tags = stats::by_tag();
+        assert_eq!(tags.get(NET), TagStats { count: 1, bytes: 200 });
+        assert_eq!(tags.get(GFX), TagStats { count: 2, bytes: 11000 });
+        assert_eq!(tags.get(IO), TagStats { count: 0, bytes: 0 });
+
+        let mut report = String::new();
+        stats::write_report(&mut report).unwrap();
+        assert!(report.contains("11000"));
+
+        ralloc::free(net[1], 200);
+        ralloc::free(gfx[0], 4000);
+        ralloc::free(gfx2, 7000);
+
+        let tags = stats::by_tag();
+        for &tag in &[NET, GFX, IO] {
+            assert_eq!(tags.get(tag), TagStats::default());
+        }
+    }
+}
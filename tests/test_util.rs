@@ -0,0 +1,57 @@
+extern crate ralloc;
+
+#[cfg(feature = "test_util")]
+mod test_util {
+    use ralloc::test_util::{self, Allocator, Align, PoolOp, Rng};
+
+    #[test]
+    fn scripted_pool() {
+        let mut meta = [0; 256];
+        let mut data = [0; 4096];
+
+        let mut pool = unsafe {
+            test_util::scripted_pool(&mut meta, &mut data, &[
+                PoolOp::Free { start: 0, size: 1024 },
+                PoolOp::Free { start: 2048, size: 1024 },
+                PoolOp::Free { start: 1024, size: 512 },
+                PoolOp::Alloc { size: 64, align: 64 },
+            ])
+        };
+        assert_eq!(pool.total_bytes(), 2560 - 64);
+
+        // Churn random buffers.
+        let mut rng = Rng::new(42);
+        let mut blocks = Vec::new();
+        for _ in 0..200 {
+            if blocks.is_empty() || rng.below(3) != 0 && blocks.len() < 20 {
+                let size = rng.below(64) + 1;
+                blocks.push(pool.alloc(size, Align::new(8).unwrap()));
+            } else {
+                let n = rng.below(blocks.len());
+                pool.free(blocks.swap_remove(n));
+            }
+
+            pool.check();
+        }
+
+        for block in blocks {
+            pool.free(block);
+        }
+        pool.check();
+        assert_eq!(pool.total_bytes(), 2560 - 64);
+    }
+
+    #[test]
+    fn buffer_block() {
+        let mut meta = [0; 256];
+        let mut buf = [0; 256];
+        let mut pool = unsafe { test_util::TestPool::new(&mut meta) };
+
+        let block = unsafe { test_util::buffer_block(&mut buf) };
+        assert_eq!(block.size(), 256);
+
+        pool.free(block);
+        pool.check();
+        assert_eq!(pool.total_bytes(), 256);
+    }
+}
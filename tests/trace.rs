@@ -0,0 +1,92 @@
+extern crate ralloc;
+
+// The trace is global, so this is the only test in its process.
+#[cfg(all(feature = "test_util", feature = "trace"))]
+mod trace {
+    use ralloc;
+    use ralloc::test_util::{self, Block, PoolOp, Rng};
+    use ralloc::trace::{self, ReplayStats};
+
+    /// Replay a trace against a fresh pool of almost 8 MiB.
+    ///
+    /// The statistics and the number of bytes left in the pool are returned.
+    fn replay(bytes: &[u8]) -> (ReplayStats, usize) {
+        let mut meta = vec![0; 16384];
+        let mut data = vec![0; 8 << 20];
+        let mut slots: Vec<Option<(u32, Block)>> = (0..16384).map(|_| None).collect();
+
+        // Page align the pool, such that the alignments play out the same in every replay.
+        let start = (4096 - data.as_ptr() as usize % 4096) % 4096;
+        let mut pool = unsafe {
+            test_util::scripted_pool(&mut meta, &mut data, &[
+                PoolOp::Free { start: start, size: (8 << 20) - 4096 },
+            ])
+        };
+        let stats = trace::replay(trace::records(bytes), &mut pool, &mut slots);
+        pool.check();
+
+        (stats, pool.total_bytes())
+    }
+
+    #[test]
+    fn round_trip() {
+        // The buffers are allocated before the trace starts, such that it holds exactly the
+        // calls of the workload.
+        let mut bytes = vec![0; trace::encoded_len(16384)];
+        let mut ptrs = Vec::with_capacity(64);
+        trace::clear();
+
+        // Record a randomized workload, keeping the statistics, which the replay must reproduce.
+        let mut rng = Rng::new(11);
+        let mut run = ReplayStats::default();
+        for _ in 0..2000 {
+            match rng.below(4) {
+                0 | 1 if ptrs.len() < 64 => {
+                    let size = rng.below(2000) + 1;
+                    ptrs.push((ralloc::alloc(size, 1 << rng.below(5)), size));
+                    run.allocs += 1;
+                    run.live += 1;
+                    run.live_bytes += size;
+                },
+                2 if !ptrs.is_empty() => {
+                    let n = rng.below(ptrs.len());
+                    let (ptr, size) = ptrs[n];
+                    let new_size = rng.below(4000) + 1;
+                    ptrs[n] = (unsafe { ralloc::realloc(ptr, size, new_size, 8) }, new_size);
+                    run.reallocs += 1;
+                    run.live_bytes = run.live_bytes - size + new_size;
+                },
+                _ if !ptrs.is_empty() => {
+                    let n = rng.below(ptrs.len());
+                    let (ptr, size) = ptrs.swap_remove(n);
+                    unsafe { ralloc::free(ptr, size); }
+                    run.frees += 1;
+                    run.live -= 1;
+                    run.live_bytes -= size;
+                },
+                _ => {},
+            }
+
+            if run.live_bytes > run.peak_bytes {
+                run.peak_bytes = run.live_bytes;
+            }
+        }
+        // The vector itself is freed only after the trace is read.
+        while let Some((ptr, size)) = ptrs.pop() {
+            unsafe { ralloc::free(ptr, size); }
+            run.frees += 1;
+            run.live -= 1;
+            run.live_bytes -= size;
+        }
+
+        let len = trace::read_bytes(&mut bytes);
+        let bytes = &bytes[..len];
+        assert_eq!(trace::encoded_len(trace::records(bytes).count()), len);
+        assert_eq!(trace::dropped(), 0);
+
+        // The replay does what the workload did, and gives every block back.
+        let (stats, total) = replay(bytes);
+        assert_eq!(stats, run);
+        assert_eq!(total, (8 << 20) - 4096);
+    }
+}
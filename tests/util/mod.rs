@@ -1,20 +1,24 @@
 //! Test automation.
 
+#[cfg(not(feature = "single_threaded"))]
 use std::{thread, mem};
 
 /// Magic trait for boxed `FnOnce`s.
 ///
 /// This is a temporary replacement as the trait from libstd is stabilized.
+#[cfg(not(feature = "single_threaded"))]
 trait FnBox {
     /// Call the closure.
     fn call_box(self: Box<Self>);
 }
 
+#[cfg(not(feature = "single_threaded"))]
 impl<F: FnOnce()> FnBox for F {
     fn call_box(self: Box<Self>) { (*self)() }
 }
 
 /// Like `std::thread::spawn`, but without the closure bounds.
+#[cfg(not(feature = "single_threaded"))]
 unsafe fn spawn_unsafe<'a, F: FnOnce() + Send + 'a>(func: F) -> thread::JoinHandle<()> {
     let closure: Box<FnBox + 'a> = Box::new(func);
     let closure: Box<FnBox + Send> = mem::transmute(closure);
@@ -22,6 +26,7 @@ unsafe fn spawn_unsafe<'a, F: FnOnce() + Send + 'a>(func: F) -> thread::JoinHand
 }
 
 /// Spawn three threads and `join` them.
+#[cfg(not(feature = "single_threaded"))]
 fn spawn_double<F: Fn() + Sync + Send>(func: F) {
     let handle;
 
@@ -38,12 +43,24 @@ fn spawn_double<F: Fn() + Sync + Send>(func: F) {
 ///
 /// This will test for memory leaks, as well as acid wrapping.
 #[allow(dead_code)]
+#[cfg(not(feature = "single_threaded"))]
 pub fn multiply<F: Fn() + Sync + Send + 'static>(func: F) {
     spawn_double(|| spawn_double(|| acid(|| func())));
 
     // TODO assert no leaks.
 }
 
+/// "Multiply" a closure, by running it four times in a row.
+///
+/// The allocator must not be used by multiple threads with the `single_threaded` feature.
+#[allow(dead_code)]
+#[cfg(feature = "single_threaded")]
+pub fn multiply<F: Fn() + Sync + Send + 'static>(func: F) {
+    for _ in 0..4 {
+        acid(|| func());
+    }
+}
+
 /// Wrap a block in acid tests.
 ///
 /// This performs a number of temporary allocations to try to detect inconsistency.
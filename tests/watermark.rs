@@ -0,0 +1,82 @@
+extern crate ralloc;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ralloc::Direction;
+
+/// A mebibyte.
+const MB: usize = 1 << 20;
+/// An alignment beyond the pool's limit, such that every buffer is a mapping of its own.
+const ALIGN: usize = 4 * MB;
+
+/// The watermarks, relative to the usage at the start of the test.
+static mut LEVELS: [usize; 2] = [0; 2];
+/// The callback invocations.
+static mut CALLS: [(usize, Direction); 8] = [(0, Direction::Up); 8];
+/// The number of callback invocations.
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn record(level: usize, direction: Direction) {
+    let n = COUNT.fetch_add(1, Ordering::SeqCst);
+
+    unsafe {
+        CALLS[n] = (level, direction);
+    }
+}
+
+#[test]
+fn watermarks() {
+    let base = ralloc::heap_usage();
+    let (low, high) = unsafe {
+        LEVELS = [base + 256 * MB, base + 512 * MB];
+        ralloc::set_watermark_callback(&LEVELS, record);
+
+        (LEVELS[0], LEVELS[1])
+    };
+
+    let alloc = |size: usize| {
+        let ptr = ralloc::alloc(size * MB, ALIGN);
+        assert!(!ptr.is_null());
+        ptr
+    };
+
+    unsafe {
+        // Rise across both levels.
+        let a = alloc(160);
+        assert_eq!(COUNT.load(Ordering::SeqCst), 0);
+        let b = alloc(120);
+        assert_eq!(COUNT.load(Ordering::SeqCst), 1);
+        let c = alloc(260);
+        assert_eq!(COUNT.load(Ordering::SeqCst), 2);
+
+        // Drop below the upper level.
+        ralloc::free(c, 260 * MB);
+        assert_eq!(COUNT.load(Ordering::SeqCst), 3);
+
+        // Just below the lower level, but within its margin, nothing is reported.
+        let d = alloc(88);
+        ralloc::free(b, 120 * MB);
+        assert!(ralloc::heap_usage() < low);
+        assert_eq!(COUNT.load(Ordering::SeqCst), 3);
+
+        // Leave the margin.
+        ralloc::free(d, 88 * MB);
+        ralloc::free(a, 160 * MB);
+    }
+
+    assert_eq!(COUNT.load(Ordering::SeqCst), 4);
+    assert_eq!(unsafe { &CALLS[..4] }, &[
+        (low, Direction::Up),
+        (high, Direction::Up),
+        (high, Direction::Down),
+        (low, Direction::Down),
+    ]);
+}
+
+#[test]
+#[should_panic]
+fn unsorted_watermarks() {
+    static LEVELS: [usize; 2] = [2, 1];
+
+    ralloc::set_watermark_callback(&LEVELS, record);
+}
@@ -0,0 +1,33 @@
+extern crate ralloc;
+
+use std::ptr;
+
+#[test]
+fn zero_on_free() {
+    ralloc::set_zero_on_free(true);
+
+    let buf = ralloc::alloc(64, 8);
+
+    unsafe {
+        ptr::write_bytes(buf, 0xAA, 64);
+        ralloc::free(buf, 64);
+
+        // Peek into the freed buffer through the retained pointer.
+        for i in 0..64 {
+            assert_eq!(*buf.offset(i), 0);
+        }
+    }
+
+    ralloc::set_zero_on_free(false);
+
+    let buf = ralloc::alloc(64, 8);
+
+    unsafe {
+        ptr::write_bytes(buf, 0xAA, 64);
+        ralloc::free(buf, 64);
+
+        for i in 0..64 {
+            assert_eq!(*buf.offset(i), 0xAA);
+        }
+    }
+}